@@ -0,0 +1,103 @@
+//! Parser Benchmarks
+//!
+//! Measure the parsers on generated manifests of different sizes. Run with
+//! `cargo bench`. Each benchmark reports the median time per iteration.
+
+use r_osbuild::borrowed::{Bump, Manifest2Ref};
+use r_osbuild::manifest::{Manifest, Manifest1, Manifest2};
+use std::time::{Duration, Instant};
+
+// Generate a version-1 manifest with the given number of stages.
+fn manifest1(stages: usize) -> String {
+    let stages: Vec<_> = (0..stages)
+        .map(|i| {
+            serde_json::json!({
+                "name": "org.osbuild.rpm",
+                "options": { "gpgkeys": ["key"], "packages": [format!("sha256:{:064x}", i)] },
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "pipeline": {
+            "build": {
+                "pipeline": { "stages": stages.clone() },
+                "runner": "org.osbuild.fedora39",
+            },
+            "stages": stages,
+            "assembler": { "name": "org.osbuild.qemu", "options": { "format": "qcow2" } },
+        },
+        "sources": { "org.osbuild.files": { "urls": {} } },
+    })
+    .to_string()
+}
+
+// Generate a version-2 manifest with the given number of stages and inline
+// source items.
+fn manifest2(stages: usize, items: usize) -> String {
+    let stages: Vec<_> = (0..stages)
+        .map(|i| {
+            serde_json::json!({
+                "type": "org.osbuild.copy",
+                "inputs": { "tree": { "type": "org.osbuild.tree", "origin": "org.osbuild.pipeline", "references": ["name:build"] } },
+                "options": { "paths": [{ "from": format!("input://tree/{}", i), "to": "tree:///" }] },
+            })
+        })
+        .collect();
+    let items: serde_json::Map<_, _> = (0..items)
+        .map(|i| {
+            let data = serde_json::json!({ "encoding": "base64", "data": "YWJj".repeat(64) });
+            (format!("sha256:{:064x}", i), data)
+        })
+        .collect();
+    serde_json::json!({
+        "version": "2",
+        "pipelines": [
+            { "name": "build", "runner": "org.osbuild.fedora39", "stages": stages.clone() },
+            { "name": "os", "build": "name:build", "stages": stages },
+        ],
+        "sources": { "org.osbuild.inline": { "items": items } },
+    })
+    .to_string()
+}
+
+fn bench<T>(name: &str, text: &str, f: impl Fn(&str) -> T) {
+    let mut times = Vec::new();
+    let start = Instant::now();
+    while times.len() < 5 || (start.elapsed() < Duration::from_secs(1) && times.len() < 1000) {
+        let t = Instant::now();
+        std::hint::black_box(f(std::hint::black_box(text)));
+        times.push(t.elapsed());
+    }
+    times.sort();
+    println!(
+        "{:<32} {:>10.1} us/iter ({} KiB)",
+        name,
+        times[times.len() / 2].as_secs_f64() * 1e6,
+        text.len() / 1024,
+    );
+}
+
+fn main() {
+    for (size, stages, items) in [("small", 4, 2), ("medium", 256, 128), ("huge", 8192, 4096)] {
+        let v1 = manifest1(stages);
+        let v2 = manifest2(stages, items);
+
+        bench(&format!("manifest1/{}", size), &v1, |v| {
+            v.parse::<Manifest1>().unwrap()
+        });
+        bench(&format!("manifest2/{}", size), &v2, |v| {
+            v.parse::<Manifest2>().unwrap()
+        });
+        bench(&format!("detect1/{}", size), &v1, |v| {
+            Manifest::parse(v).unwrap()
+        });
+        bench(&format!("detect2/{}", size), &v2, |v| {
+            Manifest::parse(v).unwrap()
+        });
+        bench(&format!("borrowed2/{}", size), &v2, |v| {
+            let bump = Bump::new();
+            let n = Manifest2Ref::parse(v, &bump).unwrap().pipelines.len();
+            n
+        });
+    }
+}
@@ -0,0 +1,20 @@
+//! Fuzz Manifest Round-Trips
+//!
+//! Generate arbitrary version-1 manifests from the input and check that
+//! they survive serialization, linting, and conversion to version 2.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use r_osbuild::arbitrary::Unstructured;
+use r_osbuild::lint;
+use r_osbuild::manifest::{Manifest1, Manifest2};
+
+fuzz_target!(|data: &[u8]| {
+    let manifest: Manifest1 = Unstructured::new(data).arbitrary();
+    assert_eq!(manifest.to_string().parse::<Manifest1>().unwrap(), manifest);
+    lint::check(&manifest);
+
+    let converted = Manifest2::from(&manifest);
+    let _ = Manifest1::try_from(&converted);
+});
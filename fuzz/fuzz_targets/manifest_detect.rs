@@ -0,0 +1,24 @@
+//! Fuzz Format Detection
+//!
+//! Parse the input with the format-detecting parser. Manifests that parse
+//! must parse to the same manifest again, and must be accepted by the
+//! parser of their version.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use r_osbuild::manifest::{Manifest, Manifest1, Manifest2, ManifestFormat};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(manifest) = Manifest::parse(text) {
+        assert_eq!(Manifest::parse(&manifest.serialize()).unwrap(), manifest);
+        match manifest {
+            Manifest::V1(_) => assert!(text.parse::<Manifest1>().is_ok()),
+            Manifest::V2(_) => assert!(text.parse::<Manifest2>().is_ok()),
+        }
+        let _ = manifest.pipelines();
+    }
+});
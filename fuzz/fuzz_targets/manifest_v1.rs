@@ -0,0 +1,16 @@
+//! Fuzz Version-1 Manifest Parser
+//!
+//! Parse the input as version-1 manifest. Manifests that parse must
+//! serialize and parse again to the same manifest.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use r_osbuild::manifest::Manifest1;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(manifest) = serde_json::from_slice::<Manifest1>(data) {
+        let text = manifest.to_string();
+        assert_eq!(text.parse::<Manifest1>().unwrap(), manifest);
+    }
+});
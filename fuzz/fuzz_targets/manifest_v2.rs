@@ -0,0 +1,20 @@
+//! Fuzz Version-2 Manifest Parser
+//!
+//! Parse the input as version-2 manifest. Manifests that parse must
+//! serialize and parse again to the same manifest, and their pipelines
+//! must be orderable unless their dependencies are cyclic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use r_osbuild::manifest::Manifest2;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(manifest) = serde_json::from_slice::<Manifest2>(data) {
+        let text = manifest.to_string();
+        assert_eq!(text.parse::<Manifest2>().unwrap(), manifest);
+        if let Some(v) = manifest.iter_pipelines_topological() {
+            assert_eq!(v.count(), manifest.pipelines.len());
+        }
+    }
+});
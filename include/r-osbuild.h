@@ -0,0 +1,51 @@
+#pragma once
+
+/*
+ * r-osbuild C API
+ *
+ * Declarations of the C ABI exposed by the r-osbuild library when built
+ * with the `capi` feature. See `src/capi.rs` for details.
+ */
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+enum {
+        R_OSBUILD_E_OK = 0,
+        R_OSBUILD_E_INVALID_ARGUMENT = 1,
+        R_OSBUILD_E_PARSE = 2,
+        R_OSBUILD_E_VALIDATION = 3,
+};
+
+typedef struct r_osbuild_error {
+        int code;
+        char *message;
+        size_t line;
+        size_t column;
+} r_osbuild_error;
+
+#define R_OSBUILD_ERROR_NULL { 0, NULL, 0, 0 }
+
+typedef struct r_osbuild_manifest r_osbuild_manifest;
+
+int r_osbuild_manifest_parse(const char *data,
+                             size_t len,
+                             r_osbuild_manifest **manifest,
+                             r_osbuild_error *error);
+int r_osbuild_manifest_validate(const r_osbuild_manifest *manifest,
+                                char **diagnostics,
+                                r_osbuild_error *error);
+int r_osbuild_manifest_to_json(const r_osbuild_manifest *manifest,
+                               char **json,
+                               r_osbuild_error *error);
+void r_osbuild_manifest_free(r_osbuild_manifest *manifest);
+
+void r_osbuild_string_free(char *s);
+void r_osbuild_error_clear(r_osbuild_error *error);
+
+#ifdef __cplusplus
+}
+#endif
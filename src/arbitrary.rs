@@ -0,0 +1,299 @@
+//! Arbitrary Manifests
+//!
+//! This module generates arbitrary manifests from unstructured bytes, for
+//! property tests and fuzzing. The `Arbitrary` trait and `Unstructured`
+//! type follow the interface of the `arbitrary` crate: every value is
+//! derived from a byte buffer, so a failing input can be reproduced and
+//! minimized by reducing its buffer. Exhausted buffers yield the smallest
+//! values, so generation always terminates.
+//!
+//! Generated strings are short, but include characters that need escaping
+//! in JSON. Inner JSON payload is nested a few levels at most.
+
+use crate::manifest::{
+    Assembler1, Build1, Json, Manifest1, Manifest2, Object, Pipeline1, Pipeline2, Sources, Stage1,
+    Stage2,
+};
+use crate::runner::Runner;
+use crate::symbol::Symbol;
+
+const ALPHABET: &[char] = &[
+    'a', 'b', 'z', '.', '-', '/', ':', '"', '\\', '\n', 'ä', '😀',
+];
+const MAX_DEPTH: usize = 4;
+
+/// Unstructured Input
+#[derive(Clone, Debug)]
+pub struct Unstructured<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Unstructured<'a> {
+    /// Create Unstructured Input
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, depth: 0 }
+    }
+
+    /// Whether the Input is Exhausted
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Take a Byte
+    ///
+    /// Return the next byte of the input, or 0 if it is exhausted.
+    pub fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((v, rest)) => {
+                self.data = rest;
+                *v
+            }
+            None => 0,
+        }
+    }
+
+    /// Take an Integer Below a Bound
+    ///
+    /// Return an integer in `0..bound`, or 0 if the input is exhausted.
+    /// `bound` must not be 0 and is capped at 256.
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.byte() as usize % bound.min(256)
+    }
+
+    /// Take a Flag
+    pub fn flag(&mut self) -> bool {
+        self.below(2) == 1
+    }
+
+    /// Take a Value
+    pub fn arbitrary<T: Arbitrary>(&mut self) -> T {
+        T::arbitrary(self)
+    }
+}
+
+/// Arbitrary Values
+pub trait Arbitrary: Sized {
+    /// Generate a value from unstructured input.
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self;
+}
+
+impl Arbitrary for String {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        (0..u.below(8))
+            .map(|_| ALPHABET[u.below(ALPHABET.len())])
+            .collect()
+    }
+}
+
+impl Arbitrary for Symbol {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        Symbol::from(u.arbitrary::<String>())
+    }
+}
+
+impl Arbitrary for Runner {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        Runner::from(u.arbitrary::<String>())
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        u.flag().then(|| u.arbitrary())
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        (0..u.below(4)).map(|_| u.arbitrary()).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Object<T> {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        (0..u.below(4))
+            .map(|_| (u.arbitrary(), u.arbitrary()))
+            .collect()
+    }
+}
+
+impl Arbitrary for Sources {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        (0..u.below(4))
+            .map(|_| (u.arbitrary(), u.arbitrary()))
+            .collect()
+    }
+}
+
+impl Arbitrary for Json {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let nested = u.depth < MAX_DEPTH;
+        u.depth += 1;
+        let v = match u.below(if nested { 7 } else { 5 }) {
+            0 => Json::Null,
+            1 => Json::Bool(u.flag()),
+            2 => Json::from(u64::from(u.byte()) << (u.below(8) * 8)),
+            3 => Json::from(-i64::from(u.byte())),
+            4 => Json::String(u.arbitrary()),
+            5 => Json::Array(u.arbitrary()),
+            _ => Json::Object(u.arbitrary::<Object<Json>>().into_iter().collect()),
+        };
+        u.depth -= 1;
+        v
+    }
+}
+
+impl Arbitrary for Stage1 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.name = u.arbitrary();
+        v.options = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Assembler1 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.name = u.arbitrary();
+        v.options = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Build1 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.pipeline = u.arbitrary();
+        v.runner = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Pipeline1 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.stages = u.arbitrary();
+        v.assembler = u.arbitrary();
+        if u.depth < MAX_DEPTH && u.flag() {
+            u.depth += 1;
+            v.build = Some(Box::new(u.arbitrary()));
+            u.depth -= 1;
+        }
+        v
+    }
+}
+
+impl Arbitrary for Manifest1 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.pipeline = u.arbitrary();
+        v.sources = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Stage2 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.r#type = u.arbitrary();
+        v.id = u.arbitrary();
+        v.options = u.arbitrary();
+        v.inputs = u.arbitrary();
+        v.devices = u.arbitrary();
+        v.mounts = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Pipeline2 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.name = u.arbitrary();
+        v.build = u.arbitrary();
+        v.runner = u.arbitrary();
+        v.source_epoch = u.flag().then(|| u64::from(u.byte()));
+        v.stages = u.arbitrary();
+        v
+    }
+}
+
+impl Arbitrary for Manifest2 {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Self {
+        let mut v = Self::default();
+        v.version = "2".to_owned();
+        v.pipelines = u.arbitrary();
+        v.sources = u.arbitrary();
+        v
+    }
+}
+
+/// Generate Input Buffers
+///
+/// Return `count` pseudo-random buffers derived from `seed`, for property
+/// tests that do not run under a fuzzer. The same seed always yields the
+/// same buffers.
+pub fn inputs(seed: u64, count: usize) -> Vec<Vec<u8>> {
+    // xorshift64*, which is good enough to cover the input space.
+    let mut state = seed | 1;
+    let mut next = move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    };
+    (0..count)
+        .map(|_| {
+            let len = (next() % 512) as usize;
+            (0..len).map(|_| (next() >> 56) as u8).collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint;
+
+    // Verify Generation
+    #[test]
+    fn verify_generation() {
+        assert_eq!(
+            Manifest1::arbitrary(&mut Unstructured::new(&[])),
+            Manifest1::default()
+        );
+        assert_eq!(inputs(7, 4), inputs(7, 4));
+
+        let mut u = Unstructured::new(&[1, 2]);
+        assert_eq!(u.arbitrary::<String>(), "z");
+        assert!(u.is_empty());
+    }
+
+    // Verify Manifest Properties
+    #[test]
+    fn verify_properties() {
+        for data in inputs(0x5eed, 256) {
+            let manifest: Manifest1 = Unstructured::new(&data).arbitrary();
+            let text = serde_json::to_string(&manifest).unwrap();
+            assert_eq!(serde_json::from_str::<Manifest1>(&text).unwrap(), manifest);
+            assert_eq!(text.parse::<Manifest1>().unwrap(), manifest);
+            lint::check(&manifest);
+
+            // Assemblers of build pipelines have no version-2 equivalent
+            // and are dropped by conversions.
+            let mut manifest = manifest;
+            let mut build = manifest.pipeline.build.as_deref_mut();
+            while let Some(v) = build {
+                v.pipeline.assembler = None;
+                build = v.pipeline.build.as_deref_mut();
+            }
+            let converted = Manifest2::from(&manifest);
+            assert_eq!(Manifest1::try_from(&converted).unwrap(), manifest);
+
+            let manifest: Manifest2 = Unstructured::new(&data).arbitrary();
+            let text = serde_json::to_string(&manifest).unwrap();
+            assert_eq!(serde_json::from_str::<Manifest2>(&text).unwrap(), manifest);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Build Audit Records
+//!
+//! An audit record documents a single build: the manifest it built, the
+//! sources it resolved, the osbuild version and host it ran on, the timings
+//! of its stages, and the digests of its outputs. Unlike the provenance
+//! statement, it records details of the run that verifiers do not need,
+//! but operators do when investigating a build later on.
+//!
+//! Records are typed documents, serialized to JSON and signed as payload
+//! of a DSSE envelope. The provenance statement of a build is derived from
+//! its record, and references the record as byproduct.
+
+use crate::dsse;
+use crate::events::Event;
+use crate::hash::Digest;
+use crate::manifest::{Json, Manifest1};
+use crate::provenance::{self, Builder, Invocation, ResourceDescriptor, Statement};
+use crate::sidecar;
+use std::path::Path;
+
+/// Audit Record Payload Type
+pub const PAYLOAD_TYPE: &str = "application/vnd.osbuild.audit+json";
+
+/// Host Information
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Host {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Operating system, like `linux`.
+    pub os: String,
+    /// Architecture, like `x86_64`.
+    pub arch: String,
+    /// Kernel release, like `6.5.6-300.fc39.x86_64`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+}
+
+impl Host {
+    /// Describe Current Host
+    ///
+    /// Describe the host this process runs on. The hostname and kernel
+    /// release are read from `/proc`, and are left unset where it is not
+    /// available.
+    pub fn current() -> Self {
+        let read = |path: &str| {
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|v| v.trim().to_owned())
+                .filter(|v| !v.is_empty())
+        };
+        Self {
+            hostname: read("/proc/sys/kernel/hostname"),
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            kernel: read("/proc/sys/kernel/osrelease"),
+        }
+    }
+}
+
+/// Stage Timing
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Timing {
+    pub pipeline: String,
+    /// Position of the stage in its pipeline.
+    pub index: usize,
+    /// Stage type, like `org.osbuild.rpm`.
+    pub stage: String,
+    pub duration_ms: u64,
+}
+
+/// Audit Record
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Record {
+    /// Identifier of the build, as used by its events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// Digest of the manifest, like `sha256:<hex>`.
+    pub manifest: String,
+    /// Sources of the manifest, with the digests they are verified against.
+    pub sources: Vec<ResourceDescriptor>,
+    pub osbuild_version: String,
+    pub host: Host,
+    /// Start time, as UTC timestamp like `2023-11-01T00:00:00Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_on: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_on: Option<String>,
+    /// Timings of the stages, in the order they finished.
+    pub stages: Vec<Timing>,
+    /// Artifacts produced by the build.
+    pub outputs: Vec<ResourceDescriptor>,
+}
+
+impl Record {
+    /// Create Audit Record
+    ///
+    /// Create the record of a build of the manifest, on the current host.
+    /// The manifest is identified by the same digest as in its provenance.
+    pub fn new(manifest: &Manifest1, osbuild_version: &str) -> Self {
+        let serialized = serde_json::to_vec(manifest).unwrap_or_default();
+        Self {
+            build: None,
+            manifest: Digest::sha256(&serialized).to_string(),
+            sources: provenance::materials(manifest),
+            osbuild_version: osbuild_version.to_owned(),
+            host: Host::current(),
+            started_on: None,
+            finished_on: None,
+            stages: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Record Event
+    ///
+    /// Record the timing of finished stages, and the build identifier.
+    /// Events of other builds are ignored.
+    pub fn record(&mut self, event: &Event) {
+        if self.build.get_or_insert_with(|| event.build().to_owned()) != event.build() {
+            return;
+        }
+        if let Event::StageFinished {
+            pipeline,
+            index,
+            stage,
+            duration_ms,
+            ..
+        } = event
+        {
+            self.stages.push(Timing {
+                pipeline: pipeline.clone(),
+                index: *index,
+                stage: stage.clone(),
+                duration_ms: *duration_ms,
+            });
+        }
+    }
+
+    /// Record Output
+    ///
+    /// Read the artifact at the given path and record it with its digests.
+    pub fn output(&mut self, path: &Path) -> std::io::Result<()> {
+        let metadata = sidecar::Metadata::from_path(path)?;
+        self.outputs.push(ResourceDescriptor {
+            name: Some(metadata.filename),
+            uri: None,
+            digest: [
+                ("sha256".to_owned(), metadata.sha256),
+                ("sha512".to_owned(), metadata.sha512),
+            ]
+            .into(),
+        });
+        Ok(())
+    }
+
+    /// Create Unsigned Envelope
+    ///
+    /// Serialize the record as payload of a DSSE envelope, to be signed.
+    pub fn envelope(&self) -> Result<dsse::Envelope, dsse::Error> {
+        dsse::Envelope::with_json(PAYLOAD_TYPE, self)
+    }
+
+    /// Generate Provenance
+    ///
+    /// Create the provenance statement of the build. The outputs are its
+    /// subjects, the osbuild version is added to the versions of the
+    /// builder, and the record itself is referenced as byproduct.
+    pub fn statement(&self, manifest: &Manifest1, mut builder: Builder) -> Statement {
+        builder
+            .version
+            .insert("osbuild".to_owned(), self.osbuild_version.clone());
+        let invocation = Invocation {
+            builder,
+            metadata: provenance::Metadata {
+                invocation_id: self.build.clone(),
+                started_on: self.started_on.clone(),
+                finished_on: self.finished_on.clone(),
+            },
+            parameters: Default::default(),
+        };
+
+        let mut statement = provenance::statement(manifest, self.outputs.clone(), &invocation);
+        let serialized = serde_json::to_vec(self).unwrap_or_default();
+        statement
+            .predicate
+            .run_details
+            .byproducts
+            .push(ResourceDescriptor {
+                name: Some("audit".to_owned()),
+                uri: None,
+                digest: [(
+                    "sha256".to_owned(),
+                    crate::hash::to_hex(&crate::hash::Sha256::digest(&serialized)),
+                )]
+                .into(),
+            });
+        statement
+    }
+
+    /// Serialize to JSON
+    pub fn to_json(&self) -> Json {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Audit Records
+    #[test]
+    fn verify_record() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": { "sha256:a": "https://example.com/a.rpm" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("r-osbuild-audit-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+
+        let mut record = Record::new(&manifest, "100");
+        record.started_on = Some("2023-11-01T00:00:00Z".to_owned());
+        for event in [
+            Event::Queued {
+                build: "b".to_owned(),
+            },
+            Event::StageFinished {
+                build: "b".to_owned(),
+                pipeline: "os".to_owned(),
+                index: 0,
+                stage: "org.osbuild.rpm".to_owned(),
+                duration_ms: 1500,
+            },
+            Event::StageFinished {
+                build: "other".to_owned(),
+                pipeline: "os".to_owned(),
+                index: 1,
+                stage: "org.osbuild.selinux".to_owned(),
+                duration_ms: 10,
+            },
+        ] {
+            record.record(&event);
+        }
+        record.output(&path).unwrap();
+        assert!(record.output(&path.with_extension("none")).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let json = record.to_json();
+        assert_eq!(json["build"], "b");
+        assert_eq!(json["sources"][0]["uri"], "https://example.com/a.rpm");
+        assert_eq!(json["osbuild_version"], "100");
+        assert_eq!(json["host"]["os"], std::env::consts::OS);
+        assert_eq! {
+            json["stages"],
+            serde_json::json!([{
+                "pipeline": "os",
+                "index": 0,
+                "stage": "org.osbuild.rpm",
+                "duration_ms": 1500,
+            }]),
+        }
+        assert_eq!(
+            json["outputs"][0]["digest"]["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let envelope = record.envelope().unwrap();
+        let parsed: Record = serde_json::from_slice(&envelope.payload().unwrap()).unwrap();
+        assert_eq!(parsed, record);
+
+        let statement = record.statement(&manifest, Builder::default());
+        let json = serde_json::to_value(&statement).unwrap();
+        let predicate = &json["predicate"];
+        assert_eq!(
+            json["subject"][0]["name"],
+            record.outputs[0].name.as_deref().unwrap()
+        );
+        assert_eq!(
+            format!(
+                "sha256:{}",
+                predicate["buildDefinition"]["resolvedDependencies"][0]["digest"]["sha256"]
+                    .as_str()
+                    .unwrap()
+            ),
+            record.manifest
+        );
+        assert_eq!(
+            predicate["runDetails"]["builder"]["version"]["osbuild"],
+            "100"
+        );
+        assert_eq!(predicate["runDetails"]["metadata"]["invocationId"], "b");
+        assert_eq!(predicate["runDetails"]["byproducts"][0]["name"], "audit");
+    }
+}
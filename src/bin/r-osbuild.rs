@@ -0,0 +1,429 @@
+//! r-osbuild Command-Line Interface
+//!
+//! This binary makes the manifest tooling of the crate available to shell
+//! scripts and CI pipelines. Every subcommand reads manifests from files,
+//! or from standard input if the path is `-`. Manifests of all versions are
+//! accepted, and only converted between versions by `convert`. Files can be
+//! gzip-compressed, while xz and zstd compression is not supported.
+//!
+//! The exit status is 0 on success, 1 if the command completed but found
+//! problems or differences, and 2 on usage or input errors.
+
+use r_osbuild::manifest::{Json, Manifest, Manifest1};
+use r_osbuild::{browse, catalog, compress, convert, diff, inspect, lint, lsp, rest, watch};
+
+const USAGE: &str = "\
+Usage: r-osbuild <command> [options] <file>...
+
+Commands:
+  validate [--cache <dir>] <file>...  Parse and lint manifests, caching
+                                      results in the directory
+  validate --watch <path>...          Watch files and directories and
+                                      re-validate manifests on changes
+  format [--check|--in-place] <file>  Pretty-print a manifest
+  inspect [--json] <file>             Show statistics and stage identifiers
+  diff [--ignore <rule>]... <old> <new>
+                                      Show structural differences, ignoring
+                                      `key:<name>`, `path:<pointer>`, `hosts`,
+                                      or `stage-order`
+  convert --to <v1|v2> <file>         Convert between format versions
+  explain [--json] [<name>...]        Describe stages and sources
+  browse <file>                       Browse a manifest interactively
+  lsp                                 Run a language server on stdio
+  serve [--listen <address>]          Run the REST service
+
+Files can be gzip-compressed. xz and zstd compression is not supported.
+";
+
+// A failure of a command, reported with the given exit status.
+struct Failure(i32, String);
+
+impl Failure {
+    fn usage(message: &str) -> Self {
+        Self(2, format!("{}\n\n{}", message, USAGE))
+    }
+}
+
+// Read the file, decompressing it if needed.
+fn read(path: &str) -> Result<String, Failure> {
+    use std::io::Read;
+
+    let mut acc = Vec::new();
+    let r = match path {
+        "-" => std::io::stdin().read_to_end(&mut acc).map(|_| ()),
+        v => std::fs::read(v).map(|v| acc = v),
+    };
+    r.map_err(|e| Failure(2, format!("{}: {}", path, e)))?;
+    let data = compress::decompress(&acc).map_err(|e| Failure(2, format!("{}: {}", path, e)))?;
+    String::from_utf8(data.into_owned()).map_err(|e| Failure(2, format!("{}: {}", path, e)))
+}
+
+// Write the file, compressed according to its extension.
+fn compressed(path: &str, text: String) -> Result<(), r_osbuild::error::Error> {
+    let data = match compress::Codec::from_path(std::path::Path::new(path)) {
+        Some(v) => compress::compress(v, text.as_bytes())?,
+        None => text.into_bytes(),
+    };
+    Ok(std::fs::write(path, data)?)
+}
+
+fn parse_json(path: &str, data: &str) -> Result<Json, Failure> {
+    serde_json::from_str(data).map_err(|e| Failure(2, format!("{}: {}", path, e)))
+}
+
+// Parse a manifest of either version.
+fn manifest(path: &str, data: &str) -> Result<Manifest, Failure> {
+    Manifest::parse(data).map_err(|e| Failure(2, format!("{}: {}", path, e)))
+}
+
+fn load(path: &str) -> Result<Manifest, Failure> {
+    manifest(path, &read(path)?)
+}
+
+fn pretty(json: &Json) -> String {
+    let mut s = serde_json::to_string_pretty(json).expect("JSON values always serialize");
+    s.push('\n');
+    s
+}
+
+fn severity(v: lint::Severity) -> &'static str {
+    match v {
+        lint::Severity::Note => "note",
+        lint::Severity::Warning => "warning",
+        lint::Severity::Error => "error",
+    }
+}
+
+fn watch(args: &[String]) -> Result<i32, Failure> {
+    if args.is_empty() {
+        return Err(Failure::usage("validate: no files to watch"));
+    }
+
+    let roots = args.iter().map(std::path::PathBuf::from).collect();
+    let mut watcher = watch::Watcher::new(roots);
+    watcher.run(std::time::Duration::from_millis(500), |events| {
+        for event in events {
+            let path = event.path.display();
+            match &event.status {
+                watch::Status::Checked(v) if v.is_empty() => println!("{}: ok", path),
+                watch::Status::Checked(v) => {
+                    for d in v {
+                        println!(
+                            "{}:{}: {}: {} [{}]",
+                            path,
+                            d.path,
+                            severity(d.severity),
+                            d.message,
+                            d.rule
+                        );
+                    }
+                }
+                watch::Status::Invalid(v) => println!("{}: {}", path, v),
+                watch::Status::Removed => println!("{}: removed", path),
+            }
+        }
+        true
+    });
+    Ok(0)
+}
+
+fn validate(args: &[String]) -> Result<i32, Failure> {
+    let (cache, args) = match args {
+        [flag, args @ ..] if flag == "--watch" => return watch(args),
+        [flag, dir, args @ ..] if flag == "--cache" => (Some(dir), args),
+        args => (None, args),
+    };
+    if args.is_empty() {
+        return Err(Failure::usage("validate: no input files"));
+    }
+
+    let mut cache = cache.map(|v| lint::cache::Cache::new(lint::cache::Disk::new(v)));
+    let mut status = 0;
+    for path in args {
+        let manifest = match load(path) {
+            Ok(v) => v,
+            Err(Failure(_, message)) => {
+                eprintln!("{}", message);
+                status = 2;
+                continue;
+            }
+        };
+        let diagnostics = match cache.as_mut() {
+            Some(v) => v.check(&manifest),
+            None => lint::check(&manifest),
+        };
+        for d in diagnostics {
+            if d.severity == lint::Severity::Error {
+                status = status.max(1);
+            }
+            println!(
+                "{}:{}: {}: {} [{}]",
+                path,
+                d.path,
+                severity(d.severity),
+                d.message,
+                d.rule
+            );
+        }
+    }
+    Ok(status)
+}
+
+fn format(args: &[String]) -> Result<i32, Failure> {
+    let (mode, path) = match args {
+        [path] => ("", path),
+        [mode, path] if mode == "--check" || mode == "--in-place" => (mode.as_str(), path),
+        _ => return Err(Failure::usage("format: expected a single input file")),
+    };
+
+    let data = read(path)?;
+    manifest(path, &data)?;
+    let formatted = pretty(&parse_json(path, &data)?);
+
+    match mode {
+        "--check" if formatted != data => {
+            eprintln!("{}: not formatted", path);
+            Ok(1)
+        }
+        "--check" => Ok(0),
+        "--in-place" if path == "-" => Err(Failure::usage("format: cannot modify standard input")),
+        "--in-place" => compressed(path, formatted)
+            .map(|_| 0)
+            .map_err(|e| Failure(2, format!("{}: {}", path, e))),
+        _ => {
+            print!("{}", formatted);
+            Ok(0)
+        }
+    }
+}
+
+fn inspect(args: &[String]) -> Result<i32, Failure> {
+    let (json, path) = match args {
+        [path] => (false, path),
+        [flag, path] if flag == "--json" => (true, path),
+        _ => return Err(Failure::usage("inspect: expected a single input file")),
+    };
+
+    let (stats, ids) = match load(path)? {
+        Manifest::V1(v) => (inspect::stats(&v), inspect::ids(&v)),
+        Manifest::V2(v) => (inspect::stats2(&v), inspect::ids2(&v)),
+    };
+
+    if json {
+        let ids: serde_json::Map<_, _> = ids
+            .into_iter()
+            .map(|(p, v)| (p, Json::String(v.to_string())))
+            .collect();
+        print!(
+            "{}",
+            pretty(&serde_json::json!({ "stats": stats, "ids": ids }))
+        );
+        return Ok(0);
+    }
+
+    println!("pipelines: {}", stats.pipelines);
+    println!("stages: {}", stats.stages);
+    for (name, n) in &stats.stage_types {
+        println!("  {}: {}", name, n);
+    }
+    if let Some(v) = &stats.assembler {
+        println!("assembler: {}", v);
+    }
+    println!("packages: {}", stats.packages);
+    for (name, n) in &stats.sources {
+        println!("source {}: {} items", name, n);
+    }
+    for (path, id) in ids {
+        println!("{} {}", id, path);
+    }
+    Ok(0)
+}
+
+fn diff(mut args: &[String]) -> Result<i32, Failure> {
+    let mut rules = Vec::new();
+    while let [flag, rule, rest @ ..] = args {
+        if flag != "--ignore" {
+            break;
+        }
+        rules.push(match rule.split_once(':') {
+            Some(("key", v)) => diff::Ignore::Key(v.to_owned()),
+            Some(("path", v)) => diff::Ignore::Path(v.to_owned()),
+            None if rule == "hosts" => diff::Ignore::UrlHosts,
+            None if rule == "stage-order" => diff::Ignore::StageOrder,
+            _ => {
+                return Err(Failure::usage(&format!(
+                    "diff: unknown ignore rule `{}`",
+                    rule
+                )))
+            }
+        });
+        args = rest;
+    }
+
+    let changes = match args {
+        [old, new] => match diff::manifests_any(&load(old)?, &load(new)?, &rules) {
+            Some(v) => v,
+            None => {
+                return Err(Failure(
+                    2,
+                    "diff: manifests of different versions, convert one first".to_owned(),
+                ))
+            }
+        },
+        _ => return Err(Failure::usage("diff: expected two input files")),
+    };
+
+    for change in &changes {
+        println!("{}", change);
+    }
+    Ok(if changes.is_empty() { 0 } else { 1 })
+}
+
+fn convert(args: &[String]) -> Result<i32, Failure> {
+    let (target, path) = match args {
+        [flag, target, path] if flag == "--to" => (target.as_str(), path),
+        _ => {
+            return Err(Failure::usage(
+                "convert: expected --to <version> and an input file",
+            ))
+        }
+    };
+
+    let data = read(path)?;
+    let json = parse_json(path, &data)?;
+    let output = match (target, manifest(path, &data)?) {
+        ("v1", Manifest::V1(_)) | ("v2", Manifest::V2(_)) => json,
+        ("v1", Manifest::V2(_)) => {
+            let v1: Manifest1 =
+                convert::to_v1(&json).map_err(|e| Failure(2, format!("{}: {}", path, e)))?;
+            serde_json::to_value(v1).expect("manifests always serialize")
+        }
+        ("v2", Manifest::V1(v)) => convert::to_v2(&v),
+        _ => {
+            return Err(Failure::usage(&format!(
+                "convert: unknown version `{}`",
+                target
+            )))
+        }
+    };
+    print!("{}", pretty(&output));
+    Ok(0)
+}
+
+fn explain(args: &[String]) -> Result<i32, Failure> {
+    let (json, names) = match args.split_first() {
+        Some((flag, names)) if flag == "--json" => (true, names),
+        _ => (false, args),
+    };
+
+    let entries: Vec<_> = match names {
+        [] => catalog::CATALOG.iter().collect(),
+        v => v.iter().flat_map(|v| catalog::lookup(v)).collect(),
+    };
+    if let Some(v) = names.iter().find(|v| catalog::lookup(v).is_empty()) {
+        return Err(Failure(
+            1,
+            format!("explain: unknown stage or source `{}`", v),
+        ));
+    }
+
+    match json {
+        true => print!("{}", pretty(&catalog::to_json(&entries))),
+        false => print!("{}", catalog::to_markdown(&entries)),
+    }
+    Ok(0)
+}
+
+fn browse(args: &[String]) -> Result<i32, Failure> {
+    use std::io::{BufRead, Write};
+
+    let path = match args {
+        [path] if path != "-" => path,
+        _ => return Err(Failure::usage("browse: expected a single input file")),
+    };
+
+    // Invalid manifests can still be browsed.
+    let data = read(path)?;
+    let json = parse_json(path, &data)?;
+    let diagnostics = match manifest(path, &data) {
+        Ok(v) => lint::check(&v),
+        Err(Failure(_, message)) => vec![lint::Diagnostic::new(
+            "manifest",
+            lint::Severity::Error,
+            "",
+            &message,
+        )],
+    };
+    let mut browser = browse::Browser::new(json, diagnostics);
+
+    println!("commands: <n>, <key>, .., b, f <n>, /<pointer>, d, q");
+    let mut input = String::new();
+    loop {
+        match browser.command(&input) {
+            Some(v) => print!("{}", v),
+            None => return Ok(0),
+        }
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        input.clear();
+        match std::io::stdin().lock().read_line(&mut input) {
+            Ok(0) => return Ok(0),
+            Ok(_) => {}
+            Err(e) => return Err(Failure(2, format!("browse: {}", e))),
+        }
+    }
+}
+
+fn lsp(args: &[String]) -> Result<i32, Failure> {
+    if !args.is_empty() {
+        return Err(Failure::usage("lsp: unexpected arguments"));
+    }
+
+    lsp::run(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+        .map_err(|e| Failure(2, format!("lsp: {}", e)))
+}
+
+fn rest(args: &[String]) -> Result<i32, Failure> {
+    let address = match args {
+        [] => "127.0.0.1:8080",
+        [flag, address] if flag == "--listen" => address.as_str(),
+        _ => return Err(Failure::usage("serve: expected --listen <address>")),
+    };
+
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| Failure(2, format!("{}: {}", address, e)))?;
+    rest::serve(listener)
+        .map(|_| 0)
+        .map_err(|e| Failure(2, format!("serve: {}", e)))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, args)) => match command.as_str() {
+            "validate" => validate(args),
+            "format" => format(args),
+            "inspect" => inspect(args),
+            "diff" => diff(args),
+            "convert" => convert(args),
+            "explain" => explain(args),
+            "browse" => browse(args),
+            "lsp" => lsp(args),
+            "serve" => rest(args),
+            "-h" | "--help" | "help" => {
+                print!("{}", USAGE);
+                Ok(0)
+            }
+            v => Err(Failure::usage(&format!("unknown command `{}`", v))),
+        },
+        None => Err(Failure::usage("no command given")),
+    };
+
+    match result {
+        Ok(v) => std::process::exit(v),
+        Err(Failure(v, message)) => {
+            eprintln!("r-osbuild: {}", message);
+            std::process::exit(v);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Image Blueprints
+//!
+//! Blueprints are the user-facing description of an image, as accepted by
+//! osbuild-composer and image-builder. They list the packages, modules, and
+//! containers to include, as well as customizations of the installed system,
+//! like users, services, or file-systems. Blueprints are written in TOML.
+//!
+//! This module provides typed access to blueprints. Only the commonly used
+//! parts of the format are typed. Other members of the top-level and of the
+//! customizations table are retained verbatim, so blueprints survive a
+//! round-trip through these types unchanged.
+
+use crate::estimate;
+use crate::manifest::{Json, Object};
+use crate::toml;
+
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+/// Blueprint
+///
+/// The root of a blueprint document.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Blueprint {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<Package>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<Package>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<PackageGroup>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<Container>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customizations: Option<Customizations>,
+    /// Members not covered by the typed fields.
+    #[serde(flatten)]
+    pub other: Object<Json>,
+}
+
+/// Package or Module
+///
+/// A package or module to install. The version is a glob, with `*` or an
+/// absent version selecting the latest available version.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Package {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Package Group
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct PackageGroup {
+    pub name: String,
+}
+
+/// Container
+///
+/// A container image to embed into the image, pulled from `source`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Container {
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        rename = "tls-verify",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tls_verify: Option<bool>,
+}
+
+/// Customizations
+///
+/// Modifications of the installed system.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Customizations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<Kernel>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sshkey: Vec<SshKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user: Vec<User>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group: Vec<Group>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Timezone>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall: Option<Firewall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<Services>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filesystem: Vec<Filesystem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<Directory>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<File>,
+    /// Members not covered by the typed fields.
+    #[serde(flatten)]
+    pub other: Object<Json>,
+}
+
+/// Kernel Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Kernel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append: Option<String>,
+}
+
+/// SSH Key Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SshKey {
+    pub user: String,
+    pub key: String,
+}
+
+/// User Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct User {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u64>,
+}
+
+/// Group Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Group {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u64>,
+}
+
+/// Timezone Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Timezone {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ntpservers: Vec<String>,
+}
+
+/// Locale Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Locale {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard: Option<String>,
+}
+
+/// Firewall Customization
+///
+/// Ports are given as `port:protocol` or `service:protocol`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Firewall {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<Services>,
+}
+
+/// Service Customization
+///
+/// Lists of systemd units or firewall services to enable and disable.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Services {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enabled: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+}
+
+/// File-System Customization
+///
+/// The minimum size of a mount point, either in bytes or as string with a
+/// unit suffix, like `20 GiB`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Filesystem {
+    pub mountpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minsize: Option<Json>,
+}
+
+/// Directory Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Directory {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ensure_parents: bool,
+}
+
+/// File Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct File {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+/// Blueprint Error
+///
+/// Parsing a blueprint fails either because the document is not valid
+/// TOML, or because its content does not match the blueprint format.
+#[derive(Debug)]
+pub enum Error {
+    Toml(toml::Error),
+    Data(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Toml(v) => write!(fmt, "invalid TOML: {}", v),
+            Error::Data(v) => write!(fmt, "invalid blueprint: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Blueprint {
+    /// Parse Blueprint
+    ///
+    /// Parse a blueprint from its TOML representation.
+    pub fn from_toml(data: &str) -> Result<Self, Error> {
+        let value = toml::from_str(data).map_err(Error::Toml)?;
+        serde_json::from_value(value).map_err(Error::Data)
+    }
+
+    /// Serialize Blueprint
+    ///
+    /// Serialize the blueprint to its TOML representation.
+    pub fn to_toml(&self) -> Result<String, toml::Error> {
+        let value = serde_json::to_value(self).map_err(|e| toml::Error {
+            line: 0,
+            message: e.to_string(),
+        })?;
+        toml::to_string(&value)
+    }
+
+    /// Validate Blueprint
+    ///
+    /// Check the blueprint for semantic errors that the format itself does
+    /// not rule out, and return a description of each of them.
+    pub fn validate(&self) -> Vec<String> {
+        let mut acc = Vec::new();
+
+        if self.name.is_empty() {
+            acc.push("blueprint name must not be empty".to_owned());
+        }
+
+        let packages = self.packages.iter().chain(self.modules.iter());
+        for (i, package) in packages.clone().enumerate() {
+            if package.name.is_empty() {
+                acc.push("package names must not be empty".to_owned());
+            } else if packages.clone().take(i).any(|v| v.name == package.name) {
+                acc.push(format!("package `{}` is listed twice", package.name));
+            }
+        }
+
+        let customizations = match self.customizations {
+            None => return acc,
+            Some(ref v) => v,
+        };
+
+        for (i, user) in customizations.user.iter().enumerate() {
+            if customizations.user[..i].iter().any(|v| v.name == user.name) {
+                acc.push(format!("user `{}` is defined twice", user.name));
+            }
+        }
+        for (i, group) in customizations.group.iter().enumerate() {
+            if customizations.group[..i]
+                .iter()
+                .any(|v| v.name == group.name)
+            {
+                acc.push(format!("group `{}` is defined twice", group.name));
+            }
+        }
+        for fs in customizations.filesystem.iter() {
+            if !fs.mountpoint.starts_with('/') {
+                acc.push(format!("mount point `{}` must be absolute", fs.mountpoint));
+            }
+            if let Some(ref size) = fs.minsize {
+                if estimate::parse_size(size).is_none() {
+                    acc.push(format!("invalid size of mount point `{}`", fs.mountpoint));
+                }
+            }
+        }
+
+        let paths = customizations
+            .directories
+            .iter()
+            .map(|v| &v.path)
+            .chain(customizations.files.iter().map(|v| &v.path));
+        for path in paths {
+            if !path.starts_with('/') || path.split('/').any(|v| v == "..") {
+                acc.push(format!("path `{}` must be absolute and normalized", path));
+            }
+        }
+
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Blueprint Parsing
+    #[test]
+    fn verify_parse() {
+        let data = r#"
+            name = "base"
+            description = "A base image"
+            version = "0.0.1"
+
+            [[packages]]
+            name = "tmux"
+            version = "*"
+
+            [[containers]]
+            source = "quay.io/fedora/fedora:latest"
+            tls-verify = false
+
+            [customizations]
+            hostname = "base"
+            fips = true
+
+            [customizations.kernel]
+            append = "nosmt=force"
+
+            [[customizations.user]]
+            name = "admin"
+            groups = ["wheel"]
+
+            [[customizations.filesystem]]
+            mountpoint = "/var"
+            minsize = "2 GiB"
+        "#;
+
+        let blueprint = Blueprint::from_toml(data).unwrap();
+        assert_eq!(blueprint.name, "base");
+        assert_eq!(blueprint.packages[0].version.as_deref(), Some("*"));
+        assert_eq!(blueprint.containers[0].tls_verify, Some(false));
+
+        let customizations = blueprint.customizations.as_ref().unwrap();
+        assert_eq!(customizations.user[0].groups, ["wheel"]);
+        assert_eq!(customizations.other["fips"], Json::Bool(true));
+        assert!(blueprint.validate().is_empty());
+
+        // Serialization round-trips, including untyped members.
+        let s = blueprint.to_toml().unwrap();
+        assert_eq!(Blueprint::from_toml(&s).unwrap(), blueprint);
+    }
+
+    // Verify Blueprint Errors
+    #[test]
+    fn verify_errors() {
+        assert!(matches!(
+            Blueprint::from_toml("name = "),
+            Err(Error::Toml(_)),
+        ));
+        assert!(matches!(
+            Blueprint::from_toml("name = 1"),
+            Err(Error::Data(_)),
+        ));
+
+        let blueprint = Blueprint::from_toml(
+            r#"
+            name = ""
+
+            [[packages]]
+            name = "tmux"
+
+            [[packages]]
+            name = "tmux"
+
+            [[customizations.filesystem]]
+            mountpoint = "var"
+            minsize = "lots"
+
+            [[customizations.files]]
+            path = "/etc/../x"
+            "#,
+        )
+        .unwrap();
+        assert_eq! {
+            blueprint.validate(),
+            [
+                "blueprint name must not be empty",
+                "package `tmux` is listed twice",
+                "mount point `var` must be absolute",
+                "invalid size of mount point `var`",
+                "path `/etc/../x` must be absolute and normalized",
+            ],
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Bootable Container Images
+//!
+//! Bootable container images, as built for `bootc`, carry a complete
+//! operating system as an OSTree commit in a container image. This module
+//! generates manifests deploying such images into a disk tree, and checks
+//! manifests doing so. Images are fetched from a registry via the
+//! `org.osbuild.skopeo` source, or taken from the local container storage
+//! via `org.osbuild.containers-storage`, and deployed with the
+//! `org.osbuild.ostree.deploy.container` stage.
+
+use crate::container::{Reference, Resolved};
+use crate::lint::{self, Diagnostic, Severity};
+use crate::manifest::{Json, Manifest2, Pipeline2, Sources, Stage2, Symbol};
+
+/// Deployment Stage Type
+pub const DEPLOY: &str = "org.osbuild.ostree.deploy.container";
+
+/// Image Storage
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Storage {
+    /// A registry, fetched via `org.osbuild.skopeo`.
+    #[default]
+    Registry,
+    /// The local container storage, via `org.osbuild.containers-storage`.
+    Local,
+}
+
+impl Storage {
+    /// Name of the Source
+    pub fn source(&self) -> &'static str {
+        match self {
+            Storage::Registry => "org.osbuild.skopeo",
+            Storage::Local => "org.osbuild.containers-storage",
+        }
+    }
+}
+
+/// Bootable Container Image
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Image {
+    pub reference: Reference,
+    /// The image ID, which is the digest of the image configuration.
+    pub image_id: String,
+    /// Digest of the image manifest, pinning images of registries.
+    pub digest: Option<String>,
+    pub storage: Storage,
+}
+
+impl Image {
+    /// Create Image from Resolved Reference
+    pub fn from_resolved(resolved: &Resolved) -> Self {
+        Self {
+            reference: resolved.reference.clone(),
+            image_id: resolved.image_id.clone(),
+            digest: Some(resolved.digest.clone()),
+            storage: Storage::Registry,
+        }
+    }
+
+    /// Image Sources
+    ///
+    /// Return the `sources` section providing the image.
+    pub fn sources(&self) -> Sources {
+        let item = match self.storage {
+            Storage::Registry => {
+                let mut image = serde_json::json!({ "name": self.reference.name() });
+                if let Some(v) = &self.digest {
+                    image["digest"] = Json::from(v.as_str());
+                }
+                serde_json::json!({ "image": image })
+            }
+            Storage::Local => serde_json::json!({}),
+        };
+        let items = serde_json::json!({ self.image_id.as_str(): item });
+        [(
+            Symbol::new(self.storage.source()),
+            [("items".to_owned(), items)].into(),
+        )]
+        .into()
+    }
+
+    /// Target Image Reference
+    ///
+    /// Return the reference the deployment tracks for updates, in the
+    /// syntax of `ostree container`.
+    pub fn target_imgref(&self) -> String {
+        match self.storage {
+            Storage::Registry => format!("ostree-unverified-registry:{}", self.reference),
+            Storage::Local => format!(
+                "ostree-unverified-image:containers-storage:{}",
+                self.reference
+            ),
+        }
+    }
+}
+
+/// Deployment Options
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Options {
+    /// Name of the OSTree stateroot.
+    pub osname: String,
+    /// Label of the root file-system.
+    pub rootfs_label: String,
+    pub kernel_opts: Vec<String>,
+    /// Mount points of the deployment, which exist in the disk tree.
+    pub mounts: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            osname: "default".to_owned(),
+            rootfs_label: "root".to_owned(),
+            kernel_opts: Vec::new(),
+            mounts: vec!["/boot".to_owned(), "/boot/efi".to_owned()],
+        }
+    }
+}
+
+fn stage(kind: &str, options: Json, inputs: Json) -> Stage2 {
+    let mut stage = Stage2::default();
+    stage.r#type = kind.into();
+    stage.options = options
+        .as_object()
+        .map(|v| v.clone().into_iter().collect())
+        .unwrap_or_default();
+    stage.inputs = inputs
+        .as_object()
+        .map(|v| v.clone().into_iter().collect())
+        .unwrap_or_default();
+    stage
+}
+
+/// Generate Manifest
+///
+/// Generate a manifest deploying the image into the tree of pipeline
+/// `image-tree`. The partitioning and the bootloader of the disk image
+/// are left to further pipelines.
+pub fn manifest(image: &Image, options: &Options) -> Manifest2 {
+    let deployment = serde_json::json!({ "osname": options.osname, "ref": "ostree/1/1/0" });
+    let name = image.reference.name();
+
+    let mut tree = Pipeline2::default();
+    tree.name = "image-tree".to_owned();
+    tree.stages = vec![
+        stage("org.osbuild.ostree.init-fs", Json::Null, Json::Null),
+        stage(
+            "org.osbuild.ostree.os-init",
+            serde_json::json!({ "osname": options.osname }),
+            Json::Null,
+        ),
+        stage(
+            "org.osbuild.ostree.config",
+            serde_json::json!({
+                "repo": "/ostree/repo",
+                "config": { "sysroot": { "readonly": true, "bootloader": "none" } },
+            }),
+            Json::Null,
+        ),
+        stage(
+            "org.osbuild.mkdir",
+            serde_json::json!({
+                "paths": options
+                    .mounts
+                    .iter()
+                    .map(|v| serde_json::json!({ "path": v, "parents": true, "exist_ok": true }))
+                    .collect::<Vec<_>>(),
+            }),
+            Json::Null,
+        ),
+        stage(
+            DEPLOY,
+            serde_json::json!({
+                "osname": options.osname,
+                "target_imgref": image.target_imgref(),
+                "mounts": options.mounts,
+                "kernel_opts": options.kernel_opts,
+                "rootfs": { "label": options.rootfs_label },
+            }),
+            serde_json::json!({ "images": {
+                "type": "org.osbuild.containers",
+                "origin": "org.osbuild.source",
+                "references": { image.image_id.as_str(): { "name": name } },
+            } }),
+        ),
+        stage(
+            "org.osbuild.ostree.aleph",
+            serde_json::json!({ "deployment": deployment }),
+            Json::Null,
+        ),
+        stage(
+            "org.osbuild.ostree.selinux",
+            serde_json::json!({ "deployment": deployment }),
+            Json::Null,
+        ),
+    ];
+
+    let mut manifest = Manifest2::default();
+    manifest.version = "2".to_owned();
+    manifest.pipelines = vec![tree];
+    manifest.sources = image.sources();
+    manifest
+}
+
+/// Check Deployments
+///
+/// Check the container deployments of the manifest. Every deployment
+/// needs a single image input provided by a container source, the
+/// stateroot initialized before, and a target reference. Images of
+/// registries should be pinned by digest.
+pub fn check(manifest: &Manifest2) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+    let items = |source: &str| {
+        manifest
+            .sources
+            .get(source)
+            .and_then(|v| v.get("items"))
+            .and_then(Json::as_object)
+    };
+
+    for (i, pipeline) in manifest.pipelines.iter().enumerate() {
+        let mut osnames = Vec::new();
+        for (j, stage) in pipeline.stages.iter().enumerate() {
+            let path = format!("/pipelines/{}/stages/{}", i, j);
+            let osname = stage.options.get("osname").and_then(Json::as_str);
+            if stage.r#type.as_str() == "org.osbuild.ostree.os-init" {
+                osnames.extend(osname);
+            }
+            if stage.r#type.as_str() != DEPLOY {
+                continue;
+            }
+
+            match osname {
+                None => acc.push(Diagnostic::new(
+                    "bootc-options",
+                    Severity::Error,
+                    &lint::pointer(&path, "options"),
+                    "deployment lacks `osname`",
+                )),
+                Some(v) if !osnames.contains(&v) => acc.push(Diagnostic::new(
+                    "bootc-os-init",
+                    Severity::Warning,
+                    &path,
+                    &format!("stateroot `{}` is not initialized before", v),
+                )),
+                Some(_) => {}
+            }
+            if !stage.options.contains_key("target_imgref") {
+                acc.push(Diagnostic::new(
+                    "bootc-options",
+                    Severity::Error,
+                    &lint::pointer(&path, "options"),
+                    "deployment lacks `target_imgref`",
+                ));
+            }
+
+            let references = stage
+                .inputs
+                .get("images")
+                .and_then(|v| v.get("references"))
+                .and_then(Json::as_object);
+            let Some(id) = references
+                .filter(|v| v.len() == 1)
+                .and_then(|v| v.keys().next())
+            else {
+                acc.push(Diagnostic::new(
+                    "bootc-input",
+                    Severity::Error,
+                    &lint::pointer(&path, "inputs"),
+                    "deployment needs a single image as `images` input",
+                ));
+                continue;
+            };
+
+            let path = format!("/pipelines/{}/stages/{}/inputs/images/references", i, j);
+            if let Some(item) = items("org.osbuild.skopeo").and_then(|v| v.get(id)) {
+                if item.pointer("/image/digest").is_none() {
+                    acc.push(Diagnostic::new(
+                        "bootc-unpinned",
+                        Severity::Warning,
+                        &lint::pointer("/sources/org.osbuild.skopeo/items", id),
+                        "image is not pinned by digest",
+                    ));
+                }
+            } else if !items("org.osbuild.containers-storage").is_some_and(|v| v.contains_key(id)) {
+                acc.push(Diagnostic::new(
+                    "bootc-source",
+                    Severity::Error,
+                    &lint::pointer(&path, id),
+                    &format!("image `{}` is not provided by a container source", id),
+                ));
+            }
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Deployment Generation and Checks
+    #[test]
+    fn verify_bootc() {
+        let image = Image::from_resolved(&Resolved {
+            reference: Reference::parse("quay.io/centos-bootc/centos-bootc:stream9").unwrap(),
+            digest: "sha256:m".to_owned(),
+            list_digest: None,
+            image_id: "sha256:id".to_owned(),
+        });
+        let manifest = manifest(&image, &Options::default());
+        assert!(check(&manifest).is_empty());
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq! {
+            json["sources"],
+            serde_json::json!({ "org.osbuild.skopeo": { "items": { "sha256:id": {
+                "image": { "name": "quay.io/centos-bootc/centos-bootc", "digest": "sha256:m" },
+            } } } }),
+        }
+        let deploy = &json["pipelines"][0]["stages"][4];
+        assert_eq!(deploy["type"], DEPLOY);
+        assert_eq!(
+            deploy["options"]["target_imgref"],
+            "ostree-unverified-registry:quay.io/centos-bootc/centos-bootc:stream9"
+        );
+        let text = manifest.to_string();
+        assert_eq!(text.parse::<Manifest2>().unwrap(), manifest);
+
+        let local = Image {
+            storage: Storage::Local,
+            digest: None,
+            ..image.clone()
+        };
+        assert_eq! {
+            serde_json::to_value(local.sources()).unwrap(),
+            serde_json::json!({ "org.osbuild.containers-storage": { "items": { "sha256:id": {} } } }),
+        }
+
+        let mut broken = manifest.clone();
+        broken.sources = Image {
+            digest: None,
+            ..image.clone()
+        }
+        .sources();
+        broken.pipelines[0].stages.remove(1);
+        let rules: Vec<_> = check(&broken).into_iter().map(|v| v.rule).collect();
+        assert_eq!(rules, ["bootc-os-init", "bootc-unpinned"]);
+
+        broken.sources.clear();
+        broken.pipelines[0].stages[3]
+            .options
+            .remove("target_imgref");
+        let rules: Vec<_> = check(&broken).into_iter().map(|v| v.rule).collect();
+        assert_eq!(rules, ["bootc-os-init", "bootc-options", "bootc-source"]);
+
+        broken.pipelines[0].stages[3].inputs.clear();
+        let rules: Vec<_> = check(&broken).into_iter().map(|v| v.rule).collect();
+        assert_eq!(rules, ["bootc-os-init", "bootc-options", "bootc-input"]);
+    }
+}
@@ -0,0 +1,917 @@
+//! Borrowed Manifests
+//!
+//! This module provides borrowed variants of the manifest types, for
+//! services that inspect large numbers of manifests and only look at a few
+//! members of each. They deserialize from an in-memory buffer and borrow
+//! strings from it, rather than allocating them. Strings only need to be
+//! allocated if they contain escape sequences. Lists of pipelines and
+//! stages, and build pipelines, are allocated in a bump arena (`Bump`)
+//! passed to the parser, so releasing them is a matter of resetting the
+//! arena. Options, sources, and other inner payload are not parsed at all,
+//! but kept as raw JSON text. They are only parsed when accessed, into
+//! `Json` or typed structures, so consumers pay just for the stages they
+//! look at. Batches of manifests can be kept in a `ManifestArena`, and
+//! large manifest files can be mapped into memory with
+//! `Manifest::from_path_mmap()` rather than read.
+//!
+//! The borrowed types reject unknown members like their owned counterparts,
+//! but unlike those, they do not reject arrays in place of objects.
+
+use crate::error::Error;
+use crate::lint::pointer;
+use crate::manifest::{Manifest, Manifest1, Manifest2, Object};
+use crate::track;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+pub use bumpalo::Bump;
+
+/// List of Borrowed Nodes
+///
+/// Lists of borrowed manifests are allocated in the bump arena of the
+/// parser.
+pub type List<'a, T> = bumpalo::collections::Vec<'a, T>;
+
+/// Borrowed Node
+///
+/// This is implemented by the borrowed manifests and their members. They
+/// can only be deserialized with a bump arena at hand, which their lists
+/// are allocated in.
+pub trait Node<'a>: Sized {
+    /// Deserialize the node, allocating its lists in the arena.
+    fn deserialize_in<D: serde::Deserializer<'a>>(
+        deserializer: D,
+        bump: &'a Bump,
+    ) -> Result<Self, D::Error>;
+
+    /// Parse Node
+    ///
+    /// Parse the node from JSON text, allocating its lists in the arena.
+    fn parse_in(text: &'a str, bump: &'a Bump) -> Result<Self, Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let v = Self::deserialize_in(&mut deserializer, bump)?;
+        deserializer.end()?;
+        Ok(v)
+    }
+}
+
+// Seed to deserialize nodes via `DeserializeSeed`, carrying the arena.
+struct Seed<'a, T>(&'a Bump, PhantomData<T>);
+
+impl<'a, T: Node<'a>> serde::de::DeserializeSeed<'a> for Seed<'a, T> {
+    type Value = T;
+
+    fn deserialize<D: serde::Deserializer<'a>>(self, deserializer: D) -> Result<T, D::Error> {
+        T::deserialize_in(deserializer, self.0)
+    }
+}
+
+// Value of members missing in the manifest.
+trait Empty<'a> {
+    fn empty(bump: &'a Bump) -> Self;
+}
+
+impl<'a, T> Empty<'a> for Option<T> {
+    fn empty(_: &'a Bump) -> Self {
+        None
+    }
+}
+
+impl<'a, T> Empty<'a> for List<'a, T> {
+    fn empty(bump: &'a Bump) -> Self {
+        List::new_in(bump)
+    }
+}
+
+// Implement `Node` for types that do not allocate, via `Deserialize`.
+macro_rules! leaf {
+    ($($type:ty),* $(,)?) => {
+        $(
+            impl<'a> Node<'a> for $type {
+                fn deserialize_in<D: serde::Deserializer<'a>>(
+                    deserializer: D,
+                    _: &'a Bump,
+                ) -> Result<Self, D::Error> {
+                    serde::Deserialize::deserialize(deserializer)
+                }
+            }
+        )*
+    };
+}
+
+leaf!(u64, &'a RawValue, Stage1Ref<'a>, Stage2Ref<'a>);
+
+// Implement `Node` for a struct of nodes, like `#[derive(Deserialize)]`
+// with `#[serde(deny_unknown_fields)]` would, but passing the arena down
+// to all members. Members are required, unless marked `= empty`, in which
+// case missing members take their `Empty` value.
+macro_rules! node {
+    ($type:ident { $($field:ident: $key:literal $(= $empty:ident)?),* $(,)? }) => {
+        impl<'a> Node<'a> for $type<'a> {
+            fn deserialize_in<D: serde::Deserializer<'a>>(
+                deserializer: D,
+                bump: &'a Bump,
+            ) -> Result<Self, D::Error> {
+                const FIELDS: &[&str] = &[$($key),*];
+
+                struct Visitor<'a>(&'a Bump);
+
+                impl<'a> serde::de::Visitor<'a> for Visitor<'a> {
+                    type Value = $type<'a>;
+
+                    fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(fmt, "struct {}", stringify!($type))
+                    }
+
+                    fn visit_map<A: serde::de::MapAccess<'a>>(
+                        self,
+                        mut map: A,
+                    ) -> Result<Self::Value, A::Error> {
+                        use serde::de::Error as _;
+
+                        $(let mut $field = None;)*
+                        while let Some(key) = map.next_key_seed(Seed::<Cow<str>>(self.0, PhantomData))? {
+                            match key.as_ref() {
+                                $(
+                                    $key => {
+                                        if $field.is_some() {
+                                            return Err(A::Error::duplicate_field($key));
+                                        }
+                                        $field = Some(map.next_value_seed(Seed(self.0, PhantomData))?);
+                                    }
+                                )*
+                                v => return Err(A::Error::unknown_field(v, FIELDS)),
+                            }
+                        }
+
+                        Ok($type {
+                            $(
+                                $field: match $field {
+                                    Some(v) => v,
+                                    None => node!(@missing A, self.0, $key $(, $empty)?),
+                                },
+                            )*
+                        })
+                    }
+                }
+
+                deserializer.deserialize_struct(stringify!($type), FIELDS, Visitor(bump))
+            }
+        }
+    };
+    (@missing $access:ident, $bump:expr, $key:literal) => {
+        return Err(<$access::Error as serde::de::Error>::missing_field($key))
+    };
+    (@missing $access:ident, $bump:expr, $key:literal, empty) => {
+        Empty::empty($bump)
+    };
+}
+
+impl<'a> Node<'a> for Cow<'a, str> {
+    fn deserialize_in<D: serde::Deserializer<'a>>(
+        deserializer: D,
+        _: &'a Bump,
+    ) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'a> serde::de::Visitor<'a> for Visitor {
+            type Value = Cow<'a, str>;
+
+            fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                fmt.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'a str) -> Result<Self::Value, E> {
+                Ok(Cow::Borrowed(v))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Cow::Owned(v.to_owned()))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+impl<'a, T: Node<'a>> Node<'a> for Option<T> {
+    fn deserialize_in<D: serde::Deserializer<'a>>(
+        deserializer: D,
+        bump: &'a Bump,
+    ) -> Result<Self, D::Error> {
+        struct Visitor<'a, T>(&'a Bump, PhantomData<T>);
+
+        impl<'a, T: Node<'a>> serde::de::Visitor<'a> for Visitor<'a, T> {
+            type Value = Option<T>;
+
+            fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                fmt.write_str("an optional value")
+            }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_some<D: serde::Deserializer<'a>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                T::deserialize_in(deserializer, self.0).map(Some)
+            }
+        }
+
+        deserializer.deserialize_option(Visitor(bump, PhantomData))
+    }
+}
+
+impl<'a, T: Node<'a> + 'a> Node<'a> for List<'a, T> {
+    fn deserialize_in<D: serde::Deserializer<'a>>(
+        deserializer: D,
+        bump: &'a Bump,
+    ) -> Result<Self, D::Error> {
+        struct Visitor<'a, T>(&'a Bump, PhantomData<T>);
+
+        impl<'a, T: Node<'a> + 'a> serde::de::Visitor<'a> for Visitor<'a, T> {
+            type Value = List<'a, T>;
+
+            fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                fmt.write_str("a sequence")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'a>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut acc = List::new_in(self.0);
+                while let Some(v) = seq.next_element_seed(Seed(self.0, PhantomData))? {
+                    acc.push(v);
+                }
+                Ok(acc)
+            }
+        }
+
+        deserializer.deserialize_seq(Visitor(bump, PhantomData))
+    }
+}
+
+impl<'a> Node<'a> for &'a Build1Ref<'a> {
+    fn deserialize_in<D: serde::Deserializer<'a>>(
+        deserializer: D,
+        bump: &'a Bump,
+    ) -> Result<Self, D::Error> {
+        Ok(bump.alloc(Build1Ref::deserialize_in(deserializer, bump)?))
+    }
+}
+
+/// Borrowed Manifest1
+#[derive(Clone, Debug)]
+#[derive(serde::Serialize)]
+pub struct Manifest1Ref<'a> {
+    pub pipeline: Pipeline1Ref<'a>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<&'a RawValue>,
+}
+
+node!(Manifest1Ref {
+    pipeline: "pipeline" = empty,
+    sources: "sources" = empty,
+});
+
+/// Borrowed Pipeline1
+#[derive(Clone, Debug)]
+#[derive(serde::Serialize)]
+pub struct Pipeline1Ref<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assembler: Option<Stage1Ref<'a>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<&'a Build1Ref<'a>>,
+
+    pub stages: List<'a, Stage1Ref<'a>>,
+}
+
+node!(Pipeline1Ref {
+    assembler: "assembler" = empty,
+    build: "build" = empty,
+    stages: "stages" = empty,
+});
+
+impl<'a> Empty<'a> for Pipeline1Ref<'a> {
+    fn empty(bump: &'a Bump) -> Self {
+        Self {
+            assembler: None,
+            build: None,
+            stages: List::new_in(bump),
+        }
+    }
+}
+
+/// Borrowed Build1
+#[derive(Clone, Debug)]
+#[derive(serde::Serialize)]
+pub struct Build1Ref<'a> {
+    pub pipeline: Pipeline1Ref<'a>,
+    pub runner: Cow<'a, str>,
+}
+
+node!(Build1Ref {
+    pipeline: "pipeline",
+    runner: "runner",
+});
+
+/// Borrowed Stage1
+///
+/// This represents both stages and assemblers of version-1 manifests.
+#[derive(Clone, Debug)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage1Ref<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<&'a RawValue>,
+}
+
+/// Borrowed Manifest2
+#[derive(Clone, Debug)]
+#[derive(serde::Serialize)]
+pub struct Manifest2Ref<'a> {
+    pub version: Cow<'a, str>,
+
+    pub pipelines: List<'a, Pipeline2Ref<'a>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<&'a RawValue>,
+}
+
+node!(Manifest2Ref {
+    version: "version",
+    pipelines: "pipelines" = empty,
+    sources: "sources" = empty,
+});
+
+/// Borrowed Pipeline2
+#[derive(Clone, Debug)]
+#[derive(serde::Serialize)]
+pub struct Pipeline2Ref<'a> {
+    pub name: Cow<'a, str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<Cow<'a, str>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runner: Option<Cow<'a, str>>,
+
+    #[serde(rename = "source-epoch", skip_serializing_if = "Option::is_none")]
+    pub source_epoch: Option<u64>,
+
+    pub stages: List<'a, Stage2Ref<'a>>,
+}
+
+node!(Pipeline2Ref {
+    name: "name",
+    build: "build" = empty,
+    runner: "runner" = empty,
+    source_epoch: "source-epoch" = empty,
+    stages: "stages" = empty,
+});
+
+/// Borrowed Stage2
+#[derive(Clone, Debug)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage2Ref<'a> {
+    #[serde(borrow)]
+    pub r#type: Cow<'a, str>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Cow<'a, str>>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<&'a RawValue>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<&'a RawValue>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub devices: Option<&'a RawValue>,
+
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub mounts: Option<&'a RawValue>,
+}
+
+// Parse raw payload on access. Errors carry the path of the payload in the
+// manifest, but no position, since the payload was parsed separately.
+fn parse_raw<'a, T: serde::Deserialize<'a>>(
+    raw: Option<&'a RawValue>,
+    version: u32,
+    path: &str,
+) -> Result<Option<T>, Error> {
+    raw.map(|v| track::from_str(v.get(), Some(version)))
+        .transpose()
+        .map_err(|e| match e {
+            Error::Parse {
+                version,
+                path: at,
+                message,
+                ..
+            } => Error::Parse {
+                version,
+                line: 0,
+                column: 0,
+                path: format!("{}{}", path, at),
+                message,
+            },
+            v => v,
+        })
+}
+
+// Parse a single item of raw sources on access.
+fn parse_source<'a, T: serde::Deserialize<'a>>(
+    sources: Option<&'a RawValue>,
+    version: u32,
+    name: &str,
+) -> Result<Option<T>, Error> {
+    let sources: Option<Object<&'a RawValue>> = parse_raw(sources, version, "/sources")?;
+    parse_raw(
+        sources.and_then(|v| v.get(name).copied()),
+        version,
+        &pointer("/sources", name),
+    )
+}
+
+impl<'a> Manifest1Ref<'a> {
+    /// Parse Borrowed Manifest
+    ///
+    /// Parse the manifest text, allocating its lists in the arena.
+    pub fn parse(text: &'a str, bump: &'a Bump) -> Result<Self, Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        Self::deserialize_in(&mut deserializer, bump)
+            .and_then(|v| deserializer.end().map(|_| v))
+            .map_err(|e| Error::parse(Some(1), e))
+    }
+
+    /// Convert to Owned Manifest
+    ///
+    /// Parse the raw payload and return the owned manifest. This fails if
+    /// the payload does not match the owned types.
+    pub fn to_owned_manifest(&self) -> Result<Manifest1, Error> {
+        serde_json::to_string(self)?.parse()
+    }
+
+    /// Parse Source
+    ///
+    /// Parse the source of the given type, like `org.osbuild.curl`, into
+    /// `Json` or a typed structure. Other sources are not parsed. Returns
+    /// `None` if the manifest has no such source.
+    pub fn source<T: serde::Deserialize<'a>>(&self, name: &str) -> Result<Option<T>, Error> {
+        parse_source(self.sources, 1, name)
+    }
+}
+
+impl<'a> Stage1Ref<'a> {
+    /// Parse Options
+    ///
+    /// Parse the options of the stage into `Json` or a typed structure.
+    /// Returns `None` if the stage has no options.
+    pub fn options<T: serde::Deserialize<'a>>(&self) -> Result<Option<T>, Error> {
+        parse_raw(self.options, 1, "/options")
+    }
+}
+
+impl Pipeline1Ref<'_> {
+    /// Iterate Stages Deeply
+    ///
+    /// Iterate the stages of the pipeline and its nested build pipelines in
+    /// execution order, like `Pipeline1::iter_stages_deep()`.
+    pub fn iter_stages_deep(&self) -> impl Iterator<Item = &Stage1Ref<'_>> {
+        let mut chain = vec![self];
+        while let Some(build) = &chain[chain.len() - 1].build {
+            chain.push(&build.pipeline);
+        }
+        chain.into_iter().rev().flat_map(|v| v.stages.iter())
+    }
+}
+
+impl<'a> Manifest2Ref<'a> {
+    /// Parse Borrowed Manifest
+    ///
+    /// Parse the manifest text, allocating its lists in the arena.
+    pub fn parse(text: &'a str, bump: &'a Bump) -> Result<Self, Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        Self::deserialize_in(&mut deserializer, bump)
+            .and_then(|v| deserializer.end().map(|_| v))
+            .map_err(|e| Error::parse(Some(2), e))
+    }
+
+    /// Convert to Owned Manifest
+    ///
+    /// Parse the raw payload and return the owned manifest. This fails if
+    /// the payload does not match the owned types.
+    pub fn to_owned_manifest(&self) -> Result<Manifest2, Error> {
+        serde_json::to_string(self)?.parse()
+    }
+
+    /// Parse Source
+    ///
+    /// Parse the source of the given type into `Json` or a typed structure,
+    /// like `Manifest1Ref::source()`.
+    pub fn source<T: serde::Deserialize<'a>>(&self, name: &str) -> Result<Option<T>, Error> {
+        parse_source(self.sources, 2, name)
+    }
+}
+
+impl<'a> Stage2Ref<'a> {
+    /// Parse Options
+    ///
+    /// Parse the options of the stage into `Json` or a typed structure.
+    /// Returns `None` if the stage has no options.
+    pub fn options<T: serde::Deserialize<'a>>(&self) -> Result<Option<T>, Error> {
+        parse_raw(self.options, 2, "/options")
+    }
+}
+
+/// Manifest Arena
+///
+/// An arena for batches of manifests. The texts of all manifests are
+/// appended to a single buffer, and manifests are parsed as borrowed types
+/// from it, with their lists allocated in a bump arena. Thus, the strings,
+/// inner payload, and nodes of all manifests of a batch share a few
+/// allocations, and `clear()` releases a batch without visiting its nodes,
+/// while keeping the memory for the next one.
+#[derive(Debug, Default)]
+pub struct ManifestArena {
+    buffer: String,
+    spans: Vec<std::ops::Range<usize>>,
+    bump: Bump,
+}
+
+impl ManifestArena {
+    /// Create Arena
+    ///
+    /// Create an arena with room for `bytes` bytes of manifest text.
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self {
+            buffer: String::with_capacity(bytes),
+            spans: Vec::new(),
+            bump: Bump::new(),
+        }
+    }
+
+    /// Add Manifest Text
+    ///
+    /// Copy the text into the arena and return its index.
+    pub fn push(&mut self, text: &str) -> usize {
+        let start = self.buffer.len();
+        self.buffer.push_str(text);
+        self.spans.push(start..self.buffer.len());
+        self.spans.len() - 1
+    }
+
+    /// Number of Manifests
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Whether the Arena is Empty
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Manifest Text by Index
+    pub fn text(&self, index: usize) -> Option<&str> {
+        Some(&self.buffer[self.spans.get(index)?.clone()])
+    }
+
+    /// Parse Manifest by Index
+    ///
+    /// Parse the manifest text at the given index as borrowed type, like
+    /// `Manifest1Ref` or `Manifest2Ref`, with its lists allocated in the
+    /// arena. Returns `None` if the index is out of bounds.
+    pub fn parse<'a, T: Node<'a>>(&'a self, index: usize) -> Option<Result<T, Error>> {
+        Some(T::parse_in(self.text(index)?, &self.bump))
+    }
+
+    /// Number of Bytes Allocated for Nodes
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+
+    /// Release All Manifests
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.spans.clear();
+        self.bump.reset();
+    }
+}
+
+// Memory mapping is only done on 64-bit unix, where the prototypes below
+// match the platform ABI. Elsewhere, files are read into a buffer.
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod map {
+    use std::os::fd::AsRawFd;
+
+    const PROT_READ: i32 = 1;
+    const MAP_PRIVATE: i32 = 2;
+
+    extern "C" {
+        fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, off: i64) -> *mut u8;
+        fn munmap(addr: *mut u8, len: usize) -> i32;
+    }
+
+    pub struct Map {
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    // The mapping is private and read-only, so it can be shared like a
+    // byte slice.
+    unsafe impl Send for Map {}
+    unsafe impl Sync for Map {}
+
+    impl Map {
+        pub fn new(file: &std::fs::File) -> std::io::Result<Self> {
+            let len = usize::try_from(file.metadata()?.len())
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::OutOfMemory))?;
+            if len == 0 {
+                // Empty mappings are rejected by the kernel.
+                return Ok(Self {
+                    ptr: std::ptr::null_mut(),
+                    len,
+                });
+            }
+
+            // SAFETY: The arguments describe a new private, read-only
+            // mapping of the file, which is checked for failure.
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    PROT_READ,
+                    MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr as isize == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Self { ptr, len })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            match self.len {
+                0 => &[],
+                // SAFETY: The mapping is valid for `len` bytes until it is
+                // dropped.
+                _ => unsafe { std::slice::from_raw_parts(self.ptr, self.len) },
+            }
+        }
+    }
+
+    impl Drop for Map {
+        fn drop(&mut self) {
+            if self.len > 0 {
+                // SAFETY: The mapping was created by `new()` and is no
+                // longer borrowed.
+                unsafe { munmap(self.ptr, self.len) };
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+mod map {
+    use std::io::Read;
+
+    pub struct Map(Vec<u8>);
+
+    impl Map {
+        pub fn new(mut file: &std::fs::File) -> std::io::Result<Self> {
+            let mut v = Vec::new();
+            file.read_to_end(&mut v)?;
+            Ok(Self(v))
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            &self.0
+        }
+    }
+}
+
+/// Mapped Manifest File
+///
+/// The text of a manifest file mapped into memory, as returned by
+/// `Manifest::from_path_mmap()`. Borrowed manifests can be parsed from it
+/// like from a `ManifestArena`. The file must not be modified while it is
+/// mapped, as changes would be visible through the mapping, or truncation
+/// would fault on access.
+pub struct ManifestFile {
+    map: map::Map,
+}
+
+impl ManifestFile {
+    /// Manifest Text
+    pub fn text(&self) -> &str {
+        // The text was validated as UTF-8 when the file was mapped.
+        std::str::from_utf8(self.map.bytes()).unwrap_or_default()
+    }
+
+    /// Parse Manifest
+    ///
+    /// Parse the manifest text as borrowed type, like `Manifest1Ref` or
+    /// `Manifest2Ref`, with its lists allocated in the given arena.
+    pub fn parse<'a, T: Node<'a>>(&'a self, bump: &'a Bump) -> Result<T, Error> {
+        T::parse_in(self.text(), bump)
+    }
+}
+
+impl std::fmt::Debug for ManifestFile {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("ManifestFile")
+            .field("len", &self.map.bytes().len())
+            .finish()
+    }
+}
+
+impl Manifest {
+    /// Map Manifest File
+    ///
+    /// Map the manifest file at the given path into memory, so borrowed
+    /// manifests can be parsed from it without copying its text. This
+    /// avoids reading large files with embedded sources into a buffer.
+    /// On platforms without memory mapping, the file is read instead.
+    pub fn from_path_mmap(path: &std::path::Path) -> Result<ManifestFile, Error> {
+        let file = std::fs::File::open(path)?;
+        let map = map::Map::new(&file)?;
+        if let Err(e) = std::str::from_utf8(map.bytes()) {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e,
+            )));
+        }
+        Ok(ManifestFile { map })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Borrowed Manifests
+    #[test]
+    fn verify_borrowed() {
+        let bump = Bump::new();
+        let text = r#"{
+            "pipeline": {
+                "build": {
+                    "pipeline": { "stages": [{ "name": "org.osbuild.rpm" }] },
+                    "runner": "org.osbuild.linux"
+                },
+                "stages": [{ "name": "org.osbuild.t\u0061r", "options": { "a": [1, 2] } }]
+            },
+            "sources": {}
+        }"#;
+
+        let manifest = Manifest1Ref::parse(text, &bump).unwrap();
+        let names: Vec<_> = manifest
+            .pipeline
+            .iter_stages_deep()
+            .map(|v| &v.name)
+            .collect();
+        assert_eq!(names, ["org.osbuild.rpm", "org.osbuild.tar"]);
+        assert!(matches!(names[0], Cow::Borrowed(_)));
+        assert!(matches!(names[1], Cow::Owned(_)));
+        assert_eq! {
+            manifest.pipeline.stages[0].options.unwrap().get(),
+            r#"{ "a": [1, 2] }"#,
+        }
+        assert_eq!(
+            manifest.to_owned_manifest().unwrap(),
+            serde_json::from_str::<Manifest1>(text).unwrap(),
+        );
+        assert!(Manifest1Ref::parse(r#"{ "pipelines": [] }"#, &bump).is_err());
+
+        let text = r#"{
+            "version": "2",
+            "pipelines": [{ "name": "tree", "source-epoch": 1, "stages": [{ "type": "org.osbuild.noop" }] }]
+        }"#;
+        let manifest = Manifest2Ref::parse(text, &bump).unwrap();
+        assert_eq!(manifest.pipelines[0].stages[0].r#type, "org.osbuild.noop");
+        assert_eq!(
+            manifest.to_owned_manifest().unwrap(),
+            serde_json::from_str::<Manifest2>(text).unwrap(),
+        );
+    }
+
+    // Verify Lazy Parsing
+    #[test]
+    fn verify_lazy() {
+        let bump = Bump::new();
+        #[derive(serde::Deserialize)]
+        struct Options<'a> {
+            path: &'a str,
+        }
+
+        let text = r#"{
+            "pipeline": { "stages": [{ "name": "org.osbuild.a", "options": { "path": "/a" } }] },
+            "sources": { "org.osbuild.curl": { "items": {} }, "org.osbuild.x": 1 }
+        }"#;
+        let manifest = Manifest1Ref::parse(text, &bump).unwrap();
+        let stage = &manifest.pipeline.stages[0];
+        assert_eq!(stage.options::<Options>().unwrap().unwrap().path, "/a");
+        assert_eq! {
+            stage.options::<crate::manifest::Json>().unwrap(),
+            Some(serde_json::json!({ "path": "/a" })),
+        }
+        assert!(matches! {
+            stage.options::<Object<u32>>(),
+            Err(Error::Parse { version: Some(1), path, .. }) if path == "/options/path",
+        });
+        assert_eq! {
+            manifest.source::<crate::manifest::Json>("org.osbuild.curl").unwrap(),
+            Some(serde_json::json!({ "items": {} })),
+        }
+        assert_eq!(manifest.source::<u32>("org.osbuild.x").unwrap(), Some(1));
+        assert!(manifest.source::<u32>("org.osbuild.y").unwrap().is_none());
+
+        let text = r#"{
+            "version": "2",
+            "pipelines": [{ "name": "tree", "stages": [{ "type": "org.osbuild.noop" }] }]
+        }"#;
+        let manifest = Manifest2Ref::parse(text, &bump).unwrap();
+        let stage = &manifest.pipelines[0].stages[0];
+        assert!(stage.options::<Options>().unwrap().is_none());
+        assert!(manifest
+            .source::<u32>("org.osbuild.curl")
+            .unwrap()
+            .is_none());
+    }
+
+    // Verify Mapped Manifest Files
+    #[test]
+    fn verify_mmap() {
+        let dir = std::env::temp_dir().join(format!("r-osbuild-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = r#"{ "version": "2", "pipelines": [{ "name": "tree" }] }"#;
+        std::fs::write(dir.join("manifest"), text).unwrap();
+        std::fs::write(dir.join("empty"), "").unwrap();
+        std::fs::write(dir.join("invalid"), b"\xff").unwrap();
+
+        let file = Manifest::from_path_mmap(&dir.join("manifest")).unwrap();
+        assert_eq!(file.text(), text);
+        let bump = Bump::new();
+        let manifest: Manifest2Ref = file.parse(&bump).unwrap();
+        assert_eq!(manifest.pipelines[0].name, "tree");
+        assert!(matches!(manifest.pipelines[0].name, Cow::Borrowed(_)));
+
+        let file = Manifest::from_path_mmap(&dir.join("empty")).unwrap();
+        assert_eq!(file.text(), "");
+        assert!(file.parse::<Manifest2Ref>(&bump).is_err());
+        assert!(matches! {
+            Manifest::from_path_mmap(&dir.join("invalid")),
+            Err(Error::Io(_)),
+        });
+        assert!(Manifest::from_path_mmap(&dir.join("none")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Verify Manifest Arenas
+    #[test]
+    fn verify_arena() {
+        let mut arena = ManifestArena::with_capacity(64);
+        assert_eq!(arena.push(r#"{ "pipeline": {} }"#), 0);
+        assert_eq!(arena.push(r#"{ "version": "2" }"#), 1);
+        assert_eq!(arena.len(), 2);
+
+        let v1: Manifest1Ref = arena.parse(0).unwrap().unwrap();
+        assert!(v1.pipeline.stages.is_empty());
+        let v2: Manifest2Ref = arena.parse(1).unwrap().unwrap();
+        assert_eq!(v2.version, "2");
+        assert!(arena.parse::<Manifest1Ref>(1).unwrap().is_err());
+        assert!(arena.parse::<Manifest1Ref>(2).is_none());
+
+        // Nodes are allocated in the arena, and released with it.
+        drop((v1, v2));
+        let before = arena.allocated_bytes();
+        arena.push(r#"{ "version": "2", "pipelines": [{ "name": "a" }, { "name": "b" }] }"#);
+        let v2: Manifest2Ref = arena.parse(2).unwrap().unwrap();
+        assert_eq!(
+            v2.pipelines.bump() as *const Bump,
+            &arena.bump as *const Bump
+        );
+        assert!(arena.allocated_bytes() >= before);
+        drop(v2);
+
+        arena.clear();
+        assert!(arena.is_empty());
+        assert!(arena.text(0).is_none());
+    }
+}
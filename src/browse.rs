@@ -0,0 +1,427 @@
+//! Manifest Browsing
+//!
+//! Generated manifests easily span thousands of lines, which makes them
+//! hard to follow in an editor. This module implements the model of an
+//! interactive manifest browser: a cursor on a node of the manifest, which
+//! moves into child nodes, back up, and along references from inputs to
+//! the pipelines and source items they refer to. Diagnostics are attached
+//! to the nodes their JSON pointers refer to, and summed up for subtrees,
+//! so problems can be found from the root.
+//!
+//! The browser renders its state as plain text. Front-ends only read
+//! commands and display screens, see `Browser::command()`.
+
+use crate::lint::{self, Diagnostic, Severity};
+use crate::manifest::Json;
+
+/// Browser Entry
+///
+/// A child of the current node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// Member name or array index of the child.
+    pub key: String,
+    /// JSON pointer of the child.
+    pub pointer: String,
+    /// One-line summary of the child.
+    pub summary: String,
+    /// Number of diagnostics in the subtree of the child.
+    pub diagnostics: usize,
+}
+
+/// Browser Reference
+///
+/// A reference from the subtree of the current node to another node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+    /// The reference as written in the manifest.
+    pub label: String,
+    /// JSON pointer of the referenced node.
+    pub target: String,
+}
+
+/// Manifest Browser
+#[derive(Clone, Debug)]
+pub struct Browser {
+    document: Json,
+    diagnostics: Vec<Diagnostic>,
+    path: String,
+    history: Vec<String>,
+}
+
+// Summarize a value on a single line.
+fn summary(value: &Json) -> String {
+    let name = ["name", "type"]
+        .iter()
+        .find_map(|k| value.get(k).and_then(Json::as_str));
+    match value {
+        Json::Object(v) => match name {
+            Some(name) => format!("{} {{{}}}", name, v.len()),
+            None => format!("{{{}}}", v.len()),
+        },
+        Json::Array(v) => format!("[{}]", v.len()),
+        Json::String(v) if v.chars().count() > 60 => {
+            format!("{:?}...", v.chars().take(57).collect::<String>())
+        }
+        v => v.to_string(),
+    }
+}
+
+// Check whether the pointer is the base, or below it.
+fn within(pointer: &str, base: &str) -> bool {
+    pointer == base
+        || pointer
+            .strip_prefix(base)
+            .is_some_and(|v| v.starts_with('/'))
+}
+
+impl Browser {
+    /// Create Browser
+    ///
+    /// Create a browser on the root of the document. Diagnostics whose
+    /// pointers do not resolve in the document are attached to the root.
+    pub fn new(document: Json, diagnostics: Vec<Diagnostic>) -> Self {
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|mut v| {
+                if document.pointer(&v.path).is_none() {
+                    v.path = String::new();
+                }
+                v
+            })
+            .collect();
+        Self {
+            document,
+            diagnostics,
+            path: String::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// JSON Pointer of the Current Node
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Current Node
+    pub fn node(&self) -> &Json {
+        self.document
+            .pointer(&self.path)
+            .expect("the cursor always points to a node")
+    }
+
+    /// Children of the Current Node
+    pub fn entries(&self) -> Vec<Entry> {
+        let children: Vec<(String, &Json)> = match self.node() {
+            Json::Object(v) => v.iter().map(|(k, v)| (k.clone(), v)).collect(),
+            Json::Array(v) => v
+                .iter()
+                .enumerate()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            _ => Vec::new(),
+        };
+        children
+            .into_iter()
+            .map(|(key, value)| {
+                let pointer = lint::pointer(&self.path, &key);
+                Entry {
+                    diagnostics: self.diagnostics_within(&pointer).len(),
+                    summary: summary(value),
+                    key,
+                    pointer,
+                }
+            })
+            .collect()
+    }
+
+    /// Diagnostics of a Subtree
+    ///
+    /// Return the diagnostics of the node at the pointer and its
+    /// descendants.
+    pub fn diagnostics_within(&self, pointer: &str) -> Vec<&Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|v| within(&v.path, pointer))
+            .collect()
+    }
+
+    /// References of the Current Node
+    ///
+    /// Return the references in the subtree of the current node that
+    /// resolve: names of pipelines, as `name:<pipeline>` or plain in
+    /// pipeline inputs, and identifiers of source items.
+    pub fn references(&self) -> Vec<Reference> {
+        let mut targets: Vec<(String, String)> = Vec::new();
+        if let Some(Json::Array(pipelines)) = self.document.get("pipelines") {
+            for (i, v) in pipelines.iter().enumerate() {
+                if let Some(name) = v.get("name").and_then(Json::as_str) {
+                    let target = format!("/pipelines/{}", i);
+                    targets.push((format!("name:{}", name), target.clone()));
+                    targets.push((name.to_owned(), target));
+                }
+            }
+        }
+        if let Some(Json::Object(sources)) = self.document.get("sources") {
+            for (source, v) in sources {
+                let base = lint::pointer(&lint::pointer("/sources", source), "items");
+                for item in v
+                    .get("items")
+                    .and_then(Json::as_object)
+                    .into_iter()
+                    .flatten()
+                {
+                    targets.push((item.0.clone(), lint::pointer(&base, item.0)));
+                }
+            }
+        }
+
+        // Only strings and member names in inputs and build references
+        // are considered, so pipeline names used elsewhere do not match.
+        fn collect<'a>(value: &'a Json, inputs: bool, acc: &mut Vec<&'a str>) {
+            match value {
+                Json::String(v) if inputs => acc.push(v),
+                Json::Array(v) => v.iter().for_each(|v| collect(v, inputs, acc)),
+                Json::Object(v) => v.iter().for_each(|(k, v)| {
+                    if inputs {
+                        acc.push(k);
+                    }
+                    let inputs = inputs || k == "inputs";
+                    match (k.as_str(), v) {
+                        ("build", Json::String(v)) => acc.push(v),
+                        _ => collect(v, inputs, acc),
+                    }
+                }),
+                _ => {}
+            }
+        }
+        let mut labels = Vec::new();
+        let inputs = self.path.split('/').any(|v| v == "inputs");
+        collect(self.node(), inputs, &mut labels);
+
+        let mut acc: Vec<Reference> = Vec::new();
+        for label in labels {
+            let target = targets.iter().find(|(k, _)| k == label);
+            if let Some((_, target)) = target {
+                if !acc.iter().any(|v| v.label == label) {
+                    acc.push(Reference {
+                        label: label.to_owned(),
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+        acc
+    }
+
+    /// Go to Node
+    ///
+    /// Move the cursor to the node at the pointer, remembering the current
+    /// node for `back()`. Returns `false` if no node is at the pointer.
+    pub fn goto(&mut self, pointer: &str) -> bool {
+        if self.document.pointer(pointer).is_none() {
+            return false;
+        }
+        let previous = std::mem::replace(&mut self.path, pointer.to_owned());
+        self.history.push(previous);
+        true
+    }
+
+    /// Enter Child
+    ///
+    /// Move the cursor to the child with the given member name or index.
+    pub fn enter(&mut self, key: &str) -> bool {
+        let pointer = lint::pointer(&self.path, key);
+        self.goto(&pointer)
+    }
+
+    /// Move to the Parent
+    pub fn up(&mut self) -> bool {
+        match self.path.rfind('/') {
+            Some(i) => {
+                let parent = self.path[..i].to_owned();
+                self.goto(&parent)
+            }
+            None => false,
+        }
+    }
+
+    /// Move Back
+    ///
+    /// Return to the node the cursor was on before the last move.
+    pub fn back(&mut self) -> bool {
+        match self.history.pop() {
+            Some(v) => {
+                self.path = v;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Render Screen
+    ///
+    /// Render the current node as text: its pointer, its children with
+    /// their summaries and number of diagnostics, the references of its
+    /// subtree, and the diagnostics of the node itself.
+    pub fn render(&self) -> String {
+        let mut acc = format!(
+            "{}\n",
+            if self.path.is_empty() {
+                "/"
+            } else {
+                &self.path
+            }
+        );
+        let entries = self.entries();
+        if entries.is_empty() {
+            acc += &format!("  {}\n", self.node());
+        }
+        for (i, v) in entries.iter().enumerate() {
+            let marker = match v.diagnostics {
+                0 => String::new(),
+                n => format!("  ({} diagnostics)", n),
+            };
+            acc += &format!("  {:>3}  {}: {}{}\n", i, v.key, v.summary, marker);
+        }
+        for (i, v) in self.references().iter().enumerate() {
+            acc += &format!("  ->{} {} => {}\n", i, v.label, v.target);
+        }
+        for v in self.diagnostics.iter().filter(|v| v.path == self.path) {
+            let severity = match v.severity {
+                Severity::Note => "note",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            acc += &format!("  {}: {} [{}]\n", severity, v.message, v.rule);
+        }
+        acc
+    }
+
+    /// Run Command
+    ///
+    /// Run a command of a line-based front-end and return the message to
+    /// show, or `None` for the quit command. Commands are:
+    ///
+    ///  * `<n>` or `<key>`: enter the child with the given index or key,
+    ///  * `..`: move to the parent,
+    ///  * `b`: move back,
+    ///  * `f <n>`: follow the reference with the given index,
+    ///  * `/<pointer>`: go to the node at the pointer, or `/` to the root,
+    ///  * `d`: list all diagnostics of the subtree,
+    ///  * `q`: quit.
+    ///
+    /// An empty command renders the current node.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let moved = match line {
+            "" => true,
+            "q" => return None,
+            ".." => self.up(),
+            "b" => self.back(),
+            "/" => self.goto(""),
+            "d" => {
+                let mut acc = String::new();
+                for v in self.diagnostics_within(&self.path) {
+                    acc += &format!("{}: {} [{}]\n", v.path, v.message, v.rule);
+                }
+                return Some(acc);
+            }
+            v if v.starts_with('/') => self.goto(v),
+            v if v.starts_with("f ") => {
+                let target = v[2..]
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| self.references().into_iter().nth(i));
+                match target {
+                    Some(v) => self.goto(&v.target),
+                    None => false,
+                }
+            }
+            v => {
+                let entries = self.entries();
+                let key = v
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| entries.get(i))
+                    .map_or(v, |e| e.key.as_str())
+                    .to_owned();
+                self.enter(&key)
+            }
+        };
+        Some(match moved {
+            true => self.render(),
+            false => format!("no such node: {}\n", line),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Browsing
+    #[test]
+    fn verify_browse() {
+        let document = serde_json::json!({
+            "version": "2",
+            "pipelines": [
+                { "name": "build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                {
+                    "name": "os",
+                    "build": "name:build",
+                    "stages": [{
+                        "type": "org.osbuild.rpm",
+                        "inputs": { "packages": {
+                            "type": "org.osbuild.files",
+                            "origin": "org.osbuild.source",
+                            "references": ["sha256:aa"]
+                        } }
+                    }]
+                }
+            ],
+            "sources": { "org.osbuild.curl": { "items": { "sha256:aa": "https://example.com/aa" } } }
+        });
+        let diagnostics = vec![
+            Diagnostic::new("a", Severity::Error, "/pipelines/1/stages/0", "bad"),
+            Diagnostic::new("b", Severity::Note, "/pipeline/stages/0", "v1"),
+        ];
+        let mut browser = Browser::new(document, diagnostics);
+
+        let entries = browser.entries();
+        let pipelines = entries.iter().find(|v| v.key == "pipelines").unwrap();
+        assert_eq!(pipelines.summary, "[2]");
+        assert_eq!(pipelines.diagnostics, 1);
+        assert!(browser.render().contains("note: v1 [b]"));
+
+        assert!(browser.enter("pipelines"));
+        assert_eq!(browser.entries()[1].summary, "os {3}");
+        assert!(browser.command("1").unwrap().contains("(1 diagnostics)"));
+        assert_eq!(browser.path(), "/pipelines/1");
+        assert_eq! {
+            browser.references(),
+            vec![
+                Reference { label: "name:build".to_owned(), target: "/pipelines/0".to_owned() },
+                Reference {
+                    label: "sha256:aa".to_owned(),
+                    target: "/sources/org.osbuild.curl/items/sha256:aa".to_owned(),
+                },
+            ],
+        }
+
+        assert!(browser
+            .command("f 1")
+            .unwrap()
+            .contains("https://example.com/aa"));
+        assert_eq!(browser.path(), "/sources/org.osbuild.curl/items/sha256:aa");
+        browser.command("b");
+        assert_eq!(browser.path(), "/pipelines/1");
+        browser.command("..");
+        assert_eq!(browser.path(), "/pipelines");
+        assert!(browser.command("x").unwrap().starts_with("no such node"));
+        assert_eq!(browser.command("/").unwrap().lines().next(), Some("/"));
+        assert_eq!(browser.command("d").unwrap().lines().count(), 2);
+        assert_eq!(browser.command("q"), None);
+    }
+}
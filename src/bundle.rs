@@ -0,0 +1,410 @@
+//! Manifest Bundles
+//!
+//! A bundle ships a build as a single file: a tar archive with the
+//! manifest, its lockfile, and all source objects the manifest fetches,
+//! so it can be built at sites without network access. Unpacking a bundle
+//! yields an osbuild source cache, which makes osbuild find all objects
+//! without fetching them.
+//!
+//! The archive holds, in this order:
+//!
+//!  * `manifest.json`: the manifest,
+//!  * `lock.json`: the lockfile, pinning the digest of the manifest text
+//!    and the size of every source object,
+//!  * `sources/org.osbuild.files/<digest>`: the source objects, sorted by
+//!    digest, as found in the osbuild source cache.
+//!
+//! Both packing and unpacking verify every object against its digest, and
+//! unpacking verifies the bundle against the lockfile, rejecting missing,
+//! unexpected, or altered entries. Only file sources can be bundled.
+//! Inline items are part of the manifest anyway, while manifests with
+//! container or OSTree sources are rejected, since their objects are not
+//! plain files.
+
+use crate::hash::Digest;
+use crate::manifest::{Json, Manifest, ManifestFormat, Object};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Manifest Entry
+pub const MANIFEST: &str = "manifest.json";
+/// Lockfile Entry
+pub const LOCK: &str = "lock.json";
+/// Directory of Source Objects
+pub const OBJECTS: &str = "sources/org.osbuild.files";
+
+// Sources whose items are files in `OBJECTS`.
+const FILE_SOURCES: &[&str] = &["org.osbuild.curl", "org.osbuild.files"];
+
+/// Bundle Error
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation failed.
+    Io(std::io::Error),
+    /// The archive is malformed, or does not follow the bundle layout.
+    Format(String),
+    /// The manifest or lockfile cannot be parsed.
+    Manifest(crate::error::Error),
+    /// The manifest has items of a source that cannot be bundled.
+    Unsupported(String),
+    /// The source object with the given digest is missing.
+    Missing(String),
+    /// The content does not match the given digest or size.
+    Mismatch(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(v) => v.fmt(fmt),
+            Error::Format(v) => write!(fmt, "invalid bundle: {}", v),
+            Error::Manifest(v) => v.fmt(fmt),
+            Error::Unsupported(v) => write!(fmt, "cannot bundle items of source {}", v),
+            Error::Missing(v) => write!(fmt, "missing source object {}", v),
+            Error::Mismatch(v) => write!(fmt, "content does not match {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(v: std::io::Error) -> Self {
+        Error::Io(v)
+    }
+}
+
+/// Locked Source Object
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Locked {
+    /// Name of the source listing the object.
+    pub source: String,
+    pub size: u64,
+}
+
+/// Lockfile
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lock {
+    /// Digest of the text of `manifest.json`.
+    pub manifest: String,
+    /// Source objects, by digest.
+    pub objects: Object<Locked>,
+}
+
+/// Unpacked Bundle
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub lock: Lock,
+}
+
+// Return the digests of all objects the manifest fetches, with the name of
+// the source listing them.
+fn objects(manifest: &Manifest) -> Result<Object<String>, Error> {
+    let mut acc = Object::new();
+    for (name, source) in manifest.sources() {
+        let items = source
+            .get("items")
+            .or_else(|| source.get("urls"))
+            .and_then(Json::as_object);
+        let Some(items) = items.filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        if name == "org.osbuild.inline" {
+            continue;
+        }
+        if !FILE_SOURCES.contains(&name.as_str()) {
+            return Err(Error::Unsupported(name.to_string()));
+        }
+        for digest in items.keys() {
+            if digest.parse::<Digest>().is_err() {
+                return Err(Error::Format(format!("invalid digest {}", digest)));
+            }
+            acc.insert(digest.clone(), name.to_string());
+        }
+    }
+    Ok(acc)
+}
+
+// Write a tar header of a regular file, splitting long names into the
+// prefix and name fields of the ustar format.
+fn header(name: &str, size: u64, writer: &mut dyn Write) -> Result<(), Error> {
+    let (prefix, name) = match name.len() {
+        0..=100 => ("", name),
+        _ => name
+            .char_indices()
+            .filter(|(i, c)| *c == '/' && *i <= 155 && name.len() - i - 1 <= 100)
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .next()
+            .ok_or_else(|| Error::Format(format!("name too long: {}", name)))?,
+    };
+
+    let mut block = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        block[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\x0000");
+    field(345, prefix.as_bytes());
+    let sum: u32 = block.iter().map(|v| *v as u32).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    writer.write_all(&block)?;
+    Ok(())
+}
+
+fn entry(name: &str, data: &[u8], writer: &mut dyn Write) -> Result<(), Error> {
+    header(name, data.len() as u64, writer)?;
+    writer.write_all(data)?;
+    writer.write_all(&[0; 512][..(512 - data.len() % 512) % 512])?;
+    Ok(())
+}
+
+/// Pack Bundle
+///
+/// Write a bundle of the manifest to the writer, taking source objects
+/// from the osbuild source cache at the given path, which has a
+/// subdirectory per source, like `org.osbuild.files`. Each object is
+/// verified against its digest. Returns the lockfile of the bundle.
+pub fn pack(manifest: &Manifest, cache: &Path, writer: &mut dyn Write) -> Result<Lock, Error> {
+    let text = manifest.serialize();
+    let mut lock = Lock {
+        manifest: Digest::sha256(text.as_bytes()).to_string(),
+        objects: Object::new(),
+    };
+
+    // Objects are read twice, first to verify them and fill the lockfile,
+    // which precedes them in the archive, then to write them. Only one of
+    // them is held in memory at a time.
+    let read = |digest: &String| -> Result<Vec<u8>, Error> {
+        let path = cache.join("org.osbuild.files").join(digest);
+        let content = match std::fs::read(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::Missing(digest.clone()))
+            }
+            v => v?,
+        };
+        let parsed: Digest = digest.parse().expect("digests are validated");
+        match parsed.verify(&content) {
+            Some(true) => Ok(content),
+            _ => Err(Error::Mismatch(digest.clone())),
+        }
+    };
+
+    let objects = objects(manifest)?;
+    for (digest, source) in &objects {
+        let size = read(digest)?.len() as u64;
+        let source = source.clone();
+        lock.objects.insert(digest.clone(), Locked { source, size });
+    }
+
+    entry(MANIFEST, text.as_bytes(), writer)?;
+    let lock_text = serde_json::to_string_pretty(&lock).expect("lockfiles always serialize");
+    entry(LOCK, lock_text.as_bytes(), writer)?;
+    for digest in objects.keys() {
+        entry(&format!("{}/{}", OBJECTS, digest), &read(digest)?, writer)?;
+    }
+    writer.write_all(&[0; 1024])?;
+    Ok(lock)
+}
+
+// Parse a numeric tar header field.
+fn octal(field: &[u8]) -> Result<u64, Error> {
+    let text = std::str::from_utf8(field)
+        .ok()
+        .map(|v| v.trim_matches(|c: char| c == '\0' || c == ' '))
+        .ok_or_else(|| Error::Format("invalid header field".to_owned()))?;
+    u64::from_str_radix(text, 8).map_err(|_| Error::Format("invalid header field".to_owned()))
+}
+
+fn string(field: &[u8]) -> Result<&str, Error> {
+    let end = field.iter().position(|v| *v == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).map_err(|_| Error::Format("invalid name".to_owned()))
+}
+
+// Read the next tar entry, returning its name and content, or `None` at
+// the end of the archive.
+fn next(reader: &mut dyn Read) -> Result<Option<(String, Vec<u8>)>, Error> {
+    let mut block = [0u8; 512];
+    reader.read_exact(&mut block)?;
+    if block.iter().all(|v| *v == 0) {
+        return Ok(None);
+    }
+
+    let sum: u64 = block[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&block[156..])
+        .map(|v| *v as u64)
+        .sum();
+    if sum != octal(&block[148..156])? {
+        return Err(Error::Format("header checksum mismatch".to_owned()));
+    }
+    if block[156] != b'0' && block[156] != 0 {
+        return Err(Error::Format("entries must be regular files".to_owned()));
+    }
+
+    let (name, prefix) = (string(&block[..100])?, string(&block[345..500])?);
+    let name = match prefix.is_empty() {
+        true => name.to_owned(),
+        false => format!("{}/{}", prefix, name),
+    };
+    let size = octal(&block[124..136])?;
+    let mut content = Vec::new();
+    reader.take(size).read_to_end(&mut content)?;
+    if content.len() as u64 != size {
+        return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    let padding = (512 - size % 512) % 512;
+    std::io::copy(&mut reader.take(padding), &mut std::io::sink())?;
+    Ok(Some((name, content)))
+}
+
+// Read the entry with the given name, which must come next.
+fn expect(reader: &mut dyn Read, name: &str) -> Result<Vec<u8>, Error> {
+    match next(reader)? {
+        Some((v, content)) if v == name => Ok(content),
+        _ => Err(Error::Format(format!("expected {}", name))),
+    }
+}
+
+/// Unpack Bundle
+///
+/// Read a bundle from the reader and verify it. If a target directory is
+/// given, the manifest and lockfile are written to it, and the source
+/// objects into its `sources` subdirectory, which can serve as osbuild
+/// source cache. Without a target, the bundle is only verified.
+pub fn unpack(reader: &mut dyn Read, target: Option<&Path>) -> Result<Bundle, Error> {
+    let text = expect(reader, MANIFEST)?;
+    let lock_text = expect(reader, LOCK)?;
+    let manifest = Manifest::parse_bytes(&text).map_err(Error::Manifest)?;
+    let lock: Lock = serde_json::from_slice(&lock_text)
+        .map_err(|e| Error::Manifest(crate::error::Error::parse(None, e)))?;
+
+    if Digest::sha256(&text).to_string() != lock.manifest {
+        return Err(Error::Mismatch(lock.manifest));
+    }
+    let expected = objects(&manifest)?;
+    if let Some(v) = expected.keys().find(|v| !lock.objects.contains_key(*v)) {
+        return Err(Error::Missing(v.clone()));
+    }
+    if let Some(v) = lock.objects.keys().find(|v| !expected.contains_key(*v)) {
+        return Err(Error::Format(format!("unexpected object {}", v)));
+    }
+
+    if let Some(v) = target {
+        std::fs::create_dir_all(v.join(OBJECTS))?;
+        std::fs::write(v.join(MANIFEST), &text)?;
+        std::fs::write(v.join(LOCK), &lock_text)?;
+    }
+
+    let mut seen = Object::new();
+    while let Some((name, content)) = next(reader)? {
+        let digest = name
+            .strip_prefix(OBJECTS)
+            .and_then(|v| v.strip_prefix('/'))
+            .filter(|v| lock.objects.contains_key(*v) && !seen.contains_key(*v))
+            .ok_or_else(|| Error::Format(format!("unexpected entry {}", name)))?;
+        let parsed: Digest = digest.parse().expect("digests are validated");
+        if parsed.verify(&content) != Some(true)
+            || content.len() as u64 != lock.objects[digest].size
+        {
+            return Err(Error::Mismatch(digest.to_owned()));
+        }
+        if let Some(v) = target {
+            std::fs::write(v.join(OBJECTS).join(digest), &content)?;
+        }
+        seen.insert(digest.to_owned(), ());
+    }
+    if let Some(v) = lock.objects.keys().find(|v| !seen.contains_key(*v)) {
+        return Err(Error::Missing(v.clone()));
+    }
+
+    Ok(Bundle { manifest, lock })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Packing and Unpacking
+    #[test]
+    fn verify_bundle() {
+        let dir = std::env::temp_dir().join(format!("r-osbuild-bundle-{}", std::process::id()));
+        let cache = dir.join("cache");
+        std::fs::create_dir_all(cache.join("org.osbuild.files")).unwrap();
+        let (a, b) = (Digest::sha256(b"a"), Digest::sha256(&[b'b'; 600]));
+        std::fs::write(cache.join("org.osbuild.files").join(a.to_string()), b"a").unwrap();
+        std::fs::write(
+            cache.join("org.osbuild.files").join(b.to_string()),
+            [b'b'; 600],
+        )
+        .unwrap();
+
+        let manifest = Manifest::parse(&format!(
+            r#"{{
+                "version": "2",
+                "pipelines": [],
+                "sources": {{
+                    "org.osbuild.curl": {{ "items": {{ "{}": "https://example.com/a", "{}": "https://example.com/b" }} }},
+                    "org.osbuild.inline": {{ "items": {{ "{}": {{ "encoding": "base64", "data": "YQ==" }} }} }}
+                }}
+            }}"#,
+            a, b, a,
+        ))
+        .unwrap();
+
+        let mut archive = Vec::new();
+        let lock = pack(&manifest, &cache, &mut archive).unwrap();
+        assert_eq!(archive.len() % 512, 0);
+        assert_eq!(lock.objects.len(), 2);
+        assert_eq!(lock.objects[&b.to_string()].size, 600);
+
+        let target = dir.join("target");
+        let bundle = unpack(&mut archive.as_slice(), Some(&target)).unwrap();
+        assert_eq!(bundle.manifest, manifest);
+        assert_eq!(bundle.lock, lock);
+        assert_eq!(
+            std::fs::read(target.join(OBJECTS).join(b.to_string())).unwrap(),
+            [b'b'; 600]
+        );
+
+        // Altered objects are detected when packing and unpacking.
+        let mut altered = archive.clone();
+        let i = archive.len() - 1024 - 512;
+        altered[i] ^= 1;
+        assert!(matches!(
+            unpack(&mut altered.as_slice(), None),
+            Err(Error::Mismatch(_))
+        ));
+        std::fs::write(cache.join("org.osbuild.files").join(a.to_string()), b"x").unwrap();
+        assert!(matches!(
+            pack(&manifest, &cache, &mut Vec::new()),
+            Err(Error::Mismatch(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Truncated bundles lack objects.
+        let truncated = &archive[..512 * 4];
+        assert!(unpack(&mut &truncated[..], None).is_err());
+
+        let manifest = Manifest::parse(
+            r#"{ "version": "2", "pipelines": [], "sources": { "org.osbuild.skopeo": { "items": { "sha256:01": {} } } } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            pack(&manifest, &dir, &mut Vec::new()),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
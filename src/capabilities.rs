@@ -0,0 +1,285 @@
+//! Required Capabilities
+//!
+//! Builds need different privileges on the host, depending on what the
+//! manifest does: disk images need loop devices and mounts, encrypted or
+//! LVM volumes need device-mapper, SELinux labeling needs `CAP_MAC_ADMIN`,
+//! and sources fetch over the network. Schedulers use this to route builds
+//! to workers that provide these capabilities. This module computes the
+//! capabilities a manifest requires, with the parts of the manifest that
+//! require each of them.
+//!
+//! Requirements are derived from tables of stages, device types, and
+//! sources, similar to the capabilities osbuild modules declare. The
+//! tables only cover modules known to this crate, and can be extended, for
+//! instance with stages that run virtual machines and require KVM.
+
+use crate::lint;
+use crate::manifest::{Json, Manifest, ManifestFormat};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Host Capability
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Loop devices, to access image files as block devices.
+    Loop,
+    /// Privileged mounts of file systems.
+    Mount,
+    /// Device-mapper, for LUKS and LVM volumes.
+    DeviceMapper,
+    /// KVM, to run virtual machines.
+    Kvm,
+    /// Network access, to fetch sources.
+    Network,
+    /// `CAP_MAC_ADMIN`, to set SELinux labels unknown to the host policy.
+    MacAdmin,
+}
+
+impl Capability {
+    /// Name of the Capability
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Loop => "loop",
+            Capability::Mount => "mount",
+            Capability::DeviceMapper => "device-mapper",
+            Capability::Kvm => "kvm",
+            Capability::Network => "network",
+            Capability::MacAdmin => "mac-admin",
+        }
+    }
+}
+
+/// Stage Capability Table
+///
+/// Capabilities required by stages and assemblers, regardless of their
+/// options. The version-1 image assemblers partition, format, and mount
+/// their images themselves.
+pub const STAGES: &[(&str, &[Capability])] = &[
+    ("org.osbuild.qemu", &[Capability::Loop, Capability::Mount]),
+    ("org.osbuild.rawfs", &[Capability::Loop, Capability::Mount]),
+    ("org.osbuild.rpm", &[Capability::MacAdmin]),
+    ("org.osbuild.selinux", &[Capability::MacAdmin]),
+];
+
+/// Device Capability Table
+///
+/// Capabilities required by devices of version-2 stages, by device type.
+pub const DEVICES: &[(&str, &[Capability])] = &[
+    ("org.osbuild.loopback", &[Capability::Loop]),
+    ("org.osbuild.luks2", &[Capability::DeviceMapper]),
+    ("org.osbuild.lvm2.lv", &[Capability::DeviceMapper]),
+];
+
+/// Network Source Table
+///
+/// Sources that fetch their items over the network.
+pub const NETWORK_SOURCES: &[&str] = &[
+    "org.osbuild.curl",
+    "org.osbuild.files",
+    "org.osbuild.librepo",
+    "org.osbuild.ostree",
+    "org.osbuild.skopeo",
+];
+
+/// Capability Rules
+///
+/// The tables requirements are derived from. `Rules::default()` uses the
+/// tables of this module.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rules {
+    pub stages: BTreeMap<String, Vec<Capability>>,
+    pub devices: BTreeMap<String, Vec<Capability>>,
+    pub network_sources: BTreeSet<String>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        let table = |v: &[(&str, &[Capability])]| {
+            v.iter()
+                .map(|(k, v)| ((*k).to_owned(), v.to_vec()))
+                .collect()
+        };
+        Self {
+            stages: table(STAGES),
+            devices: table(DEVICES),
+            network_sources: NETWORK_SOURCES.iter().map(|v| (*v).to_owned()).collect(),
+        }
+    }
+}
+
+/// Capability Requirements
+///
+/// The capabilities required by a manifest, each with the JSON pointers of
+/// the parts of the manifest requiring it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Requirements(pub BTreeMap<Capability, Vec<String>>);
+
+impl Requirements {
+    fn add(&mut self, capabilities: &[Capability], pointer: &str) {
+        for v in capabilities {
+            self.0.entry(*v).or_default().push(pointer.to_owned());
+        }
+    }
+
+    /// Check for a Capability
+    pub fn requires(&self, capability: Capability) -> bool {
+        self.0.contains_key(&capability)
+    }
+
+    /// Required Capabilities
+    pub fn capabilities(&self) -> impl Iterator<Item = Capability> + '_ {
+        self.0.keys().copied()
+    }
+}
+
+impl Rules {
+    /// Compute Requirements
+    ///
+    /// Return the capabilities the manifest requires, according to the
+    /// rules. Version-2 stages with mounts require privileged mounts.
+    /// Sources only require network access if they have items.
+    pub fn requirements(&self, manifest: &Manifest) -> Requirements {
+        let mut acc = Requirements::default();
+        let stage = |acc: &mut Requirements, name: &str, pointer: &str| {
+            if let Some(v) = self.stages.get(name) {
+                acc.add(v, pointer);
+            }
+        };
+
+        match manifest {
+            Manifest::V1(v) => {
+                for (pointer, v) in lint::all_stages(v) {
+                    stage(&mut acc, &v.name, &pointer);
+                }
+                if let Some(v) = &v.pipeline.assembler {
+                    stage(&mut acc, &v.name, "/pipeline/assembler");
+                }
+            }
+            Manifest::V2(v) => {
+                for (i, pipeline) in v.pipelines.iter().enumerate() {
+                    for (j, v) in pipeline.stages.iter().enumerate() {
+                        let pointer = format!("/pipelines/{}/stages/{}", i, j);
+                        stage(&mut acc, &v.r#type, &pointer);
+                        for (name, device) in &v.devices {
+                            let kind = device.get("type").and_then(Json::as_str);
+                            if let Some(v) = kind.and_then(|v| self.devices.get(v)) {
+                                let pointer = lint::pointer(&pointer, "devices");
+                                acc.add(v, &lint::pointer(&pointer, name));
+                            }
+                        }
+                        if !v.mounts.is_empty() {
+                            acc.add(&[Capability::Mount], &format!("{}/mounts", pointer));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (name, source) in manifest.sources() {
+            let items = source.get("items");
+            let empty = match items {
+                Some(Json::Object(v)) => v.is_empty(),
+                Some(Json::Array(v)) => v.is_empty(),
+                _ => true,
+            };
+            if self.network_sources.contains(name.as_str()) && !empty {
+                acc.add(&[Capability::Network], &lint::pointer("/sources", name));
+            }
+        }
+
+        acc
+    }
+}
+
+impl Manifest {
+    /// Required Capabilities
+    ///
+    /// Return the capabilities the manifest requires, according to the
+    /// default rules.
+    pub fn requirements(&self) -> Requirements {
+        Rules::default().requirements(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Capability Requirements
+    #[test]
+    fn verify_requirements() {
+        let v1 = Manifest::parse(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": { "stages": [{ "name": "org.osbuild.rpm" }] },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "assembler": { "name": "org.osbuild.qemu" }
+                },
+                "sources": { "org.osbuild.curl": { "items": {} } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq! {
+            serde_json::to_value(v1.requirements()).unwrap(),
+            serde_json::json!({
+                "loop": ["/pipeline/assembler"],
+                "mount": ["/pipeline/assembler"],
+                "mac-admin": ["/pipeline/build/pipeline/stages/0"],
+            }),
+        }
+
+        let v2 = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [{
+                    "name": "image",
+                    "stages": [
+                        { "type": "org.osbuild.truncate" },
+                        {
+                            "type": "org.osbuild.copy",
+                            "devices": {
+                                "disk": { "type": "org.osbuild.loopback" },
+                                "root": { "type": "org.osbuild.luks2", "parent": "disk" }
+                            },
+                            "mounts": [{ "name": "root", "type": "org.osbuild.xfs", "source": "root", "target": "/" }]
+                        },
+                        { "type": "org.osbuild.coreos.live-artifacts.mono" }
+                    ]
+                }],
+                "sources": { "org.osbuild.curl": { "items": { "sha256:aa": "https://example.com/aa" } } }
+            }"#,
+        )
+        .unwrap();
+        let requirements = v2.requirements();
+        assert_eq! {
+            requirements.capabilities().collect::<Vec<_>>(),
+            vec![Capability::Loop, Capability::Mount, Capability::DeviceMapper, Capability::Network],
+        }
+        assert_eq! {
+            requirements.0[&Capability::DeviceMapper],
+            vec!["/pipelines/0/stages/1/devices/root"],
+        }
+        assert_eq!(
+            requirements.0[&Capability::Network],
+            vec!["/sources/org.osbuild.curl"]
+        );
+
+        // Custom rules add capabilities of further stages.
+        let mut rules = Rules::default();
+        rules.stages.insert(
+            "org.osbuild.coreos.live-artifacts.mono".to_owned(),
+            vec![Capability::Kvm],
+        );
+        let requirements = rules.requirements(&v2);
+        assert!(requirements.requires(Capability::Kvm));
+        assert_eq!(
+            requirements.0[&Capability::Kvm],
+            vec!["/pipelines/0/stages/2"]
+        );
+        assert_eq!(Capability::DeviceMapper.as_str(), "device-mapper");
+    }
+}
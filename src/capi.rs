@@ -0,0 +1,309 @@
+//! C API
+//!
+//! This module exposes the manifest parser and validator via a stable C
+//! ABI, so C and C++ tooling, as well as bindings for other languages, can
+//! reuse them. The matching declarations are provided by
+//! `include/r-osbuild.h`. This module is only available with the `capi`
+//! feature.
+//!
+//! Manifests are handed out as opaque pointers. Strings returned by the
+//! API are NUL-terminated, allocated by this library, and must be released
+//! with `r_osbuild_string_free()`. Failing calls return a non-zero error
+//! code and, if the caller passed an error structure, fill it in.
+
+use crate::lint;
+use crate::manifest::Manifest1;
+use std::ffi::{c_char, c_int, CString};
+
+/// Success
+pub const R_OSBUILD_E_OK: c_int = 0;
+/// Invalid arguments were passed.
+pub const R_OSBUILD_E_INVALID_ARGUMENT: c_int = 1;
+/// The input is not a valid manifest.
+pub const R_OSBUILD_E_PARSE: c_int = 2;
+/// The manifest has error diagnostics.
+pub const R_OSBUILD_E_VALIDATION: c_int = 3;
+
+/// Error Information
+///
+/// Filled in by failing calls. `message` must be released with
+/// `r_osbuild_error_clear()`. `line` and `column` are 1-based positions
+/// of parse errors, or 0 if not applicable.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug)]
+pub struct r_osbuild_error {
+    pub code: c_int,
+    pub message: *mut c_char,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Opaque Manifest Handle
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+pub struct r_osbuild_manifest {
+    manifest: Manifest1,
+}
+
+// Convert a Rust string to an allocated C string. Interior NULs cannot be
+// represented and are replaced.
+fn to_c(s: String) -> *mut c_char {
+    CString::new(s.replace('\0', "\u{fffd}"))
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+// Record an error in the caller-provided structure, if any.
+unsafe fn fail(
+    error: *mut r_osbuild_error,
+    code: c_int,
+    message: String,
+    pos: (usize, usize),
+) -> c_int {
+    if let Some(error) = error.as_mut() {
+        r_osbuild_error_clear(error);
+        error.code = code;
+        error.message = to_c(message);
+        error.line = pos.0;
+        error.column = pos.1;
+    }
+    code
+}
+
+/// Parse Manifest
+///
+/// Parse `len` bytes of JSON at `data` as manifest, strictly rejecting
+/// unknown members. On success, a new handle is stored in `manifest`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `manifest` must be a valid
+/// pointer, and `error` must be NULL or a valid pointer to an initialized
+/// error structure.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_manifest_parse(
+    data: *const c_char,
+    len: usize,
+    manifest: *mut *mut r_osbuild_manifest,
+    error: *mut r_osbuild_error,
+) -> c_int {
+    if data.is_null() || manifest.is_null() {
+        return fail(
+            error,
+            R_OSBUILD_E_INVALID_ARGUMENT,
+            "invalid argument".to_owned(),
+            (0, 0),
+        );
+    }
+
+    let data = std::slice::from_raw_parts(data as *const u8, len);
+    match serde_json::from_slice::<Manifest1>(data) {
+        Ok(v) => {
+            *manifest = Box::into_raw(Box::new(r_osbuild_manifest { manifest: v }));
+            R_OSBUILD_E_OK
+        }
+        Err(e) => fail(
+            error,
+            R_OSBUILD_E_PARSE,
+            e.to_string(),
+            (e.line(), e.column()),
+        ),
+    }
+}
+
+/// Validate Manifest
+///
+/// Run all lints on the manifest. If `diagnostics` is not NULL, the
+/// diagnostics are stored there as JSON array of objects with `rule`,
+/// `severity`, `path`, and `message` members. Fails with
+/// `R_OSBUILD_E_VALIDATION` if any diagnostic has error severity.
+///
+/// # Safety
+///
+/// `manifest` must be a handle returned by `r_osbuild_manifest_parse()`,
+/// `diagnostics` must be NULL or a valid pointer, and `error` must be NULL
+/// or a valid pointer to an initialized error structure.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_manifest_validate(
+    manifest: *const r_osbuild_manifest,
+    diagnostics: *mut *mut c_char,
+    error: *mut r_osbuild_error,
+) -> c_int {
+    let manifest = match manifest.as_ref() {
+        None => {
+            return fail(
+                error,
+                R_OSBUILD_E_INVALID_ARGUMENT,
+                "invalid argument".to_owned(),
+                (0, 0),
+            )
+        }
+        Some(v) => &v.manifest,
+    };
+
+    let list = lint::check(manifest);
+    if let Some(diagnostics) = diagnostics.as_mut() {
+        *diagnostics = to_c(serde_json::to_string(&list).unwrap_or_default());
+    }
+
+    match list
+        .iter()
+        .filter(|v| v.severity == lint::Severity::Error)
+        .count()
+    {
+        0 => R_OSBUILD_E_OK,
+        n => fail(
+            error,
+            R_OSBUILD_E_VALIDATION,
+            format!("manifest has {} error diagnostics", n),
+            (0, 0),
+        ),
+    }
+}
+
+/// Serialize Manifest
+///
+/// Serialize the manifest to JSON and store the string in `json`.
+///
+/// # Safety
+///
+/// `manifest` must be a handle returned by `r_osbuild_manifest_parse()`,
+/// `json` must be a valid pointer, and `error` must be NULL or a valid
+/// pointer to an initialized error structure.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_manifest_to_json(
+    manifest: *const r_osbuild_manifest,
+    json: *mut *mut c_char,
+    error: *mut r_osbuild_error,
+) -> c_int {
+    let manifest = match (manifest.as_ref(), json.is_null()) {
+        (Some(v), false) => &v.manifest,
+        _ => {
+            return fail(
+                error,
+                R_OSBUILD_E_INVALID_ARGUMENT,
+                "invalid argument".to_owned(),
+                (0, 0),
+            )
+        }
+    };
+
+    match serde_json::to_string(manifest) {
+        Ok(v) => {
+            *json = to_c(v);
+            R_OSBUILD_E_OK
+        }
+        Err(e) => fail(error, R_OSBUILD_E_PARSE, e.to_string(), (0, 0)),
+    }
+}
+
+/// Free Manifest
+///
+/// # Safety
+///
+/// `manifest` must be NULL or a handle returned by
+/// `r_osbuild_manifest_parse()` that was not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_manifest_free(manifest: *mut r_osbuild_manifest) {
+    if !manifest.is_null() {
+        drop(Box::from_raw(manifest));
+    }
+}
+
+/// Free String
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library that was not
+/// freed before.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Clear Error
+///
+/// Release the message of the error and reset it to success.
+///
+/// # Safety
+///
+/// `error` must be NULL or a valid pointer to an initialized error
+/// structure.
+#[no_mangle]
+pub unsafe extern "C" fn r_osbuild_error_clear(error: *mut r_osbuild_error) {
+    if let Some(error) = error.as_mut() {
+        r_osbuild_string_free(error.message);
+        error.code = R_OSBUILD_E_OK;
+        error.message = std::ptr::null_mut();
+        error.line = 0;
+        error.column = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Borrow a string returned by this library.
+    unsafe fn from_c<'a>(s: *const c_char) -> &'a str {
+        std::ffi::CStr::from_ptr(s).to_str().unwrap()
+    }
+
+    fn error() -> r_osbuild_error {
+        r_osbuild_error {
+            code: 0,
+            message: std::ptr::null_mut(),
+            line: 0,
+            column: 0,
+        }
+    }
+
+    // Verify C API
+    #[test]
+    fn verify_capi() {
+        let mut err = error();
+        let mut manifest = std::ptr::null_mut();
+
+        unsafe {
+            let input = br#"{ "pipeline": { "stages": [] }, "sources": {} }"#;
+            assert_eq! {
+                r_osbuild_manifest_parse(input.as_ptr() as _, input.len(), &mut manifest, &mut err),
+                R_OSBUILD_E_OK,
+            }
+
+            let mut json = std::ptr::null_mut();
+            assert_eq!(r_osbuild_manifest_to_json(manifest, &mut json, &mut err), 0);
+            assert!(from_c(json).starts_with("{\"pipeline\":"));
+            r_osbuild_string_free(json);
+
+            let mut diagnostics = std::ptr::null_mut();
+            assert_eq!(
+                r_osbuild_manifest_validate(manifest, &mut diagnostics, &mut err),
+                0
+            );
+            assert!(from_c(diagnostics).starts_with('['));
+            r_osbuild_string_free(diagnostics);
+            r_osbuild_manifest_free(manifest);
+
+            // Unknown members are rejected, with position.
+            let input = b"{\n  \"pipelines\": {} }";
+            assert_eq! {
+                r_osbuild_manifest_parse(input.as_ptr() as _, input.len(), &mut manifest, &mut err),
+                R_OSBUILD_E_PARSE,
+            }
+            assert_eq!(err.code, R_OSBUILD_E_PARSE);
+            assert_eq!(err.line, 2);
+            assert!(from_c(err.message).contains("pipelines"));
+
+            assert_eq! {
+                r_osbuild_manifest_to_json(std::ptr::null(), &mut json, std::ptr::null_mut()),
+                R_OSBUILD_E_INVALID_ARGUMENT,
+            }
+            r_osbuild_error_clear(&mut err);
+            assert!(err.message.is_null());
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Stage Catalog
+//!
+//! This module bundles metadata about the stages, assemblers, and sources
+//! known to this crate: a short description, the osbuild release that
+//! introduced them where known, and a summary of their most common options.
+//! The catalog can be rendered as JSON or Markdown, for documentation and
+//! editor tooling.
+//!
+//! The catalog is not exhaustive. Manifests can use any stage osbuild
+//! provides, regardless of whether it is listed here.
+
+use crate::manifest::Json;
+
+/// Entry Kind
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Kind {
+    /// Stage, run as part of a pipeline.
+    Stage,
+    /// Assembler, producing the artifact of a version-1 pipeline. Version-2
+    /// manifests run assemblers as regular stages.
+    Assembler,
+    /// Source, fetching content referenced by the manifest.
+    Source,
+}
+
+impl Kind {
+    /// Name of the Kind
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Stage => "stage",
+            Kind::Assembler => "assembler",
+            Kind::Source => "source",
+        }
+    }
+}
+
+/// Catalog Entry
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Entry {
+    /// Name of the stage, assembler, or source.
+    pub name: &'static str,
+    /// Kind of the entry.
+    pub kind: Kind,
+    /// One-line description.
+    pub description: &'static str,
+    /// osbuild release that introduced the entry, if known.
+    pub since: Option<&'static str>,
+    /// Common options, as tuples of option name and description.
+    pub options: &'static [(&'static str, &'static str)],
+}
+
+/// Catalog
+///
+/// All known entries, ordered by name and kind.
+pub const CATALOG: &[Entry] = &[
+    Entry {
+        name: "org.osbuild.chrony",
+        kind: Kind::Stage,
+        description: "Configure the chrony time service.",
+        since: None,
+        options: &[("timeservers", "List of NTP servers to use.")],
+    },
+    Entry {
+        name: "org.osbuild.copy",
+        kind: Kind::Stage,
+        description: "Copy files from inputs or mounts into the tree.",
+        since: None,
+        options: &[(
+            "paths",
+            "List of copy operations with `from` and `to` URLs.",
+        )],
+    },
+    Entry {
+        name: "org.osbuild.curl",
+        kind: Kind::Source,
+        description: "Download files by URL, verified by checksum.",
+        since: None,
+        options: &[("items", "Map of checksums to URLs or URL objects.")],
+    },
+    Entry {
+        name: "org.osbuild.dracut",
+        kind: Kind::Stage,
+        description: "Create initramfs images with dracut.",
+        since: None,
+        options: &[
+            ("kernel", "List of kernel versions to create images for."),
+            ("add_modules", "Additional dracut modules to include."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.files",
+        kind: Kind::Source,
+        description: "Download files by URL, verified by checksum.",
+        since: None,
+        options: &[("urls", "Map of checksums to URLs or URL objects.")],
+    },
+    Entry {
+        name: "org.osbuild.fix-bls",
+        kind: Kind::Stage,
+        description: "Fix paths in Boot Loader Specification entries.",
+        since: None,
+        options: &[("prefix", "Path prefix of the boot directory.")],
+    },
+    Entry {
+        name: "org.osbuild.fstab",
+        kind: Kind::Stage,
+        description: "Create the file system table `/etc/fstab`.",
+        since: None,
+        options: &[("filesystems", "List of file systems to mount.")],
+    },
+    Entry {
+        name: "org.osbuild.grub2",
+        kind: Kind::Stage,
+        description: "Configure the GRUB2 boot loader.",
+        since: None,
+        options: &[
+            ("rootfs", "Root file system, by `uuid` or `label`."),
+            ("bootfs", "Boot file system, if separate."),
+            ("kernel_opts", "Kernel command line."),
+            ("uefi", "UEFI configuration, with the `vendor` directory."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.hostname",
+        kind: Kind::Stage,
+        description: "Set the static host name.",
+        since: None,
+        options: &[("hostname", "Host name to set.")],
+    },
+    Entry {
+        name: "org.osbuild.inline",
+        kind: Kind::Source,
+        description: "Embed file contents in the manifest.",
+        since: None,
+        options: &[("items", "Map of checksums to `encoding` and `data`.")],
+    },
+    Entry {
+        name: "org.osbuild.keymap",
+        kind: Kind::Stage,
+        description: "Set the virtual console keyboard layout.",
+        since: None,
+        options: &[("keymap", "Name of the keyboard layout.")],
+    },
+    Entry {
+        name: "org.osbuild.locale",
+        kind: Kind::Stage,
+        description: "Set the system locale.",
+        since: None,
+        options: &[("language", "Locale, for example `en_US.UTF-8`.")],
+    },
+    Entry {
+        name: "org.osbuild.mkdir",
+        kind: Kind::Stage,
+        description: "Create directories in the tree.",
+        since: None,
+        options: &[("paths", "List of directories, with `path` and `mode`.")],
+    },
+    Entry {
+        name: "org.osbuild.noop",
+        kind: Kind::Stage,
+        description: "Do nothing, passing the tree through unchanged.",
+        since: None,
+        options: &[],
+    },
+    Entry {
+        name: "org.osbuild.ostree",
+        kind: Kind::Source,
+        description: "Fetch commits from OSTree repositories.",
+        since: None,
+        options: &[("items", "Map of commit checksums to `remote` objects.")],
+    },
+    Entry {
+        name: "org.osbuild.ostree",
+        kind: Kind::Stage,
+        description: "Initialize the tree from an OSTree commit.",
+        since: None,
+        options: &[
+            ("commit", "Checksum of the commit to deploy."),
+            ("ref", "Reference to create for the commit."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.qemu",
+        kind: Kind::Assembler,
+        description: "Assemble a bootable disk image for QEMU.",
+        since: None,
+        options: &[
+            ("format", "Image format, for example `qcow2` or `raw`."),
+            ("filename", "Name of the image file."),
+            ("size", "Size of the image, in bytes."),
+            ("ptuuid", "Partition table identifier."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.rawfs",
+        kind: Kind::Assembler,
+        description: "Assemble a raw file system image.",
+        since: None,
+        options: &[
+            ("filename", "Name of the image file."),
+            ("root_fs_uuid", "UUID of the file system."),
+            ("size", "Size of the image, in bytes."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.rpm",
+        kind: Kind::Stage,
+        description: "Install RPM packages into the tree.",
+        since: None,
+        options: &[
+            (
+                "packages",
+                "List of package checksums, or objects with `checksum`.",
+            ),
+            ("gpgkeys", "ASCII-armored GPG keys to import."),
+            ("exclude", "Paths to exclude, such as documentation."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.selinux",
+        kind: Kind::Stage,
+        description: "Label the tree according to the SELinux policy.",
+        since: None,
+        options: &[("file_contexts", "Path of the file contexts of the policy.")],
+    },
+    Entry {
+        name: "org.osbuild.skopeo",
+        kind: Kind::Source,
+        description: "Fetch container images from registries.",
+        since: None,
+        options: &[("items", "Map of image digests to `image` objects.")],
+    },
+    Entry {
+        name: "org.osbuild.skopeo",
+        kind: Kind::Stage,
+        description: "Copy container images into the tree.",
+        since: None,
+        options: &[("destination", "Container storage to copy the images to.")],
+    },
+    Entry {
+        name: "org.osbuild.systemd",
+        kind: Kind::Stage,
+        description: "Enable, disable, or mask systemd units.",
+        since: None,
+        options: &[
+            ("enabled_services", "Units to enable."),
+            ("disabled_services", "Units to disable."),
+            ("default_target", "Default target to boot into."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.tar",
+        kind: Kind::Assembler,
+        description: "Assemble the tree into a tar archive.",
+        since: None,
+        options: &[
+            ("filename", "Name of the archive."),
+            ("compression", "Compression, for example `xz` or `gzip`."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.timezone",
+        kind: Kind::Stage,
+        description: "Set the system time zone.",
+        since: None,
+        options: &[("zone", "Time zone, for example `Europe/Berlin`.")],
+    },
+    Entry {
+        name: "org.osbuild.truncate",
+        kind: Kind::Stage,
+        description: "Create or resize a file.",
+        since: None,
+        options: &[
+            ("filename", "Path of the file."),
+            ("size", "New size of the file."),
+        ],
+    },
+    Entry {
+        name: "org.osbuild.users",
+        kind: Kind::Stage,
+        description: "Create and modify user accounts.",
+        since: None,
+        options: &[("users", "Map of user names to account settings.")],
+    },
+];
+
+/// Look Up Entries
+///
+/// Return all entries with the given name. Names are shared between a stage
+/// and a source, if the source fetches content for the stage.
+pub fn lookup(name: &str) -> Vec<&'static Entry> {
+    CATALOG.iter().filter(|v| v.name == name).collect()
+}
+
+impl Entry {
+    /// Render as JSON
+    pub fn to_json(&self) -> Json {
+        let options: serde_json::Map<_, _> = self
+            .options
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), Json::String((*v).to_owned())))
+            .collect();
+        let mut acc = serde_json::json!({
+            "name": self.name,
+            "kind": self.kind.as_str(),
+            "description": self.description,
+            "options": options,
+        });
+        if let Some(v) = self.since {
+            acc["since"] = Json::String(v.to_owned());
+        }
+        acc
+    }
+
+    /// Render as Markdown
+    ///
+    /// Render the entry as Markdown section, with a level-2 heading and a
+    /// list of its options.
+    pub fn to_markdown(&self) -> String {
+        let mut acc = format!(
+            "## `{}` ({})\n\n{}\n",
+            self.name,
+            self.kind.as_str(),
+            self.description,
+        );
+        if let Some(v) = self.since {
+            acc.push_str(&format!("\nAvailable since osbuild {}.\n", v));
+        }
+        if !self.options.is_empty() {
+            acc.push_str("\nOptions:\n\n");
+            for (k, v) in self.options {
+                acc.push_str(&format!("- `{}`: {}\n", k, v));
+            }
+        }
+        acc
+    }
+}
+
+/// Render Entries as JSON
+pub fn to_json(entries: &[&Entry]) -> Json {
+    Json::Array(entries.iter().map(|v| v.to_json()).collect())
+}
+
+/// Render Entries as Markdown
+///
+/// Render the entries as Markdown document, separating the sections of
+/// the individual entries with empty lines.
+pub fn to_markdown(entries: &[&Entry]) -> String {
+    entries
+        .iter()
+        .map(|v| v.to_markdown())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Catalog
+    #[test]
+    fn verify_catalog() {
+        // Entries are sorted, and unique per name and kind.
+        for w in CATALOG.windows(2) {
+            assert!((w[0].name, w[0].kind.as_str()) < (w[1].name, w[1].kind.as_str()));
+        }
+
+        assert_eq!(lookup("org.osbuild.skopeo").len(), 2);
+        assert!(lookup("org.osbuild.unknown").is_empty());
+
+        let rpm = lookup("org.osbuild.rpm");
+        assert_eq! {
+            to_json(&rpm)[0]["options"]["packages"],
+            "List of package checksums, or objects with `checksum`.",
+        }
+        assert_eq! {
+            to_markdown(&lookup("org.osbuild.hostname")),
+            "## `org.osbuild.hostname` (stage)\n\nSet the static host name.\n\nOptions:\n\n- `hostname`: Host name to set.\n",
+        }
+
+        let entry = Entry {
+            since: Some("100"),
+            options: &[],
+            ..*lookup("org.osbuild.noop")[0]
+        };
+        assert_eq!(entry.to_json()["since"], "100");
+        assert_eq! {
+            entry.to_markdown(),
+            "## `org.osbuild.noop` (stage)\n\nDo nothing, passing the tree through unchanged.\n\nAvailable since osbuild 100.\n",
+        }
+        assert!(to_json(&lookup("org.osbuild.copy"))[0]
+            .get("since")
+            .is_none());
+    }
+}
@@ -0,0 +1,347 @@
+//! CBOR Serialization
+//!
+//! This module encodes manifests as CBOR (RFC 8949), for services that
+//! pass manifests over message queues, where the size and parse cost of
+//! JSON text matter. Values are encoded via their JSON form, so the CBOR
+//! form carries exactly the data of the JSON form and converts back to it
+//! losslessly.
+//!
+//! Encoding uses the preferred serialization: the shortest heads, definite
+//! lengths, and 64-bit floats. Decoding also accepts indefinite lengths,
+//! shorter floats, and tags, which are skipped. Byte strings and simple
+//! values other than booleans and null have no JSON equivalent and are
+//! rejected.
+
+use crate::manifest::{Json, Manifest};
+
+// Maximum nesting of arrays and maps, matching the JSON parser.
+const MAX_DEPTH: usize = 128;
+
+/// CBOR Error
+#[derive(Debug)]
+pub enum Error {
+    /// The data ended before the value was complete.
+    Truncated,
+    /// The data is not well-formed CBOR, or has no JSON equivalent.
+    Invalid(&'static str),
+    /// The decoded value does not match the requested type.
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Truncated => write!(fmt, "truncated CBOR data"),
+            Error::Invalid(v) => write!(fmt, "invalid CBOR data: {}", v),
+            Error::Json(v) => v.fmt(fmt),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn head(major: u8, arg: u64, acc: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => acc.push(major | arg as u8),
+        24..=0xff => acc.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            acc.push(major | 25);
+            acc.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            acc.push(major | 26);
+            acc.extend_from_slice(&(arg as u32).to_be_bytes());
+        }
+        _ => {
+            acc.push(major | 27);
+            acc.extend_from_slice(&arg.to_be_bytes());
+        }
+    }
+}
+
+/// Encode JSON Value
+///
+/// Append the CBOR encoding of the value to the buffer.
+pub fn encode(value: &Json, acc: &mut Vec<u8>) {
+    match value {
+        Json::Null => acc.push(0xf6),
+        Json::Bool(false) => acc.push(0xf4),
+        Json::Bool(true) => acc.push(0xf5),
+        Json::Number(v) => {
+            if let Some(v) = v.as_u64() {
+                head(0, v, acc);
+            } else if let Some(v) = v.as_i64() {
+                head(1, !(v as u64), acc);
+            } else {
+                acc.push(0xfb);
+                acc.extend_from_slice(&v.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Json::String(v) => {
+            head(3, v.len() as u64, acc);
+            acc.extend_from_slice(v.as_bytes());
+        }
+        Json::Array(v) => {
+            head(4, v.len() as u64, acc);
+            v.iter().for_each(|v| encode(v, acc));
+        }
+        Json::Object(v) => {
+            head(5, v.len() as u64, acc);
+            for (k, v) in v {
+                head(3, k.len() as u64, acc);
+                acc.extend_from_slice(k.as_bytes());
+                encode(v, acc);
+            }
+        }
+    }
+}
+
+// Convert a half-precision float.
+fn half(bits: u16) -> f64 {
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        e => (1.0 + mantissa / 1024.0) * 2f64.powi(e as i32 - 15),
+    };
+    if bits & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        let v = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::Truncated)?;
+        self.pos += n;
+        Ok(v)
+    }
+
+    // Read the head of an item, returning its major type, additional
+    // information, and argument.
+    fn head(&mut self) -> Result<(u8, u8, u64), Error> {
+        let byte = self.take(1)?[0];
+        let (major, info) = (byte >> 5, byte & 0x1f);
+        let arg = match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 => 0,
+            _ => return Err(Error::Invalid("reserved additional information")),
+        };
+        Ok((major, info, arg))
+    }
+
+    fn text(&mut self, info: u8, arg: u64) -> Result<String, Error> {
+        if info != 31 {
+            let data = self.take(arg as usize)?;
+            return String::from_utf8(data.to_vec()).map_err(|_| Error::Invalid("invalid UTF-8"));
+        }
+
+        // Indefinite-length text is a sequence of definite-length chunks.
+        let mut acc = String::new();
+        loop {
+            match self.head()? {
+                (7, 31, _) => return Ok(acc),
+                (3, info, arg) if info != 31 => acc += &self.text(info, arg)?,
+                _ => return Err(Error::Invalid("invalid text chunk")),
+            }
+        }
+    }
+
+    // Check whether an array or map has more items, given the number of
+    // items read so far. Indefinite-length items end with a break.
+    fn more(&mut self, info: u8, i: u64, n: u64) -> Result<bool, Error> {
+        if info != 31 {
+            return Ok(i < n);
+        }
+        match self.data.get(self.pos) {
+            Some(0xff) => {
+                self.pos += 1;
+                Ok(false)
+            }
+            Some(_) => Ok(true),
+            None => Err(Error::Truncated),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Invalid("nesting too deep"));
+        }
+        let float = |v: f64| {
+            serde_json::Number::from_f64(v)
+                .map(Json::Number)
+                .ok_or(Error::Invalid("non-finite float"))
+        };
+
+        match self.head()? {
+            (0, 31, _) | (1, 31, _) | (6, 31, _) => Err(Error::Invalid("indefinite integer")),
+            (0, _, v) => Ok(Json::from(v)),
+            (1, _, v) => match i64::try_from(v) {
+                Ok(v) => Ok(Json::from(-1 - v)),
+                Err(_) => Err(Error::Invalid("integer out of range")),
+            },
+            (2, _, _) => Err(Error::Invalid("byte strings are not supported")),
+            (3, info, arg) => self.text(info, arg).map(Json::String),
+            (4, info, arg) => {
+                let mut acc = Vec::new();
+                let mut i = 0;
+                while self.more(info, i, arg)? {
+                    acc.push(self.value(depth + 1)?);
+                    i += 1;
+                }
+                Ok(Json::Array(acc))
+            }
+            (5, info, arg) => {
+                let mut acc = serde_json::Map::new();
+                let mut i = 0;
+                while self.more(info, i, arg)? {
+                    let key = match self.head()? {
+                        (3, info, arg) => self.text(info, arg)?,
+                        _ => return Err(Error::Invalid("map keys must be text")),
+                    };
+                    acc.insert(key, self.value(depth + 1)?);
+                    i += 1;
+                }
+                Ok(Json::Object(acc))
+            }
+            (6, _, _) => self.value(depth + 1),
+            (7, 20, _) => Ok(Json::Bool(false)),
+            (7, 21, _) => Ok(Json::Bool(true)),
+            (7, 22, _) => Ok(Json::Null),
+            (7, 25, v) => float(half(v as u16)),
+            (7, 26, v) => float(f32::from_bits(v as u32) as f64),
+            (7, 27, v) => float(f64::from_bits(v)),
+            _ => Err(Error::Invalid("unsupported simple value")),
+        }
+    }
+}
+
+/// Decode JSON Value
+///
+/// Decode a single CBOR item into a JSON value. Trailing data is an
+/// error.
+pub fn decode(data: &[u8]) -> Result<Json, Error> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let value = decoder.value(0)?;
+    match decoder.pos == data.len() {
+        true => Ok(value),
+        false => Err(Error::Invalid("trailing data")),
+    }
+}
+
+/// Serialize to CBOR
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: serde::Serialize + ?Sized,
+{
+    let mut acc = Vec::new();
+    encode(&serde_json::to_value(value)?, &mut acc);
+    Ok(acc)
+}
+
+/// Deserialize from CBOR
+pub fn from_slice<T>(data: &[u8]) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_json::from_value(decode(data)?).map_err(Error::Json)
+}
+
+impl Manifest {
+    /// Serialize to CBOR
+    pub fn to_cbor(&self) -> Vec<u8> {
+        match self {
+            Manifest::V1(v) => to_vec(v),
+            Manifest::V2(v) => to_vec(v),
+        }
+        .expect("manifests always serialize")
+    }
+
+    /// Parse CBOR
+    ///
+    /// Parse a manifest from its CBOR form, detecting the format version.
+    pub fn from_cbor(data: &[u8]) -> Result<Self, crate::error::Error> {
+        let value = decode(data).map_err(|e| crate::error::Error::Parse {
+            version: None,
+            line: 0,
+            column: 0,
+            path: String::new(),
+            message: e.to_string(),
+        })?;
+        Manifest::from_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify CBOR Encoding
+    #[test]
+    fn verify_cbor() {
+        let value = serde_json::json!({
+            "a": [0, 23, 24, 256, 65536, 4294967296u64, -1, -25, 1.5, true, null],
+            "é": "",
+        });
+        let mut acc = Vec::new();
+        encode(&value, &mut acc);
+        assert_eq!(&acc[..4], &[0xa2, 0x61, b'a', 0x8b]);
+        assert_eq!(&acc[4..11], &[0x00, 0x17, 0x18, 0x18, 0x19, 0x01, 0x00]);
+        assert_eq!(decode(&acc).unwrap(), value);
+
+        // Indefinite lengths, half floats, and tags.
+        let data = [
+            0xbf, 0x61, b'a', 0x9f, 0xf9, 0x3e, 0x00, 0xd9, 0xd9, 0xf7, 0x01, 0xff, 0xff,
+        ];
+        assert_eq!(decode(&data).unwrap(), serde_json::json!({ "a": [1.5, 1] }));
+
+        assert!(matches!(
+            decode(&[0x42, 0x00, 0x01]),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(decode(&[0x82, 0x01]), Err(Error::Truncated)));
+        assert!(matches!(decode(&[0x01, 0x01]), Err(Error::Invalid(_))));
+        assert!(matches!(decode(&[0x81; 200]), Err(Error::Invalid(_))));
+    }
+
+    // Verify Manifest Round-Trips
+    #[test]
+    fn verify_manifest() {
+        for text in [
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.rpm", "options": { "gpgkeys": ["x"] } }] } }"#,
+            r#"{ "version": "2", "pipelines": [{ "name": "os", "stages": [{ "type": "org.osbuild.noop" }] }], "sources": {} }"#,
+        ] {
+            let manifest = Manifest::parse(text).unwrap();
+            let cbor = manifest.to_cbor();
+            assert!(cbor.len() < text.len());
+            assert_eq!(Manifest::from_cbor(&cbor).unwrap(), manifest);
+            assert_eq!(
+                decode(&cbor).unwrap(),
+                serde_json::from_str::<Json>(&crate::manifest::ManifestFormat::serialize(
+                    &manifest
+                ))
+                .unwrap()
+            );
+        }
+        assert!(Manifest::from_cbor(&[
+            0xa1, 0x67, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0x61, b'3'
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,602 @@
+//! Blueprint Compilation
+//!
+//! This module compiles blueprints into manifest skeletons. Given a
+//! blueprint and the selection of distribution, architecture, and image
+//! type, it produces a manifest with a build pipeline, the customization
+//! stages matching the blueprint, and an assembler for the image type.
+//!
+//! The result is a skeleton, since package selection requires resolving
+//! package dependencies against repositories, which is out of scope here.
+//! Instead, the rpm stages of the skeleton are left empty and the package
+//! specifications to resolve are returned alongside the manifest. Once
+//! resolved, the checksums must be added to the rpm stages and the sources.
+//!
+//! Only simple cases are covered. Customizations that cannot be compiled
+//! are reported, rather than silently dropped.
+
+use crate::blueprint::{self, Blueprint};
+use crate::estimate;
+use crate::manifest::{
+    self, Assembler1, Build1, Json, Manifest1, Object, Pipeline1, Runner, Stage1,
+};
+use crate::options;
+use crate::uuid::{self, Uuid};
+
+/// Image Type
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ImageType {
+    /// Plain tarball of the file-system tree.
+    Tar,
+    /// Bootable disk image in qcow2 format.
+    Qcow2,
+    /// Bootable raw disk image.
+    Raw,
+}
+
+/// Compilation Target
+///
+/// The distribution, like `fedora-39` or `rhel-9.2`, the architecture, and
+/// the image type to compile a blueprint for.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Target {
+    pub distro: String,
+    pub arch: String,
+    pub image_type: ImageType,
+}
+
+/// Compilation Error
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The distribution is not known.
+    UnsupportedDistro(String),
+    /// The architecture is not supported.
+    UnsupportedArch(String),
+    /// The blueprint fails validation.
+    InvalidBlueprint(Vec<String>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedDistro(v) => write!(fmt, "unsupported distribution `{}`", v),
+            Error::UnsupportedArch(v) => write!(fmt, "unsupported architecture `{}`", v),
+            Error::InvalidBlueprint(v) => write!(fmt, "invalid blueprint: {}", v.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compiled Manifest
+///
+/// The result of compiling a blueprint. The rpm stages of the build and
+/// the main pipeline of the manifest are empty, and `build_packages` and
+/// `packages` list the package specifications to resolve for them.
+/// Customizations that were not compiled are listed in `unsupported` by
+/// their blueprint key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Compiled {
+    pub manifest: Manifest1,
+    pub build_packages: Vec<blueprint::Package>,
+    pub packages: Vec<blueprint::Package>,
+    pub unsupported: Vec<String>,
+}
+
+/// Default Image Size
+///
+/// The size of disk images if the blueprint does not specify a minimum
+/// size for the root file-system.
+pub const DEFAULT_SIZE: u64 = 4 << 30;
+
+const ARCHES: &[&str] = &["aarch64", "x86_64"];
+
+const TAR_PACKAGES: &[&str] = &["bash", "coreutils", "dnf", "rpm", "selinux-policy-targeted"];
+
+const DISK_PACKAGES: &[&str] = &[
+    "@core",
+    "dracut-config-generic",
+    "kernel",
+    "selinux-policy-targeted",
+    "xfsprogs",
+];
+
+const BUILD_PACKAGES: &[&str] = &[
+    "dnf",
+    "policycoreutils",
+    "qemu-img",
+    "rpm",
+    "selinux-policy-targeted",
+    "systemd",
+    "tar",
+    "xfsprogs",
+];
+
+// Map a distribution to the runner of its build pipeline. Versions are
+// joined without separator, so `rhel-9.2` uses `org.osbuild.rhel92`.
+pub(crate) fn runner(distro: &str) -> Option<Runner> {
+    let (name, version) = distro.split_once('-')?;
+    if !["centos", "fedora", "rhel"].contains(&name) {
+        return None;
+    }
+    let runner = Runner::for_distro(name, version);
+    runner.version().is_some().then_some(runner)
+}
+
+fn stage(name: &str, options: Json) -> Stage1 {
+    let mut stage = Stage1::default();
+    stage.name = name.into();
+    if let Json::Object(options) = options {
+        stage.options = manifest::object_from_map(options);
+    }
+    stage
+}
+
+fn specs(names: &[&str]) -> Vec<blueprint::Package> {
+    names
+        .iter()
+        .map(|v| blueprint::Package {
+            name: (*v).to_owned(),
+            version: None,
+        })
+        .collect()
+}
+
+// Compile the user and group customizations, including SSH keys of users
+// that are not otherwise customized.
+fn compile_users(c: &blueprint::Customizations, acc: &mut Vec<Stage1>) {
+    if !c.group.is_empty() {
+        let groups: serde_json::Map<_, _> = c
+            .group
+            .iter()
+            .map(|v| {
+                let mut group = serde_json::Map::new();
+                if let Some(gid) = v.gid {
+                    group.insert("gid".to_owned(), gid.into());
+                }
+                (v.name.clone(), Json::Object(group))
+            })
+            .collect();
+        acc.push(stage(
+            "org.osbuild.groups",
+            serde_json::json!({ "groups": groups }),
+        ));
+    }
+
+    let mut users = serde_json::Map::new();
+    for v in c.user.iter() {
+        let mut user = serde_json::Map::new();
+        let strings = [
+            ("description", &v.description),
+            ("home", &v.home),
+            ("shell", &v.shell),
+            ("password", &v.password),
+            ("key", &v.key),
+        ];
+        for (key, value) in strings {
+            if let Some(value) = value {
+                user.insert(key.to_owned(), value.clone().into());
+            }
+        }
+        if let Some(uid) = v.uid {
+            user.insert("uid".to_owned(), uid.into());
+        }
+        if let Some(gid) = v.gid {
+            user.insert("gid".to_owned(), gid.into());
+        }
+        if !v.groups.is_empty() {
+            user.insert("groups".to_owned(), v.groups.clone().into());
+        }
+        users.insert(v.name.clone(), Json::Object(user));
+    }
+    for v in c.sshkey.iter() {
+        let user = users
+            .entry(v.user.clone())
+            .or_insert_with(|| Json::Object(Default::default()));
+        if let Json::Object(user) = user {
+            user.entry("key").or_insert_with(|| v.key.clone().into());
+        }
+    }
+    if !users.is_empty() {
+        acc.push(stage(
+            "org.osbuild.users",
+            serde_json::json!({ "users": users }),
+        ));
+    }
+}
+
+// Compile all customizations into stages, recording unsupported ones.
+fn compile_customizations(
+    c: &blueprint::Customizations,
+    stages: &mut Vec<Stage1>,
+    packages: &mut Vec<blueprint::Package>,
+    unsupported: &mut Vec<String>,
+) {
+    if let Some(ref v) = c.hostname {
+        stages.push(Stage1::typed(&options::HostnameOptions {
+            hostname: v.clone(),
+        }));
+    }
+    if let Some(ref v) = c.locale {
+        if let Some(language) = v.languages.first() {
+            stages.push(Stage1::typed(&options::LocaleOptions {
+                language: language.clone(),
+            }));
+        }
+        if let Some(ref keyboard) = v.keyboard {
+            stages.push(Stage1::typed(&options::KeymapOptions {
+                keymap: keyboard.clone(),
+            }));
+        }
+    }
+    if let Some(ref v) = c.timezone {
+        if let Some(ref zone) = v.timezone {
+            stages.push(Stage1::typed(&options::TimezoneOptions {
+                zone: zone.clone(),
+            }));
+        }
+        if !v.ntpservers.is_empty() {
+            packages.extend(specs(&["chrony"]));
+            stages.push(Stage1::typed(&options::ChronyOptions {
+                timeservers: v.ntpservers.clone(),
+            }));
+        }
+    }
+
+    compile_users(c, stages);
+
+    if let Some(ref v) = c.kernel {
+        if let Some(ref name) = v.name {
+            packages.retain(|v| v.name != "kernel");
+            packages.extend(specs(&[name]));
+        }
+        if let Some(ref append) = v.append {
+            stages.push(stage(
+                "org.osbuild.kernel-cmdline",
+                serde_json::json!({ "kernel_opts": append }),
+            ));
+        }
+    }
+    if let Some(ref v) = c.firewall {
+        packages.extend(specs(&["firewalld"]));
+        let mut options = serde_json::json!({ "ports": v.ports });
+        if let Some(ref services) = v.services {
+            options["enabled_services"] = services.enabled.clone().into();
+            options["disabled_services"] = services.disabled.clone().into();
+        }
+        stages.push(stage("org.osbuild.firewall", options));
+    }
+    if let Some(ref v) = c.services {
+        stages.push(stage(
+            "org.osbuild.systemd",
+            serde_json::json!({
+                "enabled_services": v.enabled,
+                "disabled_services": v.disabled,
+            }),
+        ));
+    }
+
+    // Mount points other than the root file-system require custom
+    // partitioning, which is not supported.
+    if c.filesystem.iter().any(|v| v.mountpoint != "/") {
+        unsupported.push("customizations.filesystem".to_owned());
+    }
+    if !c.directories.is_empty() {
+        unsupported.push("customizations.directories".to_owned());
+    }
+    if !c.files.is_empty() {
+        unsupported.push("customizations.files".to_owned());
+    }
+    for key in c.other.keys() {
+        unsupported.push(format!("customizations.{}", key));
+    }
+}
+
+// Compile the boot-loader stages and the assembler of disk images. The
+// file-system and partition-table identifiers are derived from the
+// blueprint name, so compilation is deterministic.
+fn compile_disk(
+    blueprint: &Blueprint,
+    target: &Target,
+    stages: &mut Vec<Stage1>,
+    packages: &mut Vec<blueprint::Package>,
+) -> Assembler1 {
+    let seed = format!("{}/{}/{}", blueprint.name, target.distro, target.arch);
+    let root_uuid = Uuid::new_v5(&uuid::NAMESPACE_URL, format!("{}/root", seed).as_bytes());
+    let efi_uuid = Uuid::new_v5(&uuid::NAMESPACE_URL, format!("{}/efi", seed).as_bytes());
+    let efi_volid = format!(
+        "{:02X}{:02X}{:02X}{:02X}",
+        efi_uuid.0[0], efi_uuid.0[1], efi_uuid.0[2], efi_uuid.0[3]
+    );
+    let efi_fs_id = format!("{}-{}", &efi_volid[..4], &efi_volid[4..]);
+
+    let size = blueprint
+        .customizations
+        .iter()
+        .flat_map(|v| v.filesystem.iter())
+        .filter(|v| v.mountpoint == "/")
+        .filter_map(|v| v.minsize.as_ref().and_then(estimate::parse_size))
+        .max()
+        .unwrap_or(DEFAULT_SIZE);
+    // Round up to full MiB, and reserve space for the partition table and
+    // the boot partitions.
+    let size = size.div_ceil(1 << 20) * (1 << 20) + (512 << 20);
+
+    let mut filesystems = vec![serde_json::json!({
+        "uuid": root_uuid.to_string(),
+        "vfs_type": "xfs",
+        "path": "/",
+        "options": "defaults",
+    })];
+    let mut partitions = Vec::new();
+    let uefi = target.arch == "aarch64";
+
+    let grub2 = if uefi {
+        packages.extend(specs(&["grub2-efi-aa64", "shim-aa64"]));
+        filesystems.push(serde_json::json!({
+            "uuid": efi_fs_id,
+            "vfs_type": "vfat",
+            "path": "/boot/efi",
+            "options": "umask=0077,shortname=winnt",
+            "passno": 2,
+        }));
+        partitions.push(serde_json::json!({
+            "size": 204800,
+            "type": "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+            "filesystem": {
+                "type": "vfat",
+                "uuid": efi_fs_id,
+                "label": "EFI-SYSTEM",
+                "mountpoint": "/boot/efi",
+            },
+        }));
+        serde_json::json!({
+            "rootfs": { "uuid": root_uuid.to_string() },
+            "uefi": { "vendor": target.distro.split('-').next() },
+        })
+    } else {
+        packages.extend(specs(&["grub2-pc"]));
+        serde_json::json!({
+            "rootfs": { "uuid": root_uuid.to_string() },
+            "legacy": "i386-pc",
+        })
+    };
+    partitions.push(serde_json::json!({
+        "bootable": !uefi,
+        "type": if uefi { "0FC63DAF-8483-4772-8E79-3D69D8477DE4" } else { "83" },
+        "filesystem": {
+            "type": "xfs",
+            "uuid": root_uuid.to_string(),
+            "label": "root",
+            "mountpoint": "/",
+        },
+    }));
+
+    stages.push(stage("org.osbuild.fix-bls", serde_json::json!({})));
+    stages.push(stage(
+        "org.osbuild.fstab",
+        serde_json::json!({ "filesystems": filesystems }),
+    ));
+    stages.push(stage("org.osbuild.grub2", grub2));
+
+    let ptuuid = if uefi {
+        Uuid::new_v5(&uuid::NAMESPACE_URL, format!("{}/pt", seed).as_bytes()).to_string()
+    } else {
+        format!("0x{}", &root_uuid.to_string()[..8])
+    };
+    let (format, extension) = match target.image_type {
+        ImageType::Qcow2 => ("qcow2", "qcow2"),
+        _ => ("raw", "raw"),
+    };
+
+    let mut assembler = Assembler1::default();
+    assembler.name = "org.osbuild.qemu".into();
+    assembler.options = serde_json::json!({
+        "format": format,
+        "filename": format!("disk.{}", extension),
+        "size": size,
+        "ptuuid": ptuuid,
+        "pttype": if uefi { "gpt" } else { "dos" },
+        "partitions": partitions,
+    })
+    .as_object()
+    .cloned()
+    .unwrap_or_default()
+    .into_iter()
+    .collect();
+    assembler
+}
+
+/// Compile Blueprint
+///
+/// Compile a blueprint into a manifest skeleton for the given target. The
+/// blueprint is validated first, and compilation fails if validation does.
+pub fn compile(blueprint: &Blueprint, target: &Target) -> Result<Compiled, Error> {
+    let runner =
+        runner(&target.distro).ok_or_else(|| Error::UnsupportedDistro(target.distro.clone()))?;
+    if !ARCHES.contains(&target.arch.as_str()) {
+        return Err(Error::UnsupportedArch(target.arch.clone()));
+    }
+    let problems = blueprint.validate();
+    if !problems.is_empty() {
+        return Err(Error::InvalidBlueprint(problems));
+    }
+
+    let mut packages = specs(match target.image_type {
+        ImageType::Tar => TAR_PACKAGES,
+        ImageType::Qcow2 | ImageType::Raw => DISK_PACKAGES,
+    });
+    let mut unsupported = Vec::new();
+    let mut stages = vec![Stage1::typed(&options::RpmOptions::default())];
+
+    packages.extend(blueprint.packages.iter().cloned());
+    if !blueprint.modules.is_empty() {
+        unsupported.push("modules".to_owned());
+    }
+    packages.extend(blueprint.groups.iter().map(|v| blueprint::Package {
+        name: format!("@{}", v.name),
+        version: None,
+    }));
+    if !blueprint.containers.is_empty() {
+        unsupported.push("containers".to_owned());
+    }
+    if let Some(ref c) = blueprint.customizations {
+        compile_customizations(c, &mut stages, &mut packages, &mut unsupported);
+    }
+
+    let assembler = match target.image_type {
+        ImageType::Tar => {
+            let mut assembler = Assembler1::default();
+            assembler.name = "org.osbuild.tar".into();
+            assembler.options = Object::from([("filename".to_owned(), "root.tar.xz".into())]);
+            assembler
+                .options
+                .insert("compression".to_owned(), "xz".into());
+            assembler
+        }
+        ImageType::Qcow2 | ImageType::Raw => {
+            compile_disk(blueprint, target, &mut stages, &mut packages)
+        }
+    };
+
+    stages.push(stage(
+        "org.osbuild.selinux",
+        serde_json::json!({
+            "file_contexts": "etc/selinux/targeted/contexts/files/file_contexts",
+        }),
+    ));
+
+    let mut build = Build1::default();
+    build.runner = runner;
+    build
+        .pipeline
+        .stages
+        .push(Stage1::typed(&options::RpmOptions::default()));
+
+    let mut pipeline = Pipeline1::default();
+    pipeline.build = Some(Box::new(build));
+    pipeline.stages = stages;
+    pipeline.assembler = Some(assembler);
+
+    let mut manifest = Manifest1::default();
+    manifest.pipeline = pipeline;
+
+    Ok(Compiled {
+        manifest,
+        build_packages: specs(BUILD_PACKAGES),
+        packages,
+        unsupported,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint;
+
+    fn target(image_type: ImageType) -> Target {
+        Target {
+            distro: "fedora-39".to_owned(),
+            arch: "x86_64".to_owned(),
+            image_type,
+        }
+    }
+
+    // Verify Blueprint Compilation
+    #[test]
+    fn verify_compile() {
+        let blueprint = Blueprint::from_toml(
+            r#"
+            name = "base"
+
+            [[packages]]
+            name = "tmux"
+
+            [customizations]
+            hostname = "base"
+            fips = true
+
+            [customizations.kernel]
+            append = "nosmt=force"
+
+            [[customizations.sshkey]]
+            user = "root"
+            key = "ssh-ed25519 AAAA"
+
+            [customizations.services]
+            enabled = ["sshd"]
+
+            [[customizations.filesystem]]
+            mountpoint = "/"
+            minsize = "10 GiB"
+            "#,
+        )
+        .unwrap();
+
+        let compiled = compile(&blueprint, &target(ImageType::Qcow2)).unwrap();
+        let manifest = &compiled.manifest;
+        let build = manifest.pipeline.build.as_ref().unwrap();
+        let assembler = manifest.pipeline.assembler.as_ref().unwrap();
+
+        assert_eq!(build.runner, "org.osbuild.fedora39");
+        assert_eq! {
+            manifest.pipeline.stages.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(),
+            [
+                "org.osbuild.rpm",
+                "org.osbuild.hostname",
+                "org.osbuild.users",
+                "org.osbuild.kernel-cmdline",
+                "org.osbuild.systemd",
+                "org.osbuild.fix-bls",
+                "org.osbuild.fstab",
+                "org.osbuild.grub2",
+                "org.osbuild.selinux",
+            ],
+        }
+        assert_eq!(
+            manifest.pipeline.stages[2].options["users"]["root"]["key"],
+            "ssh-ed25519 AAAA"
+        );
+        assert_eq!(assembler.options["format"], "qcow2");
+        assert_eq!(assembler.options["size"], (10u64 << 30) + (512 << 20));
+        assert!(compiled.packages.iter().any(|v| v.name == "tmux"));
+        assert!(compiled.packages.iter().any(|v| v.name == "grub2-pc"));
+        assert_eq!(compiled.unsupported, ["customizations.fips"]);
+
+        // Generated identifiers are valid and deterministic.
+        assert!(lint::identifiers::check(manifest).is_empty());
+        assert_eq!(
+            compile(&blueprint, &target(ImageType::Qcow2)).unwrap(),
+            compiled,
+        );
+
+        let compiled = compile(&blueprint, &target(ImageType::Tar)).unwrap();
+        let assembler = compiled.manifest.pipeline.assembler.as_ref().unwrap();
+        assert_eq!(assembler.name, "org.osbuild.tar");
+    }
+
+    // Verify Compilation Errors
+    #[test]
+    fn verify_errors() {
+        let blueprint = Blueprint::from_toml("name = \"base\"").unwrap();
+
+        let mut t = target(ImageType::Tar);
+        t.distro = "debian-12".to_owned();
+        assert_eq! {
+            compile(&blueprint, &t).unwrap_err(),
+            Error::UnsupportedDistro("debian-12".to_owned()),
+        }
+
+        let mut t = target(ImageType::Tar);
+        t.arch = "s390x".to_owned();
+        assert_eq! {
+            compile(&blueprint, &t).unwrap_err(),
+            Error::UnsupportedArch("s390x".to_owned()),
+        }
+
+        let blueprint = Blueprint::from_toml("name = \"\"").unwrap();
+        assert!(matches!(
+            compile(&blueprint, &target(ImageType::Tar)),
+            Err(Error::InvalidBlueprint(_)),
+        ));
+    }
+}
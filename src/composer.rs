@@ -0,0 +1,13 @@
+//! osbuild-composer Interfaces
+//!
+//! osbuild-composer is the service that turns image requests into osbuild
+//! manifests and schedules their builds. This module provides typed
+//! representations of its public interfaces, so Rust clients and proxies
+//! can talk to it without maintaining their own JSON structures.
+//!
+//! All types serialize to and from the JSON representation used on the
+//! wire. Optional members that are absent are omitted when serializing.
+
+pub mod cloud;
+pub mod koji;
+pub mod weldr;
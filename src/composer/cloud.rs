@@ -0,0 +1,472 @@
+//! Cloud API
+//!
+//! This module provides the request and response types of the composer
+//! cloud API, which is served below `/api/image-builder-composer/v2/`. A
+//! compose is started by posting a `ComposeRequest` to `/compose`, which is
+//! answered with a `ComposeId`. The state of the compose is then polled via
+//! `/composes/{id}`, which returns a `ComposeStatus`. Failed requests are
+//! answered with an `Error`.
+
+use crate::manifest::{Json, Object};
+
+/// Compose Request
+///
+/// A request to build one or more images of a distribution. Either a
+/// single `image_request` or a list of `image_requests` must be given.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeRequest {
+    pub distribution: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_request: Option<ImageRequest>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_requests: Vec<ImageRequest>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customizations: Option<Customizations>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub koji: Option<Koji>,
+}
+
+/// Image Type
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageType {
+    Aws,
+    AwsHaRhui,
+    AwsRhui,
+    AwsSapRhui,
+    Azure,
+    AzureRhui,
+    EdgeCommit,
+    EdgeContainer,
+    EdgeInstaller,
+    Gcp,
+    GcpRhui,
+    GuestImage,
+    ImageInstaller,
+    IotCommit,
+    IotContainer,
+    IotInstaller,
+    IotRawImage,
+    LiveInstaller,
+    Oci,
+    Vsphere,
+    VsphereOva,
+    Wsl,
+}
+
+/// Image Request
+///
+/// A request for a single image of a given type and architecture, built
+/// from the given repositories.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ImageRequest {
+    pub architecture: String,
+    pub image_type: ImageType,
+    #[serde(default)]
+    pub repositories: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_options: Option<UploadOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ostree: Option<OSTree>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Repository
+///
+/// A package repository, given by exactly one of `baseurl`, `mirrorlist`,
+/// or `metalink`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Repository {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseurl: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrorlist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metalink: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpgkey: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_gpg: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rhsm: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub package_sets: Vec<String>,
+}
+
+/// OSTree Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct OSTree {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contenturl: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+/// Upload Options
+///
+/// The upload target of an image depends on its image type. The wire
+/// format carries no discriminator, so options are told apart by their
+/// members. Since the S3 and GCP options can both consist of only a region,
+/// such options always parse as `AwsS3`, and the image type must be
+/// consulted to tell them apart.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum UploadOptions {
+    Azure(AzureUploadOptions),
+    AwsEc2(AwsEc2UploadOptions),
+    AwsS3(AwsS3UploadOptions),
+    Gcp(GcpUploadOptions),
+}
+
+/// AWS EC2 Upload Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AwsEc2UploadOptions {
+    pub region: String,
+    pub share_with_accounts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_name: Option<String>,
+}
+
+/// AWS S3 Upload Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AwsS3UploadOptions {
+    pub region: String,
+}
+
+/// GCP Upload Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcpUploadOptions {
+    pub region: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub share_with_accounts: Vec<String>,
+}
+
+/// Azure Upload Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AzureUploadOptions {
+    pub tenant_id: String,
+    pub subscription_id: String,
+    pub resource_group: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_name: Option<String>,
+}
+
+/// Compose Customizations
+///
+/// Only the commonly used customizations are typed. All others are
+/// retained verbatim in `other`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Customizations {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<User>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payload_repositories: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<Services>,
+    /// Members not covered by the typed fields.
+    #[serde(flatten)]
+    pub other: Object<Json>,
+}
+
+/// User Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct User {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// Service Customization
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Services {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enabled: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled: Vec<String>,
+}
+
+/// Koji Options
+///
+/// Options for composes that are imported into Koji as builds.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Koji {
+    pub server: String,
+    pub task_id: u64,
+    pub name: String,
+    pub version: String,
+    pub release: String,
+}
+
+/// Compose Identifier
+///
+/// The response to a successfully submitted compose request.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeId {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+}
+
+/// Compose Status Value
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComposeStatusValue {
+    Pending,
+    Success,
+    Failure,
+}
+
+/// Compose Status
+///
+/// The state of a compose, and of all its images.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+    pub status: ComposeStatusValue,
+    pub image_status: ImageStatus,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_statuses: Vec<ImageStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub koji_status: Option<Json>,
+}
+
+/// Image Status Value
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStatusValue {
+    Pending,
+    Building,
+    Uploading,
+    Registering,
+    Success,
+    Failure,
+}
+
+/// Image Status
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ImageStatus {
+    pub status: ImageStatusValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_status: Option<UploadStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ComposeError>,
+}
+
+/// Upload Status Value
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatusValue {
+    Pending,
+    Running,
+    Success,
+    Failure,
+}
+
+/// Upload Status
+///
+/// The state of an upload. The `options` describe the uploaded image and
+/// depend on the upload type, like the AMI and region for `aws`.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct UploadStatus {
+    pub status: UploadStatusValue,
+    #[serde(rename = "type")]
+    pub upload_type: String,
+    #[serde(default)]
+    pub options: Json,
+}
+
+/// Compose Error
+///
+/// The reason an image failed to build.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeError {
+    pub id: u64,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Json>,
+}
+
+/// API Error
+///
+/// The response to a failed request.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Error {
+    pub id: String,
+    pub kind: String,
+    pub href: String,
+    pub code: String,
+    pub reason: String,
+    pub operation_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Json>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}: {}", self.code, self.reason)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Compose Requests
+    #[test]
+    fn verify_request() {
+        let request: ComposeRequest = serde_json::from_str(
+            r#"{
+                "distribution": "rhel-9.2",
+                "image_request": {
+                    "architecture": "x86_64",
+                    "image_type": "aws",
+                    "repositories": [{ "baseurl": "https://example.com/baseos", "rhsm": true }],
+                    "upload_options": { "region": "eu-central-1", "share_with_accounts": ["123"] }
+                },
+                "customizations": {
+                    "packages": ["tmux"],
+                    "users": [{ "name": "admin", "groups": ["wheel"] }],
+                    "subscription": { "organization": "42" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let image = request.image_request.as_ref().unwrap();
+        assert_eq!(image.image_type, ImageType::Aws);
+        assert!(matches!(
+            image.upload_options,
+            Some(UploadOptions::AwsEc2(ref v)) if v.share_with_accounts == ["123"],
+        ));
+
+        let customizations = request.customizations.as_ref().unwrap();
+        assert_eq!(customizations.users[0].name, "admin");
+        assert!(customizations.other.contains_key("subscription"));
+
+        // Serialization round-trips and omits absent members.
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("koji").is_none());
+        assert_eq!(json["image_request"]["repositories"][0]["rhsm"], true);
+        assert_eq!(
+            serde_json::from_value::<ComposeRequest>(json).unwrap(),
+            request
+        );
+
+        // Upload options are told apart by their members.
+        assert!(matches!(
+            serde_json::from_str(r#"{ "region": "us-east-1" }"#),
+            Ok(UploadOptions::AwsS3(_)),
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{ "region": "eu", "bucket": "b" }"#),
+            Ok(UploadOptions::Gcp(_)),
+        ));
+        assert!(matches!(
+            serde_json::from_str(
+                r#"{ "tenant_id": "t", "subscription_id": "s", "resource_group": "r" }"#
+            ),
+            Ok(UploadOptions::Azure(_)),
+        ));
+    }
+
+    // Verify Compose Status
+    #[test]
+    fn verify_status() {
+        let status: ComposeStatus = serde_json::from_str(
+            r#"{
+                "href": "/api/image-builder-composer/v2/composes/abc",
+                "id": "abc",
+                "kind": "ComposeStatus",
+                "status": "failure",
+                "image_status": {
+                    "status": "failure",
+                    "error": { "id": 10, "reason": "osbuild build failed" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(status.status, ComposeStatusValue::Failure);
+        assert_eq!(status.image_status.error.as_ref().unwrap().id, 10);
+
+        let status: ImageStatus = serde_json::from_str(
+            r#"{
+                "status": "success",
+                "upload_status": {
+                    "status": "success",
+                    "type": "aws",
+                    "options": { "ami": "ami-123", "region": "eu-central-1" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(status.upload_status.unwrap().options["ami"], "ami-123");
+
+        let error: Error = serde_json::from_str(
+            r#"{
+                "id": "5",
+                "kind": "Error",
+                "href": "/api/image-builder-composer/v2/errors/5",
+                "code": "IMAGE-BUILDER-COMPOSER-5",
+                "reason": "Unsupported distribution",
+                "operation_id": "op"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "IMAGE-BUILDER-COMPOSER-5: Unsupported distribution"
+        );
+    }
+}
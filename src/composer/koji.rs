@@ -0,0 +1,233 @@
+//! Koji Integration
+//!
+//! This module provides the types of the osbuild Koji plugin. The plugin
+//! adds an `osbuildImage` task to Koji, which forwards image builds to
+//! osbuild-composer and imports the results as Koji builds. Release
+//! engineering tools submit such tasks with `TaskParams` and interpret
+//! their outcome with `TaskResult` and the `ImageExtra` metadata attached
+//! to the imported build outputs.
+
+use crate::composer::cloud::OSTree;
+use crate::manifest::Json;
+
+/// Task Repository
+///
+/// A repository to build from, either as plain URL or with the package
+/// sets it applies to.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Repository {
+    Url(String),
+    Detailed {
+        baseurl: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_sets: Vec<String>,
+    },
+}
+
+/// Task Options
+///
+/// The optional parameters of an `osbuildImage` task. Upload options and
+/// customizations are passed through to composer unmodified.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TaskOptions {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repo: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_tag: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ostree: Option<OSTree>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_options: Option<Json>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customizations: Option<Json>,
+}
+
+/// Task Parameters
+///
+/// The parameters of an `osbuildImage` task. Koji passes task parameters
+/// positionally, so this serializes as JSON array in the order of the
+/// fields.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(from = "TaskArgs", into = "TaskArgs")]
+pub struct TaskParams {
+    pub name: String,
+    pub version: String,
+    pub distro: String,
+    pub image_types: Vec<String>,
+    pub target: String,
+    pub arches: Vec<String>,
+    pub opts: TaskOptions,
+}
+
+// Positional representation of the task parameters. The options are
+// optional on the wire.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+struct TaskArgs(
+    String,
+    String,
+    String,
+    Vec<String>,
+    String,
+    Vec<String>,
+    #[serde(default)] TaskOptions,
+);
+
+impl From<TaskArgs> for TaskParams {
+    fn from(v: TaskArgs) -> Self {
+        Self {
+            name: v.0,
+            version: v.1,
+            distro: v.2,
+            image_types: v.3,
+            target: v.4,
+            arches: v.5,
+            opts: v.6,
+        }
+    }
+}
+
+impl From<TaskParams> for TaskArgs {
+    fn from(v: TaskParams) -> Self {
+        Self(
+            v.name,
+            v.version,
+            v.distro,
+            v.image_types,
+            v.target,
+            v.arches,
+            v.opts,
+        )
+    }
+}
+
+/// Composer Reference
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposerRef {
+    pub server: String,
+    pub id: String,
+}
+
+/// Koji Build Reference
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BuildRef {
+    pub build: u64,
+}
+
+/// Task Result
+///
+/// The result of a successful `osbuildImage` task, referring to the
+/// compose in composer and the build imported into Koji.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TaskResult {
+    pub composer: ComposerRef,
+    pub koji: BuildRef,
+}
+
+/// Build Artifact Reference
+///
+/// The osbuild pipeline export an output file was taken from.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Artifact {
+    pub export_filename: String,
+    pub export_name: String,
+}
+
+/// Image Output Metadata
+///
+/// The `extra.image` metadata attached to image outputs of imported
+/// builds.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ImageExtra {
+    pub arch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osbuild_artifact: Option<Artifact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub osbuild_version: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Task Parameters
+    #[test]
+    fn verify_params() {
+        let json = serde_json::json!([
+            "fedora-guest",
+            "39",
+            "fedora-39",
+            ["qcow2"],
+            "f39-candidate",
+            ["x86_64", "aarch64"],
+            {
+                "repo": [
+                    "https://example.com/everything",
+                    { "baseurl": "https://example.com/extra", "package_sets": ["blueprint"] }
+                ],
+                "release": "1",
+                "ostree": { "ref": "fedora/39/x86_64/iot" }
+            }
+        ]);
+
+        let params: TaskParams = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(params.distro, "fedora-39");
+        assert_eq!(params.arches, ["x86_64", "aarch64"]);
+        assert_eq! {
+            params.opts.repo[1],
+            Repository::Detailed {
+                baseurl: "https://example.com/extra".to_owned(),
+                package_sets: vec!["blueprint".to_owned()],
+            },
+        }
+        assert_eq!(
+            params.opts.ostree.as_ref().unwrap().reference.as_deref(),
+            Some("fedora/39/x86_64/iot")
+        );
+        assert_eq!(serde_json::to_value(&params).unwrap(), json);
+
+        // Options are optional on the wire.
+        let params: TaskParams =
+            serde_json::from_str(r#"["a", "1", "rhel-9", [], "t", []]"#).unwrap();
+        assert_eq!(params.opts, TaskOptions::default());
+    }
+
+    // Verify Task Results
+    #[test]
+    fn verify_result() {
+        let result: TaskResult = serde_json::from_str(
+            r#"{
+                "composer": { "server": "https://composer.example.com", "id": "abc" },
+                "koji": { "build": 42 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(result.koji.build, 42);
+
+        let extra: ImageExtra = serde_json::from_str(
+            r#"{
+                "arch": "x86_64",
+                "boot_mode": "hybrid",
+                "image_type": "qcow2",
+                "osbuild_artifact": { "export_filename": "disk.qcow2", "export_name": "qcow2" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(extra.osbuild_artifact.unwrap().export_name, "qcow2");
+    }
+}
@@ -0,0 +1,600 @@
+//! Weldr API
+//!
+//! This module provides the types of the Weldr API, the on-premise
+//! interface of osbuild-composer used by `composer-cli` and the cockpit
+//! plugin. It manages blueprints, queues composes, and reports on their
+//! status. The API is served via HTTP on a UNIX socket, by default at
+//! `/run/weldr/api.socket`.
+//!
+//! Additionally, a minimal synchronous client is provided. It only
+//! requires the standard library and speaks just enough HTTP/1.1 to talk to
+//! composer, one connection per request.
+
+use crate::blueprint::Blueprint;
+use crate::manifest::Json;
+
+/// Default Socket Path
+pub const SOCKET: &str = "/run/weldr/api.socket";
+
+/// Server Status
+///
+/// The response of `/api/status`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Status {
+    pub api: String,
+    pub backend: String,
+    pub build: String,
+    pub db_supported: bool,
+    pub db_version: String,
+    pub schema_version: String,
+    #[serde(default)]
+    pub messages: Vec<String>,
+}
+
+/// API Error
+///
+/// Errors reported by the API, both for failed requests and for partial
+/// failures of otherwise successful requests.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ApiError {
+    pub id: String,
+    pub msg: String,
+}
+
+/// Status Response
+///
+/// The response of requests without result, like storing or deleting a
+/// blueprint. Failed requests carry the same structure.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct StatusResponse {
+    pub status: bool,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+/// Blueprint List
+///
+/// The response of `/api/v1/blueprints/list`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BlueprintsList {
+    pub blueprints: Vec<String>,
+    pub offset: u64,
+    pub limit: u64,
+    pub total: u64,
+}
+
+/// Blueprint Change State
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BlueprintChange {
+    pub name: String,
+    pub changed: bool,
+}
+
+/// Blueprint Information
+///
+/// The response of `/api/v1/blueprints/info/{names}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BlueprintsInfo {
+    pub blueprints: Vec<Blueprint>,
+    #[serde(default)]
+    pub changes: Vec<BlueprintChange>,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+/// Compose Request
+///
+/// The body of `POST /api/v1/compose`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeRequest {
+    pub blueprint_name: String,
+    pub compose_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Json>,
+}
+
+/// Compose Response
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeResponse {
+    pub build_id: String,
+    pub status: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Compose Type
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeType {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Compose Types
+///
+/// The response of `/api/v1/compose/types`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeTypes {
+    pub types: Vec<ComposeType>,
+}
+
+/// Queue Status
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum QueueStatus {
+    Waiting,
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Compose Entry
+///
+/// The state of a single compose. Timestamps are in seconds since the
+/// epoch.
+#[derive(Clone, Debug, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeEntry {
+    pub id: String,
+    pub blueprint: String,
+    pub version: String,
+    pub compose_type: String,
+    #[serde(default)]
+    pub image_size: u64,
+    pub queue_status: QueueStatus,
+    pub job_created: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_started: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_finished: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uploads: Vec<Json>,
+}
+
+/// Compose Queue
+///
+/// The response of `/api/v1/compose/queue`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeQueue {
+    pub new: Vec<ComposeEntry>,
+    pub run: Vec<ComposeEntry>,
+}
+
+/// Finished Composes
+///
+/// The response of `/api/v1/compose/finished`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeFinished {
+    pub finished: Vec<ComposeEntry>,
+}
+
+/// Failed Composes
+///
+/// The response of `/api/v1/compose/failed`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeFailed {
+    pub failed: Vec<ComposeEntry>,
+}
+
+/// Compose Status List
+///
+/// The response of `/api/v1/compose/status/{ids}`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeStatus {
+    pub uuids: Vec<ComposeEntry>,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+/// Deletion State
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DeleteStatus {
+    pub uuid: String,
+    pub status: bool,
+}
+
+/// Compose Deletion
+///
+/// The response of `DELETE /api/v1/compose/delete/{ids}`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ComposeDelete {
+    pub uuids: Vec<DeleteStatus>,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+/// Client Error
+#[derive(Debug)]
+pub enum Error {
+    /// Communication with the server failed.
+    Io(std::io::Error),
+    /// The server sent a malformed HTTP response.
+    Protocol(&'static str),
+    /// The server rejected the request with the given HTTP status.
+    Api(u16, Vec<ApiError>),
+    /// The response body does not match the expected type.
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(v) => write!(fmt, "I/O error: {}", v),
+            Error::Protocol(v) => write!(fmt, "protocol error: {}", v),
+            Error::Api(code, errors) => {
+                write!(fmt, "request failed with status {}", code)?;
+                for v in errors {
+                    write!(fmt, ", {}: {}", v.id, v.msg)?;
+                }
+                Ok(())
+            }
+            Error::Json(v) => write!(fmt, "invalid response: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|v| v == needle)
+}
+
+// Decode a body with chunked transfer-encoding.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut acc = Vec::new();
+    loop {
+        let eol = find(data, b"\r\n").ok_or(Error::Protocol("truncated chunk"))?;
+        let size = std::str::from_utf8(&data[..eol])
+            .ok()
+            .map(|v| v.split(';').next().unwrap_or("").trim())
+            .and_then(|v| usize::from_str_radix(v, 16).ok())
+            .ok_or(Error::Protocol("invalid chunk size"))?;
+        data = &data[eol + 2..];
+        if size == 0 {
+            return Ok(acc);
+        }
+        let chunk = data.get(..size).ok_or(Error::Protocol("truncated chunk"))?;
+        acc.extend_from_slice(chunk);
+        data = data
+            .get(size + 2..)
+            .ok_or(Error::Protocol("truncated chunk"))?;
+    }
+}
+
+// Parse a complete HTTP/1.1 response into its status code and body.
+fn parse_response(data: &[u8]) -> Result<(u16, Vec<u8>), Error> {
+    let end = find(data, b"\r\n\r\n").ok_or(Error::Protocol("truncated header"))?;
+    let head = std::str::from_utf8(&data[..end]).map_err(|_| Error::Protocol("invalid header"))?;
+    let body = &data[end + 4..];
+    let mut lines = head.split("\r\n");
+
+    let code = lines
+        .next()
+        .and_then(|v| v.strip_prefix("HTTP/1."))
+        .and_then(|v| v.split(' ').nth(1))
+        .and_then(|v| v.parse::<u16>().ok())
+        .ok_or(Error::Protocol("invalid status line"))?;
+
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or(Error::Protocol("invalid header"))?;
+        let value = value.trim();
+        if key.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if key.eq_ignore_ascii_case("content-length") {
+            length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| Error::Protocol("invalid content length"))?,
+            );
+        }
+    }
+
+    let body = if chunked {
+        dechunk(body)?
+    } else if let Some(length) = length {
+        body.get(..length)
+            .ok_or(Error::Protocol("truncated body"))?
+            .to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Ok((code, body))
+}
+
+/// Weldr Client
+///
+/// A synchronous client for the Weldr API on a UNIX socket.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct Client {
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Client {
+    /// Create New Client
+    ///
+    /// Create a client for the API served on the UNIX socket at `path`. No
+    /// connection is made until the first request.
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Perform Raw Request
+    ///
+    /// Send a request with the given method, path, and optional body with
+    /// its content type, and return the response body. Responses with an
+    /// error status are turned into `Error::Api`.
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+    ) -> Result<Vec<u8>, Error> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::os::unix::net::UnixStream::connect(&self.path).map_err(Error::Io)?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n",
+            method, path,
+        )
+        .into_bytes();
+        if let Some((content_type, data)) = body {
+            request.extend_from_slice(
+                format!(
+                    "Content-Type: {}\r\nContent-Length: {}\r\n",
+                    content_type,
+                    data.len(),
+                )
+                .as_bytes(),
+            );
+            request.extend_from_slice(b"\r\n");
+            request.extend_from_slice(data);
+        } else {
+            request.extend_from_slice(b"\r\n");
+        }
+        stream.write_all(&request).map_err(Error::Io)?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(Error::Io)?;
+        let (code, body) = parse_response(&response)?;
+
+        if (200..300).contains(&code) {
+            Ok(body)
+        } else {
+            let errors = serde_json::from_slice::<StatusResponse>(&body)
+                .map(|v| v.errors)
+                .unwrap_or_default();
+            Err(Error::Api(code, errors))
+        }
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let body = self.request("GET", path, None)?;
+        serde_json::from_slice(&body).map_err(Error::Json)
+    }
+
+    fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<(&str, &[u8])>,
+    ) -> Result<T, Error> {
+        let body = self.request(method, path, body)?;
+        serde_json::from_slice(&body).map_err(Error::Json)
+    }
+
+    /// Query Server Status
+    pub fn status(&self) -> Result<Status, Error> {
+        self.get("/api/status")
+    }
+
+    /// List Blueprints
+    pub fn blueprints_list(&self) -> Result<BlueprintsList, Error> {
+        self.get("/api/v1/blueprints/list")
+    }
+
+    /// Query Blueprints
+    pub fn blueprints_info(&self, names: &[&str]) -> Result<BlueprintsInfo, Error> {
+        self.get(&format!("/api/v1/blueprints/info/{}", names.join(",")))
+    }
+
+    /// Store Blueprint
+    pub fn blueprints_new(&self, blueprint: &Blueprint) -> Result<StatusResponse, Error> {
+        let data = serde_json::to_vec(blueprint).map_err(Error::Json)?;
+        self.send(
+            "POST",
+            "/api/v1/blueprints/new",
+            Some(("application/json", &data)),
+        )
+    }
+
+    /// Delete Blueprint
+    pub fn blueprints_delete(&self, name: &str) -> Result<StatusResponse, Error> {
+        self.send(
+            "DELETE",
+            &format!("/api/v1/blueprints/delete/{}", name),
+            None,
+        )
+    }
+
+    /// List Compose Types
+    pub fn compose_types(&self) -> Result<ComposeTypes, Error> {
+        self.get("/api/v1/compose/types")
+    }
+
+    /// Start Compose
+    pub fn compose(&self, request: &ComposeRequest) -> Result<ComposeResponse, Error> {
+        let data = serde_json::to_vec(request).map_err(Error::Json)?;
+        self.send("POST", "/api/v1/compose", Some(("application/json", &data)))
+    }
+
+    /// Query Compose Queue
+    pub fn compose_queue(&self) -> Result<ComposeQueue, Error> {
+        self.get("/api/v1/compose/queue")
+    }
+
+    /// List Finished Composes
+    pub fn compose_finished(&self) -> Result<ComposeFinished, Error> {
+        self.get("/api/v1/compose/finished")
+    }
+
+    /// List Failed Composes
+    pub fn compose_failed(&self) -> Result<ComposeFailed, Error> {
+        self.get("/api/v1/compose/failed")
+    }
+
+    /// Query Compose Status
+    pub fn compose_status(&self, ids: &[&str]) -> Result<ComposeStatus, Error> {
+        self.get(&format!("/api/v1/compose/status/{}", ids.join(",")))
+    }
+
+    /// Fetch Compose Log
+    ///
+    /// Return the build log of a compose. The log is returned as text, with
+    /// invalid UTF-8 replaced.
+    pub fn compose_log(&self, id: &str) -> Result<String, Error> {
+        let body = self.request("GET", &format!("/api/v1/compose/log/{}", id), None)?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Cancel Compose
+    pub fn compose_cancel(&self, id: &str) -> Result<StatusResponse, Error> {
+        self.send("POST", &format!("/api/v1/compose/cancel/{}", id), None)
+    }
+
+    /// Delete Composes
+    pub fn compose_delete(&self, ids: &[&str]) -> Result<ComposeDelete, Error> {
+        self.send(
+            "DELETE",
+            &format!("/api/v1/compose/delete/{}", ids.join(",")),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Response Types
+    #[test]
+    fn verify_types() {
+        let queue: ComposeQueue = serde_json::from_str(
+            r#"{
+                "new": [],
+                "run": [{
+                    "id": "b27c5a7b-d1f6-4057-ac5c-8f1e1e2e3c5a",
+                    "blueprint": "base",
+                    "version": "0.0.1",
+                    "compose_type": "qcow2",
+                    "image_size": 0,
+                    "queue_status": "RUNNING",
+                    "job_created": 1600000000.5,
+                    "job_started": 1600000001.5
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(queue.run[0].queue_status, QueueStatus::Running);
+        assert_eq!(queue.run[0].job_finished, None);
+
+        let info: BlueprintsInfo = serde_json::from_str(
+            r#"{
+                "blueprints": [{ "name": "base", "version": "0.0.1", "packages": [{ "name": "tmux" }] }],
+                "changes": [{ "name": "base", "changed": false }],
+                "errors": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(info.blueprints[0].packages[0].name, "tmux");
+    }
+
+    // Verify HTTP Response Parsing
+    #[test]
+    fn verify_response() {
+        let (code, body) =
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}trailing").unwrap();
+        assert_eq!(code, 200);
+        assert_eq!(body, b"{}");
+
+        let (code, body) = parse_response(
+            b"HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n2;x=y\r\nef\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(code, 400);
+        assert_eq!(body, b"abcdef");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"SPDY 200\r\n\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n{}").is_err());
+    }
+
+    // Verify Client
+    #[cfg(unix)]
+    #[test]
+    fn verify_client() {
+        use std::io::{Read, Write};
+
+        let path =
+            std::env::temp_dir().join(format!("r-osbuild-weldr-{}.socket", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let server = std::thread::spawn(move || {
+            let responses: [&[u8]; 2] = [
+                b"HTTP/1.1 200 OK\r\nContent-Length: 55\r\n\r\n{\"blueprints\":[\"base\"],\"offset\":0,\"limit\":20,\"total\":1}",
+                b"HTTP/1.1 400 Bad Request\r\nContent-Length: 66\r\n\r\n{\"status\":false,\"errors\":[{\"id\":\"UnknownBlueprint\",\"msg\":\"nope\"}]}",
+            ];
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0u8; 4096];
+                let n = stream.read(&mut buffer).unwrap();
+                requests.push(String::from_utf8_lossy(&buffer[..n]).into_owned());
+                stream.write_all(response).unwrap();
+            }
+            requests
+        });
+
+        let client = Client::new(&path);
+        let list = client.blueprints_list();
+        let delete = client.blueprints_delete("foo");
+        let requests = server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(requests[0].starts_with("GET /api/v1/blueprints/list HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with("DELETE /api/v1/blueprints/delete/foo HTTP/1.1\r\n"));
+        assert_eq!(list.unwrap().blueprints, ["base"]);
+        match delete {
+            Err(Error::Api(400, errors)) => assert_eq!(errors[0].id, "UnknownBlueprint"),
+            v => panic!("unexpected result: {:?}", v),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Compressed Manifests
+//!
+//! Artifact stores commonly keep manifests compressed, since their JSON
+//! text compresses well. This module reads and writes compressed
+//! manifests transparently: compressed input is detected by its magic
+//! bytes, and output is compressed according to the file extension, like
+//! `.json.gz`.
+//!
+//! Codecs are feature-gated. The `gzip` feature provides gzip, with a
+//! DEFLATE encoder using fixed Huffman codes. It trades compression ratio
+//! for simplicity, but output is readable by any gzip implementation. The
+//! xz and zstd formats are detected, but no codec is available for them,
+//! so they are rejected with an error of kind `Unsupported`.
+
+use crate::error::Error;
+use crate::manifest::{Manifest, ManifestFormat};
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Compression Codec
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Codec {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Codec {
+    /// Name of the Codec
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Xz => "xz",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// File Extension
+    ///
+    /// The extension of files compressed with the codec, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Xz => "xz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    /// Codec of a Path
+    ///
+    /// Return the codec implied by the extension of the path, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+        [Codec::Gzip, Codec::Xz, Codec::Zstd]
+            .into_iter()
+            .find(|v| extension == v.extension())
+    }
+
+    /// Detect Codec
+    ///
+    /// Return the codec of compressed data, detected by its magic bytes,
+    /// or `None` if the data is not compressed.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Codec::Gzip)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Codec::Xz)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Codec::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+fn unsupported(codec: Codec) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{} compression is not supported", codec.name()),
+    ))
+}
+
+/// Decompress Data
+///
+/// Decompress the data if it starts with the magic bytes of a codec,
+/// otherwise return it unchanged.
+pub fn decompress(data: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    match Codec::detect(data) {
+        None => Ok(Cow::Borrowed(data)),
+        #[cfg(feature = "gzip")]
+        Some(Codec::Gzip) => crate::inflate::gunzip(data)
+            .map(Cow::Owned)
+            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))),
+        Some(v) => Err(unsupported(v)),
+    }
+}
+
+/// Compress Data
+#[cfg_attr(not(feature = "gzip"), allow(unused_variables))]
+pub fn compress(codec: Codec, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match codec {
+        #[cfg(feature = "gzip")]
+        Codec::Gzip => Ok(gzip(data)),
+        v => Err(unsupported(v)),
+    }
+}
+
+// Writer of bits, least-significant bit first.
+#[cfg(feature = "gzip")]
+#[derive(Default)]
+struct Bits {
+    output: Vec<u8>,
+    bit: u32,
+    n_bits: u32,
+}
+
+#[cfg(feature = "gzip")]
+impl Bits {
+    fn put(&mut self, value: u32, n: u32) {
+        self.bit |= value << self.n_bits;
+        self.n_bits += n;
+        while self.n_bits >= 8 {
+            self.output.push(self.bit as u8);
+            self.bit >>= 8;
+            self.n_bits -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting with their most-significant bit.
+    fn code(&mut self, code: u32, n: u32) {
+        self.put(code.reverse_bits() >> (32 - n), n);
+    }
+
+    // Write a symbol of the fixed literal/length code.
+    fn symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n_bits > 0 {
+            self.output.push(self.bit as u8);
+        }
+        self.output
+    }
+}
+
+// Compress the data as a single DEFLATE block with fixed Huffman codes,
+// finding matches via hash chains over 3-byte prefixes.
+#[cfg(feature = "gzip")]
+fn deflate(data: &[u8]) -> Vec<u8> {
+    use crate::inflate::{DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+    const WINDOW: usize = 32768;
+    const CHAIN: usize = 64;
+    const NONE: usize = usize::MAX;
+
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff
+    };
+    let mut head = vec![NONE; 1 << 15];
+    let mut prev = vec![NONE; data.len()];
+    let mut bits = Bits::default();
+    bits.put(1, 1);
+    bits.put(1, 2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut len, mut dist) = (0, 0);
+        if i + 3 <= data.len() {
+            let mut j = head[hash(i)];
+            let mut n = 0;
+            while j != NONE && i - j <= WINDOW && n < CHAIN {
+                let l = data[j..]
+                    .iter()
+                    .zip(&data[i..])
+                    .take(258)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if l > len {
+                    (len, dist) = (l, i - j);
+                }
+                if l == 258 {
+                    break;
+                }
+                j = prev[j];
+                n += 1;
+            }
+        }
+
+        let step = if len >= 3 {
+            let k = LENGTH_BASE
+                .iter()
+                .rposition(|v| *v as usize <= len)
+                .unwrap();
+            bits.symbol(257 + k as u32);
+            bits.put(
+                (len - LENGTH_BASE[k] as usize) as u32,
+                LENGTH_EXTRA[k] as u32,
+            );
+            let k = DIST_BASE.iter().rposition(|v| *v as usize <= dist).unwrap();
+            bits.code(k as u32, 5);
+            bits.put((dist - DIST_BASE[k] as usize) as u32, DIST_EXTRA[k] as u32);
+            len
+        } else {
+            bits.symbol(data[i] as u32);
+            1
+        };
+        let end = (i + step).min(data.len().saturating_sub(2));
+        for (k, v) in prev.iter_mut().enumerate().take(end).skip(i) {
+            let h = hash(k);
+            *v = head[h];
+            head[h] = k;
+        }
+        i += step;
+    }
+
+    bits.symbol(256);
+    bits.finish()
+}
+
+// Compress the data as a single gzip member.
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut acc = vec![0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+    acc.extend_from_slice(&deflate(data));
+    acc.extend_from_slice(&crate::inflate::crc32(data).to_le_bytes());
+    acc.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    acc
+}
+
+impl Manifest {
+    /// Parse Manifest Bytes
+    ///
+    /// Parse the data as manifest like `Manifest::parse()`, decompressing
+    /// it first if it is compressed.
+    pub fn parse_bytes(data: &[u8]) -> Result<Self, Error> {
+        let data = decompress(data)?;
+        let text = std::str::from_utf8(&data)
+            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))?;
+        Manifest::parse(text)
+    }
+
+    /// Read Manifest File
+    ///
+    /// Read and parse the manifest file at the given path, which may be
+    /// compressed.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        Self::parse_bytes(&std::fs::read(path)?)
+    }
+
+    /// Write Manifest File
+    ///
+    /// Serialize the manifest to the given path, compressed with the codec
+    /// implied by its extension, if any.
+    pub fn to_path(&self, path: &Path) -> Result<(), Error> {
+        let text = self.serialize();
+        let data = match Codec::from_path(path) {
+            Some(v) => Cow::Owned(compress(v, text.as_bytes())?),
+            None => Cow::Borrowed(text.as_bytes()),
+        };
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+
+    // Verify Compressed Manifest Files
+    #[test]
+    fn verify_compressed() {
+        let manifest = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "os", "build": "name:build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "image", "build": "name:build", "stages": [{ "type": "org.osbuild.truncate" }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("r-osbuild-compress-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["manifest.json", "manifest.json.gz"] {
+            let path = dir.join(name);
+            manifest.to_path(&path).unwrap();
+            assert_eq!(Manifest::from_path(&path).unwrap(), manifest);
+        }
+        let compressed = std::fs::read(dir.join("manifest.json.gz")).unwrap();
+        let err = manifest
+            .to_path(&dir.join("manifest.json.zst"))
+            .unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Codec::detect(&compressed), Some(Codec::Gzip));
+        assert!(compressed.len() < manifest.serialize().len());
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == std::io::ErrorKind::Unsupported));
+
+        let xz = [0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00];
+        assert!(matches!(Manifest::parse_bytes(&xz), Err(Error::Io(_))));
+        assert_eq!(Codec::from_path(Path::new("a.json.zst")), Some(Codec::Zstd));
+        assert_eq!(Codec::from_path(Path::new("a.json")), None);
+    }
+
+    // Verify the DEFLATE Encoder
+    #[test]
+    fn verify_deflate() {
+        let inputs: [&[u8]; 4] = [
+            b"",
+            b"a",
+            &[0u8; 1000],
+            &(0..70000u32)
+                .map(|v| (v % 251 * (v % 17)) as u8)
+                .collect::<Vec<_>>(),
+        ];
+        for data in inputs {
+            let compressed = compress(Codec::Gzip, data).unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert!(compress(Codec::Gzip, &[0u8; 1000]).unwrap().len() < 50);
+    }
+}
@@ -0,0 +1,602 @@
+//! Container Resolution
+//!
+//! Manifests and blueprints refer to container images by reference, which
+//! usually names a floating tag. To make builds reproducible, such
+//! references are resolved to digests at manifest-generation time, using
+//! the registry HTTP API (the OCI distribution specification).
+//!
+//! The resolver implements the registry protocol, including bearer token
+//! and basic authentication, and the selection of the image for the target
+//! architecture from manifest lists. The HTTP requests themselves are
+//! performed by a caller-provided [`crate::http::Transport`], since registries require
+//! TLS, which this crate does not implement.
+
+#[cfg(feature = "blueprint")]
+use crate::blueprint::Blueprint;
+use crate::hash;
+use crate::http::Transport;
+use crate::manifest::{Json, Object};
+
+/// Default Registry
+///
+/// The registry of references without an explicit registry.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+const MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// Image Reference
+///
+/// A parsed container image reference, normalized as done by the
+/// container tools: references without registry refer to `docker.io`,
+/// where single-component repositories live in `library/`.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl Reference {
+    /// Parse Reference
+    ///
+    /// Parse a reference of the form `[registry/]repository[:tag][@digest]`.
+    /// References with neither tag nor digest refer to the `latest` tag.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (name, digest) = match s.split_once('@') {
+            None => (s, None),
+            Some((name, digest)) => {
+                let (algorithm, hex) = digest.split_once(':')?;
+                if algorithm.is_empty() || hex.is_empty() {
+                    return None;
+                }
+                (name, Some(digest.to_owned()))
+            }
+        };
+
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((n, t)) if !t.contains('/') => (n, Some(t.to_owned())),
+            _ => (name, None),
+        };
+        let tag = match (tag, &digest) {
+            (None, None) => Some("latest".to_owned()),
+            (v, _) => v,
+        };
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((r, rest)) if r.contains('.') || r.contains(':') || r == "localhost" => {
+                (r.to_owned(), rest.to_owned())
+            }
+            _ => (DEFAULT_REGISTRY.to_owned(), name.to_owned()),
+        };
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+
+        let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-/".contains(c);
+        if repository.is_empty() || !repository.chars().all(valid) {
+            return None;
+        }
+
+        Some(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+
+    /// Name without Tag and Digest
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+
+    // The host serving the registry API.
+    fn api_host(&self) -> &str {
+        match self.registry.as_str() {
+            DEFAULT_REGISTRY => "registry-1.docker.io",
+            v => v,
+        }
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}", self.name())?;
+        if let Some(v) = &self.tag {
+            write!(fmt, ":{}", v)?;
+        }
+        if let Some(v) = &self.digest {
+            write!(fmt, "@{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+/// Registry Credentials
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Resolved Image
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Resolved {
+    /// The resolved reference.
+    pub reference: Reference,
+    /// Digest of the image manifest for the target architecture.
+    pub digest: String,
+    /// Digest of the manifest list, if the reference named one.
+    pub list_digest: Option<String>,
+    /// The image ID, which is the digest of the image configuration.
+    pub image_id: String,
+}
+
+/// Resolution Error
+#[derive(Debug)]
+pub enum Error {
+    /// A reference could not be parsed.
+    Reference(String),
+    /// The transport failed.
+    Transport(String),
+    /// The registry rejected a request with the given status.
+    Status(String, u16),
+    /// The registry returned malformed data.
+    Invalid(String, String),
+    /// A manifest list has no image for the target architecture.
+    NoArch(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Reference(v) => write!(fmt, "invalid container reference `{}`", v),
+            Error::Transport(v) => write!(fmt, "transport error: {}", v),
+            Error::Status(u, v) => write!(fmt, "{}: registry responded with status {}", u, v),
+            Error::Invalid(u, v) => write!(fmt, "{}: {}", u, v),
+            Error::NoArch(r, v) => write!(fmt, "{}: no image for architecture `{}`", r, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Map an architecture name to the name used by container registries.
+pub(crate) fn goarch(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "ppc64le" => "ppc64le",
+        "s390x" => "s390x",
+        v => v,
+    }
+}
+
+// Parse the parameters of an authentication challenge.
+fn challenge(header: &str) -> (String, Object<String>) {
+    let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header, ""));
+    let mut params = Object::new();
+    let mut rest = rest.trim();
+
+    while let Some((key, tail)) = rest.split_once('=') {
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(v) => v.split_once('"').unwrap_or((v, "")),
+            None => tail.split_once(',').unwrap_or((tail, "")),
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value.to_owned());
+        rest = tail.trim_start_matches([',', ' ']);
+    }
+
+    (scheme.to_ascii_lowercase(), params)
+}
+
+// Percent-encode a query parameter value.
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'/' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Container Resolver
+pub struct Resolver<T> {
+    transport: T,
+    arch: String,
+    credentials: Object<Credentials>,
+}
+
+impl<T: Transport> Resolver<T> {
+    /// Create New Resolver
+    ///
+    /// Create a resolver selecting images for the given architecture, as
+    /// named by RPM (e.g., `x86_64`).
+    pub fn new(transport: T, arch: &str) -> Self {
+        Self {
+            transport,
+            arch: arch.to_owned(),
+            credentials: Object::new(),
+        }
+    }
+
+    /// Set Registry Credentials
+    pub fn set_credentials(&mut self, registry: &str, credentials: Credentials) {
+        self.credentials.insert(registry.to_owned(), credentials);
+    }
+
+    // Fetch a token for a bearer challenge.
+    fn token(&self, reference: &Reference, params: &Object<String>) -> Result<String, Error> {
+        let realm = params.get("realm").ok_or_else(|| {
+            Error::Invalid(reference.to_string(), "challenge lacks a realm".to_owned())
+        })?;
+        let mut url = realm.clone();
+        let mut sep = if url.contains('?') { '&' } else { '?' };
+        for key in ["service", "scope"] {
+            if let Some(v) = params.get(key) {
+                url.push_str(&format!("{}{}={}", sep, key, encode(v)));
+                sep = '&';
+            }
+        }
+
+        let basic = self.basic(reference);
+        let mut headers = Vec::new();
+        if let Some(v) = &basic {
+            headers.push(("Authorization", v.as_str()));
+        }
+        let r = self
+            .transport
+            .get(&url, &headers)
+            .map_err(Error::Transport)?;
+        if r.status != 200 {
+            return Err(Error::Status(url, r.status));
+        }
+
+        let json: Json = serde_json::from_slice(&r.body)
+            .map_err(|e| Error::Invalid(url.clone(), e.to_string()))?;
+        json.get("token")
+            .or_else(|| json.get("access_token"))
+            .and_then(Json::as_str)
+            .map(|v| format!("Bearer {}", v))
+            .ok_or_else(|| Error::Invalid(url, "token response lacks a token".to_owned()))
+    }
+
+    fn basic(&self, reference: &Reference) -> Option<String> {
+        self.credentials.get(&reference.registry).map(|c| {
+            let pair = format!("{}:{}", c.username, c.password);
+            format!("Basic {}", hash::to_base64(pair.as_bytes()))
+        })
+    }
+
+    // Fetch a manifest, authenticating as requested by the registry.
+    // Returns the manifest together with its digest.
+    fn manifest(
+        &self,
+        reference: &Reference,
+        name: &str,
+        auth: &mut Option<String>,
+    ) -> Result<(Json, String), Error> {
+        let url = format!(
+            "https://{}/v2/{}/manifests/{}",
+            reference.api_host(),
+            reference.repository,
+            name,
+        );
+        let accept = MEDIA_TYPES.join(", ");
+
+        let mut retried = false;
+        let r = loop {
+            let mut headers = vec![("Accept", accept.as_str())];
+            if let Some(v) = auth.as_deref() {
+                headers.push(("Authorization", v));
+            }
+            let r = self
+                .transport
+                .get(&url, &headers)
+                .map_err(Error::Transport)?;
+            if r.status != 401 || retried {
+                break r;
+            }
+
+            retried = true;
+            let (scheme, params) = challenge(r.header("WWW-Authenticate").unwrap_or(""));
+            *auth = match scheme.as_str() {
+                "bearer" => Some(self.token(reference, &params)?),
+                "basic" => match self.basic(reference) {
+                    None => return Err(Error::Status(url, 401)),
+                    v => v,
+                },
+                _ => return Err(Error::Status(url, 401)),
+            };
+        };
+        if r.status != 200 {
+            return Err(Error::Status(url, r.status));
+        }
+
+        let digest = match r.header("Docker-Content-Digest") {
+            Some(v) => v.to_owned(),
+            None => format!("sha256:{}", hash::to_hex(&hash::Sha256::digest(&r.body))),
+        };
+        let json =
+            serde_json::from_slice(&r.body).map_err(|e| Error::Invalid(url, e.to_string()))?;
+        Ok((json, digest))
+    }
+
+    /// Resolve Reference
+    ///
+    /// Resolve a reference to the digests of the image for the target
+    /// architecture.
+    pub fn resolve(&self, reference: &Reference) -> Result<Resolved, Error> {
+        span!("resolve-container", reference = reference);
+        let mut auth = None;
+        let name = reference
+            .digest
+            .as_deref()
+            .or(reference.tag.as_deref())
+            .unwrap_or("latest");
+        let (mut manifest, mut digest) = self.manifest(reference, name, &mut auth)?;
+        let mut list_digest = None;
+
+        if let Some(list) = manifest.get("manifests").and_then(Json::as_array) {
+            let arch = goarch(&self.arch);
+            let entry = list
+                .iter()
+                .find(|v| {
+                    v.pointer("/platform/architecture").and_then(Json::as_str) == Some(arch)
+                        && v.pointer("/platform/os")
+                            .and_then(Json::as_str)
+                            .is_none_or(|v| v == "linux")
+                })
+                .and_then(|v| v.get("digest"))
+                .and_then(Json::as_str)
+                .ok_or_else(|| Error::NoArch(reference.to_string(), self.arch.clone()))?
+                .to_owned();
+            list_digest = Some(digest);
+            (manifest, digest) = self.manifest(reference, &entry, &mut auth)?;
+        }
+
+        let image_id = manifest
+            .pointer("/config/digest")
+            .and_then(Json::as_str)
+            .ok_or_else(|| {
+                Error::Invalid(reference.to_string(), "manifest lacks a config".to_owned())
+            })?
+            .to_owned();
+
+        event!(Debug, "resolved", digest = digest, image = image_id);
+        Ok(Resolved {
+            reference: reference.clone(),
+            digest,
+            list_digest,
+            image_id,
+        })
+    }
+
+    /// Resolve Blueprint Containers
+    ///
+    /// Resolve the container references of a blueprint.
+    #[cfg(feature = "blueprint")]
+    pub fn resolve_blueprint(&self, blueprint: &Blueprint) -> Result<Vec<Resolved>, Error> {
+        blueprint
+            .containers
+            .iter()
+            .map(|v| {
+                let reference = Reference::parse(&v.source)
+                    .ok_or_else(|| Error::Reference(v.source.clone()))?;
+                self.resolve(&reference)
+            })
+            .collect()
+    }
+
+    /// Pin Manifest Sources
+    ///
+    /// Resolve the items of the `org.osbuild.skopeo` source of a manifest
+    /// that lack a digest, re-keying them by their image ID and adding the
+    /// resolved manifest digest. The image name is reduced to the name
+    /// without tag. Items that already have a digest are kept.
+    pub fn pin(&self, manifest: &mut Json) -> Result<(), Error> {
+        let items = match manifest
+            .pointer_mut("/sources/org.osbuild.skopeo/items")
+            .and_then(Json::as_object_mut)
+        {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+
+        for (key, item) in std::mem::take(items) {
+            let name = item.pointer("/image/name").and_then(Json::as_str);
+            let pinned = item.pointer("/image/digest").is_some();
+            let (name, mut item) = match (name, pinned) {
+                (Some(name), false) => (name.to_owned(), item),
+                _ => {
+                    items.insert(key, item);
+                    continue;
+                }
+            };
+
+            let reference = Reference::parse(&name).ok_or(Error::Reference(name))?;
+            let resolved = self.resolve(&reference)?;
+            if let Some(Json::Object(image)) = item.get_mut("image") {
+                image.insert("name".to_owned(), Json::String(reference.name()));
+                image.insert("digest".to_owned(), Json::String(resolved.digest));
+            }
+            items.insert(resolved.image_id, item);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+    use std::cell::RefCell;
+
+    // Verify Reference Parsing
+    #[test]
+    fn verify_reference() {
+        let r = Reference::parse("fedora").unwrap();
+        assert_eq!(r.to_string(), "docker.io/library/fedora:latest");
+
+        let r = Reference::parse("quay.io/centos-bootc/centos-bootc:stream9").unwrap();
+        assert_eq!(r.registry, "quay.io");
+        assert_eq!(r.repository, "centos-bootc/centos-bootc");
+        assert_eq!(r.tag.as_deref(), Some("stream9"));
+
+        let r = Reference::parse("localhost:5000/img@sha256:abcd").unwrap();
+        assert_eq!(r.registry, "localhost:5000");
+        assert_eq!(r.tag, None);
+        assert_eq!(r.digest.as_deref(), Some("sha256:abcd"));
+
+        assert!(Reference::parse("Upper/Case").is_none());
+        assert!(Reference::parse("img@sha256").is_none());
+    }
+
+    // A transport replaying canned responses, recording all requests.
+    // Each response applies to requests of its URL with or without a
+    // bearer token, as given by its flag.
+    struct Fake {
+        responses: Vec<(&'static str, bool, Response)>,
+        log: RefCell<Vec<(String, Vec<String>)>>,
+    }
+
+    impl Transport for Fake {
+        fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, String> {
+            let auth: Vec<String> = headers
+                .iter()
+                .filter(|(k, _)| *k == "Authorization")
+                .map(|(_, v)| v.to_string())
+                .collect();
+            self.log.borrow_mut().push((url.to_owned(), auth.clone()));
+
+            let bearer = auth.iter().any(|v| v.starts_with("Bearer "));
+            self.responses
+                .iter()
+                .find(|(u, b, _)| *u == url && *b == bearer)
+                .map(|(_, _, r)| r.clone())
+                .ok_or_else(|| format!("unexpected request {}", url))
+        }
+    }
+
+    fn ok(body: &str, digest: Option<&str>) -> Response {
+        Response {
+            status: 200,
+            headers: digest
+                .map(|v| vec![("docker-content-digest".to_owned(), v.to_owned())])
+                .unwrap_or_default(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    // Verify Resolution with Token Authentication
+    #[test]
+    fn verify_resolve() {
+        let base = "https://quay.io/v2/org/app/manifests";
+        let fake = Fake {
+            responses: vec![
+                (
+                    "https://quay.io/v2/org/app/manifests/latest",
+                    false,
+                    Response {
+                        status: 401,
+                        headers: vec![(
+                            "WWW-Authenticate".to_owned(),
+                            r#"Bearer realm="https://quay.io/v2/auth",service="quay.io",scope="repository:org/app:pull""#.to_owned(),
+                        )],
+                        body: Vec::new(),
+                    },
+                ),
+                (
+                    "https://quay.io/v2/auth?service=quay.io&scope=repository:org/app:pull",
+                    false,
+                    ok(r#"{ "token": "secret" }"#, None),
+                ),
+                (
+                    "https://quay.io/v2/org/app/manifests/latest",
+                    true,
+                    ok(
+                        r#"{ "manifests": [
+                            { "digest": "sha256:arm", "platform": { "architecture": "arm64", "os": "linux" } },
+                            { "digest": "sha256:amd", "platform": { "architecture": "amd64", "os": "linux" } }
+                        ] }"#,
+                        Some("sha256:list"),
+                    ),
+                ),
+                (
+                    "https://quay.io/v2/org/app/manifests/sha256:amd",
+                    true,
+                    ok(r#"{ "config": { "digest": "sha256:id" } }"#, None),
+                ),
+            ],
+            log: RefCell::new(Vec::new()),
+        };
+        let mut resolver = Resolver::new(fake, "x86_64");
+        resolver.set_credentials(
+            "quay.io",
+            Credentials {
+                username: "user".to_owned(),
+                password: "pass".to_owned(),
+            },
+        );
+
+        let mut manifest = serde_json::json!({
+            "sources": {
+                "org.osbuild.skopeo": {
+                    "items": {
+                        "app": { "image": { "name": "quay.io/org/app" } },
+                        "sha256:pinned": { "image": { "name": "quay.io/org/other", "digest": "sha256:x" } }
+                    }
+                }
+            }
+        });
+        resolver.pin(&mut manifest).unwrap();
+
+        let body = r#"{ "config": { "digest": "sha256:id" } }"#;
+        let digest = format!(
+            "sha256:{}",
+            hash::to_hex(&hash::Sha256::digest(body.as_bytes()))
+        );
+        assert_eq! {
+            manifest["sources"]["org.osbuild.skopeo"]["items"],
+            serde_json::json!({
+                "sha256:id": { "image": { "name": "quay.io/org/app", "digest": digest } },
+                "sha256:pinned": { "image": { "name": "quay.io/org/other", "digest": "sha256:x" } }
+            }),
+        }
+
+        let log = resolver.transport.log.borrow();
+        assert_eq!(log[1].1, ["Basic dXNlcjpwYXNz"]);
+        assert_eq!(log[2].1, ["Bearer secret"]);
+        assert_eq!(
+            log[3],
+            (
+                format!("{}/sha256:amd", base),
+                vec!["Bearer secret".to_owned()]
+            )
+        );
+        drop(log);
+
+        let resolver = Resolver::new(
+            Fake {
+                responses: resolver.transport.responses.clone(),
+                log: RefCell::new(Vec::new()),
+            },
+            "s390x",
+        );
+        assert!(matches!(
+            resolver.resolve(&Reference::parse("quay.io/org/app").unwrap()),
+            Err(Error::NoArch(..)),
+        ));
+    }
+}
@@ -0,0 +1,424 @@
+//! Containerfile Conversion
+//!
+//! This module converts simple Containerfiles into version-2 manifests
+//! producing an OCI archive. It is experimental, and covers the common
+//! instructions only:
+//!
+//!  * `FROM` deploys the base image via an `org.osbuild.skopeo` source. The
+//!    source is not pinned, so the manifest must be passed through
+//!    `container::Client::pin()` before it can be built.
+//!  * `RUN` runs its command as `org.osbuild.script` stage, with the
+//!    environment and working directory set so far.
+//!  * `COPY` copies files of the build context via `org.osbuild.inline`
+//!    sources.
+//!  * `ENV`, `LABEL`, `CMD`, `ENTRYPOINT`, `WORKDIR`, `USER`, and `EXPOSE`
+//!    end up in the configuration of the OCI archive.
+//!
+//! Other instructions, multi-stage builds, and copies of directories are
+//! reported as unsupported, rather than silently dropped.
+
+use crate::container::{self, Reference};
+use crate::manifest::{Json, Manifest2, Pipeline2, Stage2, Symbol};
+use crate::sources::{self, SourcesBuilder};
+use std::path::Path;
+
+/// Conversion Error
+#[derive(Debug)]
+pub enum Error {
+    /// The instruction on the given line is not valid.
+    Syntax(usize, String),
+    /// The file of the build context cannot be read.
+    Context(sources::Error),
+    /// The Containerfile has no `FROM` instruction.
+    NoBase,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Syntax(line, v) => write!(fmt, "line {}: {}", line, v),
+            Error::Context(v) => write!(fmt, "build context: {}", v),
+            Error::NoBase => write!(fmt, "no base image given via `FROM`"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Converted Containerfile
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Converted {
+    /// The manifest, exporting the OCI archive as pipeline `container`.
+    pub manifest: Manifest2,
+    /// Instructions that were not converted.
+    pub unsupported: Vec<String>,
+}
+
+// Join continuation lines and drop comments, returning the instructions
+// together with their line numbers.
+fn instructions(text: &str) -> Vec<(usize, String)> {
+    let mut acc = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (n, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if current.is_none() && (trimmed.is_empty() || trimmed.starts_with('#')) {
+            continue;
+        }
+        let (_, v) = current.get_or_insert_with(|| (n + 1, String::new()));
+        match trimmed.strip_suffix('\\') {
+            Some(head) => {
+                *v += head;
+                *v += " ";
+            }
+            None => {
+                *v += trimmed;
+                acc.extend(current.take());
+            }
+        }
+    }
+    acc.extend(current);
+    acc
+}
+
+// Split `KEY=VALUE` pairs of `ENV` and `LABEL`, honoring double quotes.
+// The legacy form `KEY VALUE` sets a single variable.
+fn pairs(args: &str) -> Result<Vec<(String, String)>, String> {
+    let first = args.split_whitespace().next().unwrap_or_default();
+    if !first.contains('=') {
+        let (key, value) = args
+            .split_once(char::is_whitespace)
+            .ok_or("missing value")?;
+        return Ok(vec![(key.to_owned(), value.trim().to_owned())]);
+    }
+
+    let mut acc = Vec::new();
+    let mut chars = args.chars().peekable();
+    loop {
+        while chars.next_if(|v| v.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(acc);
+        }
+        let key: String = std::iter::from_fn(|| chars.next_if(|v| *v != '=')).collect();
+        if chars.next() != Some('=') || key.contains(char::is_whitespace) {
+            return Err(format!("invalid pair `{}`", key.trim()));
+        }
+        let mut value = String::new();
+        while let Some(c) = chars.next_if(|v| !v.is_whitespace()) {
+            match c {
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(v) => value.push(v),
+                        None => return Err("unterminated quote".to_owned()),
+                    }
+                },
+                '\\' => value.extend(chars.next()),
+                c => value.push(c),
+            }
+        }
+        acc.push((key, value));
+    }
+}
+
+// Parse the exec form `["a", "b"]` of a command, or wrap the shell form.
+fn command(args: &str) -> Result<Vec<String>, String> {
+    match args.starts_with('[') {
+        true => serde_json::from_str(args).map_err(|e| e.to_string()),
+        false => Ok(vec!["/bin/sh".to_owned(), "-c".to_owned(), args.to_owned()]),
+    }
+}
+
+// Quote a word for the shell.
+fn quote(v: &str) -> String {
+    format!("'{}'", v.replace('\'', "'\\''"))
+}
+
+fn stage(kind: &str, options: Json, inputs: Json) -> Stage2 {
+    let mut stage = Stage2::default();
+    stage.r#type = kind.into();
+    stage.options = options
+        .as_object()
+        .map(|v| v.clone().into_iter().collect())
+        .unwrap_or_default();
+    stage.inputs = inputs
+        .as_object()
+        .map(|v| v.clone().into_iter().collect())
+        .unwrap_or_default();
+    stage
+}
+
+/// Convert Containerfile
+///
+/// Convert the Containerfile for the architecture, like `x86_64`, reading
+/// copied files relative to the build context directory.
+pub fn convert(text: &str, context: &Path, arch: &str) -> Result<Converted, Error> {
+    let mut unsupported = Vec::new();
+    let mut base: Option<Reference> = None;
+    let mut stages = Vec::new();
+    let mut sources = SourcesBuilder::new();
+    sources.threshold = usize::MAX;
+    let mut env: Vec<(String, String)> = Vec::new();
+    let mut config = serde_json::Map::new();
+
+    for (n, line) in instructions(text) {
+        let (keyword, args) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        let args = args.trim();
+        let syntax = |v: String| Error::Syntax(n, v);
+
+        match (keyword.to_ascii_uppercase().as_str(), &base) {
+            ("FROM", None) => {
+                let reference = match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [v] => v,
+                    _ => {
+                        unsupported.push(line.clone());
+                        args.split_whitespace().next().unwrap_or_default()
+                    }
+                };
+                let reference = Reference::parse(reference)
+                    .ok_or_else(|| syntax(format!("invalid reference `{}`", reference)))?;
+                base = Some(reference);
+            }
+            (_, None) => return Err(Error::NoBase),
+            ("RUN", _) => {
+                let mut script = "#!/bin/sh\nset -e\n".to_owned();
+                for (key, value) in &env {
+                    script += &format!("export {}={}\n", key, quote(value));
+                }
+                if let Some(Json::String(v)) = config.get("WorkingDir") {
+                    script += &format!("mkdir -p {0}\ncd {0}\n", quote(v));
+                }
+                match command(args).map_err(syntax)?.as_slice() {
+                    [sh, c, v] if sh == "/bin/sh" && c == "-c" => script += v,
+                    v => script += &v.iter().map(|v| quote(v)).collect::<Vec<_>>().join(" "),
+                }
+                script += "\n";
+                stages.push(stage(
+                    "org.osbuild.script",
+                    serde_json::json!({ "script": script }),
+                    Json::Null,
+                ));
+            }
+            ("COPY", _) if !args.starts_with("--") => {
+                let paths: Vec<&str> = args.split_whitespace().collect();
+                let Some((to, from)) = paths.split_last().filter(|v| !v.1.is_empty()) else {
+                    return Err(syntax("COPY requires a source and a destination".into()));
+                };
+                let mut references = serde_json::Map::new();
+                let mut copies = Vec::new();
+                for path in from {
+                    let file = context.join(path);
+                    if !file.is_file() {
+                        unsupported.push(format!("COPY {}", path));
+                        continue;
+                    }
+                    let digest = sources.file(&file).map_err(Error::Context)?;
+                    let name = Path::new(path).file_name().unwrap_or_default();
+                    let to = match to.ends_with('/') || from.len() > 1 {
+                        true => format!("{}/{}", to.trim_end_matches('/'), name.to_string_lossy()),
+                        false => (*to).to_owned(),
+                    };
+                    copies.push(serde_json::json!({
+                        "from": format!("input://files/{}", digest),
+                        "to": format!("tree://{}", to),
+                    }));
+                    references.insert(digest, serde_json::json!({}));
+                }
+                if !copies.is_empty() {
+                    stages.push(stage(
+                        "org.osbuild.copy",
+                        serde_json::json!({ "paths": copies }),
+                        serde_json::json!({ "files": {
+                            "type": "org.osbuild.files",
+                            "origin": "org.osbuild.source",
+                            "references": references,
+                        } }),
+                    ));
+                }
+            }
+            ("ENV", _) => {
+                for (key, value) in pairs(args).map_err(syntax)? {
+                    env.retain(|v| v.0 != key);
+                    env.push((key, value));
+                }
+            }
+            ("LABEL", _) => {
+                let labels = config
+                    .entry("Labels")
+                    .or_insert_with(|| serde_json::json!({}));
+                for (key, value) in pairs(args).map_err(syntax)? {
+                    labels[key] = Json::String(value);
+                }
+            }
+            ("CMD", _) => {
+                config.insert("Cmd".into(), command(args).map_err(syntax)?.into());
+            }
+            ("ENTRYPOINT", _) => {
+                config.insert("Entrypoint".into(), command(args).map_err(syntax)?.into());
+            }
+            ("WORKDIR", _) => {
+                let dir = match (config.get("WorkingDir"), args.starts_with('/')) {
+                    (Some(Json::String(v)), false) => format!("{}/{}", v, args),
+                    _ => args.to_owned(),
+                };
+                config.insert("WorkingDir".into(), dir.into());
+            }
+            ("USER", _) => {
+                config.insert("User".into(), args.into());
+            }
+            ("EXPOSE", _) => {
+                let ports = config
+                    .entry("ExposedPorts")
+                    .or_insert_with(|| serde_json::json!([]));
+                if let Json::Array(ports) = ports {
+                    ports.extend(args.split_whitespace().map(Json::from));
+                }
+            }
+            _ => unsupported.push(line.clone()),
+        }
+    }
+
+    let base = base.ok_or(Error::NoBase)?;
+    if !env.is_empty() {
+        let env: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        config.insert("Env".into(), env.into());
+    }
+
+    let name = base.to_string();
+    stages.insert(
+        0,
+        stage(
+            "org.osbuild.container-deploy",
+            Json::Null,
+            serde_json::json!({ "images": {
+                "type": "org.osbuild.containers",
+                "origin": "org.osbuild.source",
+                "references": { name.as_str(): { "name": name } },
+            } }),
+        ),
+    );
+
+    let mut tree = Pipeline2::default();
+    tree.name = "tree".to_owned();
+    tree.stages = stages;
+
+    let mut archive = Pipeline2::default();
+    archive.name = "container".to_owned();
+    archive.stages = vec![stage(
+        "org.osbuild.oci-archive",
+        serde_json::json!({
+            "architecture": container::goarch(arch),
+            "filename": "container.tar",
+            "config": config,
+        }),
+        serde_json::json!({ "base": {
+            "type": "org.osbuild.tree",
+            "origin": "org.osbuild.pipeline",
+            "references": ["name:tree"],
+        } }),
+    )];
+
+    let mut manifest = Manifest2::default();
+    manifest.version = "2".to_owned();
+    manifest.pipelines = vec![tree, archive];
+    manifest.sources = sources.build();
+    manifest.sources.insert(
+        Symbol::new("org.osbuild.skopeo"),
+        [(
+            "items".to_owned(),
+            serde_json::json!({ name.as_str(): { "image": { "name": name } } }),
+        )]
+        .into(),
+    );
+
+    Ok(Converted {
+        manifest,
+        unsupported,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Containerfile Conversion
+    #[test]
+    fn verify_convert() {
+        let dir =
+            std::env::temp_dir().join(format!("r-osbuild-containerfile-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf")).unwrap();
+        std::fs::write(dir.join("app.conf"), "abc").unwrap();
+
+        let converted = convert(
+            r#"
+            # An application image
+            FROM quay.io/fedora/fedora:39
+            ENV LANG=C.UTF-8 GREETING="hello world"
+            WORKDIR /srv
+            RUN dnf -y install nginx \
+                && dnf clean all
+            COPY app.conf /etc/app.conf
+            COPY conf /etc/conf
+            LABEL org.opencontainers.image.title=app
+            EXPOSE 80/tcp
+            USER nginx
+            CMD ["nginx", "-g", "daemon off;"]
+            HEALTHCHECK CMD true
+            "#,
+            &dir,
+            "aarch64",
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let text = converted.manifest.to_string();
+        assert_eq!(text.parse::<Manifest2>().unwrap(), converted.manifest);
+        let json = serde_json::to_value(&converted.manifest).unwrap();
+        let abc = "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let name = "quay.io/fedora/fedora:39";
+        assert_eq!(
+            json["sources"]["org.osbuild.skopeo"]["items"][name]["image"]["name"],
+            name
+        );
+        assert_eq!(
+            json["sources"]["org.osbuild.inline"]["items"][abc]["data"],
+            "YWJj"
+        );
+
+        let tree = &json["pipelines"][0]["stages"];
+        assert_eq!(tree[0]["type"], "org.osbuild.container-deploy");
+        assert_eq! {
+            tree[1]["options"]["script"],
+            "#!/bin/sh\nset -e\nexport LANG='C.UTF-8'\nexport GREETING='hello world'\nmkdir -p '/srv'\ncd '/srv'\ndnf -y install nginx  && dnf clean all\n",
+        }
+        assert_eq! {
+            tree[2]["options"]["paths"],
+            serde_json::json!([{ "from": format!("input://files/{}", abc), "to": "tree:///etc/app.conf" }]),
+        }
+        assert_eq!(tree.as_array().unwrap().len(), 3);
+
+        let archive = &json["pipelines"][1]["stages"][0];
+        assert_eq!(archive["options"]["architecture"], "arm64");
+        assert_eq! {
+            archive["options"]["config"],
+            serde_json::json!({
+                "WorkingDir": "/srv",
+                "Labels": { "org.opencontainers.image.title": "app" },
+                "ExposedPorts": ["80/tcp"],
+                "User": "nginx",
+                "Cmd": ["nginx", "-g", "daemon off;"],
+                "Env": ["LANG=C.UTF-8", "GREETING=hello world"],
+            }),
+        }
+        assert_eq!(converted.unsupported, ["COPY conf", "HEALTHCHECK CMD true"]);
+
+        assert!(matches!(
+            convert("RUN true", &dir, "x86_64"),
+            Err(Error::NoBase)
+        ));
+        assert!(matches!(
+            convert("FROM Invalid", &dir, "x86_64"),
+            Err(Error::Syntax(1, _))
+        ));
+    }
+}
@@ -0,0 +1,525 @@
+//! Manifest Format Conversion
+//!
+//! This module converts manifests between format version 1 and version 2.
+//! Version-1 manifests describe a single pipeline with nested build
+//! pipelines and an optional assembler. Version 2 instead has a flat list
+//! of named pipelines, referring to each other by name, and stages consume
+//! sources and other pipelines via explicit inputs.
+//!
+//! Conversion to version 2 always succeeds. The build chain becomes a list
+//! of pipelines named `build`, `build-2`, ..., the main pipeline is named
+//! `tree`, and the assembler becomes a stage of its own `assembler`
+//! pipeline, with the tree as input. Package lists of rpm stages become
+//! inputs, and file sources are renamed to `org.osbuild.curl`.
+//!
+//! Conversion to version 1 is the reverse, and only succeeds for
+//! manifests of that shape. Version-2 features without version-1
+//! counterpart, like devices, mounts, or multiple exported trees, are
+//! reported as errors.
+
+use crate::manifest::{
+    self, build_name, Build1, Json, Manifest1, Manifest2, Object, Pipeline1, Stage1, Symbol,
+};
+
+/// Conversion Error
+#[derive(Debug)]
+pub enum Error {
+    /// The manifest is not a version-2 manifest.
+    Version,
+    /// The value at the given JSON pointer cannot be represented in the
+    /// target format.
+    Unsupported(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Version => write!(fmt, "not a version-2 manifest"),
+            Error::Unsupported(p, v) => write!(fmt, "{}: {}", p, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const RPM: &str = "org.osbuild.rpm";
+
+fn stage_v2(stage: &Stage1) -> Json {
+    let mut options = stage.options.clone();
+    let mut acc = serde_json::Map::new();
+    acc.insert("type".to_owned(), Json::String(stage.name.to_string()));
+
+    if stage.name == RPM {
+        if let Some(Json::Array(packages)) = options.remove("packages") {
+            let references: serde_json::Map<_, _> = packages
+                .into_iter()
+                .filter_map(|v| match v {
+                    Json::String(c) => Some((c, serde_json::json!({}))),
+                    Json::Object(mut o) => {
+                        let checksum = o.remove("checksum")?.as_str()?.to_owned();
+                        let metadata = match o.remove("check_gpg") {
+                            Some(g) => serde_json::json!({ "metadata": { "rpm.check_gpg": g } }),
+                            None => serde_json::json!({}),
+                        };
+                        Some((checksum, metadata))
+                    }
+                    _ => None,
+                })
+                .collect();
+            acc.insert(
+                "inputs".to_owned(),
+                serde_json::json!({
+                    "packages": {
+                        "type": "org.osbuild.files",
+                        "origin": "org.osbuild.source",
+                        "references": references,
+                    }
+                }),
+            );
+        }
+    }
+
+    if !options.is_empty() {
+        acc.insert("options".to_owned(), manifest::object_to_value(options));
+    }
+    Json::Object(acc)
+}
+
+/// Convert to Version 2
+pub fn to_v2(manifest: &Manifest1) -> Json {
+    // The main pipeline, followed by its build pipelines, innermost last.
+    let mut chain = vec![(&manifest.pipeline, None)];
+    let mut pipeline = &manifest.pipeline;
+    while let Some(build) = &pipeline.build {
+        chain.last_mut().unwrap().1 = Some(build.runner.as_str());
+        chain.push((&build.pipeline, None));
+        pipeline = &build.pipeline;
+    }
+
+    let mut pipelines = Vec::new();
+    for (depth, (pipeline, runner)) in chain.iter().enumerate().rev() {
+        let mut acc = serde_json::Map::new();
+        acc.insert(
+            "name".to_owned(),
+            Json::String(match depth {
+                0 => "tree".to_owned(),
+                n => build_name(n),
+            }),
+        );
+        if let Some(runner) = runner {
+            acc.insert(
+                "build".to_owned(),
+                Json::String(format!("name:{}", build_name(depth + 1))),
+            );
+            acc.insert("runner".to_owned(), Json::String((*runner).to_owned()));
+        }
+        acc.insert(
+            "stages".to_owned(),
+            Json::Array(pipeline.stages.iter().map(stage_v2).collect()),
+        );
+        pipelines.push(Json::Object(acc));
+    }
+
+    if let Some(assembler) = &manifest.pipeline.assembler {
+        let mut acc = serde_json::Map::new();
+        acc.insert("name".to_owned(), Json::String("assembler".to_owned()));
+        if let Some(runner) = chain[0].1 {
+            acc.insert("build".to_owned(), Json::String("name:build".to_owned()));
+            acc.insert("runner".to_owned(), Json::String(runner.to_owned()));
+        }
+        let mut stage = serde_json::json!({
+            "type": assembler.name,
+            "inputs": {
+                "tree": {
+                    "type": "org.osbuild.tree",
+                    "origin": "org.osbuild.pipeline",
+                    "references": ["name:tree"],
+                }
+            }
+        });
+        if !assembler.options.is_empty() {
+            stage["options"] = manifest::object_to_value(assembler.options.clone());
+        }
+        acc.insert("stages".to_owned(), Json::Array(vec![stage]));
+        pipelines.push(Json::Object(acc));
+    }
+
+    let mut sources = serde_json::Map::new();
+    for (name, source) in &manifest.sources {
+        let mut source = manifest::object_to_map(source.clone());
+        let name = match name.as_str() {
+            "org.osbuild.files" => {
+                if let Some(urls) = source.remove("urls") {
+                    source.insert("items".to_owned(), urls);
+                }
+                "org.osbuild.curl"
+            }
+            v => v,
+        };
+        sources.insert(name.to_owned(), Json::Object(source));
+    }
+
+    let mut acc = serde_json::Map::new();
+    acc.insert("version".to_owned(), Json::String("2".to_owned()));
+    acc.insert("pipelines".to_owned(), Json::Array(pipelines));
+    if !sources.is_empty() {
+        acc.insert("sources".to_owned(), Json::Object(sources));
+    }
+    Json::Object(acc)
+}
+
+fn unsupported(path: &str, reason: &str) -> Error {
+    Error::Unsupported(path.to_owned(), reason.to_owned())
+}
+
+// Find the name of the pipeline a pipeline reference refers to.
+fn reference(value: &Json) -> Option<&str> {
+    value.as_str()?.strip_prefix("name:")
+}
+
+fn stage_v1(path: &str, stage: &Json) -> Result<Stage1, Error> {
+    let object = stage
+        .as_object()
+        .ok_or_else(|| unsupported(path, "stage is not an object"))?;
+    let mut acc = Stage1::default();
+    acc.name = object
+        .get("type")
+        .and_then(Json::as_str)
+        .ok_or_else(|| unsupported(path, "stage has no type"))?
+        .into();
+    if let Some(Json::Object(options)) = object.get("options") {
+        acc.options = manifest::object_from_map(options.clone());
+    }
+
+    for key in object.keys() {
+        if !matches!(key.as_str(), "type" | "options" | "inputs") {
+            return Err(unsupported(
+                &format!("{}/{}", path, key),
+                "not supported by version 1",
+            ));
+        }
+    }
+
+    let inputs = match object.get("inputs").and_then(Json::as_object) {
+        None => return Ok(acc),
+        Some(v) => v,
+    };
+    for (key, input) in inputs {
+        let ipath = format!("{}/inputs/{}", path, key);
+        if acc.name != RPM || key != "packages" {
+            return Err(unsupported(&ipath, "inputs are not supported by version 1"));
+        }
+        if input.get("origin").and_then(Json::as_str) != Some("org.osbuild.source") {
+            return Err(unsupported(&ipath, "packages must come from sources"));
+        }
+
+        let packages: Vec<Json> = match input.get("references") {
+            Some(Json::Array(v)) => v.clone(),
+            Some(Json::Object(v)) => v
+                .iter()
+                .map(
+                    |(k, v)| match v.pointer("/metadata/rpm.check_gpg").cloned() {
+                        Some(g) => serde_json::json!({ "checksum": k, "check_gpg": g }),
+                        None => Json::String(k.clone()),
+                    },
+                )
+                .collect(),
+            _ => return Err(unsupported(&ipath, "invalid references")),
+        };
+        acc.options
+            .insert("packages".to_owned(), Json::Array(packages));
+    }
+
+    Ok(acc)
+}
+
+/// Convert to Version 1
+pub fn to_v1(manifest: &Json) -> Result<Manifest1, Error> {
+    if manifest.get("version").and_then(Json::as_str) != Some("2") {
+        return Err(Error::Version);
+    }
+    let pipelines = manifest
+        .get("pipelines")
+        .and_then(Json::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let index = |name: &str| {
+        pipelines
+            .iter()
+            .position(|v| v.get("name").and_then(Json::as_str) == Some(name))
+    };
+    let mut used = vec![false; pipelines.len()];
+
+    // The last pipeline is exported. If it consists of a single stage
+    // consuming another pipeline as tree, that stage is the assembler.
+    let mut target = match pipelines.len() {
+        0 => return Ok(Manifest1::default()),
+        n => n - 1,
+    };
+    let mut assembler = None;
+    let last = &pipelines[target];
+    if let Some([stage]) = last
+        .get("stages")
+        .and_then(Json::as_array)
+        .map(Vec::as_slice)
+    {
+        let tree = stage
+            .pointer("/inputs/tree")
+            .filter(|v| v.get("origin").and_then(Json::as_str) == Some("org.osbuild.pipeline"))
+            .and_then(|v| v.get("references"))
+            .and_then(Json::as_array)
+            .and_then(|v| match v.as_slice() {
+                [v] => reference(v).and_then(index),
+                _ => None,
+            });
+        if let Some(tree) = tree {
+            if last.get("build") != pipelines[tree].get("build")
+                || last.get("runner") != pipelines[tree].get("runner")
+            {
+                return Err(unsupported(
+                    &format!("/pipelines/{}", target),
+                    "assembler must share the build pipeline of its tree",
+                ));
+            }
+            let mut stage = stage.clone();
+            if let Some(v) = stage.as_object_mut() {
+                v.remove("inputs");
+            }
+            let stage = stage_v1(&format!("/pipelines/{}/stages/0", target), &stage)?;
+            let mut v = crate::manifest::Assembler1::default();
+            v.name = stage.name;
+            v.options = stage.options;
+            assembler = Some(v);
+            used[target] = true;
+            target = tree;
+        }
+    }
+
+    // Convert the build chain, starting at the tree.
+    let mut chain: Vec<(Pipeline1, Option<String>)> = Vec::new();
+    let mut next = Some(target);
+    while let Some(i) = next.take() {
+        let path = format!("/pipelines/{}", i);
+        if used[i] {
+            return Err(unsupported(&path, "build pipelines form a cycle"));
+        }
+        used[i] = true;
+
+        let pipeline = &pipelines[i];
+        for key in pipeline.as_object().into_iter().flat_map(|v| v.keys()) {
+            if !matches!(key.as_str(), "name" | "build" | "runner" | "stages") {
+                return Err(unsupported(
+                    &format!("{}/{}", path, key),
+                    "not supported by version 1",
+                ));
+            }
+        }
+
+        let mut acc = Pipeline1::default();
+        for (j, stage) in pipeline
+            .get("stages")
+            .and_then(Json::as_array)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            acc.stages
+                .push(stage_v1(&format!("{}/stages/{}", path, j), stage)?);
+        }
+
+        let runner = pipeline
+            .get("runner")
+            .and_then(Json::as_str)
+            .map(str::to_owned);
+        if let Some(build) = pipeline.get("build") {
+            next = Some(
+                reference(build)
+                    .and_then(index)
+                    .ok_or_else(|| unsupported(&format!("{}/build", path), "unknown pipeline"))?,
+            );
+            if runner.is_none() {
+                return Err(unsupported(&path, "build pipelines require a runner"));
+            }
+        }
+        chain.push((acc, runner));
+    }
+
+    if let Some(i) = used.iter().position(|v| !v) {
+        return Err(unsupported(
+            &format!("/pipelines/{}", i),
+            "pipeline is not part of the exported build chain",
+        ));
+    }
+
+    // Nest the chain, innermost build pipeline first.
+    let mut pipeline = None;
+    while let Some((mut p, runner)) = chain.pop() {
+        if let (Some(inner), Some(runner)) = (pipeline.take(), runner) {
+            let mut build = Build1::default();
+            build.pipeline = inner;
+            build.runner = runner.into();
+            p.build = Some(Box::new(build));
+        }
+        pipeline = Some(p);
+    }
+
+    let mut acc = Manifest1::default();
+    acc.pipeline = pipeline.unwrap_or_default();
+    acc.pipeline.assembler = assembler;
+
+    let sources = manifest.get("sources").and_then(Json::as_object);
+    for (name, source) in sources.into_iter().flatten() {
+        let mut source: Object<Json> = match source {
+            Json::Object(v) => manifest::object_from_map(v.clone()),
+            _ => return Err(unsupported(&format!("/sources/{}", name), "invalid source")),
+        };
+        let name = match name.as_str() {
+            "org.osbuild.curl" => {
+                if let Some(items) = source.remove("items") {
+                    source.insert("urls".to_owned(), items);
+                }
+                "org.osbuild.files"
+            }
+            v => v,
+        };
+        acc.sources.insert(Symbol::new(name), source);
+    }
+
+    Ok(acc)
+}
+
+impl From<&Manifest1> for Manifest2 {
+    fn from(v: &Manifest1) -> Self {
+        serde_json::from_value(to_v2(v)).expect("conversions produce valid manifests")
+    }
+}
+
+impl TryFrom<&Manifest2> for Manifest1 {
+    type Error = Error;
+
+    fn try_from(v: &Manifest2) -> Result<Self, Error> {
+        to_v1(&serde_json::to_value(v).expect("manifests always serialize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Round-Trip Conversion
+    #[test]
+    fn verify_convert() {
+        let v1: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "build": {
+                                "pipeline": { "stages": [{ "name": "org.osbuild.noop" }] },
+                                "runner": "org.osbuild.linux"
+                            },
+                            "stages": [{
+                                "name": "org.osbuild.rpm",
+                                "options": { "packages": ["sha256:a", { "checksum": "sha256:b", "check_gpg": true }] }
+                            }]
+                        },
+                        "runner": "org.osbuild.fedora39"
+                    },
+                    "stages": [{ "name": "org.osbuild.locale", "options": { "language": "en_US" } }],
+                    "assembler": { "name": "org.osbuild.tar", "options": { "filename": "root.tar" } }
+                },
+                "sources": {
+                    "org.osbuild.files": { "urls": { "sha256:a": "https://example.com/a.rpm" } }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let v2 = to_v2(&v1);
+        assert_eq! {
+            v2,
+            serde_json::json!({
+                "version": "2",
+                "pipelines": [
+                    { "name": "build-2", "stages": [{ "type": "org.osbuild.noop" }] },
+                    {
+                        "name": "build",
+                        "build": "name:build-2",
+                        "runner": "org.osbuild.linux",
+                        "stages": [{
+                            "type": "org.osbuild.rpm",
+                            "inputs": {
+                                "packages": {
+                                    "type": "org.osbuild.files",
+                                    "origin": "org.osbuild.source",
+                                    "references": {
+                                        "sha256:a": {},
+                                        "sha256:b": { "metadata": { "rpm.check_gpg": true } }
+                                    }
+                                }
+                            }
+                        }]
+                    },
+                    {
+                        "name": "tree",
+                        "build": "name:build",
+                        "runner": "org.osbuild.fedora39",
+                        "stages": [{ "type": "org.osbuild.locale", "options": { "language": "en_US" } }]
+                    },
+                    {
+                        "name": "assembler",
+                        "build": "name:build",
+                        "runner": "org.osbuild.fedora39",
+                        "stages": [{
+                            "type": "org.osbuild.tar",
+                            "inputs": {
+                                "tree": {
+                                    "type": "org.osbuild.tree",
+                                    "origin": "org.osbuild.pipeline",
+                                    "references": ["name:tree"]
+                                }
+                            },
+                            "options": { "filename": "root.tar" }
+                        }]
+                    }
+                ],
+                "sources": {
+                    "org.osbuild.curl": { "items": { "sha256:a": "https://example.com/a.rpm" } }
+                }
+            }),
+        }
+        assert_eq!(to_v1(&v2).unwrap(), v1);
+
+        // Typed conversions agree with the JSON conversions.
+        let typed = Manifest2::from(&v1);
+        assert_eq!(serde_json::to_value(&typed).unwrap(), v2);
+        assert_eq!(Manifest1::try_from(&typed).unwrap(), v1);
+    }
+
+    // Verify Unsupported Manifests
+    #[test]
+    fn verify_unsupported() {
+        assert!(matches!(to_v1(&serde_json::json!({})), Err(Error::Version)));
+
+        let v2 = serde_json::json!({
+            "version": "2",
+            "pipelines": [
+                { "name": "a", "stages": [] },
+                { "name": "b", "stages": [{ "type": "org.osbuild.mkfs", "devices": {} }] }
+            ]
+        });
+        match to_v1(&v2) {
+            Err(Error::Unsupported(p, _)) => assert_eq!(p, "/pipelines/1/stages/0/devices"),
+            v => panic!("unexpected result: {:?}", v),
+        }
+
+        let v2 = serde_json::json!({
+            "version": "2",
+            "pipelines": [{ "name": "a" }, { "name": "b" }]
+        });
+        match to_v1(&v2) {
+            Err(Error::Unsupported(p, _)) => assert_eq!(p, "/pipelines/0"),
+            v => panic!("unexpected result: {:?}", v),
+        }
+    }
+}
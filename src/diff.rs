@@ -0,0 +1,384 @@
+//! Structural Manifest Diffs
+//!
+//! This module compares manifests structurally, rather than textually. Both
+//! sides are normalized first, so differences in member order, defaults, or
+//! option spellings do not show up. The result is a list of changes, each
+//! referring to the affected value via its JSON pointer.
+//!
+//! Some differences are expected between otherwise equal manifests, like
+//! generated identifiers or mirror hostnames. Ignore rules remove them from
+//! both sides before comparing, so regression checks only report
+//! meaningful changes.
+
+use crate::lint;
+use crate::manifest::{Json, Manifest, Manifest1, Manifest2, ManifestFormat};
+use crate::normalize;
+
+/// Structural Change
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+    /// A value was added at the given path.
+    Added(String, Json),
+    /// A value was removed from the given path.
+    Removed(String, Json),
+    /// The value at the given path was replaced, from old to new.
+    Changed(String, Json, Json),
+}
+
+impl Change {
+    /// Path of the Change
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added(v, _) | Change::Removed(v, _) | Change::Changed(v, _, _) => v,
+        }
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(p, v) => write!(fmt, "+ {}: {}", p, v),
+            Change::Removed(p, v) => write!(fmt, "- {}: {}", p, v),
+            Change::Changed(p, a, b) => write!(fmt, "~ {}: {} -> {}", p, a, b),
+        }
+    }
+}
+
+/// Ignore Rule
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Ignore {
+    /// Ignore all object members with the given name, at any depth.
+    Key(String),
+    /// Ignore the value at the given JSON pointer. A segment of `*`
+    /// matches any member or element.
+    Path(String),
+    /// Ignore the scheme and host of URLs in sources, so content fetched
+    /// from different mirrors compares equal.
+    UrlHosts,
+    /// Ignore the order of stages within pipelines. Paths of changes
+    /// within stages then refer to the stages in sorted order.
+    StageOrder,
+}
+
+// Remove all members with the given name.
+fn strip_key(value: &mut Json, key: &str) {
+    match value {
+        Json::Object(v) => {
+            v.remove(key);
+            v.values_mut().for_each(|v| strip_key(v, key));
+        }
+        Json::Array(v) => v.iter_mut().for_each(|v| strip_key(v, key)),
+        _ => {}
+    }
+}
+
+// Remove the values matching the JSON pointer segments.
+fn strip_path(value: &mut Json, segments: &[String]) {
+    let (first, rest) = match segments.split_first() {
+        None => return,
+        Some(v) => v,
+    };
+    match value {
+        Json::Object(v) if rest.is_empty() => match first.as_str() {
+            "*" => v.clear(),
+            k => {
+                v.remove(k);
+            }
+        },
+        Json::Object(v) => {
+            for (k, v) in v.iter_mut() {
+                if first == "*" || first == k {
+                    strip_path(v, rest);
+                }
+            }
+        }
+        // Elements are only ever cleared, not removed, since that would
+        // shift the indices of the remaining ones.
+        Json::Array(v) => {
+            for (i, v) in v.iter_mut().enumerate() {
+                if first == "*" || *first == i.to_string() {
+                    match rest.is_empty() {
+                        true => *v = Json::Null,
+                        false => strip_path(v, rest),
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Replace the scheme and host of all URLs within the value.
+fn strip_hosts(value: &mut Json) {
+    match value {
+        Json::String(v) => {
+            if let Some((_, rest)) = v.split_once("://") {
+                let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+                *v = format!("*://*{}", &rest[end..]);
+            }
+        }
+        Json::Object(v) => v.values_mut().for_each(strip_hosts),
+        Json::Array(v) => v.iter_mut().for_each(strip_hosts),
+        _ => {}
+    }
+}
+
+// Sort all arrays of stages by their serialized form.
+fn sort_stages(value: &mut Json) {
+    match value {
+        Json::Object(v) => {
+            for (k, v) in v.iter_mut() {
+                if let (true, Json::Array(stages)) = (k == "stages", &mut *v) {
+                    stages.sort_by_cached_key(Json::to_string);
+                }
+                sort_stages(v);
+            }
+        }
+        Json::Array(v) => v.iter_mut().for_each(sort_stages),
+        _ => {}
+    }
+}
+
+// Apply the ignore rules to the value.
+fn strip(value: &mut Json, rules: &[Ignore]) {
+    for rule in rules {
+        match rule {
+            Ignore::Key(k) => strip_key(value, k),
+            Ignore::Path(p) => {
+                let segments: Vec<String> = p
+                    .split('/')
+                    .skip(1)
+                    .map(|v| v.replace("~1", "/").replace("~0", "~"))
+                    .collect();
+                strip_path(value, &segments);
+            }
+            Ignore::UrlHosts => {
+                if let Some(v) = value.get_mut("sources") {
+                    strip_hosts(v);
+                }
+            }
+            Ignore::StageOrder => sort_stages(value),
+        }
+    }
+}
+
+fn walk(path: &str, old: &Json, new: &Json, acc: &mut Vec<Change>) {
+    match (old, new) {
+        (Json::Object(a), Json::Object(b)) => {
+            for (k, v) in a {
+                match b.get(k) {
+                    Some(w) => walk(&lint::pointer(path, k), v, w, acc),
+                    None => acc.push(Change::Removed(lint::pointer(path, k), v.clone())),
+                }
+            }
+            for (k, w) in b {
+                if !a.contains_key(k) {
+                    acc.push(Change::Added(lint::pointer(path, k), w.clone()));
+                }
+            }
+        }
+        (Json::Array(a), Json::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                let p = format!("{}/{}", path, i);
+                match (a.get(i), b.get(i)) {
+                    (Some(v), Some(w)) => walk(&p, v, w, acc),
+                    (Some(v), None) => acc.push(Change::Removed(p, v.clone())),
+                    (None, Some(w)) => acc.push(Change::Added(p, w.clone())),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (a, b) if a == b => {}
+        (a, b) => acc.push(Change::Changed(path.to_owned(), a.clone(), b.clone())),
+    }
+}
+
+/// Diff JSON Values
+///
+/// Compare two JSON values and return the changes from `old` to `new`.
+/// Objects are compared by member, arrays by index.
+pub fn diff(old: &Json, new: &Json) -> Vec<Change> {
+    let mut acc = Vec::new();
+    walk("", old, new, &mut acc);
+    acc
+}
+
+/// Diff JSON Values with Ignore Rules
+///
+/// Compare two JSON values like `diff()`, after applying the ignore rules
+/// to both.
+pub fn diff_with(old: &Json, new: &Json, rules: &[Ignore]) -> Vec<Change> {
+    let (mut old, mut new) = (old.clone(), new.clone());
+    strip(&mut old, rules);
+    strip(&mut new, rules);
+    diff(&old, &new)
+}
+
+/// Diff Manifests
+///
+/// Compare the normalized forms of two manifests.
+pub fn manifests(old: &Manifest1, new: &Manifest1) -> Vec<Change> {
+    diff(&normalize::normalize(old), &normalize::normalize(new))
+}
+
+/// Diff Manifests with Ignore Rules
+///
+/// Compare the normalized forms of two manifests, after applying the
+/// ignore rules to both.
+pub fn manifests_with(old: &Manifest1, new: &Manifest1, rules: &[Ignore]) -> Vec<Change> {
+    let (mut old, mut new) = (normalize::normalize(old), normalize::normalize(new));
+    strip(&mut old, rules);
+    strip(&mut new, rules);
+    diff(&old, &new)
+}
+
+/// Diff Version-2 Manifests with Ignore Rules
+///
+/// Compare the normalized forms of two version-2 manifests, like
+/// `manifests_with()`.
+pub fn manifests2_with(old: &Manifest2, new: &Manifest2, rules: &[Ignore]) -> Vec<Change> {
+    let (mut old, mut new) = (normalize::normalize2(old), normalize::normalize2(new));
+    strip(&mut old, rules);
+    strip(&mut new, rules);
+    diff(&old, &new)
+}
+
+/// Diff Manifests of Either Version
+///
+/// Compare the normalized forms of two manifests of the same version, in
+/// that version, after applying the ignore rules to both. Manifests that
+/// are equal under `Manifest::semantic_eq()` have no changes. Returns
+/// `None` if the manifests are of different versions.
+pub fn manifests_any(old: &Manifest, new: &Manifest, rules: &[Ignore]) -> Option<Vec<Change>> {
+    if old.version() != new.version() {
+        return None;
+    }
+    let (mut old, mut new) = (normalize::normalize_any(old), normalize::normalize_any(new));
+    if old == new {
+        return Some(Vec::new());
+    }
+    strip(&mut old, rules);
+    strip(&mut new, rules);
+    Some(diff(&old, &new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Manifest Diffs
+    #[test]
+    fn verify_diff() {
+        let old: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        { "name": "org.osbuild.truncate", "options": { "size": "1024", "filename": "a" } },
+                        { "name": "org.osbuild.locale", "options": { "language": "en_US" } }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let new: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        { "name": "org.osbuild.truncate", "options": { "filename": "b", "size": "1 KiB" } }
+                    ]
+                },
+                "sources": { "org.osbuild.files": { "urls": {} } }
+            }"#,
+        )
+        .unwrap();
+
+        let changes = manifests(&old, &new);
+        assert_eq! {
+            changes,
+            vec![
+                Change::Changed(
+                    "/pipeline/stages/0/options/filename".to_owned(),
+                    serde_json::json!("a"),
+                    serde_json::json!("b"),
+                ),
+                Change::Removed(
+                    "/pipeline/stages/1".to_owned(),
+                    serde_json::json!({ "name": "org.osbuild.locale", "options": { "language": "en_US" } }),
+                ),
+                Change::Added(
+                    "/sources".to_owned(),
+                    serde_json::json!({ "org.osbuild.files": { "urls": {} } }),
+                ),
+            ],
+        }
+        assert_eq!(
+            changes[0].to_string(),
+            r#"~ /pipeline/stages/0/options/filename: "a" -> "b""#
+        );
+        assert!(manifests(&old, &old).is_empty());
+    }
+    // Verify Ignore Rules
+    #[test]
+    fn verify_ignore() {
+        let old: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        { "name": "org.osbuild.mkfs.xfs", "options": { "uuid": "a", "label": "root" } },
+                        { "name": "org.osbuild.locale", "options": { "language": "en_US" } }
+                    ]
+                },
+                "sources": { "org.osbuild.curl": { "items": { "sha256:01": "https://a.example.com/x.rpm" } } }
+            }"#,
+        )
+        .unwrap();
+        let new: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        { "name": "org.osbuild.locale", "options": { "language": "en_US" } },
+                        { "name": "org.osbuild.mkfs.xfs", "options": { "uuid": "b", "label": "boot" } }
+                    ]
+                },
+                "sources": { "org.osbuild.curl": { "items": { "sha256:01": "http://b.example.com/x.rpm" } } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifests(&old, &new).len(), 9);
+        let rules = [
+            Ignore::Key("uuid".to_owned()),
+            Ignore::UrlHosts,
+            Ignore::StageOrder,
+        ];
+        assert_eq! {
+            manifests_with(&old, &new, &rules),
+            vec![Change::Changed(
+                "/pipeline/stages/1/options/label".to_owned(),
+                serde_json::json!("root"),
+                serde_json::json!("boot"),
+            )],
+        }
+
+        let rules = [
+            Ignore::Path("/pipeline/stages/*/options".to_owned()),
+            Ignore::Path("/sources".to_owned()),
+            Ignore::StageOrder,
+        ];
+        assert!(manifests_with(&old, &new, &rules).is_empty());
+
+        let (old, new) = (crate::convert::to_v2(&old), crate::convert::to_v2(&new));
+        let old: Manifest2 = serde_json::from_value(old).unwrap();
+        let new: Manifest2 = serde_json::from_value(new).unwrap();
+        let rules = [
+            Ignore::Key("uuid".to_owned()),
+            Ignore::UrlHosts,
+            Ignore::StageOrder,
+        ];
+        let changes = manifests2_with(&old, &new, &rules);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0]
+            .to_string()
+            .ends_with(r#"/options/label: "root" -> "boot""#));
+    }
+}
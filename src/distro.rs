@@ -0,0 +1,339 @@
+//! Distribution Profiles
+//!
+//! This module describes the distributions commonly built with osbuild:
+//! Fedora, CentOS Stream, and RHEL. A profile names the runner of the
+//! build pipeline, the repositories to resolve packages from, and the
+//! standard stage sequence of each image type. Simple images can be
+//! generated from a profile without reimplementing the distribution logic
+//! of composer.
+//!
+//! Profiles cover the defaults only. Repositories of RHEL require an
+//! entitlement, which must be configured by the caller.
+
+use crate::blueprint::{self, Blueprint};
+use crate::compile::{self, Compiled, Target};
+use crate::dnfjson::Repository;
+use crate::runner::Runner;
+
+/// Distribution Families
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Family {
+    Fedora,
+    CentosStream,
+    Rhel,
+}
+
+/// Image Types
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ImageType {
+    /// Bootable disk image in qcow2 format, for virtual machines.
+    Qcow2,
+    /// Raw disk image prepared for import as Amazon Machine Image.
+    Ami,
+    /// Bootable installer ISO.
+    Iso,
+}
+
+impl ImageType {
+    /// Standard Stage Sequence
+    ///
+    /// Return the stage types that build an image of this type, in order.
+    /// Stages that only apply to some customizations are not listed.
+    pub fn stages(&self) -> &'static [&'static str] {
+        match self {
+            ImageType::Qcow2 => &[
+                "org.osbuild.rpm",
+                "org.osbuild.fix-bls",
+                "org.osbuild.fstab",
+                "org.osbuild.grub2",
+                "org.osbuild.selinux",
+                "org.osbuild.qemu",
+            ],
+            ImageType::Ami => &[
+                "org.osbuild.rpm",
+                "org.osbuild.kernel-cmdline",
+                "org.osbuild.systemd",
+                "org.osbuild.fix-bls",
+                "org.osbuild.fstab",
+                "org.osbuild.grub2",
+                "org.osbuild.selinux",
+                "org.osbuild.qemu",
+            ],
+            ImageType::Iso => &[
+                "org.osbuild.rpm",
+                "org.osbuild.dracut",
+                "org.osbuild.selinux",
+                "org.osbuild.squashfs",
+                "org.osbuild.bootiso.mono",
+                "org.osbuild.grub2.iso",
+                "org.osbuild.xorrisofs",
+                "org.osbuild.implantisomd5",
+            ],
+        }
+    }
+}
+
+/// Profile Error
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The image type cannot be generated.
+    UnsupportedImageType(ImageType),
+    /// Compilation of the image failed.
+    Compile(compile::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedImageType(v) => write!(fmt, "unsupported image type {:?}", v),
+            Error::Compile(v) => v.fmt(fmt),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Distribution Profile
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Profile {
+    pub family: Family,
+    /// Version, like `39` for Fedora, `9` for CentOS Stream, or `9.4` for
+    /// RHEL.
+    pub version: String,
+}
+
+// Packages and kernel options of images for EC2.
+const AMI_PACKAGES: &[&str] = &["cloud-init", "cloud-utils-growpart", "dhcpcd"];
+const AMI_KERNEL_OPTS: &str =
+    "console=ttyS0,115200n8 console=tty0 net.ifnames=0 nvme_core.io_timeout=4294967295";
+
+impl Profile {
+    /// Look Up Profile
+    ///
+    /// Return the profile of a distribution, given like `fedora-39`,
+    /// `centos-9`, or `rhel-9.4`. Returns `None` for unknown distributions.
+    pub fn lookup(distro: &str) -> Option<Self> {
+        let (name, version) = distro.split_once('-')?;
+        let numeric = |v: &str| !v.is_empty() && v.bytes().all(|v| v.is_ascii_digit());
+        let family = match name {
+            "fedora" if numeric(version) => Family::Fedora,
+            "centos" if numeric(version) => Family::CentosStream,
+            "rhel" => match version.split_once('.') {
+                Some((major, minor)) if numeric(major) && numeric(minor) => Family::Rhel,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Self {
+            family,
+            version: version.to_owned(),
+        })
+    }
+
+    /// Distribution Name
+    ///
+    /// Return the name of the distribution, as accepted by `lookup()`.
+    pub fn name(&self) -> String {
+        let family = match self.family {
+            Family::Fedora => "fedora",
+            Family::CentosStream => "centos",
+            Family::Rhel => "rhel",
+        };
+        format!("{}-{}", family, self.version)
+    }
+
+    /// Build Runner
+    ///
+    /// Return the runner of build pipelines of the distribution.
+    pub fn runner(&self) -> Runner {
+        compile::runner(&self.name()).unwrap_or_default()
+    }
+
+    /// Default Repositories
+    ///
+    /// Return the repositories of the distribution for the architecture,
+    /// with GPG checks enabled against the keys installed by the release
+    /// package of the distribution.
+    pub fn repositories(&self, arch: &str) -> Vec<Repository> {
+        let v = &self.version;
+        let repo =
+            |id: String, metalink: Option<String>, baseurl: Option<String>, key: &str| Repository {
+                id,
+                metalink,
+                baseurl: baseurl.into_iter().collect(),
+                gpgkeys: vec![format!("file:///etc/pki/rpm-gpg/{}", key)],
+                check_gpg: Some(true),
+                ..Default::default()
+            };
+
+        match self.family {
+            Family::Fedora => {
+                let key = format!("RPM-GPG-KEY-fedora-{}-primary", v);
+                let metalink = |repo: &str| {
+                    Some(format!(
+                        "https://mirrors.fedoraproject.org/metalink?repo={}&arch={}",
+                        repo, arch
+                    ))
+                };
+                vec![
+                    repo(
+                        "fedora".into(),
+                        metalink(&format!("fedora-{}", v)),
+                        None,
+                        &key,
+                    ),
+                    repo(
+                        "updates".into(),
+                        metalink(&format!("updates-released-f{}", v)),
+                        None,
+                        &key,
+                    ),
+                ]
+            }
+            Family::CentosStream => ["baseos", "appstream"]
+                .iter()
+                .map(|name| {
+                    let metalink = format!(
+                        "https://mirrors.centos.org/metalink?repo=centos-{}-{}-stream&arch={}",
+                        name, v, arch
+                    );
+                    repo(
+                        (*name).to_owned(),
+                        Some(metalink),
+                        None,
+                        "RPM-GPG-KEY-centosofficial",
+                    )
+                })
+                .collect(),
+            Family::Rhel => {
+                let major = v.split('.').next().unwrap_or_default();
+                ["baseos", "appstream"]
+                    .iter()
+                    .map(|name| {
+                        let baseurl = format!(
+                            "https://cdn.redhat.com/content/dist/rhel{}/{}/{}/{}/os",
+                            major, v, arch, name
+                        );
+                        repo(
+                            (*name).to_owned(),
+                            None,
+                            Some(baseurl),
+                            "RPM-GPG-KEY-redhat-release",
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Generate Image
+    ///
+    /// Compile the blueprint into a manifest skeleton of the image type for
+    /// the architecture, with the defaults of the distribution. Images for
+    /// EC2 get the packages and kernel options required there. Installer
+    /// ISOs cannot be generated, since they require version-2 manifests.
+    pub fn generate(
+        &self,
+        blueprint: &Blueprint,
+        image_type: ImageType,
+        arch: &str,
+    ) -> Result<Compiled, Error> {
+        let mut blueprint = blueprint.clone();
+        let image_type = match image_type {
+            ImageType::Qcow2 => compile::ImageType::Qcow2,
+            ImageType::Ami => {
+                blueprint
+                    .packages
+                    .extend(AMI_PACKAGES.iter().map(|v| blueprint::Package {
+                        name: (*v).to_owned(),
+                        version: None,
+                    }));
+                let customizations = blueprint
+                    .customizations
+                    .get_or_insert_with(Default::default);
+                let kernel = customizations.kernel.get_or_insert_with(Default::default);
+                kernel.append = Some(match kernel.append.take() {
+                    Some(v) => format!("{} {}", AMI_KERNEL_OPTS, v),
+                    None => AMI_KERNEL_OPTS.to_owned(),
+                });
+                let services = customizations.services.get_or_insert_with(Default::default);
+                services.enabled.push("cloud-init".to_owned());
+                compile::ImageType::Raw
+            }
+            ImageType::Iso => return Err(Error::UnsupportedImageType(image_type)),
+        };
+
+        let target = Target {
+            distro: self.name(),
+            arch: arch.to_owned(),
+            image_type,
+        };
+        compile::compile(&blueprint, &target).map_err(Error::Compile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Distribution Profiles
+    #[test]
+    fn verify_profile() {
+        assert!(Profile::lookup("debian-12").is_none());
+        assert!(Profile::lookup("rhel-9").is_none());
+        assert!(Profile::lookup("fedora-").is_none());
+
+        let fedora = Profile::lookup("fedora-39").unwrap();
+        assert_eq!(fedora.family, Family::Fedora);
+        assert_eq!(fedora.runner(), "org.osbuild.fedora39");
+        let repos = fedora.repositories("aarch64");
+        assert_eq!(
+            repos[1].metalink.as_deref(),
+            Some(
+                "https://mirrors.fedoraproject.org/metalink?repo=updates-released-f39&arch=aarch64"
+            ),
+        );
+        assert_eq!(
+            repos[0].gpgkeys,
+            ["file:///etc/pki/rpm-gpg/RPM-GPG-KEY-fedora-39-primary"],
+        );
+
+        let rhel = Profile::lookup("rhel-9.4").unwrap();
+        assert_eq!(rhel.name(), "rhel-9.4");
+        assert_eq!(rhel.runner(), "org.osbuild.rhel94");
+        assert_eq!(
+            rhel.repositories("x86_64")[1].baseurl,
+            ["https://cdn.redhat.com/content/dist/rhel9/9.4/x86_64/appstream/os"],
+        );
+        let centos = Profile::lookup("centos-9").unwrap();
+        assert_eq!(centos.repositories("x86_64")[0].id, "baseos");
+
+        let blueprint = Blueprint {
+            name: "base".to_owned(),
+            ..Default::default()
+        };
+        for image_type in [ImageType::Qcow2, ImageType::Ami] {
+            let compiled = centos.generate(&blueprint, image_type, "x86_64").unwrap();
+            let pipeline = &compiled.manifest.pipeline;
+            let mut stages: Vec<&str> = pipeline.stages.iter().map(|v| v.name.as_str()).collect();
+            stages.extend(pipeline.assembler.as_ref().map(|v| v.name.as_str()));
+            assert_eq!(stages, image_type.stages());
+        }
+        let ami = centos
+            .generate(&blueprint, ImageType::Ami, "x86_64")
+            .unwrap();
+        assert!(ami.packages.iter().any(|v| v.name == "cloud-init"));
+        assert_eq!(
+            ami.manifest.pipeline.assembler.unwrap().options["format"],
+            "raw"
+        );
+        assert_eq! {
+            centos.generate(&blueprint, ImageType::Iso, "x86_64").unwrap_err(),
+            Error::UnsupportedImageType(ImageType::Iso),
+        }
+        assert!(matches!(
+            centos.generate(&blueprint, ImageType::Qcow2, "s390x"),
+            Err(Error::Compile(compile::Error::UnsupportedArch(_))),
+        ));
+    }
+}
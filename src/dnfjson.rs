@@ -0,0 +1,469 @@
+//! DNF-JSON Depsolver
+//!
+//! image-builder resolves package sets with `dnf-json` (nowadays shipped
+//! by osbuild as `osbuild-depsolve-dnf`), a helper that reads a single JSON
+//! request on its standard input, resolves it with DNF, and writes a JSON
+//! response to its standard output. A non-zero exit status signals an
+//! error, described by the JSON object written instead of the response.
+//!
+//! This module provides the typed request and response structures of this
+//! protocol, and a client that runs the helper as a subprocess. The client
+//! implements the solver interface of the manifest preprocessor, so
+//! `mpp-depsolve` directives can be resolved with it.
+
+use crate::manifest::{Json, Object};
+#[cfg(feature = "mpp")]
+use crate::mpp::depsolve;
+
+/// Default Solver Command
+pub const DEFAULT_COMMAND: &str = "/usr/libexec/osbuild-depsolve-dnf";
+
+/// Repository Configuration
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Repository {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub baseurl: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metalink: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrorlist: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gpgkeys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_gpg: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_repogpg: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sslverify: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_hotfixes: Option<bool>,
+}
+
+/// Transaction
+///
+/// A set of packages to resolve. Multiple transactions are resolved in
+/// order, each on top of the result of the previous ones.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Transaction {
+    pub package_specs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_specs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub module_enable_specs: Vec<String>,
+    /// Identifiers of the repositories to use. All repositories are used
+    /// if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repo_ids: Vec<String>,
+    #[serde(default, rename = "install_weak_deps")]
+    pub install_weak_deps: bool,
+}
+
+/// Request Arguments
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Arguments {
+    pub repos: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<Transaction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_dir: Option<String>,
+    #[serde(
+        default,
+        rename = "optional-metadata",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub optional_metadata: Vec<String>,
+}
+
+/// Solver Command
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    #[default]
+    Depsolve,
+    Dump,
+    Search,
+}
+
+/// Solver Request
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Request {
+    pub command: Command,
+    pub arch: String,
+    pub module_platform_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub releasever: Option<String>,
+    pub cachedir: String,
+    pub arguments: Arguments,
+}
+
+/// Resolved Package
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Package {
+    pub name: String,
+    #[serde(default)]
+    pub epoch: u64,
+    pub version: String,
+    pub release: String,
+    pub arch: String,
+    pub repo_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub remote_location: String,
+    /// Checksum of the package, as `<algorithm>:<hex>`.
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_gpg: Option<bool>,
+}
+
+/// Depsolve Response
+///
+/// The response to a depsolve request. Older solvers respond with the
+/// plain package list, which is accepted as well.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(from = "ResponseRepr")]
+pub struct Response {
+    pub packages: Vec<Package>,
+    /// The repository configurations, by identifier, as used by the
+    /// solver.
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub repos: Object<Json>,
+    /// Module metadata of enabled modules, by module name.
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub modules: Object<Json>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ResponseRepr {
+    Legacy(Vec<Package>),
+    Current {
+        packages: Vec<Package>,
+        #[serde(default)]
+        repos: Object<Json>,
+        #[serde(default)]
+        modules: Object<Json>,
+    },
+}
+
+impl From<ResponseRepr> for Response {
+    fn from(v: ResponseRepr) -> Self {
+        match v {
+            ResponseRepr::Legacy(packages) => Self {
+                packages,
+                ..Default::default()
+            },
+            ResponseRepr::Current {
+                packages,
+                repos,
+                modules,
+            } => Self {
+                packages,
+                repos,
+                modules,
+            },
+        }
+    }
+}
+
+/// Solver Error
+///
+/// The error object a solver writes when it fails.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SolverError {
+    pub kind: String,
+    pub reason: String,
+}
+
+/// Client Error
+#[derive(Debug)]
+pub enum Error {
+    /// The solver could not be run.
+    Io(std::io::Error),
+    /// The solver output could not be parsed.
+    Json(serde_json::Error),
+    /// The solver failed without a parsable error description. The exit
+    /// status and standard error are included.
+    Failed(std::process::ExitStatus, String),
+    /// The solver reported an error.
+    Solver(SolverError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(v) => write!(fmt, "cannot run solver: {}", v),
+            Error::Json(v) => write!(fmt, "invalid solver output: {}", v),
+            Error::Failed(s, v) => write!(fmt, "solver failed ({}): {}", s, v.trim()),
+            Error::Solver(v) => write!(fmt, "{}: {}", v.kind, v.reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Solver Client
+///
+/// Runs the solver as a subprocess for each request.
+#[derive(Clone, Debug)]
+pub struct Client {
+    command: Vec<String>,
+    cachedir: String,
+}
+
+impl Client {
+    /// Create New Client
+    ///
+    /// Create a client running the default solver command, and using the
+    /// given cache directory for repository metadata.
+    pub fn new(cachedir: &str) -> Self {
+        Self {
+            command: vec![DEFAULT_COMMAND.to_owned()],
+            cachedir: cachedir.to_owned(),
+        }
+    }
+
+    /// Set Solver Command
+    ///
+    /// Set the program to run, together with its arguments.
+    pub fn set_command(&mut self, command: &[&str]) {
+        self.command = command.iter().map(|v| (*v).to_owned()).collect();
+    }
+
+    /// Cache Directory
+    pub fn cachedir(&self) -> &str {
+        &self.cachedir
+    }
+
+    /// Run Raw Request
+    ///
+    /// Run the solver with the given request and return its response as
+    /// untyped JSON. This is used for commands without typed response.
+    pub fn call(&self, request: &Request) -> Result<Json, Error> {
+        use std::io::Write;
+
+        let (program, args) = match self.command.split_first() {
+            None => {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "empty solver command",
+                )))
+            }
+            Some(v) => v,
+        };
+
+        let mut child = std::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(Error::Io)?;
+
+        let input = serde_json::to_vec(request).map_err(Error::Json)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A solver failing early may close its input, so write errors
+            // are reported via the exit status instead.
+            let _ = stdin.write_all(&input);
+        }
+        let output = child.wait_with_output().map_err(Error::Io)?;
+
+        if !output.status.success() {
+            return Err(
+                match serde_json::from_slice::<SolverError>(&output.stdout) {
+                    Ok(v) => Error::Solver(v),
+                    Err(_) => Error::Failed(
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).into_owned(),
+                    ),
+                },
+            );
+        }
+        serde_json::from_slice(&output.stdout).map_err(Error::Json)
+    }
+
+    /// Depsolve
+    ///
+    /// Run a depsolve request and return the resolved packages.
+    pub fn depsolve(&self, request: &Request) -> Result<Response, Error> {
+        let mut request = request.clone();
+        request.command = Command::Depsolve;
+        serde_json::from_value(self.call(&request)?).map_err(Error::Json)
+    }
+}
+
+#[cfg(feature = "mpp")]
+impl depsolve::Solver for Client {
+    fn depsolve(&self, request: &depsolve::Request) -> Result<Vec<depsolve::Package>, String> {
+        let repos = request
+            .repos
+            .iter()
+            .map(|v| Repository {
+                id: v.id.clone(),
+                baseurl: v.baseurl.iter().cloned().collect(),
+                metalink: v.metalink.clone(),
+                mirrorlist: v.mirrorlist.clone(),
+                gpgkeys: v.gpgkey.iter().cloned().collect(),
+                check_gpg: v.gpgkey.as_ref().map(|_| true),
+                ..Default::default()
+            })
+            .collect();
+        let request = Request {
+            command: Command::Depsolve,
+            arch: request.architecture.clone(),
+            module_platform_id: request.module_platform_id.clone(),
+            releasever: request.releasever.clone(),
+            cachedir: self.cachedir.clone(),
+            arguments: Arguments {
+                repos,
+                transactions: vec![Transaction {
+                    package_specs: request.packages.clone(),
+                    exclude_specs: request.excludes.clone(),
+                    install_weak_deps: true,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        };
+
+        let response = Client::depsolve(self, &request).map_err(|e| e.to_string())?;
+        Ok(response
+            .packages
+            .into_iter()
+            .map(|v| depsolve::Package {
+                name: v.name,
+                checksum: v.checksum,
+                url: v.remote_location,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Request Serialization
+    #[test]
+    fn verify_request() {
+        let request = Request {
+            arch: "x86_64".to_owned(),
+            module_platform_id: "platform:f39".to_owned(),
+            releasever: Some("39".to_owned()),
+            cachedir: "/var/cache/dnf-json".to_owned(),
+            arguments: Arguments {
+                repos: vec![Repository {
+                    id: "fedora".to_owned(),
+                    baseurl: vec!["https://example.com/fedora".to_owned()],
+                    ..Default::default()
+                }],
+                transactions: vec![Transaction {
+                    package_specs: vec!["@core".to_owned()],
+                    install_weak_deps: true,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq! {
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "command": "depsolve",
+                "arch": "x86_64",
+                "module_platform_id": "platform:f39",
+                "releasever": "39",
+                "cachedir": "/var/cache/dnf-json",
+                "arguments": {
+                    "repos": [{ "id": "fedora", "baseurl": ["https://example.com/fedora"] }],
+                    "transactions": [{ "package-specs": ["@core"], "install_weak_deps": true }]
+                }
+            }),
+        }
+    }
+
+    // Verify Response Parsing
+    #[test]
+    fn verify_response() {
+        let package = r#"{
+            "name": "bash", "epoch": 0, "version": "5.2.15", "release": "5.fc39", "arch": "x86_64",
+            "repo_id": "fedora", "remote_location": "https://example.com/bash.rpm",
+            "checksum": "sha256:abcd"
+        }"#;
+
+        let v: Response = serde_json::from_str(&format!("[{}]", package)).unwrap();
+        assert_eq!(v.packages[0].name, "bash");
+        assert!(v.repos.is_empty());
+
+        let v: Response = serde_json::from_str(&format!(
+            r#"{{ "packages": [{}], "repos": {{ "fedora": {{}} }} }}"#,
+            package
+        ))
+        .unwrap();
+        assert_eq!(v.packages[0].checksum, "sha256:abcd");
+        assert!(v.repos.contains_key("fedora"));
+    }
+
+    // Verify Subprocess Client
+    #[cfg(all(unix, feature = "mpp"))]
+    #[test]
+    fn verify_client() {
+        use crate::mpp::depsolve::Solver;
+
+        let mut client = Client::new("/tmp/cache");
+        client.set_command(&[
+            "sh",
+            "-c",
+            r#"grep -q '"package-specs":\["bash"\]' || { echo '{"kind":"MarkingErrors","reason":"no match"}'; exit 1; }
+               echo '[{"name":"bash","version":"5","release":"1","arch":"x86_64","repo_id":"r","remote_location":"https://r/bash.rpm","checksum":"sha256:00"}]'"#,
+        ]);
+
+        let request = depsolve::Request {
+            architecture: "x86_64".to_owned(),
+            module_platform_id: "platform:f39".to_owned(),
+            repos: vec![depsolve::Repository {
+                id: "r".to_owned(),
+                baseurl: Some("https://r".to_owned()),
+                ..Default::default()
+            }],
+            packages: vec!["bash".to_owned()],
+            ..Default::default()
+        };
+        assert_eq! {
+            Solver::depsolve(&client, &request).unwrap(),
+            vec![depsolve::Package {
+                name: "bash".to_owned(),
+                checksum: "sha256:00".to_owned(),
+                url: "https://r/bash.rpm".to_owned(),
+            }],
+        }
+
+        let request = depsolve::Request {
+            packages: vec!["tmux".to_owned()],
+            ..request
+        };
+        assert_eq! {
+            Solver::depsolve(&client, &request).unwrap_err(),
+            "MarkingErrors: no match",
+        }
+
+        client.set_command(&["/nonexistent/solver"]);
+        assert!(matches!(
+            client.call(&Request::default()),
+            Err(Error::Io(_)),
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+//! Manifest Documents
+//!
+//! This module provides a document-preserving mode for tools that modify
+//! manifests checked into version control. The typed manifest model stores
+//! objects as sorted maps, so serializing an edited manifest reorders every
+//! key and replaces the formatting of the original file. A `Document`
+//! instead retains the JSON tree in its original member order, together
+//! with the layout of the text it was parsed from. Edits made on the typed
+//! model are merged back into this tree, so only the edited values change
+//! when the document is written out again.
+//!
+//! Only the indentation and the trailing newline of the original text are
+//! retained. Documents are always written with one member or element per
+//! line, or on a single line if the original text was.
+
+use crate::error::Error;
+use crate::manifest::Json;
+
+/// Document Layout
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Layout {
+    /// Indentation of a nesting level, or `None` for single-line text.
+    pub indent: Option<String>,
+    /// Whether the text ends with a newline.
+    pub newline: bool,
+}
+
+impl Layout {
+    /// Detect Layout
+    ///
+    /// Derive the layout from JSON text. The indentation is taken from the
+    /// first indented line. Multi-line text without indentation uses an
+    /// empty indentation.
+    pub fn detect(text: &str) -> Self {
+        let body = text.trim_end();
+        let indent = body.contains('\n').then(|| {
+            body.lines()
+                .skip(1)
+                .map(|v| &v[..v.len() - v.trim_start().len()])
+                .find(|v| !v.is_empty())
+                .unwrap_or_default()
+                .to_owned()
+        });
+        Self {
+            indent,
+            newline: text.ends_with('\n'),
+        }
+    }
+}
+
+/// Manifest Document
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Document {
+    pub root: Json,
+    pub layout: Layout,
+}
+
+impl Document {
+    /// Parse Document
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        Ok(Self {
+            root: serde_json::from_str(text)?,
+            layout: Layout::detect(text),
+        })
+    }
+
+    /// Decode Document
+    ///
+    /// Deserialize the document into a typed model, like `Manifest1`.
+    pub fn decode<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        Ok(serde_json::from_value(self.root.clone())?)
+    }
+
+    /// Update Document
+    ///
+    /// Merge the serialized form of a typed model into the document. Members
+    /// retain their position, new members are appended to their object, and
+    /// members missing from the model are removed. Members the model
+    /// serializes as `null`, `{}`, or `[]` are not added if the document
+    /// lacks them, since typed models cannot tell an absent member from its
+    /// empty default.
+    pub fn update<T: serde::Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        merge(&mut self.root, serde_json::to_value(value)?);
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use serde::Serialize;
+
+        let mut acc = Vec::new();
+        let r = match self.layout.indent {
+            None => serde_json::to_writer(&mut acc, &self.root),
+            Some(ref indent) => {
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                let mut serializer = serde_json::Serializer::with_formatter(&mut acc, formatter);
+                self.root.serialize(&mut serializer)
+            }
+        };
+        r.map_err(|_| std::fmt::Error)?;
+        fmt.write_str(std::str::from_utf8(&acc).map_err(|_| std::fmt::Error)?)?;
+        if self.layout.newline {
+            fmt.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_empty(v: &Json) -> bool {
+    match v {
+        Json::Null => true,
+        Json::Array(v) => v.is_empty(),
+        Json::Object(v) => v.is_empty(),
+        _ => false,
+    }
+}
+
+// Merge `new` into `old`, keeping the member order of `old`.
+fn merge(old: &mut Json, new: Json) {
+    match (old, new) {
+        (Json::Object(old), Json::Object(mut new)) => {
+            old.retain(|k, _| new.contains_key(k));
+            for (k, v) in old.iter_mut() {
+                if let Some(n) = new.remove(k) {
+                    merge(v, n);
+                }
+            }
+            for (k, v) in new {
+                if !is_empty(&v) {
+                    old.insert(k, v);
+                }
+            }
+        }
+        (Json::Array(old), Json::Array(new)) => {
+            old.truncate(new.len());
+            let mut new = new.into_iter();
+            for (o, n) in old.iter_mut().zip(new.by_ref()) {
+                merge(o, n);
+            }
+            old.extend(new);
+        }
+        (old, new) => *old = new,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Document Round-Trips
+    #[test]
+    fn verify_document() {
+        let text = concat!(
+            "{\n",
+            "    \"pipeline\": {\n",
+            "        \"stages\": [\n",
+            "            {\n",
+            "                \"options\": {\n",
+            "                    \"zone\": \"UTC\",\n",
+            "                    \"a\": 1\n",
+            "                },\n",
+            "                \"name\": \"org.osbuild.timezone\"\n",
+            "            }\n",
+            "        ]\n",
+            "    }\n",
+            "}\n",
+        );
+        let mut document = Document::parse(text).unwrap();
+        assert_eq!(document.layout.indent.as_deref(), Some("    "));
+        assert_eq!(document.to_string(), text);
+
+        let mut manifest: Manifest1 = document.decode().unwrap();
+        let options = &mut manifest.pipeline.stages[0].options;
+        options.insert("zone".to_owned(), "Europe/Berlin".into());
+        options.remove("a");
+        document.update(&manifest).unwrap();
+        assert_eq! {
+            document.to_string(),
+            text.replace("\"UTC\",", "\"Europe/Berlin\"")
+                .replace("                    \"a\": 1\n", ""),
+        }
+
+        let mut document = Document::parse(r#"{"pipeline":{"stages":[]}}"#).unwrap();
+        assert_eq!(document.layout, Layout::default());
+        document.update(&Manifest1::default()).unwrap();
+        assert_eq!(document.to_string(), r#"{"pipeline":{"stages":[]}}"#);
+    }
+}
@@ -0,0 +1,254 @@
+//! DSSE Envelopes
+//!
+//! This module implements the Dead Simple Signing Envelope, as used by
+//! in-toto to wrap attestations. An envelope carries an arbitrary payload
+//! together with its type and any number of signatures. Signatures are
+//! computed over the pre-authentication encoding of type and payload, so
+//! the type cannot be altered without invalidating them.
+//!
+//! Cryptography is not part of this module. Signatures are created and
+//! checked through the [`Signer`] and [`Verifier`] traits, implemented by
+//! the caller for the key backend of their choice.
+
+use crate::hash;
+
+/// In-toto Payload Type
+pub const PAYLOAD_TYPE_IN_TOTO: &str = "application/vnd.in-toto+json";
+
+/// Signer
+///
+/// A key able to sign messages. Signers are used to add signatures to
+/// envelopes.
+pub trait Signer {
+    /// Identifier of the key, recorded as hint for verifiers.
+    fn key_id(&self) -> Option<String>;
+
+    /// Sign the message with the key.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Verifier
+///
+/// A key able to verify signatures of messages.
+pub trait Verifier {
+    /// Identifier of the key. If both the key and a signature have an
+    /// identifier, the signature is only checked if they match.
+    fn key_id(&self) -> Option<String>;
+
+    /// Verify a signature of the message, returning whether it is valid.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Envelope Error
+#[derive(Debug)]
+pub enum Error {
+    /// A signer failed.
+    Signer(String),
+    /// The payload could not be serialized or deserialized.
+    Json(serde_json::Error),
+    /// A member of the envelope is not valid base64.
+    Encoding,
+    /// No signature of the envelope could be verified.
+    Unverified,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Signer(v) => write!(fmt, "signing failed: {}", v),
+            Error::Json(v) => write!(fmt, "invalid payload: {}", v),
+            Error::Encoding => write!(fmt, "invalid base64 encoding"),
+            Error::Unverified => write!(fmt, "no valid signature"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Envelope Signature
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Signature {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyid: Option<String>,
+    /// The base64 encoded signature.
+    pub sig: String,
+}
+
+/// Envelope
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    /// The base64 encoded payload.
+    pub payload: String,
+    pub payload_type: String,
+    pub signatures: Vec<Signature>,
+}
+
+/// Pre-Authentication Encoding
+///
+/// Encode payload type and payload as the message that is signed.
+pub fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut acc = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    acc.extend_from_slice(payload);
+    acc
+}
+
+impl Envelope {
+    /// Create Unsigned Envelope
+    pub fn new(payload_type: &str, payload: &[u8]) -> Self {
+        Self {
+            payload: hash::to_base64(payload),
+            payload_type: payload_type.to_owned(),
+            signatures: Vec::new(),
+        }
+    }
+
+    /// Create Envelope from JSON
+    ///
+    /// Serialize the value as payload of a new envelope, like an in-toto
+    /// statement, an SBOM, or a manifest.
+    pub fn with_json<T: serde::Serialize>(payload_type: &str, value: &T) -> Result<Self, Error> {
+        let payload = serde_json::to_vec(value).map_err(Error::Json)?;
+        Ok(Self::new(payload_type, &payload))
+    }
+
+    /// Decoded Payload
+    pub fn payload(&self) -> Result<Vec<u8>, Error> {
+        hash::from_base64(&self.payload).ok_or(Error::Encoding)
+    }
+
+    /// Sign Envelope
+    ///
+    /// Add a signature created by the given signer.
+    pub fn sign(&mut self, signer: &dyn Signer) -> Result<(), Error> {
+        let message = pae(&self.payload_type, &self.payload()?);
+        let sig = signer.sign(&message).map_err(Error::Signer)?;
+        self.signatures.push(Signature {
+            keyid: signer.key_id(),
+            sig: hash::to_base64(&sig),
+        });
+        Ok(())
+    }
+
+    /// Verify Envelope
+    ///
+    /// Check that any of the signatures was created by any of the given
+    /// keys, and return the payload on success.
+    pub fn verify(&self, verifiers: &[&dyn Verifier]) -> Result<Vec<u8>, Error> {
+        let payload = self.payload()?;
+        let message = pae(&self.payload_type, &payload);
+
+        for signature in &self.signatures {
+            let sig = match hash::from_base64(&signature.sig) {
+                None => continue,
+                Some(v) => v,
+            };
+            let verified = verifiers.iter().any(|v| {
+                let matches = match (&signature.keyid, v.key_id()) {
+                    (Some(a), Some(b)) => *a == b,
+                    _ => true,
+                };
+                matches && v.verify(&message, &sig)
+            });
+            if verified {
+                return Ok(payload);
+            }
+        }
+
+        Err(Error::Unverified)
+    }
+
+    /// Verify JSON Envelope
+    ///
+    /// Verify the envelope, then deserialize its payload.
+    pub fn verify_json<T: serde::de::DeserializeOwned>(
+        &self,
+        verifiers: &[&dyn Verifier],
+    ) -> Result<T, Error> {
+        let payload = self.verify(verifiers)?;
+        serde_json::from_slice(&payload).map_err(Error::Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A keyed hash, standing in for a real signature scheme.
+    struct Key(&'static str);
+
+    impl Key {
+        fn mac(&self, message: &[u8]) -> Vec<u8> {
+            let mut h = hash::Sha256::new();
+            h.update(self.0.as_bytes());
+            h.update(message);
+            h.finalize().to_vec()
+        }
+    }
+
+    impl Signer for Key {
+        fn key_id(&self) -> Option<String> {
+            Some(self.0.to_owned())
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(self.mac(message))
+        }
+    }
+
+    impl Verifier for Key {
+        fn key_id(&self) -> Option<String> {
+            Some(self.0.to_owned())
+        }
+
+        fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+            self.mac(message) == signature
+        }
+    }
+
+    // Verify Pre-Authentication Encoding
+    #[test]
+    fn verify_pae() {
+        assert_eq! {
+            pae("http://example.com/HelloWorld", b"hello world"),
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world",
+        }
+        assert_eq!(pae("", b""), b"DSSEv1 0  0 ");
+    }
+
+    // Verify Signing and Verification
+    #[test]
+    fn verify_envelope() {
+        let mut envelope =
+            Envelope::with_json(PAYLOAD_TYPE_IN_TOTO, &serde_json::json!({ "a": 1 })).unwrap();
+        envelope.sign(&Key("one")).unwrap();
+        envelope.sign(&Key("two")).unwrap();
+        assert_eq!(envelope.payload, "eyJhIjoxfQ==");
+        assert_eq!(envelope.signatures[1].keyid.as_deref(), Some("two"));
+
+        let value: serde_json::Value = envelope.verify_json(&[&Key("two")]).unwrap();
+        assert_eq!(value["a"], 1);
+        assert!(matches!(
+            envelope.verify(&[&Key("three")]),
+            Err(Error::Unverified),
+        ));
+
+        // Changing the payload type invalidates all signatures.
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["payloadType"], PAYLOAD_TYPE_IN_TOTO);
+        let mut envelope: Envelope = serde_json::from_value(json).unwrap();
+        envelope.payload_type = "text/plain".to_owned();
+        assert!(matches!(
+            envelope.verify(&[&Key("one"), &Key("two")]),
+            Err(Error::Unverified),
+        ));
+    }
+}
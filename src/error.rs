@@ -0,0 +1,215 @@
+//! Crate Errors
+//!
+//! The modules of this crate report failures with their own error types,
+//! which describe them in as much detail as the module can provide. This
+//! module provides a crate-level error type that all of them convert into,
+//! so applications using several modules can propagate failures with `?`
+//! and still report the JSON path, position, or manifest version they
+//! relate to. The manifest parsers of the `manifest` module report this
+//! type directly.
+
+use crate::convert;
+#[cfg(feature = "exec")]
+use crate::dnfjson;
+use crate::lint::{Diagnostic, Severity};
+
+/// Crate Error
+#[derive(Debug)]
+pub enum Error {
+    /// The text is not a well-formed manifest of the given version. Lines
+    /// and columns are 1-based, or 0 if unknown. The path is the JSON
+    /// pointer of the value the error occurred in, or empty if unknown.
+    Parse {
+        version: Option<u32>,
+        line: usize,
+        column: usize,
+        path: String,
+        message: String,
+    },
+    /// The manifest has an unsupported format version.
+    Version(String),
+    /// The manifest has error diagnostics.
+    Validation(Vec<Diagnostic>),
+    /// The value at the given JSON pointer cannot be converted between the
+    /// given format versions.
+    Conversion {
+        from: u32,
+        to: u32,
+        path: String,
+        message: String,
+    },
+    /// An external program failed.
+    Exec { program: String, message: String },
+    /// The manifest exceeds a resource limit, given as the limit and the
+    /// unit it counts.
+    Limit { limit: usize, unit: String },
+    /// An I/O operation failed.
+    Io(std::io::Error),
+}
+
+impl Error {
+    /// Create Parse Error
+    ///
+    /// Create a parse error for the given format version from a JSON
+    /// error.
+    pub fn parse(version: Option<u32>, e: serde_json::Error) -> Self {
+        Error::Parse {
+            version,
+            line: e.line(),
+            column: e.column(),
+            path: String::new(),
+            message: e.to_string(),
+        }
+    }
+
+    /// Create Validation Error
+    ///
+    /// Return a validation error if any of the diagnostics is an error,
+    /// keeping only those.
+    pub fn validation(diagnostics: &[Diagnostic]) -> Option<Self> {
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .filter(|v| v.severity == Severity::Error)
+            .cloned()
+            .collect();
+        (!errors.is_empty()).then_some(Error::Validation(errors))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse {
+                version,
+                path,
+                message,
+                ..
+            } => {
+                match version {
+                    Some(v) => write!(fmt, "invalid version-{} manifest: ", v)?,
+                    None => fmt.write_str("invalid manifest: ")?,
+                }
+                if !path.is_empty() {
+                    write!(fmt, "{}: ", path)?;
+                }
+                fmt.write_str(message)
+            }
+            Error::Version(v) => write!(fmt, "unsupported manifest version {}", v),
+            Error::Validation(v) => match v.as_slice() {
+                [v] => write!(fmt, "{}: {}", v.path, v.message),
+                v => write!(fmt, "manifest has {} errors", v.len()),
+            },
+            Error::Conversion {
+                from,
+                to,
+                path,
+                message,
+            } => write!(
+                fmt,
+                "cannot convert version-{} manifest to version {}: {}: {}",
+                from, to, path, message,
+            ),
+            Error::Exec { program, message } => write!(fmt, "{} failed: {}", program, message),
+            Error::Limit { limit, unit } => {
+                write!(fmt, "manifest exceeds the limit of {} {}", limit, unit)
+            }
+            Error::Io(v) => v.fmt(fmt),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(v: serde_json::Error) -> Self {
+        match v.classify() {
+            serde_json::error::Category::Io => Error::Io(v.into()),
+            _ => Error::parse(None, v),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(v: std::io::Error) -> Self {
+        Error::Io(v)
+    }
+}
+
+impl From<convert::Error> for Error {
+    fn from(v: convert::Error) -> Self {
+        match v {
+            convert::Error::Version => Error::Conversion {
+                from: 2,
+                to: 1,
+                path: String::new(),
+                message: v.to_string(),
+            },
+            convert::Error::Unsupported(path, message) => Error::Conversion {
+                from: 2,
+                to: 1,
+                path,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "exec")]
+impl From<dnfjson::Error> for Error {
+    fn from(v: dnfjson::Error) -> Self {
+        match v {
+            dnfjson::Error::Io(v) => Error::Io(v),
+            v => Error::Exec {
+                program: "depsolver".to_owned(),
+                message: v.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Manifest, Manifest1};
+
+    // Verify Crate Errors
+    #[test]
+    fn verify_error() {
+        match "{\n  \"pipelines\": {} }".parse::<Manifest1>() {
+            Err(Error::Parse {
+                version: Some(1),
+                line: 2,
+                ..
+            }) => {}
+            v => panic!("unexpected result: {:?}", v),
+        }
+        assert!(matches! {
+            Manifest::parse(r#"{ "version": "3" }"#),
+            Err(Error::Version(v)) if v == "3",
+        });
+
+        let e = Error::from(convert::Error::Unsupported(
+            "/pipelines/0".to_owned(),
+            "devices are not supported".to_owned(),
+        ));
+        assert_eq! {
+            e.to_string(),
+            "cannot convert version-2 manifest to version 1: /pipelines/0: devices are not supported",
+        }
+
+        let diagnostics = [
+            Diagnostic::new("a", Severity::Warning, "/x", "warning"),
+            Diagnostic::new("b", Severity::Error, "/y", "error"),
+        ];
+        let e = Error::validation(&diagnostics).unwrap();
+        assert_eq!(e.to_string(), "/y: error");
+        assert!(Error::validation(&diagnostics[..1]).is_none());
+    }
+}
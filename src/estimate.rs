@@ -0,0 +1,471 @@
+//! Artifact Size Estimation
+//!
+//! This module estimates the size of the artifact a manifest produces,
+//! without running the build. The estimate is derived from the explicit
+//! sizes declared in stage and assembler options, the extent of declared
+//! partition tables, the sizes of the packages installed into the tree, and
+//! heuristics for the compression applied by the assembler.
+//!
+//! Estimates are always provided as ranges together with a confidence level,
+//! since most inputs only allow approximations. They are meant for capacity
+//! planning and quota checks, not for exact allocation.
+
+use crate::manifest::{Json, Manifest1, Object, Pipeline1};
+
+/// Estimation Confidence
+///
+/// This describes how reliable an estimate is. `High` is only used if the
+/// artifact size is fixed by the manifest. `Medium` is used if the range is
+/// derived from known inputs but subject to heuristics. `Low` is used if
+/// some inputs were unknown and had to be extrapolated.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// Size Estimate
+///
+/// This represents the estimated artifact size as an inclusive range of
+/// bytes, together with the confidence in that range.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Estimate {
+    pub min: u64,
+    pub max: u64,
+    pub confidence: Confidence,
+}
+
+/// Estimation Hints
+///
+/// The manifest itself does not carry the sizes of the referenced sources.
+/// Callers that have this information (e.g., from a depsolver or a source
+/// cache) can provide it here, keyed by the checksum used to reference the
+/// source in the manifest.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Hints {
+    pub source_sizes: Object<u64>,
+}
+
+// Installed size of a package relative to its download size, in percent. RPM
+// payloads are compressed, so the tree ends up larger than the downloads.
+const INSTALL_FACTOR: (u64, u64) = (100, 300);
+
+fn scale(v: u64, percent: u64) -> u64 {
+    v.saturating_mul(percent) / 100
+}
+
+fn lower(confidence: Confidence) -> Confidence {
+    match confidence {
+        Confidence::High => Confidence::Medium,
+        _ => Confidence::Low,
+    }
+}
+
+/// Parse Size Option
+///
+/// Parse a size as used in stage and assembler options. Sizes can be given
+/// as plain integers in bytes, or as strings with an optional unit suffix
+/// like `"4 GiB"` or `"512MB"`. Returns `None` if the value is not a valid
+/// size.
+pub fn parse_size(value: &Json) -> Option<u64> {
+    if let Some(v) = value.as_u64() {
+        return Some(v);
+    }
+
+    let s = value.as_str()?.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let factor: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "kB" => 1000,
+        "KiB" => 1 << 10,
+        "MB" => 1000 * 1000,
+        "MiB" => 1 << 20,
+        "GB" => 1000 * 1000 * 1000,
+        "GiB" => 1 << 30,
+        "TB" => 1000 * 1000 * 1000 * 1000,
+        "TiB" => 1 << 40,
+        _ => return None,
+    };
+
+    number.checked_mul(factor)
+}
+
+// Extract the checksums of all packages installed by rpm stages of the
+// pipeline. Packages can be given as plain checksums or as objects with a
+// `checksum` member.
+fn rpm_packages(pipeline: &Pipeline1) -> Option<Vec<&str>> {
+    let mut found = false;
+    let mut packages = Vec::new();
+
+    for stage in pipeline.stages.iter() {
+        if stage.name != "org.osbuild.rpm" {
+            continue;
+        }
+
+        found = true;
+        if let Some(list) = stage.options.get("packages").and_then(Json::as_array) {
+            for package in list {
+                let checksum = match package {
+                    Json::String(v) => Some(v.as_str()),
+                    Json::Object(v) => v.get("checksum").and_then(Json::as_str),
+                    _ => None,
+                };
+                packages.extend(checksum);
+            }
+        }
+    }
+
+    if found {
+        Some(packages)
+    } else {
+        None
+    }
+}
+
+// Estimate the size of the file-system tree built by the pipeline, based on
+// the packages it installs.
+fn estimate_tree(pipeline: &Pipeline1, hints: &Hints) -> Option<Estimate> {
+    let packages = rpm_packages(pipeline)?;
+    let mut known: u64 = 0;
+    let mut n_known: u64 = 0;
+    let mut n_unknown: u64 = 0;
+
+    for checksum in packages {
+        match hints.source_sizes.get(checksum) {
+            Some(v) => {
+                known = known.saturating_add(*v);
+                n_known += 1;
+            }
+            None => n_unknown += 1,
+        }
+    }
+
+    if n_known == 0 && n_unknown > 0 {
+        return None;
+    }
+
+    // Extrapolate the unknown packages from the average known package size
+    // for the upper bound. The lower bound only covers what is known.
+    let average = known.checked_div(n_known).unwrap_or(0);
+    let extrapolated = known.saturating_add(average.saturating_mul(n_unknown));
+
+    Some(Estimate {
+        min: scale(known, INSTALL_FACTOR.0),
+        max: scale(extrapolated, INSTALL_FACTOR.1),
+        confidence: if n_unknown > 0 {
+            Confidence::Low
+        } else {
+            Confidence::Medium
+        },
+    })
+}
+
+// Find the last size fixed by a truncate stage, if any.
+fn truncated_size(pipeline: &Pipeline1) -> Option<u64> {
+    pipeline
+        .stages
+        .iter()
+        .filter(|v| v.name == "org.osbuild.truncate")
+        .filter_map(|v| v.options.get("size").and_then(parse_size))
+        .next_back()
+}
+
+// Sector size assumed for partition tables, as used by osbuild.
+const SECTOR: u64 = 512;
+
+// Sectors reserved at the end of the disk for the backup GPT header and
+// partition entries.
+const GPT_BACKUP: u64 = 33;
+
+// Compute the minimal disk size covering a partition table, as described by
+// the options of sfdisk, sgdisk, and the qemu assembler. Partition starts and
+// sizes are given in sectors. Returns `None` if no partition has a start and
+// a size.
+fn partitioned_size(options: &Object<Json>) -> Option<u64> {
+    let end = options
+        .get("partitions")
+        .and_then(Json::as_array)?
+        .iter()
+        .filter_map(|v| {
+            let start = v.get("start").and_then(Json::as_u64)?;
+            let size = v.get("size").and_then(Json::as_u64)?;
+            Some(start.saturating_add(size))
+        })
+        .max()?;
+
+    let label = options
+        .get("label")
+        .or_else(|| options.get("pttype"))
+        .and_then(Json::as_str);
+    let end = match label {
+        Some("gpt") => end.saturating_add(GPT_BACKUP),
+        _ => end,
+    };
+
+    Some(end.saturating_mul(SECTOR))
+}
+
+// Find the size of the disk covered by the last partitioning stage, if any.
+fn table_size(pipeline: &Pipeline1) -> Option<u64> {
+    pipeline
+        .stages
+        .iter()
+        .filter(|v| v.name == "org.osbuild.sfdisk" || v.name == "org.osbuild.sgdisk")
+        .filter_map(|v| partitioned_size(&v.options))
+        .next_back()
+}
+
+// Ratio of compressed to uncompressed size in percent, per compression
+// method. These are rough values for typical operating system trees.
+fn compression_factor(method: Option<&str>) -> (u64, u64) {
+    match method {
+        None | Some("none") => (100, 100),
+        Some("gzip") => (30, 60),
+        Some("bzip2") => (25, 55),
+        Some("zstd") => (25, 55),
+        Some("xz") => (20, 50),
+        Some(_) => (10, 100),
+    }
+}
+
+/// Estimate Artifact Size
+///
+/// Estimate the size of the artifact produced by the manifest. Only the
+/// main pipeline contributes to the artifact. Build pipelines are ignored,
+/// since they merely provide the build environment.
+///
+/// Returns `None` if the manifest provides no information to base an
+/// estimate on.
+pub fn estimate(manifest: &Manifest1, hints: &Hints) -> Option<Estimate> {
+    let pipeline = &manifest.pipeline;
+    let tree = estimate_tree(pipeline, hints);
+    let declared = truncated_size(pipeline).or_else(|| table_size(pipeline));
+
+    let assembler = match pipeline.assembler {
+        None => {
+            return match declared {
+                Some(v) => Some(Estimate {
+                    min: v,
+                    max: v,
+                    confidence: Confidence::High,
+                }),
+                None => tree,
+            };
+        }
+        Some(ref v) => v,
+    };
+
+    let option = |key: &str| assembler.options.get(key);
+    let declared = option("size")
+        .and_then(parse_size)
+        .or_else(|| partitioned_size(&assembler.options))
+        .or(declared);
+
+    match (assembler.name.as_str(), declared) {
+        ("org.osbuild.rawfs", Some(size)) => Some(Estimate {
+            min: size,
+            max: size,
+            confidence: Confidence::High,
+        }),
+        ("org.osbuild.qemu", Some(size)) => {
+            if option("format").and_then(Json::as_str) == Some("raw") {
+                Some(Estimate {
+                    min: size,
+                    max: size,
+                    confidence: Confidence::High,
+                })
+            } else {
+                // Sparse formats only store the used blocks, so the tree
+                // size is a lower bound, while the disk size is an upper
+                // bound (ignoring format metadata).
+                Some(Estimate {
+                    min: tree.map_or(0, |v| v.min.min(size)),
+                    max: size,
+                    confidence: Confidence::Medium,
+                })
+            }
+        }
+        ("org.osbuild.tar", _) | ("org.osbuild.oci-archive", _) => {
+            let tree = tree?;
+            let (lo, hi) = compression_factor(option("compression").and_then(Json::as_str));
+            Some(Estimate {
+                min: scale(tree.min, lo),
+                max: scale(tree.max, hi),
+                confidence: if lo == hi {
+                    tree.confidence
+                } else {
+                    lower(tree.confidence)
+                },
+            })
+        }
+        (_, Some(size)) => Some(Estimate {
+            min: tree.map_or(0, |v| v.min.min(size)),
+            max: size,
+            confidence: Confidence::Low,
+        }),
+        (_, None) => tree.map(|v| Estimate {
+            confidence: lower(v.confidence),
+            ..v
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Manifest1 {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // Verify Size Parsing
+    #[test]
+    fn verify_parse_size() {
+        assert_eq!(parse_size(&Json::from(71)), Some(71));
+        assert_eq!(parse_size(&Json::from("71")), Some(71));
+        assert_eq!(parse_size(&Json::from("2 KiB")), Some(2048));
+        assert_eq!(parse_size(&Json::from("3GB")), Some(3_000_000_000));
+        assert_eq!(parse_size(&Json::from("3 XB")), None);
+        assert_eq!(parse_size(&Json::from("GiB")), None);
+        assert_eq!(parse_size(&Json::from(-1)), None);
+    }
+
+    // Verify Estimates
+    #[test]
+    fn verify_estimate() {
+        let hints = Hints {
+            source_sizes: Object::from([
+                ("sha256:a".to_owned(), 100),
+                ("sha256:b".to_owned(), 300),
+            ]),
+        };
+
+        // Empty manifests have nothing to base an estimate on.
+        assert_eq!(estimate(&parse(r#"{}"#), &hints), None);
+
+        // Raw disk images have a fixed size.
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "assembler": {
+                            "name": "org.osbuild.qemu",
+                            "options": { "format": "raw", "size": "1 MiB" }
+                        }
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 1 << 20, max: 1 << 20, confidence: Confidence::High }),
+        }
+
+        // Without an explicit size, partition tables bound the disk size,
+        // including the backup header of GPT.
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "assembler": {
+                            "name": "org.osbuild.qemu",
+                            "options": {
+                                "format": "raw",
+                                "pttype": "dos",
+                                "partitions": [
+                                    { "start": 2048, "size": 2048 },
+                                    { "start": 4096, "size": 4096 }
+                                ]
+                            }
+                        }
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 8192 * 512, max: 8192 * 512, confidence: Confidence::High }),
+        }
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "stages": [
+                            {
+                                "name": "org.osbuild.sfdisk",
+                                "options": {
+                                    "label": "gpt",
+                                    "partitions": [{ "start": 2048, "size": 4096 }]
+                                }
+                            }
+                        ]
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 6177 * 512, max: 6177 * 512, confidence: Confidence::High }),
+        }
+
+        // Trees are derived from package sizes, with unknown packages
+        // extrapolated from the known ones.
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "stages": [
+                            {
+                                "name": "org.osbuild.rpm",
+                                "options": { "packages": ["sha256:a", "sha256:b"] }
+                            }
+                        ]
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 400, max: 1200, confidence: Confidence::Medium }),
+        }
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "stages": [
+                            {
+                                "name": "org.osbuild.rpm",
+                                "options": {
+                                    "packages": ["sha256:a", { "checksum": "sha256:b" }]
+                                }
+                            },
+                            {
+                                "name": "org.osbuild.rpm",
+                                "options": { "packages": ["sha256:c"] }
+                            }
+                        ]
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 400, max: 1800, confidence: Confidence::Low }),
+        }
+
+        // Compressed archives scale the tree estimate.
+        assert_eq! {
+            estimate(
+                &parse(r#"{
+                    "pipeline": {
+                        "assembler": {
+                            "name": "org.osbuild.tar",
+                            "options": { "compression": "xz" }
+                        },
+                        "stages": [
+                            {
+                                "name": "org.osbuild.rpm",
+                                "options": { "packages": ["sha256:a"] }
+                            }
+                        ]
+                    }
+                }"#),
+                &hints,
+            ),
+            Some(Estimate { min: 20, max: 150, confidence: Confidence::Low }),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Build Events
+//!
+//! Builds are long-running, and services driving them report their
+//! progress to other systems, like chat-ops bots or dashboards. This module
+//! defines structured events for the lifecycle of a build, and sinks that
+//! deliver them. `Webhook` posts each event as JSON to a URL via the
+//! caller-provided transport.
+//!
+//! This crate does not run osbuild itself. Events are emitted by the
+//! service executing the build, which passes them to its sinks as the
+//! build progresses.
+
+use crate::http::Transport;
+use crate::lint::Diagnostic;
+
+/// Build Event
+///
+/// Events are tagged by their kind, and refer to the build by the
+/// identifier chosen by the service, like the content identifier of its
+/// manifest. Stages are identified by their pipeline, their index within
+/// it, and their type.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The build was accepted and waits for a builder.
+    Queued { build: String },
+    /// A stage started.
+    StageStarted {
+        build: String,
+        pipeline: String,
+        index: usize,
+        stage: String,
+    },
+    /// A stage finished successfully after the given number of
+    /// milliseconds.
+    StageFinished {
+        build: String,
+        pipeline: String,
+        index: usize,
+        stage: String,
+        duration_ms: u64,
+    },
+    /// The build finished successfully.
+    Finished { build: String },
+    /// The build failed with the given message and diagnostics.
+    Failed {
+        build: String,
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        diagnostics: Vec<Diagnostic>,
+    },
+}
+
+impl Event {
+    /// Kind of the Event
+    ///
+    /// Return the kind of the event, as used for its tag, like
+    /// `stage-started`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Queued { .. } => "queued",
+            Event::StageStarted { .. } => "stage-started",
+            Event::StageFinished { .. } => "stage-finished",
+            Event::Finished { .. } => "finished",
+            Event::Failed { .. } => "failed",
+        }
+    }
+
+    /// Build Identifier
+    pub fn build(&self) -> &str {
+        match self {
+            Event::Queued { build }
+            | Event::StageStarted { build, .. }
+            | Event::StageFinished { build, .. }
+            | Event::Finished { build }
+            | Event::Failed { build, .. } => build,
+        }
+    }
+}
+
+/// Event Sink
+///
+/// Delivers events. Errors are returned as human-readable description.
+/// Services usually log them rather than failing the build, since events
+/// are informational.
+pub trait Sink {
+    fn emit(&self, event: &Event) -> Result<(), String>;
+}
+
+impl<F: Fn(&Event) -> Result<(), String>> Sink for F {
+    fn emit(&self, event: &Event) -> Result<(), String> {
+        self(event)
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for [Box<S>] {
+    // Deliver to all sinks, even if some fail, and report the first
+    // failure.
+    fn emit(&self, event: &Event) -> Result<(), String> {
+        let mut acc = Ok(());
+        for sink in self {
+            let r = sink.emit(event);
+            acc = acc.and(r);
+        }
+        acc
+    }
+}
+
+/// Webhook Sink
+///
+/// Posts events as JSON objects to the URL, with the configured headers,
+/// like an authorization token. If `kinds` is non-empty, only events of
+/// the listed kinds are posted.
+pub struct Webhook<T> {
+    pub transport: T,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub kinds: Vec<String>,
+}
+
+impl<T: Transport> Webhook<T> {
+    /// Create Webhook
+    ///
+    /// Create a webhook posting all events to the URL.
+    pub fn new(transport: T, url: &str) -> Self {
+        Self {
+            transport,
+            url: url.to_owned(),
+            headers: Vec::new(),
+            kinds: Vec::new(),
+        }
+    }
+}
+
+impl<T: Transport> Sink for Webhook<T> {
+    fn emit(&self, event: &Event) -> Result<(), String> {
+        if !self.kinds.is_empty() && !self.kinds.iter().any(|v| v == event.kind()) {
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        let mut headers = vec![("content-type", "application/json")];
+        headers.extend(self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        let r = self.transport.request("POST", &self.url, &headers, &body)?;
+        match r.status {
+            200..=299 => Ok(()),
+            v => Err(format!("{}: webhook responded with status {}", self.url, v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+    use crate::lint::Severity;
+    use std::cell::RefCell;
+
+    struct Fake(RefCell<Vec<(String, Vec<u8>)>>);
+
+    impl Transport for Fake {
+        fn get(&self, _url: &str, _headers: &[(&str, &str)]) -> Result<Response, String> {
+            unreachable!()
+        }
+
+        fn request(
+            &self,
+            method: &str,
+            url: &str,
+            headers: &[(&str, &str)],
+            body: &[u8],
+        ) -> Result<Response, String> {
+            assert_eq!(method, "POST");
+            assert!(headers.contains(&("authorization", "Bearer t")));
+            self.0.borrow_mut().push((url.to_owned(), body.to_vec()));
+            Ok(Response {
+                status: if url.ends_with("/gone") { 410 } else { 204 },
+                ..Default::default()
+            })
+        }
+    }
+
+    // Verify Build Events
+    #[test]
+    fn verify_events() {
+        let failed = Event::Failed {
+            build: "b".to_owned(),
+            message: "stage failed".to_owned(),
+            diagnostics: vec![Diagnostic::new("r", Severity::Error, "/x", "m")],
+        };
+        assert_eq!(failed.kind(), "failed");
+        assert_eq!(failed.build(), "b");
+        assert_eq! {
+            serde_json::to_value(&failed).unwrap(),
+            serde_json::json!({
+                "event": "failed",
+                "build": "b",
+                "message": "stage failed",
+                "diagnostics": [{ "rule": "r", "severity": "error", "path": "/x", "message": "m" }],
+            }),
+        }
+
+        let started = Event::StageStarted {
+            build: "b".to_owned(),
+            pipeline: "os".to_owned(),
+            index: 0,
+            stage: "org.osbuild.rpm".to_owned(),
+        };
+        let mut hook = Webhook::new(Fake(RefCell::new(Vec::new())), "https://hooks/build");
+        hook.headers
+            .push(("authorization".to_owned(), "Bearer t".to_owned()));
+        hook.kinds.push("failed".to_owned());
+        hook.emit(&started).unwrap();
+        hook.emit(&failed).unwrap();
+        let posted = hook.transport.0.borrow();
+        assert_eq!(posted.len(), 1);
+        assert_eq!(
+            serde_json::from_slice::<Event>(&posted[0].1).unwrap(),
+            failed
+        );
+        drop(posted);
+
+        let mut gone = Webhook::new(Fake(RefCell::new(Vec::new())), "https://hooks/gone");
+        gone.headers = hook.headers.clone();
+        let seen = RefCell::new(Vec::new());
+        let log = |e: &Event| {
+            seen.borrow_mut().push(e.kind());
+            Ok(())
+        };
+        let sinks: Vec<Box<dyn Sink + '_>> = vec![Box::new(gone), Box::new(log)];
+        assert_eq! {
+            sinks[..].emit(&started),
+            Err("https://hooks/gone: webhook responded with status 410".to_owned()),
+        }
+        assert_eq!(*seen.borrow(), ["stage-started"]);
+    }
+}
@@ -0,0 +1,278 @@
+//! Export Targets
+//!
+//! osbuild only stores the trees of the pipelines requested via `--export
+//! <name>`, so callers have to know which pipelines of a manifest produce
+//! the artifacts. Manifests do not record this. Generators of manifests
+//! can mark pipelines via `Pipeline2::export`, otherwise exports are
+//! guessed from conventional pipeline names, like `image` or `qcow2`, and
+//! finally from pipelines no other pipeline depends on.
+//!
+//! Formats and filenames of artifacts are derived from the last stage of
+//! the exported pipeline, like the `format` of `org.osbuild.qemu`.
+
+use crate::manifest::{Json, Manifest2, Pipeline2};
+
+/// Conventional names of exported pipelines.
+pub const NAMES: &[&str] = &[
+    "assembler",
+    "image",
+    "archive",
+    "container",
+    "commit",
+    "bootiso",
+    "qcow2",
+    "vmdk",
+    "vhd",
+    "vpc",
+    "ova",
+    "gce",
+    "tar",
+    "xz",
+];
+
+// Formats of artifacts by stage type, if not given by the stage options.
+const FORMATS: &[(&str, &str)] = &[
+    ("org.osbuild.tar", "tar"),
+    ("org.osbuild.oci-archive", "oci-archive"),
+    ("org.osbuild.ostree.commit", "ostree-commit"),
+    ("org.osbuild.xorrisofs", "iso"),
+    ("org.osbuild.squashfs", "squashfs"),
+    ("org.osbuild.erofs", "erofs"),
+    ("org.osbuild.truncate", "raw"),
+    ("org.osbuild.xz", "xz"),
+    ("org.osbuild.gzip", "gzip"),
+    ("org.osbuild.zstd", "zstd"),
+];
+
+/// Reason of an Export
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Reason {
+    /// The pipeline has export metadata.
+    Metadata,
+    /// The pipeline was requested explicitly.
+    Requested,
+    /// The pipeline has a conventional name.
+    Name,
+    /// No other pipeline depends on the pipeline.
+    Leaf,
+}
+
+/// Exportable Artifact
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Artifact {
+    /// Name of the pipeline, as passed to `--export`.
+    pub pipeline: String,
+    pub reason: Reason,
+    /// Format of the artifact, like `qcow2`, if known.
+    pub format: Option<String>,
+    /// Filename of the artifact in the exported tree, if known.
+    pub filename: Option<String>,
+}
+
+/// Format of a Pipeline
+///
+/// Derive the format and filename of the artifact of the pipeline from its
+/// last stage. Export metadata takes precedence.
+pub fn format(pipeline: &Pipeline2) -> (Option<String>, Option<String>) {
+    let export = pipeline.export.as_ref();
+    let stage = pipeline.stages.last();
+    let options = stage.map(|v| &v.options);
+
+    let format = export.and_then(|v| v.format.clone()).or_else(|| {
+        let stage = stage?;
+        match options.and_then(|v| v.get("format")) {
+            Some(Json::String(v)) => Some(v.clone()),
+            Some(Json::Object(v)) => v.get("type").and_then(Json::as_str).map(str::to_owned),
+            _ => FORMATS
+                .iter()
+                .find(|v| v.0 == stage.r#type.as_str())
+                .map(|v| v.1.to_owned()),
+        }
+    });
+    let filename = export.and_then(|v| v.filename.clone()).or_else(|| {
+        options
+            .and_then(|v| v.get("filename"))
+            .and_then(Json::as_str)
+            .map(str::to_owned)
+    });
+    (format, filename)
+}
+
+fn artifact(pipeline: &Pipeline2, reason: Reason) -> Artifact {
+    let (format, filename) = format(pipeline);
+    Artifact {
+        pipeline: pipeline.name.clone(),
+        reason,
+        format,
+        filename,
+    }
+}
+
+/// Enumerate Exports
+///
+/// Return the artifacts of the manifest, in order of the pipelines. If any
+/// pipeline has export metadata, only those are returned. Otherwise, the
+/// pipelines with conventional names are returned, or, if there are none,
+/// the pipelines no other pipeline depends on.
+pub fn exports(manifest: &Manifest2) -> Vec<Artifact> {
+    let marked: Vec<Artifact> = manifest
+        .pipelines
+        .iter()
+        .filter(|v| v.export.is_some())
+        .map(|v| artifact(v, Reason::Metadata))
+        .collect();
+    if !marked.is_empty() {
+        return marked;
+    }
+
+    let named: Vec<Artifact> = manifest
+        .pipelines
+        .iter()
+        .filter(|v| NAMES.contains(&v.name.as_str()))
+        .map(|v| artifact(v, Reason::Name))
+        .collect();
+    if !named.is_empty() {
+        return named;
+    }
+
+    let deps: Vec<&str> = manifest
+        .pipelines
+        .iter()
+        .flat_map(Pipeline2::dependencies)
+        .collect();
+    manifest
+        .pipelines
+        .iter()
+        .filter(|v| !deps.contains(&v.name.as_str()))
+        .map(|v| artifact(v, Reason::Leaf))
+        .collect()
+}
+
+/// Parse Export Arguments
+///
+/// Collect the pipeline names of the `--export <name>` and
+/// `--export=<name>` arguments of an osbuild command line. Other arguments
+/// are ignored.
+pub fn parse_args<S: AsRef<str>>(args: &[S]) -> Vec<String> {
+    let mut acc = Vec::new();
+    let mut args = args.iter().map(AsRef::as_ref);
+    while let Some(arg) = args.next() {
+        if arg == "--export" {
+            acc.extend(args.next().map(str::to_owned));
+        } else if let Some(v) = arg.strip_prefix("--export=") {
+            acc.push(v.to_owned());
+        }
+    }
+    acc
+}
+
+/// Select Exports
+///
+/// Return the artifacts of the requested pipelines, in order of the
+/// request. Without requested pipelines, this is `exports()`. Fails with
+/// the name of the first requested pipeline that is not part of the
+/// manifest.
+pub fn select<S: AsRef<str>>(manifest: &Manifest2, names: &[S]) -> Result<Vec<Artifact>, String> {
+    if names.is_empty() {
+        return Ok(exports(manifest));
+    }
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref();
+            manifest
+                .pipelines
+                .iter()
+                .find(|v| v.name == name)
+                .map(|v| artifact(v, Reason::Requested))
+                .ok_or_else(|| name.to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Export;
+
+    // Verify Export Targets
+    #[test]
+    fn verify_exports() {
+        let mut manifest: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "os", "build": "name:build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "image", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.truncate",
+                        "options": { "filename": "disk.raw", "size": "10G" }
+                    }] },
+                    { "name": "qcow2", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.qemu",
+                        "options": { "filename": "disk.qcow2", "format": { "type": "qcow2" } },
+                        "inputs": { "image": {
+                            "type": "org.osbuild.files",
+                            "origin": "org.osbuild.pipeline",
+                            "references": { "name:image": { "file": "disk.raw" } }
+                        } }
+                    }] },
+                    { "name": "tarball", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.tar",
+                        "options": { "filename": "root.tar" },
+                        "inputs": { "tree": {
+                            "type": "org.osbuild.tree",
+                            "origin": "org.osbuild.pipeline",
+                            "references": ["name:os"]
+                        } }
+                    }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let exports = exports(&manifest);
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].pipeline, "image");
+        assert_eq!(exports[0].format.as_deref(), Some("raw"));
+        assert_eq!(exports[1].reason, Reason::Name);
+        assert_eq!(exports[1].format.as_deref(), Some("qcow2"));
+        assert_eq!(exports[1].filename.as_deref(), Some("disk.qcow2"));
+
+        let mut unnamed = manifest.clone();
+        unnamed
+            .pipelines
+            .retain(|v| !NAMES.contains(&v.name.as_str()));
+        let leaves = super::exports(&unnamed);
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].pipeline, "tarball");
+        assert_eq!(leaves[0].reason, Reason::Leaf);
+        assert_eq!(leaves[0].format.as_deref(), Some("tar"));
+
+        manifest.pipelines[1].export = Some(Export {
+            format: Some("directory".to_owned()),
+            filename: None,
+        });
+        let marked = super::exports(&manifest);
+        assert_eq!(marked.len(), 1);
+        assert_eq!(marked[0].pipeline, "os");
+        assert_eq!(marked[0].reason, Reason::Metadata);
+        assert_eq!(marked[0].format.as_deref(), Some("directory"));
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(!json.contains("directory"));
+
+        let names = parse_args(&[
+            "--export",
+            "qcow2",
+            "--output-dir",
+            "out",
+            "--export=tarball",
+        ]);
+        assert_eq!(names, vec!["qcow2", "tarball"]);
+        let selected = select(&manifest, &names).unwrap();
+        assert_eq!(selected[1].pipeline, "tarball");
+        assert_eq!(selected[1].reason, Reason::Requested);
+        assert_eq!(select(&manifest, &["missing"]), Err("missing".to_owned()));
+        assert_eq!(select::<&str>(&manifest, &[]).unwrap(), marked);
+    }
+}
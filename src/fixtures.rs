@@ -0,0 +1,331 @@
+//! Manifest Fixtures
+//!
+//! This module loads corpora of manifests for parameterized tests and
+//! benchmarks, like the osbuild manifest-db or manifests exported from
+//! image builders. A corpus is a directory tree of manifest files, each
+//! of which becomes a fixture tagged with its format version and the
+//! metadata stored alongside the manifest.
+//!
+//! Manifests can be stored as JSON (`.json`) or YAML (`.yaml`, `.yml`),
+//! optionally gzip-compressed (`.gz`). Corpus entries can embed the
+//! manifest in a `manifest` member, next to metadata like the compose
+//! request or the expected image-info. All other files are ignored.
+//! Files that fail to load are collected as failures rather than aborting
+//! the whole corpus, since real-world corpora contain the occasional
+//! broken entry.
+
+use crate::inflate;
+use crate::manifest::{Json, Manifest};
+use crate::yaml;
+use std::path::{Path, PathBuf};
+
+/// Corpus Variable
+///
+/// The environment variable used by `Corpus::from_env()` if no other is
+/// given.
+pub const CORPUS_VARIABLE: &str = "R_OSBUILD_CORPUS";
+
+/// Fixture Error
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The file could not be decompressed.
+    Inflate(inflate::Error),
+    /// The file is not valid UTF-8.
+    Utf8,
+    /// The file is not valid JSON.
+    Json(serde_json::Error),
+    /// The file is not valid YAML.
+    Yaml(yaml::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(fmt, "cannot read fixture: {}", e),
+            Error::Inflate(e) => write!(fmt, "cannot decompress fixture: {}", e),
+            Error::Utf8 => write!(fmt, "fixture is not valid UTF-8"),
+            Error::Json(e) => write!(fmt, "invalid JSON: {}", e),
+            Error::Yaml(e) => write!(fmt, "invalid YAML: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Fixture Format
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Detect Format
+    ///
+    /// Detect the format of a fixture file from its name, returning
+    /// whether it is compressed. Returns `None` for files that are not
+    /// fixtures.
+    pub fn detect(path: &Path) -> Option<(Format, bool)> {
+        let name = path.file_name()?.to_str()?;
+        let (name, compressed) = match name.strip_suffix(".gz") {
+            Some(v) => (v, true),
+            None => (name, false),
+        };
+        let format = match name.rsplit_once('.')?.1 {
+            "json" => Format::Json,
+            "yaml" | "yml" => Format::Yaml,
+            _ => return None,
+        };
+        Some((format, compressed))
+    }
+}
+
+/// Manifest Fixture
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixture {
+    /// Path of the file, as found below the corpus directory.
+    pub path: PathBuf,
+    /// Name of the fixture, which is its path relative to the corpus
+    /// directory, with `/` as separator.
+    pub name: String,
+    pub format: Format,
+    pub compressed: bool,
+    /// Format version of the manifest, or `None` if it declares a version
+    /// this crate does not implement.
+    pub version: Option<u32>,
+    /// The manifest, as JSON value.
+    pub manifest: Json,
+    /// Members stored next to an embedded manifest. This is empty for
+    /// files holding a bare manifest.
+    pub metadata: serde_json::Map<String, Json>,
+}
+
+impl Fixture {
+    /// Load Fixture
+    ///
+    /// Load a single fixture file. The name of the fixture is the file
+    /// name. Files with unknown extensions are read as JSON.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let (format, compressed) = Format::detect(path).unwrap_or((Format::Json, false));
+        let data = std::fs::read(path).map_err(Error::Io)?;
+        let data = match compressed || inflate::is_gzip(&data) {
+            true => inflate::gunzip(&data).map_err(Error::Inflate)?,
+            false => data,
+        };
+        let text = std::str::from_utf8(&data).map_err(|_| Error::Utf8)?;
+        let mut json = match format {
+            Format::Json => serde_json::from_str(text).map_err(Error::Json)?,
+            Format::Yaml => yaml::from_str(text).map_err(Error::Yaml)?,
+        };
+
+        let mut metadata = serde_json::Map::new();
+        if let Some(v) = json.get_mut("manifest").filter(|v| v.is_object()) {
+            let manifest = v.take();
+            if let Json::Object(v) = json {
+                metadata = v;
+                metadata.remove("manifest");
+            }
+            json = manifest;
+        }
+
+        let version = match json.get("version") {
+            None => Some(1),
+            Some(Json::String(v)) if v == "2" => Some(2),
+            Some(_) => None,
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            name: path
+                .file_name()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            format,
+            compressed,
+            version,
+            manifest: json,
+            metadata,
+        })
+    }
+
+    /// Manifest Text
+    ///
+    /// Return the manifest serialized as JSON, as input for the parsers.
+    pub fn text(&self) -> String {
+        self.manifest.to_string()
+    }
+
+    /// Parse Manifest
+    ///
+    /// Parse the manifest of the fixture, detecting its format version.
+    pub fn parse(&self) -> Result<Manifest, crate::error::Error> {
+        Manifest::parse(&self.text())
+    }
+}
+
+/// Manifest Corpus
+#[derive(Debug, Default)]
+pub struct Corpus {
+    pub root: PathBuf,
+    /// Fixtures, sorted by name.
+    pub fixtures: Vec<Fixture>,
+    /// Files that looked like fixtures but failed to load.
+    pub failures: Vec<(PathBuf, Error)>,
+}
+
+fn collect(dir: &Path, acc: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, acc)?;
+        } else if Format::detect(&path).is_some() {
+            acc.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Corpus {
+    /// Load Corpus
+    ///
+    /// Load all fixtures below the directory, recursively. Only failures to
+    /// walk the directory are returned as error, failures of individual
+    /// files are collected in `failures`.
+    pub fn load(root: &Path) -> std::io::Result<Self> {
+        let mut paths = Vec::new();
+        collect(root, &mut paths)?;
+        paths.sort();
+
+        let mut acc = Self {
+            root: root.to_owned(),
+            ..Default::default()
+        };
+        for path in paths {
+            match Fixture::load(&path) {
+                Ok(mut v) => {
+                    let relative = path.strip_prefix(root).unwrap_or(&path);
+                    v.name = relative
+                        .components()
+                        .map(|v| v.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    acc.fixtures.push(v);
+                }
+                Err(e) => acc.failures.push((path, e)),
+            }
+        }
+        Ok(acc)
+    }
+
+    /// Load Corpus from Environment
+    ///
+    /// Load the corpus at the directory named by the environment variable,
+    /// or `CORPUS_VARIABLE` if `None`. Returns `None` if the variable is
+    /// not set, so tests can skip corpora that are not available.
+    pub fn from_env(variable: Option<&str>) -> Option<std::io::Result<Self>> {
+        let root = std::env::var_os(variable.unwrap_or(CORPUS_VARIABLE))?;
+        Some(Self::load(Path::new(&root)))
+    }
+
+    /// Iterate Fixtures
+    pub fn iter(&self) -> std::slice::Iter<'_, Fixture> {
+        self.fixtures.iter()
+    }
+
+    /// Iterate Fixtures of a Version
+    ///
+    /// Iterate the fixtures with manifests of the given format version.
+    pub fn version(&self, version: u32) -> impl Iterator<Item = &Fixture> {
+        self.iter().filter(move |v| v.version == Some(version))
+    }
+
+    /// Iterate Unsupported Fixtures
+    ///
+    /// Iterate the fixtures with manifests of format versions this crate
+    /// does not implement, which are coverage gaps.
+    pub fn unsupported(&self) -> impl Iterator<Item = &Fixture> {
+        self.iter().filter(|v| v.version.is_none())
+    }
+
+    /// Iterate Fixtures with Metadata
+    ///
+    /// Iterate the fixtures having the given metadata member.
+    pub fn with<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Fixture> {
+        self.iter().filter(move |v| v.metadata.contains_key(key))
+    }
+}
+
+impl<'a> IntoIterator for &'a Corpus {
+    type Item = &'a Fixture;
+    type IntoIter = std::slice::Iter<'a, Fixture>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Corpus Loading
+    #[test]
+    fn verify_corpus() {
+        let root = std::env::temp_dir().join(format!("r-osbuild-fixtures-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("fedora")).unwrap();
+        std::fs::write(
+            root.join("fedora/qcow2.json"),
+            r#"{ "compose-request": { "arch": "x86_64" }, "manifest": { "version": "2", "pipelines": [] } }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("tar.yaml"),
+            "pipeline:\n  stages:\n    - name: org.osbuild.noop\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("future.yml"), "version: '3'\n").unwrap();
+        std::fs::write(root.join("broken.json"), "{").unwrap();
+        std::fs::write(root.join("README.md"), "# Corpus").unwrap();
+        // `printf '{}' | gzip -n`
+        std::fs::write(
+            root.join("empty.json.gz"),
+            [
+                0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xab, 0xae, 0x05, 0x00,
+                0x43, 0xbf, 0xa6, 0xa3, 0x02, 0x00, 0x00, 0x00,
+            ],
+        )
+        .unwrap();
+
+        let corpus = Corpus::load(&root).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<_> = corpus.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "empty.json.gz",
+                "fedora/qcow2.json",
+                "future.yml",
+                "tar.yaml"
+            ]
+        );
+        assert_eq!(corpus.failures.len(), 1);
+        assert!(matches!(corpus.failures[0].1, Error::Json(_)));
+
+        assert_eq!(corpus.version(1).count(), 2);
+        assert_eq!(corpus.unsupported().next().unwrap().name, "future.yml");
+        let qcow2 = corpus.with("compose-request").next().unwrap();
+        assert_eq!(qcow2.version, Some(2));
+        assert_eq!(qcow2.metadata["compose-request"]["arch"], "x86_64");
+        assert!(matches!(qcow2.parse().unwrap(), Manifest::V2(_)));
+
+        let empty = &corpus.fixtures[0];
+        assert!(empty.compressed);
+        assert_eq!(empty.manifest, serde_json::json!({}));
+        for fixture in corpus.version(1) {
+            assert!(matches!(fixture.parse().unwrap(), Manifest::V1(_)));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! GVariant Serialization
+//!
+//! OSTree stores its metadata, like repository summaries and commit
+//! objects, in the GVariant serialization format of GLib. This module
+//! implements decoding and encoding of that format for the basic types,
+//! arrays, dictionaries, tuples, and variants. Maybe types are not
+//! supported.
+//!
+//! Values are serialized in little-endian byte order. Formats that store
+//! individual values in big-endian order, as OSTree does for timestamps,
+//! must swap these themselves.
+
+/// GVariant Value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Byte(u8),
+    /// Signed integers of types `n`, `i`, and `x`.
+    Int(i64),
+    /// Unsigned integers of types `q`, `u`, `h`, and `t`.
+    Uint(u64),
+    Double(f64),
+    /// Strings of types `s`, `o`, and `g`.
+    Str(String),
+    /// Byte arrays.
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+    /// Arrays of dictionary entries.
+    Dict(Vec<(Value, Value)>),
+    Tuple(Vec<Value>),
+    /// A variant, with the type of the contained value.
+    Variant(String, Box<Value>),
+}
+
+impl Value {
+    /// Look Up Dictionary Entry
+    ///
+    /// Return the value of the entry with the given string key, unwrapping
+    /// variants.
+    pub fn lookup(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(v) => v
+                .iter()
+                .find(|(k, _)| matches!(k, Value::Str(k) if k == key))
+                .map(|(_, v)| match v {
+                    Value::Variant(_, v) => v.as_ref(),
+                    v => v,
+                }),
+            _ => None,
+        }
+    }
+
+    /// String Content
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+// Split the first complete type off a type string.
+fn split_type(ty: &str) -> Result<(&str, &str), String> {
+    let mut depth = 0usize;
+    for (i, c) in ty.char_indices() {
+        match c {
+            'a' | 'm' => continue,
+            '(' | '{' => depth += 1,
+            ')' | '}' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("unbalanced type `{}`", ty))?;
+            }
+            _ => {}
+        }
+        if depth == 0 {
+            return Ok(ty.split_at(i + 1));
+        }
+    }
+    Err(format!("incomplete type `{}`", ty))
+}
+
+// Split the member types of a tuple or dictionary entry type.
+fn members(ty: &str) -> Result<Vec<&str>, String> {
+    let mut inner = &ty[1..ty.len() - 1];
+    let mut acc = Vec::new();
+    while !inner.is_empty() {
+        let (first, rest) = split_type(inner)?;
+        acc.push(first);
+        inner = rest;
+    }
+    Ok(acc)
+}
+
+fn alignment(ty: &str) -> Result<usize, String> {
+    Ok(match ty.as_bytes()[0] {
+        b'b' | b'y' | b's' | b'o' | b'g' => 1,
+        b'n' | b'q' => 2,
+        b'i' | b'u' | b'h' => 4,
+        b'x' | b't' | b'd' | b'v' => 8,
+        b'a' => alignment(&ty[1..])?,
+        b'(' | b'{' => members(ty)?
+            .into_iter()
+            .map(alignment)
+            .try_fold(1, |acc, v| v.map(|v| acc.max(v)))?,
+        _ => return Err(format!("unsupported type `{}`", ty)),
+    })
+}
+
+fn align(pos: usize, alignment: usize) -> usize {
+    pos.div_ceil(alignment) * alignment
+}
+
+fn fixed_size(ty: &str) -> Result<Option<usize>, String> {
+    Ok(match ty.as_bytes()[0] {
+        b'b' | b'y' => Some(1),
+        b'n' | b'q' => Some(2),
+        b'i' | b'u' | b'h' => Some(4),
+        b'x' | b't' | b'd' => Some(8),
+        b'(' | b'{' => {
+            let mut pos = 0;
+            for m in members(ty)? {
+                match fixed_size(m)? {
+                    None => return Ok(None),
+                    Some(n) => pos = align(pos, alignment(m)?) + n,
+                }
+            }
+            Some(align(pos, alignment(ty)?).max(1))
+        }
+        _ => None,
+    })
+}
+
+// The size of framing offsets in a container of the given size.
+fn offset_size(len: usize) -> usize {
+    match len {
+        0 => 0,
+        1..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xffff_ffff => 4,
+        _ => 8,
+    }
+}
+
+fn read_offset(data: &[u8], pos: usize, size: usize) -> Result<usize, String> {
+    let bytes = data
+        .get(pos..pos + size)
+        .ok_or("framing offset out of bounds")?;
+    let mut v = 0u64;
+    for (i, b) in bytes.iter().enumerate() {
+        v |= (*b as u64) << (8 * i);
+    }
+    usize::try_from(v).map_err(|_| "framing offset out of bounds".to_owned())
+}
+
+fn slice(data: &[u8], start: usize, end: usize) -> Result<&[u8], String> {
+    data.get(start..end)
+        .ok_or_else(|| "value out of bounds".to_owned())
+}
+
+fn number<const N: usize>(data: &[u8]) -> Result<[u8; N], String> {
+    data.try_into()
+        .map_err(|_| "invalid size of fixed-size value".to_owned())
+}
+
+/// Decode Value
+///
+/// Decode serialized data of the given type.
+pub fn decode(ty: &str, data: &[u8]) -> Result<Value, String> {
+    if !split_type(ty)?.1.is_empty() {
+        return Err(format!("`{}` is not a single complete type", ty));
+    }
+
+    Ok(match ty.as_bytes()[0] {
+        b'b' => Value::Bool(number::<1>(data)?[0] != 0),
+        b'y' => Value::Byte(number::<1>(data)?[0]),
+        b'n' => Value::Int(i16::from_le_bytes(number(data)?) as i64),
+        b'q' => Value::Uint(u16::from_le_bytes(number(data)?) as u64),
+        b'i' => Value::Int(i32::from_le_bytes(number(data)?) as i64),
+        b'u' | b'h' => Value::Uint(u32::from_le_bytes(number(data)?) as u64),
+        b'x' => Value::Int(i64::from_le_bytes(number(data)?)),
+        b't' => Value::Uint(u64::from_le_bytes(number(data)?)),
+        b'd' => Value::Double(f64::from_le_bytes(number(data)?)),
+        b's' | b'o' | b'g' => match data.split_last() {
+            Some((0, v)) => {
+                Value::Str(String::from_utf8(v.to_vec()).map_err(|_| "string is not valid UTF-8")?)
+            }
+            _ => return Err("string is not nul-terminated".to_owned()),
+        },
+        b'v' => {
+            let nul = data
+                .iter()
+                .rposition(|v| *v == 0)
+                .ok_or("variant lacks a type")?;
+            let inner = std::str::from_utf8(&data[nul + 1..])
+                .map_err(|_| "variant type is not valid UTF-8")?
+                .to_owned();
+            let value = decode(&inner, &data[..nul])?;
+            Value::Variant(inner, Box::new(value))
+        }
+        b'a' => {
+            let element = &ty[1..];
+            if element == "y" {
+                return Ok(Value::Bytes(data.to_vec()));
+            }
+
+            let mut items = Vec::new();
+            match fixed_size(element)? {
+                Some(n) => {
+                    if !data.len().is_multiple_of(n) {
+                        return Err("array size is not a multiple of its element size".to_owned());
+                    }
+                    for chunk in data.chunks(n) {
+                        items.push(decode(element, chunk)?);
+                    }
+                }
+                None if data.is_empty() => {}
+                None => {
+                    let size = offset_size(data.len());
+                    let table = read_offset(data, data.len() - size, size)?;
+                    if table > data.len() || !(data.len() - table).is_multiple_of(size) {
+                        return Err("invalid array framing".to_owned());
+                    }
+                    let align_to = alignment(element)?;
+                    let mut pos = 0;
+                    for i in 0..(data.len() - table) / size {
+                        let end = read_offset(data, table + i * size, size)?;
+                        pos = align(pos, align_to);
+                        items.push(decode(element, slice(data, pos, end.min(table))?)?);
+                        pos = end;
+                    }
+                }
+            }
+
+            if element.starts_with('{') {
+                Value::Dict(
+                    items
+                        .into_iter()
+                        .map(|v| match v {
+                            Value::Tuple(mut v) if v.len() == 2 => {
+                                let value = v.pop().unwrap_or(Value::Bool(false));
+                                (v.pop().unwrap_or(Value::Bool(false)), value)
+                            }
+                            v => (v, Value::Tuple(Vec::new())),
+                        })
+                        .collect(),
+                )
+            } else {
+                Value::Array(items)
+            }
+        }
+        b'(' | b'{' => {
+            let members = members(ty)?;
+            let size = offset_size(data.len());
+            let mut frames = data.len();
+            let mut pos = 0;
+            let mut acc = Vec::new();
+
+            for (i, m) in members.iter().enumerate() {
+                pos = align(pos, alignment(m)?);
+                let end = match fixed_size(m)? {
+                    Some(n) => pos + n,
+                    None if i + 1 == members.len() => frames,
+                    None => {
+                        frames = frames.checked_sub(size).ok_or("invalid tuple framing")?;
+                        read_offset(data, frames, size)?
+                    }
+                };
+                acc.push(decode(m, slice(data, pos, end)?)?);
+                pos = end;
+            }
+            Value::Tuple(acc)
+        }
+        _ => return Err(format!("unsupported type `{}`", ty)),
+    })
+}
+
+// Append framing offsets, choosing the smallest offset size that can
+// address the resulting container.
+fn frame(mut data: Vec<u8>, offsets: &[usize]) -> Vec<u8> {
+    if offsets.is_empty() {
+        return data;
+    }
+    let size = [1, 2, 4, 8]
+        .into_iter()
+        .find(|s| offset_size(data.len() + offsets.len() * s) == *s)
+        .unwrap_or(8);
+    for v in offsets {
+        data.extend_from_slice(&(*v as u64).to_le_bytes()[..size]);
+    }
+    data
+}
+
+fn pad(data: &mut Vec<u8>, alignment: usize) {
+    data.resize(align(data.len(), alignment), 0);
+}
+
+/// Encode Value
+///
+/// Serialize a value as the given type.
+pub fn encode(ty: &str, value: &Value) -> Result<Vec<u8>, String> {
+    let mismatch = || format!("value does not match type `{}`", ty);
+
+    Ok(match (ty.as_bytes()[0], value) {
+        (b'b', Value::Bool(v)) => vec![*v as u8],
+        (b'y', Value::Byte(v)) => vec![*v],
+        (b'n', Value::Int(v)) => (*v as i16).to_le_bytes().to_vec(),
+        (b'q', Value::Uint(v)) => (*v as u16).to_le_bytes().to_vec(),
+        (b'i', Value::Int(v)) => (*v as i32).to_le_bytes().to_vec(),
+        (b'u' | b'h', Value::Uint(v)) => (*v as u32).to_le_bytes().to_vec(),
+        (b'x', Value::Int(v)) => v.to_le_bytes().to_vec(),
+        (b't', Value::Uint(v)) => v.to_le_bytes().to_vec(),
+        (b'd', Value::Double(v)) => v.to_le_bytes().to_vec(),
+        (b's' | b'o' | b'g', Value::Str(v)) => {
+            let mut data = v.as_bytes().to_vec();
+            data.push(0);
+            data
+        }
+        (b'v', Value::Variant(inner, v)) => {
+            let mut data = encode(inner, v)?;
+            data.push(0);
+            data.extend_from_slice(inner.as_bytes());
+            data
+        }
+        (b'a', Value::Bytes(v)) if ty == "ay" => v.clone(),
+        (b'a', Value::Array(_) | Value::Dict(_)) => {
+            let element = &ty[1..];
+            let items: Vec<Value> = match value {
+                Value::Dict(v) => v
+                    .iter()
+                    .map(|(k, v)| Value::Tuple(vec![k.clone(), v.clone()]))
+                    .collect(),
+                Value::Array(v) => v.clone(),
+                _ => return Err(mismatch()),
+            };
+
+            let fixed = fixed_size(element)?.is_some();
+            let mut data = Vec::new();
+            let mut offsets = Vec::new();
+            for v in &items {
+                pad(&mut data, alignment(element)?);
+                data.extend(encode(element, v)?);
+                offsets.push(data.len());
+            }
+            if fixed {
+                data
+            } else {
+                frame(data, &offsets)
+            }
+        }
+        (b'(' | b'{', Value::Tuple(values)) => {
+            let members = members(ty)?;
+            if members.len() != values.len() {
+                return Err(mismatch());
+            }
+
+            let mut data = Vec::new();
+            let mut offsets = Vec::new();
+            for (i, (m, v)) in members.iter().zip(values).enumerate() {
+                pad(&mut data, alignment(m)?);
+                data.extend(encode(m, v)?);
+                if fixed_size(m)?.is_none() && i + 1 < members.len() {
+                    offsets.push(data.len());
+                }
+            }
+
+            match fixed_size(ty)? {
+                Some(n) => {
+                    data.resize(n, 0);
+                    data
+                }
+                None => {
+                    offsets.reverse();
+                    frame(data, &offsets)
+                }
+            }
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Serialization
+    //
+    // The expected data follows the examples of the GVariant
+    // specification.
+    #[test]
+    fn verify_serialization() {
+        let cases: &[(&str, Value, &[u8])] = &[
+            ("s", Value::Str("hello world".to_owned()), b"hello world\0"),
+            (
+                "as",
+                Value::Array(vec![
+                    Value::Str("i".to_owned()),
+                    Value::Str("can".to_owned()),
+                ]),
+                b"i\0can\0\x02\x06",
+            ),
+            (
+                "(si)",
+                Value::Tuple(vec![Value::Str("foo".to_owned()), Value::Int(-1)]),
+                b"foo\0\xff\xff\xff\xff\x04",
+            ),
+            (
+                "a{si}",
+                Value::Dict(vec![(Value::Str("hi".to_owned()), Value::Int(-2))]),
+                b"hi\0\0\xfe\xff\xff\xff\x03\x09",
+            ),
+            (
+                "(yy)",
+                Value::Tuple(vec![Value::Byte(1), Value::Byte(2)]),
+                b"\x01\x02",
+            ),
+            (
+                "v",
+                Value::Variant("t".to_owned(), Box::new(Value::Uint(7))),
+                b"\x07\0\0\0\0\0\0\0\0t",
+            ),
+        ];
+
+        for (ty, value, data) in cases {
+            assert_eq!(encode(ty, value).unwrap(), *data, "{}", ty);
+            assert_eq!(&decode(ty, data).unwrap(), value, "{}", ty);
+        }
+
+        let ty = "(a{sv}aya(say)sstayay)";
+        let value = Value::Tuple(vec![
+            Value::Dict(vec![(
+                Value::Str("version".to_owned()),
+                Value::Variant("s".to_owned(), Box::new(Value::Str("39.1".to_owned()))),
+            )]),
+            Value::Bytes(Vec::new()),
+            Value::Array(Vec::new()),
+            Value::Str("subject".to_owned()),
+            Value::Str(String::new()),
+            Value::Uint(42),
+            Value::Bytes(vec![1; 32]),
+            Value::Bytes(vec![2; 32]),
+        ]);
+        let data = encode(ty, &value).unwrap();
+        let decoded = decode(ty, &data).unwrap();
+        assert_eq!(decoded, value);
+        match decoded {
+            Value::Tuple(v) => assert_eq!(v[0].lookup("version").unwrap().as_str(), Some("39.1")),
+            _ => unreachable!(),
+        }
+
+        assert!(decode("s", b"abc").is_err());
+        assert!(decode("(s", b"").is_err());
+        assert!(decode("as", b"\x05").is_err());
+    }
+}
@@ -0,0 +1,820 @@
+//! Hash Functions
+//!
+//! This module provides the cryptographic hash functions needed to compute
+//! identifiers and digests of manifest content. They are implemented
+//! directly, to avoid pulling in external dependencies for a handful of
+//! well-specified algorithms.
+//!
+//! All hashers follow the same pattern: create a hasher with `new()`, feed
+//! data via `update()`, and retrieve the digest via `finalize()`.
+
+/// SHA-1 Hasher
+///
+/// This implements SHA-1 as specified in FIPS 180-4. SHA-1 is not
+/// collision resistant and must not be used for security purposes. It is
+/// only provided for formats that require it, like name-based UUIDs.
+#[derive(Clone, Debug)]
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: [u8; 64],
+    n_buffer: usize,
+    n_total: u64,
+}
+
+impl Sha1 {
+    /// Create New Hasher
+    pub fn new() -> Self {
+        Self {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            buffer: [0; 64],
+            n_buffer: 0,
+            n_total: 0,
+        }
+    }
+
+    fn compress(state: &mut [u32; 5], block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = *state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    /// Feed Data
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.n_total = self.n_total.wrapping_add(data.len() as u64);
+
+        while !data.is_empty() {
+            let n = (64 - self.n_buffer).min(data.len());
+            self.buffer[self.n_buffer..self.n_buffer + n].copy_from_slice(&data[..n]);
+            self.n_buffer += n;
+            data = &data[n..];
+
+            if self.n_buffer == 64 {
+                Self::compress(&mut self.state, &self.buffer);
+                self.n_buffer = 0;
+            }
+        }
+    }
+
+    /// Finalize Digest
+    pub fn finalize(mut self) -> [u8; 20] {
+        let bits = self.n_total.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.n_buffer != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0u8; 20];
+        for (chunk, v) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&v.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Hash Data
+    ///
+    /// Convenience helper that hashes a single buffer.
+    pub fn digest(data: &[u8]) -> [u8; 20] {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SHA-256 Hasher
+///
+/// This implements SHA-256 as specified in FIPS 180-4. It is the digest
+/// used by osbuild to address source content.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    n_buffer: usize,
+    n_total: u64,
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    /// Create New Hasher
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            buffer: [0; 64],
+            n_buffer: 0,
+            n_total: 0,
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (w, k) in w.iter().zip(SHA256_K) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    /// Feed Data
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.n_total = self.n_total.wrapping_add(data.len() as u64);
+
+        while !data.is_empty() {
+            let n = (64 - self.n_buffer).min(data.len());
+            self.buffer[self.n_buffer..self.n_buffer + n].copy_from_slice(&data[..n]);
+            self.n_buffer += n;
+            data = &data[n..];
+
+            if self.n_buffer == 64 {
+                Self::compress(&mut self.state, &self.buffer);
+                self.n_buffer = 0;
+            }
+        }
+    }
+
+    /// Finalize Digest
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bits = self.n_total.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.n_buffer != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0u8; 32];
+        for (chunk, v) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&v.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Hash Data
+    ///
+    /// Convenience helper that hashes a single buffer.
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SHA-512 Hasher
+///
+/// This implements SHA-512 as specified in FIPS 180-4. It is used for
+/// checksums of published artifacts.
+#[derive(Clone, Debug)]
+pub struct Sha512 {
+    state: [u64; 8],
+    buffer: [u8; 128],
+    n_buffer: usize,
+    n_total: u128,
+}
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+impl Sha512 {
+    /// Create New Hasher
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+                0x510e527fade682d1,
+                0x9b05688c2b3e6c1f,
+                0x1f83d9abfb41bd6b,
+                0x5be0cd19137e2179,
+            ],
+            buffer: [0; 128],
+            n_buffer: 0,
+            n_total: 0,
+        }
+    }
+
+    fn compress(state: &mut [u64; 8], block: &[u8]) {
+        let mut w = [0u64; 80];
+        for (i, chunk) in block.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (w, k) in w.iter().zip(SHA512_K) {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    /// Feed Data
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.n_total = self.n_total.wrapping_add(data.len() as u128);
+
+        while !data.is_empty() {
+            let n = (128 - self.n_buffer).min(data.len());
+            self.buffer[self.n_buffer..self.n_buffer + n].copy_from_slice(&data[..n]);
+            self.n_buffer += n;
+            data = &data[n..];
+
+            if self.n_buffer == 128 {
+                Self::compress(&mut self.state, &self.buffer);
+                self.n_buffer = 0;
+            }
+        }
+    }
+
+    /// Finalize Digest
+    pub fn finalize(mut self) -> [u8; 64] {
+        let bits = self.n_total.wrapping_mul(8);
+
+        self.update(&[0x80]);
+        while self.n_buffer != 112 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0u8; 64];
+        for (chunk, v) in digest.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&v.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Hash Data
+    ///
+    /// Convenience helper that hashes a single buffer.
+    pub fn digest(data: &[u8]) -> [u8; 64] {
+        let mut hasher = Self::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Default for Sha512 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compute HMAC-SHA-256
+///
+/// Compute the keyed message authentication code of the data as specified
+/// in RFC 2104, with SHA-256 as hash function.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block.map(|v| v ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(&block.map(|v| v ^ 0x5c));
+    outer.update(&inner.finalize());
+    outer.finalize()
+}
+
+/// Encode as Hexadecimal
+///
+/// Encode binary data as lower-case hexadecimal string.
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+/// Decode Hexadecimal
+///
+/// Decode a hexadecimal string, accepting both lower-case and upper-case
+/// digits.
+pub fn from_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    data.as_bytes()
+        .chunks(2)
+        .map(|v| {
+            let hi = (v[0] as char).to_digit(16)?;
+            let lo = (v[1] as char).to_digit(16)?;
+            Some((hi << 4 | lo) as u8)
+        })
+        .collect()
+}
+
+/// Encode as Base64
+///
+/// Encode binary data with the standard base64 alphabet, with padding.
+pub fn to_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut acc = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let v = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                acc.push(ALPHABET[(v >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                acc.push('=');
+            }
+        }
+    }
+    acc
+}
+
+/// Decode Base64
+///
+/// Decode data encoded with the standard base64 alphabet. Padding is
+/// optional, but whitespace and the URL-safe alphabet are rejected.
+pub fn from_base64(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    let data = match data {
+        [v @ .., b'=', b'='] | [v @ .., b'='] if data.len().is_multiple_of(4) => v,
+        v => v,
+    };
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut acc = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut v = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let bits = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => return None,
+            };
+            v |= (bits as u32) << (18 - 6 * i);
+        }
+        acc.extend_from_slice(&v.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(acc)
+}
+
+/// Digest Parsing Error
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Error {
+    /// The digest algorithm is not known.
+    Algorithm(String),
+    /// The string is not a well-formed digest.
+    Format,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Algorithm(v) => write!(fmt, "unknown digest algorithm `{}`", v),
+            Error::Format => write!(fmt, "malformed digest"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Digest Algorithm
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Name of the Algorithm
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Digest Size in Bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha384 => 48,
+            Algorithm::Sha512 => 64,
+        }
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "sha1" => Ok(Algorithm::Sha1),
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha384" => Ok(Algorithm::Sha384),
+            "sha512" => Ok(Algorithm::Sha512),
+            v => Err(Error::Algorithm(v.to_owned())),
+        }
+    }
+}
+
+/// Content Digest
+///
+/// A digest in the `<algorithm>:<hex>` notation, as used for checksums of
+/// source items and container images. Only SHA-1, SHA-256, and SHA-512
+/// digests can be computed by this crate, but all algorithms can be
+/// parsed.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Digest {
+    pub algorithm: Algorithm,
+    pub value: Vec<u8>,
+}
+
+impl Digest {
+    /// Compute SHA-256 Digest
+    pub fn sha256(data: &[u8]) -> Self {
+        Self {
+            algorithm: Algorithm::Sha256,
+            value: Sha256::digest(data).to_vec(),
+        }
+    }
+
+    /// Verify Data
+    ///
+    /// Check whether the data matches the digest. Returns `None` if the
+    /// algorithm cannot be computed by this crate.
+    pub fn verify(&self, data: &[u8]) -> Option<bool> {
+        match self.algorithm {
+            Algorithm::Sha1 => Some(Sha1::digest(data)[..] == self.value[..]),
+            Algorithm::Sha256 => Some(Sha256::digest(data)[..] == self.value[..]),
+            Algorithm::Sha512 => Some(Sha512::digest(data)[..] == self.value[..]),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}:{}", self.algorithm.name(), to_hex(&self.value))
+    }
+}
+
+impl std::str::FromStr for Digest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (algorithm, hex) = s.split_once(':').ok_or(Error::Format)?;
+        let algorithm: Algorithm = algorithm.parse()?;
+        match from_hex(hex) {
+            Some(value) if value.len() == algorithm.size() => Ok(Self { algorithm, value }),
+            _ => Err(Error::Format),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify SHA-1
+    #[test]
+    fn verify_sha1() {
+        assert_eq! {
+            to_hex(&Sha1::digest(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+        }
+        assert_eq! {
+            to_hex(&Sha1::digest(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+        }
+        assert_eq! {
+            to_hex(&Sha1::digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+        }
+
+        // Incremental updates match single-shot hashing.
+        let data = [0x71u8; 1000];
+        let mut hasher = Sha1::new();
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), Sha1::digest(&data));
+        assert_eq! {
+            to_hex(&Sha1::digest(&[b'a'; 1000])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba",
+        }
+    }
+
+    // Verify SHA-256
+    #[test]
+    fn verify_sha256() {
+        assert_eq! {
+            to_hex(&Sha256::digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        }
+        assert_eq! {
+            to_hex(&Sha256::digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        }
+        assert_eq! {
+            to_hex(&Sha256::digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        }
+
+        let data = [0x71u8; 1000];
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), Sha256::digest(&data));
+    }
+
+    // Verify SHA-512
+    #[test]
+    fn verify_sha512() {
+        assert_eq! {
+            to_hex(&Sha512::digest(b"")),
+            concat!(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce",
+                "47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+            ),
+        }
+        assert_eq! {
+            to_hex(&Sha512::digest(b"abc")),
+            concat!(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+                "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+        }
+
+        let data = [0x71u8; 1000];
+        let mut hasher = Sha512::new();
+        for chunk in data.chunks(13) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), Sha512::digest(&data));
+    }
+
+    // Verify HMAC-SHA-256
+    #[test]
+    fn verify_hmac() {
+        assert_eq! {
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        }
+        assert_eq! {
+            to_hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+        }
+    }
+
+    // Verify Base64 Encoding
+    #[test]
+    fn verify_base64() {
+        assert_eq!(to_base64(b""), "");
+        assert_eq!(to_base64(b"f"), "Zg==");
+        assert_eq!(to_base64(b"fo"), "Zm8=");
+        assert_eq!(to_base64(b"foo"), "Zm9v");
+        assert_eq!(to_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(to_base64(&[0xfb, 0xff]), "+/8=");
+
+        assert_eq!(from_base64("").unwrap(), b"");
+        assert_eq!(from_base64("Zg==").unwrap(), b"f");
+        assert_eq!(from_base64("Zm8").unwrap(), b"fo");
+        assert_eq!(from_base64("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(from_base64("+/8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(from_base64("Z"), None);
+        assert_eq!(from_base64("Zm9v\n"), None);
+        assert_eq!(from_base64("-_8="), None);
+    }
+
+    // Verify Content Digests
+    #[test]
+    fn verify_digest() {
+        assert_eq!(from_hex("00aBff"), Some(vec![0x00, 0xab, 0xff]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+
+        let digest = Digest::sha256(b"abc");
+        let s = "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(digest.to_string(), s);
+        assert_eq!(s.parse::<Digest>().unwrap(), digest);
+        assert_eq!(digest.verify(b"abc"), Some(true));
+        assert_eq!(digest.verify(b"abd"), Some(false));
+
+        let digest: Digest = format!("sha512:{}", "00".repeat(64)).parse().unwrap();
+        assert_eq!(digest.algorithm, Algorithm::Sha512);
+        assert_eq!(digest.verify(b""), Some(false));
+        let digest: Digest = format!("sha384:{}", "00".repeat(48)).parse().unwrap();
+        assert_eq!(digest.verify(b""), None);
+
+        assert_eq!("sha256:00".parse::<Digest>(), Err(Error::Format));
+        assert_eq!("sha256".parse::<Digest>(), Err(Error::Format));
+        assert_eq! {
+            "md5:00".parse::<Digest>(),
+            Err(Error::Algorithm("md5".to_owned())),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! HTTP Transport
+//!
+//! Resolvers contacting remote services, like container registries or
+//! OSTree remotes, need HTTPS. This crate does not implement TLS, so such
+//! requests are performed by a caller-provided transport, typically backed
+//! by the HTTP client of the application.
+
+/// HTTP Response
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Look Up Header
+    ///
+    /// Return the first header of the given name, compared
+    /// case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP Transport
+///
+/// Performs HTTP GET requests. Errors are returned as human-readable
+/// description. Responses with error status must be returned as
+/// responses, since callers handle them, for instance to answer
+/// authentication challenges.
+///
+/// Transports used for uploads must also implement `request()`, which
+/// performs requests of any method with a body. By default, it only
+/// supports GET requests without body.
+pub trait Transport {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, String>;
+
+    fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, String> {
+        match method {
+            "GET" if body.is_empty() => self.get(url, headers),
+            _ => Err(format!("{} requests are not supported", method)),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Image Information
+//!
+//! The osbuild test tooling inspects built artifacts with `image-info`,
+//! which emits a JSON description of the image: its format, partitions,
+//! installed packages, boot configuration, and system configuration. This
+//! module provides typed deserialization of that output, so verification
+//! suites can assert on image properties directly.
+//!
+//! The output varies between image types and grows over time. Only the
+//! commonly used members are typed, all others are retained verbatim.
+
+use crate::manifest::{Json, Object};
+use crate::packages::Nevra;
+
+/// Image Format
+///
+/// The format of the image file. Older versions of `image-info` report it
+/// as plain string, newer versions as object with additional details.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum ImageFormat {
+    Name(String),
+    Detailed {
+        #[serde(rename = "type")]
+        format: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compat: Option<String>,
+    },
+}
+
+impl ImageFormat {
+    /// Name of the Image Format
+    pub fn name(&self) -> &str {
+        match self {
+            ImageFormat::Name(v) => v,
+            ImageFormat::Detailed { format, .. } => format,
+        }
+    }
+}
+
+/// Partition
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Partition {
+    #[serde(default)]
+    pub bootable: bool,
+    pub start: u64,
+    pub size: u64,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub partition_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partuuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fstype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+}
+
+/// Boot Menu Entry
+///
+/// A boot loader entry, as described by the boot loader specification.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BootEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linux: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+    /// Members not covered by the typed fields.
+    #[serde(flatten)]
+    pub other: Object<Json>,
+}
+
+/// RPM Verification
+///
+/// Files of installed packages that are missing or differ from the
+/// package database, as reported by `rpm --verify`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RpmVerify {
+    #[serde(default)]
+    pub missing: Vec<String>,
+    #[serde(default)]
+    pub changed: Object<String>,
+}
+
+/// Image Information
+///
+/// The root of the `image-info` output.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImageInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_format: Option<ImageFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_table: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_table_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<Partition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bootmenu: Vec<BootEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpm_verify: Option<RpmVerify>,
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub os_release: Object<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services_enabled: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services_disabled: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_target: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fstab: Vec<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Members not covered by the typed fields.
+    #[serde(flatten)]
+    pub other: Object<Json>,
+}
+
+impl ImageInfo {
+    /// Installed Package Identities
+    ///
+    /// Parse the installed packages, which are listed as
+    /// `name-version-release.arch`. Entries that cannot be parsed are
+    /// skipped.
+    pub fn nevras(&self) -> Vec<Nevra> {
+        self.packages
+            .iter()
+            .filter_map(|v| Nevra::parse(&format!("{}.rpm", v)))
+            .collect()
+    }
+
+    /// Check for Installed Package
+    ///
+    /// Check whether a package of the given name is installed.
+    pub fn has_package(&self, name: &str) -> bool {
+        self.nevras().iter().any(|v| v.name == name)
+    }
+
+    /// Find Partition by Mount Point
+    ///
+    /// Look up the partition mounted at the given mount point, by matching
+    /// the fstab entries against the file-system UUIDs and labels of the
+    /// partitions.
+    pub fn partition_of(&self, mountpoint: &str) -> Option<&Partition> {
+        let device = self
+            .fstab
+            .iter()
+            .find(|v| v.get(1).is_some_and(|v| v == mountpoint))?
+            .first()?;
+
+        self.partitions
+            .iter()
+            .find(|p| match device.split_once('=') {
+                Some(("UUID", v)) => p.uuid.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(v)),
+                Some(("LABEL", v)) => p.label.as_deref() == Some(v),
+                Some(("PARTUUID", v)) => p
+                    .partuuid
+                    .as_deref()
+                    .is_some_and(|u| u.eq_ignore_ascii_case(v)),
+                _ => false,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Image Information Parsing
+    #[test]
+    fn verify_parse() {
+        let info: ImageInfo = serde_json::from_str(
+            r#"{
+                "image-format": { "type": "qcow2", "compat": "1.1" },
+                "partition-table": "dos",
+                "partition-table-id": "0x14fc63d2",
+                "partitions": [
+                    {
+                        "bootable": true,
+                        "fstype": "xfs",
+                        "label": "root",
+                        "partuuid": "14fc63d2-01",
+                        "size": 4293918720,
+                        "start": 1048576,
+                        "type": "83",
+                        "uuid": "76a22bf4-f153-4541-b6c7-0332c0dfaeac"
+                    }
+                ],
+                "bootmenu": [
+                    {
+                        "title": "Fedora (5.6.6-300.fc32.x86_64) 32",
+                        "linux": "/boot/vmlinuz-5.6.6-300.fc32.x86_64",
+                        "options": "$kernelopts",
+                        "grub_class": "kernel"
+                    }
+                ],
+                "packages": ["bash-5.0.11-2.fc32.x86_64", "gpg-pubkey-12c944d0-5d5156ab"],
+                "rpm-verify": { "changed": { "/etc/machine-id": ".M......." }, "missing": [] },
+                "os-release": { "NAME": "Fedora", "VERSION_ID": "32" },
+                "services-enabled": ["sshd.service"],
+                "default-target": "multi-user.target",
+                "fstab": [["UUID=76A22BF4-F153-4541-B6C7-0332C0DFAEAC", "/", "xfs", "defaults", "0", "0"]],
+                "timezone": "UTC",
+                "selinux": { "policy": { "SELINUXTYPE": "targeted" } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(info.image_format.as_ref().unwrap().name(), "qcow2");
+        assert_eq!(info.partitions[0].partition_type.as_deref(), Some("83"));
+        assert_eq!(info.bootmenu[0].other["grub_class"], "kernel");
+        assert_eq!(info.os_release["VERSION_ID"], "32");
+        assert!(info.other.contains_key("selinux"));
+        assert!(info.has_package("bash"));
+        assert!(!info.has_package("tmux"));
+        assert_eq!(
+            info.partition_of("/").unwrap().label.as_deref(),
+            Some("root")
+        );
+        assert!(info.partition_of("/boot").is_none());
+
+        let info: ImageInfo = serde_json::from_str(r#"{ "image-format": "raw" }"#).unwrap();
+        assert_eq!(info.image_format.unwrap().name(), "raw");
+    }
+}
@@ -0,0 +1,394 @@
+//! DEFLATE Decompression
+//!
+//! This module implements decompression of DEFLATE streams (RFC 1951) and
+//! the gzip container format (RFC 1952). Corpora of manifests and build
+//! logs are commonly stored gzip-compressed, and this allows reading them
+//! without external dependencies. Compression is not provided.
+//!
+//! The decoder follows the canonical-Huffman approach of zlib's `puff`:
+//! codes are decoded bit by bit against per-length symbol counts, which is
+//! slower than table-driven decoders but small and easy to verify.
+
+/// Decompression Error
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The input ended before the stream was complete.
+    Truncated,
+    /// The stream is malformed, with a description of the defect.
+    Invalid(&'static str),
+    /// The checksum or length in the gzip trailer does not match.
+    Checksum,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Truncated => write!(fmt, "truncated stream"),
+            Error::Invalid(v) => write!(fmt, "invalid stream: {}", v),
+            Error::Checksum => write!(fmt, "checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const MAXBITS: usize = 15;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order of code length codes in dynamic block headers.
+const ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Canonical Huffman code, as symbol counts per code length and symbols
+// ordered by code.
+struct Huffman {
+    counts: [u16; MAXBITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0u16; MAXBITS + 1];
+        for &v in lengths {
+            counts[v as usize] += 1;
+        }
+
+        // Reject over-subscribed codes. Incomplete codes are permitted, as
+        // needed for single-symbol distance codes.
+        let mut left = 1i32;
+        for &v in &counts[1..] {
+            left = (left << 1) - v as i32;
+            if left < 0 {
+                return Err(Error::Invalid("over-subscribed code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAXBITS + 2];
+        for len in 1..=MAXBITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &v) in lengths.iter().enumerate() {
+            if v != 0 {
+                symbols[offsets[v as usize] as usize] = symbol as u16;
+                offsets[v as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    bit: u32,
+    n_bits: u32,
+    output: Vec<u8>,
+}
+
+impl Decoder<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, Error> {
+        let mut v = self.bit;
+        while self.n_bits < n {
+            let byte = *self.input.get(self.pos).ok_or(Error::Truncated)?;
+            self.pos += 1;
+            v |= (byte as u32) << self.n_bits;
+            self.n_bits += 8;
+        }
+        self.bit = v >> n;
+        self.n_bits -= n;
+        Ok(v & ((1u32 << n) - 1))
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAXBITS {
+            code |= self.bits(1)? as i32;
+            let count = h.counts[len] as i32;
+            if code - count < first {
+                return Ok(h.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Invalid("invalid code"))
+    }
+
+    fn stored(&mut self) -> Result<(), Error> {
+        self.bit = 0;
+        self.n_bits = 0;
+        let header = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .ok_or(Error::Truncated)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        if len != !u16::from_le_bytes([header[2], header[3]]) {
+            return Err(Error::Invalid("stored block length mismatch"));
+        }
+        self.pos += 4;
+        let data = self
+            .input
+            .get(self.pos..self.pos + len as usize)
+            .ok_or(Error::Truncated)?;
+        self.output.extend_from_slice(data);
+        self.pos += len as usize;
+        Ok(())
+    }
+
+    fn codes(&mut self, lengths: &Huffman, distances: &Huffman) -> Result<(), Error> {
+        loop {
+            let symbol = self.decode(lengths)? as usize;
+            match symbol {
+                0..=255 => self.output.push(symbol as u8),
+                256 => return Ok(()),
+                _ => {
+                    let i = symbol - 257;
+                    if i >= LENGTH_BASE.len() {
+                        return Err(Error::Invalid("invalid length symbol"));
+                    }
+                    let len = LENGTH_BASE[i] as usize + self.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                    let i = self.decode(distances)? as usize;
+                    if i >= DIST_BASE.len() {
+                        return Err(Error::Invalid("invalid distance symbol"));
+                    }
+                    let dist = DIST_BASE[i] as usize + self.bits(DIST_EXTRA[i] as u32)? as usize;
+                    if dist > self.output.len() {
+                        return Err(Error::Invalid("distance too far back"));
+                    }
+                    let start = self.output.len() - dist;
+                    for j in 0..len {
+                        self.output.push(self.output[start + j]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fixed(&mut self) -> Result<(), Error> {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        let lengths = Huffman::new(&lengths)?;
+        let distances = Huffman::new(&[5; 30])?;
+        self.codes(&lengths, &distances)
+    }
+
+    fn dynamic(&mut self) -> Result<(), Error> {
+        let n_len = self.bits(5)? as usize + 257;
+        let n_dist = self.bits(5)? as usize + 1;
+        let n_code = self.bits(4)? as usize + 4;
+        if n_len > 286 || n_dist > 30 {
+            return Err(Error::Invalid("bad code counts"));
+        }
+
+        let mut lengths = [0u8; 19];
+        for &i in &ORDER[..n_code] {
+            lengths[i] = self.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0u8; n_len + n_dist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let (value, repeat) = match self.decode(&code)? {
+                v @ 0..=15 => (v as u8, 1),
+                16 if i == 0 => return Err(Error::Invalid("repeat without length")),
+                16 => (lengths[i - 1], 3 + self.bits(2)? as usize),
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(Error::Invalid("too many lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(Error::Invalid("missing end-of-block code"));
+        }
+
+        let distances = Huffman::new(&lengths[n_len..])?;
+        let lengths = Huffman::new(&lengths[..n_len])?;
+        self.codes(&lengths, &distances)
+    }
+}
+
+// Decompress a raw DEFLATE stream, returning the output and the number of
+// input bytes consumed.
+fn deflate(input: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let mut d = Decoder {
+        input,
+        pos: 0,
+        bit: 0,
+        n_bits: 0,
+        output: Vec::new(),
+    };
+    loop {
+        let last = d.bits(1)? == 1;
+        match d.bits(2)? {
+            0 => d.stored()?,
+            1 => d.fixed()?,
+            2 => d.dynamic()?,
+            _ => return Err(Error::Invalid("invalid block type")),
+        }
+        if last {
+            return Ok((d.output, d.pos));
+        }
+    }
+}
+
+/// Inflate DEFLATE Stream
+///
+/// Decompress a raw DEFLATE stream. Trailing data after the final block is
+/// ignored.
+pub fn inflate(input: &[u8]) -> Result<Vec<u8>, Error> {
+    deflate(input).map(|v| v.0)
+}
+
+/// CRC-32
+///
+/// Compute the CRC-32 checksum (ISO-HDLC, as used by gzip) of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Check for gzip Data
+///
+/// Return whether the data starts with the gzip magic bytes.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Decompress gzip Data
+///
+/// Decompress data in the gzip format, verifying the checksum and length
+/// of each member. Concatenated members are decompressed in sequence.
+pub fn gunzip(mut input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut acc = Vec::new();
+    loop {
+        if !is_gzip(input) {
+            return Err(Error::Invalid("missing gzip header"));
+        }
+        let header = input.get(..10).ok_or(Error::Truncated)?;
+        if header[2] != 8 {
+            return Err(Error::Invalid("unsupported compression method"));
+        }
+        let flags = header[3];
+        let mut pos = 10;
+        if flags & 0x04 != 0 {
+            let n = input.get(pos..pos + 2).ok_or(Error::Truncated)?;
+            pos += 2 + u16::from_le_bytes([n[0], n[1]]) as usize;
+        }
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                let rest = input.get(pos..).ok_or(Error::Truncated)?;
+                pos += 1 + rest.iter().position(|v| *v == 0).ok_or(Error::Truncated)?;
+            }
+        }
+        if flags & 0x02 != 0 {
+            pos += 2;
+        }
+
+        let (data, n) = deflate(input.get(pos..).ok_or(Error::Truncated)?)?;
+        pos += n;
+        let trailer = input.get(pos..pos + 8).ok_or(Error::Truncated)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&data) || size != data.len() as u32 {
+            return Err(Error::Checksum);
+        }
+        acc.extend_from_slice(&data);
+
+        input = &input[pos + 8..];
+        if input.is_empty() {
+            return Ok(acc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify gzip Decompression
+    #[test]
+    fn verify_gunzip() {
+        // `printf 'hello hello hello\n' | gzip -n`, using fixed codes.
+        let fixed = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00,
+            0x00,
+        ];
+        assert_eq!(gunzip(&fixed).unwrap(), b"hello hello hello\n");
+
+        // A stored block, concatenated with the previous member.
+        let mut stored = vec![
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x02, 0x00, 0xfd,
+            0xff, b'o', b'k',
+        ];
+        stored.extend_from_slice(&crc32(b"ok").to_le_bytes());
+        stored.extend_from_slice(&2u32.to_le_bytes());
+        let mut both = fixed.to_vec();
+        both.extend_from_slice(&stored);
+        assert_eq!(gunzip(&both).unwrap(), b"hello hello hello\nok");
+
+        let mut corrupt = fixed;
+        corrupt[21] ^= 1;
+        assert_eq!(gunzip(&corrupt), Err(Error::Checksum));
+        assert_eq!(gunzip(&fixed[..20]), Err(Error::Truncated));
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    // Verify Dynamic Huffman Codes
+    #[test]
+    fn verify_inflate() {
+        let deflated = [
+            0xa5, 0xcc, 0x3b, 0x0a, 0xc3, 0x30, 0x10, 0x45, 0xd1, 0x3e, 0xab, 0x08, 0x53, 0x1b,
+            0x1b, 0x52, 0x66, 0x2b, 0x21, 0x85, 0x24, 0x2b, 0x8e, 0xb0, 0xec, 0x19, 0xe6, 0x03,
+            0x31, 0xc2, 0x7b, 0x8f, 0xdd, 0xa7, 0x09, 0xd3, 0x3d, 0xde, 0x85, 0xd3, 0x40, 0x37,
+            0xca, 0x70, 0xbf, 0x02, 0xf2, 0xd4, 0xa3, 0x44, 0x2b, 0x75, 0xec, 0x99, 0x16, 0xe8,
+            0x8e, 0x8b, 0xb4, 0xe0, 0x2a, 0x47, 0x6d, 0x40, 0x41, 0xdf, 0xe7, 0x7a, 0xc0, 0x60,
+            0xc2, 0x67, 0x1d, 0xb2, 0x26, 0x78, 0xee, 0xfb, 0xa5, 0xfd, 0x34, 0x2a, 0xa6, 0x50,
+            0xb3, 0x9b, 0x91, 0x5c, 0xcb, 0x6a, 0x1f, 0xb7, 0xf3, 0x12, 0x0d, 0xd1, 0xad, 0x4c,
+            0x6c, 0xf1, 0xe6, 0x56, 0x4c, 0x32, 0x8b, 0x5b, 0x59, 0xe6, 0xb1, 0xb0, 0x5b, 0x49,
+            0x48, 0xdb, 0x1f, 0xc8, 0x17,
+        ];
+        let expected: String = ["rpm", "locale", "selinux", "fstab", "grub2", "users", "mkdir", "copy"]
+            .iter()
+            .map(|v| {
+                format!(
+                    "{{\"type\": \"org.osbuild.{}\", \"options\": {{\"paths\": [\"/usr\", \"/etc\"]}}}}\n",
+                    v
+                )
+            })
+            .collect();
+        assert_eq!(inflate(&deflated).unwrap(), expected.as_bytes());
+        assert_eq!(inflate(&deflated[..60]), Err(Error::Truncated));
+    }
+}
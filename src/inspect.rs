@@ -0,0 +1,633 @@
+//! Manifest Inspection
+//!
+//! This module summarizes manifests: counts of pipelines, stages, and
+//! source items, and the content identifiers of all stages. Stage
+//! identifiers follow the scheme osbuild uses for version-1 manifests: a
+//! SHA-256 over the stage name, the identifier of the build pipeline, the
+//! identifier of the preceding stage, and the stage options, each
+//! serialized as JSON with sorted keys. The identifier of a pipeline is the
+//! identifier of its last stage, so equal identifiers denote equal trees.
+//! Stages of version-2 manifests additionally cover their inputs, devices,
+//! and mounts, and the source epoch of their pipeline.
+//!
+//! The build chain of a manifest can be flattened into a list of pipelines
+//! with their identifiers, and nested again.
+
+use crate::hash;
+use crate::lint;
+use crate::manifest::{
+    self, Build1, Json, Manifest1, Manifest2, Object, Pipeline1, Runner, Sources,
+};
+use crate::packages;
+use std::collections::HashMap;
+
+/// Manifest Statistics
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Stats {
+    /// Number of pipelines, including build pipelines.
+    pub pipelines: usize,
+    /// Number of stages, across all pipelines.
+    pub stages: usize,
+    /// Stage count per stage type.
+    pub stage_types: Object<usize>,
+    /// Name of the assembler, if any.
+    pub assembler: Option<String>,
+    /// Number of items per source type.
+    pub sources: Object<usize>,
+    /// Number of installed packages.
+    pub packages: usize,
+}
+
+/// Collect Statistics
+pub fn stats(manifest: &Manifest1) -> Stats {
+    let mut acc = Stats {
+        pipelines: manifest.pipeline.iter_pipelines_deep().count(),
+        ..Default::default()
+    };
+
+    for (_, stage) in lint::all_stages(manifest) {
+        acc.stages += 1;
+        *acc.stage_types.entry(stage.name.to_string()).or_default() += 1;
+    }
+
+    acc.assembler = manifest
+        .pipeline
+        .assembler
+        .as_ref()
+        .map(|v| v.name.to_string());
+    acc.sources = sources(&manifest.sources);
+    acc.packages = packages::packages(manifest).len();
+
+    acc
+}
+
+/// Collect Statistics of Version-2 Manifests
+///
+/// Collect statistics like `stats()`. Version-2 manifests have no
+/// assembler, and packages are counted as the references of the
+/// `packages` inputs of rpm stages.
+pub fn stats2(manifest: &Manifest2) -> Stats {
+    let mut acc = Stats {
+        pipelines: manifest.pipelines.len(),
+        ..Default::default()
+    };
+
+    for stage in manifest.pipelines.iter().flat_map(|v| v.stages.iter()) {
+        acc.stages += 1;
+        *acc.stage_types.entry(stage.r#type.to_string()).or_default() += 1;
+        if stage.r#type == "org.osbuild.rpm" {
+            acc.packages += match stage
+                .inputs
+                .get("packages")
+                .and_then(|v| v.get("references"))
+            {
+                Some(Json::Object(v)) => v.len(),
+                Some(Json::Array(v)) => v.len(),
+                _ => 0,
+            };
+        }
+    }
+    acc.sources = sources(&manifest.sources);
+
+    acc
+}
+
+// Count the items of each source.
+fn sources(sources: &Sources) -> Object<usize> {
+    let mut acc = Object::new();
+    for (name, source) in sources {
+        let n = source
+            .get("urls")
+            .or_else(|| source.get("items"))
+            .and_then(Json::as_object)
+            .map_or(0, |v| v.len());
+        acc.insert(name.to_string(), n);
+    }
+    acc
+}
+
+// Serialize JSON like Python's `json.dumps(value, sort_keys=True)`.
+fn dumps(value: &Json, acc: &mut String) {
+    match value {
+        Json::Array(v) => {
+            acc.push('[');
+            for (i, v) in v.iter().enumerate() {
+                if i > 0 {
+                    acc.push_str(", ");
+                }
+                dumps(v, acc);
+            }
+            acc.push(']');
+        }
+        Json::Object(v) => {
+            let mut members: Vec<_> = v.iter().collect();
+            members.sort_by(|a, b| a.0.cmp(b.0));
+            acc.push('{');
+            for (i, (k, v)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    acc.push_str(", ");
+                }
+                dumps(&Json::String(k.clone()), acc);
+                acc.push_str(": ");
+                dumps(v, acc);
+            }
+            acc.push('}');
+        }
+        Json::String(v) => {
+            acc.push('"');
+            for c in v.chars() {
+                match c {
+                    '"' => acc.push_str("\\\""),
+                    '\\' => acc.push_str("\\\\"),
+                    '\n' => acc.push_str("\\n"),
+                    '\r' => acc.push_str("\\r"),
+                    '\t' => acc.push_str("\\t"),
+                    '\x08' => acc.push_str("\\b"),
+                    '\x0c' => acc.push_str("\\f"),
+                    c if (c as u32) < 0x20 || (c as u32) > 0x7e => {
+                        let mut buf = [0u16; 2];
+                        for u in c.encode_utf16(&mut buf) {
+                            acc.push_str(&format!("\\u{:04x}", u));
+                        }
+                    }
+                    c => acc.push(c),
+                }
+            }
+            acc.push('"');
+        }
+        v => acc.push_str(&v.to_string()),
+    }
+}
+
+/// Stage Identifier
+///
+/// The content identifier of a stage, formatted as lower-case hexadecimal
+/// SHA-256 digest.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Id(pub [u8; 32]);
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_str(&hash::to_hex(&self.0))
+    }
+}
+
+impl std::str::FromStr for Id {
+    type Err = hash::Error;
+
+    fn from_str(s: &str) -> Result<Self, hash::Error> {
+        hash::from_hex(s)
+            .and_then(|v| v.try_into().ok())
+            .map(Self)
+            .ok_or(hash::Error::Format)
+    }
+}
+
+// Hash the values, each serialized by `dumps()`.
+fn digest(values: impl IntoIterator<Item = Json>) -> Id {
+    let mut h = hash::Sha256::new();
+    for v in values {
+        let mut s = String::new();
+        dumps(&v, &mut s);
+        h.update(s.as_bytes());
+    }
+    Id(h.finalize())
+}
+
+// Values hashed for every stage, regardless of the format version.
+fn stage_values(
+    name: &str,
+    build: Option<&Id>,
+    base: Option<&Id>,
+    options: &Object<Json>,
+) -> Vec<Json> {
+    vec![
+        Json::String(name.to_owned()),
+        build.map_or(Json::Null, |v| Json::String(v.to_string())),
+        base.map_or(Json::Null, |v| Json::String(v.to_string())),
+        manifest::object_to_value(options.clone()),
+    ]
+}
+
+fn stage_id(name: &str, build: Option<&Id>, base: Option<&Id>, options: &Object<Json>) -> Id {
+    digest(stage_values(name, build, base, options))
+}
+
+// Compute the stage identifiers of a pipeline and its build pipelines,
+// returning the identifier of the pipeline.
+fn pipeline_ids(pipeline: &Pipeline1, base: &str, acc: &mut Vec<(String, Id)>) -> Option<Id> {
+    span!("pipeline", path = base);
+    let build = pipeline
+        .build
+        .as_ref()
+        .and_then(|v| pipeline_ids(&v.pipeline, &format!("{}/build/pipeline", base), acc));
+
+    let mut tree: Option<Id> = None;
+    for (path, stage) in lint::stages(pipeline, base) {
+        let id = stage_id(&stage.name, build.as_ref(), tree.as_ref(), &stage.options);
+        event!(Trace, "stage", path = path, id = id);
+        acc.push((path, id));
+        tree = Some(id);
+    }
+    tree
+}
+
+/// Stage Identifiers
+///
+/// Compute the identifiers of all stages of the manifest, returned
+/// together with the JSON pointers of the stages. Build pipelines are
+/// listed first, in execution order.
+pub fn ids(manifest: &Manifest1) -> Vec<(String, Id)> {
+    let mut acc = Vec::new();
+    pipeline_ids(&manifest.pipeline, "/pipeline", &mut acc);
+    acc
+}
+
+// Replace the pipeline references of the inputs with the identifiers of
+// the referenced pipelines. References to unknown or empty pipelines are
+// kept as they are.
+fn resolve_inputs(inputs: &Object<Json>, resolve: &dyn Fn(&str) -> Option<Id>) -> Json {
+    let id = |v: &str| resolve(v).map_or_else(|| v.to_owned(), |v| v.to_string());
+    let mut inputs = manifest::object_to_value(inputs.clone());
+
+    for input in inputs
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|v| v.values_mut())
+    {
+        if input.get("origin").and_then(Json::as_str) != Some("org.osbuild.pipeline") {
+            continue;
+        }
+        match input.get_mut("references") {
+            Some(Json::Array(list)) => {
+                for v in list {
+                    let reference = match v {
+                        Json::Object(v) => v.get_mut("id"),
+                        v => Some(v),
+                    };
+                    if let Some(Json::String(v)) = reference {
+                        *v = id(v);
+                    }
+                }
+            }
+            Some(Json::Object(map)) => {
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .map(|(k, v)| (id(&k), v))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    inputs
+}
+
+/// Stage Identifiers of Version-2 Manifests
+///
+/// Compute the identifiers of all stages of the manifest like `ids()`, in
+/// order of the pipelines. Build pipelines and pipelines referenced by
+/// inputs are identified by their identifiers rather than their names, so
+/// the identifiers of a stage change with the content of those pipelines.
+/// Inputs, devices, mounts, and the source epoch are only hashed if
+/// present, so stages without them share the identifiers of their
+/// version-1 counterparts.
+pub fn ids2(manifest: &Manifest2) -> Vec<(String, Id)> {
+    let mut pipelines: HashMap<&str, Option<Id>> = HashMap::new();
+    let mut acc = Vec::new();
+
+    for (i, pipeline) in manifest.pipelines.iter().enumerate() {
+        let resolve = |v: &str| -> Option<Id> {
+            let name = v.strip_prefix("name:").unwrap_or(v);
+            pipelines.get(name).copied().flatten()
+        };
+        let build = pipeline.build.as_deref().and_then(resolve);
+
+        let mut tree: Option<Id> = None;
+        for (j, stage) in pipeline.stages.iter().enumerate() {
+            let mut values =
+                stage_values(&stage.r#type, build.as_ref(), tree.as_ref(), &stage.options);
+            if !stage.inputs.is_empty() {
+                values.push(resolve_inputs(&stage.inputs, &resolve));
+            }
+            if !stage.devices.is_empty() {
+                values.push(manifest::object_to_value(stage.devices.clone()));
+            }
+            if !stage.mounts.is_empty() {
+                values.push(Json::Array(stage.mounts.clone()));
+            }
+            if let Some(v) = pipeline.source_epoch {
+                values.push(Json::from(v));
+            }
+
+            let id = digest(values);
+            acc.push((format!("/pipelines/{}/stages/{}", i, j), id));
+            tree = Some(id);
+        }
+        pipelines.entry(&pipeline.name).or_insert(tree);
+    }
+    acc
+}
+
+/// Flattened Pipeline
+///
+/// A pipeline of the build chain of a version-1 manifest, without its
+/// build pipeline, as returned by `flatten()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Level {
+    /// Nesting depth: 0 for the pipeline of the manifest, 1 for its build
+    /// pipeline, and so on.
+    pub depth: usize,
+    /// Runner of the build pipeline, `None` for the pipeline of the
+    /// manifest.
+    pub runner: Option<Runner>,
+    /// The pipeline, with `build` cleared.
+    pub pipeline: Pipeline1,
+    /// Identifier of the pipeline, `None` if it has no stages.
+    pub id: Option<Id>,
+}
+
+/// Flatten Build Chain
+///
+/// Flatten the pipeline of the manifest and its nested build pipelines
+/// into a list, in execution order, starting with the innermost build
+/// pipeline. Identifiers are computed as by `ids()`.
+pub fn flatten(manifest: &Manifest1) -> Vec<Level> {
+    let mut acc: Vec<Level> = Vec::new();
+    let mut runners: Vec<Option<&Runner>> = vec![None];
+    let mut next = &manifest.pipeline;
+    while let Some(build) = &next.build {
+        runners.push(Some(&build.runner));
+        next = &build.pipeline;
+    }
+
+    for (i, pipeline) in manifest.pipeline.iter_pipelines_deep().enumerate() {
+        let build = acc.last().and_then(|v| v.id);
+        let mut tree: Option<Id> = None;
+        for stage in &pipeline.stages {
+            tree = Some(stage_id(
+                &stage.name,
+                build.as_ref(),
+                tree.as_ref(),
+                &stage.options,
+            ));
+        }
+
+        let depth = runners.len() - 1 - i;
+        let mut pipeline = pipeline.clone();
+        pipeline.build = None;
+        acc.push(Level {
+            depth,
+            runner: runners[depth].cloned(),
+            pipeline,
+            id: tree,
+        });
+    }
+    acc
+}
+
+/// Unflatten Build Chain
+///
+/// Nest the flattened pipelines into a build chain again, which is the
+/// inverse of `flatten()`. The pipelines are taken in execution order, so
+/// each pipeline becomes the build pipeline of the following one. Depths
+/// and identifiers are ignored, and so are the runner of the last
+/// pipeline and the build pipelines of all pipelines. A missing runner of
+/// a build pipeline is left at its default.
+pub fn unflatten(levels: impl IntoIterator<Item = Level>) -> Pipeline1 {
+    let mut acc: Option<(Pipeline1, Option<Runner>)> = None;
+    for level in levels {
+        let mut pipeline = level.pipeline;
+        pipeline.build = acc.map(|(pipeline, runner)| {
+            let mut build = Build1::default();
+            build.pipeline = pipeline;
+            build.runner = runner.unwrap_or_default();
+            Box::new(build)
+        });
+        acc = Some((pipeline, level.runner));
+    }
+    acc.map(|v| v.0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Inspection
+    #[test]
+    fn verify_inspect() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [{ "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a"] } }]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        { "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a", "sha256:b"] } },
+                        { "name": "org.osbuild.locale", "options": { "language": "en_US" } }
+                    ],
+                    "assembler": { "name": "org.osbuild.tar", "options": { "filename": "root.tar" } }
+                },
+                "sources": {
+                    "org.osbuild.files": { "urls": { "sha256:a": "a", "sha256:b": "b" } }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let stats = stats(&manifest);
+        assert_eq!(stats.pipelines, 2);
+        assert_eq!(stats.stages, 3);
+        assert_eq!(stats.stage_types["org.osbuild.rpm"], 2);
+        assert_eq!(stats.assembler.as_deref(), Some("org.osbuild.tar"));
+        assert_eq!(stats.sources["org.osbuild.files"], 2);
+        assert_eq!(stats.packages, 3);
+
+        let manifest2: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{
+                        "type": "org.osbuild.rpm",
+                        "inputs": { "packages": {
+                            "type": "org.osbuild.files",
+                            "origin": "org.osbuild.source",
+                            "references": { "sha256:a": {}, "sha256:b": {} }
+                        } }
+                    }] },
+                    { "name": "tree", "build": "name:build", "stages": [{ "type": "org.osbuild.noop" }] }
+                ],
+                "sources": {
+                    "org.osbuild.curl": { "items": { "sha256:a": "a", "sha256:b": "b" } }
+                }
+            }"#,
+        )
+        .unwrap();
+        let stats2 = stats2(&manifest2);
+        assert_eq!(stats2.pipelines, 2);
+        assert_eq!(stats2.stages, 2);
+        assert_eq!(stats2.assembler, None);
+        assert_eq!(stats2.sources["org.osbuild.curl"], 2);
+        assert_eq!(stats2.packages, 2);
+
+        let ids = ids(&manifest);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0].0, "/pipeline/build/pipeline/stages/0");
+        assert_eq!(ids[2].0, "/pipeline/stages/1");
+        assert_eq!(
+            ids[0].1,
+            stage_id(
+                "org.osbuild.rpm",
+                None,
+                None,
+                &manifest.pipeline.build.as_ref().unwrap().pipeline.stages[0].options
+            )
+        );
+        assert_eq!(
+            ids[1].1,
+            stage_id(
+                "org.osbuild.rpm",
+                Some(&ids[0].1),
+                None,
+                &manifest.pipeline.stages[0].options
+            )
+        );
+        assert_eq!(
+            ids[2].1,
+            stage_id(
+                "org.osbuild.locale",
+                Some(&ids[0].1),
+                Some(&ids[1].1),
+                &manifest.pipeline.stages[1].options
+            )
+        );
+    }
+
+    // Verify Stage Identifiers of Version-2 Manifests
+    #[test]
+    fn verify_ids2() {
+        let text = r#"{
+            "version": "2",
+            "pipelines": [
+                { "name": "build", "stages": [{ "type": "org.osbuild.noop" }] },
+                { "name": "tree", "build": "name:build", "stages": [
+                    { "type": "org.osbuild.locale", "options": { "language": "en_US" } }
+                ] },
+                { "name": "image", "build": "name:build", "stages": [{
+                    "type": "org.osbuild.tar",
+                    "inputs": { "tree": {
+                        "type": "org.osbuild.tree",
+                        "origin": "org.osbuild.pipeline",
+                        "references": ["name:tree"]
+                    } }
+                }] }
+            ]
+        }"#;
+        let manifest: Manifest2 = text.parse().unwrap();
+        let ids = ids2(&manifest);
+        assert_eq! {
+            ids.iter().map(|v| v.0.as_str()).collect::<Vec<_>>(),
+            ["/pipelines/0/stages/0", "/pipelines/1/stages/0", "/pipelines/2/stages/0"],
+        }
+
+        // Stages without inputs, devices, and mounts are identified like
+        // in version 1.
+        assert_eq!(
+            ids[0].1,
+            stage_id("org.osbuild.noop", None, None, &Object::new())
+        );
+        assert_eq! {
+            ids[1].1,
+            stage_id(
+                "org.osbuild.locale",
+                Some(&ids[0].1),
+                None,
+                &manifest.pipelines[1].stages[0].options,
+            ),
+        }
+
+        // Changes propagate to the stages using the pipeline, but not to
+        // unrelated ones.
+        let changed: Manifest2 = text.replace("en_US", "de_DE").parse().unwrap();
+        let changed = ids2(&changed);
+        assert_eq!(changed[0].1, ids[0].1);
+        assert_ne!(changed[1].1, ids[1].1);
+        assert_ne!(changed[2].1, ids[2].1);
+    }
+
+    // Verify Build Chain Flattening
+    #[test]
+    fn verify_flatten() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "build": {
+                                "pipeline": {},
+                                "runner": "org.osbuild.linux"
+                            },
+                            "stages": [{ "name": "org.osbuild.rpm" }]
+                        },
+                        "runner": "org.osbuild.fedora39"
+                    },
+                    "stages": [{ "name": "org.osbuild.noop" }],
+                    "assembler": { "name": "org.osbuild.tar", "options": { "filename": "root.tar" } }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let levels = flatten(&manifest);
+        assert_eq!(
+            levels.iter().map(|v| v.depth).collect::<Vec<_>>(),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            levels[0].runner.as_ref().unwrap().as_str(),
+            "org.osbuild.linux"
+        );
+        assert_eq!(
+            levels[1].runner.as_ref().unwrap().as_str(),
+            "org.osbuild.fedora39"
+        );
+        assert_eq!(levels[2].runner, None);
+        assert!(levels.iter().all(|v| v.pipeline.build.is_none()));
+        assert_eq!(levels[0].id, None);
+        assert_eq!(
+            levels.iter().filter_map(|v| v.id).collect::<Vec<_>>(),
+            ids(&manifest).into_iter().map(|v| v.1).collect::<Vec<_>>(),
+        );
+
+        assert_eq!(unflatten(levels), manifest.pipeline);
+        assert_eq!(unflatten(Vec::new()), Pipeline1::default());
+    }
+
+    // Verify Python-Compatible Serialization
+    #[test]
+    fn verify_dumps() {
+        let value: Json =
+            serde_json::from_str(r#"{ "b": [1, 2.5, null], "a": "é\n\u0001😀" }"#).unwrap();
+        let mut s = String::new();
+        dumps(&value, &mut s);
+        assert_eq!(
+            s,
+            r#"{"a": "\u00e9\n\u0001\ud83d\ude00", "b": [1, 2.5, null]}"#
+        );
+
+        // The identifier of a lone stage without options is fixed.
+        assert_eq! {
+            stage_id("org.osbuild.noop", None, None, &Object::new()),
+            Id(hash::Sha256::digest(br#""org.osbuild.noop"nullnull{}"#)),
+        }
+
+        let id: Id = "2d3ec2a1e1d5a85d0a3ab7ed78ed45e2a5b0fd1a5e3ec1e0c84e9dc3b1dd9c08"
+            .parse()
+            .unwrap();
+        assert_eq!(id.to_string().parse::<Id>().unwrap(), id);
+        assert_eq!("2d3e".parse::<Id>(), Err(hash::Error::Format));
+    }
+}
@@ -0,0 +1,455 @@
+//! Kickstart Import
+//!
+//! This module imports kickstart files, as used by Anaconda and the
+//! image builders preceding osbuild, to ease migrating them. A subset of
+//! kickstart is translated into a blueprint, which is then compiled into a
+//! manifest skeleton like any other blueprint:
+//!
+//!  * `%packages` sections, with packages and `@groups`
+//!  * `user`, `group`, `rootpw` with encrypted passwords, and `sshkey`
+//!  * `lang`, `keyboard`, `timezone`, `network --hostname`, `services`,
+//!    `firewall`, and `bootloader --append`
+//!  * `part /` sizes
+//!  * `%post` sections, which run as script stages in the image tree
+//!
+//! Commands that only configure the installation, like `url` or `reboot`,
+//! are ignored. Everything else is reported as unsupported, rather than
+//! silently dropped.
+
+use crate::blueprint::{self, Blueprint, Customizations};
+use crate::compile::{self, Compiled, Target};
+use crate::manifest::{Json, Stage1};
+
+/// Kickstart Error
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The line is not valid kickstart.
+    Syntax(usize, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Syntax(line, v) => write!(fmt, "line {}: {}", line, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Imported Kickstart
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Kickstart {
+    pub blueprint: Blueprint,
+    /// Bodies of the `%post` sections, with interpreter lines added.
+    pub post: Vec<String>,
+    /// Commands and sections that were not imported.
+    pub unsupported: Vec<String>,
+}
+
+// Commands that configure the installation rather than the installed
+// system. They have no equivalent in a manifest.
+const IGNORED: &[&str] = &[
+    "autopart",
+    "cdrom",
+    "clearpart",
+    "cmdline",
+    "eula",
+    "firstboot",
+    "graphical",
+    "halt",
+    "ignoredisk",
+    "install",
+    "poweroff",
+    "reboot",
+    "repo",
+    "reqpart",
+    "shutdown",
+    "skipx",
+    "text",
+    "url",
+    "zerombr",
+];
+
+// Split a line into words, honoring quotes and backslash escapes.
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut acc = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => acc.extend(word.take()),
+            '#' if word.is_none() => break,
+            '\'' | '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(v) if v == c => break,
+                        Some('\\') if c == '"' => word.extend(chars.next()),
+                        Some(v) => word.push(v),
+                        None => return Err("unterminated quote".to_owned()),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    acc.extend(word);
+    Ok(acc)
+}
+
+// Split the arguments of a command into `--option[=value]` pairs and
+// positional arguments.
+fn options(args: &[String]) -> (Vec<(&str, Option<&str>)>, Vec<&str>) {
+    let mut acc = (Vec::new(), Vec::new());
+    for arg in args {
+        match arg.strip_prefix("--") {
+            Some(v) => match v.split_once('=') {
+                Some((k, v)) => acc.0.push((k, Some(v))),
+                None => acc.0.push((v, None)),
+            },
+            None => acc.1.push(arg.as_str()),
+        }
+    }
+    acc
+}
+
+fn list(v: &str) -> Vec<String> {
+    v.split(',')
+        .filter(|v| !v.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn number(line: usize, key: &str, v: Option<&str>) -> Result<u64, Error> {
+    v.and_then(|v| v.parse().ok())
+        .ok_or_else(|| Error::Syntax(line, format!("invalid --{}", key)))
+}
+
+// Apply a command to the kickstart, returning whether it is supported.
+fn command(ks: &mut Kickstart, line: usize, words: &[String]) -> Result<bool, Error> {
+    let (options, args) = options(&words[1..]);
+    let c: &mut Customizations = ks
+        .blueprint
+        .customizations
+        .get_or_insert_with(Default::default);
+    let option = |key: &str| options.iter().find(|v| v.0 == key).map(|v| v.1);
+
+    match words[0].as_str() {
+        "lang" => {
+            let locale = c.locale.get_or_insert_with(Default::default);
+            locale
+                .languages
+                .extend(args.first().map(|v| (*v).to_owned()));
+        }
+        "keyboard" => {
+            let keymap = option("vckeymap").flatten().or(args.first().copied());
+            c.locale.get_or_insert_with(Default::default).keyboard = keymap.map(str::to_owned);
+        }
+        "timezone" => {
+            let timezone = c.timezone.get_or_insert_with(Default::default);
+            timezone.timezone = args.first().map(|v| (*v).to_owned());
+            if let Some(Some(v)) = option("ntpservers") {
+                timezone.ntpservers = list(v);
+            }
+        }
+        "network" => match option("hostname") {
+            Some(Some(v)) => c.hostname = Some(v.to_owned()),
+            _ => return Ok(false),
+        },
+        "services" => {
+            let services = c.services.get_or_insert_with(Default::default);
+            for (key, value) in &options {
+                match (*key, value) {
+                    ("enabled", Some(v)) => services.enabled.extend(list(v)),
+                    ("disabled", Some(v)) => services.disabled.extend(list(v)),
+                    _ => return Ok(false),
+                }
+            }
+        }
+        "firewall" => {
+            let firewall = c.firewall.get_or_insert_with(Default::default);
+            for (key, value) in &options {
+                match (*key, value) {
+                    ("enabled", None) => {}
+                    ("port", Some(v)) => firewall.ports.extend(list(v)),
+                    ("service", Some(v)) => {
+                        let services = firewall.services.get_or_insert_with(Default::default);
+                        services.enabled.extend(list(v));
+                    }
+                    _ => return Ok(false),
+                }
+            }
+        }
+        "bootloader" => match option("append") {
+            Some(Some(v)) => {
+                c.kernel.get_or_insert_with(Default::default).append = Some(v.to_owned());
+            }
+            _ => return Ok(false),
+        },
+        "part" => {
+            if args.first() != Some(&"/") {
+                return Ok(false);
+            }
+            // Sizes are given in MiB.
+            let size = number(line, "size", option("size").flatten())?;
+            c.filesystem.push(blueprint::Filesystem {
+                mountpoint: "/".to_owned(),
+                minsize: Some(Json::from(size << 20)),
+            });
+        }
+        "rootpw" => {
+            // Only encrypted passwords can be embedded in the image.
+            if option("iscrypted").is_none() || args.len() != 1 {
+                return Ok(false);
+            }
+            c.user.push(blueprint::User {
+                name: "root".to_owned(),
+                password: Some(args[0].to_owned()),
+                ..Default::default()
+            });
+        }
+        "user" => {
+            let mut user = blueprint::User::default();
+            for (key, value) in &options {
+                match (*key, value) {
+                    ("name", Some(v)) => user.name = (*v).to_owned(),
+                    ("password", Some(v)) => user.password = Some((*v).to_owned()),
+                    ("iscrypted", None) => {}
+                    ("groups", Some(v)) => user.groups = list(v),
+                    ("homedir", Some(v)) => user.home = Some((*v).to_owned()),
+                    ("shell", Some(v)) => user.shell = Some((*v).to_owned()),
+                    ("gecos", Some(v)) => user.description = Some((*v).to_owned()),
+                    ("uid", v) => user.uid = Some(number(line, key, *v)?),
+                    ("gid", v) => user.gid = Some(number(line, key, *v)?),
+                    _ => return Ok(false),
+                }
+            }
+            if user.name.is_empty() {
+                return Err(Error::Syntax(line, "user without --name".to_owned()));
+            }
+            if user.password.is_some() && option("iscrypted").is_none() {
+                return Ok(false);
+            }
+            c.user.push(user);
+        }
+        "group" => {
+            let name = match option("name") {
+                Some(Some(v)) => v.to_owned(),
+                _ => return Err(Error::Syntax(line, "group without --name".to_owned())),
+            };
+            let gid = match option("gid") {
+                Some(v) => Some(number(line, "gid", v)?),
+                None => None,
+            };
+            c.group.push(blueprint::Group { name, gid });
+        }
+        "sshkey" => match (option("username"), args.as_slice()) {
+            (Some(Some(user)), [key]) => c.sshkey.push(blueprint::SshKey {
+                user: user.to_owned(),
+                key: (*key).to_owned(),
+            }),
+            _ => return Err(Error::Syntax(line, "invalid sshkey".to_owned())),
+        },
+        v => return Ok(IGNORED.contains(&v)),
+    }
+    Ok(true)
+}
+
+/// Parse Kickstart
+///
+/// Import the kickstart file as blueprint named `name`, with its `%post`
+/// sections and the list of unsupported commands.
+pub fn parse(name: &str, text: &str) -> Result<Kickstart, Error> {
+    let mut ks = Kickstart::default();
+    ks.blueprint.name = name.to_owned();
+    let mut lines = text.lines().enumerate().map(|(i, v)| (i + 1, v));
+
+    while let Some((n, line)) = lines.next() {
+        let words = split(line).map_err(|e| Error::Syntax(n, e))?;
+        let Some(first) = words.first() else {
+            continue;
+        };
+
+        if let Some(section) = first.strip_prefix('%') {
+            let mut body = Vec::new();
+            loop {
+                match lines.next() {
+                    Some((_, v)) if v.trim() == "%end" => break,
+                    Some((_, v)) => body.push(v),
+                    None => return Err(Error::Syntax(n, format!("unterminated %{}", section))),
+                }
+            }
+            let (options, _) = options(&words[1..]);
+
+            match section {
+                "packages" => {
+                    for line in body {
+                        let Some(name) = split(line).ok().and_then(|v| v.into_iter().next()) else {
+                            continue;
+                        };
+                        if let Some(group) = name.strip_prefix('@') {
+                            ks.blueprint.groups.push(blueprint::PackageGroup {
+                                name: group.to_owned(),
+                            });
+                        } else if name.starts_with('-') {
+                            ks.unsupported.push(format!("%packages {}", name));
+                        } else {
+                            ks.blueprint.packages.push(blueprint::Package {
+                                name,
+                                version: None,
+                            });
+                        }
+                    }
+                }
+                "post" if options.iter().all(|v| v.0 == "interpreter" || v.0 == "log") => {
+                    let mut script = String::new();
+                    if let Some((_, Some(v))) = options.iter().find(|v| v.0 == "interpreter") {
+                        script = format!("#!{}\n", v);
+                    }
+                    for line in body {
+                        script += line;
+                        script += "\n";
+                    }
+                    ks.post.push(script);
+                }
+                _ => ks.unsupported.push(line.trim().to_owned()),
+            }
+            continue;
+        }
+
+        if !command(&mut ks, n, &words)? {
+            ks.unsupported.push(line.trim().to_owned());
+        }
+    }
+
+    if ks.blueprint.customizations == Some(Default::default()) {
+        ks.blueprint.customizations = None;
+    }
+    Ok(ks)
+}
+
+impl Kickstart {
+    /// Compile Kickstart
+    ///
+    /// Compile the imported blueprint for the target, and run the `%post`
+    /// sections as `org.osbuild.script` stages right before the SELinux
+    /// labels are applied. Unsupported kickstart commands are added to the
+    /// unsupported customizations.
+    pub fn compile(&self, target: &Target) -> Result<Compiled, compile::Error> {
+        let mut compiled = compile::compile(&self.blueprint, target)?;
+        let stages = &mut compiled.manifest.pipeline.stages;
+        let at = stages
+            .iter()
+            .rposition(|v| v.name == "org.osbuild.selinux")
+            .unwrap_or(stages.len());
+        let scripts = self.post.iter().map(|v| {
+            let mut stage = Stage1::default();
+            stage.name = "org.osbuild.script".into();
+            stage
+                .options
+                .insert("script".to_owned(), Json::from(v.as_str()));
+            stage
+        });
+        stages.splice(at..at, scripts);
+        compiled
+            .unsupported
+            .extend(self.unsupported.iter().cloned());
+        Ok(compiled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Kickstart Import
+    #[test]
+    fn verify_kickstart() {
+        let ks = parse(
+            "base",
+            r#"
+            # Installation
+            text
+            url --url=https://example.com/os
+            lang en_US.UTF-8
+            keyboard --vckeymap=de
+            timezone Europe/Berlin --ntpservers=a.example.com,b.example.com
+            network --bootproto=dhcp --hostname=base.example.com
+            rootpw --iscrypted $6$salt$hash
+            user --name=admin --groups=wheel --uid=1000 --gecos="The Admin"
+            sshkey --username=admin "ssh-ed25519 AAAA admin"
+            services --enabled=sshd,chronyd --disabled=kdump
+            firewall --enabled --service=ssh --port=8080:tcp
+            bootloader --location=mbr --append="console=ttyS0"
+            part / --size=8192 --fstype=xfs
+            part swap --size=1024
+            selinux --permissive
+
+            %packages --excludedocs
+            @core
+            tmux
+            -plymouth
+            %end
+
+            %post --interpreter=/bin/bash
+            echo done > /etc/motd
+            %end
+
+            %pre
+            true
+            %end
+            "#,
+        )
+        .unwrap();
+
+        let c = ks.blueprint.customizations.as_ref().unwrap();
+        assert_eq!(c.hostname.as_deref(), Some("base.example.com"));
+        assert_eq!(c.locale.as_ref().unwrap().keyboard.as_deref(), Some("de"));
+        assert_eq!(c.timezone.as_ref().unwrap().ntpservers.len(), 2);
+        assert_eq!(c.user[0].password.as_deref(), Some("$6$salt$hash"));
+        assert_eq!(c.user[1].description.as_deref(), Some("The Admin"));
+        assert_eq!(c.user[1].uid, Some(1000));
+        assert_eq!(c.sshkey[0].key, "ssh-ed25519 AAAA admin");
+        assert_eq!(c.services.as_ref().unwrap().disabled, ["kdump"]);
+        assert_eq!(c.firewall.as_ref().unwrap().ports, ["8080:tcp"]);
+        assert_eq!(c.filesystem[0].minsize, Some(Json::from(8u64 << 30)));
+        assert_eq!(ks.blueprint.groups[0].name, "core");
+        assert_eq!(ks.blueprint.packages[0].name, "tmux");
+        assert_eq!(
+            ks.post,
+            ["#!/bin/bash\n            echo done > /etc/motd\n"]
+        );
+        assert_eq! {
+            ks.unsupported,
+            [
+                "part swap --size=1024",
+                "selinux --permissive",
+                "%packages -plymouth",
+                "%pre",
+            ],
+        }
+
+        let compiled = ks
+            .compile(&Target {
+                distro: "fedora-39".to_owned(),
+                arch: "x86_64".to_owned(),
+                image_type: compile::ImageType::Qcow2,
+            })
+            .unwrap();
+        let stages = &compiled.manifest.pipeline.stages;
+        assert_eq!(stages[stages.len() - 2].name, "org.osbuild.script");
+        assert_eq!(stages[stages.len() - 1].name, "org.osbuild.selinux");
+        assert!(compiled.unsupported.contains(&"%pre".to_owned()));
+
+        assert_eq! {
+            parse("base", "user --uid=1000").unwrap_err(),
+            Error::Syntax(1, "user without --name".to_owned()),
+        }
+        assert_eq! {
+            parse("base", "\n%post\ntrue").unwrap_err(),
+            Error::Syntax(2, "unterminated %post".to_owned()),
+        }
+    }
+}
@@ -3,5 +3,235 @@
 //! The r-osbuild project implements the osbuild manifest format in Rust,
 //! allowing Rust programs access to the osbuild pipeline-based build system
 //! for operating system artifacts.
+//!
+//! The data model of the manifest format has no dependencies. The `serde`
+//! feature adds serialization support to it, and the `json` feature adds
+//! JSON support together with the tooling built on top of it. Larger
+//! subsystems have features of their own, each of which implies `json`:
+//!
+//!  * `blueprint`: blueprints, their compilation to manifests, kickstart
+//!    import, and the composer clients (`blueprint`, `compile`,
+//!    `composer`, `kickstart`)
+//!  * `distro`: profiles of common distributions, generating images from
+//!    blueprints (`distro`), implying `blueprint` and `exec`
+//!  * `exec`: the client of the external dependency solver (`dnfjson`)
+//!  * `fetch`: resolvers of remote content via an HTTP transport, and the
+//!    container tooling built on them (`bootc`, `container`,
+//!    `containerfile`, `ostree`)
+//!  * `mpp`: the manifest preprocessor (`mpp`)
+//!  * `schema`: JSON schemas of the manifest formats (`schema`)
+//!  * `upload`: uploads of artifacts to cloud image stores (`upload`)
+//!
+//! Integrations between subsystems are available if both features are
+//! enabled, like resolving blueprint containers with `blueprint` and
+//! `fetch`, or solving `mpp-depsolve` directives via `dnfjson` with `exec`
+//! and `mpp`. All of these features are enabled by default.
+//!
+//! The `gzip` feature provides the gzip codec (`inflate`), which is used
+//! to read and write compressed manifests (`compress`). It only requires
+//! `std` and is enabled by default as well. Gzip is the only supported
+//! codec: xz and zstd input is detected, but rejected with an error.
+//!
+//! The `cbor` and `msgpack` features add binary serialization of manifests
+//! as CBOR (`cbor`) and MessagePack (`msgpack`). They are not enabled by
+//! default.
+//!
+//! The `trace` feature instruments parsing, validation, and fetches with
+//! spans and events for a tracing subscriber (`trace`). It is not enabled
+//! by default. Neither is the `test-util` feature, which provides snapshot
+//! assertions for tests of manifest generators (`test_util`), and loaders
+//! of manifest corpora for parameterized tests and benchmarks
+//! (`fixtures`).
+//!
+//! Without the `std` feature, the crate is `no_std` and only requires
+//! `alloc`, providing the manifest types of the `manifest` module. The
+//! `serde` feature can be combined with this. All other features imply
+//! `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// The `cdylib` crate type needs a panic handler and an allocator, which
+// `std` provides wherever it is available. Only freestanding targets lack
+// it, and they do not support `cdylib` anyway.
+#[cfg(all(not(feature = "std"), not(target_os = "none")))]
+extern crate std;
+
+// Enter a span of the `trace` module until the end of the scope, with the
+// given fields. Without the `trace` feature, this expands to nothing.
+#[allow(unused_macros)]
+macro_rules! span {
+    ($name:literal $(, $key:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "trace")]
+        let _span = crate::trace::Span::enter($name, || {
+            vec![$((stringify!($key), ($value).to_string())),*]
+        });
+    };
+}
+
+// Record an event of the `trace` module, with the given level and fields.
+// Without the `trace` feature, this expands to nothing.
+#[allow(unused_macros)]
+macro_rules! event {
+    ($level:ident, $message:literal $(, $key:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "trace")]
+        crate::trace::event(crate::trace::Level::$level, $message, || {
+            vec![$((stringify!($key), ($value).to_string())),*]
+        });
+    };
+}
 
+#[cfg(feature = "json")]
+pub mod anonymize;
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+#[cfg(feature = "json")]
+pub mod audit;
+#[cfg(feature = "blueprint")]
+pub mod blueprint;
+#[cfg(feature = "fetch")]
+pub mod bootc;
+#[cfg(feature = "json")]
+pub mod borrowed;
+#[cfg(feature = "json")]
+pub mod browse;
+#[cfg(feature = "json")]
+pub mod bundle;
+#[cfg(feature = "json")]
+pub mod capabilities;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "json")]
+pub mod catalog;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "blueprint")]
+pub mod compile;
+#[cfg(feature = "blueprint")]
+pub mod composer;
+#[cfg(feature = "json")]
+pub mod compress;
+#[cfg(feature = "fetch")]
+pub mod container;
+#[cfg(feature = "fetch")]
+pub mod containerfile;
+#[cfg(feature = "json")]
+pub mod convert;
+#[cfg(feature = "json")]
+pub mod diff;
+#[cfg(feature = "distro")]
+pub mod distro;
+#[cfg(feature = "exec")]
+pub mod dnfjson;
+#[cfg(feature = "json")]
+pub mod document;
+#[cfg(feature = "json")]
+pub mod dsse;
+#[cfg(feature = "json")]
+pub mod error;
+#[cfg(feature = "json")]
+pub mod estimate;
+#[cfg(feature = "json")]
+pub mod events;
+#[cfg(feature = "json")]
+pub mod export;
+#[cfg(feature = "test-util")]
+pub mod fixtures;
+#[cfg(feature = "std")]
+pub mod gvariant;
+#[cfg(feature = "std")]
+pub mod hash;
+#[cfg(feature = "std")]
+pub mod http;
+#[cfg(feature = "json")]
+pub mod image_info;
+#[cfg(feature = "gzip")]
+pub mod inflate;
+#[cfg(feature = "json")]
+pub mod inspect;
+#[cfg(feature = "blueprint")]
+pub mod kickstart;
+#[cfg(feature = "json")]
+pub mod limits;
+#[cfg(feature = "json")]
+pub mod lint;
+#[cfg(feature = "json")]
+pub mod lsp;
 pub mod manifest;
+#[cfg(feature = "json")]
+pub mod merge;
+#[cfg(feature = "json")]
+pub mod metrics;
+#[cfg(feature = "json")]
+pub mod minimize;
+#[cfg(feature = "mpp")]
+pub mod mpp;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "json")]
+pub mod multiarch;
+#[cfg(feature = "json")]
+pub mod normalize;
+#[cfg(feature = "json")]
+pub mod oci;
+#[cfg(feature = "json")]
+pub mod options;
+#[cfg(feature = "fetch")]
+pub mod ostree;
+#[cfg(feature = "json")]
+pub mod packages;
+#[cfg(feature = "json")]
+pub mod provenance;
+#[cfg(feature = "json")]
+pub mod query;
+#[cfg(feature = "json")]
+pub mod redact;
+#[cfg(feature = "json")]
+pub mod rest;
+pub mod runner;
+#[cfg(feature = "json")]
+pub mod sbom;
+#[cfg(feature = "json")]
+pub mod schedule;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "json")]
+pub mod serialize;
+#[cfg(feature = "json")]
+pub mod service;
+#[cfg(feature = "json")]
+pub mod sidecar;
+#[cfg(feature = "json")]
+pub mod signature;
+#[cfg(feature = "sigstore")]
+pub mod sigstore;
+#[cfg(feature = "json")]
+pub mod sources;
+#[cfg(feature = "json")]
+pub mod split;
+#[cfg(feature = "json")]
+pub mod store;
+#[cfg(feature = "json")]
+pub mod stream;
+pub mod symbol;
+#[cfg(feature = "json")]
+pub mod template;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "json")]
+pub mod toml;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(feature = "json")]
+pub mod track;
+#[cfg(feature = "upload")]
+pub mod upload;
+#[cfg(feature = "std")]
+pub mod uuid;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "json")]
+pub mod watch;
+#[cfg(feature = "json")]
+pub mod yaml;
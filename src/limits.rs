@@ -0,0 +1,192 @@
+//! Resource Limits
+//!
+//! Services that accept manifests from untrusted users must bound the
+//! resources a single manifest can claim. This module checks manifests
+//! against configurable limits on their size, the nesting of their build
+//! pipelines, and the number of their pipelines, stages, source items, and
+//! inline payload bytes. The size is checked before parsing, everything else
+//! right after, before the manifest is processed any further. JSON nesting
+//! itself is bounded by the parser.
+
+use crate::error::Error;
+use crate::manifest::{Json, Manifest, ManifestFormat};
+use std::collections::HashMap;
+
+/// Resource Limits
+///
+/// The defaults suit interactive services and are well above the needs of
+/// real-world manifests.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Limits {
+    /// Maximum size of the manifest text, in bytes.
+    pub size: usize,
+    /// Maximum number of nested build pipelines below any pipeline.
+    pub depth: usize,
+    /// Maximum number of pipelines.
+    pub pipelines: usize,
+    /// Maximum number of stages, including assemblers.
+    pub stages: usize,
+    /// Maximum number of source items.
+    pub sources: usize,
+    /// Maximum number of bytes of inline source data.
+    pub inline: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            size: 16 << 20,
+            depth: 8,
+            pipelines: 256,
+            stages: 1024,
+            sources: 65536,
+            inline: 8 << 20,
+        }
+    }
+}
+
+fn exceeds(limit: usize, unit: &str) -> Error {
+    Error::Limit {
+        limit,
+        unit: unit.to_owned(),
+    }
+}
+
+impl Limits {
+    /// Check Manifest
+    ///
+    /// Check a parsed manifest of any version against all limits but the
+    /// size.
+    pub fn check(&self, manifest: &dyn ManifestFormat) -> Result<(), Error> {
+        let pipelines = manifest.pipelines();
+        if pipelines.len() > self.pipelines {
+            return Err(exceeds(self.pipelines, "pipelines"));
+        }
+
+        let stages: usize = pipelines.iter().map(|v| v.stages.len()).sum();
+        if stages > self.stages {
+            return Err(exceeds(self.stages, "stages"));
+        }
+
+        // Resolve build pipelines by name, once. If names are duplicated,
+        // the first pipeline of a name is used.
+        let mut names: HashMap<&str, usize> = HashMap::with_capacity(pipelines.len());
+        for (i, pipeline) in pipelines.iter().enumerate() {
+            names.entry(pipeline.name.as_str()).or_insert(i);
+        }
+
+        for pipeline in &pipelines {
+            let mut depth = 0;
+            let mut build = pipeline.build.as_deref();
+            while let Some(name) = build {
+                depth += 1;
+                if depth > self.depth {
+                    return Err(exceeds(self.depth, "nested build pipelines"));
+                }
+                build = names.get(name).and_then(|v| pipelines[*v].build.as_deref());
+            }
+        }
+
+        let mut items = 0;
+        let mut inline = 0;
+        for (name, source) in manifest.sources() {
+            for key in ["items", "urls"] {
+                if let Some(Json::Object(v)) = source.get(key) {
+                    items += v.len();
+                    if name == "org.osbuild.inline" {
+                        inline += v
+                            .values()
+                            .filter_map(|v| v.get("data")?.as_str())
+                            .map(str::len)
+                            .sum::<usize>();
+                    }
+                }
+            }
+        }
+        if items > self.sources {
+            return Err(exceeds(self.sources, "source items"));
+        }
+        if inline > self.inline {
+            return Err(exceeds(self.inline, "bytes of inline data"));
+        }
+
+        Ok(())
+    }
+
+    /// Parse Manifest within Limits
+    ///
+    /// Parse a manifest of either format version, and fail if it exceeds
+    /// any of the limits.
+    pub fn parse(&self, text: &str) -> Result<Manifest, Error> {
+        if text.len() > self.size {
+            return Err(exceeds(self.size, "bytes"));
+        }
+        let manifest = Manifest::parse(text)?;
+        self.check(&manifest)?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Resource Limits
+    #[test]
+    fn verify_limits() {
+        let text = r#"{
+            "pipeline": {
+                "build": {
+                    "pipeline": {
+                        "build": { "pipeline": {}, "runner": "org.osbuild.linux" },
+                        "stages": [{ "name": "org.osbuild.rpm" }]
+                    },
+                    "runner": "org.osbuild.linux"
+                },
+                "stages": [{ "name": "org.osbuild.noop" }],
+                "assembler": { "name": "org.osbuild.tar" }
+            },
+            "sources": {
+                "org.osbuild.inline": { "items": { "sha256:a": { "encoding": "base64", "data": "YWJj" } } }
+            }
+        }"#;
+        let limits = Limits::default();
+        assert!(limits.parse(text).is_ok());
+
+        let check = |limits: Limits| limits.parse(text).unwrap_err().to_string();
+        assert_eq! {
+            check(Limits { size: 16, ..limits }),
+            "manifest exceeds the limit of 16 bytes",
+        }
+        assert_eq! {
+            check(Limits { depth: 1, ..limits }),
+            "manifest exceeds the limit of 1 nested build pipelines",
+        }
+        assert_eq! {
+            check(Limits { pipelines: 3, ..limits }),
+            "manifest exceeds the limit of 3 pipelines",
+        }
+        assert_eq! {
+            check(Limits { stages: 2, ..limits }),
+            "manifest exceeds the limit of 2 stages",
+        }
+        assert_eq! {
+            check(Limits { sources: 0, ..limits }),
+            "manifest exceeds the limit of 0 source items",
+        }
+        assert_eq! {
+            check(Limits { inline: 3, ..limits }),
+            "manifest exceeds the limit of 3 bytes of inline data",
+        }
+        assert!(Limits {
+            depth: 2,
+            pipelines: 4,
+            stages: 3,
+            sources: 1,
+            inline: 4,
+            ..limits
+        }
+        .parse(text)
+        .is_ok());
+    }
+}
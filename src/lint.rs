@@ -0,0 +1,257 @@
+//! Manifest Linting
+//!
+//! This module provides checks on manifests that go beyond what the format
+//! itself enforces. The individual checks are grouped into submodules and all
+//! report their findings as `Diagnostic` values, so results of different
+//! checks can be combined, filtered, and reported uniformly.
+//!
+//! Diagnostics refer to the offending part of a manifest via JSON pointers
+//! (RFC-6901) relative to the root of the manifest.
+//!
+//! Checks operate on the stages of a manifest, as provided by `Target`, so
+//! they apply to manifests of all versions.
+
+use crate::manifest::{Manifest, Manifest1, Manifest2, Pipeline1, Stage1, StageRef};
+use std::collections::HashSet;
+
+pub mod cache;
+pub mod compliance;
+pub mod deprecated;
+pub mod embedded;
+pub mod fix;
+pub mod identifiers;
+pub mod paths;
+pub mod policy;
+pub mod reproducibility;
+pub mod sarif;
+
+/// Diagnostic Severity
+///
+/// The severity of a diagnostic describes how the reported issue should be
+/// treated. Severities are ordered, with `Error` being the most severe.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+/// Diagnostic Report
+///
+/// A diagnostic describes a single finding of a check. It carries the name
+/// of the rule that produced it, its severity, a human-readable message, and
+/// a JSON pointer to the part of the manifest it refers to.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Diagnostic {
+    pub rule: String,
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create New Diagnostic
+    pub fn new(rule: &str, severity: Severity, path: &str, message: &str) -> Self {
+        Self {
+            rule: rule.to_owned(),
+            severity,
+            path: path.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// Lint Target
+///
+/// The view of a manifest the checks operate on: its stages together with
+/// their JSON pointers. This is implemented by all manifest versions.
+pub trait Target {
+    /// All stages of the manifest, including those of build pipelines.
+    /// Build pipelines are listed first, in execution order.
+    fn stages(&self) -> Vec<(String, StageRef<'_>)>;
+    /// Stages of the pipelines that end up in artifacts. Pipelines only
+    /// used as build pipelines are excluded.
+    fn tree_stages(&self) -> Vec<(String, StageRef<'_>)>;
+    /// JSON pointer at which stages missing from `tree_stages()` are
+    /// reported.
+    fn tree(&self) -> &'static str;
+    /// Assembler of version-1 manifests, with its JSON pointer.
+    fn assembler(&self) -> Option<(String, StageRef<'_>)> {
+        None
+    }
+}
+
+fn stage_ref(stage: &Stage1) -> StageRef<'_> {
+    StageRef {
+        name: &stage.name,
+        options: &stage.options,
+    }
+}
+
+impl Target for Manifest1 {
+    fn stages(&self) -> Vec<(String, StageRef<'_>)> {
+        all_stages(self)
+            .into_iter()
+            .map(|(p, v)| (p, stage_ref(v)))
+            .collect()
+    }
+
+    fn tree_stages(&self) -> Vec<(String, StageRef<'_>)> {
+        stages(&self.pipeline, "/pipeline")
+            .map(|(p, v)| (p, stage_ref(v)))
+            .collect()
+    }
+
+    fn tree(&self) -> &'static str {
+        "/pipeline/stages"
+    }
+
+    fn assembler(&self) -> Option<(String, StageRef<'_>)> {
+        self.pipeline.assembler.as_ref().map(|v| {
+            let stage = StageRef {
+                name: &v.name,
+                options: &v.options,
+            };
+            ("/pipeline/assembler".to_owned(), stage)
+        })
+    }
+}
+
+// Collect the stages of all pipelines of a version-2 manifest whose names
+// match the filter, together with their JSON pointers.
+fn stages2(manifest: &Manifest2, filter: impl Fn(&str) -> bool) -> Vec<(String, StageRef<'_>)> {
+    let mut acc = Vec::new();
+    for (i, pipeline) in manifest.pipelines.iter().enumerate() {
+        if !filter(&pipeline.name) {
+            continue;
+        }
+        for (j, v) in pipeline.stages.iter().enumerate() {
+            let stage = StageRef {
+                name: &v.r#type,
+                options: &v.options,
+            };
+            acc.push((format!("/pipelines/{}/stages/{}", i, j), stage));
+        }
+    }
+    acc
+}
+
+impl Target for Manifest2 {
+    fn stages(&self) -> Vec<(String, StageRef<'_>)> {
+        stages2(self, |_| true)
+    }
+
+    fn tree_stages(&self) -> Vec<(String, StageRef<'_>)> {
+        let builds: HashSet<&str> = self
+            .pipelines
+            .iter()
+            .filter_map(|v| v.build.as_deref())
+            .map(|v| v.strip_prefix("name:").unwrap_or(v))
+            .collect();
+        stages2(self, |v| !builds.contains(&v))
+    }
+
+    fn tree(&self) -> &'static str {
+        "/pipelines"
+    }
+}
+
+impl Target for Manifest {
+    fn stages(&self) -> Vec<(String, StageRef<'_>)> {
+        match self {
+            Manifest::V1(v) => v.stages(),
+            Manifest::V2(v) => v.stages(),
+        }
+    }
+
+    fn tree_stages(&self) -> Vec<(String, StageRef<'_>)> {
+        match self {
+            Manifest::V1(v) => v.tree_stages(),
+            Manifest::V2(v) => v.tree_stages(),
+        }
+    }
+
+    fn tree(&self) -> &'static str {
+        match self {
+            Manifest::V1(v) => v.tree(),
+            Manifest::V2(v) => v.tree(),
+        }
+    }
+
+    fn assembler(&self) -> Option<(String, StageRef<'_>)> {
+        match self {
+            Manifest::V1(v) => v.assembler(),
+            Manifest::V2(v) => v.assembler(),
+        }
+    }
+}
+
+/// Run All Checks
+///
+/// Run all checks that need no configuration and return their combined
+/// diagnostics, most severe first.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    span!("lint");
+    let mut acc = Vec::new();
+    acc.extend(deprecated::check(manifest));
+    acc.extend(embedded::check(manifest));
+    acc.extend(identifiers::check(manifest));
+    acc.extend(paths::check(manifest));
+    acc.extend(reproducibility::check(manifest));
+    acc.sort_by_key(|v| std::cmp::Reverse(v.severity));
+    event!(Debug, "linted", diagnostics = acc.len());
+    acc
+}
+
+/// Append JSON Pointer Segment
+///
+/// Append a segment to a JSON pointer, escaping it as required by RFC-6901.
+pub fn pointer(base: &str, segment: &str) -> String {
+    format!("{}/{}", base, segment.replace('~', "~0").replace('/', "~1"))
+}
+
+// Iterate all stages of a pipeline together with their JSON pointers. The
+// pointer of the pipeline itself must be given as `base`.
+pub(crate) fn stages<'a>(
+    pipeline: &'a Pipeline1,
+    base: &'a str,
+) -> impl Iterator<Item = (String, &'a Stage1)> + 'a {
+    pipeline
+        .stages
+        .iter()
+        .enumerate()
+        .map(move |(i, v)| (format!("{}/stages/{}", base, i), v))
+}
+
+// Collect all stages of the manifest, including the stages of all build
+// pipelines, together with their JSON pointers. Build pipelines are listed
+// first, in execution order.
+pub(crate) fn all_stages(manifest: &Manifest1) -> Vec<(String, &Stage1)> {
+    let mut acc = Vec::new();
+    let mut pipeline = &manifest.pipeline;
+    let mut base = "/pipeline".to_owned();
+
+    loop {
+        let mut list: Vec<_> = pipeline
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("{}/stages/{}", base, i), v))
+            .collect();
+        list.append(&mut acc);
+        acc = list;
+
+        match pipeline.build {
+            Some(ref build) => {
+                pipeline = &build.pipeline;
+                base = format!("{}/build/pipeline", base);
+            }
+            None => break,
+        }
+    }
+
+    acc
+}
@@ -0,0 +1,234 @@
+//! Lint Result Cache
+//!
+//! CI pipelines validate entire repositories of manifests on every change,
+//! even though most manifests are unchanged. This module memoizes lint
+//! results keyed by the content identifier of the manifest, as computed by
+//! `store::ManifestStore::id()`, and the version of the rule set, so only
+//! new or changed manifests are linted.
+//!
+//! Results are kept by a backend, either in memory or on disk. The disk
+//! backend stores one file per result, so it can be persisted between CI
+//! runs like any other cache directory, and shared by concurrent runs.
+
+use crate::hash::{self, Digest, Sha256};
+use crate::lint::{self, Diagnostic, Target};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Rule Set Revision
+///
+/// Revision of the checks of `lint::check()`, which is bumped whenever
+/// their results change, so cached results of older checks are not used.
+pub const REVISION: u32 = 1;
+
+/// Cache Key
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Key {
+    /// Content identifier of the manifest.
+    pub id: Digest,
+    /// Version of the rule set.
+    pub rules: String,
+}
+
+/// Cache Backend
+pub trait Backend {
+    /// Look up the result of a key, if any.
+    fn get(&mut self, key: &Key) -> Option<Vec<Diagnostic>>;
+    /// Store the result of a key.
+    fn put(&mut self, key: &Key, diagnostics: &[Diagnostic]) -> std::io::Result<()>;
+}
+
+/// In-Memory Backend
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    entries: HashMap<Key, Vec<Diagnostic>>,
+}
+
+impl Memory {
+    /// Create In-Memory Backend
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of Results
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check Whether No Result Is Stored
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Backend for Memory {
+    fn get(&mut self, key: &Key) -> Option<Vec<Diagnostic>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn put(&mut self, key: &Key, diagnostics: &[Diagnostic]) -> std::io::Result<()> {
+        self.entries.insert(key.clone(), diagnostics.to_vec());
+        Ok(())
+    }
+}
+
+/// On-Disk Backend
+///
+/// Stores each result as JSON file in a directory, named after the digest
+/// of its key. Files are replaced atomically, and unreadable files are
+/// treated as missing.
+#[derive(Clone, Debug)]
+pub struct Disk {
+    dir: PathBuf,
+}
+
+impl Disk {
+    /// Create On-Disk Backend
+    ///
+    /// Create a backend storing results in the directory, which is created
+    /// on the first write if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &Key) -> PathBuf {
+        let name = format!("{}\0{}", key.rules, key.id);
+        let name = hash::to_hex(&Sha256::digest(name.as_bytes()));
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+impl Backend for Disk {
+    fn get(&mut self, key: &Key) -> Option<Vec<Diagnostic>> {
+        let text = std::fs::read(self.path(key)).ok()?;
+        serde_json::from_slice(&text).ok()
+    }
+
+    fn put(&mut self, key: &Key, diagnostics: &[Diagnostic]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+        let text = serde_json::to_vec(diagnostics).expect("diagnostics always serialize");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+    }
+}
+
+/// Lint Result Cache
+pub struct Cache<B> {
+    backend: B,
+    rules: String,
+    /// Number of results served from the cache.
+    pub hits: usize,
+    /// Number of results computed.
+    pub misses: usize,
+}
+
+impl<B: Backend> Cache<B> {
+    /// Create Cache
+    ///
+    /// Create a cache of the results of `lint::check()`, with the rule set
+    /// version derived from the crate version and `REVISION`.
+    pub fn new(backend: B) -> Self {
+        let rules = format!("{}+{}", env!("CARGO_PKG_VERSION"), REVISION);
+        Self::with_rules(backend, &rules)
+    }
+
+    /// Create Cache for Custom Rules
+    ///
+    /// Create a cache with the given rule set version, for results of
+    /// other checks than those of `lint::check()`, see `check_with()`.
+    pub fn with_rules(backend: B, rules: &str) -> Self {
+        Self {
+            backend,
+            rules: rules.to_owned(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Backend of the Cache
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Check Manifest
+    ///
+    /// Return the diagnostics of `lint::check()` for the manifest, from
+    /// the cache if possible.
+    pub fn check<M: Target + std::fmt::Display>(&mut self, manifest: &M) -> Vec<Diagnostic> {
+        self.check_with(manifest, lint::check)
+    }
+
+    /// Check Manifest with Custom Rules
+    ///
+    /// Return the diagnostics of the checks for the manifest, from the
+    /// cache if possible. The checks must match the rule set version of
+    /// the cache. Failures to store results are ignored, since they only
+    /// cost performance.
+    pub fn check_with<M: std::fmt::Display>(
+        &mut self,
+        manifest: &M,
+        checks: impl FnOnce(&M) -> Vec<Diagnostic>,
+    ) -> Vec<Diagnostic> {
+        let key = Key {
+            id: Digest::sha256(manifest.to_string().as_bytes()),
+            rules: self.rules.clone(),
+        };
+        if let Some(v) = self.backend.get(&key) {
+            self.hits += 1;
+            return v;
+        }
+
+        self.misses += 1;
+        let diagnostics = checks(manifest);
+        let _ = self.backend.put(&key, &diagnostics);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Cached Linting
+    #[test]
+    fn verify_cache() {
+        let a: Manifest1 = serde_json::from_str(
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.mkdir", "options": { "paths": [{ "path": "x" }] } }] } }"#,
+        )
+        .unwrap();
+        let b: Manifest1 = serde_json::from_str(r#"{ "pipeline": {} }"#).unwrap();
+
+        let mut cache = Cache::new(Memory::new());
+        assert_eq!(cache.check(&a), lint::check(&a));
+        assert_eq!(cache.check(&b), Vec::new());
+        assert_eq!(cache.check(&a), lint::check(&a));
+        assert_eq!((cache.hits, cache.misses), (1, 2));
+        assert_eq!(cache.backend().len(), 2);
+
+        let dir = std::env::temp_dir().join(format!("r-osbuild-cache-{}", std::process::id()));
+        let mut cache = Cache::new(Disk::new(&dir));
+        assert_eq!(cache.check(&a), lint::check(&a));
+        let mut cache = Cache::new(Disk::new(&dir));
+        assert_eq!(cache.check(&a), lint::check(&a));
+        assert_eq!(cache.hits, 1);
+
+        // Results of other rule sets are not used.
+        let mut cache = Cache::with_rules(Disk::new(&dir), "custom");
+        assert_eq!(cache.check_with(&a, |_| Vec::new()), Vec::new());
+        assert_eq!(cache.misses, 1);
+
+        // Corrupt entries are recomputed.
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            std::fs::write(entry.unwrap().path(), "[").unwrap();
+        }
+        let mut cache = Cache::new(Disk::new(&dir));
+        assert_eq!(cache.check(&a), lint::check(&a));
+        assert_eq!(cache.misses, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+//! Compliance Profiles
+//!
+//! This module checks manifests against compliance profiles. A profile is a
+//! set of requirements on crypto- and security-related stage options, like
+//! the key-derivation function used for encrypted volumes, or the system-wide
+//! crypto policy. Any deviation from the selected profile is reported as a
+//! diagnostic.
+//!
+//! Only the main pipeline is checked, since build pipelines merely provide
+//! the build environment and do not end up in the artifact. Of version-2
+//! manifests, all pipelines not used as build pipelines are checked.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{Json, StageRef};
+
+/// Compliance Profile
+///
+/// The profiles supported by the compliance checker. `Fips` requires the
+/// artifact to boot in FIPS mode and use FIPS-approved primitives. `Cis`
+/// checks a subset of the CIS benchmark hardening recommendations that can
+/// be derived from the manifest.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Profile {
+    Fips,
+    Cis,
+}
+
+fn deviation(path: &str, rule: &str, message: &str) -> Diagnostic {
+    Diagnostic::new(rule, Severity::Error, path, message)
+}
+
+fn option<'a>(stage: StageRef<'a>, key: &str) -> Option<&'a Json> {
+    stage.options.get(key)
+}
+
+fn check_fips<M: Target + ?Sized>(manifest: &M, diagnostics: &mut Vec<Diagnostic>) {
+    let mut policy = false;
+    let mut dracut = false;
+    let mut cmdline = false;
+
+    for (path, stage) in manifest.tree_stages() {
+        match stage.name {
+            "org.osbuild.luks2.format" => {
+                let method = option(stage, "pbkdf")
+                    .and_then(|v| v.get("method"))
+                    .and_then(Json::as_str);
+                if method != Some("pbkdf2") {
+                    diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-fips-pbkdf",
+                        "LUKS2 volumes must use `pbkdf2` as key-derivation function in FIPS mode",
+                    ));
+                }
+            }
+            "org.osbuild.dracut" => {
+                let modules = option(stage, "add_modules").and_then(Json::as_array);
+                if modules.is_some_and(|v| v.iter().any(|m| m == "fips")) {
+                    dracut = true;
+                }
+            }
+            "org.osbuild.update-crypto-policies" => {
+                let value = option(stage, "policy").and_then(Json::as_str);
+                match value {
+                    Some(v) if v == "FIPS" || v.starts_with("FIPS:") => policy = true,
+                    _ => diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-fips-crypto-policy",
+                        "the system-wide crypto policy must be `FIPS` in FIPS mode",
+                    )),
+                }
+            }
+            "org.osbuild.kernel-cmdline" | "org.osbuild.grub2" => {
+                let opts = option(stage, "kernel_opts").and_then(Json::as_str);
+                if opts.is_some_and(|v| v.split_whitespace().any(|o| o == "fips=1")) {
+                    cmdline = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !policy {
+        diagnostics.push(deviation(
+            manifest.tree(),
+            "compliance-fips-crypto-policy",
+            "no stage sets the system-wide crypto policy to `FIPS`",
+        ));
+    }
+    if !dracut {
+        diagnostics.push(deviation(
+            manifest.tree(),
+            "compliance-fips-dracut",
+            "no dracut stage adds the `fips` module to the initramfs",
+        ));
+    }
+    if !cmdline {
+        diagnostics.push(deviation(
+            manifest.tree(),
+            "compliance-fips-cmdline",
+            "no stage adds `fips=1` to the kernel command-line",
+        ));
+    }
+}
+
+fn check_cis<M: Target + ?Sized>(manifest: &M, diagnostics: &mut Vec<Diagnostic>) {
+    let mut selinux = false;
+
+    for (path, stage) in manifest.tree_stages() {
+        match stage.name {
+            "org.osbuild.sshd.config" => {
+                let config = option(stage, "config");
+                // Besides keywords, the option accepts booleans for `yes`
+                // and `no`.
+                let root = config.and_then(|v| v.get("PermitRootLogin"));
+                let permitted = match root {
+                    Some(Json::String(v)) => v != "no",
+                    Some(Json::Bool(v)) => *v,
+                    _ => false,
+                };
+                if permitted {
+                    diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-cis-sshd-root",
+                        "sshd must not permit root logins",
+                    ));
+                }
+                let password = config
+                    .and_then(|v| v.get("PasswordAuthentication"))
+                    .and_then(Json::as_bool);
+                if password == Some(true) {
+                    diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-cis-sshd-password",
+                        "sshd must not allow password authentication",
+                    ));
+                }
+            }
+            "org.osbuild.selinux.config" => {
+                let state = option(stage, "state").and_then(Json::as_str);
+                if state == Some("enforcing") {
+                    selinux = true;
+                } else {
+                    diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-cis-selinux",
+                        "SELinux must be configured as `enforcing`",
+                    ));
+                }
+            }
+            "org.osbuild.update-crypto-policies" => {
+                let value = option(stage, "policy").and_then(Json::as_str);
+                if value.is_some_and(|v| v == "LEGACY" || v.starts_with("LEGACY:")) {
+                    diagnostics.push(deviation(
+                        &lint::pointer(&path, "options"),
+                        "compliance-cis-crypto-policy",
+                        "the system-wide crypto policy must not be `LEGACY`",
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !selinux {
+        diagnostics.push(Diagnostic::new(
+            "compliance-cis-selinux",
+            Severity::Warning,
+            manifest.tree(),
+            "no stage configures SELinux as `enforcing`",
+        ));
+    }
+}
+
+/// Check Manifest Against Profile
+///
+/// Check the main pipeline of the manifest against the given compliance
+/// profile and return a diagnostic for each deviation.
+pub fn check<M: Target + ?Sized>(manifest: &M, profile: Profile) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    match profile {
+        Profile::Fips => check_fips(manifest, &mut diagnostics),
+        Profile::Cis => check_cis(manifest, &mut diagnostics),
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Manifest, Manifest1};
+
+    fn rules(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|v| v.rule.as_str()).collect()
+    }
+
+    // Verify FIPS Profile
+    #[test]
+    fn verify_fips() {
+        // Empty manifests miss all required stages.
+        let manifest: Manifest1 = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq! {
+            rules(&check(&manifest, Profile::Fips)),
+            [
+                "compliance-fips-crypto-policy",
+                "compliance-fips-dracut",
+                "compliance-fips-cmdline",
+            ],
+        }
+
+        // Compliant manifests only report deviating stages.
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        {
+                            "name": "org.osbuild.update-crypto-policies",
+                            "options": { "policy": "FIPS" }
+                        },
+                        {
+                            "name": "org.osbuild.dracut",
+                            "options": { "add_modules": ["fips"] }
+                        },
+                        {
+                            "name": "org.osbuild.kernel-cmdline",
+                            "options": { "kernel_opts": "ro fips=1" }
+                        },
+                        {
+                            "name": "org.osbuild.luks2.format",
+                            "options": { "pbkdf": { "method": "argon2id" } }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = check(&manifest, Profile::Fips);
+        assert_eq!(rules(&diagnostics), ["compliance-fips-pbkdf"]);
+        assert_eq!(diagnostics[0].path, "/pipeline/stages/3/options");
+
+        // Version-2 manifests are checked in all but build pipelines.
+        let manifest = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    {
+                        "name": "build",
+                        "stages": [
+                            { "type": "org.osbuild.update-crypto-policies", "options": { "policy": "DEFAULT" } }
+                        ]
+                    },
+                    {
+                        "name": "os",
+                        "build": "name:build",
+                        "stages": [
+                            { "type": "org.osbuild.update-crypto-policies", "options": { "policy": "FIPS" } },
+                            { "type": "org.osbuild.dracut", "options": { "add_modules": ["fips"] } },
+                            { "type": "org.osbuild.kernel-cmdline", "options": { "kernel_opts": "fips=1" } }
+                        ]
+                    },
+                    {
+                        "name": "image",
+                        "build": "name:build",
+                        "stages": [
+                            {
+                                "type": "org.osbuild.luks2.format",
+                                "options": { "passphrase": "x" },
+                                "devices": { "device": { "type": "org.osbuild.loopback" } }
+                            }
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = check(&manifest, Profile::Fips);
+        assert_eq!(rules(&diagnostics), ["compliance-fips-pbkdf"]);
+        assert_eq!(diagnostics[0].path, "/pipelines/2/stages/0/options");
+    }
+
+    // Verify CIS Profile
+    #[test]
+    fn verify_cis() {
+        let mut manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        {
+                            "name": "org.osbuild.sshd.config",
+                            "options": {
+                                "config": {
+                                    "PermitRootLogin": "yes",
+                                    "PasswordAuthentication": false
+                                }
+                            }
+                        },
+                        {
+                            "name": "org.osbuild.selinux.config",
+                            "options": { "state": "enforcing" }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq! {
+            rules(&check(&manifest, Profile::Cis)),
+            ["compliance-cis-sshd-root"],
+        }
+
+        // Booleans are accepted in place of `yes` and `no`.
+        for (value, expected) in [(true, vec!["compliance-cis-sshd-root"]), (false, vec![])] {
+            manifest.pipeline.stages[0].options.insert(
+                "config".to_owned(),
+                serde_json::json!({ "PermitRootLogin": value }),
+            );
+            assert_eq!(rules(&check(&manifest, Profile::Cis)), expected);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Deprecated Options
+//!
+//! This module detects stage options that were superseded by newer
+//! spellings. Deprecated options are still accepted by osbuild, but might
+//! be dropped in the future. Every deprecated option has a mechanical
+//! replacement, so these diagnostics can be fixed automatically.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+
+/// Deprecated Option Table
+///
+/// This lists all known deprecated options as tuples of stage name,
+/// deprecated option, and the JSON pointer of the replacement relative to
+/// the stage options.
+pub const DEPRECATED: &[(&str, &str, &str)] = &[
+    ("org.osbuild.grub2", "boot_fs_uuid", "/bootfs/uuid"),
+    ("org.osbuild.grub2", "root_fs_uuid", "/rootfs/uuid"),
+];
+
+/// Check Deprecated Options
+///
+/// Check all pipelines of the manifest, including build pipelines, for
+/// deprecated options and return a diagnostic for each of them.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+
+    for (path, stage) in manifest.stages() {
+        for (name, key, replacement) in DEPRECATED {
+            if stage.name == *name && stage.options.contains_key(*key) {
+                acc.push(Diagnostic::new(
+                    "deprecated-option",
+                    Severity::Warning,
+                    &lint::pointer(&lint::pointer(&path, "options"), key),
+                    &format!(
+                        "option `{}` of `{}` is deprecated in favor of `{}`",
+                        key, name, replacement,
+                    ),
+                ));
+            }
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Deprecation Checks
+    #[test]
+    fn verify_check() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [
+                                {
+                                    "name": "org.osbuild.grub2",
+                                    "options": { "root_fs_uuid": "x" }
+                                }
+                            ]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        {
+                            "name": "org.osbuild.grub2",
+                            "options": { "rootfs": { "uuid": "x" } }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            check(&manifest).iter().map(|v| v.path.as_str()).collect::<Vec<_>>(),
+            ["/pipeline/build/pipeline/stages/0/options/root_fs_uuid"],
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Embedded Configuration Checks
+//!
+//! Several stages take options that end up verbatim in configuration files
+//! of other formats, like fstab entries, sshd_config keywords, or systemd
+//! unit files. osbuild only validates the structure of these options, so
+//! malformed content is usually only noticed when the built image fails to
+//! boot. This module validates such embedded configuration with lightweight
+//! parsers, so these errors are caught when the manifest is checked.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{Json, StageRef};
+
+// Keywords accepted by sshd_config(5). Keywords are case-insensitive.
+const SSHD_KEYWORDS: &[&str] = &[
+    "AcceptEnv",
+    "AddressFamily",
+    "AllowAgentForwarding",
+    "AllowGroups",
+    "AllowStreamLocalForwarding",
+    "AllowTcpForwarding",
+    "AllowUsers",
+    "AuthenticationMethods",
+    "AuthorizedKeysCommand",
+    "AuthorizedKeysCommandUser",
+    "AuthorizedKeysFile",
+    "AuthorizedPrincipalsFile",
+    "Banner",
+    "ChallengeResponseAuthentication",
+    "ChrootDirectory",
+    "Ciphers",
+    "ClientAliveCountMax",
+    "ClientAliveInterval",
+    "Compression",
+    "DenyGroups",
+    "DenyUsers",
+    "GSSAPIAuthentication",
+    "GSSAPICleanupCredentials",
+    "HostKey",
+    "HostbasedAuthentication",
+    "IgnoreRhosts",
+    "Include",
+    "KbdInteractiveAuthentication",
+    "KerberosAuthentication",
+    "KexAlgorithms",
+    "ListenAddress",
+    "LogLevel",
+    "LoginGraceTime",
+    "MACs",
+    "MaxAuthTries",
+    "MaxSessions",
+    "MaxStartups",
+    "PasswordAuthentication",
+    "PermitEmptyPasswords",
+    "PermitRootLogin",
+    "PermitTTY",
+    "PermitTunnel",
+    "PermitUserEnvironment",
+    "Port",
+    "PrintLastLog",
+    "PrintMotd",
+    "PubkeyAuthentication",
+    "Subsystem",
+    "SyslogFacility",
+    "TCPKeepAlive",
+    "UseDNS",
+    "UsePAM",
+    "X11DisplayOffset",
+    "X11Forwarding",
+    "X11UseLocalhost",
+];
+
+// Unit types known to systemd.
+const UNIT_SUFFIXES: &[&str] = &[
+    ".automount",
+    ".device",
+    ".mount",
+    ".path",
+    ".scope",
+    ".service",
+    ".slice",
+    ".socket",
+    ".swap",
+    ".target",
+    ".timer",
+];
+
+// Section names of systemd unit files.
+const UNIT_SECTIONS: &[&str] = &[
+    "Automount",
+    "Install",
+    "Mount",
+    "Path",
+    "Service",
+    "Slice",
+    "Socket",
+    "Swap",
+    "Timer",
+    "Unit",
+];
+
+fn invalid(path: &str, message: &str) -> Diagnostic {
+    Diagnostic::new("embedded-config", Severity::Error, path, message)
+}
+
+// Check whether a value can be embedded into a whitespace-separated line
+// based format without breaking the line structure.
+fn is_word(v: &str) -> bool {
+    !v.is_empty() && !v.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn check_fstab(stage: &StageRef<'_>, path: &str, acc: &mut Vec<Diagnostic>) {
+    let filesystems = match stage.options.get("filesystems").and_then(Json::as_array) {
+        None => return,
+        Some(v) => v,
+    };
+
+    for (i, fs) in filesystems.iter().enumerate() {
+        let path = format!("{}/options/filesystems/{}", path, i);
+
+        let sources = ["uuid", "label", "partlabel", "device"];
+        if !sources.iter().any(|v| fs.get(*v).is_some()) {
+            acc.push(invalid(&path, "fstab entry lacks a device specification"));
+        }
+        for key in sources.iter().chain(["vfs_type", "options"].iter()) {
+            if let Some(v) = fs.get(*key).and_then(Json::as_str) {
+                if !is_word(v) {
+                    acc.push(invalid(
+                        &lint::pointer(&path, key),
+                        &format!("fstab field `{}` must be a single non-empty word", key),
+                    ));
+                }
+            }
+        }
+        match fs.get("path").and_then(Json::as_str) {
+            Some(v) if v.starts_with('/') && is_word(v) => {}
+            Some("none") | Some("swap") => {}
+            _ => acc.push(invalid(
+                &lint::pointer(&path, "path"),
+                "fstab mount point must be an absolute path without whitespace",
+            )),
+        }
+        if let Some(v) = fs.get("passno") {
+            if v.as_u64().is_none_or(|v| v > 2) {
+                acc.push(invalid(
+                    &lint::pointer(&path, "passno"),
+                    "fstab pass number must be 0, 1, or 2",
+                ));
+            }
+        }
+    }
+}
+
+fn check_sshd(stage: &StageRef<'_>, path: &str, acc: &mut Vec<Diagnostic>) {
+    let config = match stage.options.get("config").and_then(Json::as_object) {
+        None => return,
+        Some(v) => v,
+    };
+
+    for (key, value) in config {
+        let path = lint::pointer(&format!("{}/options/config", path), key);
+
+        if !SSHD_KEYWORDS.iter().any(|v| v.eq_ignore_ascii_case(key)) {
+            acc.push(Diagnostic::new(
+                "embedded-config",
+                Severity::Warning,
+                &path,
+                &format!("unknown sshd_config keyword `{}`", key),
+            ));
+        }
+        if value.as_str().is_some_and(|v| v.contains('\n')) {
+            acc.push(invalid(&path, "sshd_config values must not span lines"));
+        }
+    }
+}
+
+fn check_unit_section(section: &Json, path: &str, acc: &mut Vec<Diagnostic>) {
+    let section = match section.as_object() {
+        None => return,
+        Some(v) => v,
+    };
+
+    for (key, value) in section {
+        let path = lint::pointer(path, key);
+        let valid = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid {
+            acc.push(invalid(
+                &path,
+                &format!("invalid unit setting name `{}`", key),
+            ));
+        }
+
+        let values = match value {
+            Json::Array(v) => v.iter().collect(),
+            v => vec![v],
+        };
+        if values
+            .iter()
+            .any(|v| v.as_str().is_some_and(|v| v.contains('\n')))
+        {
+            acc.push(invalid(&path, "unit settings must not span lines"));
+        }
+    }
+}
+
+fn check_unit(stage: &StageRef<'_>, path: &str, acc: &mut Vec<Diagnostic>) {
+    let options = &stage.options;
+
+    if let Some(unit) = options.get("unit").and_then(Json::as_str) {
+        let (stem, suffix) = match unit.rfind('.') {
+            Some(v) => unit.split_at(v),
+            None => (unit, ""),
+        };
+        let stem_valid = !stem.is_empty()
+            && stem
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ":-_.\\@".contains(c));
+        if !stem_valid || !UNIT_SUFFIXES.contains(&suffix) {
+            acc.push(invalid(
+                &format!("{}/options/unit", path),
+                &format!("invalid unit name `{}`", unit),
+            ));
+        }
+    }
+    if let Some(dropin) = options.get("dropin").and_then(Json::as_str) {
+        if !dropin.ends_with(".conf") || dropin.contains('/') {
+            acc.push(invalid(
+                &format!("{}/options/dropin", path),
+                "drop-in names must be plain file names ending in `.conf`",
+            ));
+        }
+    }
+    if let Some(config) = options.get("config").and_then(Json::as_object) {
+        for (section, content) in config {
+            let path = lint::pointer(&format!("{}/options/config", path), section);
+            if !UNIT_SECTIONS.contains(&section.as_str()) {
+                acc.push(invalid(
+                    &path,
+                    &format!("unknown unit section `{}`", section),
+                ));
+            }
+            check_unit_section(content, &path, acc);
+        }
+    }
+}
+
+fn check_kickstart(stage: &StageRef<'_>, path: &str, acc: &mut Vec<Diagnostic>) {
+    let options = &stage.options;
+
+    if let Some(v) = options.get("path").and_then(Json::as_str) {
+        if !v.starts_with('/') || !is_word(v) {
+            acc.push(invalid(
+                &format!("{}/options/path", path),
+                "kickstart path must be absolute and must not contain whitespace",
+            ));
+        }
+    }
+    if let Some(users) = options.get("users").and_then(Json::as_object) {
+        for name in users.keys() {
+            let valid = !name.is_empty()
+                && !name.starts_with('-')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+            if !valid {
+                acc.push(invalid(
+                    &lint::pointer(&format!("{}/options/users", path), name),
+                    &format!("invalid kickstart user name `{}`", name),
+                ));
+            }
+        }
+    }
+}
+
+/// Check Embedded Configuration
+///
+/// Check the options of all stages that embed configuration of other
+/// formats, in all pipelines of the manifest, and return a diagnostic for
+/// each malformed entry.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+
+    for (path, stage) in manifest.stages() {
+        match stage.name {
+            "org.osbuild.fstab" => check_fstab(&stage, &path, &mut acc),
+            "org.osbuild.sshd.config" => check_sshd(&stage, &path, &mut acc),
+            "org.osbuild.systemd.unit" => check_unit(&stage, &path, &mut acc),
+            "org.osbuild.kickstart" => check_kickstart(&stage, &path, &mut acc),
+            _ => {}
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    fn paths(json: &str) -> Vec<String> {
+        let manifest: Manifest1 = serde_json::from_str(json).unwrap();
+        check(&manifest).into_iter().map(|v| v.path).collect()
+    }
+
+    // Verify fstab Checks
+    #[test]
+    fn verify_fstab() {
+        assert_eq! {
+            paths(r#"{
+                "pipeline": {
+                    "stages": [{
+                        "name": "org.osbuild.fstab",
+                        "options": {
+                            "filesystems": [
+                                { "uuid": "76a22bf4", "vfs_type": "xfs", "path": "/", "passno": 1 },
+                                { "label": "my boot", "vfs_type": "ext4", "path": "boot" },
+                                { "vfs_type": "swap", "path": "swap", "passno": 3 }
+                            ]
+                        }
+                    }]
+                }
+            }"#),
+            [
+                "/pipeline/stages/0/options/filesystems/1/label",
+                "/pipeline/stages/0/options/filesystems/1/path",
+                "/pipeline/stages/0/options/filesystems/2",
+                "/pipeline/stages/0/options/filesystems/2/passno",
+            ],
+        }
+    }
+
+    // Verify sshd_config Checks
+    #[test]
+    fn verify_sshd() {
+        assert_eq! {
+            paths(r#"{
+                "pipeline": {
+                    "stages": [{
+                        "name": "org.osbuild.sshd.config",
+                        "options": {
+                            "config": {
+                                "passwordauthentication": false,
+                                "PermitRootLogn": "no",
+                                "Banner": "foo\nbar"
+                            }
+                        }
+                    }]
+                }
+            }"#),
+            [
+                "/pipeline/stages/0/options/config/PermitRootLogn",
+                "/pipeline/stages/0/options/config/Banner",
+            ],
+        }
+    }
+
+    // Verify systemd Unit Checks
+    #[test]
+    fn verify_unit() {
+        assert_eq! {
+            paths(r#"{
+                "pipeline": {
+                    "stages": [{
+                        "name": "org.osbuild.systemd.unit",
+                        "options": {
+                            "unit": "foo@.srvice",
+                            "dropin": "override",
+                            "config": {
+                                "Service": {
+                                    "Environment": ["A=b", "C=d\n"],
+                                    "Exec Start": "/bin/true"
+                                },
+                                "Servce": {}
+                            }
+                        }
+                    }]
+                }
+            }"#),
+            [
+                "/pipeline/stages/0/options/unit",
+                "/pipeline/stages/0/options/dropin",
+                "/pipeline/stages/0/options/config/Service/Environment",
+                "/pipeline/stages/0/options/config/Service/Exec Start",
+                "/pipeline/stages/0/options/config/Servce",
+            ],
+        }
+    }
+
+    // Verify Kickstart Checks
+    #[test]
+    fn verify_kickstart() {
+        assert_eq! {
+            paths(r#"{
+                "pipeline": {
+                    "stages": [{
+                        "name": "org.osbuild.kickstart",
+                        "options": {
+                            "path": "osbuild.ks",
+                            "users": { "root": {}, "-x": {} }
+                        }
+                    }]
+                }
+            }"#),
+            [
+                "/pipeline/stages/0/options/path",
+                "/pipeline/stages/0/options/users/-x",
+            ],
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Automatic Fixes
+//!
+//! This module applies mechanical fixes for diagnostics. Some checks report
+//! issues that have a single obvious remedy, like renaming a deprecated
+//! option or pinning a value that would otherwise be generated at build
+//! time. The fix engine takes such diagnostics, applies the remedy to the
+//! manifest, and reports every edit it made.
+//!
+//! Diagnostics without a known fix are ignored, and so are diagnostics
+//! that do not refer to the options of a stage. Fixes never overwrite
+//! values chosen by the user, so they can be applied repeatedly.
+
+use crate::lint::{deprecated, Diagnostic};
+use crate::manifest::{Json, Manifest};
+
+/// Fix Options
+///
+/// Some fixes need additional input from the caller. `source_epoch` is the
+/// timestamp used to pin stages that embed the build time. If not set,
+/// those diagnostics are not fixed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Options {
+    pub source_epoch: Option<u64>,
+}
+
+/// Applied Edit
+///
+/// This describes a single edit made by the fix engine. It carries the rule
+/// of the diagnostic that was fixed, a JSON pointer to the edited value, and
+/// a human-readable description of the edit.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Edit {
+    pub rule: String,
+    pub path: String,
+    pub description: String,
+}
+
+// Split a JSON pointer into its parent pointer and its unescaped last
+// segment.
+fn split(pointer: &str) -> Option<(&str, String)> {
+    let idx = pointer.rfind('/')?;
+    let key = pointer[idx + 1..].replace("~1", "/").replace("~0", "~");
+    Some((&pointer[..idx], key))
+}
+
+// Insert a value at a JSON pointer, creating intermediate objects as needed.
+// Fails if the target exists already or an intermediate is not a container.
+fn insert(value: &mut Json, pointer: &str, new: Json) -> Option<()> {
+    let (parent, key) = split(pointer)?;
+    let mut cur = value;
+
+    for segment in parent.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        cur = match cur {
+            Json::Array(list) => list.get_mut(segment.parse::<usize>().ok()?)?,
+            Json::Object(map) => map
+                .entry(segment)
+                .or_insert_with(|| Json::Object(Default::default())),
+            _ => return None,
+        };
+    }
+
+    let map = cur.as_object_mut()?;
+    if map.contains_key(&key) {
+        return None;
+    }
+    map.insert(key, new);
+    Some(())
+}
+
+// Return the name of the stage whose options the pointer refers to. Stages
+// are named by `name` in version 1, and by `type` in version 2. Fails if the
+// pointer does not refer to the options object of a stage or assembler.
+fn stage_name(json: &Json, options: &str) -> Option<String> {
+    let (stage, key) = split(options)?;
+    if key != "options" || !json.pointer(options)?.is_object() {
+        return None;
+    }
+    let stage = json.pointer(stage)?;
+    let name = stage.get("name").or_else(|| stage.get("type"))?;
+    name.as_str().map(str::to_owned)
+}
+
+fn fix_deprecated(json: &mut Json, diagnostic: &Diagnostic) -> Option<Edit> {
+    let (options, key) = split(&diagnostic.path)?;
+    let name = stage_name(json, options)?;
+
+    let (_, _, replacement) = deprecated::DEPRECATED
+        .iter()
+        .find(|(n, k, _)| *n == name && *k == key)?;
+
+    let target = json.pointer_mut(options)?;
+    let value = target.as_object_mut()?.remove(&key)?;
+    let old = value.clone();
+    if insert(target, replacement, value).is_none() {
+        target.as_object_mut()?.insert(key, old);
+        return None;
+    }
+
+    Some(Edit {
+        rule: diagnostic.rule.clone(),
+        path: format!("{}{}", options, replacement),
+        description: format!("moved deprecated option `{}` to `{}`", key, replacement),
+    })
+}
+
+fn fix_machine_id(json: &mut Json, diagnostic: &Diagnostic) -> Option<Edit> {
+    stage_name(json, &diagnostic.path)?;
+    let value = json.pointer_mut(&diagnostic.path)?.get_mut("first-boot")?;
+    if value.as_str() != Some("preserve") {
+        return None;
+    }
+    *value = Json::from("yes");
+
+    Some(Edit {
+        rule: diagnostic.rule.clone(),
+        path: format!("{}/first-boot", diagnostic.path),
+        description: "set `first-boot` to `yes` to generate the machine-id on first boot"
+            .to_owned(),
+    })
+}
+
+fn fix_timestamp(json: &mut Json, diagnostic: &Diagnostic, epoch: u64) -> Option<Edit> {
+    stage_name(json, &diagnostic.path)?;
+    let path = format!("{}/source_epoch", diagnostic.path);
+    insert(json, &path, Json::from(epoch))?;
+
+    Some(Edit {
+        rule: diagnostic.rule.clone(),
+        path,
+        description: format!("pinned the embedded timestamps to `{}`", epoch),
+    })
+}
+
+/// Apply Fixes
+///
+/// Apply the fixes for all given diagnostics to the manifest, in order, and
+/// return the edits that were made. The diagnostics must have been produced
+/// for this manifest, and the manifest must not have been modified since.
+/// Manifests of either version are fixed in their own version.
+pub fn fix(manifest: &mut Manifest, diagnostics: &[Diagnostic], options: &Options) -> Vec<Edit> {
+    match manifest {
+        Manifest::V1(v) => apply(v, diagnostics, options),
+        Manifest::V2(v) => apply(v, diagnostics, options),
+    }
+}
+
+fn apply<M>(manifest: &mut M, diagnostics: &[Diagnostic], options: &Options) -> Vec<Edit>
+where
+    M: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut json = serde_json::to_value(&*manifest).expect("manifests always serialize");
+    let mut edits = Vec::new();
+
+    for diagnostic in diagnostics {
+        let edit = match diagnostic.rule.as_str() {
+            "deprecated-option" => fix_deprecated(&mut json, diagnostic),
+            "reproducibility-machine-id" => fix_machine_id(&mut json, diagnostic),
+            "reproducibility-timestamp" => options
+                .source_epoch
+                .and_then(|v| fix_timestamp(&mut json, diagnostic, v)),
+            _ => None,
+        };
+        edits.extend(edit);
+    }
+
+    // Fixes only edit options of stages, which are free-form objects, so
+    // the manifest structure is retained.
+    if !edits.is_empty() {
+        *manifest = serde_json::from_value(json).expect("fixes retain the manifest structure");
+    }
+
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{reproducibility, Severity};
+
+    // Verify Fix Engine
+    #[test]
+    fn verify_fix() {
+        let mut manifest = Manifest::parse(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        {
+                            "name": "org.osbuild.grub2",
+                            "options": { "root_fs_uuid": "x", "boot_fs_uuid": "y" }
+                        },
+                        {
+                            "name": "org.osbuild.machine-id",
+                            "options": { "first-boot": "preserve" }
+                        },
+                        {
+                            "name": "org.osbuild.tar",
+                            "options": { "filename": "root.tar" }
+                        },
+                        {
+                            "name": "org.osbuild.machine-id",
+                            "options": { "first-boot": "no" }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let mut diagnostics = deprecated::check(&manifest);
+        diagnostics.extend(reproducibility::check(&manifest));
+
+        // Diagnostics that do not refer to stage options, or to values
+        // other than `preserve`, are skipped.
+        for path in ["/pipeline", "/pipeline/stages/3/options", "/sources/x"] {
+            diagnostics.push(Diagnostic::new(
+                "reproducibility-machine-id",
+                Severity::Warning,
+                path,
+                "",
+            ));
+        }
+        let edits = fix(
+            &mut manifest,
+            &diagnostics,
+            &Options {
+                source_epoch: Some(71),
+            },
+        );
+
+        assert_eq! {
+            edits.iter().map(|v| v.path.as_str()).collect::<Vec<_>>(),
+            [
+                "/pipeline/stages/0/options/bootfs/uuid",
+                "/pipeline/stages/0/options/rootfs/uuid",
+                "/pipeline/stages/1/options/first-boot",
+                "/pipeline/stages/2/options/source_epoch",
+            ],
+        }
+        let Manifest::V1(ref v1) = manifest else {
+            panic!("fixes retain the manifest version");
+        };
+        assert_eq!(v1.pipeline.stages[3].options["first-boot"], "no");
+        assert_eq! {
+            serde_json::to_value(&v1.pipeline.stages[0].options).unwrap(),
+            serde_json::json!({
+                "bootfs": { "uuid": "y" },
+                "rootfs": { "uuid": "x" },
+            }),
+        }
+
+        // Fixed manifests no longer produce the diagnostics.
+        assert_eq!(deprecated::check(&manifest), []);
+        assert_eq!(reproducibility::check(&manifest), []);
+
+        // Version-2 manifests are fixed in their own version.
+        let mut manifest = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [{ "name": "tree", "stages": [{
+                    "type": "org.osbuild.machine-id",
+                    "options": { "first-boot": "preserve" }
+                }] }]
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = reproducibility::check(&manifest);
+        let edits = fix(&mut manifest, &diagnostics, &Options::default());
+        assert_eq!(edits[0].path, "/pipelines/0/stages/0/options/first-boot");
+        assert!(matches!(manifest, Manifest::V2(_)));
+        assert_eq!(reproducibility::check(&manifest), []);
+    }
+}
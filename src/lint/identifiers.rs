@@ -0,0 +1,273 @@
+//! Identifier Checks
+//!
+//! This module validates the partition-table identifiers, partition types,
+//! file-system identifiers, and volume labels passed to partitioning,
+//! file-system, and boot-loader stages. Invalid values are usually only
+//! rejected by the respective tools at build time, or worse, silently
+//! truncated.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{self, Json, Object};
+use crate::uuid;
+
+fn invalid(path: &str, message: String) -> Diagnostic {
+    Diagnostic::new("invalid-identifier", Severity::Error, path, &message)
+}
+
+fn string<'a>(value: &'a Json, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Json::as_str)
+}
+
+fn check_uuid(value: &Json, key: &str, path: &str, acc: &mut Vec<Diagnostic>) {
+    if let Some(v) = string(value, key) {
+        if uuid::Uuid::parse(v).is_none() {
+            acc.push(invalid(
+                &lint::pointer(path, key),
+                format!("`{}` is not a valid UUID", v),
+            ));
+        }
+    }
+}
+
+// Check the identifier and label of a file-system of the given type. The
+// identifier and label are taken from the given keys of `value`.
+fn check_fs(
+    fs_type: &str,
+    value: &Json,
+    keys: (&str, &str),
+    path: &str,
+    acc: &mut Vec<Diagnostic>,
+) {
+    if let Some(v) = string(value, keys.0) {
+        if !uuid::is_fs_id(fs_type, v) {
+            acc.push(invalid(
+                &lint::pointer(path, keys.0),
+                format!(
+                    "`{}` is not a valid `{}` file-system identifier",
+                    v, fs_type
+                ),
+            ));
+        }
+    }
+    if let Some(v) = string(value, keys.1) {
+        if let Err(e) = uuid::check_label(fs_type, v) {
+            acc.push(invalid(
+                &lint::pointer(path, keys.1),
+                format!("invalid label `{}`: {}", v, e),
+            ));
+        }
+    }
+}
+
+// Check a partition table description, as used by sfdisk and the qemu
+// assembler. The keys of the table identifier and the partition identifiers
+// differ between the two.
+fn check_table(options: &Object<Json>, id: &str, path: &str, acc: &mut Vec<Diagnostic>) {
+    let label = options
+        .get("label")
+        .or_else(|| options.get("pttype"))
+        .and_then(Json::as_str)
+        .unwrap_or("dos");
+
+    if let Some(v) = options.get(id).and_then(Json::as_str) {
+        let valid = match label {
+            "gpt" => uuid::Uuid::parse(v).is_some(),
+            _ => uuid::is_dos_id(v),
+        };
+        if !valid {
+            acc.push(invalid(
+                &lint::pointer(path, id),
+                format!(
+                    "`{}` is not a valid `{}` partition-table identifier",
+                    v, label
+                ),
+            ));
+        }
+    }
+
+    let partitions = match options.get("partitions").and_then(Json::as_array) {
+        None => return,
+        Some(v) => v,
+    };
+
+    for (i, partition) in partitions.iter().enumerate() {
+        let path = format!("{}/partitions/{}", path, i);
+
+        if let Some(v) = string(partition, "type") {
+            if !uuid::is_partition_type(label, v) {
+                acc.push(invalid(
+                    &lint::pointer(&path, "type"),
+                    format!("`{}` is not a valid `{}` partition type", v, label),
+                ));
+            }
+        }
+        if label == "gpt" {
+            check_uuid(partition, "uuid", &path, acc);
+        }
+        if let Some(fs) = partition.get("filesystem") {
+            let fs_type = string(fs, "type").unwrap_or("");
+            let path = lint::pointer(&path, "filesystem");
+            check_fs(fs_type, fs, ("uuid", "label"), &path, acc);
+        }
+    }
+}
+
+/// Check Identifiers
+///
+/// Check all identifiers and labels in the options of known partitioning,
+/// file-system, and boot-loader stages, as well as the qemu assembler, in
+/// all pipelines of the manifest.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+
+    for (path, stage) in manifest.stages() {
+        let path = lint::pointer(&path, "options");
+        let options = stage.options;
+        let json = manifest::object_to_value(options.clone());
+
+        match stage.name {
+            "org.osbuild.sfdisk" => check_table(options, "uuid", &path, &mut acc),
+            "org.osbuild.mkfs.fat" => check_fs("fat", &json, ("volid", "label"), &path, &mut acc),
+            "org.osbuild.mkfs.btrfs" | "org.osbuild.mkfs.ext4" | "org.osbuild.mkfs.xfs" => {
+                let fs_type = &stage.name["org.osbuild.mkfs.".len()..];
+                check_fs(fs_type, &json, ("uuid", "label"), &path, &mut acc);
+            }
+            "org.osbuild.fstab" => {
+                let list = options.get("filesystems").and_then(Json::as_array);
+                for (i, fs) in list.into_iter().flatten().enumerate() {
+                    let fs_type = string(fs, "vfs_type").unwrap_or("");
+                    let path = format!("{}/filesystems/{}", path, i);
+                    check_fs(fs_type, fs, ("uuid", "label"), &path, &mut acc);
+                }
+            }
+            "org.osbuild.grub2" => {
+                check_uuid(&json, "root_fs_uuid", &path, &mut acc);
+                check_uuid(&json, "boot_fs_uuid", &path, &mut acc);
+                for key in ["rootfs", "bootfs"] {
+                    if let Some(fs) = options.get(key) {
+                        check_uuid(fs, "uuid", &lint::pointer(&path, key), &mut acc);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((path, assembler)) = manifest.assembler() {
+        if assembler.name == "org.osbuild.qemu" {
+            let path = lint::pointer(&path, "options");
+            let json = manifest::object_to_value(assembler.options.clone());
+            check_table(assembler.options, "ptuuid", &path, &mut acc);
+            check_uuid(&json, "root_fs_uuid", &path, &mut acc);
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Manifest1, Manifest2};
+
+    // Verify Identifier Checks
+    #[test]
+    fn verify_check() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        {
+                            "name": "org.osbuild.sfdisk",
+                            "options": {
+                                "label": "gpt",
+                                "uuid": "0x14fc63d2",
+                                "partitions": [
+                                    { "type": "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" },
+                                    { "type": "83", "uuid": "6ba7b810-9dad-11d1-80b4-00c04fd430c8" }
+                                ]
+                            }
+                        },
+                        {
+                            "name": "org.osbuild.mkfs.fat",
+                            "options": { "volid": "7B7795E7", "label": "efi" }
+                        },
+                        {
+                            "name": "org.osbuild.mkfs.xfs",
+                            "options": { "uuid": "76a22bf4", "label": "root" }
+                        },
+                        {
+                            "name": "org.osbuild.grub2",
+                            "options": { "rootfs": { "uuid": "foo" } }
+                        }
+                    ],
+                    "assembler": {
+                        "name": "org.osbuild.qemu",
+                        "options": {
+                            "ptuuid": "0x14fc63d2",
+                            "partitions": [
+                                { "type": "83", "filesystem": { "type": "ext4", "label": "a-very-long-label" } }
+                            ]
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            check(&manifest).iter().map(|v| v.path.as_str()).collect::<Vec<_>>(),
+            [
+                "/pipeline/stages/0/options/uuid",
+                "/pipeline/stages/0/options/partitions/1/type",
+                "/pipeline/stages/1/options/label",
+                "/pipeline/stages/2/options/uuid",
+                "/pipeline/stages/3/options/rootfs/uuid",
+                "/pipeline/assembler/options/partitions/0/filesystem/label",
+            ],
+        }
+    }
+
+    // Verify Identifier Checks of Version-2 Manifests
+    #[test]
+    fn verify_check_v2() {
+        // File-systems are created on devices, which only exist in
+        // version-2 manifests.
+        let manifest: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    {
+                        "name": "image",
+                        "stages": [
+                            {
+                                "type": "org.osbuild.sfdisk",
+                                "options": { "label": "dos", "uuid": "0x14fc63d2" },
+                                "devices": { "device": { "type": "org.osbuild.loopback" } }
+                            },
+                            {
+                                "type": "org.osbuild.mkfs.fat",
+                                "options": { "volid": "7B7795E7", "label": "EFI-SYSTEM-PART" },
+                                "devices": { "device": { "type": "org.osbuild.loopback" } }
+                            },
+                            {
+                                "type": "org.osbuild.mkfs.ext4",
+                                "options": { "uuid": "not-a-uuid", "label": "root" },
+                                "devices": { "device": { "type": "org.osbuild.loopback" } }
+                            }
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            check(&manifest).iter().map(|v| v.path.as_str()).collect::<Vec<_>>(),
+            [
+                "/pipelines/0/stages/1/options/label",
+                "/pipelines/0/stages/2/options/uuid",
+            ],
+        }
+    }
+}
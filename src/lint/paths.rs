@@ -0,0 +1,211 @@
+//! Path Safety Checks
+//!
+//! Stages that manipulate the file-system tree, like `copy`, `mkdir`, and
+//! `chmod`, take paths into the tree as options. These must be absolute and
+//! must not escape the tree via `..` components. Furthermore, two stages
+//! creating the same path usually indicate a mistake, since the later stage
+//! either fails or silently replaces the result of the former.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{Json, StageRef};
+
+// How a stage writes to a path. Collisions are only reported between writes
+// of the same kind, since changing the mode of a created path is expected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Create,
+    Mode,
+}
+
+// Collect the tree paths written by a stage, together with their JSON
+// pointers and the kind of the write.
+fn writes(stage: &StageRef<'_>, path: &str) -> Vec<(String, String, Kind)> {
+    let mut acc = Vec::new();
+    let options = &stage.options;
+    let base = lint::pointer(path, "options");
+
+    match stage.name {
+        "org.osbuild.copy" => {
+            let list = options.get("paths").and_then(Json::as_array);
+            for (i, v) in list.into_iter().flatten().enumerate() {
+                let to = match v.get("to").and_then(Json::as_str) {
+                    None => continue,
+                    Some(v) => v,
+                };
+                // Only destinations in the tree are checked. Destinations in
+                // mounts are not part of the tree.
+                let to = match to.strip_prefix("tree://") {
+                    Some(v) => v,
+                    None if to.contains("://") => continue,
+                    None => to,
+                };
+                acc.push((
+                    to.to_owned(),
+                    format!("{}/paths/{}/to", base, i),
+                    Kind::Create,
+                ));
+            }
+        }
+        "org.osbuild.mkdir" => {
+            let list = options.get("paths").and_then(Json::as_array);
+            for (i, v) in list.into_iter().flatten().enumerate() {
+                let (p, pointer) = match v {
+                    Json::String(v) => (v.as_str(), format!("{}/paths/{}", base, i)),
+                    v => match v.get("path").and_then(Json::as_str) {
+                        None => continue,
+                        Some(p) => (p, format!("{}/paths/{}/path", base, i)),
+                    },
+                };
+                acc.push((p.to_owned(), pointer, Kind::Create));
+            }
+        }
+        "org.osbuild.chmod" => {
+            let items = options.get("items").and_then(Json::as_object);
+            for key in items.into_iter().flat_map(|v| v.keys()) {
+                acc.push((
+                    key.clone(),
+                    lint::pointer(&lint::pointer(&base, "items"), key),
+                    Kind::Mode,
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    acc
+}
+
+// Normalize a path for comparison by dropping empty and `.` components.
+fn normalize(path: &str) -> String {
+    let v: Vec<_> = path
+        .split('/')
+        .filter(|v| !v.is_empty() && *v != ".")
+        .collect();
+    format!("/{}", v.join("/"))
+}
+
+/// Check Path Safety
+///
+/// Check the paths written by tree-manipulation stages in all pipelines of
+/// the manifest. Relative paths and paths with `..` components are
+/// reported individually. Paths written by more than one stage of the same
+/// pipeline are reported at the later write, naming both stage indices.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+    let mut seen: Vec<(String, String, usize, Kind)> = Vec::new();
+
+    for (path, stage) in manifest.stages() {
+        let (pipeline, index) = match path.rsplit_once("/stages/") {
+            Some((p, i)) => (p, i.parse::<usize>().unwrap_or(0)),
+            None => continue,
+        };
+
+        for (target, pointer, kind) in writes(&stage, &path) {
+            if !target.starts_with('/') {
+                acc.push(Diagnostic::new(
+                    "path-absolute",
+                    Severity::Error,
+                    &pointer,
+                    &format!("path `{}` must be absolute", target),
+                ));
+                continue;
+            }
+            if target.split('/').any(|v| v == "..") {
+                acc.push(Diagnostic::new(
+                    "path-traversal",
+                    Severity::Error,
+                    &pointer,
+                    &format!("path `{}` must not contain `..` components", target),
+                ));
+                continue;
+            }
+
+            let target = normalize(&target);
+            let prev = seen
+                .iter()
+                .find(|v| v.0 == pipeline && v.1 == target && v.3 == kind);
+            match prev {
+                Some(&(_, _, prev, _)) if prev != index => {
+                    acc.push(Diagnostic::new(
+                        "path-collision",
+                        Severity::Warning,
+                        &pointer,
+                        &format!(
+                            "path `{}` is written by both stage {} and stage {}",
+                            target, prev, index,
+                        ),
+                    ));
+                }
+                Some(_) => {}
+                None => seen.push((pipeline.to_owned(), target, index, kind)),
+            }
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Path Safety Checks
+    #[test]
+    fn verify_check() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [
+                                { "name": "org.osbuild.mkdir", "options": { "paths": ["/etc/foo"] } }
+                            ]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        {
+                            "name": "org.osbuild.mkdir",
+                            "options": {
+                                "paths": [
+                                    { "path": "/etc/foo" },
+                                    { "path": "etc/bar" },
+                                    "/etc/../../root"
+                                ]
+                            }
+                        },
+                        {
+                            "name": "org.osbuild.copy",
+                            "options": {
+                                "paths": [
+                                    { "from": "input://a/b", "to": "tree:///etc//foo/" },
+                                    { "from": "input://a/b", "to": "mount://root/etc/foo" }
+                                ]
+                            }
+                        },
+                        {
+                            "name": "org.osbuild.chmod",
+                            "options": { "items": { "/etc/foo": { "mode": "0755" } } }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let diags = check(&manifest);
+        assert_eq! {
+            diags.iter().map(|v| (v.rule.as_str(), v.path.as_str())).collect::<Vec<_>>(),
+            [
+                ("path-absolute", "/pipeline/stages/0/options/paths/1/path"),
+                ("path-traversal", "/pipeline/stages/0/options/paths/2"),
+                ("path-collision", "/pipeline/stages/1/options/paths/0/to"),
+            ],
+        }
+        assert_eq! {
+            diags[2].message,
+            "path `/etc/foo` is written by both stage 0 and stage 1",
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Organization Policies
+//!
+//! This module implements a policy engine for organization-specific rules.
+//! A policy is a set of rules evaluated against a manifest, each producing
+//! diagnostics in the same format as the built-in checks. Rules can either
+//! be arbitrary Rust functions registered by the caller, or declarative
+//! rules, which can be loaded from JSON.
+//!
+//! Declarative rules select parts of the manifest via JSON pointers. In
+//! addition to plain RFC-6901 pointers, a segment of `*` matches every member
+//! of an object or array. For instance, `/sources/*/urls/*` selects all URLs
+//! of all sources.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{Json, ManifestFormat};
+
+/// Declarative Rule
+///
+/// This represents a single declarative rule of a policy. Each rule has an
+/// identifier that is used as rule name of the diagnostics it produces. The
+/// severity defaults to `error`, and an optional message replaces the
+/// default message of the rule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Rule {
+    pub id: String,
+
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+
+    #[serde(default)]
+    pub message: Option<String>,
+
+    #[serde(flatten)]
+    pub check: Check,
+}
+
+/// Declarative Rule Check
+///
+/// The different checks a declarative rule can perform. `RequireStage` and
+/// `ForbidStage` check for the presence of a stage in the pipelines that
+/// end up in the artifact, see `Target::tree_stages()`. `AllowPrefix`
+/// requires all strings selected by `path` to start with one of the given
+/// prefixes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case", tag = "check")]
+pub enum Check {
+    RequireStage { stage: String },
+    ForbidStage { stage: String },
+    AllowPrefix { path: String, prefixes: Vec<String> },
+}
+
+fn default_severity() -> Severity {
+    Severity::Error
+}
+
+type RuleFn = dyn Fn(&dyn Target) -> Vec<Diagnostic> + Send + Sync;
+
+/// Policy
+///
+/// A policy is a collection of rules. Rule functions are called with the
+/// manifest to check and return their diagnostics. Declarative rules are
+/// evaluated by the policy itself.
+#[derive(Default)]
+pub struct Policy {
+    functions: Vec<Box<RuleFn>>,
+    rules: Vec<Rule>,
+}
+
+// Select all values matching a pointer pattern, together with their actual
+// JSON pointers.
+fn select<'a>(value: &'a Json, pattern: &str) -> Vec<(String, &'a Json)> {
+    let mut acc = vec![(String::new(), value)];
+
+    for segment in pattern.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        let mut next = Vec::new();
+
+        for (path, value) in acc {
+            match value {
+                Json::Object(map) => {
+                    for (k, v) in map {
+                        if segment == "*" || segment == *k {
+                            next.push((lint::pointer(&path, k), v));
+                        }
+                    }
+                }
+                Json::Array(list) => {
+                    for (i, v) in list.iter().enumerate() {
+                        if segment == "*" || segment == i.to_string() {
+                            next.push((lint::pointer(&path, &i.to_string()), v));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        acc = next;
+    }
+
+    acc
+}
+
+impl Rule {
+    fn diagnostic(&self, path: &str, message: String) -> Diagnostic {
+        Diagnostic {
+            rule: self.id.clone(),
+            severity: self.severity,
+            path: path.to_owned(),
+            message: self.message.clone().unwrap_or(message),
+        }
+    }
+
+    fn evaluate(&self, manifest: &dyn Target, json: &Json, acc: &mut Vec<Diagnostic>) {
+        let stages = manifest.tree_stages();
+
+        match self.check {
+            Check::RequireStage { ref stage } => {
+                if !stages.iter().any(|(_, v)| v.name == *stage) {
+                    acc.push(self.diagnostic(
+                        manifest.tree(),
+                        format!("required stage `{}` is missing", stage),
+                    ));
+                }
+            }
+            Check::ForbidStage { ref stage } => {
+                for (path, _) in stages.iter().filter(|(_, v)| v.name == *stage) {
+                    acc.push(self.diagnostic(path, format!("stage `{}` is forbidden", stage)));
+                }
+            }
+            Check::AllowPrefix {
+                ref path,
+                ref prefixes,
+            } => {
+                for (path, value) in select(json, path) {
+                    let allowed = value
+                        .as_str()
+                        .is_some_and(|v| prefixes.iter().any(|p| v.starts_with(p.as_str())));
+                    if !allowed {
+                        acc.push(self.diagnostic(
+                            &path,
+                            format!("value {} does not match any allowed prefix", value),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Policy {
+    /// Create Empty Policy
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load Declarative Policy
+    ///
+    /// Create a new policy from a JSON array of declarative rules.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            functions: Vec::new(),
+            rules: serde_json::from_str(json)?,
+        })
+    }
+
+    /// Register Rule Function
+    ///
+    /// Add a custom rule function to the policy. The function is invoked for
+    /// every manifest checked against this policy.
+    pub fn register<F>(&mut self, function: F)
+    where
+        F: Fn(&dyn Target) -> Vec<Diagnostic> + Send + Sync + 'static,
+    {
+        self.functions.push(Box::new(function));
+    }
+
+    /// Add Declarative Rule
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// Check Manifest Against Policy
+    ///
+    /// Evaluate all rules of the policy against the manifest and return the
+    /// combined diagnostics. Declarative rules are evaluated first, in
+    /// order, followed by the registered rule functions.
+    pub fn check<M: Target + ManifestFormat>(&self, manifest: &M) -> Vec<Diagnostic> {
+        let mut acc = Vec::new();
+
+        if !self.rules.is_empty() {
+            let json =
+                serde_json::from_str(&manifest.serialize()).expect("manifests always serialize");
+            for rule in self.rules.iter() {
+                rule.evaluate(manifest, &json, &mut acc);
+            }
+        }
+
+        for function in self.functions.iter() {
+            acc.extend(function(manifest));
+        }
+
+        acc
+    }
+}
+
+impl std::fmt::Debug for Policy {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Policy")
+            .field("functions", &self.functions.len())
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Declarative Rules
+    #[test]
+    fn verify_declarative() {
+        let policy = Policy::from_json(
+            r#"[
+                { "id": "audit", "check": "require-stage", "stage": "org.example.audit" },
+                { "id": "no-legacy", "check": "forbid-stage", "stage": "org.osbuild.legacy" },
+                {
+                    "id": "mirrors",
+                    "severity": "warning",
+                    "check": "allow-prefix",
+                    "path": "/sources/org.osbuild.files/urls/*",
+                    "prefixes": ["https://mirror.example.com/"]
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        // Unknown checks and fields are rejected.
+        assert! {
+            Policy::from_json(r#"[{ "id": "x", "check": "foobar" }]"#).unwrap_err().is_data(),
+        }
+        assert! {
+            Policy::from_json(
+                r#"[{ "id": "x", "check": "forbid-stage", "stage": "x", "foo": 0 }]"#,
+            ).unwrap_err().is_data(),
+        }
+
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "stages": [
+                        { "name": "org.osbuild.legacy" }
+                    ]
+                },
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": {
+                            "sha256:a": "https://mirror.example.com/a.rpm",
+                            "sha256:b": "https://evil.example.org/b.rpm"
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            policy.check(&manifest),
+            [
+                Diagnostic::new(
+                    "audit",
+                    Severity::Error,
+                    "/pipeline/stages",
+                    "required stage `org.example.audit` is missing",
+                ),
+                Diagnostic::new(
+                    "no-legacy",
+                    Severity::Error,
+                    "/pipeline/stages/0",
+                    "stage `org.osbuild.legacy` is forbidden",
+                ),
+                Diagnostic::new(
+                    "mirrors",
+                    Severity::Warning,
+                    "/sources/org.osbuild.files/urls/sha256:b",
+                    "value \"https://evil.example.org/b.rpm\" does not match any allowed prefix",
+                ),
+            ],
+        }
+    }
+
+    // Verify Rule Functions
+    #[test]
+    fn verify_functions() {
+        let mut policy = Policy::new();
+        policy.register(|manifest| {
+            if manifest.assembler().is_none() {
+                vec![Diagnostic::new(
+                    "assembler",
+                    Severity::Note,
+                    "/pipeline",
+                    "no assembler",
+                )]
+            } else {
+                Vec::new()
+            }
+        });
+
+        let manifest: Manifest1 = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(policy.check(&manifest).len(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Reproducibility Checks
+//!
+//! This module detects sources of non-determinism in a manifest. Building
+//! the same manifest twice should produce bit-identical artifacts, but
+//! several stages embed build-time timestamps, machine identifiers, or
+//! randomly generated UUIDs unless explicitly told otherwise.
+//!
+//! Like the compliance checks, only the main pipeline is considered, since
+//! build pipelines do not end up in the artifact.
+
+use crate::lint::{self, Diagnostic, Severity, Target};
+use crate::manifest::{Json, Object};
+
+// Stages and assemblers which embed the build time into their output, unless
+// the timestamp is pinned explicitly.
+const TIMESTAMPS: &[&str] = &[
+    "org.osbuild.oci-archive",
+    "org.osbuild.ostree.commit",
+    "org.osbuild.squashfs",
+    "org.osbuild.tar",
+    "org.osbuild.xorrisofs",
+];
+
+// Options which pin the timestamps embedded by a stage.
+const EPOCH_KEYS: &[&str] = &["source_epoch", "source-epoch", "mtime"];
+
+// Stages which generate a random identifier at build time, unless it is
+// passed explicitly, together with the option that carries it.
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("org.osbuild.mkfs.btrfs", "uuid"),
+    ("org.osbuild.mkfs.ext4", "uuid"),
+    ("org.osbuild.mkfs.fat", "volid"),
+    ("org.osbuild.mkfs.xfs", "uuid"),
+    ("org.osbuild.rawfs", "root_fs_uuid"),
+];
+
+fn check_timestamp(name: &str, options: &Object<Json>, path: &str, acc: &mut Vec<Diagnostic>) {
+    if TIMESTAMPS.contains(&name) && !EPOCH_KEYS.iter().any(|v| options.contains_key(*v)) {
+        acc.push(Diagnostic::new(
+            "reproducibility-timestamp",
+            Severity::Note,
+            path,
+            &format!(
+                "`{}` embeds the build time unless a source epoch is set",
+                name
+            ),
+        ));
+    }
+}
+
+fn check_identifier(name: &str, options: &Object<Json>, path: &str, acc: &mut Vec<Diagnostic>) {
+    for (stage, key) in IDENTIFIERS {
+        if name == *stage && !options.contains_key(*key) {
+            acc.push(Diagnostic::new(
+                "reproducibility-uuid",
+                Severity::Warning,
+                path,
+                &format!("`{}` generates a random `{}` at build time", name, key),
+            ));
+        }
+    }
+}
+
+fn check_qemu(options: &Object<Json>, path: &str, acc: &mut Vec<Diagnostic>) {
+    let uuid = |path: &str, key: &str| {
+        Diagnostic::new(
+            "reproducibility-uuid",
+            Severity::Warning,
+            path,
+            &format!("`{}` is generated randomly at build time", key),
+        )
+    };
+
+    if !options.contains_key("ptuuid") {
+        acc.push(uuid(path, "ptuuid"));
+    }
+
+    match options.get("partitions").and_then(Json::as_array) {
+        None => {
+            if !options.contains_key("root_fs_uuid") {
+                acc.push(uuid(path, "root_fs_uuid"));
+            }
+        }
+        Some(partitions) => {
+            for (i, partition) in partitions.iter().enumerate() {
+                let fs = match partition.get("filesystem") {
+                    None => continue,
+                    Some(v) => v,
+                };
+                if fs.get("uuid").is_none() {
+                    let path = format!("{}/partitions/{}/filesystem", path, i);
+                    acc.push(uuid(&path, "uuid"));
+                }
+            }
+        }
+    }
+}
+
+/// Check Manifest Reproducibility
+///
+/// Check the main pipeline of the manifest for sources of non-determinism
+/// and return a diagnostic for each of them.
+pub fn check<M: Target + ?Sized>(manifest: &M) -> Vec<Diagnostic> {
+    let mut acc = Vec::new();
+
+    for (path, stage) in manifest.tree_stages() {
+        let path = lint::pointer(&path, "options");
+
+        check_timestamp(stage.name, stage.options, &path, &mut acc);
+        check_identifier(stage.name, stage.options, &path, &mut acc);
+
+        if stage.name == "org.osbuild.machine-id"
+            && stage.options.get("first-boot").and_then(Json::as_str) == Some("preserve")
+        {
+            acc.push(Diagnostic::new(
+                "reproducibility-machine-id",
+                Severity::Warning,
+                &path,
+                "the machine-id of the build host is preserved in the artifact",
+            ));
+        }
+    }
+
+    if let Some((path, assembler)) = manifest.assembler() {
+        let path = lint::pointer(&path, "options");
+
+        check_timestamp(assembler.name, assembler.options, &path, &mut acc);
+        check_identifier(assembler.name, assembler.options, &path, &mut acc);
+
+        if assembler.name == "org.osbuild.qemu" {
+            check_qemu(assembler.options, &path, &mut acc);
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Reproducibility Checks
+    #[test]
+    fn verify_check() {
+        // Empty manifests are reproducible.
+        let manifest: Manifest1 = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(check(&manifest), []);
+
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "assembler": {
+                        "name": "org.osbuild.qemu",
+                        "options": {
+                            "ptuuid": "0x14fc63d2",
+                            "partitions": [
+                                { "filesystem": { "type": "ext4" } },
+                                { "filesystem": { "type": "xfs", "uuid": "fb180daf" } },
+                                {}
+                            ]
+                        }
+                    },
+                    "stages": [
+                        {
+                            "name": "org.osbuild.machine-id",
+                            "options": { "first-boot": "preserve" }
+                        },
+                        {
+                            "name": "org.osbuild.mkfs.fat",
+                            "options": { "volid": "7B7795E7" }
+                        },
+                        {
+                            "name": "org.osbuild.tar",
+                            "options": { "filename": "root.tar" }
+                        },
+                        {
+                            "name": "org.osbuild.tar",
+                            "options": { "filename": "root.tar", "source_epoch": 0 }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = check(&manifest);
+        assert_eq! {
+            diagnostics.iter().map(|v| (v.rule.as_str(), v.path.as_str())).collect::<Vec<_>>(),
+            [
+                ("reproducibility-machine-id", "/pipeline/stages/0/options"),
+                ("reproducibility-timestamp", "/pipeline/stages/2/options"),
+                ("reproducibility-uuid", "/pipeline/assembler/options/partitions/0/filesystem"),
+            ],
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! SARIF Output
+//!
+//! This module serializes diagnostics into the Static Analysis Results
+//! Interchange Format (SARIF) version 2.1.0. This allows integrating manifest
+//! checks into code-scanning interfaces of forges like GitHub or GitLab.
+//!
+//! Diagnostics refer to manifest locations via JSON pointers. If the source
+//! text of the manifest is provided, these pointers are mapped to line and
+//! column positions in the file. Otherwise, only logical locations are
+//! reported.
+
+use crate::lint::{Diagnostic, Severity};
+use crate::manifest::Json;
+
+/// Manifest Artifact
+///
+/// This describes the manifest file the diagnostics were produced for. The
+/// URI is reported verbatim, and the source text is used to map JSON
+/// pointers to physical locations.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Artifact<'a> {
+    pub uri: &'a str,
+    pub source: Option<&'a str>,
+}
+
+struct Scanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn ws(&mut self) {
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.ws();
+        self.data.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> Option<()> {
+        if self.peek()? == c {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    // Skip a string and return its raw text, including the quotes.
+    fn string(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        self.eat(b'"')?;
+        loop {
+            match *self.data.get(self.pos)? {
+                b'\\' => self.pos += 2,
+                b'"' => break,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        std::str::from_utf8(&self.data[start..self.pos]).ok()
+    }
+
+    fn value(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'"' => {
+                self.string()?;
+            }
+            b'{' | b'[' => {
+                let mut depth = 0usize;
+                loop {
+                    match self.peek()? {
+                        b'"' => {
+                            self.string()?;
+                            continue;
+                        }
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => {}
+                    }
+                    self.pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                while self.pos < self.data.len() && !b",]} \t\r\n".contains(&self.data[self.pos]) {
+                    self.pos += 1;
+                }
+            }
+        }
+        Some(())
+    }
+
+    // Descend into the member or element named by `segment` of the current
+    // value, leaving the scanner at the start of it.
+    fn descend(&mut self, segment: &str) -> Option<()> {
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                loop {
+                    let key: String = serde_json::from_str(self.string()?).ok()?;
+                    self.eat(b':')?;
+                    if key == segment {
+                        self.ws();
+                        return Some(());
+                    }
+                    self.value()?;
+                    self.eat(b',')?;
+                }
+            }
+            b'[' => {
+                let index: usize = segment.parse().ok()?;
+                self.pos += 1;
+                for _ in 0..index {
+                    self.value()?;
+                    self.eat(b',')?;
+                }
+                self.ws();
+                Some(())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Locate JSON Pointer
+///
+/// Find the value referenced by the JSON pointer in the given JSON source
+/// text and return its position as 1-based line and column. Columns are
+/// counted in UTF-16 code units, as is the default for SARIF. Returns `None`
+/// if the pointer does not resolve.
+pub fn locate(source: &str, pointer: &str) -> Option<(usize, usize)> {
+    let mut scanner = Scanner {
+        data: source.as_bytes(),
+        pos: 0,
+    };
+
+    scanner.ws();
+    for segment in pointer.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        scanner.descend(&segment)?;
+    }
+
+    let prefix = source.get(..scanner.pos)?;
+    let line = prefix.matches('\n').count() + 1;
+    let start = prefix.rfind('\n').map_or(0, |v| v + 1);
+    let column = prefix[start..].encode_utf16().count() + 1;
+
+    Some((line, column))
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Note => "note",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+/// Serialize Diagnostics to SARIF
+///
+/// Create a SARIF log with a single run containing the given diagnostics
+/// as results. The rules referenced by the diagnostics are listed in the
+/// tool description, in order of first appearance.
+pub fn to_sarif(diagnostics: &[Diagnostic], artifact: Option<Artifact<'_>>) -> Json {
+    let mut rules: Vec<&str> = Vec::new();
+    let mut results = Vec::new();
+
+    for diagnostic in diagnostics {
+        let index = match rules.iter().position(|v| *v == diagnostic.rule) {
+            Some(v) => v,
+            None => {
+                rules.push(&diagnostic.rule);
+                rules.len() - 1
+            }
+        };
+
+        let mut location = serde_json::json!({
+            "logicalLocations": [{
+                "fullyQualifiedName": diagnostic.path,
+                "kind": "member",
+            }],
+        });
+
+        if let Some(artifact) = artifact {
+            let mut physical = serde_json::json!({
+                "artifactLocation": { "uri": artifact.uri },
+            });
+            if let Some((line, column)) = artifact.source.and_then(|v| locate(v, &diagnostic.path))
+            {
+                physical["region"] = serde_json::json!({
+                    "startLine": line,
+                    "startColumn": column,
+                });
+            }
+            location["physicalLocation"] = physical;
+        }
+
+        results.push(serde_json::json!({
+            "ruleId": diagnostic.rule,
+            "ruleIndex": index,
+            "level": level(diagnostic.severity),
+            "message": { "text": diagnostic.message },
+            "locations": [location],
+        }));
+    }
+
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                    "rules": rules.iter().map(|v| serde_json::json!({ "id": v })).collect::<Vec<_>>(),
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Pointer Locations
+    #[test]
+    fn verify_locate() {
+        let source = "{\n  \"a\": [1, {\"b~/\": \"ä\", \"c\": 2}],\n  \"d\": {}\n}";
+
+        assert_eq!(locate(source, ""), Some((1, 1)));
+        assert_eq!(locate(source, "/a"), Some((2, 8)));
+        assert_eq!(locate(source, "/a/0"), Some((2, 9)));
+        assert_eq!(locate(source, "/a/1/b~0~1"), Some((2, 20)));
+        assert_eq!(locate(source, "/a/1/c"), Some((2, 30)));
+        assert_eq!(locate(source, "/d"), Some((3, 8)));
+        assert_eq!(locate(source, "/e"), None);
+        assert_eq!(locate(source, "/a/2"), None);
+    }
+
+    // Verify SARIF Serialization
+    #[test]
+    fn verify_sarif() {
+        let source = "{\n  \"pipeline\": {}\n}";
+        let diagnostics = [
+            Diagnostic::new("foo", Severity::Warning, "/pipeline", "foo"),
+            Diagnostic::new("bar", Severity::Error, "/sources", "bar"),
+            Diagnostic::new("foo", Severity::Note, "/pipeline", "foo"),
+        ];
+        let log = to_sarif(
+            &diagnostics,
+            Some(Artifact {
+                uri: "manifest.json",
+                source: Some(source),
+            }),
+        );
+        let run = &log["runs"][0];
+
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 2);
+        assert_eq!(run["results"][2]["ruleIndex"], 0);
+        assert_eq!(run["results"][2]["level"], "note");
+        assert_eq! {
+            run["results"][0]["locations"][0]["physicalLocation"],
+            serde_json::json!({
+                "artifactLocation": { "uri": "manifest.json" },
+                "region": { "startLine": 2, "startColumn": 15 },
+            }),
+        }
+        assert! {
+            run["results"][1]["locations"][0]["physicalLocation"].get("region").is_none(),
+        }
+    }
+}
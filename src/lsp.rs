@@ -0,0 +1,468 @@
+//! Language Server
+//!
+//! This module implements a Language Server Protocol (LSP) server for
+//! editing manifests. It publishes parse errors and lint diagnostics for
+//! JSON manifests of either format version, shows catalog documentation
+//! when hovering stage and source names, and completes names from the
+//! catalog. Hover and completion only look at the text around the cursor,
+//! so they work in YAML manifests as well, while diagnostics are limited
+//! to JSON documents.
+//!
+//! The server speaks JSON-RPC over a byte stream, framed with
+//! `Content-Length` headers, as editors use for language servers launched
+//! as subprocesses. Documents are synchronized in full on every change.
+
+use crate::catalog;
+use crate::lint::{self, sarif, Severity};
+use crate::manifest::{Json, Manifest, Object};
+use std::io::{BufRead, Write};
+
+/// Read Message
+///
+/// Read a single framed JSON-RPC message from the stream. Returns `None` at
+/// the end of the stream.
+pub fn read_message(reader: &mut dyn BufRead) -> std::io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            if key.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut data = vec![0u8; length];
+    reader.read_exact(&mut data)?;
+    serde_json::from_slice(&data)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Write Message
+///
+/// Write a single JSON-RPC message to the stream, framed with its length.
+pub fn write_message(writer: &mut dyn Write, message: &Json) -> std::io::Result<()> {
+    let data = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", data.len(), data)?;
+    writer.flush()
+}
+
+// Map a 0-based LSP position, counted in UTF-16 code units, to a byte
+// offset into the text.
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let start = match line {
+        0 => 0,
+        n => match text.match_indices('\n').nth(n - 1) {
+            Some((v, _)) => v + 1,
+            None => return text.len(),
+        },
+    };
+
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_'
+}
+
+// Return the word around the byte offset, and the part of it before the
+// offset.
+fn word(text: &str, offset: usize) -> (&str, &str) {
+    let start = text[..offset].rfind(|c| !is_word(c)).map_or(0, |v| v + 1);
+    let end = text[offset..]
+        .find(|c| !is_word(c))
+        .map_or(text.len(), |v| offset + v);
+    (&text[start..end], &text[start..offset])
+}
+
+fn range(line: usize, character: usize) -> Json {
+    let position = serde_json::json!({ "line": line, "character": character });
+    serde_json::json!({ "start": position, "end": position })
+}
+
+fn diagnostic(position: (usize, usize), severity: u8, code: Option<&str>, message: &str) -> Json {
+    let mut acc = serde_json::json!({
+        "range": range(position.0, position.1),
+        "severity": severity,
+        "source": "r-osbuild",
+        "message": message,
+    });
+    if let Some(v) = code {
+        acc["code"] = Json::String(v.to_owned());
+    }
+    acc
+}
+
+// Map a 1-based line and column of a parse error to an LSP position.
+fn parse_error(e: &serde_json::Error) -> Json {
+    let position = (e.line().saturating_sub(1), e.column().saturating_sub(1));
+    diagnostic(position, 1, None, &e.to_string())
+}
+
+/// Document Diagnostics
+///
+/// Parse the JSON text of a manifest and return its diagnostics as LSP
+/// `Diagnostic` objects. Manifests are checked against the structure of
+/// their format version and linted in that version.
+pub fn diagnostics(text: &str) -> Vec<Json> {
+    let json: Json = match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(e) => return vec![parse_error(&e)],
+    };
+
+    let manifest = match json.get("version") {
+        None => serde_json::from_str(text).map(Manifest::V1),
+        Some(Json::String(v)) if v == "2" => serde_json::from_str(text).map(Manifest::V2),
+        Some(v) => {
+            let (line, column) = sarif::locate(text, "/version").unwrap_or((1, 1));
+            let message = format!("unsupported manifest version {}", v);
+            return vec![diagnostic((line - 1, column - 1), 1, None, &message)];
+        }
+    };
+    let manifest = match manifest {
+        Ok(v) => v,
+        Err(e) => return vec![parse_error(&e)],
+    };
+
+    lint::check(&manifest)
+        .into_iter()
+        .map(|d| {
+            let (line, column) = sarif::locate(text, &d.path).unwrap_or((1, 1));
+            let severity = match d.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+                Severity::Note => 3,
+            };
+            diagnostic((line - 1, column - 1), severity, Some(&d.rule), &d.message)
+        })
+        .collect()
+}
+
+/// Hover Documentation
+///
+/// Return the catalog documentation of the stage or source name at the
+/// byte offset of the text, as Markdown.
+pub fn hover(text: &str, offset: usize) -> Option<String> {
+    let (name, _) = word(text, offset);
+    match catalog::lookup(name) {
+        v if v.is_empty() => None,
+        v => Some(catalog::to_markdown(&v)),
+    }
+}
+
+/// Completion Candidates
+///
+/// Return all catalog names matching the word before the byte offset of
+/// the text, as LSP `CompletionItem` objects.
+pub fn complete(text: &str, offset: usize) -> Vec<Json> {
+    let (_, prefix) = word(text, offset);
+    catalog::CATALOG
+        .iter()
+        .filter(|v| v.name.starts_with(prefix))
+        .map(|v| {
+            serde_json::json!({
+                "label": v.name,
+                "kind": 12,
+                "detail": v.kind.as_str(),
+                "documentation": v.description,
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+struct Document {
+    text: String,
+    json: bool,
+}
+
+/// Language Server State
+#[derive(Clone, Debug, Default)]
+pub struct Server {
+    documents: Object<Document>,
+    shutdown: bool,
+    exit: Option<i32>,
+}
+
+impl Server {
+    /// Create Server
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exit Code
+    ///
+    /// Return the exit code once the client asked the server to exit. It is
+    /// 0 if the client shut the server down properly before, 1 otherwise.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    fn publish(&self, uri: &str) -> Json {
+        let diagnostics = match self.documents.get(uri) {
+            Some(v) if v.json => diagnostics(&v.text),
+            _ => Vec::new(),
+        };
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    // Resolve the document and byte offset of a text-document position.
+    fn position(&self, params: &Json) -> Option<(&str, usize)> {
+        let text = &self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?
+            .text;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((text, offset(text, line, character)))
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        match method {
+            "initialize" => Ok(serde_json::json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                },
+                "serverInfo": { "name": "r-osbuild" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => Ok(self
+                .position(params)
+                .and_then(|(text, offset)| hover(text, offset))
+                .map_or(
+                    Json::Null,
+                    |v| serde_json::json!({ "contents": { "kind": "markdown", "value": v } }),
+                )),
+            "textDocument/completion" => Ok(Json::Array(
+                self.position(params)
+                    .map(|(text, offset)| complete(text, offset))
+                    .unwrap_or_default(),
+            )),
+            v => Err((-32601, format!("method not found: {}", v))),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let json = match document["languageId"].as_str() {
+                    Some("json") | Some("jsonc") => true,
+                    Some(_) => false,
+                    None => !uri.ends_with(".yaml") && !uri.ends_with(".yml"),
+                };
+                let text = document["text"].as_str().unwrap_or_default().to_owned();
+                self.documents
+                    .insert(uri.to_owned(), Document { text, json });
+                vec![self.publish(uri)]
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|v| v.last())
+                    .and_then(|v| v["text"].as_str());
+                match (self.documents.get_mut(uri), text) {
+                    (Some(document), Some(text)) => {
+                        document.text = text.to_owned();
+                        vec![self.publish(uri)]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![self.publish(uri)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handle Message
+    ///
+    /// Handle a single JSON-RPC message of the client and return the
+    /// messages to send back, which are the response to a request and any
+    /// notifications the message triggered.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        match message.get("id") {
+            None => self.notify(method, params),
+            Some(id) => {
+                let response = match self.request(method, params) {
+                    Ok(v) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": v }),
+                    Err((code, v)) => serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": v },
+                    }),
+                };
+                vec![response]
+            }
+        }
+    }
+}
+
+/// Run Server
+///
+/// Serve a client on the given streams until it asks the server to exit,
+/// or closes the input. Returns the exit code for the server process.
+pub fn run(reader: &mut dyn BufRead, writer: &mut dyn Write) -> std::io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(reader)? {
+        for v in server.handle(&message) {
+            write_message(writer, &v)?;
+        }
+        if let Some(v) = server.exit_code() {
+            return Ok(v);
+        }
+    }
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(messages: &[Json]) -> Vec<u8> {
+        let mut acc = Vec::new();
+        for v in messages {
+            write_message(&mut acc, v).unwrap();
+        }
+        acc
+    }
+
+    // Verify Position Mapping
+    #[test]
+    fn verify_offset() {
+        let text = "ab\n😀cd\n";
+        assert_eq!(offset(text, 0, 1), 1);
+        assert_eq!(offset(text, 1, 2), 7);
+        assert_eq!(offset(text, 1, 99), 9);
+        assert_eq!(offset(text, 9, 0), text.len());
+        assert_eq!(
+            word("x: org.osbuild.rpm\n", 8),
+            ("org.osbuild.rpm", "org.o")
+        );
+    }
+
+    // Verify Language Server Session
+    #[test]
+    fn verify_session() {
+        let uri = "file:///manifest.json";
+        let text = "{\n  \"pipeline\": {\n    \"stages\": [\n      { \"name\": \"org.osbuild.grub2\", \"options\": { \"root_fs_uuid\": \"6e4ff95f-f662-45ee-a82a-bdf44a2d0b75\" } }\n    ]\n  }\n}\n";
+        let input = frame(&[
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "json", "version": 1, "text": text } },
+            }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/hover",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 3, "character": 24 } },
+            }),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "textDocument/completion",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 3, "character": 30 } },
+            }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 4, "method": "unknown" }),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        let mut output = Vec::new();
+        assert_eq!(run(&mut &input[..], &mut output).unwrap(), 0);
+
+        let mut reader = &output[..];
+        let mut replies = Vec::new();
+        while let Some(v) = read_message(&mut reader).unwrap() {
+            replies.push(v);
+        }
+        assert_eq!(replies.len(), 6);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let diagnostics = replies[1]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["code"], "deprecated-option");
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 3);
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 66);
+
+        let hover = replies[2]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.starts_with("## `org.osbuild.grub2` (stage)"));
+
+        let labels: Vec<_> = replies[3]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["org.osbuild.grub2"]);
+
+        assert_eq!(replies[4]["error"]["code"], -32601);
+        assert_eq!(replies[5]["result"], Json::Null);
+    }
+
+    // Verify Document Diagnostics
+    #[test]
+    fn verify_diagnostics() {
+        let d = diagnostics("{\n  \"pipelines\": {}\n}");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0]["severity"], 1);
+        assert_eq!(d[0]["range"]["start"]["line"], 1);
+
+        assert!(diagnostics(r#"{ "version": "2", "pipelines": [] }"#).is_empty());
+        assert!(diagnostics(r#"{ "pipeline": {} }"#).is_empty());
+
+        // Version-2 manifests are checked natively, including devices and
+        // mounts, which have no version-1 equivalent.
+        let d = diagnostics(
+            "{\n  \"version\": \"2\",\n  \"pipelines\": [{ \"name\": \"image\", \"stages\": [{\n    \"type\": \"org.osbuild.mkfs.ext4\",\n    \"options\": { \"uuid\": \"x\" },\n    \"devices\": { \"device\": { \"type\": \"org.osbuild.loopback\" } }\n  }] }]\n}",
+        );
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0]["range"]["start"]["line"], 4);
+        let d = diagnostics("{\n  \"version\": \"2\",\n  \"pipelines\": [{ \"stages\": 1 }]\n}");
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0]["severity"], 1);
+        assert_eq!(d[0]["range"]["start"]["line"], 2);
+        let d = diagnostics("{\n  \"version\": \"3\"\n}");
+        assert_eq!(d[0]["range"]["start"]["line"], 1);
+    }
+}
@@ -2,28 +2,43 @@
 //!
 //! This module implements a strongly-typed Rust representation of the
 //! osbuild manifest format. Additionally, it provides serialization and
-//! deserialization support to and from JSON.
+//! deserialization support via the `serde` feature, and JSON support via
+//! the `json` feature.
 //!
 //! Note that there are multiple versions of the format. They are provided
 //! as distinct formats, but share sub-parts of their structures. A special
 //! parser allows detecting the format automatically and returning the
 //! correct format.
+//!
+//! This module only requires `alloc`, so the manifest types are available
+//! without the `std` feature.
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(all(feature = "serde", not(feature = "json")))]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
+pub use crate::runner::Runner;
+pub use crate::symbol::Symbol;
 
 /// Manifest1 Definition
 ///
 /// This type represents the root node of an osbuild manifest v1. It contains
 /// a single pipeline and sources.
-#[derive(Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
 pub struct Manifest1 {
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub pipeline: Pipeline1,
 
-    #[serde(default)]
-    pub sources: Object<Object<Json>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sources: Sources,
 
-    #[serde(default, flatten)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     object_marker: ObjectMarker,
 }
 
@@ -37,20 +52,20 @@ pub struct Manifest1 {
 /// Additionally, a build pipeline can be specified, which is a pipeline by
 /// itself and defines the environment the stages of the embedding pipeline are
 /// run in. This can be stacked arbitrarily deep.
-#[derive(Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
 pub struct Pipeline1 {
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub assembler: Option<Assembler1>,
 
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub build: Option<Box<Build1>>,
 
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub stages: Array<Stage1>,
 
-    #[serde(default, flatten)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     object_marker: ObjectMarker,
 }
 
@@ -59,16 +74,16 @@ pub struct Pipeline1 {
 /// The manifest v1 assemblers are quite similar to the stages, but are limited
 /// to one assembler per pipeline. They operate on the output of the final
 /// stage and produces the resulting artifact of the pipeline.
-#[derive(Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
 pub struct Assembler1 {
-    pub name: String,
+    pub name: Symbol,
 
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub options: Object<Json>,
 
-    #[serde(default, flatten)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     object_marker: ObjectMarker,
 }
 
@@ -78,15 +93,15 @@ pub struct Assembler1 {
 /// pipeline. It simply combines another pipeline with a runner designator. The
 /// runner defines the execution helper used to run stages in the specified
 /// build environment.
-#[derive(Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
 pub struct Build1 {
     pub pipeline: Pipeline1,
 
-    pub runner: String,
+    pub runner: Runner,
 
-    #[serde(default, flatten)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     object_marker: ObjectMarker,
 }
 
@@ -95,26 +110,717 @@ pub struct Build1 {
 /// The individual stages of a pipeline are defined by this type. They have an
 /// associated name to specify the stage-type to pick. Additionally, the option
 /// object contains arbitrary options that are passed to the stage.
-#[derive(Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
 pub struct Stage1 {
+    pub name: Symbol,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub options: Object<Json>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    object_marker: ObjectMarker,
+}
+
+/// Manifest2 Definition
+///
+/// This type represents the root node of an osbuild manifest v2. Unlike
+/// version 1, pipelines are listed flat and refer to their build pipelines
+/// by name. The `version` member must be `"2"`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
+pub struct Manifest2 {
+    pub version: String,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub pipelines: Array<Pipeline2>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Sources::is_empty")
+    )]
+    pub sources: Sources,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    object_marker: ObjectMarker,
+}
+
+/// Pipeline2 Definition
+///
+/// A named pipeline of the manifest v2. The build pipeline is referenced
+/// as `name:<pipeline>`, together with the runner to use in it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
+pub struct Pipeline2 {
     pub name: String,
 
-    #[serde(default)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub build: Option<String>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub runner: Option<Runner>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            rename = "source-epoch",
+            skip_serializing_if = "Option::is_none"
+        )
+    )]
+    pub source_epoch: Option<u64>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub stages: Array<Stage2>,
+
+    /// Export metadata, which is not part of the manifest format and is
+    /// neither serialized nor deserialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub export: Option<Export>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    object_marker: ObjectMarker,
+}
+
+/// Export Metadata
+///
+/// Marks a pipeline of the manifest v2 as intended for export, together
+/// with the artifact it produces. Generators of manifests can record this,
+/// so tools do not have to guess the exports from naming conventions.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Export {
+    /// Format of the artifact, like `qcow2` or `tar`.
+    pub format: Option<String>,
+    /// Filename of the artifact in the exported tree.
+    pub filename: Option<String>,
+}
+
+/// Stage2 Definition
+///
+/// A stage of the manifest v2. Besides options, stages take inputs from
+/// sources or other pipelines, and can have devices and mounts. These are
+/// carried as opaque JSON.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields, remote = "Self"))]
+pub struct Stage2 {
+    pub r#type: Symbol,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub id: Option<String>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Object::is_empty")
+    )]
     pub options: Object<Json>,
 
-    #[serde(default, flatten)]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Object::is_empty")
+    )]
+    pub inputs: Object<Json>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Object::is_empty")
+    )]
+    pub devices: Object<Json>,
+
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub mounts: Array<Json>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
     object_marker: ObjectMarker,
 }
 
+impl Pipeline1 {
+    /// Iterate Pipelines Deeply
+    ///
+    /// Iterate the pipeline and its nested build pipelines in execution
+    /// order, starting with the innermost build pipeline and ending with
+    /// this pipeline.
+    pub fn iter_pipelines_deep(&self) -> impl DoubleEndedIterator<Item = &Pipeline1> {
+        let mut chain = vec![self];
+        while let Some(build) = &chain[chain.len() - 1].build {
+            chain.push(&build.pipeline);
+        }
+        chain.into_iter().rev()
+    }
+
+    /// Iterate Stages Deeply
+    ///
+    /// Iterate the stages of the pipeline and its nested build pipelines in
+    /// execution order. Assemblers are not included.
+    pub fn iter_stages_deep(&self) -> impl Iterator<Item = &Stage1> {
+        self.iter_pipelines_deep().flat_map(|v| v.stages.iter())
+    }
+}
+
+impl Pipeline2 {
+    /// Pipeline Dependencies
+    ///
+    /// Return the names of the pipelines this pipeline depends on, either
+    /// as build pipeline or via pipeline inputs of its stages. Duplicates
+    /// are removed.
+    pub fn dependencies(&self) -> Vec<&str> {
+        dependencies(self.build.as_deref(), self.stages.iter().map(|v| &v.inputs))
+    }
+}
+
+// Collect the pipeline dependencies of a version-2 pipeline from its build
+// pipeline and the inputs of its stages.
+fn dependencies<'a>(
+    build: Option<&'a str>,
+    inputs: impl Iterator<Item = &'a Object<Json>>,
+) -> Vec<&'a str> {
+    let mut acc: Vec<&str> = Vec::new();
+    let inputs = inputs
+        .flat_map(|v| v.values())
+        .filter(|v| v.get("origin").and_then(Json::as_str) == Some("org.osbuild.pipeline"))
+        .filter_map(|v| v.get("references"));
+    let references = inputs.flat_map(|v| -> Vec<&str> {
+        match v {
+            Json::Array(v) => v
+                .iter()
+                .filter_map(|v| v.as_str().or_else(|| v.get("id")?.as_str()))
+                .collect(),
+            Json::Object(v) => v.keys().map(String::as_str).collect(),
+            _ => Vec::new(),
+        }
+    });
+
+    for v in build.into_iter().chain(references) {
+        let name = v.strip_prefix("name:").unwrap_or(v);
+        if !acc.contains(&name) {
+            acc.push(name);
+        }
+    }
+    acc
+}
+
+impl Manifest2 {
+    /// Iterate Pipelines Topologically
+    ///
+    /// Iterate the pipelines so every pipeline comes after the pipelines it
+    /// depends on. Independent pipelines retain their order in the
+    /// manifest. References to unknown pipelines are ignored. Returns
+    /// `None` if the dependencies are cyclic.
+    pub fn iter_pipelines_topological(&self) -> Option<impl Iterator<Item = &Pipeline2>> {
+        let index = |name: &str| self.pipelines.iter().position(|v| v.name == name);
+        let deps: Vec<Vec<usize>> = self
+            .pipelines
+            .iter()
+            .map(|v| v.dependencies().into_iter().filter_map(index).collect())
+            .collect();
+
+        let mut done = vec![false; self.pipelines.len()];
+        let mut acc = Vec::with_capacity(self.pipelines.len());
+        while acc.len() < self.pipelines.len() {
+            let next = (0..self.pipelines.len())
+                .find(|&i| !done[i] && deps[i].iter().all(|&d| done[d]))?;
+            done[next] = true;
+            acc.push(&self.pipelines[next]);
+        }
+        Some(acc.into_iter())
+    }
+}
+
+/// Stage Reference
+///
+/// A version-independent view of a stage, as returned by
+/// `ManifestFormat::pipelines()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StageRef<'a> {
+    pub name: &'a str,
+    pub options: &'a Object<Json>,
+}
+
+/// Pipeline Reference
+///
+/// A version-independent view of a pipeline, as returned by
+/// `ManifestFormat::pipelines()`. The build pipeline is given by name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PipelineRef<'a> {
+    pub name: String,
+    pub build: Option<String>,
+    pub stages: Vec<StageRef<'a>>,
+}
+
+/// Common Manifest Interface
+///
+/// This trait is implemented by all manifest versions, so tooling can be
+/// written once rather than for each version. Pipelines of version-1
+/// manifests are named like their version-2 conversions: `build-<n>` to
+/// `build` for the build chain, `tree` for the main pipeline, and
+/// `assembler` for its assembler.
+pub trait ManifestFormat {
+    /// Format version of the manifest.
+    fn version(&self) -> u32;
+    /// Pipelines of the manifest, in execution order.
+    fn pipelines(&self) -> Vec<PipelineRef<'_>>;
+    /// Sources of the manifest, by source type.
+    fn sources(&self) -> &Sources;
+    /// Serialize the manifest to JSON text.
+    #[cfg(feature = "json")]
+    fn serialize(&self) -> String;
+    /// Serialize the manifest to JSON text as described by the options.
+    #[cfg(feature = "json")]
+    fn to_string_with(&self, options: &crate::serialize::SerializeOptions) -> String;
+}
+
+// Name of the build pipeline at the given depth below the main pipeline,
+// as used when converting version-1 manifests.
+pub(crate) fn build_name(depth: usize) -> String {
+    match depth {
+        1 => "build".to_owned(),
+        n => format!("build-{}", n),
+    }
+}
+
+impl ManifestFormat for Manifest1 {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn pipelines(&self) -> Vec<PipelineRef<'_>> {
+        let depth = self.pipeline.iter_pipelines_deep().count();
+        let mut acc: Vec<_> = self
+            .pipeline
+            .iter_pipelines_deep()
+            .zip((0..depth).rev())
+            .map(|(v, i)| PipelineRef {
+                name: if i == 0 {
+                    "tree".to_owned()
+                } else {
+                    build_name(i)
+                },
+                build: (i + 1 < depth).then(|| build_name(i + 1)),
+                stages: v
+                    .stages
+                    .iter()
+                    .map(|v| StageRef {
+                        name: &v.name,
+                        options: &v.options,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        if let Some(v) = &self.pipeline.assembler {
+            acc.push(PipelineRef {
+                name: "assembler".to_owned(),
+                build: (depth > 1).then(|| build_name(1)),
+                stages: vec![StageRef {
+                    name: &v.name,
+                    options: &v.options,
+                }],
+            });
+        }
+        acc
+    }
+
+    fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    #[cfg(feature = "json")]
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("manifests always serialize")
+    }
+
+    #[cfg(feature = "json")]
+    fn to_string_with(&self, options: &crate::serialize::SerializeOptions) -> String {
+        crate::serialize::to_string_with(self, options).expect("manifests always serialize")
+    }
+}
+
+impl ManifestFormat for Manifest2 {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn pipelines(&self) -> Vec<PipelineRef<'_>> {
+        self.pipelines
+            .iter()
+            .map(|v| PipelineRef {
+                name: v.name.clone(),
+                build: v
+                    .build
+                    .as_ref()
+                    .map(|v| v.strip_prefix("name:").unwrap_or(v).to_owned()),
+                stages: v
+                    .stages
+                    .iter()
+                    .map(|v| StageRef {
+                        name: &v.r#type,
+                        options: &v.options,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    #[cfg(feature = "json")]
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("manifests always serialize")
+    }
+
+    #[cfg(feature = "json")]
+    fn to_string_with(&self, options: &crate::serialize::SerializeOptions) -> String {
+        crate::serialize::to_string_with(self, options).expect("manifests always serialize")
+    }
+}
+
+/// Manifest of Any Version
+///
+/// This is returned by the format-detecting parser. Manifests carrying a
+/// `version` member are parsed according to it, all others as version 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Manifest {
+    V1(Manifest1),
+    V2(Manifest2),
+}
+
+impl Manifest {
+    /// Parse Manifest
+    ///
+    /// Parse JSON text as manifest, detecting the format version.
+    #[cfg(feature = "json")]
+    pub fn parse(text: &str) -> Result<Self, crate::error::Error> {
+        span!("parse");
+        let manifest = Self::parse_inner(text)?;
+        event!(
+            Debug,
+            "parsed",
+            version = manifest.version(),
+            manifest = crate::store::ManifestStore::id(&manifest),
+        );
+        Ok(manifest)
+    }
+
+    #[cfg(feature = "json")]
+    fn parse_inner(text: &str) -> Result<Self, crate::error::Error> {
+        // Parse optimistically as either version first. Manifests of the
+        // other version usually fail on their first member, so this parses
+        // valid manifests almost in a single pass. Only invalid manifests
+        // pay for detecting the version, which yields the precise error.
+        if let Ok(v) = serde_json::from_str(text) {
+            return Ok(Manifest::V1(v));
+        }
+        match serde_json::from_str::<Manifest2>(text) {
+            Ok(v) if v.version == "2" => return Ok(Manifest::V2(v)),
+            _ => {}
+        }
+
+        match peek_version(text)? {
+            1 => text.parse().map(Manifest::V1),
+            _ => text.parse().map(Manifest::V2),
+        }
+    }
+
+    /// Manifest from JSON Value
+    ///
+    /// Convert a JSON value to a manifest, detecting the format version
+    /// like `Manifest::parse()`. This serves formats that decode to JSON
+    /// values rather than text.
+    #[cfg(feature = "json")]
+    pub fn from_value(value: Json) -> Result<Self, crate::error::Error> {
+        match value.get("version") {
+            None => serde_json::from_value(value)
+                .map(Manifest::V1)
+                .map_err(|e| crate::error::Error::parse(Some(1), e)),
+            Some(Json::String(v)) if v == "2" => serde_json::from_value(value)
+                .map(Manifest::V2)
+                .map_err(|e| crate::error::Error::parse(Some(2), e)),
+            Some(v) => Err(version_error(v)),
+        }
+    }
+
+    fn inner(&self) -> &dyn ManifestFormat {
+        match self {
+            Manifest::V1(v) => v,
+            Manifest::V2(v) => v,
+        }
+    }
+}
+
+impl ManifestFormat for Manifest {
+    fn version(&self) -> u32 {
+        self.inner().version()
+    }
+
+    fn pipelines(&self) -> Vec<PipelineRef<'_>> {
+        self.inner().pipelines()
+    }
+
+    fn sources(&self) -> &Sources {
+        self.inner().sources()
+    }
+
+    #[cfg(feature = "json")]
+    fn serialize(&self) -> String {
+        self.inner().serialize()
+    }
+
+    #[cfg(feature = "json")]
+    fn to_string_with(&self, options: &crate::serialize::SerializeOptions) -> String {
+        self.inner().to_string_with(options)
+    }
+}
+
+/// Version Error
+///
+/// Return the error for a manifest with the given unsupported `version`
+/// member. Versions must be strings, like `"2"`, so other values are
+/// reported as malformed rather than as unsupported versions.
+#[cfg(feature = "json")]
+pub fn version_error(version: &Json) -> crate::error::Error {
+    match version {
+        Json::String(v) => crate::error::Error::Version(v.clone()),
+        v => crate::error::Error::Parse {
+            version: None,
+            line: 0,
+            column: 0,
+            path: "/version".to_owned(),
+            message: format!("the manifest version must be a string, not `{}`", v),
+        },
+    }
+}
+
+/// Peek Manifest Version
+///
+/// Return the format version of the manifest, without parsing anything
+/// but its `version` member.
+#[cfg(feature = "json")]
+pub fn peek_version(text: &str) -> Result<u32, crate::error::Error> {
+    #[derive(serde::Deserialize)]
+    struct Version {
+        version: Option<Json>,
+    }
+
+    let version: Version = serde_json::from_str(text)?;
+    match version.version {
+        None => Ok(1),
+        Some(Json::String(v)) if v == "2" => Ok(2),
+        Some(v) => Err(version_error(&v)),
+    }
+}
+
+/// Manifest Header
+///
+/// The summary of a manifest returned by `parse_header()`. Pipelines are
+/// named as by `ManifestFormat::pipelines()`. Exports are the pipelines
+/// no other pipeline depends on, which are the candidates to export.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub pipelines: Vec<String>,
+    pub exports: Vec<String>,
+}
+
+/// Parse Manifest Header
+///
+/// Parse the version, pipeline names, and exports of a manifest. Stage
+/// options and sources are skipped without being materialized, and
+/// unknown members are ignored.
+#[cfg(feature = "json")]
+pub fn parse_header(text: &str) -> Result<Header, crate::error::Error> {
+    use serde::de::IgnoredAny;
+
+    #[derive(serde::Deserialize)]
+    struct Pipeline1 {
+        build: Option<Box<Build1>>,
+        assembler: Option<IgnoredAny>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Build1 {
+        pipeline: Pipeline1,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Manifest1 {
+        pipeline: Option<Pipeline1>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Stage2 {
+        #[serde(default)]
+        inputs: Object<Json>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Pipeline2 {
+        name: String,
+        build: Option<String>,
+        #[serde(default)]
+        stages: Vec<Stage2>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Manifest2 {
+        #[serde(default)]
+        pipelines: Vec<Pipeline2>,
+    }
+
+    let version = peek_version(text)?;
+    let mut header = Header {
+        version,
+        ..Default::default()
+    };
+    if version == 1 {
+        let manifest: Manifest1 = crate::track::from_str(text, Some(1))?;
+        let mut depth = 0;
+        let mut assembler = false;
+        let mut pipeline = manifest.pipeline.as_ref();
+        while let Some(v) = pipeline {
+            assembler |= depth == 0 && v.assembler.is_some();
+            depth += 1;
+            pipeline = v.build.as_ref().map(|v| &v.pipeline);
+        }
+        header.pipelines = (1..depth.max(1)).rev().map(build_name).collect();
+        header.pipelines.push("tree".to_owned());
+        if assembler {
+            header.pipelines.push("assembler".to_owned());
+        }
+        header.exports = header.pipelines.last().cloned().into_iter().collect();
+    } else {
+        let manifest: Manifest2 = crate::track::from_str(text, Some(2))?;
+        let used: Vec<&str> = manifest
+            .pipelines
+            .iter()
+            .flat_map(|v| dependencies(v.build.as_deref(), v.stages.iter().map(|v| &v.inputs)))
+            .collect();
+        header.pipelines = manifest.pipelines.iter().map(|v| v.name.clone()).collect();
+        header.exports = header
+            .pipelines
+            .iter()
+            .filter(|v| !used.contains(&v.as_str()))
+            .cloned()
+            .collect();
+    }
+    Ok(header)
+}
+
+// Implement `Display` as canonical JSON and `FromStr` as strict JSON
+// parser for a manifest root type.
+#[cfg(feature = "json")]
+macro_rules! impl_text {
+    ($type:ty, $version:expr, $check:expr) => {
+        impl std::fmt::Display for $type {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let json = serde_json::to_value(self).map_err(|_| std::fmt::Error)?;
+                let text = crate::signature::canonical(&json);
+                fmt.write_str(std::str::from_utf8(&text).map_err(|_| std::fmt::Error)?)
+            }
+        }
+
+        impl std::str::FromStr for $type {
+            type Err = crate::error::Error;
+
+            fn from_str(s: &str) -> Result<Self, crate::error::Error> {
+                // Tracking the path is costly, so it is only done to report
+                // errors, by parsing again. Manifests of other versions are
+                // reported as such, rather than by the errors they cause.
+                let check: fn(&str, Option<&Self>) -> Result<(), crate::error::Error> = $check;
+                match serde_json::from_str(s) {
+                    Ok(v) => check(s, Some(&v)).map(|_| v),
+                    Err(_) => {
+                        check(s, None)?;
+                        crate::track::from_str(s, Some($version))
+                    }
+                }
+            }
+        }
+    };
+}
+
+// Version-1 manifests have no `version` member, so they only need to be
+// checked if they fail to parse. Version-2 manifests fail to parse without
+// a string `version` member, but its value has to be checked.
+#[cfg(feature = "json")]
+impl_text!(Manifest1, 1, |text, manifest| match manifest {
+    Some(_) => Ok(()),
+    None => match peek_version(text) {
+        Ok(2) => Err(crate::error::Error::Parse {
+            version: Some(1),
+            line: 0,
+            column: 0,
+            path: "/version".to_owned(),
+            message: "expected a version-1 manifest, found version 2".to_owned(),
+        }),
+        Ok(_) => Ok(()),
+        Err(crate::error::Error::Version(v)) => Err(crate::error::Error::Version(v)),
+        Err(e) => match &e {
+            crate::error::Error::Parse { path, .. } if path == "/version" => Err(e),
+            _ => Ok(()),
+        },
+    },
+});
+#[cfg(feature = "json")]
+impl_text!(Manifest2, 2, |_, manifest| match manifest {
+    Some(v) if v.version != "2" => Err(crate::error::Error::Version(v.version.clone())),
+    _ => Ok(()),
+});
+
+#[cfg(feature = "json")]
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Manifest::V1(v) => v.fmt(fmt),
+            Manifest::V2(v) => v.fmt(fmt),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::str::FromStr for Manifest {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, crate::error::Error> {
+        Self::parse(s)
+    }
+}
+
 /// JSON Object Mapping
 ///
 /// This type is an alias used to represent JSON objects. It is a simple
 /// convenience helper that shows how these objects are represented. Note
 /// that keys must be strings to be valid JSON. Hence, only the target
 /// value type must be provided.
-pub type Object<VALUE> = std::collections::BTreeMap<String, VALUE>;
+pub type Object<VALUE> = alloc::collections::BTreeMap<String, VALUE>;
+
+/// Source Mapping
+///
+/// Sources of a manifest, by the name of the source module, like
+/// `org.osbuild.curl`. Source names are interned like stage names.
+pub type Sources = alloc::collections::BTreeMap<Symbol, Object<Json>>;
 
 /// JSON Array Mapping
 ///
@@ -133,30 +839,283 @@ pub type Array<VALUE> = Vec<VALUE>;
 /// Ideally, it would be based on the `serde_json::value::RawValue` type.
 /// Unfortunately, that type is very much broken in upstream serde_json for
 /// many years. Hence, we direct it to `serde_json::value::Value` for now,
-/// but allow for future changes to pick an alternative.
+/// but allow for future changes to pick an alternative. Without the `json`
+/// feature, it is directed to the minimal `Value` type of this module.
+#[cfg(feature = "json")]
 pub type Json = serde_json::value::Value;
+#[cfg(not(feature = "json"))]
+pub type Json = Value;
+
+/// Convert Object to JSON Map
+///
+/// Objects are ordered by key, so the members are inserted into the map in
+/// sorted order. With the `preserve_order` feature of `serde_json`, the map
+/// retains this order.
+#[cfg(feature = "json")]
+pub fn object_to_map(object: Object<Json>) -> serde_json::Map<String, Json> {
+    object.into_iter().collect()
+}
+
+/// Convert JSON Map to Object
+///
+/// The members are sorted by key, dropping any insertion order the map
+/// retains. No member is lost, since keys of maps are unique.
+#[cfg(feature = "json")]
+pub fn object_from_map(map: serde_json::Map<String, Json>) -> Object<Json> {
+    map.into_iter().collect()
+}
+
+/// Convert Object to JSON Value
+///
+/// Convert the object to a `Json::Object`, like `object_to_map()`.
+#[cfg(feature = "json")]
+pub fn object_to_value(object: Object<Json>) -> Json {
+    Json::Object(object_to_map(object))
+}
+
+/// Convert JSON Value to Object
+///
+/// Convert a `Json::Object` to an object, like `object_from_map()`. Any
+/// other value is returned unchanged as error.
+#[cfg(feature = "json")]
+pub fn object_from_value(value: Json) -> Result<Object<Json>, Json> {
+    match value {
+        Json::Object(v) => Ok(object_from_map(v)),
+        v => Err(v),
+    }
+}
+
+/// Minimal JSON Value
+///
+/// This is the representation of inner JSON payload if the crate is built
+/// without the `json` feature. It mirrors the variants of
+/// `serde_json::Value`, but keeps numbers as their decimal text, so they
+/// are carried unchanged.
+#[cfg(not(feature = "json"))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Array<Value>),
+    Object(Object<Value>),
+}
+
+#[cfg(not(feature = "json"))]
+impl Value {
+    /// Look up a member of an object, or `None` for other values.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(v) => v.get(key),
+            _ => None,
+        }
+    }
+
+    /// Return the string of a string value, or `None` for other values.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(all(feature = "serde", not(feature = "json")))]
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::Number(v) => {
+                if let Ok(v) = v.parse::<u64>() {
+                    serializer.serialize_u64(v)
+                } else if let Ok(v) = v.parse::<i64>() {
+                    serializer.serialize_i64(v)
+                } else {
+                    let v: f64 = v.parse().map_err(serde::ser::Error::custom)?;
+                    serializer.serialize_f64(v)
+                }
+            }
+            Value::String(v) => serializer.serialize_str(v),
+            Value::Array(v) => serializer.collect_seq(v),
+            Value::Object(v) => serializer.collect_map(v),
+        }
+    }
+}
+
+#[cfg(all(feature = "serde", not(feature = "json")))]
+impl<'de> serde::Deserialize<'de> for Value {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Value;
+
+            fn expecting(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                fmt.write_str("a JSON value")
+            }
+
+            fn visit_unit<E>(self) -> Result<Value, E> {
+                Ok(Value::Null)
+            }
+
+            fn visit_none<E>(self) -> Result<Value, E> {
+                Ok(Value::Null)
+            }
+
+            fn visit_some<D: serde::Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+                serde::Deserialize::deserialize(d)
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+                Ok(Value::Bool(v))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+                Ok(Value::Number(v.to_string()))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+                Ok(Value::Number(v.to_string()))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Value, E> {
+                if v.is_finite() {
+                    Ok(Value::Number(v.to_string()))
+                } else {
+                    Err(E::custom("non-finite numbers are not valid JSON"))
+                }
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+                Ok(Value::String(v.to_owned()))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut a: A) -> Result<Value, A::Error> {
+                let mut acc = Array::new();
+                while let Some(v) = a.next_element()? {
+                    acc.push(v);
+                }
+                Ok(Value::Array(acc))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut a: A) -> Result<Value, A::Error> {
+                let mut acc = Object::new();
+                while let Some((k, v)) = a.next_entry()? {
+                    acc.insert(k, v);
+                }
+                Ok(Value::Object(acc))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
 
 // Marker for Object Types
 //
-// The default implementations of serde-derive for maps allow constructing maps
-// from serde `Seq`. In case of JSON, this means that a JSON-array can be
-// parsed into a rust structure. This is convenient at times, but unintentional
-// for strongly-typed parsers. To circumvent this we add an empty, unused
-// member of type `ObjectMarker` to our structures and annotate them as
-// `flatten`. This causes serde-derive to skip the `Seq` deserializer, as it
-// cannot support it in combination with `flatten`.
+// The default implementations of serde-derive for structures allow
+// constructing them from serde `Seq`. In case of JSON, this means that a
+// JSON-array can be parsed into a rust structure. This is convenient at
+// times, but unintentional for strongly-typed parsers. Hence, the derived
+// implementations are generated as inherent functions via `remote = "Self"`,
+// and the trait implementations wrap the deserializer in `MapOnly`, which
+// deserializes structures via `deserialize_map()` and thus rejects anything
+// but maps.
 //
-// This is a bit of a hacky workaround for serde-derive. The proper fix would
-// be to manually derive `Deserialize` and only provide the importers that we
-// actually want. Alternatively, serde-derive would allow us to select the
-// types we want to derive, rather than guessing them itself. However, this is
-// by far not the only issue with serde we face, so we keep this workaround
-// and avoid spending too much time on it.
+// The marker is an empty, unused member of all these structures, which
+// prevents constructing them outside of this module. This keeps adding
+// members a compatible change.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
-#[derive(serde::Deserialize, serde::Serialize)]
 struct ObjectMarker {}
 
-#[cfg(test)]
+// Deserializer that deserializes structures from maps only.
+#[cfg(feature = "serde")]
+struct MapOnly<D>(D);
+
+#[cfg(feature = "serde")]
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for MapOnly<D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.0.deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.0.deserialize_map(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+macro_rules! impl_object {
+    ($($type:ty),* $(,)?) => {
+        $(
+            #[cfg(feature = "serde")]
+            impl serde::Serialize for $type {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    <$type>::serialize(self, serializer)
+                }
+            }
+
+            #[cfg(feature = "serde")]
+            impl<'de> serde::Deserialize<'de> for $type {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    <$type>::deserialize(MapOnly(deserializer))
+                }
+            }
+        )*
+    };
+}
+
+impl_object!(Manifest1, Pipeline1, Assembler1, Build1, Stage1, Manifest2, Pipeline2, Stage2);
+
+#[cfg(all(test, feature = "serde", not(feature = "json")))]
+mod tests_value {
+    use super::*;
+    use serde::de::{value, IntoDeserializer};
+    use serde::Deserialize;
+
+    // Verify Minimal JSON Values
+    #[test]
+    fn verify_value() {
+        let number = Value::deserialize(7u64.into_deserializer());
+        assert_eq!(number, Ok::<_, value::Error>(Value::Number("7".to_owned())));
+
+        let list = Value::deserialize(vec!["a", "b"].into_deserializer());
+        assert_eq! {
+            list,
+            Ok::<_, value::Error>(Value::Array(vec![
+                Value::String("a".to_owned()),
+                Value::String("b".to_owned()),
+            ])),
+        }
+        assert_eq!(list.unwrap().get("a"), None);
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
 mod tests {
     use super::*;
 
@@ -207,12 +1166,12 @@ mod tests {
                 }"#
             ).unwrap(),
             Manifest1 {
-                sources: Object::from([
-                    ("foo".to_owned(), Object::from([
+                sources: Sources::from([
+                    (Symbol::new("foo"), Object::from([
                         ("a".to_owned(), Json::from(71)),
                         ("b".to_owned(), Json::from("foo")),
                     ])),
-                    ("bar".to_owned(), Object::from([
+                    (Symbol::new("bar"), Object::from([
                         ("a".to_owned(), Json::from(0)),
                         ("b".to_owned(), Json::from("bar")),
                     ])),
@@ -253,12 +1212,12 @@ mod tests {
             ).unwrap(),
             Pipeline1 {
                 assembler: Some(Assembler1 {
-                    name: "foobar".to_owned(),
+                    name: "foobar".into(),
                     ..Default::default()
                 }),
                 stages: Array::from([
                     Stage1 {
-                        name: "foobar".to_owned(),
+                        name: "foobar".into(),
                         ..Default::default()
                     },
                 ]),
@@ -279,7 +1238,7 @@ mod tests {
             Pipeline1 {
                 build: Some(Box::new(Build1 {
                     pipeline: Pipeline1 { ..Default::default() },
-                    runner: "foobar".to_owned(),
+                    runner: "foobar".into(),
                     ..Default::default()
                 })),
                 ..Default::default()
@@ -307,7 +1266,7 @@ mod tests {
         assert_eq! {
             serde_json::from_str::<'_, Assembler1>(r#"{"name":"foobar"}"#).unwrap(),
             Assembler1 {
-                name: "foobar".to_owned(),
+                name: "foobar".into(),
                 ..Default::default()
             },
         }
@@ -327,7 +1286,7 @@ mod tests {
                 }"#
             ).unwrap(),
             Assembler1 {
-                name: "foobar".to_owned(),
+                name: "foobar".into(),
                 options: Object::from([
                     ("foo".to_owned(), Json::from(0)),
                     ("bar".to_owned(), Json::from(71)),
@@ -370,7 +1329,7 @@ mod tests {
                 }"#
             ).unwrap(),
             Build1 {
-                runner: "foobar".to_owned(),
+                runner: "foobar".into(),
                 ..Default::default()
             },
         }
@@ -395,11 +1354,11 @@ mod tests {
             Build1 {
                 pipeline: Pipeline1 {
                     stages: Array::from([
-                        Stage1 { name: "foobar".to_owned(), ..Default::default() },
+                        Stage1 { name: "foobar".into(), ..Default::default() },
                     ]),
                     ..Default::default()
                 },
-                runner: "foobar".to_owned(),
+                runner: "foobar".into(),
                 ..Default::default()
             },
         }
@@ -425,7 +1384,7 @@ mod tests {
         assert_eq! {
             serde_json::from_str::<'_, Stage1>(r#"{"name":"foobar"}"#).unwrap(),
             Stage1 {
-                name: "foobar".to_owned(),
+                name: "foobar".into(),
                 ..Default::default()
             },
         }
@@ -445,7 +1404,7 @@ mod tests {
                 }"#
             ).unwrap(),
             Stage1 {
-                name: "foobar".to_owned(),
+                name: "foobar".into(),
                 options: Object::from([
                     ("foo".to_owned(), Json::from(0)),
                     ("bar".to_owned(), Json::from(71)),
@@ -462,4 +1421,215 @@ mod tests {
             ).unwrap_err().is_data(),
         }
     }
+
+    // Verify Common Manifest Interface
+    #[test]
+    fn verify_manifest_format() {
+        let v1 = Manifest::parse(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": { "stages": [{ "name": "org.osbuild.rpm" }] },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [{ "name": "org.osbuild.locale", "options": { "language": "en_US" } }],
+                    "assembler": { "name": "org.osbuild.tar" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(v1.version(), 1);
+
+        let pipelines = v1.pipelines();
+        let names: Vec<_> = pipelines
+            .iter()
+            .map(|v| (v.name.as_str(), v.build.as_deref()))
+            .collect();
+        assert_eq! {
+            names,
+            [("build", None), ("tree", Some("build")), ("assembler", Some("build"))],
+        }
+        assert_eq!(pipelines[1].stages[0].name, "org.osbuild.locale");
+        assert_eq!(pipelines[1].stages[0].options["language"], "en_US");
+
+        let v2 = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "tree", "build": "name:build", "runner": "org.osbuild.linux" }
+                ],
+                "sources": { "org.osbuild.curl": { "items": {} } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(v2.version(), 2);
+        assert_eq!(v2.pipelines()[1].build.as_deref(), Some("build"));
+        assert_eq!(v2.pipelines()[0].stages[0].name, "org.osbuild.rpm");
+        assert!(v2.sources().contains_key("org.osbuild.curl"));
+        assert_eq!(Manifest::parse(&v2.serialize()).unwrap(), v2);
+
+        // Unknown versions and members are rejected.
+        assert!(Manifest::parse(r#"{ "version": "3" }"#).is_err());
+        assert!(Manifest::parse(r#"{ "version": "2", "pipeline": {} }"#).is_err());
+    }
+
+    // Verify Object Interoperability
+    #[test]
+    fn verify_object_interop() {
+        let value = serde_json::json!({ "b": [1], "a": { "d": null, "c": true } });
+        let object = object_from_value(value.clone()).unwrap();
+        assert_eq!(object.keys().collect::<Vec<_>>(), ["a", "b"]);
+
+        let map = object_to_map(object.clone());
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(object_from_map(map), object);
+        assert_eq!(object_to_value(object), value);
+        assert_eq!(object_from_value(Json::Null), Err(Json::Null));
+    }
+
+    // Verify Textual Representation
+    #[test]
+    fn verify_text() {
+        let text =
+            r#"{"sources":{},"pipeline":{"stages":[{"options":{"b":1,"a":[]},"name":"x"}]}}"#;
+        let manifest: Manifest1 = text.parse().unwrap();
+        assert_eq! {
+            manifest.to_string(),
+            r#"{"pipeline":{"assembler":null,"build":null,"stages":[{"name":"x","options":{"a":[],"b":1}}]},"sources":{}}"#,
+        }
+        assert_eq!(manifest.to_string().parse::<Manifest1>().unwrap(), manifest);
+
+        let manifest: Manifest = r#"{ "version": "2" }"#.parse().unwrap();
+        assert_eq!(manifest.to_string(), r#"{"version":"2"}"#);
+        assert!("{ \"version\": 2 }".parse::<Manifest>().is_err());
+        assert!("[]".parse::<Manifest2>().is_err());
+
+        // The version member is checked for both versions.
+        assert_eq! {
+            r#"{ "version": "7" }"#.parse::<Manifest2>().unwrap_err().to_string(),
+            "unsupported manifest version 7",
+        }
+        assert_eq! {
+            r#"{ "version": "7" }"#.parse::<Manifest1>().unwrap_err().to_string(),
+            "unsupported manifest version 7",
+        }
+        assert_eq! {
+            r#"{ "version": "2" }"#.parse::<Manifest1>().unwrap_err().to_string(),
+            "invalid version-1 manifest: /version: expected a version-1 manifest, found version 2",
+        }
+        assert_eq! {
+            r#"{ "version": 2 }"#.parse::<Manifest>().unwrap_err().to_string(),
+            "invalid manifest: /version: the manifest version must be a string, not `2`",
+        }
+    }
+
+    // Verify Header Parsing
+    #[test]
+    fn verify_header() {
+        assert_eq!(peek_version(r#"{ "pipeline": {} }"#).unwrap(), 1);
+        assert_eq!(peek_version(r#"{ "version": "2" }"#).unwrap(), 2);
+        assert!(peek_version(r#"{ "version": "3" }"#).is_err());
+
+        let header = parse_header(
+            r#"{
+                "pipeline": {
+                    "build": { "pipeline": { "stages": [] }, "runner": "org.osbuild.linux" },
+                    "stages": [{ "name": "org.osbuild.noop", "options": { "a": [1] } }],
+                    "assembler": { "name": "org.osbuild.tar" }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.pipelines, ["build", "tree", "assembler"]);
+        assert_eq!(header.exports, ["assembler"]);
+        assert_eq!(parse_header("{}").unwrap().pipelines, ["tree"]);
+
+        let header = parse_header(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build" },
+                    { "name": "tree", "build": "name:build" },
+                    { "name": "image", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.copy",
+                        "inputs": { "tree": { "origin": "org.osbuild.pipeline", "references": ["name:tree"] } }
+                    }] },
+                    { "name": "other", "build": "name:build" }
+                ],
+                "sources": { "org.osbuild.curl": { "items": {} } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(header.pipelines, ["build", "tree", "image", "other"]);
+        assert_eq!(header.exports, ["image", "other"]);
+    }
+
+    // Verify Deep Iteration
+    #[test]
+    fn verify_iteration() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "build": {
+                                "pipeline": { "stages": [{ "name": "a" }] },
+                                "runner": "r"
+                            },
+                            "stages": [{ "name": "b" }, { "name": "c" }]
+                        },
+                        "runner": "r"
+                    },
+                    "stages": [{ "name": "d" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let names: Vec<_> = manifest
+            .pipeline
+            .iter_stages_deep()
+            .map(|v| v.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert_eq!(manifest.pipeline.iter_pipelines_deep().count(), 3);
+        assert!(std::ptr::eq(
+            manifest.pipeline.iter_pipelines_deep().last().unwrap(),
+            &manifest.pipeline,
+        ));
+
+        let manifest: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    {
+                        "name": "image",
+                        "build": "name:build",
+                        "stages": [{
+                            "type": "org.osbuild.copy",
+                            "inputs": {
+                                "tree": { "type": "org.osbuild.tree", "origin": "org.osbuild.pipeline", "references": ["name:os"] }
+                            }
+                        }]
+                    },
+                    { "name": "os", "build": "name:build" },
+                    { "name": "build" },
+                    { "name": "other" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.pipelines[0].dependencies(), ["build", "os"]);
+        let names: Vec<_> = manifest
+            .iter_pipelines_topological()
+            .unwrap()
+            .map(|v| v.name.as_str())
+            .collect();
+        assert_eq!(names, ["build", "os", "image", "other"]);
+
+        let mut cyclic = manifest.clone();
+        cyclic.pipelines[2].build = Some("name:image".to_owned());
+        assert!(cyclic.iter_pipelines_topological().is_none());
+    }
 }
@@ -0,0 +1,184 @@
+//! Deep Merging
+//!
+//! Manifest generators assemble stage options from layers, like defaults
+//! of an image type overlaid with customizations of a specific image. This
+//! module merges JSON objects deeply: members present only in the source
+//! are added, and nested objects present in both are merged recursively.
+//! How arrays, `null`, and other values present in both are treated is
+//! configurable.
+//!
+//! `MergeOptions::patch()` gives the semantics of JSON Merge Patch (RFC
+//! 7396), which is what most overlay formats use.
+
+use crate::manifest::{Json, Object};
+
+/// Array Merging
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Arrays {
+    /// Replace arrays by those of the source.
+    #[default]
+    Replace,
+    /// Append the elements of the source to the arrays.
+    Append,
+}
+
+/// Null Merging
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Nulls {
+    /// Merge `null` like any other value.
+    #[default]
+    Set,
+    /// Delete members set to `null` in the source. Such members are not
+    /// added either, including those nested in added objects.
+    Delete,
+}
+
+/// Conflict Resolution
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Conflicts {
+    /// Values of the source replace existing values.
+    #[default]
+    Replace,
+    /// Existing values are kept. Nested objects are still merged, and
+    /// arrays still appended to, if configured.
+    Keep,
+}
+
+/// Merge Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct MergeOptions {
+    /// Treatment of arrays present in both values.
+    pub arrays: Arrays,
+    /// Treatment of `null` in the source.
+    pub nulls: Nulls,
+    /// Treatment of other values present in both.
+    pub conflicts: Conflicts,
+}
+
+impl MergeOptions {
+    /// JSON Merge Patch
+    ///
+    /// Options with the semantics of RFC 7396: arrays are replaced, and
+    /// `null` deletes members.
+    pub fn patch() -> Self {
+        Self {
+            nulls: Nulls::Delete,
+            ..Default::default()
+        }
+    }
+}
+
+// Return the value to add for a member missing from the destination.
+fn added(value: &Json, options: &MergeOptions) -> Json {
+    match value {
+        Json::Object(v) if options.nulls == Nulls::Delete => Json::Object(
+            v.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), added(v, options)))
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
+
+/// Merge Objects
+///
+/// Merge the members of `src` deeply into `dst`, as described by the
+/// options.
+pub fn merge(dst: &mut Object<Json>, src: &Object<Json>, options: &MergeOptions) {
+    for (key, value) in src {
+        if value.is_null() && options.nulls == Nulls::Delete {
+            dst.remove(key);
+        } else if let Some(v) = dst.get_mut(key) {
+            merge_value(v, value, options);
+        } else {
+            dst.insert(key.clone(), added(value, options));
+        }
+    }
+}
+
+/// Merge Values
+///
+/// Merge `src` deeply into `dst`, like `merge()`. If either is not an
+/// object, `src` is merged like a member value present in both.
+pub fn merge_value(dst: &mut Json, src: &Json, options: &MergeOptions) {
+    match (dst, src) {
+        (Json::Object(dst), Json::Object(src)) => {
+            for (key, value) in src {
+                if value.is_null() && options.nulls == Nulls::Delete {
+                    dst.remove(key);
+                } else if let Some(v) = dst.get_mut(key) {
+                    merge_value(v, value, options);
+                } else {
+                    dst.insert(key.clone(), added(value, options));
+                }
+            }
+        }
+        (Json::Array(dst), Json::Array(src)) if options.arrays == Arrays::Append => {
+            dst.extend(src.iter().cloned());
+        }
+        (dst, src) => {
+            if options.conflicts == Conflicts::Replace {
+                *dst = added(src, options);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Deep Merging
+    #[test]
+    fn verify_merge() {
+        let base = serde_json::json!({
+            "a": { "b": 1, "c": [1], "d": "x" },
+            "e": [1],
+        });
+        let layer = serde_json::json!({
+            "a": { "b": 2, "c": [2], "d": null, "f": { "g": null, "h": 0 } },
+            "e": 2,
+        });
+
+        let mut v = base.clone();
+        merge_value(&mut v, &layer, &MergeOptions::default());
+        assert_eq! {
+            v,
+            serde_json::json!({
+                "a": { "b": 2, "c": [2], "d": null, "f": { "g": null, "h": 0 } },
+                "e": 2,
+            }),
+        }
+
+        let mut v = base.clone();
+        merge_value(&mut v, &layer, &MergeOptions::patch());
+        assert_eq! {
+            v,
+            serde_json::json!({ "a": { "b": 2, "c": [2], "f": { "h": 0 } }, "e": 2 }),
+        }
+
+        let options = MergeOptions {
+            arrays: Arrays::Append,
+            conflicts: Conflicts::Keep,
+            ..Default::default()
+        };
+        let mut v = base.clone();
+        merge_value(&mut v, &layer, &options);
+        assert_eq! {
+            v,
+            serde_json::json!({
+                "a": { "b": 1, "c": [1, 2], "d": "x", "f": { "g": null, "h": 0 } },
+                "e": [1],
+            }),
+        }
+
+        let mut object: Object<Json> = serde_json::from_value(base).unwrap();
+        let layer: Object<Json> = serde_json::from_value(layer).unwrap();
+        merge(&mut object, &layer, &MergeOptions::patch());
+        assert_eq! {
+            serde_json::to_value(object).unwrap(),
+            serde_json::json!({ "a": { "b": 2, "c": [2], "f": { "h": 0 } }, "e": 2 }),
+        }
+    }
+}
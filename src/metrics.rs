@@ -0,0 +1,297 @@
+//! Build Metrics
+//!
+//! Operators of builder fleets monitor them with Prometheus. This module
+//! collects metrics of builds and fetches, and renders them in the
+//! Prometheus text exposition format, to be served on a `/metrics`
+//! endpoint. The metrics are:
+//!
+//!  * `osbuild_stage_duration_seconds`: histogram of stage durations, by
+//!    stage type
+//!  * `osbuild_cache_requests_total`: cache lookups, by result
+//!  * `osbuild_download_bytes_total`: bytes downloaded by fetches
+//!  * `osbuild_failures_total`: failures, by error class
+//!
+//! `Metrics` is an event sink, so it can observe builds next to other
+//! sinks. Fetches are observed by wrapping their transport in
+//! `Instrumented`.
+
+use crate::events::{Event, Sink};
+use crate::http::{Response, Transport};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+// Upper bounds of the stage duration buckets, in seconds.
+const BUCKETS: [f64; 10] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, v: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if v <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += v;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    stages: BTreeMap<String, Histogram>,
+    hits: u64,
+    misses: u64,
+    downloaded: u64,
+    failures: BTreeMap<String, u64>,
+}
+
+// Escape a label value of the text format.
+fn label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics Registry
+///
+/// A thread-safe collection of the metrics of this module.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    /// Create Metrics Registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Inner) -> R) -> R {
+        f(&mut self.inner.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Observe Stage Duration
+    pub fn stage(&self, stage: &str, duration: Duration) {
+        self.with(|v| {
+            v.stages
+                .entry(stage.to_owned())
+                .or_default()
+                .observe(duration.as_secs_f64())
+        });
+    }
+
+    /// Count Cache Lookup
+    pub fn cache(&self, hit: bool) {
+        self.with(|v| match hit {
+            true => v.hits += 1,
+            false => v.misses += 1,
+        });
+    }
+
+    /// Count Downloaded Bytes
+    pub fn downloaded(&self, bytes: u64) {
+        self.with(|v| v.downloaded += bytes);
+    }
+
+    /// Count Failure
+    ///
+    /// Count a failure of the given class, like `transport` or `build`.
+    pub fn failure(&self, class: &str) {
+        self.with(|v| *v.failures.entry(class.to_owned()).or_default() += 1);
+    }
+
+    /// Render Text Format
+    ///
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut acc = String::new();
+        self.with(|v| {
+            let name = "osbuild_stage_duration_seconds";
+            acc += &format!("# HELP {} Duration of stages, by stage type.\n", name);
+            acc += &format!("# TYPE {} histogram\n", name);
+            for (stage, h) in &v.stages {
+                let stage = label(stage);
+                for (n, bound) in h.buckets.iter().zip(BUCKETS) {
+                    let _ = writeln!(
+                        acc,
+                        "{}_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                        name, stage, bound, n
+                    );
+                }
+                let _ = writeln!(
+                    acc,
+                    "{}_bucket{{stage=\"{}\",le=\"+Inf\"}} {}",
+                    name, stage, h.count
+                );
+                let _ = writeln!(acc, "{}_sum{{stage=\"{}\"}} {}", name, stage, h.sum);
+                let _ = writeln!(acc, "{}_count{{stage=\"{}\"}} {}", name, stage, h.count);
+            }
+
+            let name = "osbuild_cache_requests_total";
+            acc += &format!("# HELP {} Cache lookups, by result.\n", name);
+            acc += &format!("# TYPE {} counter\n", name);
+            let _ = writeln!(acc, "{}{{result=\"hit\"}} {}", name, v.hits);
+            let _ = writeln!(acc, "{}{{result=\"miss\"}} {}", name, v.misses);
+
+            let name = "osbuild_download_bytes_total";
+            acc += &format!("# HELP {} Bytes downloaded by fetches.\n", name);
+            acc += &format!("# TYPE {} counter\n", name);
+            let _ = writeln!(acc, "{} {}", name, v.downloaded);
+
+            let name = "osbuild_failures_total";
+            acc += &format!("# HELP {} Failures, by error class.\n", name);
+            acc += &format!("# TYPE {} counter\n", name);
+            for (class, n) in &v.failures {
+                let _ = writeln!(acc, "{}{{class=\"{}\"}} {}", name, label(class), n);
+            }
+        });
+        acc
+    }
+}
+
+impl Sink for Metrics {
+    fn emit(&self, event: &Event) -> Result<(), String> {
+        match event {
+            Event::StageFinished {
+                stage, duration_ms, ..
+            } => self.stage(stage, Duration::from_millis(*duration_ms)),
+            Event::Failed { .. } => self.failure("build"),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Instrumented Transport
+///
+/// A transport counting the bytes it downloads, and its failures as class
+/// `transport`, or `http` for responses with error status.
+pub struct Instrumented<'a, T> {
+    pub inner: T,
+    pub metrics: &'a Metrics,
+}
+
+impl<T: Transport> Instrumented<'_, T> {
+    fn observe(&self, r: Result<Response, String>) -> Result<Response, String> {
+        match &r {
+            Ok(v) if v.status >= 400 => self.metrics.failure("http"),
+            Ok(v) => self.metrics.downloaded(v.body.len() as u64),
+            Err(_) => self.metrics.failure("transport"),
+        }
+        r
+    }
+}
+
+impl<T: Transport> Transport for Instrumented<'_, T> {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, String> {
+        self.observe(self.inner.get(url, headers))
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, String> {
+        self.observe(self.inner.request(method, url, headers, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake;
+
+    impl Transport for Fake {
+        fn get(&self, url: &str, _headers: &[(&str, &str)]) -> Result<Response, String> {
+            match url {
+                "https://example.com/a" => Ok(Response {
+                    status: 200,
+                    body: vec![0; 7],
+                    ..Default::default()
+                }),
+                "https://example.com/b" => Ok(Response {
+                    status: 404,
+                    ..Default::default()
+                }),
+                _ => Err("unreachable".to_owned()),
+            }
+        }
+    }
+
+    // Verify Metrics
+    #[test]
+    fn verify_metrics() {
+        let metrics = Metrics::new();
+        metrics
+            .emit(&Event::StageFinished {
+                build: "b".to_owned(),
+                pipeline: "os".to_owned(),
+                index: 0,
+                stage: "org.osbuild.\"rpm\"".to_owned(),
+                duration_ms: 2500,
+            })
+            .unwrap();
+        metrics.stage("org.osbuild.\"rpm\"", Duration::from_millis(500));
+        metrics.cache(true);
+        metrics.cache(false);
+        metrics.cache(false);
+        metrics
+            .emit(&Event::Failed {
+                build: "b".to_owned(),
+                message: String::new(),
+                diagnostics: Vec::new(),
+            })
+            .unwrap();
+
+        let transport = Instrumented {
+            inner: Fake,
+            metrics: &metrics,
+        };
+        for url in ["https://example.com/a", "https://example.com/b", "x"] {
+            let _ = transport.get(url, &[]);
+        }
+
+        let text = metrics.render();
+        let stage = "{stage=\"org.osbuild.\\\"rpm\\\"\"";
+        for line in [
+            "# TYPE osbuild_stage_duration_seconds histogram".to_owned(),
+            format!(
+                "osbuild_stage_duration_seconds_bucket{},le=\"0.1\"}} 0",
+                stage
+            ),
+            format!(
+                "osbuild_stage_duration_seconds_bucket{},le=\"0.5\"}} 1",
+                stage
+            ),
+            format!(
+                "osbuild_stage_duration_seconds_bucket{},le=\"5\"}} 2",
+                stage
+            ),
+            format!(
+                "osbuild_stage_duration_seconds_bucket{},le=\"+Inf\"}} 2",
+                stage
+            ),
+            format!("osbuild_stage_duration_seconds_sum{}}} 3", stage),
+            format!("osbuild_stage_duration_seconds_count{}}} 2", stage),
+            "osbuild_cache_requests_total{result=\"hit\"} 1".to_owned(),
+            "osbuild_cache_requests_total{result=\"miss\"} 2".to_owned(),
+            "osbuild_download_bytes_total 7".to_owned(),
+            "osbuild_failures_total{class=\"build\"} 1".to_owned(),
+            "osbuild_failures_total{class=\"http\"} 1".to_owned(),
+            "osbuild_failures_total{class=\"transport\"} 1".to_owned(),
+        ] {
+            assert!(text.lines().any(|v| v == line), "missing: {}", line);
+        }
+    }
+}
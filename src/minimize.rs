@@ -0,0 +1,187 @@
+//! Manifest Minimization
+//!
+//! This module removes semantically inert content from manifests. Manifest
+//! generators tend to emit stages without effect and sources that no stage
+//! ever references. Such content does not change the produced artifact, but
+//! it increases the size of the manifest and the work required to build it.
+//!
+//! Combined with `normalize()` and compact serialization, this produces the
+//! smallest equivalent manifest, which is suitable for transfer to
+//! constrained builders.
+
+use crate::lint;
+use crate::manifest::{Json, Manifest1, Pipeline1};
+
+/// Inert Stage Table
+///
+/// This lists the names of all stages that never have an effect on the
+/// tree they operate on, regardless of their options.
+pub const INERT_STAGES: &[&str] = &["org.osbuild.noop"];
+
+fn contains(value: &Json, needle: &str) -> bool {
+    match value {
+        Json::String(v) => v == needle,
+        Json::Array(v) => v.iter().any(|v| contains(v, needle)),
+        Json::Object(v) => v.iter().any(|(k, v)| k == needle || contains(v, needle)),
+        _ => false,
+    }
+}
+
+fn referenced(pipeline: &Pipeline1, needle: &str) -> bool {
+    let options = pipeline
+        .stages
+        .iter()
+        .map(|v| &v.options)
+        .chain(pipeline.assembler.iter().map(|v| &v.options));
+
+    for options in options {
+        if options
+            .iter()
+            .any(|(k, v)| k == needle || contains(v, needle))
+        {
+            return true;
+        }
+    }
+
+    match pipeline.build {
+        Some(ref build) => referenced(&build.pipeline, needle),
+        None => false,
+    }
+}
+
+fn minimize_pipeline(pipeline: &mut Pipeline1, base: &str, removed: &mut Vec<String>) {
+    if let Some(ref mut build) = pipeline.build {
+        minimize_pipeline(
+            &mut build.pipeline,
+            &format!("{}/build/pipeline", base),
+            removed,
+        );
+    }
+
+    let mut index = 0;
+    pipeline.stages.retain(|v| {
+        let inert = INERT_STAGES.contains(&v.name.as_str());
+        if inert {
+            removed.push(format!("{}/stages/{}", base, index));
+        }
+        index += 1;
+        !inert
+    });
+}
+
+/// Minimize Manifest
+///
+/// Remove all inert stages and all unreferenced source items from the
+/// manifest, and return JSON pointers to the removed content, relative to
+/// the original manifest.
+///
+/// Source items are the members of the `items` or `urls` objects of a
+/// source. They are considered referenced if their key is used anywhere in
+/// the options of any stage or assembler. Sources without remaining items
+/// are removed entirely, while sources without any item collection are
+/// left untouched.
+pub fn minimize(manifest: &mut Manifest1) -> Vec<String> {
+    let mut removed = Vec::new();
+
+    minimize_pipeline(&mut manifest.pipeline, "/pipeline", &mut removed);
+
+    let pipeline = &manifest.pipeline;
+    for (name, source) in manifest.sources.iter_mut() {
+        for key in ["items", "urls"] {
+            if let Some(Json::Object(items)) = source.get_mut(key) {
+                items.retain(|k, _| {
+                    let used = referenced(pipeline, k);
+                    if !used {
+                        let path = lint::pointer(&lint::pointer("/sources", name), key);
+                        removed.push(lint::pointer(&path, k));
+                    }
+                    used
+                });
+            }
+        }
+    }
+
+    manifest.sources.retain(|name, source| {
+        let items = ["items", "urls"].iter().filter_map(|k| source.get(*k));
+        let empty = items.clone().count() > 0
+            && items
+                .clone()
+                .all(|v| v.as_object().is_some_and(|v| v.is_empty()));
+        if empty {
+            removed.push(lint::pointer("/sources", name));
+        }
+        !empty
+    });
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Minimization
+    #[test]
+    fn verify_minimize() {
+        let mut manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [
+                                { "name": "org.osbuild.noop" },
+                                {
+                                    "name": "org.osbuild.rpm",
+                                    "options": { "packages": [{ "checksum": "sha256:a" }] }
+                                }
+                            ]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        { "name": "org.osbuild.rpm", "options": { "packages": ["sha256:b"] } },
+                        { "name": "org.osbuild.noop", "options": { "foo": "sha256:c" } }
+                    ]
+                },
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": {
+                            "sha256:a": "a",
+                            "sha256:b": "b",
+                            "sha256:c": "c"
+                        }
+                    },
+                    "org.osbuild.inline": {
+                        "items": {
+                            "sha256:d": { "encoding": "base64", "data": "" }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            minimize(&mut manifest),
+            [
+                "/pipeline/build/pipeline/stages/0",
+                "/pipeline/stages/1",
+                "/sources/org.osbuild.files/urls/sha256:c",
+                "/sources/org.osbuild.inline/items/sha256:d",
+                "/sources/org.osbuild.inline",
+            ],
+        }
+        assert_eq!(manifest.pipeline.stages.len(), 1);
+        assert_eq!(manifest.sources.len(), 1);
+        assert_eq!(
+            manifest.sources["org.osbuild.files"]["urls"]
+                .as_object()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Minimization is idempotent.
+        assert_eq!(minimize(&mut manifest), Vec::<String>::new());
+    }
+}
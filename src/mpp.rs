@@ -0,0 +1,325 @@
+//! Manifest Preprocessing
+//!
+//! osbuild-mpp is the preprocessor used by many image definition
+//! repositories. It extends the manifest format with directives, which are
+//! JSON objects with `mpp-` prefixed keys, and expands them into plain
+//! manifests. This module implements this preprocessing natively.
+//!
+//! Preprocessing operates on untyped JSON, since the input is not a valid
+//! manifest until all directives are expanded. The result can then be
+//! parsed with the manifest types like any other manifest.
+//!
+//! Files named with a `.yaml` or `.yml` extension are read as YAML, all
+//! other files as JSON. Both can import each other.
+
+use crate::manifest::Json;
+use crate::merge;
+use crate::yaml;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub mod depsolve;
+pub mod embed;
+pub mod eval;
+pub mod import;
+pub mod vars;
+
+/// Preprocessing Error
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read.
+    Io(PathBuf, std::io::Error),
+    /// A file is not valid JSON.
+    Json(PathBuf, serde_json::Error),
+    /// A file is not valid YAML.
+    Yaml(PathBuf, yaml::Error),
+    /// A referenced file could not be found, neither relative to the
+    /// referencing file nor in the search paths.
+    NotFound(PathBuf, String),
+    /// A file imports itself, directly or indirectly. The import chain is
+    /// given in order, ending with the file that closes the cycle.
+    Cycle(Vec<PathBuf>),
+    /// A directive in the given file is malformed.
+    Invalid(PathBuf, String),
+    /// The dependency solver failed to resolve a directive in the given
+    /// file.
+    Solver(PathBuf, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::Json(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::Yaml(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::NotFound(p, v) => write!(fmt, "{}: cannot find `{}`", p.display(), v),
+            Error::Cycle(v) => {
+                write!(fmt, "import cycle: ")?;
+                for (i, p) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " -> ")?;
+                    }
+                    write!(fmt, "{}", p.display())?;
+                }
+                Ok(())
+            }
+            Error::Invalid(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::Solver(p, v) => write!(fmt, "{}: depsolve failed: {}", p.display(), v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Preprocessor
+///
+/// The preprocessor holds the configuration used to expand directives.
+/// Files referenced by directives are looked up relative to the
+/// referencing file first, and in the search paths second, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Processor {
+    search_paths: Vec<PathBuf>,
+    pub(crate) defines: serde_json::Map<String, Json>,
+    pub(crate) solver: Option<Arc<dyn depsolve::Solver>>,
+}
+
+impl Processor {
+    /// Create New Preprocessor
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add Search Path
+    ///
+    /// Append a directory to the list of directories to search for
+    /// referenced files.
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+
+    /// Define Variable
+    ///
+    /// Define a variable for all processed files. Defined variables take
+    /// precedence over the variables declared with `mpp-vars`.
+    pub fn define(&mut self, name: &str, value: Json) {
+        self.defines.insert(name.to_owned(), value);
+    }
+
+    /// Set Dependency Solver
+    ///
+    /// Set the solver used to expand `mpp-depsolve` directives. Without a
+    /// solver, such directives are rejected.
+    pub fn set_solver(&mut self, solver: Arc<dyn depsolve::Solver>) {
+        self.solver = Some(solver);
+    }
+
+    /// Preprocess File
+    ///
+    /// Load the manifest at `path` and expand all directives in it,
+    /// including the directives of all files it imports.
+    pub fn process(&self, path: &Path) -> Result<Json, Error> {
+        self.process_at(path, &mut Vec::new())
+    }
+
+    // Process a file as part of an import chain given by `stack`.
+    pub(crate) fn process_at(&self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<Json, Error> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| Error::Io(path.to_owned(), e))?;
+        if stack.contains(&path) {
+            let mut chain = stack.clone();
+            chain.push(path);
+            return Err(Error::Cycle(chain));
+        }
+
+        let data = std::fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        let mut json: Json = match path.extension().and_then(|v| v.to_str()) {
+            Some("yaml" | "yml") => {
+                yaml::from_str(&data).map_err(|e| Error::Yaml(path.clone(), e))?
+            }
+            _ => serde_json::from_str(&data).map_err(|e| Error::Json(path.clone(), e))?,
+        };
+
+        let embedded = embed::expand(self, &mut json, &path)?;
+        vars::expand(self, &mut json, &path, embedded)?;
+
+        stack.push(path.clone());
+        let r = import::expand(self, &mut json, &path, stack);
+        stack.pop();
+        r?;
+
+        depsolve::expand(self, &mut json, &path)?;
+
+        Ok(json)
+    }
+
+    // Resolve a file referenced by `name` from the file at `from`.
+    pub(crate) fn resolve(&self, from: &Path, name: &str) -> Result<PathBuf, Error> {
+        let base = from.parent().unwrap_or(Path::new("."));
+        std::iter::once(base)
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|v| v.join(name))
+            .find(|v| v.is_file())
+            .ok_or_else(|| Error::NotFound(from.to_owned(), name.to_owned()))
+    }
+}
+
+// Remove all directives named `key` from `value`, returning the JSON
+// pointers of the objects that contained them, with their arguments.
+pub(crate) fn take_directives(value: &mut Json, key: &str) -> Vec<(String, Json)> {
+    fn walk(value: &mut Json, key: &str, pointer: String, acc: &mut Vec<(String, Json)>) {
+        match value {
+            Json::Array(v) => {
+                for (i, v) in v.iter_mut().enumerate() {
+                    walk(v, key, format!("{}/{}", pointer, i), acc);
+                }
+            }
+            Json::Object(v) => {
+                if let Some(directive) = v.remove(key) {
+                    acc.push((pointer.clone(), directive));
+                }
+                for (k, v) in v.iter_mut() {
+                    let k = k.replace('~', "~0").replace('/', "~1");
+                    walk(v, key, format!("{}/{}", pointer, k), acc);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut acc = Vec::new();
+    walk(value, key, String::new(), &mut acc);
+    acc
+}
+
+// Merge the sources of `src` into `dst`. Source items are keyed by their
+// checksum, so existing items are kept as they are.
+pub(crate) fn merge_sources(dst: &mut Json, src: &Json) {
+    let src = match src.get("sources") {
+        Some(v @ Json::Object(_)) => v,
+        _ => return,
+    };
+    let sources = match dst.as_object_mut() {
+        None => return,
+        Some(v) => v
+            .entry("sources")
+            .or_insert_with(|| Json::Object(Default::default())),
+    };
+    let options = merge::MergeOptions {
+        conflicts: merge::Conflicts::Keep,
+        ..Default::default()
+    };
+    merge::merge_value(sources, src, &options);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Create a fresh scratch directory for a test.
+    pub(crate) fn scratch(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("r-osbuild-mpp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    // Verify Source Merging
+    #[test]
+    fn verify_merge_sources() {
+        let mut dst = serde_json::json!({
+            "sources": { "org.osbuild.curl": { "items": { "sha256:a": "x" } } }
+        });
+        merge_sources(
+            &mut dst,
+            &serde_json::json!({
+                "sources": {
+                    "org.osbuild.curl": { "items": { "sha256:a": "y", "sha256:b": "z" } },
+                    "org.osbuild.inline": { "items": {} }
+                }
+            }),
+        );
+        assert_eq! {
+            dst,
+            serde_json::json!({
+                "sources": {
+                    "org.osbuild.curl": { "items": { "sha256:a": "x", "sha256:b": "z" } },
+                    "org.osbuild.inline": { "items": {} }
+                }
+            }),
+        }
+    }
+
+    // Verify YAML Input
+    #[test]
+    fn verify_yaml() {
+        let dir = scratch("yaml");
+        std::fs::write(
+            dir.join("base.json"),
+            r#"{ "version": "2", "pipelines": [{ "name": "build", "stages": [] }] }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.yaml"),
+            concat!(
+                "version: '2'\n",
+                "pipelines:\n",
+                "  - mpp-import-pipelines:\n",
+                "      path: base.json\n",
+                "  - name: os\n",
+                "    build: name:build\n",
+                "    stages: []\n",
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad.yml"), "a: [1\n").unwrap();
+
+        let json = Processor::new().process(&dir.join("main.yaml")).unwrap();
+        assert_eq! {
+            json,
+            serde_json::json!({
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [] },
+                    { "name": "os", "build": "name:build", "stages": [] }
+                ]
+            }),
+        }
+        assert!(matches!(
+            Processor::new().process(&dir.join("bad.yml")),
+            Err(Error::Yaml(..))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Verify File Resolution
+    #[test]
+    fn verify_resolve() {
+        let dir = scratch("resolve");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("a/local.json"), "{}").unwrap();
+        std::fs::write(dir.join("lib/shared.json"), "{}").unwrap();
+
+        let mut p = Processor::new();
+        p.add_search_path(dir.join("lib"));
+        let from = dir.join("a/main.json");
+
+        assert_eq!(
+            p.resolve(&from, "local.json").unwrap(),
+            dir.join("a/local.json")
+        );
+        assert_eq!(
+            p.resolve(&from, "shared.json").unwrap(),
+            dir.join("lib/shared.json")
+        );
+        assert!(matches!(
+            p.resolve(&from, "none.json"),
+            Err(Error::NotFound(..))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,303 @@
+//! Dependency Resolution
+//!
+//! The `mpp-depsolve` directive lists the packages to install together
+//! with the repositories to take them from. The preprocessor passes it to
+//! a dependency solver, and replaces it with references to the resolved
+//! packages, adding a source item with the download URL of each package.
+//!
+//! In version-1 manifests, the directive is a member of the options of an
+//! rpm stage and is replaced by the `packages` option, with the package
+//! URLs added to the `urls` of `org.osbuild.files`. In version-2
+//! manifests, the directive is a member of a stage input and is replaced
+//! by its `references`, with the package URLs added to the `items` of
+//! `org.osbuild.curl`.
+//!
+//! The solver is provided by the caller via the [`Solver`] trait, so the
+//! preprocessor does not depend on a particular solver implementation.
+
+use crate::manifest::Json;
+use crate::mpp::{self, Error, Processor};
+use std::path::Path;
+
+type Map = serde_json::Map<String, Json>;
+
+/// Repository
+///
+/// A repository to resolve packages from. At least one of `baseurl`,
+/// `metalink`, or `mirrorlist` is required. Relative base URLs are
+/// resolved against the base URL of the request.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Repository {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseurl: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metalink: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrorlist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpgkey: Option<String>,
+}
+
+/// Depsolve Request
+///
+/// The arguments of an `mpp-depsolve` directive.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Request {
+    pub architecture: String,
+    pub module_platform_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub releasever: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseurl: Option<String>,
+    #[serde(default)]
+    pub repos: Vec<Repository>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+}
+
+/// Resolved Package
+///
+/// A package of the resolved transaction, with the checksum used to refer
+/// to it and the URL to fetch it from.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Package {
+    pub name: String,
+    pub checksum: String,
+    pub url: String,
+}
+
+/// Dependency Solver
+///
+/// A backend resolving the package set of a request into the full list
+/// of packages to install. Errors are returned as human-readable
+/// description.
+pub trait Solver: std::fmt::Debug {
+    fn depsolve(&self, request: &Request) -> Result<Vec<Package>, String>;
+}
+
+// Resolve relative repository URLs against the base URL of the request.
+fn resolve_urls(request: &mut Request) {
+    let base = match &request.baseurl {
+        None => return,
+        Some(v) => v.trim_end_matches('/').to_owned(),
+    };
+    for repo in &mut request.repos {
+        match &repo.baseurl {
+            Some(v) if !v.contains("://") => {
+                repo.baseurl = Some(format!("{}/{}", base, v.trim_start_matches('/')));
+            }
+            Some(_) => {}
+            None if repo.metalink.is_none() && repo.mirrorlist.is_none() => {
+                repo.baseurl = Some(base.clone());
+            }
+            None => {}
+        }
+    }
+}
+
+// Add the resolved packages to the object that contained the directive.
+fn insert(target: &mut Map, key: &str, packages: &[Package]) {
+    match target.entry(key).or_insert_with(|| Json::Array(Vec::new())) {
+        Json::Array(v) => v.extend(packages.iter().map(|p| Json::String(p.checksum.clone()))),
+        Json::Object(v) => {
+            for p in packages {
+                v.entry(p.checksum.clone())
+                    .or_insert_with(|| Json::Object(Map::new()));
+            }
+        }
+        _ => {}
+    }
+}
+
+// Expand all depsolve directives of the manifest loaded from `from`.
+pub(crate) fn expand(p: &Processor, manifest: &mut Json, from: &Path) -> Result<(), Error> {
+    let invalid = |m: String| Error::Invalid(from.to_owned(), m);
+
+    let directives = mpp::take_directives(manifest, "mpp-depsolve");
+    if directives.is_empty() {
+        return Ok(());
+    }
+
+    let solver = p
+        .solver
+        .as_ref()
+        .ok_or_else(|| invalid("`mpp-depsolve` requires a solver".to_owned()))?;
+    let (key, source, field) = if manifest.get("version").and_then(Json::as_str) == Some("2") {
+        ("references", "org.osbuild.curl", "items")
+    } else {
+        ("packages", "org.osbuild.files", "urls")
+    };
+
+    for (pointer, directive) in directives {
+        let mut request: Request = serde_json::from_value(directive)
+            .map_err(|e| invalid(format!("invalid `mpp-depsolve`: {}", e)))?;
+        resolve_urls(&mut request);
+        let packages = solver
+            .depsolve(&request)
+            .map_err(|e| Error::Solver(from.to_owned(), e))?;
+
+        if let Some(Json::Object(target)) = manifest.pointer_mut(&pointer) {
+            insert(target, key, &packages);
+        }
+
+        let items = manifest
+            .as_object_mut()
+            .and_then(|v| {
+                v.entry("sources")
+                    .or_insert_with(|| Json::Object(Map::new()))
+                    .as_object_mut()
+            })
+            .and_then(|v| {
+                v.entry(source)
+                    .or_insert_with(|| Json::Object(Map::new()))
+                    .as_object_mut()
+            })
+            .and_then(|v| {
+                v.entry(field)
+                    .or_insert_with(|| Json::Object(Map::new()))
+                    .as_object_mut()
+            })
+            .ok_or_else(|| invalid("malformed `sources`".to_owned()))?;
+        for p in packages {
+            items.entry(p.checksum).or_insert(Json::String(p.url));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpp::tests::scratch;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Fixed;
+
+    impl Solver for Fixed {
+        fn depsolve(&self, request: &Request) -> Result<Vec<Package>, String> {
+            let base = request.repos[0].baseurl.clone().unwrap_or_default();
+            if request.packages.iter().any(|v| v == "missing") {
+                return Err("no package `missing` available".to_owned());
+            }
+            Ok(request
+                .packages
+                .iter()
+                .map(|v| Package {
+                    name: v.clone(),
+                    checksum: format!("sha256:{}", v),
+                    url: format!("{}/{}.rpm", base, v),
+                })
+                .collect())
+        }
+    }
+
+    // Verify Depsolve Expansion
+    #[test]
+    fn verify_depsolve() {
+        let dir = scratch("depsolve");
+        std::fs::write(
+            dir.join("v1.json"),
+            r#"{
+                "pipeline": {
+                    "stages": [{
+                        "name": "org.osbuild.rpm",
+                        "options": {
+                            "mpp-depsolve": {
+                                "architecture": "x86_64",
+                                "module-platform-id": "f39",
+                                "baseurl": "https://example.com/fedora/",
+                                "repos": [{ "id": "default", "baseurl": "everything/x86_64" }],
+                                "packages": ["bash", "kernel"]
+                            }
+                        }
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("v2.json"),
+            r#"{
+                "version": "2",
+                "pipelines": [{
+                    "name": "os",
+                    "stages": [{
+                        "type": "org.osbuild.rpm",
+                        "inputs": {
+                            "packages": {
+                                "type": "org.osbuild.files",
+                                "origin": "org.osbuild.source",
+                                "mpp-depsolve": {
+                                    "architecture": "x86_64",
+                                    "module-platform-id": "f39",
+                                    "repos": [{ "id": "default", "baseurl": "https://example.com" }],
+                                    "packages": ["bash"]
+                                }
+                            }
+                        }
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        // Without a solver, the directive cannot be expanded.
+        assert!(matches!(
+            Processor::new().process(&dir.join("v1.json")),
+            Err(Error::Invalid(..)),
+        ));
+
+        let mut p = Processor::new();
+        p.set_solver(Arc::new(Fixed));
+
+        let json = p.process(&dir.join("v1.json")).unwrap();
+        let manifest: crate::manifest::Manifest1 = serde_json::from_value(json).unwrap();
+        let packages = crate::packages::packages(&manifest);
+        assert_eq!(packages.len(), 2);
+        assert_eq! {
+            packages[0].url.as_deref(),
+            Some("https://example.com/fedora/everything/x86_64/bash.rpm"),
+        }
+
+        let json = p.process(&dir.join("v2.json")).unwrap();
+        assert_eq! {
+            json["pipelines"][0]["stages"][0]["inputs"]["packages"],
+            serde_json::json!({
+                "type": "org.osbuild.files",
+                "origin": "org.osbuild.source",
+                "references": ["sha256:bash"],
+            }),
+        }
+        assert_eq! {
+            json["sources"],
+            serde_json::json!({
+                "org.osbuild.curl": { "items": { "sha256:bash": "https://example.com/bash.rpm" } }
+            }),
+        }
+
+        std::fs::write(
+            dir.join("missing.json"),
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.rpm", "options": { "mpp-depsolve": {
+                "architecture": "x86_64", "module-platform-id": "f39",
+                "repos": [{ "id": "default", "baseurl": "https://example.com" }],
+                "packages": ["missing"]
+            } } }] } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            p.process(&dir.join("missing.json")),
+            Err(Error::Solver(..)),
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
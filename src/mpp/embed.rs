@@ -0,0 +1,181 @@
+//! Embedded Files
+//!
+//! The `mpp-embed` directive embeds a file into the manifest, so the
+//! manifest is self-contained and does not need the source tree it was
+//! generated from. The directive is a member of a stage input and takes an
+//! `id` together with either a `path` to a local file or a literal `text`.
+//!
+//! The content is added as item of the `org.osbuild.inline` source,
+//! base64-encoded and keyed by its SHA-256 digest, and the directive is
+//! replaced by a reference to that item. The digests are made available to
+//! other directives as the `embedded` variable, mapping identifiers to
+//! digests, so stages can refer to embedded files by their identifier.
+//!
+//! Inputs and inline sources only exist in version-2 manifests.
+
+use crate::hash;
+use crate::manifest::Json;
+use crate::mpp::{self, Error, Processor};
+use std::path::Path;
+
+type Map = serde_json::Map<String, Json>;
+
+// Load the content of an embed directive.
+fn content(p: &Processor, directive: &Json, from: &Path) -> Result<Vec<u8>, Error> {
+    let invalid = |m: &str| Error::Invalid(from.to_owned(), m.to_owned());
+
+    match (
+        directive.get("path").and_then(Json::as_str),
+        directive.get("text").and_then(Json::as_str),
+    ) {
+        (Some(path), None) => {
+            let path = p.resolve(from, path)?;
+            std::fs::read(&path).map_err(|e| Error::Io(path, e))
+        }
+        (None, Some(text)) => Ok(text.as_bytes().to_vec()),
+        _ => Err(invalid(
+            "`mpp-embed` requires exactly one of `path` and `text`",
+        )),
+    }
+}
+
+// Expand all embed directives of the manifest loaded from `from`, and
+// return the digests of the embedded files by their identifiers.
+pub(crate) fn expand(p: &Processor, manifest: &mut Json, from: &Path) -> Result<Map, Error> {
+    let invalid = |m: String| Error::Invalid(from.to_owned(), m);
+    let mut embedded = Map::new();
+
+    let directives = mpp::take_directives(manifest, "mpp-embed");
+    if directives.is_empty() {
+        return Ok(embedded);
+    }
+    if manifest.get("version").and_then(Json::as_str) != Some("2") {
+        return Err(invalid(
+            "`mpp-embed` requires a version-2 manifest".to_owned(),
+        ));
+    }
+
+    let mut items = Map::new();
+    for (pointer, directive) in directives {
+        let data = content(p, &directive, from)?;
+        let digest = format!("sha256:{}", hash::to_hex(&hash::Sha256::digest(&data)));
+
+        if let Some(id) = directive.get("id").and_then(Json::as_str) {
+            match embedded.get(id) {
+                Some(v) if v.as_str() != Some(digest.as_str()) => {
+                    return Err(invalid(format!("embedded id `{}` is not unique", id)));
+                }
+                _ => {
+                    embedded.insert(id.to_owned(), Json::String(digest.clone()));
+                }
+            }
+        }
+
+        if let Some(Json::Object(target)) = manifest.pointer_mut(&pointer) {
+            match target
+                .entry("references")
+                .or_insert_with(|| Json::Object(Map::new()))
+            {
+                Json::Object(v) => {
+                    v.entry(digest.clone())
+                        .or_insert_with(|| Json::Object(Map::new()));
+                }
+                Json::Array(v) => v.push(Json::String(digest.clone())),
+                _ => {}
+            }
+        }
+
+        items.insert(
+            digest,
+            serde_json::json!({ "encoding": "base64", "data": hash::to_base64(&data) }),
+        );
+    }
+
+    mpp::merge_sources(
+        manifest,
+        &serde_json::json!({ "sources": { "org.osbuild.inline": { "items": items } } }),
+    );
+
+    Ok(embedded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpp::tests::scratch;
+
+    // Verify File Embedding
+    #[test]
+    fn verify_embed() {
+        let dir = scratch("embed");
+        std::fs::write(dir.join("hello.txt"), "abc").unwrap();
+        std::fs::write(
+            dir.join("main.json"),
+            r#"{
+                "version": "2",
+                "pipelines": [{
+                    "name": "os",
+                    "stages": [{
+                        "type": "org.osbuild.copy",
+                        "inputs": {
+                            "file": {
+                                "type": "org.osbuild.files",
+                                "origin": "org.osbuild.source",
+                                "mpp-embed": { "id": "hello", "path": "hello.txt" }
+                            },
+                            "motd": {
+                                "type": "org.osbuild.files",
+                                "origin": "org.osbuild.source",
+                                "mpp-embed": { "id": "motd", "text": "foobar" }
+                            }
+                        },
+                        "options": {
+                            "paths": [{
+                                "from": { "mpp-format-string": "input://file/{embedded['hello']}" },
+                                "to": "tree:///etc/hello"
+                            }]
+                        }
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let digest = "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let json = Processor::new().process(&dir.join("main.json")).unwrap();
+        let stage = &json["pipelines"][0]["stages"][0];
+        assert_eq! {
+            stage["inputs"]["file"],
+            serde_json::json!({
+                "type": "org.osbuild.files",
+                "origin": "org.osbuild.source",
+                "references": { digest: {} },
+            }),
+        }
+        assert_eq! {
+            stage["options"]["paths"][0]["from"],
+            format!("input://file/{}", digest),
+        }
+        let items = &json["sources"]["org.osbuild.inline"]["items"];
+        assert_eq! {
+            items[digest],
+            serde_json::json!({ "encoding": "base64", "data": "YWJj" }),
+        }
+        assert_eq! {
+            items.as_object().unwrap().values().map(|v| &v["data"]).collect::<Vec<_>>(),
+            ["YWJj", "Zm9vYmFy"],
+        }
+
+        std::fs::write(
+            dir.join("v1.json"),
+            r#"{ "pipeline": { "stages": [{ "name": "x", "options": { "mpp-embed": { "text": "" } } }] } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Processor::new().process(&dir.join("v1.json")),
+            Err(Error::Invalid(..)),
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
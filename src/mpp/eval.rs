@@ -0,0 +1,935 @@
+//! Expression Evaluation
+//!
+//! osbuild-mpp evaluates Python expressions for `mpp-eval` and inside the
+//! replacement fields of `mpp-format-string`. Evaluating arbitrary Python
+//! is neither possible nor desirable, so this module implements a small,
+//! side-effect free subset of the Python expression syntax on JSON values:
+//!
+//!  * literals: numbers, strings, `True`, `False`, `None`, lists, and dicts,
+//!  * variables, subscripts, and attribute access on dicts,
+//!  * arithmetic (`+`, `-`, `*`, `/`, `//`, `%`), comparisons (including
+//!    chains, `in`, and `not in`), `and`, `or`, `not`, and conditional
+//!    expressions,
+//!  * the functions `len`, `str`, `int`, `float`, `bool`, `min`, and `max`,
+//!  * the string methods `lower`, `upper`, `strip`, `replace`, `split`,
+//!    `join`, `startswith`, and `endswith`, and the dict method `get`.
+//!
+//! Anything else is rejected with an error, as are expressions nested
+//! deeper than `MAX_DEPTH`, and strings or lists built by `+` and `*` that
+//! exceed `MAX_LENGTH`.
+
+use crate::manifest::Json;
+
+type Map = serde_json::Map<String, Json>;
+
+/// Maximum Nesting of Expressions
+///
+/// Every operator, call, subscript, and literal list or dict adds a level,
+/// matching the nesting limit of the JSON parser.
+pub const MAX_DEPTH: usize = 128;
+
+/// Maximum Length of Computed Strings and Lists
+pub const MAX_LENGTH: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(Json),
+    Str(String),
+    Name(String),
+    Op(&'static str),
+}
+
+const OPS: &[&str] = &[
+    "//", "==", "!=", "<=", ">=", "(", ")", "[", "]", "{", "}", ",", ":", ".", "+", "-", "*", "/",
+    "%", "<", ">",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut acc = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                    end = j + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let text = s[i..end].replace('_', "");
+            let number = if let Ok(v) = text.parse::<i64>() {
+                Json::from(v)
+            } else {
+                text.parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Json::Number)
+                    .ok_or_else(|| format!("invalid number `{}`", &s[i..end]))?
+            };
+            acc.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    end = j + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            acc.push(Token::Name(s[i..end].to_owned()));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err("unterminated string".to_owned()),
+                    Some((_, v)) if v == c => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((_, v @ ('\\' | '\'' | '"'))) => value.push(v),
+                        _ => return Err("unsupported escape sequence".to_owned()),
+                    },
+                    Some((_, v)) => value.push(v),
+                }
+            }
+            acc.push(Token::Str(value));
+        } else {
+            let op = OPS
+                .iter()
+                .find(|v| s[i..].starts_with(**v))
+                .ok_or_else(|| format!("unexpected character `{}`", c))?;
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            acc.push(Token::Op(op));
+        }
+    }
+
+    Ok(acc)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Value(Json),
+    Var(String),
+    List(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, Vec<(&'static str, Expr)>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Attr(Box<Expr>, String),
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    // Enter a nested expression. Callers restore the depth once the
+    // expression is complete.
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err("expression nested too deeply".to_owned()),
+            false => Ok(()),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let v = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        v
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Token::Op(v)) if *v == op)
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(v)) if v == name)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.is_op(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", op))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        self.enter()?;
+        let v = self.conditional();
+        self.depth = depth;
+        v
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let v = self.or_test()?;
+        if self.is_name("if") {
+            self.pos += 1;
+            let condition = self.or_test()?;
+            if !self.is_name("else") {
+                return Err("expected `else`".to_owned());
+            }
+            self.pos += 1;
+            let otherwise = self.expr()?;
+            return Ok(Expr::If(
+                Box::new(condition),
+                Box::new(v),
+                Box::new(otherwise),
+            ));
+        }
+        Ok(v)
+    }
+
+    fn or_test(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut v = self.and_test()?;
+        while self.is_name("or") {
+            self.pos += 1;
+            self.enter()?;
+            v = Expr::Or(Box::new(v), Box::new(self.and_test()?));
+        }
+        self.depth = depth;
+        Ok(v)
+    }
+
+    fn and_test(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut v = self.not_test()?;
+        while self.is_name("and") {
+            self.pos += 1;
+            self.enter()?;
+            v = Expr::And(Box::new(v), Box::new(self.not_test()?));
+        }
+        self.depth = depth;
+        Ok(v)
+    }
+
+    fn not_test(&mut self) -> Result<Expr, String> {
+        if self.is_name("not") {
+            self.pos += 1;
+            let depth = self.depth;
+            self.enter()?;
+            let v = self.not_test()?;
+            self.depth = depth;
+            return Ok(Expr::Unary("not", Box::new(v)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let first = self.arith()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(v)) if ["==", "!=", "<", "<=", ">", ">="].contains(v) => *v,
+                Some(Token::Name(v)) if v == "in" => "in",
+                Some(Token::Name(v))
+                    if v == "not"
+                        && matches!(self.tokens.get(self.pos + 1), Some(Token::Name(v)) if v == "in") =>
+                {
+                    self.pos += 1;
+                    "not in"
+                }
+                _ => break,
+            };
+            self.pos += 1;
+            rest.push((op, self.arith()?));
+        }
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            Ok(Expr::Compare(Box::new(first), rest))
+        }
+    }
+
+    fn arith(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut v = self.term()?;
+        while let Some(Token::Op(op @ ("+" | "-"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            self.enter()?;
+            v = Expr::Binary(op, Box::new(v), Box::new(self.term()?));
+        }
+        self.depth = depth;
+        Ok(v)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut v = self.factor()?;
+        while let Some(Token::Op(op @ ("*" | "/" | "//" | "%"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            self.enter()?;
+            v = Expr::Binary(op, Box::new(v), Box::new(self.factor()?));
+        }
+        self.depth = depth;
+        Ok(v)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        if let Some(Token::Op(op @ ("-" | "+"))) = self.peek() {
+            let op = *op;
+            self.pos += 1;
+            let depth = self.depth;
+            self.enter()?;
+            let v = self.factor()?;
+            self.depth = depth;
+            return Ok(Expr::Unary(op, Box::new(v)));
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut v = self.atom()?;
+        loop {
+            if !self.is_op("[") && !self.is_op(".") && !self.is_op("(") {
+                self.depth = depth;
+                return Ok(v);
+            }
+            self.enter()?;
+            if self.is_op("[") {
+                self.pos += 1;
+                let index = self.expr()?;
+                self.expect("]")?;
+                v = Expr::Index(Box::new(v), Box::new(index));
+            } else if self.is_op(".") {
+                self.pos += 1;
+                match self.next() {
+                    Some(Token::Name(name)) => v = Expr::Attr(Box::new(v), name),
+                    _ => return Err("expected attribute name".to_owned()),
+                }
+            } else if self.is_op("(") {
+                self.pos += 1;
+                let args = self.sequence(")")?;
+                v = Expr::Call(Box::new(v), args);
+            }
+        }
+    }
+
+    // Parse a comma separated list of expressions up to the closing token.
+    fn sequence(&mut self, close: &str) -> Result<Vec<Expr>, String> {
+        let mut acc = Vec::new();
+        while !self.is_op(close) {
+            acc.push(self.expr()?);
+            if !self.is_op(close) {
+                self.expect(",")?;
+            }
+        }
+        self.pos += 1;
+        Ok(acc)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Expr::Value(v)),
+            Some(Token::Str(mut v)) => {
+                // Adjacent string literals are concatenated.
+                while let Some(Token::Str(next)) = self.peek() {
+                    v.push_str(next);
+                    self.pos += 1;
+                }
+                Ok(Expr::Value(Json::String(v)))
+            }
+            Some(Token::Name(v)) => Ok(match v.as_str() {
+                "True" => Expr::Value(Json::Bool(true)),
+                "False" => Expr::Value(Json::Bool(false)),
+                "None" => Expr::Value(Json::Null),
+                "and" | "or" | "not" | "in" | "if" | "else" | "lambda" => {
+                    return Err(format!("unexpected keyword `{}`", v))
+                }
+                _ => Expr::Var(v),
+            }),
+            Some(Token::Op("(")) => {
+                let v = self.expr()?;
+                self.expect(")")?;
+                Ok(v)
+            }
+            Some(Token::Op("[")) => Ok(Expr::List(self.sequence("]")?)),
+            Some(Token::Op("{")) => {
+                let mut acc = Vec::new();
+                while !self.is_op("}") {
+                    let key = self.expr()?;
+                    self.expect(":")?;
+                    acc.push((key, self.expr()?));
+                    if !self.is_op("}") {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Expr::Dict(acc))
+            }
+            Some(v) => Err(format!("unexpected token {:?}", v)),
+            None => Err("unexpected end of expression".to_owned()),
+        }
+    }
+}
+
+fn truthy(v: &Json) -> bool {
+    match v {
+        Json::Null => false,
+        Json::Bool(v) => *v,
+        Json::Number(v) => v.as_f64().is_some_and(|v| v != 0.0),
+        Json::String(v) => !v.is_empty(),
+        Json::Array(v) => !v.is_empty(),
+        Json::Object(v) => !v.is_empty(),
+    }
+}
+
+fn type_name(v: &Json) -> &'static str {
+    match v {
+        Json::Null => "NoneType",
+        Json::Bool(_) => "bool",
+        Json::Number(v) if v.is_f64() => "float",
+        Json::Number(_) => "int",
+        Json::String(_) => "str",
+        Json::Array(_) => "list",
+        Json::Object(_) => "dict",
+    }
+}
+
+/// Convert to String
+///
+/// Render a value like Python's `str()` does. Lists and dicts are rendered
+/// as JSON, which differs from Python in the spelling of strings and
+/// constants.
+pub fn to_str(v: &Json) -> String {
+    match v {
+        Json::Null => "None".to_owned(),
+        Json::Bool(true) => "True".to_owned(),
+        Json::Bool(false) => "False".to_owned(),
+        Json::String(v) => v.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn float(v: f64) -> Result<Json, String> {
+    serde_json::Number::from_f64(v)
+        .map(Json::Number)
+        .ok_or_else(|| "arithmetic result is not finite".to_owned())
+}
+
+fn compare(op: &str, a: &Json, b: &Json) -> Result<bool, String> {
+    let ordering = match (a, b) {
+        (Json::Number(x), Json::Number(y)) => match (x.as_i64(), y.as_i64()) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => x
+                .as_f64()
+                .unwrap_or(0.0)
+                .partial_cmp(&y.as_f64().unwrap_or(0.0)),
+        },
+        (Json::String(x), Json::String(y)) => x.partial_cmp(y),
+        _ => None,
+    };
+    Ok(match op {
+        "==" => ordering.map_or(a == b, |v| v.is_eq()),
+        "!=" => ordering.map_or(a != b, |v| v.is_ne()),
+        "in" | "not in" => {
+            let found = match b {
+                Json::String(b) => match a {
+                    Json::String(a) => b.contains(a.as_str()),
+                    _ => return Err("`in <str>` requires a string".to_owned()),
+                },
+                Json::Array(b) => b.iter().any(|v| compare("==", a, v).unwrap_or(false)),
+                Json::Object(b) => a.as_str().is_some_and(|a| b.contains_key(a)),
+                _ => return Err(format!("`in` is not supported on {}", type_name(b))),
+            };
+            found == (op == "in")
+        }
+        _ => {
+            let v = ordering
+                .ok_or_else(|| format!("cannot compare {} and {}", type_name(a), type_name(b)))?;
+            match op {
+                "<" => v.is_lt(),
+                "<=" => v.is_le(),
+                ">" => v.is_gt(),
+                _ => v.is_ge(),
+            }
+        }
+    })
+}
+
+fn binary(op: &str, a: Json, b: Json) -> Result<Json, String> {
+    match (op, &a, &b) {
+        ("+", Json::String(x), Json::String(y)) if x.len() + y.len() > MAX_LENGTH => {
+            return Err("string concatenation result too long".to_owned())
+        }
+        ("+", Json::Array(x), Json::Array(y)) if x.len() + y.len() > MAX_LENGTH => {
+            return Err("list concatenation result too long".to_owned())
+        }
+        ("+", Json::String(x), Json::String(y)) => return Ok(Json::String(format!("{}{}", x, y))),
+        ("+", Json::Array(x), Json::Array(y)) => {
+            return Ok(Json::Array(x.iter().chain(y.iter()).cloned().collect()))
+        }
+        ("*", Json::String(x), Json::Number(n)) | ("*", Json::Number(n), Json::String(x)) => {
+            let n = n.as_u64().ok_or("string repetition requires an integer")?;
+            return match x.len().checked_mul(n as usize) {
+                Some(v) if v <= MAX_LENGTH => Ok(Json::String(x.repeat(n as usize))),
+                _ => Err("string repetition result too long".to_owned()),
+            };
+        }
+        ("%", Json::String(_), _) => {
+            return Err("printf-style formatting is not supported".to_owned())
+        }
+        (_, Json::Number(_), Json::Number(_)) => {}
+        _ => {
+            return Err(format!(
+                "unsupported operand types for {}: {} and {}",
+                op,
+                type_name(&a),
+                type_name(&b),
+            ))
+        }
+    }
+
+    let (x, y) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+    if let (Some(x), Some(y)) = (a.as_i64(), b.as_i64()) {
+        let v = match op {
+            "+" => x.checked_add(y),
+            "-" => x.checked_sub(y),
+            "*" => x.checked_mul(y),
+            "//" | "%" if y == 0 => return Err("division by zero".to_owned()),
+            "//" => Some(x.div_euclid(y) - if y < 0 && x.rem_euclid(y) != 0 { 1 } else { 0 }),
+            "%" => {
+                Some(x - y * (x.div_euclid(y) - if y < 0 && x.rem_euclid(y) != 0 { 1 } else { 0 }))
+            }
+            _ => None,
+        };
+        if let Some(v) = v {
+            return Ok(Json::from(v));
+        }
+    }
+    match op {
+        "+" => float(x + y),
+        "-" => float(x - y),
+        "*" => float(x * y),
+        _ if y == 0.0 => Err("division by zero".to_owned()),
+        "/" => float(x / y),
+        "//" => float((x / y).floor()),
+        _ => float(x - y * (x / y).floor()),
+    }
+}
+
+fn call_function(name: &str, args: Vec<Json>) -> Result<Json, String> {
+    let one = |args: &[Json]| -> Result<Json, String> {
+        match args {
+            [v] => Ok(v.clone()),
+            _ => Err(format!("`{}` takes exactly one argument", name)),
+        }
+    };
+    match name {
+        "len" => match one(&args)? {
+            Json::String(v) => Ok(Json::from(v.chars().count())),
+            Json::Array(v) => Ok(Json::from(v.len())),
+            Json::Object(v) => Ok(Json::from(v.len())),
+            v => Err(format!("{} has no length", type_name(&v))),
+        },
+        "str" => Ok(Json::String(to_str(&one(&args)?))),
+        "bool" => Ok(Json::Bool(truthy(&one(&args)?))),
+        "int" => match one(&args)? {
+            Json::Bool(v) => Ok(Json::from(v as i64)),
+            Json::Number(v) => match v.as_i64() {
+                Some(v) => Ok(Json::from(v)),
+                None => Ok(Json::from(v.as_f64().unwrap_or(0.0).trunc() as i64)),
+            },
+            Json::String(v) => v
+                .trim()
+                .parse::<i64>()
+                .map(Json::from)
+                .map_err(|_| format!("invalid literal for int(): `{}`", v)),
+            v => Err(format!("cannot convert {} to int", type_name(&v))),
+        },
+        "float" => match one(&args)? {
+            Json::Number(v) => float(v.as_f64().unwrap_or(0.0)),
+            Json::String(v) => v
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid literal for float(): `{}`", v))
+                .and_then(float),
+            v => Err(format!("cannot convert {} to float", type_name(&v))),
+        },
+        "min" | "max" => {
+            let list = match args.as_slice() {
+                [Json::Array(v)] => v.clone(),
+                _ => args,
+            };
+            let mut it = list.into_iter();
+            let mut best = it
+                .next()
+                .ok_or_else(|| format!("`{}` of empty sequence", name))?;
+            for v in it {
+                let better = if name == "min" {
+                    compare("<", &v, &best)?
+                } else {
+                    compare(">", &v, &best)?
+                };
+                if better {
+                    best = v;
+                }
+            }
+            Ok(best)
+        }
+        _ => Err(format!("unknown function `{}`", name)),
+    }
+}
+
+fn call_method(object: Json, name: &str, args: Vec<Json>) -> Result<Json, String> {
+    let string = |v: &Json| -> Result<String, String> {
+        v.as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| format!("`{}` requires string arguments", name))
+    };
+    match (&object, name, args.as_slice()) {
+        (Json::String(s), "lower", []) => Ok(Json::String(s.to_lowercase())),
+        (Json::String(s), "upper", []) => Ok(Json::String(s.to_uppercase())),
+        (Json::String(s), "strip", []) => Ok(Json::String(s.trim().to_owned())),
+        (Json::String(s), "replace", [a, b]) => {
+            Ok(Json::String(s.replace(&string(a)?, &string(b)?)))
+        }
+        (Json::String(s), "split", []) => Ok(Json::Array(
+            s.split_whitespace()
+                .map(|v| Json::String(v.to_owned()))
+                .collect(),
+        )),
+        (Json::String(s), "split", [sep]) => Ok(Json::Array(
+            s.split(string(sep)?.as_str())
+                .map(|v| Json::String(v.to_owned()))
+                .collect(),
+        )),
+        (Json::String(s), "join", [Json::Array(list)]) => {
+            let parts = list.iter().map(string).collect::<Result<Vec<_>, _>>()?;
+            Ok(Json::String(parts.join(s)))
+        }
+        (Json::String(s), "startswith", [v]) => Ok(Json::Bool(s.starts_with(&string(v)?))),
+        (Json::String(s), "endswith", [v]) => Ok(Json::Bool(s.ends_with(&string(v)?))),
+        (Json::Object(o), "get", [k]) => Ok(o.get(&string(k)?).cloned().unwrap_or(Json::Null)),
+        (Json::Object(o), "get", [k, d]) => Ok(o.get(&string(k)?).cloned().unwrap_or(d.clone())),
+        _ => Err(format!(
+            "unsupported method `{}` on {}",
+            name,
+            type_name(&object)
+        )),
+    }
+}
+
+fn index(object: Json, key: Json) -> Result<Json, String> {
+    let items = match (object, &key) {
+        (Json::Object(v), Json::String(k)) => {
+            return v
+                .get(k)
+                .cloned()
+                .ok_or_else(|| format!("key `{}` not found", k))
+        }
+        (Json::Array(v), Json::Number(_)) => v,
+        (Json::String(v), Json::Number(_)) => {
+            v.chars().map(|c| Json::String(c.to_string())).collect()
+        }
+        (v, _) => {
+            return Err(format!(
+                "{} is not subscriptable by {}",
+                type_name(&v),
+                type_name(&key)
+            ))
+        }
+    };
+
+    let n = key.as_i64().ok_or("indices must be integers")?;
+    let i = if n < 0 { items.len() as i64 + n } else { n };
+    usize::try_from(i)
+        .ok()
+        .and_then(|i| items.get(i).cloned())
+        .ok_or_else(|| "index out of range".to_owned())
+}
+
+fn evaluate(expr: &Expr, vars: &Map) -> Result<Json, String> {
+    Ok(match expr {
+        Expr::Value(v) => v.clone(),
+        Expr::Var(name) => vars
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("name `{}` is not defined", name))?,
+        Expr::List(v) => Json::Array(
+            v.iter()
+                .map(|v| evaluate(v, vars))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expr::Dict(v) => {
+            let mut acc = Map::new();
+            for (k, v) in v {
+                let k = match evaluate(k, vars)? {
+                    Json::String(k) => k,
+                    _ => return Err("dict keys must be strings".to_owned()),
+                };
+                acc.insert(k, evaluate(v, vars)?);
+            }
+            Json::Object(acc)
+        }
+        Expr::Unary("not", v) => Json::Bool(!truthy(&evaluate(v, vars)?)),
+        Expr::Unary(op, v) => match evaluate(v, vars)? {
+            Json::Number(n) if *op == "+" => Json::Number(n),
+            Json::Number(n) => match n.as_i64() {
+                Some(v) => Json::from(-v),
+                None => float(-n.as_f64().unwrap_or(0.0))?,
+            },
+            v => {
+                return Err(format!(
+                    "bad operand type for unary {}: {}",
+                    op,
+                    type_name(&v)
+                ))
+            }
+        },
+        Expr::Binary(op, a, b) => binary(op, evaluate(a, vars)?, evaluate(b, vars)?)?,
+        Expr::Compare(first, rest) => {
+            let mut left = evaluate(first, vars)?;
+            for (op, right) in rest {
+                let right = evaluate(right, vars)?;
+                if !compare(op, &left, &right)? {
+                    return Ok(Json::Bool(false));
+                }
+                left = right;
+            }
+            Json::Bool(true)
+        }
+        Expr::And(a, b) => {
+            let a = evaluate(a, vars)?;
+            if truthy(&a) {
+                evaluate(b, vars)?
+            } else {
+                a
+            }
+        }
+        Expr::Or(a, b) => {
+            let a = evaluate(a, vars)?;
+            if truthy(&a) {
+                a
+            } else {
+                evaluate(b, vars)?
+            }
+        }
+        Expr::If(c, a, b) => {
+            if truthy(&evaluate(c, vars)?) {
+                evaluate(a, vars)?
+            } else {
+                evaluate(b, vars)?
+            }
+        }
+        Expr::Index(v, k) => index(evaluate(v, vars)?, evaluate(k, vars)?)?,
+        Expr::Attr(v, name) => match evaluate(v, vars)? {
+            Json::Object(o) => o
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("attribute `{}` not found", name))?,
+            v => return Err(format!("{} has no attribute `{}`", type_name(&v), name)),
+        },
+        Expr::Call(f, args) => {
+            let args = args
+                .iter()
+                .map(|v| evaluate(v, vars))
+                .collect::<Result<Vec<_>, _>>()?;
+            match f.as_ref() {
+                Expr::Var(name) if !vars.contains_key(name) => call_function(name, args)?,
+                Expr::Attr(object, name) => call_method(evaluate(object, vars)?, name, args)?,
+                _ => return Err("only builtin functions and methods can be called".to_owned()),
+            }
+        }
+    })
+}
+
+/// Evaluate Expression
+///
+/// Evaluate an expression with the given variables and return its value.
+/// Errors are returned as human-readable description.
+pub fn eval(expr: &str, vars: &Map) -> Result<Json, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        depth: 0,
+    };
+    let v = parser.expr()?;
+    if parser.pos != parser.tokens.len() {
+        return Err("unexpected trailing input".to_owned());
+    }
+    evaluate(&v, vars)
+}
+
+/// Format String
+///
+/// Expand a Python format string with the given variables. Replacement
+/// fields contain expressions, as in f-strings, and `{{` and `}}` denote
+/// literal braces. Conversions and format specifications are not
+/// supported.
+pub fn format(s: &str, vars: &Map) -> Result<String, String> {
+    let mut acc = String::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().is_some_and(|v| v.1 == '{') => {
+                chars.next();
+                acc.push('{');
+            }
+            '}' if chars.peek().is_some_and(|v| v.1 == '}') => {
+                chars.next();
+                acc.push('}');
+            }
+            '}' => return Err("single `}` in format string".to_owned()),
+            '{' => {
+                // Find the closing brace, skipping nested brackets and
+                // string literals.
+                let mut depth = 0;
+                let mut quote = None;
+                let mut end = None;
+                for (j, c) in chars.by_ref() {
+                    match (quote, c) {
+                        (Some(q), c) if c == q => quote = None,
+                        (Some(_), _) => {}
+                        (None, '\'' | '"') => quote = Some(c),
+                        (None, '(' | '[' | '{') => depth += 1,
+                        (None, ')' | ']') => depth -= 1,
+                        (None, '}') if depth > 0 => depth -= 1,
+                        (None, '}') => {
+                            end = Some(j);
+                            break;
+                        }
+                        (None, '!' | ':') if depth == 0 => {
+                            return Err("format specifications are not supported".to_owned())
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or("unterminated replacement field")?;
+                acc.push_str(&to_str(&eval(&s[i + 1..end], vars)?));
+            }
+            c => acc.push(c),
+        }
+    }
+
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Map {
+        serde_json::json!({
+            "arch": "x86_64",
+            "release": 39,
+            "debug": false,
+            "image": { "name": "base", "size": 4096 },
+            "list": [1, 2, 3],
+        })
+        .as_object()
+        .unwrap()
+        .clone()
+    }
+
+    // Verify Expression Evaluation
+    #[test]
+    fn verify_eval() {
+        let vars = vars();
+        let cases = [
+            ("1 + 2 * 3", serde_json::json!(7)),
+            ("(1 + 2) * 3", serde_json::json!(9)),
+            ("7 // 2", serde_json::json!(3)),
+            ("-7 // 2", serde_json::json!(-4)),
+            ("-7 % 3", serde_json::json!(2)),
+            ("7 / 2", serde_json::json!(3.5)),
+            (
+                "release >= 38 and arch == 'x86_64'",
+                serde_json::json!(true),
+            ),
+            ("1 < release < 40", serde_json::json!(true)),
+            (
+                "'aarch64' if arch != 'x86_64' else 'x64'",
+                serde_json::json!("x64"),
+            ),
+            ("debug or 'fallback'", serde_json::json!("fallback")),
+            ("not debug", serde_json::json!(true)),
+            ("2 in list and 5 not in list", serde_json::json!(true)),
+            ("image['size'] * 2", serde_json::json!(8192)),
+            ("image.name.upper()", serde_json::json!("BASE")),
+            ("list[-1]", serde_json::json!(3)),
+            ("len(list) + len(arch)", serde_json::json!(9)),
+            ("'-'.join(['a', str(release)])", serde_json::json!("a-39")),
+            ("image.get('missing', 'x')", serde_json::json!("x")),
+            ("max(list)", serde_json::json!(3)),
+            ("int('12') + 1", serde_json::json!(13)),
+            (
+                "{'a': [True, None]}",
+                serde_json::json!({ "a": [true, null] }),
+            ),
+            ("'a' 'b'", serde_json::json!("ab")),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(eval(expr, &vars).unwrap(), expected, "{}", expr);
+        }
+
+        // Anything outside the subset is rejected.
+        assert!(eval("__import__('os')", &vars).is_err());
+        assert!(eval("undefined", &vars).is_err());
+        assert!(eval("lambda: 1", &vars).is_err());
+        assert!(eval("1 / 0", &vars).is_err());
+        assert!(eval("arch + 1", &vars).is_err());
+        assert!(eval("list[7]", &vars).is_err());
+        assert!(eval("7 // 2,", &vars).is_err());
+
+        // Resource usage is bounded.
+        assert!(eval("'ab' * 9223372036854775807", &vars).is_err());
+        assert!(eval("'ab' * 524288 + 'ab' * 524288", &vars).is_err());
+        assert!(eval("'a' * 1048576 + 'b'", &vars).is_err());
+        assert_eq!(
+            eval("len('a' * 1048576)", &vars).unwrap(),
+            serde_json::json!(1 << 20)
+        );
+        let nested = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(eval(&nested, &vars).is_err());
+        assert!(eval(&format!("{}1", "-".repeat(10_000)), &vars).is_err());
+        assert!(eval(&vec!["1"; 10_000].join(" + "), &vars).is_err());
+        assert!(eval(&vec!["1"; 100].join(" + "), &vars).is_ok());
+    }
+
+    // Verify Format Strings
+    #[test]
+    fn verify_format() {
+        let vars = vars();
+        assert_eq! {
+            format("fedora-{release}-{arch}.{'qcow2' if not debug else 'raw'}", &vars).unwrap(),
+            "fedora-39-x86_64.qcow2",
+        }
+        assert_eq!(
+            format("{{literal}} {image['name']}", &vars).unwrap(),
+            "{literal} base"
+        );
+        assert_eq!(format("{ {'a': 1}['a'] }", &vars).unwrap(), "1");
+        assert!(format("{release:04}", &vars).is_err());
+        assert!(format("{release", &vars).is_err());
+        assert!(format("release}", &vars).is_err());
+    }
+}
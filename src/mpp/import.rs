@@ -0,0 +1,282 @@
+//! Pipeline Imports
+//!
+//! The `mpp-import-pipelines` and `mpp-import-pipeline` directives import
+//! pipelines from other manifests. In the pipeline list of a version-2
+//! manifest, an entry with `mpp-import-pipelines` is replaced by all
+//! pipelines of the referenced manifest, optionally restricted to the
+//! pipeline names listed in `ids`. An entry with `mpp-import-pipeline` is
+//! replaced by the single pipeline named `id`, and all other members of
+//! the entry override the members of the imported pipeline.
+//!
+//! In version-1 manifests, a build pipeline with `mpp-import-pipeline` is
+//! replaced by the pipeline of the referenced manifest.
+//!
+//! Imported manifests are preprocessed before their pipelines are taken,
+//! and their sources are merged into the importing manifest.
+
+use crate::manifest::Json;
+use crate::mpp::{self, Error, Processor};
+use std::path::{Path, PathBuf};
+
+type Map = serde_json::Map<String, Json>;
+
+// Parse the arguments of an import directive, returning the path and the
+// optional pipeline selection given by `key`.
+fn arguments(directive: &Json, key: &str, from: &Path) -> Result<(String, Option<Json>), Error> {
+    let invalid = |m: &str| Error::Invalid(from.to_owned(), m.to_owned());
+    let path = directive
+        .get("path")
+        .and_then(Json::as_str)
+        .ok_or_else(|| invalid("import directive lacks a `path`"))?;
+    Ok((path.to_owned(), directive.get(key).cloned()))
+}
+
+// Load and preprocess an imported manifest, merging its sources into the
+// importing manifest.
+fn load(
+    p: &Processor,
+    manifest: &mut Json,
+    name: &str,
+    from: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Json, Error> {
+    let path = p.resolve(from, name)?;
+    let imported = p.process_at(&path, stack)?;
+    mpp::merge_sources(manifest, &imported);
+    Ok(imported)
+}
+
+fn pipelines_of(imported: &Json) -> Vec<Json> {
+    imported
+        .get("pipelines")
+        .and_then(Json::as_array)
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn expand_v2(
+    p: &Processor,
+    manifest: &mut Json,
+    from: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let list = match manifest.get_mut("pipelines").and_then(Json::as_array_mut) {
+        None => return Ok(()),
+        Some(v) => std::mem::take(v),
+    };
+    let mut acc = Vec::new();
+
+    for entry in list {
+        if let Some(directive) = entry.get("mpp-import-pipelines") {
+            let (name, ids) = arguments(directive, "ids", from)?;
+            let imported = load(p, manifest, &name, from, stack)?;
+            let ids: Option<Vec<&str>> = ids
+                .as_ref()
+                .and_then(Json::as_array)
+                .map(|v| v.iter().filter_map(Json::as_str).collect());
+
+            acc.extend(pipelines_of(&imported).into_iter().filter(|v| {
+                let name = v.get("name").and_then(Json::as_str).unwrap_or("");
+                ids.as_ref().is_none_or(|ids| ids.contains(&name))
+            }));
+        } else if let Some(directive) = entry.get("mpp-import-pipeline") {
+            let (name, id) = arguments(directive, "id", from)?;
+            let id = id
+                .as_ref()
+                .and_then(Json::as_str)
+                .ok_or_else(|| {
+                    Error::Invalid(
+                        from.to_owned(),
+                        "`mpp-import-pipeline` lacks an `id`".to_owned(),
+                    )
+                })?
+                .to_owned();
+            let imported = load(p, manifest, &name, from, stack)?;
+
+            let mut pipeline = pipelines_of(&imported)
+                .into_iter()
+                .find(|v| v.get("name").and_then(Json::as_str) == Some(id.as_str()))
+                .ok_or_else(|| {
+                    Error::Invalid(
+                        from.to_owned(),
+                        format!("`{}` has no pipeline named `{}`", name, id),
+                    )
+                })?;
+
+            if let (Some(pipeline), Json::Object(entry)) = (pipeline.as_object_mut(), &entry) {
+                for (k, v) in entry {
+                    if k != "mpp-import-pipeline" {
+                        pipeline.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+            acc.push(pipeline);
+        } else {
+            acc.push(entry);
+        }
+    }
+
+    manifest["pipelines"] = Json::Array(acc);
+    Ok(())
+}
+
+fn expand_v1(
+    p: &Processor,
+    manifest: &mut Json,
+    from: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let directive = match manifest.pointer_mut("/pipeline/build") {
+        Some(Json::Object(build)) => match build.remove("mpp-import-pipeline") {
+            None => return Ok(()),
+            Some(v) => v,
+        },
+        _ => return Ok(()),
+    };
+
+    let (name, _) = arguments(&directive, "id", from)?;
+    let imported = load(p, manifest, &name, from, stack)?;
+    let pipeline = imported
+        .get("pipeline")
+        .cloned()
+        .unwrap_or_else(|| Json::Object(Map::new()));
+
+    if let Some(Json::Object(build)) = manifest.pointer_mut("/pipeline/build") {
+        build.insert("pipeline".to_owned(), pipeline);
+    }
+    Ok(())
+}
+
+// Expand all import directives of the manifest loaded from `from`.
+pub(crate) fn expand(
+    p: &Processor,
+    manifest: &mut Json,
+    from: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    if manifest.get("version").and_then(Json::as_str) == Some("2") {
+        expand_v2(p, manifest, from, stack)
+    } else {
+        expand_v1(p, manifest, from, stack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpp::tests::scratch;
+
+    // Verify Version-2 Pipeline Imports
+    #[test]
+    fn verify_v2() {
+        let dir = scratch("import-v2");
+        std::fs::write(
+            dir.join("base.json"),
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [] },
+                    { "name": "os", "stages": [{ "type": "org.osbuild.noop" }] }
+                ],
+                "sources": { "org.osbuild.curl": { "items": { "sha256:a": "https://a" } } }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.json"),
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "mpp-import-pipelines": { "path": "base.json", "ids": ["build"] } },
+                    { "mpp-import-pipeline": { "path": "base.json", "id": "os" }, "build": "name:build" },
+                    { "name": "image", "stages": [] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let json = Processor::new().process(&dir.join("main.json")).unwrap();
+        assert_eq! {
+            json,
+            serde_json::json!({
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [] },
+                    { "name": "os", "stages": [{ "type": "org.osbuild.noop" }], "build": "name:build" },
+                    { "name": "image", "stages": [] }
+                ],
+                "sources": { "org.osbuild.curl": { "items": { "sha256:a": "https://a" } } }
+            }),
+        }
+
+        std::fs::write(
+            dir.join("bad.json"),
+            r#"{ "version": "2", "pipelines": [{ "mpp-import-pipeline": { "path": "base.json", "id": "x" } }] }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Processor::new().process(&dir.join("bad.json")),
+            Err(Error::Invalid(..)),
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Verify Version-1 Build Imports
+    #[test]
+    fn verify_v1() {
+        let dir = scratch("import-v1");
+        std::fs::write(
+            dir.join("build.json"),
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.rpm" }] } }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.json"),
+            r#"{
+                "pipeline": {
+                    "build": { "mpp-import-pipeline": { "path": "build.json" }, "runner": "org.osbuild.fedora39" },
+                    "stages": []
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let json = Processor::new().process(&dir.join("main.json")).unwrap();
+        let manifest: crate::manifest::Manifest1 = serde_json::from_value(json).unwrap();
+        let build = manifest.pipeline.build.unwrap();
+        assert_eq!(build.runner, "org.osbuild.fedora39");
+        assert_eq!(build.pipeline.stages[0].name, "org.osbuild.rpm");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Verify Import Cycle Detection
+    #[test]
+    fn verify_cycle() {
+        let dir = scratch("import-cycle");
+        std::fs::write(
+            dir.join("a.json"),
+            r#"{ "version": "2", "pipelines": [{ "mpp-import-pipelines": { "path": "b.json" } }] }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.json"),
+            r#"{ "version": "2", "pipelines": [{ "mpp-import-pipelines": { "path": "a.json" } }] }"#,
+        )
+        .unwrap();
+
+        match Processor::new().process(&dir.join("a.json")) {
+            Err(Error::Cycle(v)) => {
+                let names: Vec<_> = v
+                    .iter()
+                    .map(|v| v.file_name().unwrap().to_owned())
+                    .collect();
+                assert_eq!(names, ["a.json", "b.json", "a.json"]);
+            }
+            v => panic!("unexpected result: {:?}", v),
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,173 @@
+//! Variables
+//!
+//! Manifests declare variables in the top-level `mpp-vars` object, and the
+//! preprocessor can define additional variables, which take precedence
+//! (this corresponds to the `-D` option of osbuild-mpp). Variable values
+//! may use directives themselves and can refer to previously declared
+//! variables.
+//!
+//! Variables are substituted with the following directives, each of which
+//! replaces the object it is the only member of:
+//!
+//!  * `mpp-format-string`: a format string, expanded to a string,
+//!  * `mpp-format-int`: a format string, expanded and parsed as integer,
+//!  * `mpp-format-json`: a format string, expanded and parsed as JSON,
+//!  * `mpp-eval`: an expression, replaced by its value.
+//!
+//! Embedded files are available as the `embedded` variable, which maps
+//! their identifiers to their digests (see [`crate::mpp::embed`]).
+//!
+//! Expressions are evaluated with the restricted evaluator of
+//! [`crate::mpp::eval`], never by a Python interpreter.
+
+use crate::manifest::Json;
+use crate::mpp::{eval, Error, Processor};
+use std::path::Path;
+
+type Map = serde_json::Map<String, Json>;
+
+// Expand a single directive object, if `value` is one.
+fn directive(value: &Map, vars: &Map) -> Option<Result<Json, String>> {
+    if value.len() != 1 {
+        return None;
+    }
+    let (key, arg) = value.iter().next()?;
+    let arg = match arg.as_str() {
+        None if key.starts_with("mpp-format-") || key == "mpp-eval" => {
+            return Some(Err(format!("`{}` requires a string", key)))
+        }
+        None => return None,
+        Some(v) => v,
+    };
+
+    Some(match key.as_str() {
+        "mpp-format-string" => eval::format(arg, vars).map(Json::String),
+        "mpp-format-int" => eval::format(arg, vars).and_then(|v| {
+            v.trim()
+                .parse::<i64>()
+                .map(Json::from)
+                .map_err(|_| format!("`{}` is not an integer", v))
+        }),
+        "mpp-format-json" => eval::format(arg, vars).and_then(|v| {
+            serde_json::from_str(&v).map_err(|e| format!("`{}` is not valid JSON: {}", v, e))
+        }),
+        "mpp-eval" => eval::eval(arg, vars),
+        _ => return None,
+    })
+}
+
+// Substitute all directives in `value`, recursively.
+fn substitute(value: &mut Json, vars: &Map) -> Result<(), String> {
+    match value {
+        Json::Array(v) => v.iter_mut().try_for_each(|v| substitute(v, vars)),
+        Json::Object(v) => match directive(v, vars) {
+            Some(r) => {
+                *value = r?;
+                Ok(())
+            }
+            None => v.values_mut().try_for_each(|v| substitute(v, vars)),
+        },
+        _ => Ok(()),
+    }
+}
+
+// Expand the variables of the manifest loaded from `from`.
+pub(crate) fn expand(
+    p: &Processor,
+    manifest: &mut Json,
+    from: &Path,
+    embedded: Map,
+) -> Result<(), Error> {
+    let invalid = |m: String| Error::Invalid(from.to_owned(), m);
+
+    let declared = match manifest.as_object_mut().map(|v| v.remove("mpp-vars")) {
+        None | Some(None) => Map::new(),
+        Some(Some(Json::Object(v))) => v,
+        Some(Some(_)) => return Err(invalid("`mpp-vars` must be an object".to_owned())),
+    };
+
+    let mut vars = p.defines.clone();
+    if !embedded.is_empty() {
+        vars.insert("embedded".to_owned(), Json::Object(embedded));
+    }
+    for (name, mut value) in declared {
+        if p.defines.contains_key(&name) {
+            continue;
+        }
+        substitute(&mut value, &vars).map_err(|e| invalid(format!("in `{}`: {}", name, e)))?;
+        vars.insert(name, value);
+    }
+
+    substitute(manifest, &vars).map_err(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpp::tests::scratch;
+
+    // Verify Variable Substitution
+    #[test]
+    fn verify_vars() {
+        let dir = scratch("vars");
+        std::fs::write(
+            dir.join("main.json"),
+            r#"{
+                "mpp-vars": {
+                    "release": 39,
+                    "arch": "x86_64",
+                    "runner": { "mpp-format-string": "org.osbuild.fedora{release}" }
+                },
+                "pipeline": {
+                    "build": { "pipeline": { "stages": [] }, "runner": { "mpp-eval": "runner" } },
+                    "stages": [
+                        {
+                            "name": "org.osbuild.hostname",
+                            "options": { "hostname": { "mpp-format-string": "f{release}-{arch.replace('_', '-')}" } }
+                        },
+                        {
+                            "name": "org.osbuild.test",
+                            "options": {
+                                "size": { "mpp-format-int": "{release * 1024}" },
+                                "list": { "mpp-format-json": "[{release}, {release + 1}]" },
+                                "big": { "mpp-eval": "release >= 40" }
+                            }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let json = Processor::new().process(&dir.join("main.json")).unwrap();
+        assert!(json.get("mpp-vars").is_none());
+        assert_eq!(json["pipeline"]["build"]["runner"], "org.osbuild.fedora39");
+        assert_eq! {
+            json["pipeline"]["stages"][0]["options"]["hostname"],
+            "f39-x86-64",
+        }
+        assert_eq! {
+            json["pipeline"]["stages"][1]["options"],
+            serde_json::json!({ "size": 39936, "list": [39, 40], "big": false }),
+        }
+
+        // Defines take precedence over declared variables.
+        let mut p = Processor::new();
+        p.define("release", Json::from(40));
+        let json = p.process(&dir.join("main.json")).unwrap();
+        assert_eq!(json["pipeline"]["build"]["runner"], "org.osbuild.fedora40");
+        assert_eq!(json["pipeline"]["stages"][1]["options"]["big"], true);
+
+        std::fs::write(
+            dir.join("bad.json"),
+            r#"{ "pipeline": { "stages": [{ "name": { "mpp-eval": "undefined" } }] } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Processor::new().process(&dir.join("bad.json")),
+            Err(Error::Invalid(..)),
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
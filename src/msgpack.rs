@@ -0,0 +1,331 @@
+//! MessagePack Serialization
+//!
+//! This module encodes manifests as MessagePack, the binary format common
+//! on message queues, where the size and parse cost of JSON text matter.
+//! Like the `cbor` module, values are encoded via their JSON form, so the
+//! MessagePack form converts back to it losslessly.
+//!
+//! Encoding picks the smallest representation of each value, and 64-bit
+//! floats. Decoding accepts all representations, except binary and
+//! extension types, which have no JSON equivalent and are rejected.
+
+use crate::manifest::{Json, Manifest};
+
+// Maximum nesting of arrays and maps, matching the JSON parser.
+const MAX_DEPTH: usize = 128;
+
+/// MessagePack Error
+#[derive(Debug)]
+pub enum Error {
+    /// The data ended before the value was complete.
+    Truncated,
+    /// The data is not well-formed MessagePack, or has no JSON equivalent.
+    Invalid(&'static str),
+    /// The decoded value does not match the requested type.
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Truncated => write!(fmt, "truncated MessagePack data"),
+            Error::Invalid(v) => write!(fmt, "invalid MessagePack data: {}", v),
+            Error::Json(v) => v.fmt(fmt),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Append a length with the fixed-size marker, or the 16-bit or 32-bit
+// marker following it.
+fn length(fixed: u8, max: usize, marker: u8, n: usize, acc: &mut Vec<u8>) {
+    if n <= max {
+        acc.push(fixed | n as u8);
+    } else if n <= 0xffff {
+        acc.push(marker);
+        acc.extend_from_slice(&(n as u16).to_be_bytes());
+    } else {
+        acc.push(marker + 1);
+        acc.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+}
+
+fn string(v: &str, acc: &mut Vec<u8>) {
+    if v.len() > 31 && v.len() <= 0xff {
+        acc.extend_from_slice(&[0xd9, v.len() as u8]);
+    } else {
+        length(0xa0, 31, 0xda, v.len(), acc);
+    }
+    acc.extend_from_slice(v.as_bytes());
+}
+
+/// Encode JSON Value
+///
+/// Append the MessagePack encoding of the value to the buffer.
+pub fn encode(value: &Json, acc: &mut Vec<u8>) {
+    match value {
+        Json::Null => acc.push(0xc0),
+        Json::Bool(false) => acc.push(0xc2),
+        Json::Bool(true) => acc.push(0xc3),
+        Json::Number(v) => {
+            if let Some(v) = v.as_u64() {
+                match v {
+                    0..=0x7f => acc.push(v as u8),
+                    0x80..=0xff => acc.extend_from_slice(&[0xcc, v as u8]),
+                    0x100..=0xffff => {
+                        acc.push(0xcd);
+                        acc.extend_from_slice(&(v as u16).to_be_bytes());
+                    }
+                    0x10000..=0xffff_ffff => {
+                        acc.push(0xce);
+                        acc.extend_from_slice(&(v as u32).to_be_bytes());
+                    }
+                    _ => {
+                        acc.push(0xcf);
+                        acc.extend_from_slice(&v.to_be_bytes());
+                    }
+                }
+            } else if let Some(v) = v.as_i64() {
+                match v {
+                    -32..=-1 => acc.push(v as u8),
+                    -0x80..=-33 => acc.extend_from_slice(&[0xd0, v as u8]),
+                    -0x8000..=-0x81 => {
+                        acc.push(0xd1);
+                        acc.extend_from_slice(&(v as i16).to_be_bytes());
+                    }
+                    -0x8000_0000..=-0x8001 => {
+                        acc.push(0xd2);
+                        acc.extend_from_slice(&(v as i32).to_be_bytes());
+                    }
+                    _ => {
+                        acc.push(0xd3);
+                        acc.extend_from_slice(&v.to_be_bytes());
+                    }
+                }
+            } else {
+                acc.push(0xcb);
+                acc.extend_from_slice(&v.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Json::String(v) => string(v, acc),
+        Json::Array(v) => {
+            length(0x90, 15, 0xdc, v.len(), acc);
+            v.iter().for_each(|v| encode(v, acc));
+        }
+        Json::Object(v) => {
+            length(0x80, 15, 0xde, v.len(), acc);
+            for (k, v) in v {
+                string(k, acc);
+                encode(v, acc);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let v = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or(Error::Truncated)?;
+        self.pos += N;
+        Ok(v.try_into().unwrap())
+    }
+
+    fn text(&mut self, n: usize) -> Result<String, Error> {
+        let data = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::Truncated)?;
+        self.pos += n;
+        String::from_utf8(data.to_vec()).map_err(|_| Error::Invalid("invalid UTF-8"))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Invalid("nesting too deep"));
+        }
+        let float = |v: f64| {
+            serde_json::Number::from_f64(v)
+                .map(Json::Number)
+                .ok_or(Error::Invalid("non-finite float"))
+        };
+
+        let marker = self.take::<1>()?[0];
+        let (n, map) = match marker {
+            0x00..=0x7f => return Ok(Json::from(marker)),
+            0xe0..=0xff => return Ok(Json::from(marker as i8)),
+            0xa0..=0xbf => return self.text((marker & 0x1f) as usize).map(Json::String),
+            0x80..=0x8f => ((marker & 0x0f) as usize, true),
+            0x90..=0x9f => ((marker & 0x0f) as usize, false),
+            0xc0 => return Ok(Json::Null),
+            0xc2 => return Ok(Json::Bool(false)),
+            0xc3 => return Ok(Json::Bool(true)),
+            0xca => return float(f32::from_be_bytes(self.take()?) as f64),
+            0xcb => return float(f64::from_be_bytes(self.take()?)),
+            0xcc => return Ok(Json::from(self.take::<1>()?[0])),
+            0xcd => return Ok(Json::from(u16::from_be_bytes(self.take()?))),
+            0xce => return Ok(Json::from(u32::from_be_bytes(self.take()?))),
+            0xcf => return Ok(Json::from(u64::from_be_bytes(self.take()?))),
+            0xd0 => return Ok(Json::from(i8::from_be_bytes(self.take()?))),
+            0xd1 => return Ok(Json::from(i16::from_be_bytes(self.take()?))),
+            0xd2 => return Ok(Json::from(i32::from_be_bytes(self.take()?))),
+            0xd3 => return Ok(Json::from(i64::from_be_bytes(self.take()?))),
+            0xd9 => {
+                let n = self.take::<1>()?[0] as usize;
+                return self.text(n).map(Json::String);
+            }
+            0xda => {
+                let n = u16::from_be_bytes(self.take()?) as usize;
+                return self.text(n).map(Json::String);
+            }
+            0xdb => {
+                let n = u32::from_be_bytes(self.take()?) as usize;
+                return self.text(n).map(Json::String);
+            }
+            0xdc => (u16::from_be_bytes(self.take()?) as usize, false),
+            0xdd => (u32::from_be_bytes(self.take()?) as usize, false),
+            0xde => (u16::from_be_bytes(self.take()?) as usize, true),
+            0xdf => (u32::from_be_bytes(self.take()?) as usize, true),
+            0xc4..=0xc6 => return Err(Error::Invalid("binary data is not supported")),
+            _ => return Err(Error::Invalid("extension types are not supported")),
+        };
+
+        if map {
+            let mut acc = serde_json::Map::new();
+            for _ in 0..n {
+                let key = match self.value(depth + 1)? {
+                    Json::String(v) => v,
+                    _ => return Err(Error::Invalid("map keys must be strings")),
+                };
+                acc.insert(key, self.value(depth + 1)?);
+            }
+            Ok(Json::Object(acc))
+        } else {
+            // Do not trust the length for preallocation.
+            let mut acc = Vec::new();
+            for _ in 0..n {
+                acc.push(self.value(depth + 1)?);
+            }
+            Ok(Json::Array(acc))
+        }
+    }
+}
+
+/// Decode JSON Value
+///
+/// Decode a single MessagePack value into a JSON value. Trailing data is
+/// an error.
+pub fn decode(data: &[u8]) -> Result<Json, Error> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let value = decoder.value(0)?;
+    match decoder.pos == data.len() {
+        true => Ok(value),
+        false => Err(Error::Invalid("trailing data")),
+    }
+}
+
+/// Serialize to MessagePack
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: serde::Serialize + ?Sized,
+{
+    let mut acc = Vec::new();
+    encode(&serde_json::to_value(value)?, &mut acc);
+    Ok(acc)
+}
+
+/// Deserialize from MessagePack
+pub fn from_slice<T>(data: &[u8]) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_json::from_value(decode(data)?).map_err(Error::Json)
+}
+
+impl Manifest {
+    /// Serialize to MessagePack
+    pub fn to_msgpack(&self) -> Vec<u8> {
+        match self {
+            Manifest::V1(v) => to_vec(v),
+            Manifest::V2(v) => to_vec(v),
+        }
+        .expect("manifests always serialize")
+    }
+
+    /// Parse MessagePack
+    ///
+    /// Parse a manifest from its MessagePack form, detecting the format
+    /// version.
+    pub fn from_msgpack(data: &[u8]) -> Result<Self, crate::error::Error> {
+        let value = decode(data).map_err(|e| crate::error::Error::Parse {
+            version: None,
+            line: 0,
+            column: 0,
+            path: String::new(),
+            message: e.to_string(),
+        })?;
+        Manifest::from_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify MessagePack Encoding
+    #[test]
+    fn verify_msgpack() {
+        let long = "x".repeat(40);
+        let value = serde_json::json!({
+            "a": [0, 127, 128, 65536, -1, -33, -129, 1.5, false, null],
+            "b": long,
+        });
+        let mut acc = Vec::new();
+        encode(&value, &mut acc);
+        assert_eq!(&acc[..4], &[0x82, 0xa1, b'a', 0x9a]);
+        assert_eq!(&acc[4..8], &[0x00, 0x7f, 0xcc, 0x80]);
+        assert_eq!(decode(&acc).unwrap(), value);
+
+        // Larger representations decode to the same values.
+        let data = [
+            0x92, 0xd3, 0, 0, 0, 0, 0, 0, 0, 0x05, 0xca, 0x3f, 0xc0, 0, 0,
+        ];
+        assert_eq!(decode(&data).unwrap(), serde_json::json!([5, 1.5]));
+
+        assert!(matches!(decode(&[0xc4, 0x00]), Err(Error::Invalid(_))));
+        assert!(matches!(decode(&[0x92, 0x01]), Err(Error::Truncated)));
+        assert!(matches!(
+            decode(&[0x81, 0x01, 0x01]),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(decode(&[0x91; 200]), Err(Error::Invalid(_))));
+    }
+
+    // Verify Manifest Round-Trips
+    #[test]
+    fn verify_manifest() {
+        for text in [
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.rpm", "options": { "gpgkeys": ["x"] } }] } }"#,
+            r#"{ "version": "2", "pipelines": [{ "name": "os", "stages": [{ "type": "org.osbuild.noop" }] }], "sources": {} }"#,
+        ] {
+            let manifest = Manifest::parse(text).unwrap();
+            let msgpack = manifest.to_msgpack();
+            assert!(msgpack.len() < text.len());
+            assert_eq!(Manifest::from_msgpack(&msgpack).unwrap(), manifest);
+            assert_eq!(
+                decode(&msgpack).unwrap(),
+                serde_json::from_str::<Json>(&crate::manifest::ManifestFormat::serialize(
+                    &manifest
+                ))
+                .unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Multi-Architecture Generation
+//!
+//! This module generates the manifests of several architectures from a
+//! single template. Templates are manifests of either format version with
+//! architecture-specific parts marked:
+//!
+//!  * `${arch}` in any string is replaced by the architecture name.
+//!  * Stages with an `arches` member are kept only for the architectures
+//!    it lists. The member is removed.
+//!  * Stages of type `arch.bootloader` are replaced by the bootloader
+//!    stages of the architecture, given the options of the placeholder.
+//!
+//! Architectures are described by profiles, so custom architectures or
+//! bootloader setups can be plugged in. Profiles of the common
+//! architectures are provided by `Arch`.
+
+use crate::manifest::{Json, Manifest, Object};
+
+/// Architecture Placeholder
+pub const ARCH: &str = "${arch}";
+
+/// Bootloader Stage Placeholder
+pub const BOOTLOADER: &str = "arch.bootloader";
+
+/// Generator Error
+#[derive(Debug)]
+pub enum Error {
+    /// The template is not valid.
+    Template(String),
+    /// The profile of the architecture cannot set up the bootloader.
+    Bootloader(String, String),
+    /// The generated manifest of the architecture is not valid.
+    Manifest(String, crate::error::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Template(v) => write!(fmt, "invalid template: {}", v),
+            Error::Bootloader(arch, v) => write!(fmt, "bootloader of `{}`: {}", arch, v),
+            Error::Manifest(arch, v) => write!(fmt, "manifest of `{}`: {}", arch, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Architecture Profile
+pub trait Profile {
+    /// Name of the architecture, like `x86_64`.
+    fn arch(&self) -> &str;
+
+    /// Bootloader Stages
+    ///
+    /// Return the type and options of the stages setting up the bootloader,
+    /// given the options of the placeholder stage.
+    fn bootloader(&self, options: &Object<Json>) -> Result<Vec<(String, Object<Json>)>, String>;
+}
+
+/// Common Architectures
+///
+/// Profiles of the architectures supported by osbuild. The bootloader
+/// placeholder accepts the options of `org.osbuild.grub2`, plus:
+///
+///  * `vendor`: the vendor directory of the EFI system partition, like
+///    `fedora`, used on UEFI platforms.
+///  * `bootupd`: the options of `org.osbuild.bootupd`, which is used
+///    instead of GRUB if given.
+///
+/// GRUB is set up for BIOS and UEFI on `x86_64`, UEFI on `aarch64`, and
+/// Open Firmware on `ppc64le`. `s390x` uses zipl, and does not support
+/// bootupd.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+    S390x,
+    Ppc64le,
+}
+
+impl Arch {
+    /// All Architectures
+    pub const ALL: [Arch; 4] = [Arch::X86_64, Arch::Aarch64, Arch::S390x, Arch::Ppc64le];
+}
+
+impl Profile for Arch {
+    fn arch(&self) -> &str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+            Arch::S390x => "s390x",
+            Arch::Ppc64le => "ppc64le",
+        }
+    }
+
+    fn bootloader(&self, options: &Object<Json>) -> Result<Vec<(String, Object<Json>)>, String> {
+        if let Some(bootupd) = options.get("bootupd") {
+            if *self == Arch::S390x {
+                return Err("bootupd is not supported".to_owned());
+            }
+            let bootupd = bootupd
+                .as_object()
+                .ok_or("bootupd options must be an object")?;
+            let bootupd = bootupd.clone().into_iter().collect();
+            return Ok(vec![("org.osbuild.bootupd".to_owned(), bootupd)]);
+        }
+        if *self == Arch::S390x {
+            return Ok(vec![("org.osbuild.zipl".to_owned(), Object::new())]);
+        }
+
+        let mut grub2: Object<Json> = options.clone();
+        let vendor = grub2.remove("vendor");
+        let uefi = || match &vendor {
+            Some(v) => Ok(serde_json::json!({ "vendor": v })),
+            None => Err("vendor is required for UEFI".to_owned()),
+        };
+        match self {
+            Arch::X86_64 => {
+                grub2.insert("legacy".to_owned(), Json::from("i386-pc"));
+                grub2.insert("uefi".to_owned(), uefi()?);
+            }
+            Arch::Aarch64 => {
+                grub2.insert("uefi".to_owned(), uefi()?);
+            }
+            Arch::Ppc64le => {
+                grub2.insert("legacy".to_owned(), Json::from("powerpc-ieee1275"));
+            }
+            Arch::S390x => unreachable!(),
+        }
+        Ok(vec![("org.osbuild.grub2".to_owned(), grub2)])
+    }
+}
+
+// Replace the architecture placeholder in all strings of the value.
+fn substitute(value: &mut Json, arch: &str) {
+    match value {
+        Json::String(v) if v.contains(ARCH) => *v = v.replace(ARCH, arch),
+        Json::Array(v) => v.iter_mut().for_each(|v| substitute(v, arch)),
+        Json::Object(v) => v.values_mut().for_each(|v| substitute(v, arch)),
+        _ => {}
+    }
+}
+
+// Filter and expand the stages of all pipelines in the value.
+fn expand(value: &mut Json, profile: &dyn Profile) -> Result<(), Error> {
+    match value {
+        Json::Array(v) => v.iter_mut().try_for_each(|v| expand(v, profile)),
+        Json::Object(object) => {
+            if let Some(Json::Array(stages)) = object.get_mut("stages") {
+                *stages = expand_stages(std::mem::take(stages), profile)?;
+            }
+            object.values_mut().try_for_each(|v| expand(v, profile))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn expand_stages(stages: Vec<Json>, profile: &dyn Profile) -> Result<Vec<Json>, Error> {
+    let mut acc = Vec::new();
+    for stage in stages {
+        let Json::Object(mut stage) = stage else {
+            acc.push(stage);
+            continue;
+        };
+
+        if let Some(arches) = stage.remove("arches") {
+            let arches = arches
+                .as_array()
+                .filter(|v| v.iter().all(Json::is_string))
+                .ok_or_else(|| Error::Template("`arches` must be an array of strings".into()))?;
+            if !arches.iter().any(|v| v == profile.arch()) {
+                continue;
+            }
+        }
+
+        // Manifests of version 1 name the stage type `name`, manifests of
+        // version 2 use `type`.
+        let key = if stage.contains_key("type") {
+            "type"
+        } else {
+            "name"
+        };
+        if stage.get(key).and_then(Json::as_str) != Some(BOOTLOADER) {
+            acc.push(Json::Object(stage));
+            continue;
+        }
+
+        let options: Object<Json> = match stage.get("options") {
+            None => Object::new(),
+            Some(Json::Object(v)) => v.clone().into_iter().collect(),
+            Some(_) => {
+                return Err(Error::Template(
+                    "bootloader options must be an object".into(),
+                ))
+            }
+        };
+        let stages = profile
+            .bootloader(&options)
+            .map_err(|e| Error::Bootloader(profile.arch().to_owned(), e))?;
+        for (name, options) in stages {
+            let mut stage = stage.clone();
+            stage.insert(key.to_owned(), Json::String(name));
+            stage.insert(
+                "options".to_owned(),
+                Json::Object(options.into_iter().collect()),
+            );
+            acc.push(Json::Object(stage));
+        }
+    }
+    Ok(acc)
+}
+
+/// Generate Manifest
+///
+/// Generate the manifest of the architecture described by the profile from
+/// the template.
+pub fn generate(template: &Json, profile: &dyn Profile) -> Result<Manifest, Error> {
+    if !template.is_object() {
+        return Err(Error::Template("template must be an object".into()));
+    }
+    let mut value = template.clone();
+    substitute(&mut value, profile.arch());
+    expand(&mut value, profile)?;
+    Manifest::parse(&value.to_string()).map_err(|e| Error::Manifest(profile.arch().to_owned(), e))
+}
+
+/// Generate Manifests
+///
+/// Generate the manifests of all architectures described by the profiles,
+/// returned together with the architecture names.
+pub fn generate_all(
+    template: &Json,
+    profiles: &[&dyn Profile],
+) -> Result<Vec<(String, Manifest)>, Error> {
+    profiles
+        .iter()
+        .map(|v| Ok((v.arch().to_owned(), generate(template, *v)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestFormat;
+
+    // Verify Multi-Architecture Generation
+    #[test]
+    fn verify_generate() {
+        let template = serde_json::json!({
+            "version": "2",
+            "pipelines": [{
+                "name": "os",
+                "stages": [
+                    { "type": "org.osbuild.rpm", "options": { "gpgkeys": ["${arch}"] } },
+                    { "type": "org.osbuild.grub2.inst", "arches": ["x86_64"] },
+                    {
+                        "type": BOOTLOADER,
+                        "options": { "rootfs": { "label": "root" }, "vendor": "fedora" },
+                    },
+                ],
+            }],
+        });
+
+        let profiles: Vec<&dyn Profile> = Arch::ALL.iter().map(|v| v as &dyn Profile).collect();
+        let manifests = generate_all(&template, &profiles).unwrap();
+        let stages: Vec<(String, Vec<(String, Json)>)> = manifests
+            .iter()
+            .map(|(arch, manifest)| {
+                let stages = manifest.pipelines()[0]
+                    .stages
+                    .iter()
+                    .map(|v| {
+                        let options = v.options.clone().into_iter().collect();
+                        (v.name.to_owned(), Json::Object(options))
+                    })
+                    .collect();
+                (arch.clone(), stages)
+            })
+            .collect();
+
+        let rpm = |arch: &str| {
+            (
+                "org.osbuild.rpm".to_owned(),
+                serde_json::json!({ "gpgkeys": [arch] }),
+            )
+        };
+        assert_eq! {
+            stages,
+            vec![
+                ("x86_64".to_owned(), vec![
+                    rpm("x86_64"),
+                    ("org.osbuild.grub2.inst".to_owned(), serde_json::json!({})),
+                    ("org.osbuild.grub2".to_owned(), serde_json::json!({
+                        "legacy": "i386-pc",
+                        "rootfs": { "label": "root" },
+                        "uefi": { "vendor": "fedora" },
+                    })),
+                ]),
+                ("aarch64".to_owned(), vec![
+                    rpm("aarch64"),
+                    ("org.osbuild.grub2".to_owned(), serde_json::json!({
+                        "rootfs": { "label": "root" },
+                        "uefi": { "vendor": "fedora" },
+                    })),
+                ]),
+                ("s390x".to_owned(), vec![
+                    rpm("s390x"),
+                    ("org.osbuild.zipl".to_owned(), serde_json::json!({})),
+                ]),
+                ("ppc64le".to_owned(), vec![
+                    rpm("ppc64le"),
+                    ("org.osbuild.grub2".to_owned(), serde_json::json!({
+                        "legacy": "powerpc-ieee1275",
+                        "rootfs": { "label": "root" },
+                    })),
+                ]),
+            ],
+        }
+
+        let mut template = template;
+        template["pipelines"][0]["stages"][2]["options"] =
+            serde_json::json!({ "bootupd": { "static-configs": true } });
+        assert!(generate(&template, &Arch::X86_64).is_ok());
+        assert!(matches!(
+            generate(&template, &Arch::S390x),
+            Err(Error::Bootloader(..))
+        ));
+        template["pipelines"][0]["stages"][1]["arches"] = serde_json::json!("x86_64");
+        assert!(matches!(
+            generate(&template, &Arch::X86_64),
+            Err(Error::Template(..))
+        ));
+    }
+}
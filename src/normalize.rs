@@ -0,0 +1,368 @@
+//! Manifest Normalization
+//!
+//! This module produces a canonical structural form of manifests. Manifest
+//! generators often differ in irrelevant details between runs, like the
+//! order of object members, whether default values are spelled out, or how
+//! sizes are written. Normalizing both sides before comparing them makes
+//! sure only real changes show up in diffs.
+//!
+//! The canonical form has all object members sorted, omits members that
+//! carry their default value, and unifies equivalent option spellings.
+
+use crate::estimate;
+use crate::manifest::{Json, Manifest, Manifest1, Manifest2, Object, Pipeline1};
+use crate::signature;
+
+/// Canonical Spelling
+///
+/// This describes the canonical spelling of an option that accepts
+/// multiple equivalent forms.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Spelling {
+    /// Sizes are written as integers in bytes.
+    Integer,
+    /// Sizes are written as strings of decimal bytes, without unit.
+    String,
+}
+
+/// Size Option Table
+///
+/// This lists all known size options as tuples of stage or assembler name,
+/// option name, and canonical spelling.
+pub const SIZES: &[(&str, &str, Spelling)] = &[
+    ("org.osbuild.qemu", "size", Spelling::Integer),
+    ("org.osbuild.rawfs", "size", Spelling::Integer),
+    ("org.osbuild.truncate", "size", Spelling::String),
+];
+
+fn unify_options(name: &str, options: &mut Object<Json>) {
+    for (stage, key, spelling) in SIZES {
+        if name != *stage {
+            continue;
+        }
+
+        let value = match options.get_mut(*key) {
+            None => continue,
+            Some(v) => v,
+        };
+
+        if let Some(size) = estimate::parse_size(value) {
+            *value = match spelling {
+                Spelling::Integer => Json::from(size),
+                Spelling::String => Json::from(size.to_string()),
+            };
+        }
+    }
+}
+
+fn unify_pipeline(pipeline: &mut Pipeline1) {
+    if let Some(ref mut build) = pipeline.build {
+        unify_pipeline(&mut build.pipeline);
+    }
+    if let Some(ref mut assembler) = pipeline.assembler {
+        unify_options(&assembler.name, &mut assembler.options);
+    }
+    for stage in pipeline.stages.iter_mut() {
+        unify_options(&stage.name, &mut stage.options);
+    }
+}
+
+/// Unify Option Spellings
+///
+/// Rewrite all options of the manifest that have multiple equivalent
+/// spellings to their canonical spelling. This modifies the manifest in
+/// place and is part of `normalize()`.
+pub fn unify(manifest: &mut Manifest1) {
+    unify_pipeline(&mut manifest.pipeline);
+}
+
+// Remove the member `key` from `value` if it is null or an empty container.
+fn strip(value: &mut Json, key: &str) {
+    if let Some(map) = value.as_object_mut() {
+        let empty = match map.get(key) {
+            Some(Json::Null) => true,
+            Some(Json::Array(v)) => v.is_empty(),
+            Some(Json::Object(v)) => v.is_empty(),
+            _ => false,
+        };
+        if empty {
+            map.remove(key);
+        }
+    }
+}
+
+fn strip_pipeline(value: &mut Json) {
+    if let Some(build) = value.get_mut("build") {
+        if let Some(pipeline) = build.get_mut("pipeline") {
+            strip_pipeline(pipeline);
+        }
+    }
+    if let Some(assembler) = value.get_mut("assembler") {
+        strip(assembler, "options");
+    }
+    if let Some(Json::Array(stages)) = value.get_mut("stages") {
+        for stage in stages.iter_mut() {
+            strip(stage, "options");
+        }
+    }
+
+    strip(value, "assembler");
+    strip(value, "build");
+    strip(value, "stages");
+}
+
+/// Normalize Manifest
+///
+/// Produce the canonical form of the manifest as JSON value. The result
+/// deserializes into a manifest equal to the input, except for option
+/// spellings unified by `unify()`.
+pub fn normalize(manifest: &Manifest1) -> Json {
+    let mut manifest = manifest.clone();
+
+    unify(&mut manifest);
+
+    let mut json = serde_json::to_value(&manifest).expect("manifests always serialize");
+    json.sort_all_objects();
+    if let Some(pipeline) = json.get_mut("pipeline") {
+        strip_pipeline(pipeline);
+    }
+    strip(&mut json, "pipeline");
+    strip(&mut json, "sources");
+
+    json
+}
+
+/// Unify Option Spellings of Version-2 Manifests
+///
+/// Rewrite the options of the stages of the manifest like `unify()`.
+pub fn unify2(manifest: &mut Manifest2) {
+    for stage in manifest
+        .pipelines
+        .iter_mut()
+        .flat_map(|v| v.stages.iter_mut())
+    {
+        unify_options(&stage.r#type, &mut stage.options);
+    }
+}
+
+/// Normalize Version-2 Manifest
+///
+/// Produce the canonical form of the manifest as JSON value, like
+/// `normalize()`. Members carrying their default value are never
+/// serialized for version-2 manifests, so only option spellings are
+/// unified and members sorted.
+pub fn normalize2(manifest: &Manifest2) -> Json {
+    let mut manifest = manifest.clone();
+
+    unify2(&mut manifest);
+
+    let mut json = serde_json::to_value(&manifest).expect("manifests always serialize");
+    json.sort_all_objects();
+    json
+}
+
+/// Normalize Manifest of Either Version
+///
+/// Produce the canonical form of the manifest as JSON value, in its own
+/// version, via `normalize()` or `normalize2()`.
+pub fn normalize_any(manifest: &Manifest) -> Json {
+    match manifest {
+        Manifest::V1(v) => normalize(v),
+        Manifest::V2(v) => normalize2(v),
+    }
+}
+
+/// Canonical Encoding
+///
+/// Return the canonical JSON encoding of the normalized manifest. This is
+/// what `Manifest::semantic_hash()` hashes, and what identifies manifests
+/// in the manifest store.
+pub fn canonical(manifest: &Manifest) -> Vec<u8> {
+    signature::canonical(&normalize_any(manifest))
+}
+
+impl Manifest1 {
+    /// Semantic Equality
+    ///
+    /// Compare the normalized forms of two manifests. Unlike `==`, this
+    /// ignores member order, members that carry their default value, and
+    /// the spelling of options with equivalent forms.
+    pub fn semantic_eq(&self, other: &Manifest1) -> bool {
+        normalize(self) == normalize(other)
+    }
+
+    /// Semantic Hash
+    ///
+    /// Feed the canonical JSON encoding of the normalized manifest into
+    /// the hasher. Manifests that are equal under `semantic_eq()` hash
+    /// equally, so this can be used to deduplicate manifests.
+    pub fn semantic_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write(&signature::canonical(&normalize(self)));
+    }
+}
+
+impl Manifest2 {
+    /// Semantic Equality
+    ///
+    /// Compare the normalized forms of two manifests, like
+    /// `Manifest1::semantic_eq()`.
+    pub fn semantic_eq(&self, other: &Manifest2) -> bool {
+        normalize2(self) == normalize2(other)
+    }
+
+    /// Semantic Hash
+    ///
+    /// Feed the canonical JSON encoding of the normalized manifest into
+    /// the hasher, like `Manifest1::semantic_hash()`.
+    pub fn semantic_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write(&signature::canonical(&normalize2(self)));
+    }
+}
+
+impl Manifest {
+    /// Semantic Equality
+    ///
+    /// Compare two manifests like `Manifest1::semantic_eq()`. Manifests of
+    /// different versions are never equal.
+    pub fn semantic_eq(&self, other: &Manifest) -> bool {
+        match (self, other) {
+            (Manifest::V1(a), Manifest::V1(b)) => a.semantic_eq(b),
+            (Manifest::V2(a), Manifest::V2(b)) => a.semantic_eq(b),
+            _ => false,
+        }
+    }
+
+    /// Semantic Hash
+    ///
+    /// Feed the canonical encoding of the manifest into the hasher, like
+    /// `Manifest1::semantic_hash()`.
+    pub fn semantic_hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write(&canonical(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Normalization of Version-2 Manifests
+    #[test]
+    fn verify_normalize2() {
+        let manifest: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [{
+                    "name": "image",
+                    "stages": [{ "type": "org.osbuild.truncate", "options": { "size": 1024, "filename": "a" } }]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            normalize2(&manifest).to_string(),
+            r#"{"pipelines":[{"name":"image","stages":[{"options":{"filename":"a","size":"1024"},"type":"org.osbuild.truncate"}]}],"version":"2"}"#,
+        }
+    }
+
+    // Verify Normalization
+    #[test]
+    fn verify_normalize() {
+        // Empty manifests normalize to empty objects.
+        let manifest: Manifest1 = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(normalize(&manifest), serde_json::json!({}));
+
+        // Defaults are dropped, members sorted, and sizes unified.
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [{ "name": "org.osbuild.rpm", "options": {} }]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        { "name": "org.osbuild.truncate", "options": { "size": "1 KiB" } }
+                    ],
+                    "assembler": {
+                        "name": "org.osbuild.qemu",
+                        "options": { "size": "1 MiB", "format": "qcow2" }
+                    }
+                },
+                "sources": {
+                    "org.osbuild.files": { "urls": { "sha256:b": "b", "sha256:a": "a" } }
+                }
+            }"#,
+        )
+        .unwrap();
+        let json = normalize(&manifest);
+
+        assert_eq! {
+            json,
+            serde_json::json!({
+                "pipeline": {
+                    "assembler": {
+                        "name": "org.osbuild.qemu",
+                        "options": { "format": "qcow2", "size": 1048576 }
+                    },
+                    "build": {
+                        "pipeline": {
+                            "stages": [{ "name": "org.osbuild.rpm" }]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        { "name": "org.osbuild.truncate", "options": { "size": "1024" } }
+                    ]
+                },
+                "sources": {
+                    "org.osbuild.files": { "urls": { "sha256:a": "a", "sha256:b": "b" } }
+                }
+            }),
+        }
+        assert_eq! {
+            serde_json::to_string(&json["sources"]).unwrap(),
+            r#"{"org.osbuild.files":{"urls":{"sha256:a":"a","sha256:b":"b"}}}"#,
+        }
+
+        // Normalization is idempotent.
+        let again: Manifest1 = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(normalize(&again), json);
+    }
+
+    // Verify Semantic Equality
+    #[test]
+    fn verify_semantic_eq() {
+        let hash = |v: &Manifest1| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            v.semantic_hash(&mut hasher);
+            std::hash::Hasher::finish(&hasher)
+        };
+
+        let a: Manifest1 = serde_json::from_str(
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.truncate", "options": { "size": "1 KiB", "filename": "a" } }] } }"#,
+        )
+        .unwrap();
+        let b: Manifest1 = serde_json::from_str(
+            r#"{ "pipeline": { "assembler": null, "stages": [{ "options": { "filename": "a", "size": 1024 }, "name": "org.osbuild.truncate" }] }, "sources": {} }"#,
+        )
+        .unwrap();
+        assert_ne!(a, b);
+        assert!(a.semantic_eq(&b));
+        assert_eq!(hash(&a), hash(&b));
+
+        let c = Manifest1::default();
+        assert!(!a.semantic_eq(&c));
+        assert_ne!(hash(&a), hash(&c));
+
+        // Version-2 manifests are compared in their own version, and never
+        // equal manifests of the other version.
+        let a2: Manifest = r#"{ "version": "2", "pipelines": [{ "name": "a", "stages": [{ "type": "org.osbuild.truncate", "options": { "size": 1024 } }] }] }"#.parse().unwrap();
+        let b2: Manifest = r#"{ "pipelines": [{ "stages": [{ "options": { "size": "1 KiB" }, "type": "org.osbuild.truncate" }], "name": "a" }], "version": "2" }"#.parse().unwrap();
+        assert!(a2.semantic_eq(&b2));
+        assert_eq!(canonical(&a2), canonical(&b2));
+        assert!(!a2.semantic_eq(&Manifest::V1(a.clone())));
+        assert!(Manifest::V1(a).semantic_eq(&Manifest::V1(b)));
+    }
+}
@@ -0,0 +1,354 @@
+//! OCI Artifact Packaging
+//!
+//! This module packages exported build artifacts, like disk images, ISOs,
+//! or tarballs, as OCI artifacts. Each artifact is an OCI image manifest
+//! with an empty config, one layer per file, and an artifact type
+//! identifying it as osbuild output. Supplementary documents, like SBOMs
+//! or provenance, are attached as separate artifacts referring to the
+//! packaged one via their `subject`, so registries list them as its
+//! referrers.
+//!
+//! Artifacts are written to a directory in the OCI image layout, which
+//! tools like `skopeo` or `oras` can push to any registry.
+
+use crate::hash;
+use crate::manifest::Object;
+
+/// Image Manifest Media Type
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Image Index Media Type
+pub const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Empty Config Media Type
+pub const MEDIA_TYPE_EMPTY: &str = "application/vnd.oci.empty.v1+json";
+
+/// Build Artifact Type
+pub const ARTIFACT_TYPE: &str = "application/vnd.osbuild.artifact.v1";
+
+/// SPDX SBOM Artifact Type
+pub const ARTIFACT_TYPE_SPDX: &str = "application/spdx+json";
+
+/// DSSE Envelope Artifact Type
+pub const ARTIFACT_TYPE_DSSE: &str = "application/vnd.dsse.envelope.v1+json";
+
+/// Title Annotation
+pub const ANNOTATION_TITLE: &str = "org.opencontainers.image.title";
+
+/// Media Type Table
+///
+/// This lists the media types of known artifact formats, as tuples of
+/// file-name suffix and media type. Files of other formats are packaged
+/// as `application/octet-stream`.
+pub const MEDIA_TYPES: &[(&str, &str)] = &[
+    (".qcow2", "application/x-qemu-disk"),
+    (".iso", "application/vnd.efi.iso"),
+    (".img", "application/vnd.efi.img"),
+    (".raw", "application/vnd.efi.img"),
+    (".vmdk", "application/x-vmdk"),
+    (".vhd", "application/x-vhd"),
+    (".tar", "application/x-tar"),
+    (".tar.gz", "application/gzip"),
+    (".tar.xz", "application/x-xz"),
+    (".tar.zst", "application/zstd"),
+];
+
+/// Content Descriptor
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    /// Embedded content, base64 encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub annotations: Object<String>,
+}
+
+impl Descriptor {
+    // Describe a blob of the given media type.
+    fn of(media_type: &str, data: &[u8]) -> Self {
+        Self {
+            media_type: media_type.to_owned(),
+            digest: format!("sha256:{}", hash::to_hex(&hash::Sha256::digest(data))),
+            size: data.len() as u64,
+            ..Default::default()
+        }
+    }
+}
+
+/// Image Manifest
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub annotations: Object<String>,
+}
+
+/// Image Index
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub manifests: Vec<Descriptor>,
+}
+
+/// Media Type of File
+///
+/// Look up the media type of an artifact by its file name.
+pub fn media_type(name: &str) -> &'static str {
+    MEDIA_TYPES
+        .iter()
+        .filter(|(suffix, _)| name.ends_with(suffix))
+        .max_by_key(|(suffix, _)| suffix.len())
+        .map_or("application/octet-stream", |(_, v)| v)
+}
+
+/// OCI Image Layout
+///
+/// A directory in the OCI image layout. All artifacts added to the layout
+/// are listed in its index.
+#[derive(Debug)]
+pub struct Layout {
+    path: std::path::PathBuf,
+    index: Index,
+}
+
+impl Layout {
+    /// Create Layout
+    ///
+    /// Create a new layout in the given directory, or open the existing
+    /// layout there.
+    pub fn new(path: &std::path::Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(path.join("blobs/sha256"))?;
+        std::fs::write(
+            path.join("oci-layout"),
+            br#"{"imageLayoutVersion":"1.0.0"}"#,
+        )?;
+
+        let index = match std::fs::read(path.join("index.json")) {
+            Ok(v) => serde_json::from_slice(&v)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index {
+                schema_version: 2,
+                media_type: MEDIA_TYPE_INDEX.to_owned(),
+                manifests: Vec::new(),
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            index,
+        })
+    }
+
+    /// Index of the Layout
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    fn blob_path(&self, digest: &str) -> std::path::PathBuf {
+        self.path
+            .join("blobs/sha256")
+            .join(digest.trim_start_matches("sha256:"))
+    }
+
+    fn write_blob(&self, media_type: &str, data: &[u8]) -> std::io::Result<Descriptor> {
+        let descriptor = Descriptor::of(media_type, data);
+        std::fs::write(self.blob_path(&descriptor.digest), data)?;
+        Ok(descriptor)
+    }
+
+    // Copy a file into the layout, hashing it on the way.
+    fn copy_blob(&self, path: &std::path::Path) -> std::io::Result<Descriptor> {
+        use std::io::{Read, Write};
+
+        let name = path
+            .file_name()
+            .map(|v| v.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let partial = self.path.join("blobs/sha256/.partial");
+        let mut input = std::fs::File::open(path)?;
+        let mut output = std::fs::File::create(&partial)?;
+        let mut hasher = hash::Sha256::new();
+        let mut buffer = vec![0; 1 << 16];
+        let mut size = 0;
+        loop {
+            let n = input.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            output.write_all(&buffer[..n])?;
+            size += n as u64;
+        }
+
+        let digest = format!("sha256:{}", hash::to_hex(&hasher.finalize()));
+        std::fs::rename(&partial, self.blob_path(&digest))?;
+        Ok(Descriptor {
+            media_type: media_type(&name).to_owned(),
+            digest,
+            size,
+            annotations: [(ANNOTATION_TITLE.to_owned(), name)].into(),
+            ..Default::default()
+        })
+    }
+
+    // Write a manifest and add it to the index.
+    fn add_manifest(&mut self, manifest: &Manifest) -> std::io::Result<Descriptor> {
+        let data = serde_json::to_vec(manifest)?;
+        let mut descriptor = self.write_blob(MEDIA_TYPE_MANIFEST, &data)?;
+        descriptor.artifact_type = manifest.artifact_type.clone();
+        descriptor.annotations = manifest.annotations.clone();
+
+        self.index.manifests.push(descriptor.clone());
+        std::fs::write(
+            self.path.join("index.json"),
+            serde_json::to_vec(&self.index)?,
+        )?;
+        Ok(descriptor)
+    }
+
+    fn empty_config(&self) -> std::io::Result<Descriptor> {
+        let mut config = self.write_blob(MEDIA_TYPE_EMPTY, b"{}")?;
+        config.data = Some(hash::to_base64(b"{}"));
+        Ok(config)
+    }
+
+    /// Package Artifact
+    ///
+    /// Package the given files as a single artifact, with one layer per
+    /// file, titled by the file name. The annotations are added to the
+    /// manifest, and should include a `org.opencontainers.image.created`
+    /// timestamp. Returns the descriptor of the artifact manifest.
+    pub fn add_artifact(
+        &mut self,
+        files: &[&std::path::Path],
+        annotations: Object<String>,
+    ) -> std::io::Result<Descriptor> {
+        let layers = files
+            .iter()
+            .map(|v| self.copy_blob(v))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST.to_owned(),
+            artifact_type: Some(ARTIFACT_TYPE.to_owned()),
+            config: self.empty_config()?,
+            layers,
+            subject: None,
+            annotations,
+        };
+        self.add_manifest(&manifest)
+    }
+
+    /// Attach Document
+    ///
+    /// Attach a document, like an SBOM or a provenance envelope, to the
+    /// artifact described by `subject`. The document is stored as an
+    /// artifact of the given type, referring to the subject. Returns the
+    /// descriptor of the referrer manifest.
+    pub fn attach(
+        &mut self,
+        subject: &Descriptor,
+        artifact_type: &str,
+        data: &[u8],
+    ) -> std::io::Result<Descriptor> {
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST.to_owned(),
+            artifact_type: Some(artifact_type.to_owned()),
+            config: self.empty_config()?,
+            layers: vec![self.write_blob(artifact_type, data)?],
+            subject: Some(Descriptor {
+                media_type: subject.media_type.clone(),
+                digest: subject.digest.clone(),
+                size: subject.size,
+                ..Default::default()
+            }),
+            annotations: Object::new(),
+        };
+        self.add_manifest(&manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Media Type Lookup
+    #[test]
+    fn verify_media_type() {
+        assert_eq!(media_type("disk.qcow2"), "application/x-qemu-disk");
+        assert_eq!(media_type("root.tar"), "application/x-tar");
+        assert_eq!(media_type("root.tar.gz"), "application/gzip");
+        assert_eq!(media_type("README"), "application/octet-stream");
+    }
+
+    // Verify Artifact Packaging
+    #[test]
+    fn verify_layout() {
+        let dir = std::env::temp_dir().join(format!("r-osbuild-oci-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("disk.qcow2");
+        std::fs::write(&image, b"qcow").unwrap();
+
+        let mut layout = Layout::new(&dir.join("layout")).unwrap();
+        let artifact = layout
+            .add_artifact(
+                &[&image],
+                [(
+                    "org.opencontainers.image.created".to_owned(),
+                    "2023-11-01T00:00:00Z".to_owned(),
+                )]
+                .into(),
+            )
+            .unwrap();
+        let sbom = layout
+            .attach(
+                &artifact,
+                ARTIFACT_TYPE_SPDX,
+                br#"{"spdxVersion":"SPDX-2.3"}"#,
+            )
+            .unwrap();
+        assert_eq!(artifact.artifact_type.as_deref(), Some(ARTIFACT_TYPE));
+
+        let blob = |d: &Descriptor| {
+            std::fs::read(dir.join("layout/blobs/sha256").join(&d.digest[7..])).unwrap()
+        };
+        let manifest: Manifest = serde_json::from_slice(&blob(&artifact)).unwrap();
+        assert_eq!(manifest.config.data.as_deref(), Some("e30="));
+        assert_eq!(manifest.layers[0].media_type, "application/x-qemu-disk");
+        assert_eq!(
+            manifest.layers[0].annotations[ANNOTATION_TITLE],
+            "disk.qcow2"
+        );
+        assert_eq!(blob(&manifest.layers[0]), b"qcow");
+
+        let referrer: Manifest = serde_json::from_slice(&blob(&sbom)).unwrap();
+        assert_eq!(referrer.subject.unwrap().digest, artifact.digest);
+
+        // Reopening the layout keeps its index.
+        let layout = Layout::new(&dir.join("layout")).unwrap();
+        assert_eq!(layout.index().manifests, vec![artifact, sbom]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Typed Stage Options
+//!
+//! This module provides typed representations of the options of common
+//! stages. They serialize into the generic `options` object of a stage, so
+//! manifests can be built without assembling JSON by hand. Every type
+//! names the stage it belongs to via `StageOptions::NAME`, which matches
+//! the corresponding entry of the stage catalog.
+
+use crate::error::Error;
+use crate::lint::pointer;
+use crate::manifest::{self, Json, Pipeline1, Stage1};
+
+/// Typed Stage Options
+///
+/// Implemented by option types of stages. Implementations must serialize
+/// to JSON objects.
+pub trait StageOptions: serde::Serialize {
+    /// Name of the stage the options belong to.
+    const NAME: &'static str;
+}
+
+/// Options of `org.osbuild.chrony`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ChronyOptions {
+    pub timeservers: Vec<String>,
+}
+
+impl StageOptions for ChronyOptions {
+    const NAME: &'static str = "org.osbuild.chrony";
+}
+
+/// Options of `org.osbuild.hostname`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct HostnameOptions {
+    pub hostname: String,
+}
+
+impl StageOptions for HostnameOptions {
+    const NAME: &'static str = "org.osbuild.hostname";
+}
+
+/// Options of `org.osbuild.keymap`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct KeymapOptions {
+    pub keymap: String,
+}
+
+impl StageOptions for KeymapOptions {
+    const NAME: &'static str = "org.osbuild.keymap";
+}
+
+/// Options of `org.osbuild.locale`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct LocaleOptions {
+    pub language: String,
+}
+
+impl StageOptions for LocaleOptions {
+    const NAME: &'static str = "org.osbuild.locale";
+}
+
+/// Options of `org.osbuild.rpm`
+///
+/// Packages are given by the checksums of their source items.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RpmOptions {
+    pub packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gpgkeys: Vec<String>,
+}
+
+impl StageOptions for RpmOptions {
+    const NAME: &'static str = "org.osbuild.rpm";
+}
+
+/// Options of `org.osbuild.timezone`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TimezoneOptions {
+    pub zone: String,
+}
+
+impl StageOptions for TimezoneOptions {
+    const NAME: &'static str = "org.osbuild.timezone";
+}
+
+impl Stage1 {
+    /// Create Stage from Typed Options
+    ///
+    /// Create a stage of the type the options belong to, with the options
+    /// serialized into its `options` object.
+    pub fn typed<T: StageOptions>(options: &T) -> Self {
+        let options = match serde_json::to_value(options) {
+            Ok(serde_json::Value::Object(v)) => manifest::object_from_map(v),
+            _ => panic!("options of `{}` must serialize to an object", T::NAME),
+        };
+        let mut stage = Stage1::default();
+        stage.name = T::NAME.into();
+        stage.options = options;
+        stage
+    }
+
+    /// Get Typed Option
+    ///
+    /// Deserialize the option with the given key into the given type.
+    /// Returns `None` if the stage lacks the option.
+    pub fn option<'a, T: serde::Deserialize<'a>>(&'a self, key: &str) -> Result<Option<T>, Error> {
+        self.option_path(&key.replace('/', "~1"))
+    }
+
+    /// Get Typed Option by Path
+    ///
+    /// Deserialize the value at the given `/`-separated path below the
+    /// options into the given type, like `option()`. Path segments index
+    /// objects by key and arrays by position, and are escaped like in JSON
+    /// pointers. Returns `None` if there is no value at the path.
+    pub fn option_path<'a, T>(&'a self, path: &str) -> Result<Option<T>, Error>
+    where
+        T: serde::Deserialize<'a>,
+    {
+        let mut segments = path
+            .split('/')
+            .map(|v| v.replace("~1", "/").replace("~0", "~"));
+        let first = segments.next().unwrap_or_default();
+        let mut value = match self.options.get(&first) {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+        let mut at = pointer("/options", &first);
+        for segment in segments {
+            let next = match value {
+                Json::Object(v) => v.get(&segment),
+                Json::Array(v) => segment.parse().ok().and_then(|i: usize| v.get(i)),
+                _ => None,
+            };
+            value = match next {
+                None => return Ok(None),
+                Some(v) => v,
+            };
+            at = pointer(&at, &segment);
+        }
+        T::deserialize(value).map(Some).map_err(|e| Error::Parse {
+            version: Some(1),
+            line: 0,
+            column: 0,
+            path: at,
+            message: e.to_string(),
+        })
+    }
+}
+
+impl Pipeline1 {
+    /// Append Stage
+    ///
+    /// Append the stage to the pipeline and return the pipeline, so stages
+    /// can be chained.
+    pub fn stage(mut self, stage: Stage1) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Append Typed Stage
+    ///
+    /// Append a stage created from typed options, like `stage()`.
+    pub fn stage_typed<T: StageOptions>(self, options: &T) -> Self {
+        self.stage(Stage1::typed(options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog;
+
+    // Verify Typed Options
+    #[test]
+    fn verify_options() {
+        let pipeline = Pipeline1::default()
+            .stage_typed(&RpmOptions {
+                packages: vec!["sha256:a".to_owned()],
+                ..Default::default()
+            })
+            .stage_typed(&LocaleOptions {
+                language: "en_US".to_owned(),
+            });
+
+        assert_eq! {
+            serde_json::to_value(&pipeline.stages).unwrap(),
+            serde_json::json!([
+                { "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a"] } },
+                { "name": "org.osbuild.locale", "options": { "language": "en_US" } },
+            ]),
+        }
+
+        let stage = &pipeline.stages[0];
+        assert_eq!(
+            stage.option::<Vec<&str>>("packages").unwrap(),
+            Some(vec!["sha256:a"])
+        );
+        assert_eq!(
+            stage.option_path::<String>("packages/0").unwrap().unwrap(),
+            "sha256:a"
+        );
+        assert_eq!(stage.option_path::<String>("packages/1").unwrap(), None);
+        assert_eq!(stage.option::<String>("gpgkeys").unwrap(), None);
+        assert_eq! {
+            stage.option_path::<u32>("packages/0").unwrap_err().to_string(),
+            "invalid version-1 manifest: /options/packages/0: invalid type: string \"sha256:a\", expected u32",
+        }
+
+        for name in [
+            ChronyOptions::NAME,
+            HostnameOptions::NAME,
+            KeymapOptions::NAME,
+            LocaleOptions::NAME,
+            RpmOptions::NAME,
+            TimezoneOptions::NAME,
+        ] {
+            assert!(!catalog::lookup(name).is_empty(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! OSTree Resolution
+//!
+//! OSTree sources of manifests refer to commits by checksum, while image
+//! definitions usually name a ref of a remote, like `fedora/39/x86_64/iot`.
+//! This module resolves refs to commits at manifest-generation time, by
+//! reading the summary file of the remote or, if the remote has none, the
+//! ref file itself. The commit object is fetched as well, to report its
+//! version and timestamp.
+//!
+//! Requests are performed by a caller-provided [`crate::http::Transport`].
+
+use crate::gvariant::{self, Value};
+use crate::http::Transport;
+use crate::manifest::Json;
+
+const SUMMARY_TYPE: &str = "(a(s(taya{sv}))a{sv})";
+const COMMIT_TYPE: &str = "(a{sv}aya(say)sstayay)";
+
+/// Resolved Commit
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Commit {
+    /// URL of the remote.
+    pub url: String,
+    /// The resolved ref.
+    pub reference: String,
+    /// Checksum of the commit, as lower-case hexadecimal string.
+    pub checksum: String,
+    /// The `version` metadata of the commit.
+    pub version: Option<String>,
+    /// Commit timestamp, in seconds since the epoch.
+    pub timestamp: u64,
+    pub subject: String,
+}
+
+impl Commit {
+    /// Source Item
+    ///
+    /// Create the item of the `org.osbuild.ostree` source that fetches
+    /// this commit, returned as key and value.
+    pub fn source_item(&self) -> (String, Json) {
+        (
+            self.checksum.clone(),
+            serde_json::json!({ "remote": { "url": self.url } }),
+        )
+    }
+}
+
+/// Resolution Error
+#[derive(Debug)]
+pub enum Error {
+    /// The transport failed.
+    Transport(String),
+    /// The remote responded to the given URL with an error status.
+    Status(String, u16),
+    /// The remote returned malformed data for the given URL.
+    Invalid(String, String),
+    /// The remote has no such ref.
+    NoRef(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Transport(v) => write!(fmt, "transport error: {}", v),
+            Error::Status(u, v) => write!(fmt, "{}: remote responded with status {}", u, v),
+            Error::Invalid(u, v) => write!(fmt, "{}: {}", u, v),
+            Error::NoRef(u, v) => write!(fmt, "{}: no ref `{}`", u, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn is_checksum(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|v| matches!(v, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// Extract the ref entries of a summary, as ref name and checksum.
+fn summary_refs(summary: &Value) -> Vec<(String, String)> {
+    let refs = match summary {
+        Value::Tuple(v) => match v.first() {
+            Some(Value::Array(v)) => v,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+
+    refs.iter()
+        .filter_map(|v| match v {
+            Value::Tuple(v) => match v.as_slice() {
+                [Value::Str(name), Value::Tuple(data)] => match data.get(1) {
+                    Some(Value::Bytes(c)) => Some((name.clone(), crate::hash::to_hex(c))),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// OSTree Resolver
+pub struct Resolver<T> {
+    transport: T,
+}
+
+impl<T: Transport> Resolver<T> {
+    /// Create New Resolver
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    // Fetch a file of the remote, returning `None` if it does not exist.
+    fn fetch(&self, url: &str) -> Result<Option<Vec<u8>>, Error> {
+        let r = self.transport.get(url, &[]).map_err(Error::Transport)?;
+        match r.status {
+            200 => Ok(Some(r.body)),
+            404 => Ok(None),
+            v => Err(Error::Status(url.to_owned(), v)),
+        }
+    }
+
+    // Resolve a ref to a commit checksum.
+    fn checksum(&self, base: &str, reference: &str) -> Result<String, Error> {
+        let url = format!("{}/summary", base);
+        if let Some(data) = self.fetch(&url)? {
+            let summary = gvariant::decode(SUMMARY_TYPE, &data)
+                .map_err(|e| Error::Invalid(url.clone(), e))?;
+            return summary_refs(&summary)
+                .into_iter()
+                .find(|(name, _)| name == reference)
+                .map(|(_, c)| c)
+                .ok_or_else(|| Error::NoRef(base.to_owned(), reference.to_owned()));
+        }
+
+        let url = format!("{}/refs/heads/{}", base, reference);
+        let data = self
+            .fetch(&url)?
+            .ok_or_else(|| Error::NoRef(base.to_owned(), reference.to_owned()))?;
+        let checksum = String::from_utf8_lossy(&data).trim().to_owned();
+        if !is_checksum(&checksum) {
+            return Err(Error::Invalid(
+                url,
+                "ref is not a commit checksum".to_owned(),
+            ));
+        }
+        Ok(checksum)
+    }
+
+    /// Resolve Ref
+    ///
+    /// Resolve a ref of the remote at `url` to its commit. Checksums are
+    /// accepted in place of refs, to fetch the metadata of a known commit.
+    pub fn resolve(&self, url: &str, reference: &str) -> Result<Commit, Error> {
+        span!("resolve-ostree", url = url, reference = reference);
+        let base = url.trim_end_matches('/');
+        let checksum = if is_checksum(reference) {
+            reference.to_owned()
+        } else {
+            self.checksum(base, reference)?
+        };
+
+        let url = format!(
+            "{}/objects/{}/{}.commit",
+            base,
+            &checksum[..2],
+            &checksum[2..]
+        );
+        let data = self
+            .fetch(&url)?
+            .ok_or_else(|| Error::Status(url.clone(), 404))?;
+        if crate::hash::to_hex(&crate::hash::Sha256::digest(&data)) != checksum {
+            return Err(Error::Invalid(url, "commit checksum mismatch".to_owned()));
+        }
+        let commit = match gvariant::decode(COMMIT_TYPE, &data) {
+            Ok(Value::Tuple(v)) => v,
+            Ok(_) => unreachable!(),
+            Err(e) => return Err(Error::Invalid(url, e)),
+        };
+
+        event!(Debug, "resolved", checksum = checksum);
+        Ok(Commit {
+            url: base.to_owned(),
+            reference: reference.to_owned(),
+            checksum,
+            version: commit[0]
+                .lookup("version")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            // OSTree stores the timestamp in big-endian order.
+            timestamp: match commit[5] {
+                Value::Uint(v) => v.swap_bytes(),
+                _ => 0,
+            },
+            subject: commit[3].as_str().unwrap_or_default().to_owned(),
+        })
+    }
+
+    /// Pin Manifest Sources
+    ///
+    /// Resolve the refs given by the `mpp-ref` member of the items of the
+    /// `org.osbuild.ostree` source of a manifest, re-keying them by the
+    /// resolved commit checksums.
+    pub fn pin(&self, manifest: &mut Json) -> Result<(), Error> {
+        let items = match manifest
+            .pointer_mut("/sources/org.osbuild.ostree/items")
+            .and_then(Json::as_object_mut)
+        {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+
+        for (key, mut item) in std::mem::take(items) {
+            let reference = item
+                .as_object_mut()
+                .and_then(|v| v.remove("mpp-ref"))
+                .and_then(|v| v.as_str().map(str::to_owned));
+            let url = item
+                .pointer("/remote/url")
+                .and_then(Json::as_str)
+                .map(str::to_owned);
+            match (reference, url) {
+                (Some(reference), Some(url)) => {
+                    let commit = self.resolve(&url, &reference)?;
+                    items.insert(commit.checksum, item);
+                }
+                _ => {
+                    items.insert(key, item);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+
+    struct Fake(Vec<(String, Vec<u8>)>);
+
+    impl Transport for Fake {
+        fn get(&self, url: &str, _headers: &[(&str, &str)]) -> Result<Response, String> {
+            Ok(match self.0.iter().find(|(u, _)| u == url) {
+                Some((_, body)) => Response {
+                    status: 200,
+                    body: body.clone(),
+                    ..Default::default()
+                },
+                None => Response {
+                    status: 404,
+                    ..Default::default()
+                },
+            })
+        }
+    }
+
+    fn commit() -> Vec<u8> {
+        let value = Value::Tuple(vec![
+            Value::Dict(vec![(
+                Value::Str("version".to_owned()),
+                Value::Variant(
+                    "s".to_owned(),
+                    Box::new(Value::Str("39.20231101.0".to_owned())),
+                ),
+            )]),
+            Value::Bytes(Vec::new()),
+            Value::Array(Vec::new()),
+            Value::Str("Release 39".to_owned()),
+            Value::Str(String::new()),
+            Value::Uint(1698796800u64.swap_bytes()),
+            Value::Bytes(vec![0; 32]),
+            Value::Bytes(vec![0; 32]),
+        ]);
+        gvariant::encode(COMMIT_TYPE, &value).unwrap()
+    }
+
+    // Verify Ref Resolution
+    #[test]
+    fn verify_resolve() {
+        let data = commit();
+        let digest = crate::hash::Sha256::digest(&data);
+        let checksum = crate::hash::to_hex(&digest);
+        let object = format!(
+            "https://example.com/repo/objects/{}/{}.commit",
+            &checksum[..2],
+            &checksum[2..]
+        );
+
+        let summary = Value::Tuple(vec![
+            Value::Array(vec![Value::Tuple(vec![
+                Value::Str("fedora/39/x86_64/iot".to_owned()),
+                Value::Tuple(vec![
+                    Value::Uint(data.len() as u64),
+                    Value::Bytes(digest.to_vec()),
+                    Value::Dict(Vec::new()),
+                ]),
+            ])]),
+            Value::Dict(Vec::new()),
+        ]);
+
+        // Resolution via the summary.
+        let resolver = Resolver::new(Fake(vec![
+            (
+                "https://example.com/repo/summary".to_owned(),
+                gvariant::encode(SUMMARY_TYPE, &summary).unwrap(),
+            ),
+            (object.clone(), data.clone()),
+        ]));
+        let commit = resolver
+            .resolve("https://example.com/repo/", "fedora/39/x86_64/iot")
+            .unwrap();
+        assert_eq!(commit.checksum, checksum);
+        assert_eq!(commit.version.as_deref(), Some("39.20231101.0"));
+        assert_eq!(commit.timestamp, 1698796800);
+        assert_eq!(commit.subject, "Release 39");
+        assert!(matches!(
+            resolver.resolve("https://example.com/repo", "fedora/40/x86_64/iot"),
+            Err(Error::NoRef(..)),
+        ));
+
+        // Resolution via the ref file, and source pinning.
+        let resolver = Resolver::new(Fake(vec![
+            (
+                "https://example.com/repo/refs/heads/fedora/39/x86_64/iot".to_owned(),
+                format!("{}\n", checksum).into_bytes(),
+            ),
+            (object, data),
+        ]));
+        let mut manifest = serde_json::json!({
+            "sources": {
+                "org.osbuild.ostree": {
+                    "items": {
+                        "iot": {
+                            "mpp-ref": "fedora/39/x86_64/iot",
+                            "remote": { "url": "https://example.com/repo" }
+                        }
+                    }
+                }
+            }
+        });
+        resolver.pin(&mut manifest).unwrap();
+        assert_eq! {
+            manifest["sources"]["org.osbuild.ostree"]["items"],
+            serde_json::json!({ checksum.clone(): { "remote": { "url": "https://example.com/repo" } } }),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Package Extraction
+//!
+//! This module extracts the set of packages a manifest installs, without
+//! running the build. Rpm stages only reference packages by checksum, so
+//! the checksums are joined with the URLs of the file sources to recover
+//! the package file names, which in turn encode name, version, release, and
+//! architecture of each package.
+//!
+//! The result is meant to be consumed by license-compliance and
+//! vulnerability scanners, hence all types serialize to plain JSON.
+
+use crate::lint;
+use crate::manifest::{Json, Manifest1};
+
+/// Package Identity
+///
+/// The name, epoch, version, release, and architecture of a package, as
+/// encoded in the file names of rpm packages.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Nevra {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<String>,
+    pub version: String,
+    pub release: String,
+    pub arch: String,
+}
+
+/// Installed Package
+///
+/// A package installed by an rpm stage. The URL and identity of a package
+/// are only known if the manifest carries a file source for its checksum.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Package {
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nevra: Option<Nevra>,
+    /// JSON pointer of the stage that installs the package.
+    pub stage: String,
+    /// Whether the package is installed into a build pipeline, rather than
+    /// into the tree of the final artifact.
+    pub build: bool,
+}
+
+// Sources that map checksums to URLs.
+const URL_SOURCES: &[&str] = &["org.osbuild.files", "org.osbuild.curl"];
+
+fn percent_decode(s: &str) -> String {
+    let s = s.as_bytes();
+    let mut acc = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s.get(i + 1..i + 3).and_then(|v| {
+            std::str::from_utf8(v)
+                .ok()
+                .and_then(|v| u8::from_str_radix(v, 16).ok())
+        });
+        match (s[i], hex) {
+            (b'%', Some(v)) => {
+                acc.push(v);
+                i += 3;
+            }
+            (v, _) => {
+                acc.push(v);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&acc).into_owned()
+}
+
+impl Nevra {
+    /// Parse Package File Name
+    ///
+    /// Parse the identity of a package from the file name of an rpm
+    /// package, like `bash-5.1.8-2.fc35.x86_64.rpm`. A leading path or URL
+    /// is stripped first. Returns `None` if the name does not follow the
+    /// rpm naming scheme.
+    pub fn parse(filename: &str) -> Option<Self> {
+        let filename = filename.rsplit('/').next()?;
+        let filename = percent_decode(filename);
+        let stem = filename.strip_suffix(".rpm")?;
+
+        let (stem, arch) = stem.rsplit_once('.')?;
+        let (stem, release) = stem.rsplit_once('-')?;
+        let (name, version) = stem.rsplit_once('-')?;
+        let (epoch, version) = match version.split_once(':') {
+            Some((e, v)) => (Some(e.to_owned()), v),
+            None => (None, version),
+        };
+
+        if [name, version, release, arch].iter().any(|v| v.is_empty()) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_owned(),
+            epoch,
+            version: version.to_owned(),
+            release: release.to_owned(),
+            arch: arch.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for Nevra {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}-", self.name)?;
+        if let Some(ref epoch) = self.epoch {
+            write!(fmt, "{}:", epoch)?;
+        }
+        write!(fmt, "{}-{}.{}", self.version, self.release, self.arch)
+    }
+}
+
+// Look up the URL of a checksum in the file sources of the manifest. URLs
+// can be given as plain strings or as objects with a `url` member.
+fn lookup<'a>(manifest: &'a Manifest1, checksum: &str) -> Option<&'a str> {
+    URL_SOURCES
+        .iter()
+        .filter_map(|v| manifest.sources.get(*v))
+        .filter_map(|v| v.get("urls"))
+        .filter_map(|v| v.get(checksum))
+        .find_map(|v| match v {
+            Json::String(v) => Some(v.as_str()),
+            v => v.get("url").and_then(Json::as_str),
+        })
+}
+
+/// Extract Packages
+///
+/// Collect all packages installed by rpm stages of the manifest, including
+/// those of build pipelines, in execution order. Each package is joined
+/// with its source URL and its identity is parsed from the URL, if
+/// possible. Packages installed by multiple stages are listed once per
+/// stage.
+pub fn packages(manifest: &Manifest1) -> Vec<Package> {
+    let mut acc = Vec::new();
+
+    for (path, stage) in lint::all_stages(manifest) {
+        if stage.name != "org.osbuild.rpm" {
+            continue;
+        }
+
+        let list = stage.options.get("packages").and_then(Json::as_array);
+        for package in list.into_iter().flatten() {
+            let checksum = match package {
+                Json::String(v) => v.as_str(),
+                v => match v.get("checksum").and_then(Json::as_str) {
+                    None => continue,
+                    Some(v) => v,
+                },
+            };
+            let url = lookup(manifest, checksum);
+
+            acc.push(Package {
+                checksum: checksum.to_owned(),
+                url: url.map(str::to_owned),
+                nevra: url.and_then(Nevra::parse),
+                stage: path.clone(),
+                build: path.starts_with("/pipeline/build/"),
+            });
+        }
+    }
+
+    acc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Package File Name Parsing
+    #[test]
+    fn verify_nevra() {
+        let v = Nevra::parse("https://example.com/p/bash-5.1.8-2.fc35.x86_64.rpm").unwrap();
+        assert_eq!(v.name, "bash");
+        assert_eq!(v.epoch, None);
+        assert_eq!(v.version, "5.1.8");
+        assert_eq!(v.release, "2.fc35");
+        assert_eq!(v.arch, "x86_64");
+        assert_eq!(v.to_string(), "bash-5.1.8-2.fc35.x86_64");
+
+        let v = Nevra::parse("libstdc%2B%2B-devel-1:11.2.1-1.fc35.noarch.rpm").unwrap();
+        assert_eq!(v.name, "libstdc++-devel");
+        assert_eq!(v.epoch.as_deref(), Some("1"));
+        assert_eq!(v.to_string(), "libstdc++-devel-1:11.2.1-1.fc35.noarch");
+
+        assert!(Nevra::parse("bash.rpm").is_none());
+        assert!(Nevra::parse("bash-5.1.8-2.fc35.x86_64.tar").is_none());
+    }
+
+    // Verify Package Extraction
+    #[test]
+    fn verify_packages() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [
+                                { "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a"] } }
+                            ]
+                        },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        {
+                            "name": "org.osbuild.rpm",
+                            "options": { "packages": [{ "checksum": "sha256:b" }, "sha256:c"] }
+                        }
+                    ]
+                },
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": {
+                            "sha256:a": "https://example.com/rpm-4.17.0-1.fc35.x86_64.rpm",
+                            "sha256:b": { "url": "https://example.com/bash-5.1.8-2.fc35.x86_64.rpm" }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let list = packages(&manifest);
+        assert_eq! {
+            list.iter()
+                .map(|v| (
+                    v.checksum.as_str(),
+                    v.nevra.as_ref().map(|v| v.name.as_str()),
+                    v.stage.as_str(),
+                    v.build,
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("sha256:a", Some("rpm"), "/pipeline/build/pipeline/stages/0", true),
+                ("sha256:b", Some("bash"), "/pipeline/stages/0", false),
+                ("sha256:c", None, "/pipeline/stages/0", false),
+            ],
+        }
+        assert_eq! {
+            serde_json::to_value(&list[2]).unwrap(),
+            serde_json::json!({
+                "checksum": "sha256:c",
+                "stage": "/pipeline/stages/0",
+                "build": false,
+            }),
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! SLSA Provenance
+//!
+//! This module produces SLSA v1 provenance for an osbuild build, as an
+//! in-toto v1 statement. The statement names the built artifacts as its
+//! subjects, and records the builder, the invocation, and all materials of
+//! the build. Materials are derived from the sources of the manifest, each
+//! with the digest the source is verified against, plus the manifest itself.
+//!
+//! The statement is a typed structure. It serializes to the JSON form
+//! expected as payload of an in-toto envelope.
+
+use crate::manifest::{Json, Manifest1, Object};
+
+/// In-toto Statement Type
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// SLSA Provenance Predicate Type
+pub const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+
+/// Build Type
+///
+/// The default build type, describing a build of an osbuild manifest.
+pub const BUILD_TYPE: &str = "https://osbuild.org/provenance/manifest/v1";
+
+/// Resource Descriptor
+///
+/// A reference to an artifact, used for subjects and materials.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ResourceDescriptor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// Digests of the artifact, keyed by algorithm.
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub digest: Object<String>,
+}
+
+impl ResourceDescriptor {
+    /// Create Descriptor from Checksum
+    ///
+    /// Create a descriptor from an osbuild checksum of the form
+    /// `<algorithm>:<hex>`. Returns `None` if the checksum is malformed.
+    pub fn with_checksum(uri: Option<String>, checksum: &str) -> Option<Self> {
+        let (algorithm, value) = checksum.split_once(':')?;
+        if algorithm.is_empty() || value.is_empty() {
+            return None;
+        }
+        Some(Self {
+            name: None,
+            uri,
+            digest: [(algorithm.to_owned(), value.to_owned())].into(),
+        })
+    }
+}
+
+/// Builder
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Builder {
+    /// URI identifying the build platform.
+    pub id: String,
+    /// Versions of the components of the build platform.
+    #[serde(default, skip_serializing_if = "Object::is_empty")]
+    pub version: Object<String>,
+}
+
+/// Build Metadata
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invocation_id: Option<String>,
+    /// Start time, as UTC timestamp like `2023-11-01T00:00:00Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_on: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_on: Option<String>,
+}
+
+/// Build Definition
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildDefinition {
+    pub build_type: String,
+    pub external_parameters: Json,
+    #[serde(default, skip_serializing_if = "Json::is_null")]
+    pub internal_parameters: Json,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved_dependencies: Vec<ResourceDescriptor>,
+}
+
+/// Run Details
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RunDetails {
+    pub builder: Builder,
+    #[serde(default)]
+    pub metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub byproducts: Vec<ResourceDescriptor>,
+}
+
+/// Provenance Predicate
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub build_definition: BuildDefinition,
+    pub run_details: RunDetails,
+}
+
+/// In-toto Statement
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<ResourceDescriptor>,
+    pub predicate_type: String,
+    pub predicate: Provenance,
+}
+
+/// Build Invocation
+///
+/// The information of a build that cannot be derived from the manifest.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Invocation {
+    pub builder: Builder,
+    pub metadata: Metadata,
+    /// Parameters the build was requested with, like the exported
+    /// pipelines. Recorded as external parameters, next to the manifest.
+    pub parameters: Object<Json>,
+}
+
+/// Manifest Materials
+///
+/// Collect the materials of a manifest from its sources. Every item of
+/// the file, container, and ostree sources is reported with its URI, if
+/// any, and the digest it is verified against. Items without usable digest
+/// are skipped.
+pub fn materials(manifest: &Manifest1) -> Vec<ResourceDescriptor> {
+    let mut acc = Vec::new();
+
+    for (name, source) in &manifest.sources {
+        let items = source
+            .get("urls")
+            .or_else(|| source.get("items"))
+            .and_then(Json::as_object);
+
+        for (key, item) in items.into_iter().flatten() {
+            let material = match name.as_str() {
+                "org.osbuild.skopeo" => {
+                    let image = item.pointer("/image/name").and_then(Json::as_str);
+                    let digest = item
+                        .pointer("/image/digest")
+                        .and_then(Json::as_str)
+                        .unwrap_or(key);
+                    ResourceDescriptor::with_checksum(
+                        image.map(|v| format!("docker://{}", v)),
+                        digest,
+                    )
+                }
+                "org.osbuild.ostree" => {
+                    let url = item.pointer("/remote/url").and_then(Json::as_str);
+                    Some(ResourceDescriptor {
+                        name: None,
+                        uri: url.map(str::to_owned),
+                        digest: [("ostreeCommit".to_owned(), key.clone())].into(),
+                    })
+                }
+                "org.osbuild.inline" => ResourceDescriptor::with_checksum(None, key),
+                _ => {
+                    let url = match item {
+                        Json::String(v) => Some(v.as_str()),
+                        v => v.get("url").and_then(Json::as_str),
+                    };
+                    ResourceDescriptor::with_checksum(url.map(str::to_owned), key)
+                }
+            };
+            acc.extend(material);
+        }
+    }
+
+    acc
+}
+
+/// Generate Provenance
+///
+/// Create the provenance statement for the build of `manifest` that
+/// produced the artifacts given as `subject`.
+pub fn statement(
+    manifest: &Manifest1,
+    subject: Vec<ResourceDescriptor>,
+    invocation: &Invocation,
+) -> Statement {
+    let serialized = serde_json::to_vec(manifest).unwrap_or_default();
+    let digest = crate::hash::to_hex(&crate::hash::Sha256::digest(&serialized));
+
+    let mut parameters = invocation.parameters.clone();
+    parameters.insert(
+        "manifest".to_owned(),
+        serde_json::json!({ "digest": { "sha256": digest } }),
+    );
+
+    let mut dependencies = vec![ResourceDescriptor {
+        name: Some("manifest".to_owned()),
+        uri: None,
+        digest: [("sha256".to_owned(), digest)].into(),
+    }];
+    dependencies.extend(materials(manifest));
+
+    Statement {
+        statement_type: STATEMENT_TYPE.to_owned(),
+        subject,
+        predicate_type: PREDICATE_TYPE.to_owned(),
+        predicate: Provenance {
+            build_definition: BuildDefinition {
+                build_type: BUILD_TYPE.to_owned(),
+                external_parameters: Json::Object(parameters.into_iter().collect()),
+                internal_parameters: Json::Null,
+                resolved_dependencies: dependencies,
+            },
+            run_details: RunDetails {
+                builder: invocation.builder.clone(),
+                metadata: invocation.metadata.clone(),
+                byproducts: Vec::new(),
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Provenance Generation
+    #[test]
+    fn verify_statement() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": {
+                            "sha256:a": "https://example.com/a.rpm",
+                            "sha256:b": { "url": "https://example.com/b.rpm" },
+                            "invalid": "https://example.com/c.rpm"
+                        }
+                    },
+                    "org.osbuild.skopeo": {
+                        "items": {
+                            "sha256:id": { "image": { "name": "quay.io/org/app", "digest": "sha256:m" } }
+                        }
+                    },
+                    "org.osbuild.ostree": {
+                        "items": {
+                            "0123": { "remote": { "url": "https://example.com/repo" } }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let uris: Vec<_> = materials(&manifest)
+            .into_iter()
+            .map(|v| (v.uri.unwrap_or_default(), v.digest))
+            .collect();
+        assert_eq! {
+            uris,
+            vec![
+                ("https://example.com/a.rpm".to_owned(), [("sha256".to_owned(), "a".to_owned())].into()),
+                ("https://example.com/b.rpm".to_owned(), [("sha256".to_owned(), "b".to_owned())].into()),
+                ("https://example.com/repo".to_owned(), [("ostreeCommit".to_owned(), "0123".to_owned())].into()),
+                ("docker://quay.io/org/app".to_owned(), [("sha256".to_owned(), "m".to_owned())].into()),
+            ],
+        }
+
+        let statement = statement(
+            &manifest,
+            vec![
+                ResourceDescriptor::with_checksum(Some("disk.qcow2".to_owned()), "sha256:d")
+                    .unwrap(),
+            ],
+            &Invocation {
+                builder: Builder {
+                    id: "https://example.com/builder".to_owned(),
+                    ..Default::default()
+                },
+                metadata: Metadata {
+                    invocation_id: Some("42".to_owned()),
+                    ..Default::default()
+                },
+                parameters: [("exports".to_owned(), serde_json::json!(["qcow2"]))].into(),
+            },
+        );
+
+        let json = serde_json::to_value(&statement).unwrap();
+        assert_eq!(json["_type"], STATEMENT_TYPE);
+        assert_eq!(json["subject"][0]["digest"]["sha256"], "d");
+        assert_eq!(json["predicateType"], PREDICATE_TYPE);
+        assert_eq!(
+            json["predicate"]["buildDefinition"]["externalParameters"]["exports"][0],
+            "qcow2"
+        );
+        assert_eq!(
+            json["predicate"]["buildDefinition"]["resolvedDependencies"][0]["name"],
+            "manifest"
+        );
+        assert_eq!(
+            json["predicate"]["buildDefinition"]["resolvedDependencies"]
+                .as_array()
+                .unwrap()
+                .len(),
+            5
+        );
+        assert_eq!(
+            json["predicate"]["runDetails"]["metadata"]["invocationId"],
+            "42"
+        );
+        assert!(json["predicate"]["buildDefinition"]
+            .get("internalParameters")
+            .is_none());
+
+        let parsed: Statement = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, statement);
+    }
+}
@@ -0,0 +1,153 @@
+//! Stage Queries
+//!
+//! This module provides helpers to look up stages of a manifest, for
+//! scripts that rewrite manifests. Stages are returned together with their
+//! JSON pointer, which serves as stable handle: it can be resolved again
+//! with `Manifest1::stage_mut()`, used with `serde_json::Value::pointer()`
+//! on the serialized manifest, and matches the paths reported by
+//! diagnostics.
+//!
+//! Selections take predicates over stages. The predicates of this module
+//! can be combined with closures as needed.
+
+use crate::lint;
+use crate::manifest::{Json, Manifest1, Stage1};
+
+impl Manifest1 {
+    /// Select Stages
+    ///
+    /// Return all stages matching the predicate, with their handles, in
+    /// execution order.
+    pub fn select_stages(&self, predicate: impl Fn(&Stage1) -> bool) -> Vec<(String, &Stage1)> {
+        lint::all_stages(self)
+            .into_iter()
+            .filter(|(_, v)| predicate(v))
+            .collect()
+    }
+
+    /// Find Stages by Name
+    pub fn find_stages(&self, name: &str) -> Vec<(String, &Stage1)> {
+        self.select_stages(named(name))
+    }
+
+    /// Resolve Stage Handle
+    ///
+    /// Return the stage referenced by the handle, if it still exists.
+    pub fn stage_mut(&mut self, handle: &str) -> Option<&mut Stage1> {
+        let mut segments = handle.strip_prefix("/pipeline/")?.split('/');
+        let mut pipeline = &mut self.pipeline;
+        loop {
+            match segments.next()? {
+                "build" if segments.next()? == "pipeline" => {
+                    pipeline = &mut pipeline.build.as_mut()?.pipeline;
+                }
+                "stages" => {
+                    let index: usize = segments.next()?.parse().ok()?;
+                    return match segments.next() {
+                        None => pipeline.stages.get_mut(index),
+                        Some(_) => None,
+                    };
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// First Stage by Name
+    ///
+    /// Return the first stage of the given name, in execution order.
+    pub fn first_stage_mut(&mut self, name: &str) -> Option<&mut Stage1> {
+        let handle = self.find_stages(name).into_iter().next()?.0;
+        self.stage_mut(&handle)
+    }
+}
+
+/// Select by Name
+pub fn named(name: &str) -> impl Fn(&Stage1) -> bool + '_ {
+    move |v| v.name == name
+}
+
+/// Select by Option Presence
+///
+/// Match stages that have a value at the given JSON pointer into their
+/// options.
+pub fn has_option(pointer: &str) -> impl Fn(&Stage1) -> bool + '_ {
+    move |v| option(v, pointer).is_some()
+}
+
+/// Select by Option Value
+///
+/// Match stages whose value at the given JSON pointer into their options
+/// equals `value`.
+pub fn option_eq(pointer: &str, value: Json) -> impl Fn(&Stage1) -> bool + '_ {
+    move |v| option(v, pointer) == Some(&value)
+}
+
+// Resolve a JSON pointer into the options of a stage.
+fn option<'a>(stage: &'a Stage1, pointer: &str) -> Option<&'a Json> {
+    let pointer = pointer.strip_prefix('/')?;
+    let (first, rest) = pointer.split_at(pointer.find('/').unwrap_or(pointer.len()));
+    let first = first.replace("~1", "/").replace("~0", "~");
+    stage.options.get(&first)?.pointer(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Stage Queries
+    #[test]
+    fn verify_query() {
+        let mut manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": { "stages": [{ "name": "org.osbuild.rpm", "options": { "packages": [] } }] },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [
+                        { "name": "org.osbuild.rpm", "options": { "packages": [], "gpgkeys": ["k"] } },
+                        { "name": "org.osbuild.grub2", "options": { "rootfs": { "uuid": "u" }, "a/b": 1 } }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let handles: Vec<_> = manifest
+            .find_stages("org.osbuild.rpm")
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(
+            handles,
+            ["/pipeline/build/pipeline/stages/0", "/pipeline/stages/0"]
+        );
+
+        assert_eq!(manifest.select_stages(has_option("/gpgkeys")).len(), 1);
+        assert_eq!(manifest.select_stages(has_option("/a~1b")).len(), 1);
+        let selected = manifest.select_stages(option_eq("/rootfs/uuid", "u".into()));
+        assert_eq!(selected[0].0, "/pipeline/stages/1");
+        assert!(manifest
+            .select_stages(option_eq("/rootfs/uuid", "v".into()))
+            .is_empty());
+
+        // Handles resolve to the stages they were returned for.
+        manifest
+            .stage_mut(&handles[1])
+            .unwrap()
+            .options
+            .insert("exclude".to_owned(), Json::Null);
+        assert!(manifest.pipeline.stages[0].options.contains_key("exclude"));
+        assert!(manifest.stage_mut("/pipeline/stages/7").is_none());
+        assert!(manifest.stage_mut("/pipeline/stages/0/options").is_none());
+        assert!(manifest.stage_mut("/sources").is_none());
+
+        let stage = manifest.first_stage_mut("org.osbuild.rpm").unwrap();
+        stage.options.clear();
+        assert!(manifest.pipeline.build.as_ref().unwrap().pipeline.stages[0]
+            .options
+            .is_empty());
+        assert!(manifest.first_stage_mut("org.osbuild.tar").is_none());
+    }
+}
@@ -0,0 +1,473 @@
+//! REST Service
+//!
+//! This module serves the manifest operations over plain HTTP, so build
+//! pipelines can check manifests with a single request. All endpoints take
+//! the manifest as JSON request body, in either format version:
+//!
+//! - `POST /v1/validate`: Lint the manifest and return the response of
+//!   `service::validate()`, with status 200 if it is valid, 422 otherwise.
+//! - `POST /v1/convert?to=v1|v2`: Convert the manifest to the given format
+//!   version.
+//! - `POST /v1/sarif?uri=<uri>`: Lint the manifest and return the
+//!   diagnostics as SARIF log, located in the artifact `uri`.
+//! - `POST /v1/diff`: Diff the manifests of a `service::DiffRequest`.
+//! - `POST /v1/ids`: Return the stage identifiers of the manifest.
+//! - `GET /healthz`: Report readiness.
+//!
+//! The server implements the subset of HTTP/1.1 these endpoints need, and
+//! closes every connection after its response. Request bodies must be sent
+//! with `Content-Length`; chunked transfer encoding is rejected. Request
+//! lines, headers, bodies, the time spent on a connection, and the number of
+//! concurrent connections are bounded by the constants of this module. The
+//! server does not implement TLS and is meant to run behind a reverse proxy
+//! or on trusted networks.
+
+use crate::convert;
+use crate::http::Response;
+use crate::lint::{self, sarif};
+use crate::manifest::{Json, Manifest, Manifest1};
+use crate::service;
+use std::io::{BufRead, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximum Request Body Size
+pub const MAX_BODY: usize = 16 << 20;
+
+/// Maximum Length of the Request Line and of each Header Line
+pub const MAX_LINE: usize = 8 << 10;
+
+/// Maximum Number of Request Headers
+pub const MAX_HEADERS: usize = 100;
+
+/// Maximum Number of Concurrent Connections
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Time Limit of Connections
+///
+/// This bounds the time from accepting a connection until its response is
+/// written, across all reads and writes.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn respond(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+    Response {
+        status,
+        headers: vec![("Content-Type".to_owned(), content_type.to_owned())],
+        body,
+    }
+}
+
+fn json(status: u16, value: &Json) -> Response {
+    respond(status, "application/json", value.to_string().into_bytes())
+}
+
+fn error(status: u16, message: &str) -> Response {
+    json(
+        status,
+        &serde_json::json!({ "error": { "message": message } }),
+    )
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        _ => "Unknown",
+    }
+}
+
+// Look up a query parameter. Values are used verbatim, without decoding.
+fn query<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|v| v.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+// Convert the manifest to the target version. Manifests of the target
+// version are returned unchanged.
+fn convert(text: &str, to: Option<&str>) -> Response {
+    let manifest = match service::parse(text) {
+        Ok(v) => v,
+        Err(e) => return json(422, &serde_json::json!({ "error": e })),
+    };
+    match (to, manifest) {
+        (Some("v1"), Manifest::V1(_)) | (Some("v2"), Manifest::V2(_)) => {
+            respond(200, "application/json", text.as_bytes().to_vec())
+        }
+        (Some("v1"), Manifest::V2(v)) => match Manifest1::try_from(&v) {
+            Ok(v) => json(200, &serde_json::to_value(v).unwrap_or_default()),
+            Err(e) => error(422, &e.to_string()),
+        },
+        (Some("v2"), Manifest::V1(v)) => json(200, &convert::to_v2(&v)),
+        _ => error(400, "query parameter `to` must be `v1` or `v2`"),
+    }
+}
+
+fn sarif(text: &str, uri: Option<&str>) -> Response {
+    match service::parse(text) {
+        Err(e) => json(422, &serde_json::json!({ "error": e })),
+        Ok(v) => {
+            let artifact = sarif::Artifact {
+                uri: uri.unwrap_or("manifest.json"),
+                source: Some(text),
+            };
+            let log = sarif::to_sarif(&lint::check(&v), Some(artifact));
+            respond(200, "application/sarif+json", log.to_string().into_bytes())
+        }
+    }
+}
+
+/// Handle Request
+///
+/// Route a request to its endpoint and return the response. `target` is
+/// the request target, including the query.
+pub fn handle(method: &str, target: &str, body: &[u8]) -> Response {
+    let (path, params) = target.split_once('?').unwrap_or((target, ""));
+
+    match (method, path) {
+        ("GET", "/healthz") => respond(200, "text/plain", b"ok\n".to_vec()),
+        ("POST", "/v1/validate" | "/v1/convert" | "/v1/sarif" | "/v1/diff" | "/v1/ids") => {
+            let text = match std::str::from_utf8(body) {
+                Ok(v) => v,
+                Err(_) => return error(400, "request body is not valid UTF-8"),
+            };
+            match path {
+                "/v1/validate" => {
+                    let r = service::validate(&service::ValidateRequest {
+                        manifest: text.to_owned(),
+                    });
+                    let status = if r.valid { 200 } else { 422 };
+                    json(status, &serde_json::to_value(r).unwrap_or_default())
+                }
+                "/v1/convert" => convert(text, query(params, "to")),
+                "/v1/sarif" => sarif(text, query(params, "uri")),
+                "/v1/diff" => match serde_json::from_str(text) {
+                    Ok(v) => json(
+                        200,
+                        &serde_json::to_value(service::diff(&v)).unwrap_or_default(),
+                    ),
+                    Err(e) => error(400, &e.to_string()),
+                },
+                _ => {
+                    let r = service::ids(&service::IdsRequest {
+                        manifest: text.to_owned(),
+                    });
+                    let status = if r.error.is_none() { 200 } else { 422 };
+                    json(status, &serde_json::to_value(r).unwrap_or_default())
+                }
+            }
+        }
+        (_, "/healthz" | "/v1/validate" | "/v1/convert" | "/v1/sarif" | "/v1/diff" | "/v1/ids") => {
+            error(405, "method not allowed")
+        }
+        _ => error(404, "not found"),
+    }
+}
+
+// Read a line of at most `MAX_LINE` bytes, including the line break. An
+// empty result signals the end of the stream.
+fn read_line(reader: &mut dyn BufRead) -> Result<String, Response> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE as u64)
+        .read_line(&mut line)
+        .map_err(|_| error(400, "malformed request"))?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Err(error(431, "request line or header too long"));
+    }
+    Ok(line)
+}
+
+// Read a request and return its method, target, and body.
+fn read_request(reader: &mut dyn BufRead) -> Result<(String, String, Vec<u8>), Response> {
+    let bad = |_| error(400, "malformed request");
+
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m.to_owned(), t.to_owned()),
+        _ => return Err(error(400, "malformed request")),
+    };
+
+    let mut length = 0;
+    for n in 0.. {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Err(error(400, "malformed request"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if n == MAX_HEADERS {
+            return Err(error(431, "too many request headers"));
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.eq_ignore_ascii_case("transfer-encoding") {
+                return Err(error(501, "transfer encodings are not supported"));
+            } else if k.eq_ignore_ascii_case("content-length") {
+                length = v
+                    .trim()
+                    .parse()
+                    .map_err(|_| error(400, "malformed request"))?;
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(error(413, "request body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(bad)?;
+    Ok((method, target, body))
+}
+
+/// Serve Connection
+///
+/// Read a single request from the connection, handle it, and write the
+/// response.
+pub fn serve_connection<S: Read + Write>(stream: &mut S) -> std::io::Result<()> {
+    let response = {
+        let mut reader = std::io::BufReader::new(&mut *stream);
+        match read_request(&mut reader) {
+            Ok((method, target, body)) => handle(&method, &target, &body),
+            Err(v) => v,
+        }
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len(),
+    )?;
+    for (k, v) in &response.headers {
+        write!(stream, "{}: {}\r\n", k, v)?;
+    }
+    stream.write_all(b"\r\n")?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+// Connection with a deadline. Every read and write is limited to the time
+// remaining until the deadline, and fails once it has passed, so slow
+// clients cannot hold a connection by trickling data.
+struct Timed {
+    stream: std::net::TcpStream,
+    deadline: Instant,
+}
+
+impl Timed {
+    fn remaining(&self) -> std::io::Result<Duration> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(v) if !v.is_zero() => Ok(v),
+            _ => Err(std::io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+impl Read for Timed {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Timed {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// Slot of a concurrent connection, released on drop.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn acquire(count: &Arc<AtomicUsize>) -> Option<Self> {
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| {
+                (v < MAX_CONNECTIONS).then_some(v + 1)
+            })
+            .ok()
+            .map(|_| Self(count.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Serve Requests
+///
+/// Accept connections on the listener and serve each of them on its own
+/// thread. Connections are dropped once `TIMEOUT` has passed since they
+/// were accepted. Beyond `MAX_CONNECTIONS` concurrent connections, new
+/// connections are closed without being read. Only returns if accepting
+/// connections fails.
+pub fn serve(listener: std::net::TcpListener) -> std::io::Result<()> {
+    let count = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, _) = listener.accept()?;
+        let Some(slot) = Slot::acquire(&count) else {
+            continue;
+        };
+        let mut stream = Timed {
+            stream,
+            deadline: Instant::now() + TIMEOUT,
+        };
+        std::thread::spawn(move || {
+            let _slot = slot;
+            let _ = serve_connection(&mut stream);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.noop" }] } }"#;
+
+    // Verify Request Routing
+    #[test]
+    fn verify_handle() {
+        let r = handle("POST", "/v1/validate", MANIFEST.as_bytes());
+        assert_eq!(r.status, 200);
+        assert_eq!(r.header("content-type"), Some("application/json"));
+
+        let r = handle("POST", "/v1/validate", b"{ \"pipelines\": {} }");
+        assert_eq!(r.status, 422);
+
+        let r = handle("POST", "/v1/convert?to=v2", MANIFEST.as_bytes());
+        let v: Json = serde_json::from_slice(&r.body).unwrap();
+        assert_eq!(v["version"], "2");
+        let r = handle("POST", "/v1/convert?to=v2", &r.body);
+        assert_eq!(r.status, 200);
+        assert_eq!(serde_json::from_slice::<Json>(&r.body).unwrap(), v);
+        assert_eq!(
+            handle("POST", "/v1/convert", MANIFEST.as_bytes()).status,
+            400
+        );
+
+        let r = handle("POST", "/v1/sarif?uri=a.json", MANIFEST.as_bytes());
+        let v: Json = serde_json::from_slice(&r.body).unwrap();
+        assert_eq!(v["version"], "2.1.0");
+        assert_eq!(r.header("content-type"), Some("application/sarif+json"));
+
+        let v2 = "{\n  \"version\": \"2\",\n  \"pipelines\": [{ \"name\": \"image\", \"stages\": [{\n    \"type\": \"org.osbuild.mkfs.ext4\",\n    \"options\": { \"uuid\": \"x\" }\n  }] }]\n}";
+        let r = handle("POST", "/v1/sarif", v2.as_bytes());
+        let v: Json = serde_json::from_slice(&r.body).unwrap();
+        let location = &v["runs"][0]["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["region"]["startLine"], 5);
+
+        let request = serde_json::json!({ "old": MANIFEST, "new": "{}" }).to_string();
+        let r = handle("POST", "/v1/diff", request.as_bytes());
+        let v: Json = serde_json::from_slice(&r.body).unwrap();
+        assert_eq!(v["changes"][0]["kind"], "removed");
+
+        let r = handle("POST", "/v1/ids", MANIFEST.as_bytes());
+        let v: Json = serde_json::from_slice(&r.body).unwrap();
+        assert_eq!(v["stages"][0]["path"], "/pipeline/stages/0");
+
+        assert_eq!(handle("GET", "/v1/validate", b"").status, 405);
+        assert_eq!(handle("GET", "/unknown", b"").status, 404);
+    }
+
+    // Verify HTTP Server
+    #[test]
+    fn verify_serve() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener));
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /v1/validate HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            MANIFEST.len(),
+            MANIFEST,
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"valid":true}"#));
+    }
+
+    // Verify Request Limits
+    #[test]
+    fn verify_limits() {
+        let status = |request: &[u8]| {
+            let mut reader = request;
+            read_request(&mut reader).err().map(|v| v.status)
+        };
+
+        let request = b"POST /v1/validate HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(status(request), None);
+        let request =
+            b"POST /v1/validate HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        assert_eq!(status(request), Some(501));
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(status(long.as_bytes()), Some(431));
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(status(many.as_bytes()), Some(431));
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(status(request.as_bytes()), Some(413));
+        assert_eq!(status(b"GET / HTTP/1.1\r\n"), Some(400));
+
+        // The deadline covers the whole connection, not single reads.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut stream = Timed {
+            stream: listener.accept().unwrap().0,
+            deadline: Instant::now() + Duration::from_millis(300),
+        };
+        let writer = std::thread::spawn(move || {
+            for _ in 0..10 {
+                if client.write_all(b"x").is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+        let mut data = Vec::new();
+        let e = stream.read_to_end(&mut data).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+        ));
+        assert!(data.len() < 10);
+        drop(stream);
+        writer.join().unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<Slot> = std::iter::from_fn(|| Slot::acquire(&count)).collect();
+        assert_eq!(slots.len(), MAX_CONNECTIONS);
+        drop(slots);
+        assert_eq!(count.load(Ordering::Acquire), 0);
+    }
+}
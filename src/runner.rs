@@ -0,0 +1,212 @@
+//! Runners
+//!
+//! Build pipelines name the runner that executes their stages, like
+//! `org.osbuild.fedora39`. Runners of distributions follow the convention
+//! `org.osbuild.<distro><version>`, with the version joined without
+//! separator, so `rhel-9.2` uses `org.osbuild.rhel92`. This module provides
+//! a runner type that parses this convention. Runners that do not follow
+//! it, or name unknown distributions, are kept verbatim, so every runner
+//! name round-trips unchanged.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+
+// Prefix of the names of osbuild runners.
+const PREFIX: &str = "org.osbuild.";
+
+/// Known Distributions
+///
+/// Distributions with runners following the runner naming convention.
+pub const DISTROS: &[&str] = &["arch", "centos", "fedora", "rhel", "ubuntu"];
+
+/// Generic Runner
+///
+/// The runner that works on any Linux host, independent of distribution.
+pub const LINUX: &str = "org.osbuild.linux";
+
+/// Runner
+///
+/// The name of a runner. Runners dereference to `str`, and compare, hash,
+/// and order like it.
+#[derive(Clone, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Runner(String);
+
+impl Runner {
+    /// Create Runner
+    pub fn new(name: &str) -> Self {
+        Self(name.to_owned())
+    }
+
+    /// Runner of a Distribution
+    ///
+    /// Create the runner of the given distribution and version, where the
+    /// version may contain dots, like `9.2`.
+    pub fn for_distro(distro: &str, version: &str) -> Self {
+        Self(alloc::format!(
+            "{}{}{}",
+            PREFIX,
+            distro,
+            version.replace('.', "")
+        ))
+    }
+
+    /// Name of the Runner
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // Split the name into distribution and version, if it follows the
+    // naming convention for a known distribution. The version is empty
+    // for unversioned runners, like `org.osbuild.arch`.
+    fn parse(&self) -> Option<(&str, &str)> {
+        let name = self.0.strip_prefix(PREFIX)?;
+        let i = name
+            .find(|v: char| v.is_ascii_digit())
+            .unwrap_or(name.len());
+        let (distro, version) = name.split_at(i);
+        match DISTROS.contains(&distro) && version.bytes().all(|v| v.is_ascii_digit()) {
+            true => Some((distro, version)),
+            false => None,
+        }
+    }
+
+    /// Distribution of the Runner
+    ///
+    /// Return the distribution of the runner, like `fedora`, if it is the
+    /// runner of a known distribution.
+    pub fn distro(&self) -> Option<&str> {
+        self.parse().map(|v| v.0)
+    }
+
+    /// Version of the Runner
+    ///
+    /// Return the version of the distribution of the runner, like `39`, if
+    /// it is the runner of a known distribution and has a version.
+    pub fn version(&self) -> Option<&str> {
+        self.parse().map(|v| v.1).filter(|v| !v.is_empty())
+    }
+
+    /// Check for the Generic Runner
+    pub fn is_linux(&self) -> bool {
+        self.0 == LINUX
+    }
+
+    /// Check for Known Runners
+    ///
+    /// Return whether the runner is the generic runner, or the runner of a
+    /// known distribution.
+    pub fn is_known(&self) -> bool {
+        self.is_linux() || self.parse().is_some()
+    }
+}
+
+impl core::ops::Deref for Runner {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Runner {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::fmt::Debug for Runner {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_str().fmt(fmt)
+    }
+}
+
+impl core::fmt::Display for Runner {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Runner {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Runner {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Runner {
+    fn eq(&self, other: &String) -> bool {
+        self.0 == *other
+    }
+}
+
+impl From<&str> for Runner {
+    fn from(v: &str) -> Self {
+        Self::new(v)
+    }
+}
+
+impl From<String> for Runner {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Runner> for String {
+    fn from(v: Runner) -> Self {
+        v.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Runner {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Runner {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Runner Parsing
+    #[test]
+    fn verify_runner() {
+        let fedora = Runner::new("org.osbuild.fedora39");
+        assert_eq!(fedora.distro(), Some("fedora"));
+        assert_eq!(fedora.version(), Some("39"));
+        assert!(fedora.is_known());
+        assert_eq!(Runner::for_distro("rhel", "9.2"), "org.osbuild.rhel92");
+        assert_eq!(Runner::for_distro("rhel", "9.2").version(), Some("92"));
+
+        let arch = Runner::from("org.osbuild.arch");
+        assert_eq!((arch.distro(), arch.version()), (Some("arch"), None));
+
+        let linux = Runner::from(LINUX);
+        assert!(linux.is_linux() && linux.is_known());
+        assert_eq!(linux.distro(), None);
+
+        for name in [
+            "org.osbuild.gentoo1",
+            "org.osbuild.fedora39b",
+            "fedora39",
+            "",
+        ] {
+            let runner = Runner::new(name);
+            assert!(!runner.is_known());
+            assert_eq!((runner.distro(), runner.version()), (None, None));
+            assert_eq!(runner, name);
+        }
+    }
+}
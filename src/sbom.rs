@@ -0,0 +1,493 @@
+//! SPDX Software Bill of Materials
+//!
+//! This module describes the content of a produced artifact as an SPDX 2.3
+//! document. The document is generated statically from the manifest: the
+//! packages installed by rpm stages, the container images of the skopeo
+//! source, and all other fetched files, each with their download location
+//! and digest. Packages installed into build pipelines are listed as build
+//! tools of the artifact, rather than as its content.
+//!
+//! After a build, the document can be enriched with the package metadata
+//! osbuild reports for rpm stages, which covers packages whose identity
+//! cannot be derived from the manifest alone.
+
+use crate::manifest::{Json, Manifest1};
+use crate::packages::{self, Nevra};
+
+/// SPDX Version
+pub const SPDX_VERSION: &str = "SPDX-2.3";
+
+const NOASSERTION: &str = "NOASSERTION";
+const ARTIFACT_ID: &str = "SPDXRef-Artifact";
+
+/// Creation Information
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct CreationInfo {
+    /// Creation time, as UTC timestamp like `2023-11-01T00:00:00Z`.
+    pub created: String,
+    pub creators: Vec<String>,
+}
+
+/// Checksum
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checksum {
+    pub algorithm: String,
+    pub checksum_value: String,
+}
+
+impl Checksum {
+    // Parse an osbuild checksum of the form `<algorithm>:<hex>`.
+    fn parse(s: &str) -> Option<Self> {
+        let (algorithm, value) = s.split_once(':')?;
+        let algorithm = match algorithm {
+            "md5" => "MD5",
+            "sha1" => "SHA1",
+            "sha256" => "SHA256",
+            "sha384" => "SHA384",
+            "sha512" => "SHA512",
+            _ => return None,
+        };
+        Some(Self {
+            algorithm: algorithm.to_owned(),
+            checksum_value: value.to_owned(),
+        })
+    }
+}
+
+/// External Reference
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalRef {
+    pub reference_category: String,
+    pub reference_type: String,
+    pub reference_locator: String,
+}
+
+impl ExternalRef {
+    fn purl(locator: String) -> Self {
+        Self {
+            reference_category: "PACKAGE-MANAGER".to_owned(),
+            reference_type: "purl".to_owned(),
+            reference_locator: locator,
+        }
+    }
+}
+
+/// SPDX Package
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Package {
+    #[serde(rename = "SPDXID")]
+    pub spdx_id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_info: Option<String>,
+    pub download_location: String,
+    #[serde(default)]
+    pub files_analyzed: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checksums: Vec<Checksum>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub external_refs: Vec<ExternalRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_package_purpose: Option<String>,
+}
+
+/// Relationship
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Relationship {
+    pub spdx_element_id: String,
+    pub relationship_type: String,
+    pub related_spdx_element: String,
+}
+
+/// SPDX Document
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    pub spdx_version: String,
+    pub data_license: String,
+    #[serde(rename = "SPDXID")]
+    pub spdx_id: String,
+    pub name: String,
+    pub document_namespace: String,
+    pub creation_info: CreationInfo,
+    #[serde(default)]
+    pub packages: Vec<Package>,
+    #[serde(default)]
+    pub relationships: Vec<Relationship>,
+}
+
+/// Document Options
+///
+/// The information of the document that cannot be derived from the
+/// manifest. The creation time is passed in, so documents are
+/// reproducible.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Options {
+    /// Name of the described artifact.
+    pub name: String,
+    /// Unique URI of the document.
+    pub namespace: String,
+    pub created: String,
+}
+
+// Create an SPDX identifier from arbitrary text.
+fn spdx_id(kind: &str, s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{}-{}", kind, s)
+}
+
+fn rpm_version(nevra: &Nevra) -> String {
+    match &nevra.epoch {
+        Some(e) if e != "0" => format!("{}:{}-{}", e, nevra.version, nevra.release),
+        _ => format!("{}-{}", nevra.version, nevra.release),
+    }
+}
+
+fn rpm_purl(nevra: &Nevra) -> String {
+    let mut purl = format!(
+        "pkg:rpm/{}@{}-{}?arch={}",
+        nevra.name, nevra.version, nevra.release, nevra.arch
+    );
+    if let Some(e) = nevra.epoch.as_ref().filter(|v| *v != "0") {
+        purl.push_str(&format!("&epoch={}", e));
+    }
+    purl
+}
+
+impl Document {
+    fn add(&mut self, package: Package, relationship: &str) {
+        if self.packages.iter().any(|v| v.spdx_id == package.spdx_id) {
+            return;
+        }
+        let (from, to) = match relationship {
+            "BUILD_TOOL_OF" => (package.spdx_id.clone(), ARTIFACT_ID.to_owned()),
+            _ => (ARTIFACT_ID.to_owned(), package.spdx_id.clone()),
+        };
+        self.relationships.push(Relationship {
+            spdx_element_id: from,
+            relationship_type: relationship.to_owned(),
+            related_spdx_element: to,
+        });
+        self.packages.push(package);
+    }
+
+    /// Enrich with Build Metadata
+    ///
+    /// Add the package metadata reported by osbuild for rpm stages. The
+    /// metadata is searched for `packages` lists of objects with `name`,
+    /// `version`, `release`, and `arch`, as reported in both the version-1
+    /// and version-2 build results. Packages already described are
+    /// completed, others are added as content of the artifact.
+    pub fn enrich(&mut self, result: &Json) {
+        let mut found = Vec::new();
+        collect_metadata(result, &mut found);
+
+        for v in found {
+            let nevra = Nevra {
+                name: v["name"].as_str().unwrap_or_default().to_owned(),
+                epoch: match &v["epoch"] {
+                    Json::Null => None,
+                    Json::String(v) => Some(v.clone()),
+                    v => Some(v.to_string()),
+                },
+                version: v["version"].as_str().unwrap_or_default().to_owned(),
+                release: v["release"].as_str().unwrap_or_default().to_owned(),
+                arch: v["arch"].as_str().unwrap_or_default().to_owned(),
+            };
+            let md5 = v
+                .get("sigmd5")
+                .and_then(Json::as_str)
+                .map(|v| Checksum::parse(&format!("md5:{}", v)));
+
+            let existing = self.packages.iter_mut().find(|p| {
+                p.external_refs.iter().any(|r| {
+                    r.reference_locator
+                        .starts_with(&format!("pkg:rpm/{}@", nevra.name))
+                }) && p.external_refs.iter().any(|r| {
+                    r.reference_locator
+                        .contains(&format!("arch={}", nevra.arch))
+                })
+            });
+            let package = match existing {
+                Some(v) => v,
+                None => {
+                    self.add(
+                        Package {
+                            spdx_id: spdx_id("RPM", &nevra.to_string()),
+                            name: nevra.name.clone(),
+                            download_location: NOASSERTION.to_owned(),
+                            ..Default::default()
+                        },
+                        "CONTAINS",
+                    );
+                    match self.packages.last_mut() {
+                        Some(v) => v,
+                        None => continue,
+                    }
+                }
+            };
+
+            package.version_info = Some(rpm_version(&nevra));
+            package.external_refs = vec![ExternalRef::purl(rpm_purl(&nevra))];
+            if let Some(Some(md5)) = md5 {
+                if !package.checksums.contains(&md5) {
+                    package.checksums.push(md5);
+                }
+            }
+        }
+    }
+}
+
+// Find package metadata entries in a build result.
+fn collect_metadata<'a>(value: &'a Json, acc: &mut Vec<&'a Json>) {
+    match value {
+        Json::Object(v) => {
+            if let Some(Json::Array(list)) = v.get("packages") {
+                acc.extend(list.iter().filter(|p| {
+                    ["name", "version", "release", "arch"]
+                        .iter()
+                        .all(|k| p.get(k).is_some_and(Json::is_string))
+                }));
+            }
+            v.values().for_each(|v| collect_metadata(v, acc));
+        }
+        Json::Array(v) => v.iter().for_each(|v| collect_metadata(v, acc)),
+        _ => {}
+    }
+}
+
+/// Generate SBOM from Manifest
+///
+/// Describe the artifact built by the manifest as SPDX document.
+pub fn from_manifest(manifest: &Manifest1, options: &Options) -> Document {
+    let mut doc = Document {
+        spdx_version: SPDX_VERSION.to_owned(),
+        data_license: "CC0-1.0".to_owned(),
+        spdx_id: "SPDXRef-DOCUMENT".to_owned(),
+        name: options.name.clone(),
+        document_namespace: options.namespace.clone(),
+        creation_info: CreationInfo {
+            created: options.created.clone(),
+            creators: vec![format!(
+                "Tool: {}-{}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )],
+        },
+        packages: vec![Package {
+            spdx_id: ARTIFACT_ID.to_owned(),
+            name: options.name.clone(),
+            download_location: NOASSERTION.to_owned(),
+            ..Default::default()
+        }],
+        relationships: vec![Relationship {
+            spdx_element_id: "SPDXRef-DOCUMENT".to_owned(),
+            relationship_type: "DESCRIBES".to_owned(),
+            related_spdx_element: ARTIFACT_ID.to_owned(),
+        }],
+    };
+
+    // Packages installed by rpm stages.
+    let rpms = packages::packages(manifest);
+    for p in &rpms {
+        let name = p.nevra.as_ref().map_or(p.checksum.as_str(), |v| &v.name);
+        let package = Package {
+            spdx_id: spdx_id("RPM", &p.checksum),
+            name: name.to_owned(),
+            version_info: p.nevra.as_ref().map(rpm_version),
+            download_location: p.url.clone().unwrap_or_else(|| NOASSERTION.to_owned()),
+            checksums: Checksum::parse(&p.checksum).into_iter().collect(),
+            external_refs: p
+                .nevra
+                .as_ref()
+                .map(|v| ExternalRef::purl(rpm_purl(v)))
+                .into_iter()
+                .collect(),
+            primary_package_purpose: Some("LIBRARY".to_owned()),
+            ..Default::default()
+        };
+        doc.add(package, if p.build { "BUILD_TOOL_OF" } else { "CONTAINS" });
+    }
+
+    // Container images.
+    let images = manifest
+        .sources
+        .get("org.osbuild.skopeo")
+        .and_then(|v| v.get("items"))
+        .and_then(Json::as_object);
+    for (id, item) in images.into_iter().flatten() {
+        let name = item
+            .pointer("/image/name")
+            .and_then(Json::as_str)
+            .unwrap_or(id);
+        let digest = item.pointer("/image/digest").and_then(Json::as_str);
+        let short = name.rsplit('/').next().unwrap_or(name);
+        let locator = match digest {
+            Some(d) => format!("pkg:oci/{}@{}?repository_url={}", short, d, name),
+            None => format!("pkg:oci/{}?repository_url={}", short, name),
+        };
+        doc.add(
+            Package {
+                spdx_id: spdx_id("Container", id),
+                name: name.to_owned(),
+                download_location: format!("docker://{}", name),
+                checksums: digest.and_then(Checksum::parse).into_iter().collect(),
+                external_refs: vec![ExternalRef::purl(locator)],
+                primary_package_purpose: Some("CONTAINER".to_owned()),
+                ..Default::default()
+            },
+            "CONTAINS",
+        );
+    }
+
+    // All other fetched files.
+    for source in ["org.osbuild.files", "org.osbuild.curl"] {
+        let urls = manifest.sources.get(source).and_then(|v| {
+            v.get("urls")
+                .or_else(|| v.get("items"))
+                .and_then(Json::as_object)
+        });
+        for (checksum, url) in urls.into_iter().flatten() {
+            if rpms.iter().any(|v| v.checksum == *checksum) {
+                continue;
+            }
+            let url = match url {
+                Json::String(v) => v.as_str(),
+                v => v.get("url").and_then(Json::as_str).unwrap_or(NOASSERTION),
+            };
+            doc.add(
+                Package {
+                    spdx_id: spdx_id("File", checksum),
+                    name: url.rsplit('/').next().unwrap_or(url).to_owned(),
+                    download_location: url.to_owned(),
+                    checksums: Checksum::parse(checksum).into_iter().collect(),
+                    primary_package_purpose: Some("FILE".to_owned()),
+                    ..Default::default()
+                },
+                "CONTAINS",
+            );
+        }
+    }
+
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify SBOM Generation
+    #[test]
+    fn verify_sbom() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": {
+                            "stages": [{ "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a"] } }]
+                        },
+                        "runner": "org.osbuild.fedora39"
+                    },
+                    "stages": [{ "name": "org.osbuild.rpm", "options": { "packages": ["sha256:b", "sha256:c"] } }]
+                },
+                "sources": {
+                    "org.osbuild.files": {
+                        "urls": {
+                            "sha256:a": "https://example.com/rpm-4.19.0-1.fc39.x86_64.rpm",
+                            "sha256:b": "https://example.com/bash-5.2.15-5.fc39.x86_64.rpm",
+                            "sha256:d": "https://example.com/firmware.bin"
+                        }
+                    },
+                    "org.osbuild.skopeo": {
+                        "items": {
+                            "sha256:id": { "image": { "name": "quay.io/org/app", "digest": "sha256:m" } }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let mut doc = from_manifest(
+            &manifest,
+            &Options {
+                name: "disk.qcow2".to_owned(),
+                namespace: "https://example.com/sbom/1".to_owned(),
+                created: "2023-11-01T00:00:00Z".to_owned(),
+            },
+        );
+
+        let names: Vec<_> = doc.packages.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "disk.qcow2",
+                "rpm",
+                "bash",
+                "sha256:c",
+                "quay.io/org/app",
+                "firmware.bin"
+            ]
+        );
+
+        let bash = &doc.packages[2];
+        assert_eq!(bash.version_info.as_deref(), Some("5.2.15-5.fc39"));
+        assert_eq!(bash.checksums[0].algorithm, "SHA256");
+        assert_eq! {
+            bash.external_refs[0].reference_locator,
+            "pkg:rpm/bash@5.2.15-5.fc39?arch=x86_64",
+        }
+        assert_eq! {
+            doc.relationships[1],
+            Relationship {
+                spdx_element_id: "SPDXRef-RPM-sha256-a".to_owned(),
+                relationship_type: "BUILD_TOOL_OF".to_owned(),
+                related_spdx_element: ARTIFACT_ID.to_owned(),
+            },
+        }
+
+        let json = serde_json::to_value(&doc).unwrap();
+        assert_eq!(json["SPDXID"], "SPDXRef-DOCUMENT");
+        assert_eq!(
+            json["packages"][4]["externalRefs"][0]["referenceLocator"],
+            "pkg:oci/app@sha256:m?repository_url=quay.io/org/app"
+        );
+
+        // Enrichment completes known packages and adds unknown ones.
+        doc.enrich(&serde_json::json!({
+            "success": true,
+            "stages": [{
+                "name": "org.osbuild.rpm",
+                "metadata": {
+                    "packages": [
+                        { "name": "bash", "epoch": null, "version": "5.2.15", "release": "5.fc39", "arch": "x86_64", "sigmd5": "ab" },
+                        { "name": "glibc", "epoch": "0", "version": "2.38", "release": "7.fc39", "arch": "x86_64" }
+                    ]
+                }
+            }]
+        }));
+        assert_eq!(doc.packages.len(), 7);
+        assert_eq!(doc.packages[2].checksums[1].algorithm, "MD5");
+        assert_eq!(doc.packages[6].name, "glibc");
+        assert_eq!(doc.packages[6].version_info.as_deref(), Some("2.38-7.fc39"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Pipeline Scheduling Analysis
+//!
+//! osbuild runs the pipelines of a manifest one after the other, but
+//! pipelines of version-2 manifests only depend on the pipelines they
+//! reference, so many of them could run in parallel. This module analyzes
+//! the dependency graph of a manifest: which pipelines could run in
+//! parallel, the independent subgraphs, and the critical path, which
+//! bounds the wall-clock time of a build with unlimited parallelism.
+//!
+//! Durations are not part of manifests, so they are estimated from hints,
+//! like durations measured in previous builds. The analysis is meant for
+//! capacity planning, it does not model resource contention.
+
+use crate::manifest::{Manifest2, Object, Pipeline2};
+use std::time::Duration;
+
+/// Duration Hints
+///
+/// Durations of stages, used in this order: by stage identifier, by stage
+/// type, or the default.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Hints {
+    pub ids: Object<Duration>,
+    pub types: Object<Duration>,
+    pub default: Duration,
+}
+
+impl Hints {
+    /// Duration of a Pipeline
+    ///
+    /// Return the sum of the durations of the stages of the pipeline.
+    pub fn pipeline(&self, pipeline: &Pipeline2) -> Duration {
+        pipeline
+            .stages
+            .iter()
+            .map(|v| {
+                v.id.as_ref()
+                    .and_then(|id| self.ids.get(id))
+                    .or_else(|| self.types.get(v.r#type.as_str()))
+                    .copied()
+                    .unwrap_or(self.default)
+            })
+            .sum()
+    }
+}
+
+/// Scheduled Pipeline
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Slot {
+    /// Name of the pipeline.
+    pub name: String,
+    /// Level of the pipeline: 0 if it has no dependencies, otherwise one
+    /// above the highest level of its dependencies.
+    pub level: usize,
+    /// Estimated duration of the pipeline.
+    pub duration: Duration,
+    /// Earliest start, once all dependencies finished.
+    pub start: Duration,
+}
+
+impl Slot {
+    /// Earliest Finish
+    pub fn finish(&self) -> Duration {
+        self.start + self.duration
+    }
+}
+
+/// Schedule Analysis
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Schedule {
+    /// Pipelines in topological order.
+    pub slots: Vec<Slot>,
+    /// Pipelines by level. Pipelines of a level only depend on pipelines
+    /// of lower levels, so each level can run in parallel.
+    pub levels: Vec<Vec<String>>,
+    /// Independent subgraphs of pipelines, which share no dependencies.
+    pub components: Vec<Vec<String>>,
+    /// The longest chain of dependent pipelines, by duration, first to
+    /// last.
+    pub critical_path: Vec<String>,
+    /// Estimated wall-clock time with unlimited parallelism.
+    pub wall_clock: Duration,
+    /// Estimated time of sequential execution.
+    pub sequential: Duration,
+}
+
+impl Schedule {
+    /// Maximum Parallelism
+    ///
+    /// Return the number of pipelines of the widest level, which is the
+    /// number of workers that can be used at the same time.
+    pub fn parallelism(&self) -> usize {
+        self.levels.iter().map(Vec::len).max().unwrap_or(0)
+    }
+}
+
+// Find the representative of the component of the pipeline.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Analyze Schedule
+///
+/// Analyze the dependency graph of the pipelines of the manifest, with
+/// durations estimated from the hints. References to unknown pipelines
+/// are ignored. Returns `None` if the dependencies are cyclic.
+pub fn schedule(manifest: &Manifest2, hints: &Hints) -> Option<Schedule> {
+    let order: Vec<&Pipeline2> = manifest.iter_pipelines_topological()?.collect();
+    let index = |name: &str| order.iter().position(|v| v.name == name);
+    let deps: Vec<Vec<usize>> = order
+        .iter()
+        .map(|v| v.dependencies().into_iter().filter_map(index).collect())
+        .collect();
+
+    let mut acc = Schedule::default();
+    let mut parents: Vec<usize> = (0..order.len()).collect();
+    for (i, pipeline) in order.iter().enumerate() {
+        let level = deps[i]
+            .iter()
+            .map(|&d| acc.slots[d].level + 1)
+            .max()
+            .unwrap_or(0);
+        let start = deps[i]
+            .iter()
+            .map(|&d| acc.slots[d].finish())
+            .max()
+            .unwrap_or_default();
+        for &d in &deps[i] {
+            let (a, b) = (root(&mut parents, i), root(&mut parents, d));
+            parents[a.max(b)] = a.min(b);
+        }
+
+        if acc.levels.len() <= level {
+            acc.levels.push(Vec::new());
+        }
+        acc.levels[level].push(pipeline.name.clone());
+        acc.slots.push(Slot {
+            name: pipeline.name.clone(),
+            level,
+            duration: hints.pipeline(pipeline),
+            start,
+        });
+    }
+
+    let mut components: Vec<(usize, Vec<String>)> = Vec::new();
+    for (i, slot) in acc.slots.iter().enumerate() {
+        let r = root(&mut parents, i);
+        match components.iter_mut().find(|v| v.0 == r) {
+            Some(v) => v.1.push(slot.name.clone()),
+            None => components.push((r, vec![slot.name.clone()])),
+        }
+    }
+    acc.components = components.into_iter().map(|v| v.1).collect();
+
+    // Walk back from the last pipeline to finish, along the dependencies
+    // finishing last. The first of equal candidates is taken.
+    let last = acc
+        .slots
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, v)| v.finish())
+        .map(|v| v.0);
+    let mut next = last;
+    while let Some(i) = next {
+        acc.critical_path.push(acc.slots[i].name.clone());
+        next = deps[i]
+            .iter()
+            .rev()
+            .max_by_key(|&&d| acc.slots[d].finish())
+            .copied();
+    }
+    acc.critical_path.reverse();
+
+    acc.wall_clock = last.map(|i| acc.slots[i].finish()).unwrap_or_default();
+    acc.sequential = acc.slots.iter().map(|v| v.duration).sum();
+    Some(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Schedule Analysis
+    #[test]
+    fn verify_schedule() {
+        let manifest: Manifest2 = serde_json::from_str(
+            r#"{
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{ "type": "org.osbuild.rpm" }] },
+                    { "name": "os", "build": "name:build", "stages": [
+                        { "type": "org.osbuild.rpm" },
+                        { "type": "org.osbuild.selinux", "id": "label" }
+                    ] },
+                    { "name": "image", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.copy",
+                        "inputs": { "tree": {
+                            "type": "org.osbuild.tree",
+                            "origin": "org.osbuild.pipeline",
+                            "references": ["name:os"]
+                        } }
+                    }] },
+                    { "name": "docs", "build": "name:build", "stages": [{ "type": "org.osbuild.noop" }] },
+                    { "name": "other", "stages": [{ "type": "org.osbuild.noop" }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let hints = Hints {
+            ids: [("label".to_owned(), Duration::from_secs(30))].into(),
+            types: [("org.osbuild.rpm".to_owned(), Duration::from_secs(60))].into(),
+            default: Duration::from_secs(1),
+        };
+
+        let schedule = schedule(&manifest, &hints).unwrap();
+        assert_eq! {
+            schedule.levels,
+            vec![vec!["build", "other"], vec!["os", "docs"], vec!["image"]],
+        }
+        assert_eq! {
+            schedule.components,
+            vec![vec!["build", "os", "image", "docs"], vec!["other"]],
+        }
+        assert_eq!(schedule.critical_path, vec!["build", "os", "image"]);
+        assert_eq!(schedule.wall_clock, Duration::from_secs(151));
+        assert_eq!(schedule.sequential, Duration::from_secs(153));
+        assert_eq!(schedule.parallelism(), 2);
+        assert_eq!(schedule.slots[3].start, Duration::from_secs(60));
+
+        let mut cyclic = manifest.clone();
+        cyclic.pipelines[0].build = Some("name:image".to_owned());
+        assert!(super::schedule(&cyclic, &hints).is_none());
+        assert_eq!(
+            super::schedule(&Manifest2::default(), &hints),
+            Some(Schedule::default())
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! JSON Schemas
+//!
+//! This module generates JSON Schema documents (draft 2020-12) describing
+//! the manifest formats exactly as this crate accepts them. Other tooling
+//! can validate manifests against these schemas without reimplementing the
+//! parser rules. Options of stages, inputs, and sources are opaque to this
+//! crate, so the schemas only require them to be objects.
+
+use crate::manifest::Json;
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+// Closed object with the given properties, of which `required` must be
+// present.
+fn object(properties: Json, required: &[&str]) -> Json {
+    let mut acc = serde_json::json!({
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    });
+    if !required.is_empty() {
+        acc["required"] = serde_json::json!(required);
+    }
+    acc
+}
+
+// Open object with arbitrary members of the given schema.
+fn map(values: Json) -> Json {
+    serde_json::json!({ "type": "object", "additionalProperties": values })
+}
+
+fn reference(name: &str) -> Json {
+    serde_json::json!({ "$ref": format!("#/$defs/{}", name) })
+}
+
+fn nullable(schema: Json) -> Json {
+    serde_json::json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+fn string() -> Json {
+    serde_json::json!({ "type": "string" })
+}
+
+fn document(title: &str, root: Json, defs: Json) -> Json {
+    let mut acc = serde_json::json!({
+        "$schema": DRAFT,
+        "title": title,
+    });
+    for (k, v) in root.as_object().into_iter().flatten() {
+        acc[k] = v.clone();
+    }
+    acc["$defs"] = defs;
+    acc
+}
+
+/// Manifest Version 1 Schema
+///
+/// Return the schema of version-1 manifests, as represented by
+/// `manifest::Manifest1`. Absent assemblers and build pipelines are
+/// serialized as `null` by this crate, hence the schema allows it.
+pub fn manifest_v1() -> Json {
+    let stage = object(
+        serde_json::json!({
+            "name": string(),
+            "options": map(true.into()),
+        }),
+        &["name"],
+    );
+
+    document(
+        "osbuild manifest, version 1",
+        object(
+            serde_json::json!({
+                "pipeline": reference("pipeline"),
+                "sources": reference("sources"),
+            }),
+            &[],
+        ),
+        serde_json::json!({
+            "pipeline": object(
+                serde_json::json!({
+                    "assembler": nullable(reference("assembler")),
+                    "build": nullable(reference("build")),
+                    "stages": { "type": "array", "items": reference("stage") },
+                }),
+                &[],
+            ),
+            "build": object(
+                serde_json::json!({
+                    "pipeline": reference("pipeline"),
+                    "runner": string(),
+                }),
+                &["pipeline", "runner"],
+            ),
+            "stage": stage,
+            "assembler": stage,
+            "sources": map(map(true.into())),
+        }),
+    )
+}
+
+/// Manifest Version 2 Schema
+///
+/// Return the schema of version-2 manifests, as produced and consumed by
+/// the `convert` module.
+pub fn manifest_v2() -> Json {
+    let references = serde_json::json!({
+        "anyOf": [
+            { "type": "array", "items": string() },
+            map(map(true.into())),
+            {
+                "type": "array",
+                "items": object(
+                    serde_json::json!({ "id": string(), "options": map(true.into()) }),
+                    &["id"],
+                ),
+            },
+        ],
+    });
+
+    document(
+        "osbuild manifest, version 2",
+        object(
+            serde_json::json!({
+                "version": { "const": "2" },
+                "pipelines": { "type": "array", "items": reference("pipeline") },
+                "sources": map(reference("source")),
+            }),
+            &["version"],
+        ),
+        serde_json::json!({
+            "pipeline": object(
+                serde_json::json!({
+                    "name": string(),
+                    "build": string(),
+                    "runner": string(),
+                    "source-epoch": { "type": "integer", "minimum": 0 },
+                    "stages": { "type": "array", "items": reference("stage") },
+                }),
+                &["name"],
+            ),
+            "stage": object(
+                serde_json::json!({
+                    "type": string(),
+                    "id": string(),
+                    "options": map(true.into()),
+                    "inputs": map(reference("input")),
+                    "devices": map(reference("device")),
+                    "mounts": { "type": "array", "items": reference("mount") },
+                }),
+                &["type"],
+            ),
+            "input": object(
+                serde_json::json!({
+                    "type": string(),
+                    "origin": { "enum": ["org.osbuild.source", "org.osbuild.pipeline"] },
+                    "references": references,
+                    "options": map(true.into()),
+                }),
+                &["type", "origin", "references"],
+            ),
+            "device": object(
+                serde_json::json!({
+                    "type": string(),
+                    "parent": string(),
+                    "options": map(true.into()),
+                }),
+                &["type"],
+            ),
+            "mount": object(
+                serde_json::json!({
+                    "name": string(),
+                    "type": string(),
+                    "source": string(),
+                    "target": string(),
+                    "partition": { "type": "integer" },
+                    "options": map(true.into()),
+                }),
+                &["name", "type"],
+            ),
+            "source": object(
+                serde_json::json!({
+                    "items": map(true.into()),
+                    "options": map(true.into()),
+                }),
+                &["items"],
+            ),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert;
+    use crate::manifest::Manifest1;
+
+    // Check a value against the subset of JSON Schema used by this module.
+    fn conforms(root: &Json, schema: &Json, value: &Json) -> bool {
+        if schema == &Json::Bool(true) {
+            return true;
+        }
+        if let Some(r) = schema.get("$ref").and_then(Json::as_str) {
+            let name = r.trim_start_matches("#/$defs/");
+            return conforms(root, &root["$defs"][name], value);
+        }
+        if let Some(v) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+            let n = v
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|v| conforms(root, v, value))
+                .count();
+            return if schema.get("oneOf").is_some() {
+                n == 1
+            } else {
+                n > 0
+            };
+        }
+        if let Some(v) = schema.get("const") {
+            return v == value;
+        }
+        if let Some(v) = schema.get("enum") {
+            return v.as_array().unwrap().contains(value);
+        }
+        match (schema["type"].as_str(), value) {
+            (Some("null"), Json::Null) => true,
+            (Some("string"), Json::String(_)) => true,
+            (Some("integer"), Json::Number(v)) => v.is_u64() || v.is_i64(),
+            (Some("array"), Json::Array(v)) => {
+                v.iter().all(|v| conforms(root, &schema["items"], v))
+            }
+            (Some("object"), Json::Object(v)) => {
+                let required = schema["required"].as_array().into_iter().flatten();
+                required
+                    .clone()
+                    .all(|k| v.contains_key(k.as_str().unwrap()))
+                    && v.iter().all(|(k, v)| match schema["properties"].get(k) {
+                        Some(s) => conforms(root, s, v),
+                        None => match &schema["additionalProperties"] {
+                            Json::Bool(b) => *b,
+                            s => conforms(root, s, v),
+                        },
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    // Verify Manifest Schemas
+    #[test]
+    fn verify_schema() {
+        let manifest: Manifest1 = serde_json::from_str(
+            r#"{
+                "pipeline": {
+                    "build": {
+                        "pipeline": { "stages": [{ "name": "org.osbuild.rpm", "options": { "packages": ["sha256:a"] } }] },
+                        "runner": "org.osbuild.linux"
+                    },
+                    "stages": [{ "name": "org.osbuild.locale", "options": { "language": "en_US" } }],
+                    "assembler": { "name": "org.osbuild.tar", "options": { "filename": "root.tar" } }
+                },
+                "sources": { "org.osbuild.files": { "urls": { "sha256:a": "https://example.com/a" } } }
+            }"#,
+        )
+        .unwrap();
+
+        let v1 = manifest_v1();
+        assert_eq!(v1["$schema"], DRAFT);
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert!(conforms(&v1, &v1, &json));
+        let json = serde_json::to_value(Manifest1::default()).unwrap();
+        assert!(conforms(&v1, &v1, &json));
+        assert!(!conforms(&v1, &v1, &serde_json::json!({ "pipelines": [] })));
+        assert!(!conforms(
+            &v1,
+            &v1,
+            &serde_json::json!({ "pipeline": { "stages": [{}] } })
+        ));
+
+        let v2 = manifest_v2();
+        assert!(conforms(&v2, &v2, &convert::to_v2(&manifest)));
+        assert!(!conforms(&v2, &v2, &serde_json::json!({ "version": "1" })));
+        assert!(!conforms(
+            &v2,
+            &v2,
+            &serde_json::json!({ "version": "2", "pipelines": [{}] })
+        ));
+    }
+}
@@ -0,0 +1,422 @@
+//! Configurable Serialization
+//!
+//! Manifests end up in repositories and APIs with their own conventions
+//! for JSON text. This module serializes values either compactly or with
+//! one member or element per line and a chosen indentation, optionally
+//! followed by a newline, and optionally escaping all non-ASCII characters.
+//!
+//! Members are written in the order of the serialized value by default.
+//! Sources, pipelines, and stage options can be sorted instead, for
+//! consumers sensitive to ordering, like diff-based review. Objects of the
+//! typed manifests are always ordered by key, so this mostly matters for
+//! generic JSON values like documents.
+//!
+//! `SerializeOptions::stable()` combines these for manifests kept under
+//! version control: all members are sorted, every element is on its own
+//! line, and top-level sections are separated by blank lines, so
+//! regenerated manifests yield small, reviewable diffs.
+
+use crate::error::Error;
+use crate::manifest::Json;
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+use std::io::{self, Write};
+
+/// Member Ordering
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Order {
+    /// Keep the order of the serialized value.
+    #[default]
+    Insertion,
+    /// Sort by key, or by name for pipelines.
+    Sorted,
+}
+
+/// Serialization Options
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SerializeOptions {
+    /// Indentation of a nesting level, or `None` for compact output.
+    pub indent: Option<String>,
+    /// Whether to end the output with a newline.
+    pub newline: bool,
+    /// Whether to escape all non-ASCII characters as `\uXXXX`.
+    pub ascii: bool,
+    /// Order of source types and their items.
+    pub sources: Order,
+    /// Order of version-2 pipelines. Note that osbuild runs pipelines in
+    /// the order they are listed, so sorting them is meant for review.
+    pub pipelines: Order,
+    /// Order of stage option members, at all nesting levels.
+    pub options: Order,
+    /// Order of all object members, at all nesting levels. This includes
+    /// sources and stage options, but not pipelines.
+    pub keys: Order,
+    /// Whether to separate top-level members, and the elements and members
+    /// nested directly within them, by blank lines. This only applies to
+    /// output with indentation.
+    pub separators: bool,
+}
+
+impl SerializeOptions {
+    /// Compact Output
+    pub fn compact() -> Self {
+        Self::default()
+    }
+
+    /// Pretty Output
+    ///
+    /// Output with the given indentation, followed by a newline.
+    pub fn pretty(indent: &str) -> Self {
+        Self {
+            indent: Some(indent.to_owned()),
+            newline: true,
+            ..Default::default()
+        }
+    }
+
+    /// Stable Output
+    ///
+    /// Output for version control, with two spaces of indentation, sorted
+    /// members, and section separators. Pipelines keep their order, since
+    /// it is significant to osbuild.
+    pub fn stable() -> Self {
+        Self {
+            sources: Order::Sorted,
+            options: Order::Sorted,
+            keys: Order::Sorted,
+            separators: true,
+            ..Self::pretty("  ")
+        }
+    }
+}
+
+// Formatter that delegates to `F`, optionally escaping non-ASCII text and
+// separating the outer levels of nesting by blank lines.
+struct Escape<F> {
+    inner: F,
+    ascii: bool,
+    separators: bool,
+    depth: usize,
+}
+
+impl<F> Escape<F> {
+    // Write a separator before a value that is not the first of its
+    // container, and return whether the value is then to be written like
+    // the first one.
+    fn separate<W: ?Sized + Write>(&self, w: &mut W, first: bool) -> io::Result<bool> {
+        if self.separators && !first && self.depth <= 2 {
+            w.write_all(b",\n")?;
+            return Ok(true);
+        }
+        Ok(first)
+    }
+}
+
+impl<F: Formatter> Formatter for Escape<F> {
+    fn write_string_fragment<W: ?Sized + Write>(&mut self, w: &mut W, s: &str) -> io::Result<()> {
+        if !self.ascii {
+            return self.inner.write_string_fragment(w, s);
+        }
+        let mut units = [0; 2];
+        for c in s.chars() {
+            if c.is_ascii() {
+                w.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut units) {
+                    write!(w, "\\u{:04x}", unit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.depth += 1;
+        self.inner.begin_array(w)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.depth -= 1;
+        self.inner.end_array(w)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(&mut self, w: &mut W, first: bool) -> io::Result<()> {
+        let first = self.separate(w, first)?;
+        self.inner.begin_array_value(w, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.inner.end_array_value(w)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.depth += 1;
+        self.inner.begin_object(w)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.depth -= 1;
+        self.inner.end_object(w)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(&mut self, w: &mut W, first: bool) -> io::Result<()> {
+        let first = self.separate(w, first)?;
+        self.inner.begin_object_key(w, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.inner.begin_object_value(w)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<()> {
+        self.inner.end_object_value(w)
+    }
+}
+
+// Sort the options of the stages of the given array.
+fn sort_stages(stages: Option<&mut Json>) {
+    for stage in stages.and_then(Json::as_array_mut).into_iter().flatten() {
+        if let Some(v) = stage.get_mut("options") {
+            v.sort_all_objects();
+        }
+    }
+}
+
+// Sort the options of the stages of a version-1 pipeline, including its
+// build pipelines.
+fn sort_pipeline1(mut pipeline: Option<&mut Json>) {
+    while let Some(v) = pipeline {
+        sort_stages(v.get_mut("stages"));
+        if let Some(v) = v.pointer_mut("/assembler/options") {
+            v.sort_all_objects();
+        }
+        pipeline = v.pointer_mut("/build/pipeline");
+    }
+}
+
+// Reorder the members of a manifest as described by the options.
+fn reorder(manifest: &mut Json, options: &SerializeOptions) {
+    if options.keys == Order::Sorted {
+        manifest.sort_all_objects();
+    }
+
+    if options.sources == Order::Sorted {
+        if let Some(Json::Object(sources)) = manifest.get_mut("sources") {
+            sources.sort_keys();
+            for items in sources.values_mut().filter_map(|v| v.as_object_mut()) {
+                for v in items.values_mut().filter_map(|v| v.as_object_mut()) {
+                    v.sort_keys();
+                }
+            }
+        }
+    }
+
+    if let Some(Json::Array(pipelines)) = manifest.get_mut("pipelines") {
+        if options.pipelines == Order::Sorted {
+            pipelines.sort_by(|a, b| {
+                let name = |v: &Json| v.get("name").and_then(Json::as_str).map(str::to_owned);
+                name(a).cmp(&name(b))
+            });
+        }
+        if options.options == Order::Sorted {
+            for v in pipelines {
+                sort_stages(v.get_mut("stages"));
+            }
+        }
+    } else if options.options == Order::Sorted {
+        sort_pipeline1(manifest.get_mut("pipeline"));
+    }
+}
+
+fn write<T, F>(
+    value: &T,
+    inner: F,
+    ascii: bool,
+    separators: bool,
+) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: serde::Serialize + ?Sized,
+    F: Formatter,
+{
+    let mut acc = Vec::new();
+    let formatter = Escape {
+        inner,
+        ascii,
+        separators,
+        depth: 0,
+    };
+    value.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut acc, formatter,
+    ))?;
+    Ok(acc)
+}
+
+fn write_with<T>(value: &T, options: &SerializeOptions) -> Result<Vec<u8>, serde_json::Error>
+where
+    T: serde::Serialize + ?Sized,
+{
+    match options.indent {
+        None => write(value, CompactFormatter, options.ascii, false),
+        Some(ref indent) => {
+            let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+            write(value, formatter, options.ascii, options.separators)
+        }
+    }
+}
+
+/// Serialize with Options
+///
+/// Serialize the value to JSON text as described by the options. The
+/// ordering options apply if the value serializes as manifest of either
+/// format version.
+pub fn to_string_with<T>(value: &T, options: &SerializeOptions) -> Result<String, Error>
+where
+    T: serde::Serialize + ?Sized,
+{
+    let sorted = [
+        options.sources,
+        options.pipelines,
+        options.options,
+        options.keys,
+    ]
+    .contains(&Order::Sorted);
+    let mut acc = if sorted {
+        let mut value = serde_json::to_value(value)?;
+        reorder(&mut value, options);
+        write_with(&value, options)?
+    } else {
+        write_with(value, options)?
+    };
+    if options.newline {
+        acc.push(b'\n');
+    }
+    Ok(String::from_utf8(acc).expect("JSON text is always valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Serialization Options
+    #[test]
+    fn verify_serialize() {
+        let value = serde_json::json!({ "name": "grüß 😀", "list": [1, {}] });
+
+        assert_eq! {
+            to_string_with(&value, &SerializeOptions::compact()).unwrap(),
+            r#"{"name":"grüß 😀","list":[1,{}]}"#,
+        }
+        assert_eq! {
+            to_string_with(&value, &SerializeOptions::pretty("\t")).unwrap(),
+            "{\n\t\"name\": \"grüß 😀\",\n\t\"list\": [\n\t\t1,\n\t\t{}\n\t]\n}\n",
+        }
+
+        let options = SerializeOptions {
+            ascii: true,
+            ..Default::default()
+        };
+        let text = to_string_with(&value, &options).unwrap();
+        assert_eq! {
+            text,
+            r#"{"name":"gr\u00fc\u00df \ud83d\ude00","list":[1,{}]}"#,
+        }
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            value
+        );
+    }
+
+    // Verify Member Ordering
+    #[test]
+    fn verify_order() {
+        let value = serde_json::json!({
+            "version": "2",
+            "pipelines": [
+                { "name": "os", "stages": [{ "type": "x", "options": { "b": { "d": 0, "c": 0 }, "a": 0 } }] },
+                { "name": "build" },
+            ],
+            "sources": { "org.osbuild.inline": {}, "org.osbuild.curl": { "items": { "b": "", "a": "" } } },
+        });
+
+        let text = to_string_with(&value, &Default::default()).unwrap();
+        assert_eq!(text, value.to_string());
+
+        let options = SerializeOptions {
+            sources: Order::Sorted,
+            pipelines: Order::Sorted,
+            options: Order::Sorted,
+            ..Default::default()
+        };
+        assert_eq! {
+            to_string_with(&value, &options).unwrap(),
+            concat!(
+                r#"{"version":"2","pipelines":[{"name":"build"},"#,
+                r#"{"name":"os","stages":[{"type":"x","options":{"a":0,"b":{"c":0,"d":0}}}]}],"#,
+                r#""sources":{"org.osbuild.curl":{"items":{"a":"","b":""}},"org.osbuild.inline":{}}}"#,
+            ),
+        }
+
+        let value = serde_json::json!({
+            "pipeline": { "build": { "pipeline": { "stages": [{ "name": "x", "options": { "b": 0, "a": 0 } }] } } },
+        });
+        assert_eq! {
+            to_string_with(&value, &options).unwrap(),
+            r#"{"pipeline":{"build":{"pipeline":{"stages":[{"name":"x","options":{"a":0,"b":0}}]}}}}"#,
+        }
+    }
+    // Verify Stable Output
+    #[test]
+    fn verify_stable() {
+        let value = serde_json::json!({
+            "version": "2",
+            "pipelines": [
+                { "name": "os", "stages": [{ "type": "x", "options": { "b": [1, 2], "a": 0 } }] },
+                { "name": "build" },
+            ],
+            "sources": { "org.osbuild.inline": {}, "org.osbuild.curl": {} },
+        });
+
+        let text = to_string_with(&value, &SerializeOptions::stable()).unwrap();
+        assert_eq! {
+            text,
+            r#"{
+  "pipelines": [
+    {
+      "name": "os",
+      "stages": [
+        {
+          "options": {
+            "a": 0,
+            "b": [
+              1,
+              2
+            ]
+          },
+          "type": "x"
+        }
+      ]
+    },
+
+    {
+      "name": "build"
+    }
+  ],
+
+  "sources": {
+    "org.osbuild.curl": {},
+
+    "org.osbuild.inline": {}
+  },
+
+  "version": "2"
+}
+"#,
+        }
+        assert_eq!(serde_json::from_str::<Json>(&text).unwrap(), value);
+
+        let options = SerializeOptions {
+            separators: true,
+            ..SerializeOptions::compact()
+        };
+        assert_eq!(to_string_with(&value, &options).unwrap(), value.to_string());
+    }
+}
@@ -0,0 +1,364 @@
+//! Manifest Service
+//!
+//! This module implements the operations of the manifest service described
+//! by `proto/manifest.proto`: parsing, validation, diffing, and stage
+//! identifiers. The operations are independent of any transport, taking
+//! and returning plain message types that mirror the protobuf messages, so
+//! RPC or HTTP frontends only need to map their wire format onto them.
+//!
+//! Manifests are passed as JSON text in either format version, and are
+//! processed in their own version. Invalid manifests are not treated as
+//! failure of the operation, but reported in the `error` field of the
+//! response.
+//!
+//! No RPC server is provided. The crate has no dependency on an RPC
+//! framework, so serving the protocol is left to the embedding service.
+
+use crate::diff;
+use crate::error;
+use crate::inspect;
+use crate::limits::Limits;
+use crate::lint::{self, Diagnostic, Severity};
+use crate::manifest::Manifest;
+use crate::normalize;
+
+/// Parse Failure
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Error {
+    pub message: String,
+    /// 1-based line of the failure, or 0 if unknown.
+    pub line: usize,
+    /// 1-based column of the failure, or 0 if unknown.
+    pub column: usize,
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(v: serde_json::Error) -> Self {
+        Self {
+            message: v.to_string(),
+            line: v.line(),
+            column: v.column(),
+        }
+    }
+}
+
+impl From<error::Error> for Error {
+    fn from(v: error::Error) -> Self {
+        match v {
+            error::Error::Parse {
+                line,
+                column,
+                message,
+                ..
+            } => Self {
+                message,
+                line,
+                column,
+            },
+            v => Self {
+                message: v.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Error {
+    fn new(message: &str) -> Self {
+        Self {
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parse Manifest
+///
+/// Parse a manifest of either format version. Manifests must stay within
+/// the default resource limits.
+pub fn parse(text: &str) -> Result<Manifest, Error> {
+    Ok(Limits::default().parse(text)?)
+}
+
+/// Parse Request
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ParseRequest {
+    pub manifest: String,
+}
+
+/// Parse Response
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ParseResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    /// Normalized form of the manifest, in its own version, as JSON text.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub normalized: String,
+}
+
+/// Validate Request
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ValidateRequest {
+    pub manifest: String,
+}
+
+/// Validate Response
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ValidateResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    /// Whether the manifest parsed and has no error diagnostics.
+    pub valid: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Diff Request
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DiffRequest {
+    pub old: String,
+    pub new: String,
+}
+
+/// Structural Change
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Change {
+    /// One of `added`, `removed`, or `changed`.
+    pub kind: String,
+    pub path: String,
+    /// Old value as JSON text, empty for additions.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub old: String,
+    /// New value as JSON text, empty for removals.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub new: String,
+}
+
+impl From<diff::Change> for Change {
+    fn from(v: diff::Change) -> Self {
+        let (kind, path, old, new) = match v {
+            diff::Change::Added(p, v) => ("added", p, String::new(), v.to_string()),
+            diff::Change::Removed(p, v) => ("removed", p, v.to_string(), String::new()),
+            diff::Change::Changed(p, a, b) => ("changed", p, a.to_string(), b.to_string()),
+        };
+        Self {
+            kind: kind.to_owned(),
+            path,
+            old,
+            new,
+        }
+    }
+}
+
+/// Diff Response
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DiffResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+}
+
+/// Identifier Request
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct IdsRequest {
+    pub manifest: String,
+}
+
+/// Stage Identifier
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct StageId {
+    /// JSON pointer of the stage.
+    pub path: String,
+    pub id: String,
+}
+
+/// Identifier Response
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct IdsResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<StageId>,
+}
+
+/// Parse Operation
+pub fn parse_manifest(request: &ParseRequest) -> ParseResponse {
+    match parse(&request.manifest) {
+        Err(e) => ParseResponse {
+            error: Some(e),
+            ..Default::default()
+        },
+        Ok(v) => ParseResponse {
+            normalized: match v {
+                Manifest::V1(v) => normalize::normalize(&v).to_string(),
+                Manifest::V2(v) => normalize::normalize2(&v).to_string(),
+            },
+            ..Default::default()
+        },
+    }
+}
+
+/// Validate Operation
+pub fn validate(request: &ValidateRequest) -> ValidateResponse {
+    match parse(&request.manifest) {
+        Err(e) => ValidateResponse {
+            error: Some(e),
+            ..Default::default()
+        },
+        Ok(v) => {
+            let diagnostics = lint::check(&v);
+            ValidateResponse {
+                error: None,
+                valid: !diagnostics.iter().any(|v| v.severity == Severity::Error),
+                diagnostics,
+            }
+        }
+    }
+}
+
+/// Diff Operation
+pub fn diff(request: &DiffRequest) -> DiffResponse {
+    match (parse(&request.old), parse(&request.new)) {
+        (Err(e), _) | (_, Err(e)) => DiffResponse {
+            error: Some(e),
+            ..Default::default()
+        },
+        (Ok(old), Ok(new)) => match diff::manifests_any(&old, &new, &[]) {
+            None => DiffResponse {
+                error: Some(Error::new("manifests are of different versions")),
+                ..Default::default()
+            },
+            Some(changes) => DiffResponse {
+                error: None,
+                changes: changes.into_iter().map(Change::from).collect(),
+            },
+        },
+    }
+}
+
+/// Identifier Operation
+pub fn ids(request: &IdsRequest) -> IdsResponse {
+    match parse(&request.manifest) {
+        Err(e) => IdsResponse {
+            error: Some(e),
+            ..Default::default()
+        },
+        Ok(v) => IdsResponse {
+            error: None,
+            stages: match v {
+                Manifest::V1(v) => inspect::ids(&v),
+                Manifest::V2(v) => inspect::ids2(&v),
+            }
+            .into_iter()
+            .map(|(path, id)| StageId {
+                path,
+                id: id.to_string(),
+            })
+            .collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Service Operations
+    #[test]
+    fn verify_service() {
+        let manifest =
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.noop" }] } }"#.to_owned();
+
+        let r = parse_manifest(&ParseRequest {
+            manifest: manifest.clone(),
+        });
+        assert!(r.error.is_none());
+        assert!(r.normalized.contains("org.osbuild.noop"));
+
+        let r = validate(&ValidateRequest {
+            manifest: "{\n \"pipelines\": {} }".to_owned(),
+        });
+        assert!(!r.valid);
+        assert_eq!(r.error.unwrap().line, 2);
+
+        let r = validate(&ValidateRequest {
+            manifest: manifest.clone(),
+        });
+        assert!(r.valid && r.error.is_none());
+
+        let r = diff(&DiffRequest {
+            old: manifest.clone(),
+            new: r#"{ "pipeline": {} }"#.to_owned(),
+        });
+        assert_eq! {
+            r.changes,
+            vec![Change {
+                kind: "removed".to_owned(),
+                path: "/pipeline".to_owned(),
+                old: r#"{"stages":[{"name":"org.osbuild.noop"}]}"#.to_owned(),
+                new: String::new(),
+            }],
+        }
+
+        let r = ids(&IdsRequest {
+            manifest: manifest.clone(),
+        });
+        assert_eq!(r.stages.len(), 1);
+        assert_eq!(r.stages[0].path, "/pipeline/stages/0");
+        assert_eq!(r.stages[0].id.len(), 64);
+
+        let r = ids(&IdsRequest {
+            manifest: r#"{ "version": "3" }"#.to_owned(),
+        });
+        assert!(r.error.is_some());
+
+        // Version-2 manifests are used as is, even if they cannot be
+        // converted to version 1.
+        let v2 = r#"{
+            "version": "2",
+            "pipelines": [{ "name": "image", "stages": [{
+                "type": "org.osbuild.mkfs.ext4",
+                "options": { "uuid": "x" },
+                "devices": { "device": { "type": "org.osbuild.loopback" } }
+            }] }]
+        }"#;
+        let r = parse_manifest(&ParseRequest {
+            manifest: v2.to_owned(),
+        });
+        assert!(r.error.is_none());
+        assert!(r.normalized.contains("devices"));
+        let r = validate(&ValidateRequest {
+            manifest: v2.to_owned(),
+        });
+        assert!(r.error.is_none() && !r.valid);
+        assert_eq!(r.diagnostics[0].path, "/pipelines/0/stages/0/options/uuid");
+        let r = diff(&DiffRequest {
+            old: v2.to_owned(),
+            new: v2.replace("\"x\"", "\"y\""),
+        });
+        assert_eq!(r.changes.len(), 1);
+        assert!(diff(&DiffRequest {
+            old: v2.to_owned(),
+            new: manifest.clone(),
+        })
+        .error
+        .is_some());
+        let r = ids(&IdsRequest {
+            manifest: v2.to_owned(),
+        });
+        assert!(r.error.is_none());
+        assert_eq!(r.stages[0].path, "/pipelines/0/stages/0");
+    }
+}
@@ -0,0 +1,162 @@
+//! Artifact Sidecars
+//!
+//! Published artifacts are usually accompanied by sidecar files listing
+//! their checksums, so downloads can be verified. This module computes the
+//! metadata of exported artifacts in a single pass over each file, and
+//! serializes it as the common sidecar formats: `CHECKSUM` files in the
+//! BSD tag format used by Fedora, `SHA256SUMS` files in the coreutils
+//! format, and JSON. Publishing pipelines can then use the metadata rather
+//! than hashing multi-gigabyte images once per format.
+
+use crate::hash::{self, Sha256, Sha512};
+use crate::manifest::Json;
+use std::io::Read;
+use std::path::Path;
+
+/// Artifact Metadata
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Metadata {
+    pub filename: String,
+    pub size: u64,
+    /// SHA-256 digest, as lower-case hexadecimal string.
+    pub sha256: String,
+    /// SHA-512 digest, as lower-case hexadecimal string.
+    pub sha512: String,
+    /// Identifier of the manifest the artifact was built from, like the
+    /// content identifier of `store::ManifestStore::id()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_id: Option<String>,
+    /// Build time, as UTC timestamp like `2023-11-01T00:00:00Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_time: Option<String>,
+}
+
+impl Metadata {
+    /// Compute Metadata
+    ///
+    /// Read the artifact at the given path once, and compute its size and
+    /// digests. The manifest identifier and build time are left unset.
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let (mut sha256, mut sha512) = (Sha256::new(), Sha512::new());
+        let mut buffer = vec![0; 1 << 20];
+        let mut size = 0;
+        loop {
+            let n = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            sha256.update(&buffer[..n]);
+            sha512.update(&buffer[..n]);
+            size += n as u64;
+        }
+
+        Ok(Self {
+            filename: path
+                .file_name()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size,
+            sha256: hash::to_hex(&sha256.finalize()),
+            sha512: hash::to_hex(&sha512.finalize()),
+            manifest_id: None,
+            build_time: None,
+        })
+    }
+}
+
+/// Serialize CHECKSUM File
+///
+/// Serialize the checksums of the artifacts in the BSD tag format, as
+/// produced by `sha256sum --tag`, with each artifact preceded by a comment
+/// stating its size.
+pub fn to_checksum(artifacts: &[Metadata]) -> String {
+    artifacts
+        .iter()
+        .map(|v| {
+            format!(
+                "# {}: {} bytes\nSHA256 ({}) = {}\nSHA512 ({}) = {}\n",
+                v.filename, v.size, v.filename, v.sha256, v.filename, v.sha512,
+            )
+        })
+        .collect()
+}
+
+/// Serialize SHA256SUMS File
+///
+/// Serialize the SHA-256 checksums of the artifacts in the format of
+/// `sha256sum`, which `sha256sum --check` verifies.
+pub fn to_sha256sums(artifacts: &[Metadata]) -> String {
+    artifacts
+        .iter()
+        .map(|v| format!("{}  {}\n", v.sha256, v.filename))
+        .collect()
+}
+
+/// Serialize JSON Sidecar
+///
+/// Return the metadata of the artifacts as JSON array.
+pub fn to_json(artifacts: &[Metadata]) -> Json {
+    serde_json::to_value(artifacts).expect("metadata always serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Artifact Sidecars
+    #[test]
+    fn verify_sidecar() {
+        let path = std::env::temp_dir().join(format!("r-osbuild-sidecar-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let mut metadata = Metadata::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(Metadata::from_path(&path).is_err());
+
+        assert_eq!(
+            metadata.filename,
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        assert_eq!(metadata.size, 3);
+        assert_eq! {
+            metadata.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        }
+        assert!(metadata.sha512.starts_with("ddaf35a193617aba"));
+
+        metadata.filename = "image.raw".to_owned();
+        metadata.sha512 = "00".to_owned();
+        metadata.manifest_id = Some("sha256:01".to_owned());
+        let artifacts = [metadata];
+        assert_eq! {
+            to_checksum(&artifacts),
+            concat!(
+                "# image.raw: 3 bytes\n",
+                "SHA256 (image.raw) = ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\n",
+                "SHA512 (image.raw) = 00\n",
+            ),
+        }
+        assert_eq! {
+            to_sha256sums(&artifacts),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  image.raw\n",
+        }
+        let json = to_json(&artifacts);
+        assert_eq! {
+            json,
+            serde_json::json!([{
+                "filename": "image.raw",
+                "size": 3,
+                "sha256": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                "sha512": "00",
+                "manifest_id": "sha256:01",
+            }]),
+        }
+        assert_eq!(
+            serde_json::from_value::<Vec<Metadata>>(json).unwrap(),
+            artifacts
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Detached Manifest Signatures
+//!
+//! This module signs and verifies manifests with detached signatures, so
+//! build services can ensure a manifest was not modified between its
+//! generation and its execution. Signatures are computed over the
+//! canonical JSON form of the manifest, as defined by the JSON
+//! Canonicalization Scheme (RFC 8785). Hence, re-serializing a manifest
+//! does not invalidate its signature, while any change to its content
+//! does.
+//!
+//! Keys are provided through the [`crate::dsse::Signer`] and
+//! [`crate::dsse::Verifier`] traits. This module provides backends for
+//! keys handled by the `openssl` command-line tool, for raw ed25519 keys,
+//! and for arbitrary external signing commands.
+
+use crate::dsse::{Signer, Verifier};
+use crate::hash;
+use crate::manifest::Json;
+
+/// Signature Error
+#[derive(Debug)]
+pub enum Error {
+    /// The signer failed.
+    Signer(String),
+    /// The signature is not valid base64.
+    Encoding,
+    /// The signature could not be verified with any of the given keys.
+    Unverified,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Signer(v) => write!(fmt, "signing failed: {}", v),
+            Error::Encoding => write!(fmt, "invalid base64 encoding"),
+            Error::Unverified => write!(fmt, "no valid signature"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Detached Signature
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Detached {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyid: Option<String>,
+    /// The base64 encoded signature.
+    pub sig: String,
+}
+
+fn write_canonical(value: &Json, acc: &mut String) {
+    match value {
+        Json::Array(v) => {
+            acc.push('[');
+            for (i, v) in v.iter().enumerate() {
+                if i > 0 {
+                    acc.push(',');
+                }
+                write_canonical(v, acc);
+            }
+            acc.push(']');
+        }
+        Json::Object(v) => {
+            let mut members: Vec<_> = v.iter().collect();
+            members.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+            acc.push('{');
+            for (i, (k, v)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    acc.push(',');
+                }
+                acc.push_str(&Json::String(k.clone()).to_string());
+                acc.push(':');
+                write_canonical(v, acc);
+            }
+            acc.push('}');
+        }
+        // Numbers beyond the range of doubles have no canonical form, but
+        // are kept verbatim, so they still affect signatures.
+        Json::Number(v) => match v.as_f64() {
+            Some(f) => write_number(f, acc),
+            None => acc.push_str(&v.to_string()),
+        },
+        v => acc.push_str(&v.to_string()),
+    }
+}
+
+// Write a number like ECMAScript `Number.prototype.toString()`, as
+// required by RFC 8785, section 3.2.2.3.
+fn write_number(v: f64, acc: &mut String) {
+    if v == 0.0 {
+        acc.push('0');
+        return;
+    }
+    if v < 0.0 {
+        acc.push('-');
+    }
+
+    // Shortest round-trip digits and the position of the decimal point.
+    let repr = format!("{:e}", v.abs());
+    let (mantissa, exponent) = repr.split_once('e').unwrap_or((&repr, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+
+    if k <= n && n <= 21 {
+        acc.push_str(&digits);
+        acc.extend(std::iter::repeat_n('0', (n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        acc.push_str(&digits[..n as usize]);
+        acc.push('.');
+        acc.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        acc.push_str("0.");
+        acc.extend(std::iter::repeat_n('0', -n as usize));
+        acc.push_str(&digits);
+    } else {
+        acc.push_str(&digits[..1]);
+        if k > 1 {
+            acc.push('.');
+            acc.push_str(&digits[1..]);
+        }
+        acc.push_str(&format!(
+            "e{}{}",
+            if n > 0 { "+" } else { "-" },
+            (n - 1).abs()
+        ));
+    }
+}
+
+/// Canonical JSON
+///
+/// Serialize a JSON value in the canonical form of RFC 8785: object
+/// members sorted by the UTF-16 code units of their keys, numbers in their
+/// shortest ECMAScript form, and no whitespace between tokens.
+pub fn canonical(value: &Json) -> Vec<u8> {
+    let mut acc = String::new();
+    write_canonical(value, &mut acc);
+    acc.into_bytes()
+}
+
+/// Sign Manifest
+///
+/// Create a detached signature of the manifest.
+pub fn sign(manifest: &Json, signer: &dyn Signer) -> Result<Detached, Error> {
+    let sig = signer.sign(&canonical(manifest)).map_err(Error::Signer)?;
+    Ok(Detached {
+        keyid: signer.key_id(),
+        sig: hash::to_base64(&sig),
+    })
+}
+
+/// Verify Manifest
+///
+/// Check that the detached signature of the manifest was created by any
+/// of the given keys.
+pub fn verify(
+    manifest: &Json,
+    signature: &Detached,
+    verifiers: &[&dyn Verifier],
+) -> Result<(), Error> {
+    let sig = hash::from_base64(&signature.sig).ok_or(Error::Encoding)?;
+    let message = canonical(manifest);
+
+    let verified = verifiers.iter().any(|v| {
+        let matches = match (&signature.keyid, v.key_id()) {
+            (Some(a), Some(b)) => *a == b,
+            _ => true,
+        };
+        matches && v.verify(&message, &sig)
+    });
+    match verified {
+        true => Ok(()),
+        false => Err(Error::Unverified),
+    }
+}
+
+// A temporary directory, removed when dropped. The directory has an
+// unpredictable name and is only accessible by the current user, since it
+// can hold private keys.
+pub(crate) struct Scratch(std::path::PathBuf);
+
+impl Scratch {
+    pub(crate) fn new() -> Result<Self, String> {
+        use std::hash::{BuildHasher, Hasher};
+
+        for _ in 0..16 {
+            // `RandomState` is seeded randomly by the standard library.
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            let path =
+                std::env::temp_dir().join(format!("r-osbuild-signature-{:016x}", hasher.finish()));
+
+            #[cfg_attr(not(unix), allow(unused_mut))]
+            let mut builder = std::fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&path) {
+                Ok(()) => return Ok(Self(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Err("cannot create temporary directory".to_owned())
+    }
+
+    pub(crate) fn write(&self, name: &str, data: &[u8]) -> Result<std::path::PathBuf, String> {
+        use std::io::Write;
+
+        let path = self.0.join(name);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&path)
+            .and_then(|mut v| v.write_all(data))
+            .map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// Run a command with the given input, returning its output on success.
+pub(crate) fn run(command: &[String], input: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+
+    let (program, args) = command.split_first().ok_or("empty command")?;
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Failures to consume the input are reported via the exit status.
+        let _ = stdin.write_all(input);
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{}: {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// OpenSSL Key
+///
+/// A key handled by the `openssl pkeyutl` command. Any key type supported
+/// by the installed OpenSSL can be used. A private key is needed to sign,
+/// while a public key suffices to verify.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Openssl {
+    key_id: Option<String>,
+    private: Option<std::path::PathBuf>,
+    public: Option<std::path::PathBuf>,
+    digest: Option<String>,
+}
+
+impl Openssl {
+    /// Create Key from Private Key File
+    pub fn private(path: &std::path::Path) -> Self {
+        Self {
+            private: Some(path.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// Create Key from Public Key File
+    pub fn public(path: &std::path::Path) -> Self {
+        Self {
+            public: Some(path.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// Set Key Identifier
+    pub fn set_key_id(&mut self, key_id: &str) {
+        self.key_id = Some(key_id.to_owned());
+    }
+
+    /// Set Message Digest
+    ///
+    /// Set the digest used to hash messages before signing, like `sha256`.
+    /// This is required for RSA and ECDSA keys, but must not be set for
+    /// ed25519 keys, which sign messages directly.
+    pub fn set_digest(&mut self, digest: &str) {
+        self.digest = Some(digest.to_owned());
+    }
+
+    fn command(&self, op: &str, key: &std::path::Path, message: &std::path::Path) -> Vec<String> {
+        let mut acc: Vec<String> = vec![
+            "openssl".into(),
+            "pkeyutl".into(),
+            op.into(),
+            "-rawin".into(),
+        ];
+        if let Some(v) = &self.digest {
+            acc.extend(["-digest".to_owned(), v.clone()]);
+        }
+        if key.extension().is_some_and(|v| v == "der") {
+            acc.extend(["-keyform".to_owned(), "DER".to_owned()]);
+        }
+        acc.extend([
+            "-inkey".to_owned(),
+            key.display().to_string(),
+            "-in".to_owned(),
+            message.display().to_string(),
+        ]);
+        acc
+    }
+}
+
+impl Signer for Openssl {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        let key = self.private.as_ref().ok_or("no private key")?;
+        let scratch = Scratch::new()?;
+        let message = scratch.write("message", message)?;
+        run(&self.command("-sign", key, &message), &[])
+    }
+}
+
+impl Verifier for Openssl {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let check = || -> Result<(), String> {
+            let scratch = Scratch::new()?;
+            let message = scratch.write("message", message)?;
+            let sigfile = scratch.write("signature", signature)?;
+            let mut command = match (&self.public, &self.private) {
+                (Some(key), _) => {
+                    let mut v = self.command("-verify", key, &message);
+                    v.push("-pubin".to_owned());
+                    v
+                }
+                (None, Some(key)) => self.command("-verify", key, &message),
+                (None, None) => return Err("no key".to_owned()),
+            };
+            command.extend(["-sigfile".to_owned(), sigfile.display().to_string()]);
+            run(&command, &[]).map(|_| ())
+        };
+        check().is_ok()
+    }
+}
+
+/// Raw Ed25519 Key
+///
+/// An ed25519 key given as raw 32-byte private seed or public key. The
+/// key is wrapped into its DER encoding and handled via [`Openssl`].
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Ed25519 {
+    key_id: Option<String>,
+    private: Option<[u8; 32]>,
+    public: Option<[u8; 32]>,
+}
+
+impl Ed25519 {
+    // DER prefixes of PKCS#8 private keys and SPKI public keys.
+    const PRIVATE_PREFIX: &[u8] =
+        b"\x30\x2e\x02\x01\x00\x30\x05\x06\x03\x2b\x65\x70\x04\x22\x04\x20";
+    const PUBLIC_PREFIX: &[u8] = b"\x30\x2a\x30\x05\x06\x03\x2b\x65\x70\x03\x21\x00";
+
+    /// Create Key from Private Seed
+    pub fn private(seed: [u8; 32]) -> Self {
+        Self {
+            private: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    /// Create Key from Public Key
+    pub fn public(key: [u8; 32]) -> Self {
+        Self {
+            public: Some(key),
+            ..Default::default()
+        }
+    }
+
+    /// Set Key Identifier
+    pub fn set_key_id(&mut self, key_id: &str) {
+        self.key_id = Some(key_id.to_owned());
+    }
+
+    // Write the DER encoding of the key and create an OpenSSL key for it.
+    fn openssl(&self, scratch: &Scratch) -> Result<Openssl, String> {
+        match (&self.public, &self.private) {
+            (Some(v), _) => {
+                let path = scratch.write("key.der", &[Self::PUBLIC_PREFIX, v].concat())?;
+                Ok(Openssl::public(&path))
+            }
+            (None, Some(v)) => {
+                let path = scratch.write("key.der", &[Self::PRIVATE_PREFIX, v].concat())?;
+                Ok(Openssl::private(&path))
+            }
+            (None, None) => Err("no key".to_owned()),
+        }
+    }
+}
+
+impl Signer for Ed25519 {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        let seed = self.private.ok_or("no private key")?;
+        let scratch = Scratch::new()?;
+        Signer::sign(&Self::private(seed).openssl(&scratch)?, message)
+    }
+}
+
+impl Verifier for Ed25519 {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let scratch = match Scratch::new() {
+            Err(_) => return false,
+            Ok(v) => v,
+        };
+        self.openssl(&scratch)
+            .is_ok_and(|v| Verifier::verify(&v, message, signature))
+    }
+}
+
+/// External Signer
+///
+/// A key held by an external program. To sign, the signing command is run
+/// with the message on its standard input, and must print the raw
+/// signature. To verify, the verification command is run with the base64
+/// encoded signature appended as last argument and the message on its
+/// standard input, and must exit successfully only if the signature is
+/// valid.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Command {
+    key_id: Option<String>,
+    sign: Vec<String>,
+    verify: Vec<String>,
+}
+
+impl Command {
+    /// Create External Signer
+    pub fn new(sign: &[&str], verify: &[&str]) -> Self {
+        Self {
+            key_id: None,
+            sign: sign.iter().map(|v| (*v).to_owned()).collect(),
+            verify: verify.iter().map(|v| (*v).to_owned()).collect(),
+        }
+    }
+
+    /// Set Key Identifier
+    pub fn set_key_id(&mut self, key_id: &str) {
+        self.key_id = Some(key_id.to_owned());
+    }
+}
+
+impl Signer for Command {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        run(&self.sign, message)
+    }
+}
+
+impl Verifier for Command {
+    fn key_id(&self) -> Option<String> {
+        self.key_id.clone()
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let mut command = self.verify.clone();
+        command.push(hash::to_base64(signature));
+        run(&command, message).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        hash::from_hex(s).unwrap()
+    }
+
+    fn openssl_available() -> bool {
+        std::process::Command::new("openssl")
+            .arg("version")
+            .output()
+            .is_ok_and(|v| v.status.success())
+    }
+
+    // Verify Canonical JSON
+    #[test]
+    fn verify_canonical() {
+        let value: Json =
+            serde_json::from_str(r#"{ "b": [1, { "d": null, "c": "é\n" }], "a": true }"#).unwrap();
+        assert_eq! {
+            String::from_utf8(canonical(&value)).unwrap(),
+            "{\"a\":true,\"b\":[1,{\"c\":\"\u{e9}\\n\",\"d\":null}]}",
+        }
+
+        // RFC 8785, appendix B, and section 3.2.3.
+        let value: Json = serde_json::from_str(
+            r#"[1.0, -0.0, 1e21, 1e-7, 0.000001, 123456789012345678901, 4.5, -1.5e-10, 9007199254740993]"#,
+        )
+        .unwrap();
+        assert_eq! {
+            String::from_utf8(canonical(&value)).unwrap(),
+            "[1,0,1e+21,1e-7,0.000001,123456789012345680000,4.5,-1.5e-10,9007199254740992]",
+        }
+        let value: Json = serde_json::from_str("[1e400, 1e401]").unwrap();
+        assert_eq!(
+            String::from_utf8(canonical(&value)).unwrap(),
+            "[1e+400,1e+401]"
+        );
+        let value: Json =
+            serde_json::from_str(r#"{ "\ufb33": 1, "\ud83d\ude00": 2, "\r": 3 }"#).unwrap();
+        assert_eq! {
+            String::from_utf8(canonical(&value)).unwrap(),
+            "{\"\\r\":3,\"\u{1f600}\":2,\"\u{fb33}\":1}",
+        }
+    }
+
+    // Verify External Signers
+    #[cfg(unix)]
+    #[test]
+    fn verify_command() {
+        let manifest = serde_json::json!({ "version": "2", "pipelines": [] });
+        let mut key = Command::new(
+            &["sh", "-c", "sha256sum | cut -c1-64"],
+            &[
+                "sh",
+                "-c",
+                r#"test "$(sha256sum | cut -c1-64 | base64 -w0)" = "$0""#,
+            ],
+        );
+        key.set_key_id("ext");
+
+        let signature = sign(&manifest, &key).unwrap();
+        assert_eq!(signature.keyid.as_deref(), Some("ext"));
+
+        // Member order does not affect the signature.
+        let reordered: Json =
+            serde_json::from_str(r#"{ "pipelines": [], "version": "2" }"#).unwrap();
+        verify(&reordered, &signature, &[&key]).unwrap();
+
+        let modified = serde_json::json!({ "version": "2", "pipelines": [{}] });
+        assert!(matches!(
+            verify(&modified, &signature, &[&key]),
+            Err(Error::Unverified),
+        ));
+    }
+
+    // Verify Ed25519 Keys
+    #[test]
+    fn verify_ed25519() {
+        if !openssl_available() {
+            // Bypass the output capture of the test harness, so the skip
+            // shows up in the test log.
+            let _ = std::io::Write::write_all(
+                &mut std::io::stderr(),
+                b"skipping verify_ed25519: openssl is not available\n",
+            );
+            return;
+        }
+
+        // RFC 8032, section 7.1, test 2.
+        let seed = hex("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb");
+        let public = hex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let private = Ed25519::private(seed.try_into().unwrap());
+        let public = Ed25519::public(public.try_into().unwrap());
+
+        assert_eq! {
+            Signer::sign(&private, b"\x72").unwrap(),
+            hex("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"),
+        }
+
+        let manifest = serde_json::json!({ "version": "2" });
+        let signature = sign(&manifest, &private).unwrap();
+        verify(&manifest, &signature, &[&public]).unwrap();
+        assert!(matches!(
+            verify(&serde_json::json!({}), &signature, &[&public]),
+            Err(Error::Unverified),
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+//! Sigstore Verification
+//!
+//! This module verifies sigstore signatures of container images and
+//! manifests, including keyless signatures backed by Fulcio certificates
+//! and Rekor transparency-log entries. A [`Policy`] describes the signer
+//! identities that are acceptable, as pairs of OIDC issuer and certificate
+//! identity.
+//!
+//! Verification is performed by the `cosign` command-line tool, which
+//! handles the sigstore trust root, certificate chains, and transparency
+//! log. This module is only available with the `sigstore` feature.
+
+use crate::manifest::Json;
+use crate::signature::{self, Scratch};
+
+/// Accepted Identity
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Identity {
+    /// The OIDC issuer, like `https://token.actions.githubusercontent.com`.
+    pub issuer: String,
+    /// The exact certificate identity, like an email address or a URI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// A regular expression matching the certificate identity, used if
+    /// no exact subject is given. Either this or `subject` is required.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject_regexp: Option<String>,
+}
+
+/// Verification Policy
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Policy {
+    /// Identities accepted as signers. A signature by any of them is
+    /// sufficient.
+    pub identities: Vec<Identity>,
+    /// Custom Rekor instance, instead of the public-good instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rekor_url: Option<String>,
+    /// Accept signatures without transparency-log entry.
+    #[serde(default)]
+    pub ignore_tlog: bool,
+}
+
+/// Verification Error
+#[derive(Debug)]
+pub enum Error {
+    /// The policy accepts no identities.
+    EmptyPolicy,
+    /// An identity of the policy has neither subject nor subject pattern,
+    /// given as its issuer.
+    UnboundIdentity(String),
+    /// Temporary files could not be created.
+    Io(String),
+    /// The given subject has no signature by an accepted identity.
+    Unverified(String, String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::EmptyPolicy => write!(fmt, "policy accepts no identities"),
+            Error::UnboundIdentity(v) => {
+                write!(fmt, "identity of issuer {} has no subject", v)
+            }
+            Error::Io(v) => write!(fmt, "{}", v),
+            Error::Unverified(s, v) => write!(fmt, "{}: verification failed: {}", s, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Sigstore Verifier
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Verifier {
+    command: Vec<String>,
+    policy: Policy,
+}
+
+impl Verifier {
+    /// Create New Verifier
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            command: vec!["cosign".to_owned()],
+            policy,
+        }
+    }
+
+    /// Set Cosign Command
+    ///
+    /// Set the program to run in place of `cosign`, together with its
+    /// arguments.
+    pub fn set_command(&mut self, command: &[&str]) {
+        self.command = command.iter().map(|v| (*v).to_owned()).collect();
+    }
+
+    // Run a cosign verification for each accepted identity, until any
+    // succeeds. Identities without subject are rejected, rather than
+    // accepting any certificate of their issuer.
+    fn run(&self, subject: &str, args: &[String]) -> Result<(), Error> {
+        let mut reason = String::new();
+
+        if let Some(v) = self
+            .policy
+            .identities
+            .iter()
+            .find(|v| v.subject.is_none() && v.subject_regexp.is_none())
+        {
+            return Err(Error::UnboundIdentity(v.issuer.clone()));
+        }
+
+        for identity in &self.policy.identities {
+            let mut command = self.command.clone();
+            command.extend(args[..1].iter().cloned());
+            command.extend([
+                "--certificate-oidc-issuer".to_owned(),
+                identity.issuer.clone(),
+            ]);
+            match (&identity.subject, &identity.subject_regexp) {
+                (Some(v), _) => command.extend(["--certificate-identity".to_owned(), v.clone()]),
+                (None, Some(v)) => {
+                    command.extend(["--certificate-identity-regexp".to_owned(), v.clone()])
+                }
+                (None, None) => unreachable!(),
+            }
+            if let Some(v) = &self.policy.rekor_url {
+                command.extend(["--rekor-url".to_owned(), v.clone()]);
+            }
+            if self.policy.ignore_tlog {
+                command.push("--insecure-ignore-tlog".to_owned());
+            }
+            command.extend(args[1..].iter().cloned());
+
+            match signature::run(&command, &[]) {
+                Ok(_) => return Ok(()),
+                Err(e) => reason = e,
+            }
+        }
+
+        match self.policy.identities.is_empty() {
+            true => Err(Error::EmptyPolicy),
+            false => Err(Error::Unverified(subject.to_owned(), reason)),
+        }
+    }
+
+    /// Verify Container Image
+    ///
+    /// Verify the signature of an image, given as reference that should
+    /// be pinned to a digest, like `quay.io/org/app@sha256:...`.
+    pub fn verify_image(&self, reference: &str) -> Result<(), Error> {
+        self.run(reference, &["verify".to_owned(), reference.to_owned()])
+    }
+
+    /// Verify Container Sources
+    ///
+    /// Verify the signatures of all images of the `org.osbuild.skopeo`
+    /// source of a manifest.
+    pub fn verify_sources(&self, manifest: &Json) -> Result<(), Error> {
+        let items = manifest
+            .pointer("/sources/org.osbuild.skopeo/items")
+            .and_then(Json::as_object);
+
+        for (id, item) in items.into_iter().flatten() {
+            let name = item.pointer("/image/name").and_then(Json::as_str);
+            let digest = item
+                .pointer("/image/digest")
+                .and_then(Json::as_str)
+                .unwrap_or(id);
+            if let Some(name) = name {
+                self.verify_image(&format!("{}@{}", name, digest))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify Manifest
+    ///
+    /// Verify a sigstore bundle signing the canonical JSON form of the
+    /// manifest, as produced by `cosign sign-blob --bundle`.
+    pub fn verify_manifest(&self, manifest: &Json, bundle: &Json) -> Result<(), Error> {
+        let scratch = Scratch::new().map_err(Error::Io)?;
+        let blob = scratch
+            .write("manifest.json", &signature::canonical(manifest))
+            .map_err(Error::Io)?;
+        let bundle = scratch
+            .write("bundle.json", bundle.to_string().as_bytes())
+            .map_err(Error::Io)?;
+
+        self.run(
+            "manifest",
+            &[
+                "verify-blob".to_owned(),
+                "--bundle".to_owned(),
+                bundle.display().to_string(),
+                blob.display().to_string(),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Policy Evaluation
+    #[cfg(unix)]
+    #[test]
+    fn verify_policy() {
+        let policy: Policy = serde_json::from_value(serde_json::json!({
+            "identities": [
+                { "issuer": "https://accounts.example.com", "subject": "other@example.com" },
+                { "issuer": "https://accounts.example.com", "subject_regexp": ".*@example.com" }
+            ],
+            "ignore_tlog": true
+        }))
+        .unwrap();
+
+        // The fake accepts images signed by any `example.com` identity.
+        let mut verifier = Verifier::new(policy);
+        verifier.set_command(&[
+            "sh",
+            "-c",
+            r#"test "$*" = "verify --certificate-oidc-issuer https://accounts.example.com --certificate-identity-regexp .*@example.com --insecure-ignore-tlog quay.io/org/app@sha256:m""#,
+            "cosign",
+        ]);
+
+        let manifest = serde_json::json!({
+            "sources": {
+                "org.osbuild.skopeo": {
+                    "items": {
+                        "sha256:id": { "image": { "name": "quay.io/org/app", "digest": "sha256:m" } }
+                    }
+                }
+            }
+        });
+        verifier.verify_sources(&manifest).unwrap();
+        assert!(matches!(
+            verifier.verify_image("quay.io/org/app@sha256:x"),
+            Err(Error::Unverified(..)),
+        ));
+        assert!(matches!(
+            Verifier::new(Policy::default()).verify_image("quay.io/org/app@sha256:m"),
+            Err(Error::EmptyPolicy),
+        ));
+        let mut unbound = verifier.clone();
+        unbound.policy.identities.push(Identity {
+            issuer: "https://accounts.example.com".to_owned(),
+            ..Default::default()
+        });
+        assert!(matches!(
+            unbound.verify_sources(&manifest),
+            Err(Error::UnboundIdentity(..)),
+        ));
+
+        // Manifests are verified in canonical form.
+        verifier.set_command(&[
+            "sh",
+            "-c",
+            r#"for f; do :; done; test "$1" = verify-blob && test "$(cat "$f")" = '{"a":1,"b":2}'"#,
+            "cosign",
+        ]);
+        let manifest: Json = serde_json::from_str(r#"{ "b": 2, "a": 1 }"#).unwrap();
+        verifier
+            .verify_manifest(&manifest, &serde_json::json!({}))
+            .unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! Sources Builder
+//!
+//! Stages refer to files by the digests of their content, and the files
+//! themselves are listed as items of the `sources` section of a manifest,
+//! keyed by those digests. This module computes the digests and builds the
+//! `sources` section from files, URLs, and byte buffers. Small content is
+//! embedded into the manifest as `org.osbuild.inline` item, larger content
+//! is fetched by osbuild as `org.osbuild.curl` item.
+
+use crate::hash;
+use crate::http::Transport;
+use crate::manifest::{self, Json, Object, Sources, Symbol};
+use std::path::{Path, PathBuf};
+
+/// Sources Builder Errors
+#[derive(Debug)]
+pub enum Error {
+    /// The file at the given path cannot be read.
+    Io(PathBuf, std::io::Error),
+    /// The transport failed.
+    Transport(String),
+    /// The remote responded to the given URL with an error status.
+    Status(String, u16),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::Transport(v) => write!(fmt, "transport error: {}", v),
+            Error::Status(u, v) => write!(fmt, "{}: remote responded with status {}", u, v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Sources Builder
+///
+/// Content larger than the threshold, in bytes, is fetched via its URL
+/// rather than embedded. Content without a URL is always embedded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourcesBuilder {
+    pub threshold: usize,
+    inline: Object<Json>,
+    curl: Object<Json>,
+    digests: Vec<String>,
+}
+
+impl Default for SourcesBuilder {
+    fn default() -> Self {
+        Self {
+            threshold: 4096,
+            inline: Object::new(),
+            curl: Object::new(),
+            digests: Vec::new(),
+        }
+    }
+}
+
+impl SourcesBuilder {
+    /// Create Sources Builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add content, fetchable via the URL if given, and return its digest.
+    fn add(&mut self, data: &[u8], url: Option<&str>) -> String {
+        let digest = hash::Digest::sha256(data).to_string();
+        if !self.digests.contains(&digest) {
+            match url {
+                Some(url) if data.len() > self.threshold => {
+                    self.curl
+                        .insert(digest.clone(), Json::String(url.to_owned()));
+                }
+                _ => {
+                    self.inline.insert(
+                        digest.clone(),
+                        serde_json::json!({ "encoding": "base64", "data": hash::to_base64(data) }),
+                    );
+                }
+            }
+            self.digests.push(digest.clone());
+        }
+        digest
+    }
+
+    /// Add Byte Buffer
+    ///
+    /// Embed the data and return its digest.
+    pub fn bytes(&mut self, data: &[u8]) -> String {
+        self.add(data, None)
+    }
+
+    /// Add File
+    ///
+    /// Add the content of the local file and return its digest. Large files
+    /// are referred to by their absolute `file://` URL.
+    pub fn file(&mut self, path: &Path) -> Result<String, Error> {
+        let data = std::fs::read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        let url = match path.canonicalize() {
+            Ok(v) => format!("file://{}", v.display()),
+            Err(e) => return Err(Error::Io(path.to_owned(), e)),
+        };
+        Ok(self.add(&data, Some(&url)))
+    }
+
+    /// Add URL
+    ///
+    /// Fetch the content of the URL via the transport to compute its
+    /// digest, and return the digest.
+    pub fn url(&mut self, transport: &dyn Transport, url: &str) -> Result<String, Error> {
+        span!("fetch", url = url);
+        let r = transport.get(url, &[]).map_err(Error::Transport)?;
+        if r.status != 200 {
+            return Err(Error::Status(url.to_owned(), r.status));
+        }
+        event!(Debug, "fetched", bytes = r.body.len());
+        Ok(self.add(&r.body, Some(url)))
+    }
+
+    /// Input References
+    ///
+    /// Return the references to all added content, in the order it was
+    /// added, as used by the `references` of `org.osbuild.files` inputs.
+    pub fn references(&self) -> Json {
+        Json::Object(
+            self.digests
+                .iter()
+                .map(|v| (v.clone(), Json::Object(Default::default())))
+                .collect(),
+        )
+    }
+
+    /// Build Sources
+    ///
+    /// Return the `sources` section listing all added content. Sources
+    /// without items are omitted.
+    pub fn build(self) -> Sources {
+        let mut acc = Sources::new();
+        for (name, items) in [
+            ("org.osbuild.curl", self.curl),
+            ("org.osbuild.inline", self.inline),
+        ] {
+            if !items.is_empty() {
+                let items = manifest::object_to_value(items);
+                acc.insert(Symbol::new(name), [("items".to_owned(), items)].into());
+            }
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Response;
+
+    struct Fake;
+
+    impl Transport for Fake {
+        fn get(&self, url: &str, _headers: &[(&str, &str)]) -> Result<Response, String> {
+            Ok(Response {
+                status: if url.ends_with("/large") { 200 } else { 404 },
+                body: vec![b'x'; 8],
+                ..Default::default()
+            })
+        }
+    }
+
+    // Verify Sources Builder
+    #[test]
+    fn verify_sources() {
+        let dir = std::env::temp_dir().join(format!("r-osbuild-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("small"), "abc").unwrap();
+
+        let mut builder = SourcesBuilder {
+            threshold: 4,
+            ..Default::default()
+        };
+        let abc = "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(builder.bytes(b"abc"), abc);
+        assert_eq!(builder.file(&dir.join("small")).unwrap(), abc);
+        let large = builder.url(&Fake, "https://example.com/large").unwrap();
+        assert!(matches! {
+            builder.url(&Fake, "https://example.com/none"),
+            Err(Error::Status(_, 404)),
+        });
+        assert!(matches!(
+            builder.file(&dir.join("none")),
+            Err(Error::Io(..))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq! {
+            builder.references(),
+            serde_json::json!({ abc: {}, large.as_str(): {} }),
+        }
+        assert_eq! {
+            serde_json::to_value(builder.build()).unwrap(),
+            serde_json::json!({
+                "org.osbuild.curl": {
+                    "items": { large.as_str(): "https://example.com/large" },
+                },
+                "org.osbuild.inline": {
+                    "items": { abc: { "encoding": "base64", "data": "YWJj" } },
+                },
+            }),
+        }
+    }
+}
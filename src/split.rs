@@ -0,0 +1,486 @@
+//! Manifest Fragments
+//!
+//! Multi-pipeline manifests often share pipelines, like a build root used
+//! by several image pipelines. This module splits a manifest v2 into one
+//! fragment per pipeline, so such pipelines can be maintained separately,
+//! and recombines fragments into a manifest.
+//!
+//! A fragment holds a pipeline, the names of the pipelines it requires,
+//! and the source items its stages reference. Cross-references are
+//! explicit: recombination verifies that the required pipelines are
+//! exactly those the pipeline depends on, and that all of them are
+//! present. Source items shared by fragments must be identical. Items no
+//! pipeline references are kept with the first fragment, so splitting and
+//! recombining is lossless, apart from the order of independent
+//! pipelines, which follows the order of the fragments.
+
+use crate::manifest::{Json, Manifest2, Pipeline2, Sources};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// File Extension of Fragments
+pub const EXTENSION: &str = "json";
+
+/// Fragment Error
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation failed.
+    Io(std::io::Error),
+    /// A fragment cannot be parsed, at the given path, if any.
+    Parse(String, serde_json::Error),
+    /// The pipeline name cannot be used as a file name.
+    Name(String),
+    /// Several fragments hold a pipeline of the given name.
+    Duplicate(String),
+    /// The pipeline depends on a pipeline it does not declare.
+    Undeclared { pipeline: String, reference: String },
+    /// The pipeline declares a pipeline it does not depend on.
+    Unused { pipeline: String, reference: String },
+    /// The pipeline requires a pipeline no fragment holds.
+    Missing { pipeline: String, reference: String },
+    /// Fragments define the item of the given source differently.
+    Conflict { source: String, item: String },
+    /// The pipelines depend on each other cyclically.
+    Cycle,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(v) => v.fmt(fmt),
+            Error::Parse(path, v) => write!(fmt, "invalid fragment {}: {}", path, v),
+            Error::Name(v) => write!(fmt, "pipeline name not usable as file name: {}", v),
+            Error::Duplicate(v) => write!(fmt, "duplicate pipeline: {}", v),
+            Error::Undeclared {
+                pipeline,
+                reference,
+            } => write!(
+                fmt,
+                "pipeline {} depends on undeclared pipeline {}",
+                pipeline, reference
+            ),
+            Error::Unused {
+                pipeline,
+                reference,
+            } => write!(
+                fmt,
+                "pipeline {} requires unused pipeline {}",
+                pipeline, reference
+            ),
+            Error::Missing {
+                pipeline,
+                reference,
+            } => write!(
+                fmt,
+                "pipeline {} requires missing pipeline {}",
+                pipeline, reference
+            ),
+            Error::Conflict { source, item } => {
+                write!(fmt, "conflicting definitions of {} item {}", source, item)
+            }
+            Error::Cycle => write!(fmt, "pipelines depend on each other cyclically"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(v: std::io::Error) -> Self {
+        Error::Io(v)
+    }
+}
+
+/// Manifest Fragment
+///
+/// A single pipeline of a manifest v2, with its cross-references and the
+/// source items it uses.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fragment {
+    /// Names of the pipelines this pipeline depends on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    pub pipeline: Pipeline2,
+    /// Source items referenced by the pipeline, keyed by source.
+    #[serde(default, skip_serializing_if = "Sources::is_empty")]
+    pub sources: Sources,
+}
+
+impl Fragment {
+    /// Parse Fragment
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        serde_json::from_str(text).map_err(|e| Error::Parse(String::new(), e))
+    }
+
+    /// Serialize Fragment
+    pub fn serialize(&self) -> String {
+        serde_json::to_string_pretty(self).expect("fragments always serialize")
+    }
+
+    /// File Name
+    ///
+    /// Return the file name of the fragment, derived from the name of its
+    /// pipeline, or an error if the name is not usable as file name.
+    pub fn file_name(&self) -> Result<String, Error> {
+        let name = &self.pipeline.name;
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return Err(Error::Name(name.clone()));
+        }
+        Ok(format!("{}.{}", name, EXTENSION))
+    }
+}
+
+// Collect all strings and member names of the value.
+fn strings<'a>(value: &'a Json, acc: &mut BTreeSet<&'a str>) {
+    match value {
+        Json::String(v) => {
+            acc.insert(v);
+        }
+        Json::Array(v) => v.iter().for_each(|v| strings(v, acc)),
+        Json::Object(v) => v.iter().for_each(|(k, v)| {
+            acc.insert(k);
+            strings(v, acc);
+        }),
+        _ => {}
+    }
+}
+
+/// Split Manifest
+///
+/// Split the manifest into one fragment per pipeline, in the order of the
+/// pipelines. Source items are assigned to all fragments whose stage
+/// inputs reference them. Other members of source definitions, like
+/// options, are kept with every fragment using the source.
+pub fn split(manifest: &Manifest2) -> Vec<Fragment> {
+    let mut used = BTreeSet::new();
+    let mut acc: Vec<Fragment> = manifest
+        .pipelines
+        .iter()
+        .map(|pipeline| {
+            let mut references = BTreeSet::new();
+            for stage in &pipeline.stages {
+                stage
+                    .inputs
+                    .values()
+                    .for_each(|v| strings(v, &mut references));
+            }
+
+            let mut sources = Sources::new();
+            for (name, source) in &manifest.sources {
+                let items = source.get("items").and_then(Json::as_object);
+                let items: serde_json::Map<String, Json> = items
+                    .into_iter()
+                    .flatten()
+                    .filter(|(k, _)| references.contains(k.as_str()))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                if items.is_empty() {
+                    continue;
+                }
+                used.extend(items.keys().map(|k| (name.clone(), k.clone())));
+                let mut source = source.clone();
+                source.insert("items".to_owned(), Json::Object(items));
+                sources.insert(name.clone(), source);
+            }
+
+            Fragment {
+                requires: pipeline
+                    .dependencies()
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+                pipeline: pipeline.clone(),
+                sources,
+            }
+        })
+        .collect();
+
+    // Keep unreferenced items, and sources without items, with the first
+    // fragment.
+    if let Some(first) = acc.first_mut() {
+        for (name, source) in &manifest.sources {
+            let items = source.get("items").and_then(Json::as_object);
+            let unused: serde_json::Map<String, Json> = items
+                .into_iter()
+                .flatten()
+                .filter(|(k, _)| !used.contains(&(name.clone(), (*k).clone())))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            if !unused.is_empty() || items.is_none_or(|v| v.is_empty()) {
+                let entry = first.sources.entry(name.clone()).or_insert_with(|| {
+                    let mut v = source.clone();
+                    if items.is_some() {
+                        v.insert("items".to_owned(), Json::Object(Default::default()));
+                    }
+                    v
+                });
+                if let Some(Json::Object(items)) = entry.get_mut("items") {
+                    items.extend(unused);
+                }
+            }
+        }
+    }
+
+    acc
+}
+
+/// Recombine Fragments
+///
+/// Recombine the fragments into a manifest, verifying their
+/// cross-references and source items. Pipelines are ordered so each comes
+/// after the pipelines it depends on, otherwise retaining the order of the
+/// fragments.
+pub fn recombine(fragments: &[Fragment]) -> Result<Manifest2, Error> {
+    let names: BTreeSet<&str> = fragments.iter().map(|v| v.pipeline.name.as_str()).collect();
+    if names.len() < fragments.len() {
+        let mut seen = BTreeSet::new();
+        let name = fragments
+            .iter()
+            .map(|v| &v.pipeline.name)
+            .find(|v| !seen.insert(*v))
+            .unwrap();
+        return Err(Error::Duplicate(name.clone()));
+    }
+
+    let mut manifest = Manifest2::default();
+    manifest.version = "2".to_owned();
+    for fragment in fragments {
+        let pipeline = &fragment.pipeline.name;
+        let dependencies = fragment.pipeline.dependencies();
+        for reference in &dependencies {
+            if !fragment.requires.iter().any(|v| v == reference) {
+                return Err(Error::Undeclared {
+                    pipeline: pipeline.clone(),
+                    reference: (*reference).to_owned(),
+                });
+            }
+        }
+        for reference in &fragment.requires {
+            if !dependencies.contains(&reference.as_str()) {
+                return Err(Error::Unused {
+                    pipeline: pipeline.clone(),
+                    reference: reference.clone(),
+                });
+            }
+            if !names.contains(reference.as_str()) {
+                return Err(Error::Missing {
+                    pipeline: pipeline.clone(),
+                    reference: reference.clone(),
+                });
+            }
+        }
+
+        for (name, source) in &fragment.sources {
+            let entry = manifest.sources.entry(name.clone()).or_default();
+            for (key, value) in source {
+                match (entry.get_mut(key), value) {
+                    (None, value) => {
+                        entry.insert(key.clone(), value.clone());
+                    }
+                    (Some(Json::Object(items)), Json::Object(new)) if key == "items" => {
+                        for (item, value) in new {
+                            match items.get(item) {
+                                Some(v) if v != value => {
+                                    return Err(Error::Conflict {
+                                        source: name.to_string(),
+                                        item: item.clone(),
+                                    })
+                                }
+                                Some(_) => {}
+                                None => {
+                                    items.insert(item.clone(), value.clone());
+                                }
+                            }
+                        }
+                    }
+                    (Some(v), value) if v == value => {}
+                    (Some(_), _) => {
+                        return Err(Error::Conflict {
+                            source: name.to_string(),
+                            item: key.clone(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    manifest.pipelines = fragments.iter().map(|v| v.pipeline.clone()).collect();
+    let order: Vec<String> = manifest
+        .iter_pipelines_topological()
+        .ok_or(Error::Cycle)?
+        .map(|v| v.name.clone())
+        .collect();
+    manifest.pipelines = order
+        .iter()
+        .map(|name| fragments.iter().find(|v| &v.pipeline.name == name))
+        .map(|v| v.unwrap().pipeline.clone())
+        .collect();
+    Ok(manifest)
+}
+
+/// Write Fragments
+///
+/// Write each fragment to a file in the directory, named after its
+/// pipeline.
+pub fn write_dir(fragments: &[Fragment], dir: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(dir)?;
+    for fragment in fragments {
+        std::fs::write(dir.join(fragment.file_name()?), fragment.serialize())?;
+    }
+    Ok(())
+}
+
+/// Read Fragments
+///
+/// Read all fragments in the directory, sorted by file name. Files with
+/// other extensions are ignored.
+pub fn read_dir(dir: &Path) -> Result<Vec<Fragment>, Error> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|v| v == EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let text = std::fs::read_to_string(path)?;
+            serde_json::from_str(&text).map_err(|e| Error::Parse(path.display().to_string(), e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "version": "2",
+        "pipelines": [
+            {
+                "name": "build",
+                "runner": "org.osbuild.fedora39",
+                "stages": [{
+                    "type": "org.osbuild.rpm",
+                    "inputs": { "packages": {
+                        "type": "org.osbuild.files",
+                        "origin": "org.osbuild.source",
+                        "references": ["sha256:aa", "sha256:bb"]
+                    } }
+                }]
+            },
+            {
+                "name": "os",
+                "build": "name:build",
+                "stages": [{
+                    "type": "org.osbuild.rpm",
+                    "inputs": { "packages": {
+                        "type": "org.osbuild.files",
+                        "origin": "org.osbuild.source",
+                        "references": { "sha256:bb": {}, "sha256:cc": {} }
+                    } }
+                }]
+            },
+            {
+                "name": "image",
+                "build": "name:build",
+                "stages": [{
+                    "type": "org.osbuild.copy",
+                    "inputs": { "tree": {
+                        "type": "org.osbuild.tree",
+                        "origin": "org.osbuild.pipeline",
+                        "references": ["name:os"]
+                    } }
+                }]
+            }
+        ],
+        "sources": {
+            "org.osbuild.curl": { "items": {
+                "sha256:aa": "https://example.com/aa",
+                "sha256:bb": "https://example.com/bb",
+                "sha256:cc": "https://example.com/cc",
+                "sha256:dd": "https://example.com/dd"
+            } }
+        }
+    }"#;
+
+    // Verify Splitting and Recombining
+    #[test]
+    fn verify_split() {
+        let manifest: Manifest2 = serde_json::from_str(MANIFEST).unwrap();
+        let fragments = split(&manifest);
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[0].requires, Vec::<String>::new());
+        assert_eq!(fragments[2].requires, vec!["build", "os"]);
+        let items = |i: usize| -> Vec<String> {
+            fragments[i]
+                .sources
+                .get("org.osbuild.curl")
+                .and_then(|v| v.get("items"))
+                .and_then(Json::as_object)
+                .map(|v| v.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        assert_eq!(items(0), vec!["sha256:aa", "sha256:bb", "sha256:dd"]);
+        assert_eq!(items(1), vec!["sha256:bb", "sha256:cc"]);
+        assert_eq!(items(2), Vec::<String>::new());
+
+        // Fragments in any order recombine to the manifest.
+        let mut reordered = fragments.clone();
+        reordered.reverse();
+        assert_eq!(recombine(&reordered).unwrap(), manifest);
+
+        let dir = std::env::temp_dir().join(format!("r-osbuild-split-{}", std::process::id()));
+        write_dir(&fragments, &dir).unwrap();
+        let read = read_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(recombine(&read).unwrap(), manifest);
+    }
+
+    // Verify Validation of Fragments
+    #[test]
+    fn verify_validation() {
+        let manifest: Manifest2 = serde_json::from_str(MANIFEST).unwrap();
+        let fragments = split(&manifest);
+
+        let mut v = fragments.clone();
+        v[2].requires.pop();
+        assert!(matches!(recombine(&v), Err(Error::Undeclared { .. })));
+
+        let mut v = fragments.clone();
+        v[1].requires.push("image".to_owned());
+        assert!(matches!(recombine(&v), Err(Error::Unused { .. })));
+
+        let v = fragments[1..].to_vec();
+        assert!(matches!(recombine(&v), Err(Error::Missing { .. })));
+
+        let mut v = fragments.clone();
+        v.push(fragments[0].clone());
+        assert!(matches!(recombine(&v), Err(Error::Duplicate(ref v)) if v == "build"));
+
+        let mut v = fragments.clone();
+        v[1].sources.get_mut("org.osbuild.curl").unwrap().insert(
+            "items".to_owned(),
+            serde_json::json!({ "sha256:bb": "https://example.org/bb" }),
+        );
+        assert!(matches!(recombine(&v), Err(Error::Conflict { .. })));
+
+        let mut v = fragments.clone();
+        v[0].pipeline.build = Some("name:image".to_owned());
+        v[0].requires.push("image".to_owned());
+        assert!(matches!(recombine(&v), Err(Error::Cycle)));
+
+        let mut v = fragments[0].clone();
+        v.pipeline.name = "../x".to_owned();
+        assert!(matches!(v.file_name(), Err(Error::Name(_))));
+        assert!(Fragment::parse(&fragments[0].serialize()).unwrap() == fragments[0]);
+    }
+}
@@ -0,0 +1,201 @@
+//! Manifest Store
+//!
+//! Long-running services hold many manifests in memory, often the same
+//! manifest submitted several times. This module provides a thread-safe
+//! store of parsed manifests keyed by their content identifiers, sharing
+//! each distinct manifest via `Arc`. The store keeps a reverse index from
+//! the stage and source types to the manifests using them, keyed by
+//! interned `Symbol`s, so lookups by type do not scan the store and the
+//! index stays small even with thousands of entries.
+
+use crate::hash::Digest;
+use crate::manifest::{Manifest, ManifestFormat, Symbol};
+use crate::normalize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, PoisonError, RwLock};
+
+struct Entry {
+    manifest: Arc<Manifest>,
+    names: Vec<Symbol>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Digest, Entry>,
+    index: HashMap<Symbol, HashSet<Digest>>,
+}
+
+/// Manifest Store
+#[derive(Default)]
+pub struct ManifestStore {
+    inner: RwLock<Inner>,
+}
+
+impl ManifestStore {
+    /// Create Manifest Store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Content Identifier
+    ///
+    /// Return the SHA-256 digest of the canonical encoding of the
+    /// normalized manifest, as fed into `Manifest::semantic_hash()`, which
+    /// identifies it in the store. Manifests equal under
+    /// `Manifest::semantic_eq()` have the same identifier.
+    pub fn id(manifest: &Manifest) -> Digest {
+        Digest::sha256(&normalize::canonical(manifest))
+    }
+
+    /// Insert Manifest
+    ///
+    /// Add the manifest to the store, unless it already holds a
+    /// semantically equal manifest. Returns the content identifier and the
+    /// stored manifest.
+    pub fn insert(&self, manifest: Manifest) -> (Digest, Arc<Manifest>) {
+        let id = Self::id(&manifest);
+        if let Some(v) = self.get(&id) {
+            return (id, v);
+        }
+
+        let mut names: BTreeSet<Symbol> = BTreeSet::new();
+        for pipeline in manifest.pipelines() {
+            names.extend(pipeline.stages.iter().map(|v| Symbol::new(v.name)));
+        }
+        names.extend(manifest.sources().keys().cloned());
+
+        let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(v) = inner.entries.get(&id) {
+            return (id, v.manifest.clone());
+        }
+        for name in &names {
+            inner
+                .index
+                .entry(name.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        let manifest = Arc::new(manifest);
+        let entry = Entry {
+            manifest: manifest.clone(),
+            names: names.into_iter().collect(),
+        };
+        inner.entries.insert(id.clone(), entry);
+        (id, manifest)
+    }
+
+    /// Get Manifest
+    pub fn get(&self, id: &Digest) -> Option<Arc<Manifest>> {
+        let inner = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+        inner.entries.get(id).map(|v| v.manifest.clone())
+    }
+
+    /// Remove Manifest
+    ///
+    /// Remove the manifest from the store and return it. Types no longer
+    /// used by any manifest are removed from the index.
+    pub fn remove(&self, id: &Digest) -> Option<Arc<Manifest>> {
+        let mut inner = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        let entry = inner.entries.remove(id)?;
+        for name in entry.names {
+            if let Some(ids) = inner.index.get_mut(&name) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    inner.index.remove(&name);
+                }
+            }
+        }
+        Some(entry.manifest)
+    }
+
+    /// Number of Manifests
+    pub fn len(&self) -> usize {
+        let inner = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+        inner.entries.len()
+    }
+
+    /// Check for Emptiness
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Find Manifests by Type
+    ///
+    /// Return the content identifiers of all manifests using a stage or
+    /// source of the given type, in no particular order.
+    pub fn using(&self, name: &str) -> Vec<Digest> {
+        let inner = self.inner.read().unwrap_or_else(PoisonError::into_inner);
+        inner
+            .index
+            .get(name)
+            .map_or_else(Vec::new, |v| v.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Manifest Store
+    #[test]
+    fn verify_store() {
+        let a = Manifest::parse(
+            r#"{
+                "pipeline": { "stages": [{ "name": "org.osbuild.rpm" }] },
+                "sources": { "org.osbuild.files": {} }
+            }"#,
+        )
+        .unwrap();
+        let b = Manifest::parse(
+            r#"{
+                "version": "2",
+                "pipelines": [{ "name": "os", "stages": [{ "type": "org.osbuild.rpm" }] }]
+            }"#,
+        )
+        .unwrap();
+
+        let store = ManifestStore::new();
+        let (ids, manifests): (Vec<_>, Vec<_>) = std::thread::scope(|s| {
+            let threads: Vec<_> = [&a, &b, &a]
+                .into_iter()
+                .map(|v| s.spawn(|| store.insert(v.clone())))
+                .collect();
+            threads.into_iter().map(|v| v.join().unwrap()).unzip()
+        });
+        assert_eq!(store.len(), 2);
+        assert_eq!(ids[0], ids[2]);
+        assert_ne!(ids[0], ids[1]);
+        assert!(Arc::ptr_eq(&manifests[0], &manifests[2]));
+        assert_eq!(*store.get(&ids[1]).unwrap(), b);
+
+        let mut using = store.using("org.osbuild.rpm");
+        using.sort();
+        let mut expected = vec![ids[0].clone(), ids[1].clone()];
+        expected.sort();
+        assert_eq!(using, expected);
+        assert_eq!(store.using("org.osbuild.files"), vec![ids[0].clone()]);
+        assert!(store.using("org.osbuild.tar").is_empty());
+
+        assert_eq!(*store.remove(&ids[0]).unwrap(), a);
+        assert!(store.remove(&ids[0]).is_none());
+        assert!(store.using("org.osbuild.files").is_empty());
+        assert_eq!(store.using("org.osbuild.rpm"), vec![ids[1].clone()]);
+        store.remove(&ids[1]);
+        assert!(store.is_empty());
+        assert!(store.inner.read().unwrap().index.is_empty());
+
+        // Semantically equal manifests share an entry.
+        let stage = |size: &str| {
+            Manifest::parse(&format!(
+                r#"{{ "pipeline": {{ "stages": [{{ "name": "org.osbuild.truncate", "options": {{ "size": {} }} }}] }} }}"#,
+                size,
+            ))
+            .unwrap()
+        };
+        let (c, d) = (stage("1024"), stage("\"1 KiB\""));
+        assert_ne!(c, d);
+        let (id, manifest) = store.insert(c);
+        assert_eq!(store.insert(d), (id, manifest));
+        assert_eq!(store.len(), 1);
+    }
+}
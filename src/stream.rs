@@ -0,0 +1,174 @@
+//! Streaming Manifests
+//!
+//! Manifests can carry large payloads in their sources, like the base64
+//! data of `org.osbuild.inline` items. This module parses manifests from
+//! readers without buffering these payloads: source items are handed to a
+//! callback one at a time, as soon as they are parsed, rather than being
+//! collected into the manifest. Everything else is parsed into the
+//! manifest as usual, so memory use is bounded by the size of the manifest
+//! without its source items, plus the largest single item.
+//!
+//! Source items are the members of the `items` or `urls` objects of a
+//! source. The returned manifest retains these objects, but empty.
+
+use crate::error::Error;
+use crate::manifest::{self, Json, Manifest};
+use serde::de::{DeserializeSeed, MapAccess, Visitor};
+use std::io;
+
+type Map = serde_json::Map<String, Json>;
+
+// State shared by all levels of the deserialization.
+struct Sink<'a, F> {
+    items: &'a mut F,
+    error: Option<io::Error>,
+}
+
+// Nesting level of the object being deserialized.
+#[derive(Clone, Copy)]
+enum Level<'a> {
+    Root,
+    Sources,
+    Source(&'a str),
+    Items(&'a str),
+}
+
+struct Seed<'a, 'b, F> {
+    sink: &'a mut Sink<'b, F>,
+    level: Level<'a>,
+}
+
+impl<'de, F> DeserializeSeed<'de> for Seed<'_, '_, F>
+where
+    F: FnMut(&str, &str, Json) -> io::Result<()>,
+{
+    type Value = Json;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<Json, D::Error> {
+        d.deserialize_map(self)
+    }
+}
+
+impl<'de, F> Visitor<'de> for Seed<'_, '_, F>
+where
+    F: FnMut(&str, &str, Json) -> io::Result<()>,
+{
+    type Value = Json;
+
+    fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_str("an object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut acc = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let level = match self.level {
+                Level::Root if key == "sources" => Level::Sources,
+                Level::Sources => Level::Source(&key),
+                Level::Source(v) if key == "items" || key == "urls" => Level::Items(v),
+                Level::Items(v) => {
+                    let value = map.next_value()?;
+                    if let Err(e) = (self.sink.items)(v, &key, value) {
+                        self.sink.error = Some(e);
+                        return Err(serde::de::Error::custom("source item callback failed"));
+                    }
+                    continue;
+                }
+                _ => {
+                    acc.insert(key, map.next_value()?);
+                    continue;
+                }
+            };
+            let value = map.next_value_seed(Seed {
+                sink: &mut *self.sink,
+                level,
+            })?;
+            acc.insert(key, value);
+        }
+        Ok(Json::Object(acc))
+    }
+}
+
+/// Parse Manifest from Reader
+///
+/// Parse a manifest of either format version from the reader, passing the
+/// source type, identifier, and value of each source item to `items`. If
+/// `items` fails, parsing stops and its error is returned.
+pub fn from_reader<R, F>(reader: R, mut items: F) -> Result<Manifest, Error>
+where
+    R: io::Read,
+    F: FnMut(&str, &str, Json) -> io::Result<()>,
+{
+    let mut sink = Sink {
+        items: &mut items,
+        error: None,
+    };
+    let mut de = serde_json::Deserializer::from_reader(io::BufReader::new(reader));
+    let seed = Seed {
+        sink: &mut sink,
+        level: Level::Root,
+    };
+    let root = seed.deserialize(&mut de).and_then(|v| de.end().map(|_| v));
+    let root = match (root, sink.error) {
+        (_, Some(e)) => return Err(Error::Io(e)),
+        (v, None) => v?,
+    };
+
+    match root.get("version") {
+        None => Ok(Manifest::V1(serde_json::from_value(root)?)),
+        Some(Json::String(v)) if v == "2" => Ok(Manifest::V2(serde_json::from_value(root)?)),
+        Some(v) => Err(manifest::version_error(v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestFormat;
+
+    // Verify Streaming Parser
+    #[test]
+    fn verify_stream() {
+        let text = r#"{
+            "version": "2",
+            "pipelines": [{ "name": "tree" }],
+            "sources": {
+                "org.osbuild.inline": {
+                    "items": {
+                        "sha256:a": { "encoding": "base64", "data": "YQ==" },
+                        "sha256:b": { "encoding": "base64", "data": "Yg==" }
+                    }
+                }
+            }
+        }"#;
+
+        let mut acc = Vec::new();
+        let manifest = from_reader(text.as_bytes(), |source, id, value| {
+            acc.push((source.to_owned(), id.to_owned(), value["data"].clone()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(manifest.version(), 2);
+        assert_eq! {
+            acc,
+            vec![
+                ("org.osbuild.inline".to_owned(), "sha256:a".to_owned(), Json::from("YQ==")),
+                ("org.osbuild.inline".to_owned(), "sha256:b".to_owned(), Json::from("Yg==")),
+            ],
+        }
+        assert_eq! {
+            manifest.sources()["org.osbuild.inline"]["items"],
+            serde_json::json!({}),
+        }
+
+        let r = from_reader(text.as_bytes(), |_, _, _| Err(io::ErrorKind::Other.into()));
+        assert!(matches!(r, Err(Error::Io(_))));
+
+        let r = from_reader(r#"{ "pipeline": {} } x"#.as_bytes(), |_, _, _| Ok(()));
+        assert!(matches!(r, Err(Error::Parse { .. })));
+        let r = from_reader(r#"{ "version": "3" }"#.as_bytes(), |_, _, _| Ok(()));
+        assert!(matches!(r, Err(Error::Version(v)) if v == "3"));
+        let r = from_reader(r#"{ "pipeline": {} }"#.as_bytes(), |_, _, _| Ok(()));
+        assert_eq!(r.unwrap().version(), 1);
+    }
+}
@@ -0,0 +1,400 @@
+//! Interned Symbols
+//!
+//! Manifests repeat the same few module names, like `org.osbuild.rpm`, for
+//! every stage and source. This module provides a shared string type for
+//! such names. Names of osbuild modules are interned, so all their
+//! occurrences share a single allocation and mostly compare by pointer.
+//! Other names are stored like any shared string.
+//!
+//! Well-known module names are interned up front and looked up without
+//! locking. Other module names are interned on demand, in a bounded set of
+//! shards guarded by read-mostly locks. Once a shard is full, names no
+//! longer used by any symbol are released, so untrusted input cannot
+//! permanently displace other names.
+//!
+//! Interning requires the `std` feature. Without it, symbols are never
+//! shared, but behave the same otherwise.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+
+// Prefix of the names that are interned.
+#[cfg(feature = "std")]
+const PREFIX: &str = "org.osbuild.";
+
+// Well-known names of stages, assemblers, sources, inputs, devices, and
+// mounts, sorted. These are always interned.
+#[cfg(feature = "std")]
+const KNOWN: &[&str] = &[
+    "org.osbuild.btrfs",
+    "org.osbuild.chmod",
+    "org.osbuild.chown",
+    "org.osbuild.chrony",
+    "org.osbuild.cloud-init",
+    "org.osbuild.containers",
+    "org.osbuild.containers-storage",
+    "org.osbuild.copy",
+    "org.osbuild.curl",
+    "org.osbuild.dnf.config",
+    "org.osbuild.dracut",
+    "org.osbuild.dracut.conf",
+    "org.osbuild.erofs",
+    "org.osbuild.ext4",
+    "org.osbuild.fat",
+    "org.osbuild.files",
+    "org.osbuild.firewall",
+    "org.osbuild.first-boot",
+    "org.osbuild.fix-bls",
+    "org.osbuild.fstab",
+    "org.osbuild.gcp.guest-agent.conf",
+    "org.osbuild.groups",
+    "org.osbuild.grub2",
+    "org.osbuild.grub2.inst",
+    "org.osbuild.grub2.iso",
+    "org.osbuild.grub2.legacy",
+    "org.osbuild.gunzip",
+    "org.osbuild.gzip",
+    "org.osbuild.hostname",
+    "org.osbuild.ignition",
+    "org.osbuild.implantisomd5",
+    "org.osbuild.inline",
+    "org.osbuild.isolinux",
+    "org.osbuild.kernel-cmdline",
+    "org.osbuild.keymap",
+    "org.osbuild.kickstart",
+    "org.osbuild.librepo",
+    "org.osbuild.locale",
+    "org.osbuild.loopback",
+    "org.osbuild.luks2",
+    "org.osbuild.luks2.format",
+    "org.osbuild.lvm2.create",
+    "org.osbuild.lvm2.lv",
+    "org.osbuild.lvm2.metadata",
+    "org.osbuild.machine-id",
+    "org.osbuild.mkdir",
+    "org.osbuild.mkfs.btrfs",
+    "org.osbuild.mkfs.ext4",
+    "org.osbuild.mkfs.fat",
+    "org.osbuild.mkfs.xfs",
+    "org.osbuild.modprobe",
+    "org.osbuild.nm.conn",
+    "org.osbuild.noop",
+    "org.osbuild.oci-archive",
+    "org.osbuild.ostree",
+    "org.osbuild.ostree.aleph",
+    "org.osbuild.ostree.checkout",
+    "org.osbuild.ostree.commit",
+    "org.osbuild.ostree.config",
+    "org.osbuild.ostree.deploy",
+    "org.osbuild.ostree.deploy.container",
+    "org.osbuild.ostree.deployment",
+    "org.osbuild.ostree.fillvar",
+    "org.osbuild.ostree.init-fs",
+    "org.osbuild.ostree.os-init",
+    "org.osbuild.ostree.passwd",
+    "org.osbuild.ostree.pull",
+    "org.osbuild.ostree.remotes",
+    "org.osbuild.ostree.selinux",
+    "org.osbuild.parted",
+    "org.osbuild.pipeline",
+    "org.osbuild.qemu",
+    "org.osbuild.rawfs",
+    "org.osbuild.rpm",
+    "org.osbuild.rpm.install",
+    "org.osbuild.script",
+    "org.osbuild.selinux",
+    "org.osbuild.selinux.config",
+    "org.osbuild.sfdisk",
+    "org.osbuild.sgdisk",
+    "org.osbuild.skopeo",
+    "org.osbuild.skopeo-index",
+    "org.osbuild.squashfs",
+    "org.osbuild.sshd.config",
+    "org.osbuild.sysconfig",
+    "org.osbuild.systemd",
+    "org.osbuild.systemd.unit",
+    "org.osbuild.tar",
+    "org.osbuild.timezone",
+    "org.osbuild.tmpfiles.d",
+    "org.osbuild.tree",
+    "org.osbuild.truncate",
+    "org.osbuild.tuned",
+    "org.osbuild.update-crypto-policies",
+    "org.osbuild.users",
+    "org.osbuild.xfs",
+    "org.osbuild.xorrisofs",
+    "org.osbuild.xz",
+    "org.osbuild.zipl",
+    "org.osbuild.zstd",
+];
+
+// Number of shards of names interned on demand, and the maximum number of
+// names of each. Names beyond it are not shared.
+#[cfg(feature = "std")]
+const SHARDS: usize = 16;
+#[cfg(feature = "std")]
+const CAPACITY: usize = 256;
+
+#[cfg(feature = "std")]
+type Shard = std::sync::RwLock<alloc::collections::BTreeSet<Arc<str>>>;
+
+#[cfg(feature = "std")]
+static SYMBOLS: [Shard; SHARDS] =
+    [const { std::sync::RwLock::new(alloc::collections::BTreeSet::new()) }; SHARDS];
+
+// Symbols of the well-known names, in the order of `KNOWN`.
+#[cfg(feature = "std")]
+fn known() -> &'static [Arc<str>] {
+    static KNOWN_SYMBOLS: std::sync::OnceLock<alloc::vec::Vec<Arc<str>>> =
+        std::sync::OnceLock::new();
+    KNOWN_SYMBOLS.get_or_init(|| KNOWN.iter().map(|v| Arc::from(*v)).collect())
+}
+
+// Select the shard of a name, via FNV-1a.
+#[cfg(feature = "std")]
+fn shard(name: &str) -> &'static Shard {
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |acc, v| {
+        (acc ^ v as u64).wrapping_mul(0x100000001b3)
+    });
+    &SYMBOLS[hash as usize % SHARDS]
+}
+
+// Intern a name that is not well-known.
+#[cfg(feature = "std")]
+fn intern(name: &str) -> Arc<str> {
+    use std::sync::PoisonError;
+
+    let shard = shard(name);
+    if let Some(v) = shard
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(name)
+    {
+        return v.clone();
+    }
+
+    let mut symbols = shard.write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(v) = symbols.get(name) {
+        return v.clone();
+    }
+    let v: Arc<str> = Arc::from(name);
+    if symbols.len() >= CAPACITY {
+        // Release names only referenced by the interner.
+        symbols.retain(|v| Arc::strong_count(v) > 1);
+    }
+    if symbols.len() < CAPACITY {
+        symbols.insert(v.clone());
+    }
+    v
+}
+
+/// Interned Symbol
+///
+/// A shared, immutable string. Symbols dereference to `str`, and compare,
+/// hash, and order like it.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    /// Create Symbol
+    ///
+    /// Create a symbol with the given name, interning it if it names an
+    /// osbuild module.
+    pub fn new(name: &str) -> Self {
+        #[cfg(feature = "std")]
+        if name.starts_with(PREFIX) {
+            return match KNOWN.binary_search(&name) {
+                Ok(i) => Self(known()[i].clone()),
+                Err(_) => Self(intern(name)),
+            };
+        }
+
+        Self(Arc::from(name))
+    }
+
+    /// Name of the Symbol
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Symbol {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl core::ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::borrow::Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::fmt::Debug for Symbol {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_str().fmt(fmt)
+    }
+}
+
+impl core::fmt::Display for Symbol {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl core::hash::Hash for Symbol {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(v: &str) -> Self {
+        Self::new(v)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(v: String) -> Self {
+        Self::new(&v)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Symbol;
+
+            fn expecting(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                fmt.write_str("a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Symbol, E> {
+                Ok(Symbol::new(v))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // Verify Interned Symbols
+    #[test]
+    fn verify_symbol() {
+        let a = Symbol::new("org.osbuild.rpm");
+        let b = Symbol::from(String::from("org.osbuild.rpm"));
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_eq!(a, "org.osbuild.rpm");
+        assert_eq!(
+            format!("{} {:?}", a, a),
+            "org.osbuild.rpm \"org.osbuild.rpm\""
+        );
+
+        let c = Symbol::new("custom");
+        assert!(!Arc::ptr_eq(&c.0, &Symbol::new("custom").0));
+        assert_eq!(c, Symbol::new("custom"));
+        assert!(c < a);
+        assert_eq!(Symbol::default(), "");
+
+        // Unknown module names are interned as well.
+        let d = Symbol::new("org.osbuild.verify-symbol");
+        assert!(Arc::ptr_eq(
+            &d.0,
+            &Symbol::new("org.osbuild.verify-symbol").0
+        ));
+    }
+
+    // Verify Well-Known Names
+    #[test]
+    fn verify_known() {
+        assert!(KNOWN.windows(2).all(|v| v[0] < v[1]));
+        assert!(KNOWN.iter().all(|v| v.starts_with(PREFIX)));
+        #[cfg(feature = "json")]
+        for entry in crate::catalog::CATALOG {
+            assert!(KNOWN.contains(&entry.name), "{}", entry.name);
+        }
+    }
+
+    // Verify Bounded Interning
+    #[test]
+    fn verify_capacity() {
+        // Junk names fill their shards, but are released once unused, so
+        // names interned later are still shared.
+        for i in 0..SHARDS * CAPACITY * 2 {
+            Symbol::new(&format!("org.osbuild.junk-{}", i));
+        }
+        let a = Symbol::new("org.osbuild.verify-capacity");
+        assert!(Arc::ptr_eq(
+            &a.0,
+            &Symbol::new("org.osbuild.verify-capacity").0
+        ));
+        assert!(SYMBOLS.iter().all(|v| v.read().unwrap().len() <= CAPACITY));
+    }
+}
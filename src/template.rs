@@ -0,0 +1,267 @@
+//! Parameterized Templates
+//!
+//! Templates are manifests with typed parameters, instantiated into
+//! concrete manifests given values for the parameters. They are a light
+//! alternative to the manifest preprocessor for generators written in
+//! Rust, which need to vary a few values of an otherwise fixed manifest.
+//!
+//! A template document declares its parameters next to the manifest:
+//!
+//! ```json
+//! {
+//!   "parameters": {
+//!     "hostname": { "type": "string", "default": "localhost" },
+//!     "size": { "type": "size", "default": "10 GiB" },
+//!     "packages": { "type": "packages" }
+//!   },
+//!   "manifest": { ... }
+//! }
+//! ```
+//!
+//! Strings of the manifest consisting of a single reference like
+//! `${size}` are replaced by the value of the parameter, keeping its type.
+//! References within longer strings are replaced by the value formatted as
+//! string. Sizes are given in bytes or with a unit suffix, and are always
+//! substituted as number of bytes.
+
+use crate::estimate;
+use crate::manifest::{Json, Manifest, Object};
+
+/// Parameter Types
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    String,
+    Integer,
+    Boolean,
+    /// Sizes in bytes, like `1073741824` or `"1 GiB"`.
+    Size,
+    /// Lists of package specifications.
+    Packages,
+}
+
+/// Parameter Declaration
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameter {
+    #[serde(rename = "type")]
+    pub kind: Kind,
+    /// Value of the parameter if none is given. Parameters without default
+    /// are required.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Json>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Template Error
+#[derive(Debug)]
+pub enum Error {
+    /// The template document is not valid.
+    Template(serde_json::Error),
+    /// A value was given for an undeclared parameter.
+    Unknown(String),
+    /// The manifest references an undeclared parameter.
+    Undeclared(String),
+    /// No value was given for a required parameter.
+    Missing(String),
+    /// The value of a parameter does not match its type.
+    Type(String, Kind),
+    /// The instantiated manifest is not valid.
+    Manifest(crate::error::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Template(v) => write!(fmt, "invalid template: {}", v),
+            Error::Unknown(v) => write!(fmt, "unknown parameter `{}`", v),
+            Error::Undeclared(v) => write!(fmt, "reference to undeclared parameter `{}`", v),
+            Error::Missing(v) => write!(fmt, "missing value of parameter `{}`", v),
+            Error::Type(v, kind) => write!(fmt, "parameter `{}` must be of type {:?}", v, kind),
+            Error::Manifest(v) => write!(fmt, "invalid manifest: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Manifest Template
+#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    #[serde(default)]
+    pub parameters: Object<Parameter>,
+    pub manifest: Json,
+}
+
+// Check a value against the parameter type, normalizing sizes to bytes.
+fn check(name: &str, kind: Kind, value: &Json) -> Result<Json, Error> {
+    let valid = match kind {
+        Kind::String => value.is_string(),
+        Kind::Integer => value.is_i64() || value.is_u64(),
+        Kind::Boolean => value.is_boolean(),
+        Kind::Size => {
+            return estimate::parse_size(value)
+                .map(Json::from)
+                .ok_or_else(|| Error::Type(name.to_owned(), kind));
+        }
+        Kind::Packages => value
+            .as_array()
+            .is_some_and(|v| v.iter().all(Json::is_string)),
+    };
+    match valid {
+        true => Ok(value.clone()),
+        false => Err(Error::Type(name.to_owned(), kind)),
+    }
+}
+
+// Substitute all parameter references in the value.
+fn substitute(value: &mut Json, values: &Object<Json>) -> Result<(), Error> {
+    match value {
+        Json::String(v) => {
+            let lookup = |name: &str| {
+                values
+                    .get(name)
+                    .ok_or_else(|| Error::Undeclared(name.to_owned()))
+            };
+            if let Some(name) = v.strip_prefix("${").and_then(|v| v.strip_suffix('}')) {
+                if !name.contains('}') {
+                    *value = lookup(name)?.clone();
+                    return Ok(());
+                }
+            }
+
+            let mut acc = String::new();
+            let mut rest = v.as_str();
+            while let Some((head, tail)) = rest.split_once("${") {
+                let Some((name, tail)) = tail.split_once('}') else {
+                    break;
+                };
+                acc += head;
+                match lookup(name)? {
+                    Json::String(v) => acc += v,
+                    v => acc += &v.to_string(),
+                }
+                rest = tail;
+            }
+            acc += rest;
+            *v = acc;
+            Ok(())
+        }
+        Json::Array(v) => v.iter_mut().try_for_each(|v| substitute(v, values)),
+        Json::Object(v) => v.values_mut().try_for_each(|v| substitute(v, values)),
+        _ => Ok(()),
+    }
+}
+
+impl Template {
+    /// Parse Template
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        serde_json::from_str(text).map_err(Error::Template)
+    }
+
+    /// Instantiate Template
+    ///
+    /// Substitute the given parameter values, or the defaults of parameters
+    /// without value, and parse the result as manifest.
+    pub fn instantiate(&self, params: &Object<Json>) -> Result<Manifest, Error> {
+        if let Some(name) = params.keys().find(|v| !self.parameters.contains_key(*v)) {
+            return Err(Error::Unknown(name.clone()));
+        }
+
+        let mut values = Object::new();
+        for (name, parameter) in &self.parameters {
+            let value = params
+                .get(name)
+                .or(parameter.default.as_ref())
+                .ok_or_else(|| Error::Missing(name.clone()))?;
+            values.insert(name.clone(), check(name, parameter.kind, value)?);
+        }
+
+        let mut manifest = self.manifest.clone();
+        substitute(&mut manifest, &values)?;
+        Manifest::parse(&manifest.to_string()).map_err(Error::Manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestFormat;
+
+    // Verify Template Instantiation
+    #[test]
+    fn verify_instantiate() {
+        let template = Template::parse(
+            r#"{
+                "parameters": {
+                    "hostname": { "type": "string", "default": "localhost" },
+                    "size": { "type": "size", "default": "1 KiB" },
+                    "packages": { "type": "packages" }
+                },
+                "manifest": {
+                    "version": "2",
+                    "pipelines": [{
+                        "name": "os",
+                        "stages": [
+                            { "type": "org.osbuild.hostname", "options": { "hostname": "${hostname}" } },
+                            { "type": "org.osbuild.test", "options": { "size": "${size}", "motd": "${hostname}: ${size} bytes" } },
+                            { "type": "org.osbuild.test", "options": { "packages": "${packages}" } }
+                        ]
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let params: Object<Json> = [("packages".to_owned(), serde_json::json!(["vim"]))].into();
+        let manifest = template.instantiate(&params).unwrap();
+        let pipelines = manifest.pipelines();
+        let options: Vec<Json> = pipelines[0]
+            .stages
+            .iter()
+            .map(|v| Json::Object(v.options.clone().into_iter().collect()))
+            .collect();
+        assert_eq! {
+            options,
+            vec![
+                serde_json::json!({ "hostname": "localhost" }),
+                serde_json::json!({ "size": 1024, "motd": "localhost: 1024 bytes" }),
+                serde_json::json!({ "packages": ["vim"] }),
+            ],
+        }
+
+        assert!(matches!(
+            template.instantiate(&Object::new()),
+            Err(Error::Missing(v)) if v == "packages",
+        ));
+        let mut invalid = params.clone();
+        invalid.insert("size".to_owned(), Json::from("huge"));
+        assert!(matches!(
+            template.instantiate(&invalid),
+            Err(Error::Type(v, Kind::Size)) if v == "size",
+        ));
+        invalid.insert("other".to_owned(), Json::Null);
+        assert!(matches!(
+            template.instantiate(&invalid),
+            Err(Error::Unknown(v)) if v == "other",
+        ));
+
+        let mut undeclared = template.clone();
+        undeclared.manifest["pipelines"][0]["name"] = Json::from("${name}");
+        assert!(matches!(
+            undeclared.instantiate(&params),
+            Err(Error::Undeclared(v)) if v == "name",
+        ));
+        let mut broken = template;
+        broken.manifest["version"] = Json::from("${hostname}");
+        assert!(matches!(
+            broken.instantiate(&params),
+            Err(Error::Manifest(_)),
+        ));
+    }
+}
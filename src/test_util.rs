@@ -0,0 +1,136 @@
+//! Test Utilities
+//!
+//! This module helps crates generating manifests to write golden tests.
+//! Manifests are compared in their canonical form, so tests do not break
+//! on member order or default values, and mismatches are reported as
+//! structural diffs rather than two walls of JSON.
+//!
+//! Snapshots are stored as canonical, pretty-printed JSON files. Missing
+//! snapshots are written on first use. Existing snapshots are rewritten if
+//! the `R_OSBUILD_UPDATE_SNAPSHOTS` environment variable is set to `1`,
+//! after which the changes can be reviewed via version control.
+
+use crate::diff::{self, Change, Ignore};
+use crate::manifest::{Json, Manifest};
+use crate::normalize;
+use std::path::Path;
+
+/// Snapshot Update Variable
+pub const UPDATE_VARIABLE: &str = "R_OSBUILD_UPDATE_SNAPSHOTS";
+
+/// Canonical Form
+///
+/// Return the canonical form of a manifest of either version. Version-1
+/// manifests are normalized, version-2 manifests have their members
+/// sorted.
+pub fn canonical(manifest: &Manifest) -> Json {
+    match manifest {
+        Manifest::V1(v) => normalize::normalize(v),
+        Manifest::V2(v) => {
+            let mut json = serde_json::to_value(v).expect("manifests always serialize");
+            json.sort_all_objects();
+            json
+        }
+    }
+}
+
+// Render changes, one per line, for panic messages.
+fn render(changes: &[Change]) -> String {
+    changes
+        .iter()
+        .map(|v| format!("  {}\n", v))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Assert Manifest Equality
+///
+/// Panic with the structural diff if the manifests differ in their
+/// canonical forms, ignoring the differences matched by the rules.
+#[track_caller]
+pub fn assert_manifest_eq(actual: &Manifest, expected: &Manifest, rules: &[Ignore]) {
+    let changes = diff::diff_with(&canonical(expected), &canonical(actual), rules);
+    if !changes.is_empty() {
+        panic!(
+            "manifests differ ({} changes from expected to actual):\n{}",
+            changes.len(),
+            render(&changes),
+        );
+    }
+}
+
+/// Assert Snapshot
+///
+/// Compare the manifest to the snapshot stored at the path, ignoring the
+/// differences matched by the rules. The snapshot is written if it does
+/// not exist, or if updates are requested via `UPDATE_VARIABLE`.
+#[track_caller]
+pub fn assert_snapshot(path: impl AsRef<Path>, actual: &Manifest, rules: &[Ignore]) {
+    let path = path.as_ref();
+    let json = canonical(actual);
+    let update = std::env::var(UPDATE_VARIABLE).is_ok_and(|v| v == "1");
+
+    if update || !path.exists() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+        }
+        let mut text = serde_json::to_string_pretty(&json).expect("JSON always serializes");
+        text.push('\n');
+        std::fs::write(path, text)
+            .unwrap_or_else(|e| panic!("cannot write snapshot {}: {}", path.display(), e));
+        return;
+    }
+
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("cannot read snapshot {}: {}", path.display(), e));
+    let expected = Manifest::parse(&text)
+        .unwrap_or_else(|e| panic!("invalid snapshot {}: {}", path.display(), e));
+    let changes = diff::diff_with(&canonical(&expected), &json, rules);
+    if !changes.is_empty() {
+        panic!(
+            "manifest does not match snapshot {} ({} changes, set {}=1 to update):\n{}",
+            path.display(),
+            changes.len(),
+            UPDATE_VARIABLE,
+            render(&changes),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify Snapshot Assertions
+    #[test]
+    fn verify_snapshot() {
+        let path = std::env::temp_dir()
+            .join(format!("r-osbuild-snapshot-{}", std::process::id()))
+            .join("base.json");
+        let manifest = Manifest::parse(
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.locale", "options": { "language": "en_US" } }] } }"#,
+        )
+        .unwrap();
+
+        assert_snapshot(&path, &manifest, &[]);
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("}\n"));
+        assert_snapshot(&path, &manifest, &[]);
+
+        let changed = Manifest::parse(
+            r#"{ "pipeline": { "stages": [{ "name": "org.osbuild.locale", "options": { "language": "de_DE" } }] } }"#,
+        )
+        .unwrap();
+        assert_snapshot(&path, &changed, &[Ignore::Key("language".to_owned())]);
+        let message = std::panic::catch_unwind(|| assert_snapshot(&path, &changed, &[]))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(message.contains("~ /pipeline/stages/0/options/language: \"en_US\" -> \"de_DE\""));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let v2 = Manifest::parse(r#"{ "version": "2", "pipelines": [{ "name": "a" }] }"#).unwrap();
+        assert_manifest_eq(&v2, &v2, &[]);
+        assert!(std::panic::catch_unwind(|| assert_manifest_eq(&v2, &manifest, &[])).is_err());
+    }
+}
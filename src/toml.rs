@@ -0,0 +1,813 @@
+//! TOML Encoding
+//!
+//! This module implements a parser and a serializer for TOML documents,
+//! mapping them to and from JSON values. This allows reusing the serde
+//! integration of JSON values for TOML based formats, like blueprints,
+//! without an additional dependency.
+//!
+//! The parser supports the entire TOML syntax used in practice: tables,
+//! arrays of tables, dotted and quoted keys, all string flavors, integers in
+//! all bases, floats, booleans, arrays, and inline tables. Date and time
+//! values have no JSON equivalent and are represented as strings. The
+//! serializer is the inverse, except that JSON `null` has no TOML
+//! representation and is skipped.
+
+use crate::manifest::Json;
+
+type Map = serde_json::Map<String, Json>;
+
+// Maximum nesting of tables and arrays, matching the JSON parser.
+const MAX_DEPTH: usize = 128;
+
+/// TOML Error
+///
+/// This describes a syntax error in a TOML document, or a JSON value that
+/// cannot be represented as TOML. The line number is 1-based, or 0 if the
+/// error is not related to a location in a document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(fmt, "line {}: {}", self.line, self.message)
+        } else {
+            write!(fmt, "{}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+fn is_bare(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-'
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        Err(Error {
+            line: self.line,
+            message: message.to_owned(),
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.data[self.pos..].starts_with(s.as_bytes())
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == b'\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", c as char))
+        }
+    }
+
+    // Skip spaces, tabs, and comments, but not newlines.
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' => {
+                    self.bump();
+                }
+                b'#' => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    // Skip all whitespace, including newlines and comments.
+    fn skip_all(&mut self) {
+        loop {
+            self.skip_space();
+            match self.peek() {
+                Some(b'\n') | Some(b'\r') => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    // Expect the end of a line, allowing trailing whitespace and comments.
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_space();
+        match self.peek() {
+            None => Ok(()),
+            Some(b'\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(b'\r') if self.data.get(self.pos + 1) == Some(&b'\n') => {
+                self.bump();
+                self.bump();
+                Ok(())
+            }
+            _ => self.error("expected end of line"),
+        }
+    }
+
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut acc = Vec::new();
+        loop {
+            self.skip_space();
+            let segment = match self.peek() {
+                Some(b'"') => self.basic_string()?,
+                Some(b'\'') => self.literal_string()?,
+                Some(c) if is_bare(c) => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(is_bare) {
+                        self.bump();
+                    }
+                    String::from_utf8_lossy(&self.data[start..self.pos]).into_owned()
+                }
+                _ => return self.error("expected key"),
+            };
+            acc.push(segment);
+            self.skip_space();
+            if self.peek() == Some(b'.') {
+                self.bump();
+            } else {
+                return Ok(acc);
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        let c = match self.bump() {
+            Some(b'b') => '\u{8}',
+            Some(b't') => '\t',
+            Some(b'n') => '\n',
+            Some(b'f') => '\u{c}',
+            Some(b'r') => '\r',
+            Some(b'e') => '\u{1b}',
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(c @ (b'x' | b'u' | b'U')) => {
+                let n = match c {
+                    b'x' => 2,
+                    b'u' => 4,
+                    _ => 8,
+                };
+                let hex = self
+                    .data
+                    .get(self.pos..self.pos + n)
+                    .and_then(|v| std::str::from_utf8(v).ok())
+                    .and_then(|v| u32::from_str_radix(v, 16).ok())
+                    .and_then(char::from_u32);
+                match hex {
+                    Some(v) => {
+                        self.pos += n;
+                        v
+                    }
+                    None => return self.error("invalid unicode escape"),
+                }
+            }
+            _ => return self.error("invalid escape sequence"),
+        };
+        Ok(c)
+    }
+
+    // Read the raw UTF-8 character at the current position.
+    fn utf8_char(&mut self) -> Result<char, Error> {
+        let rest = &self.data[self.pos..];
+        let n = match rest[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let c = rest
+            .get(..n)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .and_then(|v| v.chars().next());
+        match c {
+            Some(c) => {
+                for _ in 0..n {
+                    self.bump();
+                }
+                Ok(c)
+            }
+            None => self.error("invalid UTF-8"),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        let multi = self.starts_with("\"\"\"");
+        let mut acc = String::new();
+
+        if multi {
+            self.pos += 3;
+            // A newline directly after the delimiter is trimmed.
+            if self.starts_with("\r\n") {
+                self.bump();
+            }
+            if self.peek() == Some(b'\n') {
+                self.bump();
+            }
+        } else {
+            self.bump();
+        }
+
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string"),
+                Some(b'\n') if !multi => return self.error("unterminated string"),
+                Some(b'"') if !multi => {
+                    self.bump();
+                    return Ok(acc);
+                }
+                Some(b'"') if self.starts_with("\"\"\"") => {
+                    // Up to two quotes may directly precede the delimiter.
+                    let mut n = 3;
+                    while n < 5 && self.data.get(self.pos + n) == Some(&b'"') {
+                        n += 1;
+                    }
+                    for _ in 3..n {
+                        acc.push('"');
+                    }
+                    self.pos += n;
+                    return Ok(acc);
+                }
+                Some(b'\\') => {
+                    self.bump();
+                    if multi && self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                        // Line-ending backslash trims all following
+                        // whitespace.
+                        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                            self.bump();
+                        }
+                    } else {
+                        acc.push(self.escape()?);
+                    }
+                }
+                Some(_) => acc.push(self.utf8_char()?),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        let multi = self.starts_with("'''");
+        let mut acc = String::new();
+
+        if multi {
+            self.pos += 3;
+            if self.starts_with("\r\n") {
+                self.bump();
+            }
+            if self.peek() == Some(b'\n') {
+                self.bump();
+            }
+        } else {
+            self.bump();
+        }
+
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string"),
+                Some(b'\n') if !multi => return self.error("unterminated string"),
+                Some(b'\'') if !multi => {
+                    self.bump();
+                    return Ok(acc);
+                }
+                Some(b'\'') if self.starts_with("'''") => {
+                    let mut n = 3;
+                    while n < 5 && self.data.get(self.pos + n) == Some(&b'\'') {
+                        n += 1;
+                    }
+                    for _ in 3..n {
+                        acc.push('\'');
+                    }
+                    self.pos += n;
+                    return Ok(acc);
+                }
+                Some(_) => acc.push(self.utf8_char()?),
+            }
+        }
+    }
+
+    // Parse numbers, booleans, and dates, all of which are bare tokens.
+    fn scalar(&mut self) -> Result<Json, Error> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || b"+-_.:".contains(&c))
+        {
+            self.bump();
+        }
+        // Date-times may contain a single space between date and time.
+        if self.peek() == Some(b' ')
+            && self.pos - start == 10
+            && self.data.get(self.pos + 1).is_some_and(u8::is_ascii_digit)
+        {
+            self.bump();
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || b"+-.:".contains(&c))
+            {
+                self.bump();
+            }
+        }
+
+        let token = String::from_utf8_lossy(&self.data[start..self.pos]).into_owned();
+        match token.as_str() {
+            "" => return self.error("expected value"),
+            "true" => return Ok(Json::Bool(true)),
+            "false" => return Ok(Json::Bool(false)),
+            _ => {}
+        }
+
+        let digits = token.replace('_', "");
+        let (sign, body) = match digits.strip_prefix('-') {
+            Some(v) => (-1, v),
+            None => (1, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match body.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            return match i64::from_str_radix(&body[2..], radix) {
+                Ok(v) => Ok(Json::from(v * sign)),
+                Err(_) => self.error("invalid integer"),
+            };
+        }
+        if let Ok(v) = digits.parse::<i64>() {
+            return Ok(Json::from(v));
+        }
+        // Dates and times are kept verbatim. Exponents of floats can contain
+        // dashes as well, but never together with colons.
+        let date = token.contains(':') || (token.contains('-') && !token.contains(['e', 'E']));
+        if body.bytes().next().is_some_and(|c| c.is_ascii_digit()) && date {
+            return Ok(Json::String(token));
+        }
+        match digits.parse::<f64>() {
+            Ok(v) if v.is_finite() => {
+                Ok(serde_json::Number::from_f64(v).map_or(Json::Null, Json::Number))
+            }
+            _ => self.error(&format!("invalid value `{}`", token)),
+        }
+    }
+
+    // Parse a value nested in `depth` tables and arrays.
+    fn value(&mut self, depth: usize) -> Result<Json, Error> {
+        if depth > MAX_DEPTH {
+            return self.error("nesting too deep");
+        }
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Json::String),
+            Some(b'\'') => self.literal_string().map(Json::String),
+            Some(b'[') => {
+                self.bump();
+                let mut acc = Vec::new();
+                loop {
+                    self.skip_all();
+                    if self.peek() == Some(b']') {
+                        self.bump();
+                        return Ok(Json::Array(acc));
+                    }
+                    acc.push(self.value(depth + 1)?);
+                    self.skip_all();
+                    match self.peek() {
+                        Some(b',') => {
+                            self.bump();
+                        }
+                        Some(b']') => {}
+                        _ => return self.error("expected `,` or `]`"),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.bump();
+                let mut acc = Map::new();
+                self.skip_space();
+                if self.peek() == Some(b'}') {
+                    self.bump();
+                    return Ok(Json::Object(acc));
+                }
+                loop {
+                    let key = self.key()?;
+                    self.expect(b'=')?;
+                    self.skip_space();
+                    let value = self.value(depth + key.len())?;
+                    self.insert(&mut acc, &key, value)?;
+                    self.skip_space();
+                    match self.bump() {
+                        Some(b',') => {}
+                        Some(b'}') => return Ok(Json::Object(acc)),
+                        _ => return self.error("expected `,` or `}`"),
+                    }
+                }
+            }
+            _ => self.scalar(),
+        }
+    }
+
+    // Insert a value at a dotted key, creating intermediate tables.
+    fn insert(&self, map: &mut Map, key: &[String], value: Json) -> Result<(), Error> {
+        let (last, path) = key.split_last().expect("keys are never empty");
+        let mut map = map;
+        for segment in path {
+            let entry = map
+                .entry(segment.clone())
+                .or_insert_with(|| Json::Object(Map::new()));
+            map = match entry {
+                Json::Object(v) => v,
+                _ => return self.error(&format!("key `{}` is not a table", segment)),
+            };
+        }
+        if map.contains_key(last) {
+            return self.error(&format!("duplicate key `{}`", last));
+        }
+        map.insert(last.clone(), value);
+        Ok(())
+    }
+
+    // Resolve the table a header refers to, creating it if necessary. For
+    // arrays of tables, the last segment gets a new table appended.
+    fn table<'m>(
+        &self,
+        root: &'m mut Map,
+        key: &[String],
+        array: bool,
+    ) -> Result<&'m mut Map, Error> {
+        let mut map = root;
+        for (i, segment) in key.iter().enumerate() {
+            let last = i + 1 == key.len();
+            let entry = map.entry(segment.clone()).or_insert_with(|| {
+                if last && array {
+                    Json::Array(Vec::new())
+                } else {
+                    Json::Object(Map::new())
+                }
+            });
+            if last && array {
+                match entry {
+                    Json::Array(v) => {
+                        v.push(Json::Object(Map::new()));
+                        map = match v.last_mut() {
+                            Some(Json::Object(v)) => v,
+                            _ => unreachable!(),
+                        };
+                    }
+                    _ => return self.error(&format!("key `{}` is not an array", segment)),
+                }
+                continue;
+            }
+            map = match entry {
+                Json::Object(v) => v,
+                // Headers refer to the last table of an array of tables.
+                Json::Array(v) => match v.last_mut() {
+                    Some(Json::Object(v)) => v,
+                    _ => return self.error(&format!("key `{}` is not a table", segment)),
+                },
+                _ => return self.error(&format!("key `{}` is not a table", segment)),
+            };
+        }
+        Ok(map)
+    }
+
+    fn document(&mut self) -> Result<Map, Error> {
+        let mut root = Map::new();
+        let mut current: Vec<String> = Vec::new();
+
+        loop {
+            self.skip_all();
+            match self.peek() {
+                None => return Ok(root),
+                Some(b'[') => {
+                    self.bump();
+                    let array = self.peek() == Some(b'[');
+                    if array {
+                        self.bump();
+                    }
+                    current = self.key()?;
+                    if current.len() > MAX_DEPTH {
+                        return self.error("nesting too deep");
+                    }
+                    self.expect(b']')?;
+                    if array {
+                        self.expect(b']')?;
+                    }
+                    self.table(&mut root, &current, array)?;
+                    self.end_of_line()?;
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.expect(b'=')?;
+                    self.skip_space();
+                    let value = self.value(current.len() + key.len())?;
+                    let table = self.table(&mut root, &current, false)?;
+                    self.insert(table, &key, value)?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+    }
+}
+
+/// Parse TOML Document
+///
+/// Parse a TOML document into a JSON object.
+pub fn from_str(data: &str) -> Result<Json, Error> {
+    let mut parser = Parser {
+        data: data.as_bytes(),
+        pos: 0,
+        line: 1,
+    };
+    parser.document().map(Json::Object)
+}
+
+fn write_key(acc: &mut String, key: &str) {
+    if !key.is_empty() && key.bytes().all(is_bare) {
+        acc.push_str(key);
+    } else {
+        write_string(acc, key);
+    }
+}
+
+fn write_string(acc: &mut String, s: &str) {
+    acc.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => acc.push_str("\\\""),
+            '\\' => acc.push_str("\\\\"),
+            '\n' => acc.push_str("\\n"),
+            '\r' => acc.push_str("\\r"),
+            '\t' => acc.push_str("\\t"),
+            c if c.is_control() => acc.push_str(&format!("\\u{:04x}", c as u32)),
+            c => acc.push(c),
+        }
+    }
+    acc.push('"');
+}
+
+fn write_inline(acc: &mut String, value: &Json) -> Result<(), Error> {
+    match value {
+        Json::Null => {
+            return Err(Error {
+                line: 0,
+                message: "null values cannot be represented in TOML".to_owned(),
+            })
+        }
+        Json::Bool(v) => acc.push_str(if *v { "true" } else { "false" }),
+        Json::Number(v) => acc.push_str(&v.to_string()),
+        Json::String(v) => write_string(acc, v),
+        Json::Array(v) => {
+            acc.push('[');
+            for (i, v) in v.iter().filter(|v| !v.is_null()).enumerate() {
+                if i > 0 {
+                    acc.push_str(", ");
+                }
+                write_inline(acc, v)?;
+            }
+            acc.push(']');
+        }
+        Json::Object(v) => {
+            acc.push('{');
+            for (i, (k, v)) in v.iter().filter(|(_, v)| !v.is_null()).enumerate() {
+                acc.push_str(if i > 0 { ", " } else { " " });
+                write_key(acc, k);
+                acc.push_str(" = ");
+                write_inline(acc, v)?;
+            }
+            acc.push_str(if v.is_empty() { "}" } else { " }" });
+        }
+    }
+    Ok(())
+}
+
+fn is_table_array(value: &Json) -> bool {
+    value
+        .as_array()
+        .is_some_and(|v| !v.is_empty() && v.iter().all(Json::is_object))
+}
+
+fn write_table(acc: &mut String, path: &str, map: &Map) -> Result<(), Error> {
+    for (k, v) in map {
+        if v.is_null() || v.is_object() || is_table_array(v) {
+            continue;
+        }
+        write_key(acc, k);
+        acc.push_str(" = ");
+        write_inline(acc, v)?;
+        acc.push('\n');
+    }
+
+    for (k, v) in map {
+        let mut key = String::new();
+        write_key(&mut key, k);
+        let path = if path.is_empty() {
+            key
+        } else {
+            format!("{}.{}", path, key)
+        };
+
+        match v {
+            Json::Object(v) => {
+                if !acc.is_empty() {
+                    acc.push('\n');
+                }
+                acc.push_str(&format!("[{}]\n", path));
+                write_table(acc, &path, v)?;
+            }
+            Json::Array(list) if is_table_array(v) => {
+                for v in list.iter().filter_map(Json::as_object) {
+                    if !acc.is_empty() {
+                        acc.push('\n');
+                    }
+                    acc.push_str(&format!("[[{}]]\n", path));
+                    write_table(acc, &path, v)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Serialize TOML Document
+///
+/// Serialize a JSON object as TOML document. Scalars and arrays of scalars
+/// are written as key-value pairs, nested objects as tables, and arrays of
+/// objects as arrays of tables. Object members that are `null` are
+/// skipped.
+pub fn to_string(value: &Json) -> Result<String, Error> {
+    let map = match value {
+        Json::Object(v) => v,
+        _ => {
+            return Err(Error {
+                line: 0,
+                message: "TOML documents must be tables".to_owned(),
+            })
+        }
+    };
+
+    let mut acc = String::new();
+    write_table(&mut acc, "", map)?;
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify TOML Parsing
+    #[test]
+    fn verify_parse() {
+        let v = from_str(
+            r#"
+            # comment
+            name = "base"  # trailing comment
+            'quoted key' = 'C:\path'
+            "esc" = "a\tb\u00e9"
+            multi = """one \
+              two"""
+            int = 1_000
+            hex = 0xff
+            neg = -3
+            float = 1.5e3
+            flag = true
+            date = 1979-05-27T07:32:00Z
+            list = [
+                1, 2, # comment
+                3,
+            ]
+            inline = { a = 1, b.c = "x" }
+
+            [customizations.kernel]
+            append = "nosmt=force"
+
+            [[packages]]
+            name = "bash"
+
+            [[packages]]
+            name = "tmux"
+            version = "*"
+
+            [packages.extra]
+            x = 1
+            "#,
+        )
+        .unwrap();
+
+        assert_eq! {
+            v,
+            serde_json::json!({
+                "name": "base",
+                "quoted key": "C:\\path",
+                "esc": "a\tb\u{e9}",
+                "multi": "one two",
+                "int": 1000,
+                "hex": 255,
+                "neg": -3,
+                "float": 1500.0,
+                "flag": true,
+                "date": "1979-05-27T07:32:00Z",
+                "list": [1, 2, 3],
+                "inline": { "a": 1, "b": { "c": "x" } },
+                "customizations": { "kernel": { "append": "nosmt=force" } },
+                "packages": [
+                    { "name": "bash" },
+                    { "name": "tmux", "version": "*", "extra": { "x": 1 } },
+                ],
+            }),
+        }
+    }
+
+    // Verify TOML Parse Errors
+    #[test]
+    fn verify_errors() {
+        assert_eq!(from_str("a = 1\na = 2").unwrap_err().line, 2);
+        assert_eq!(from_str("a = \"x").unwrap_err().line, 1);
+        assert_eq!(from_str("a = 1 b = 2").unwrap_err().line, 1);
+        assert_eq!(from_str("a = 1\n[a]").unwrap_err().line, 2);
+        assert!(from_str("a = [1 2]").is_err());
+        assert!(from_str("a = nope").is_err());
+
+        let nested = format!("a = {}{}", "[".repeat(50_000), "]".repeat(50_000));
+        assert!(from_str(&nested).is_err());
+        let nested = format!("a = {}1{}", "{b = ".repeat(50_000), "}".repeat(50_000));
+        assert!(from_str(&nested).is_err());
+        let nested = format!("[{}]\na = 1", vec!["a"; 50_000].join("."));
+        assert!(from_str(&nested).is_err());
+        assert!(from_str(&format!("{} = 1", vec!["a"; 50_000].join("."))).is_err());
+        let nested = format!("a = {}{}", "[".repeat(100), "]".repeat(100));
+        assert!(from_str(&nested).is_ok());
+    }
+
+    // Verify TOML Serialization
+    #[test]
+    fn verify_serialize() {
+        let v = serde_json::json!({
+            "name": "base",
+            "list": ["a", "b\"c"],
+            "skip": null,
+            "packages": [{ "name": "bash" }, { "name": "tmux", "version": "*" }],
+            "customizations": {
+                "hostname": "host",
+                "kernel": { "append": "nosmt=force" },
+                "user": [{ "name": "admin", "groups": ["wheel"] }],
+            },
+        });
+        let s = to_string(&v).unwrap();
+
+        assert_eq! {
+            s,
+            r#"name = "base"
+list = ["a", "b\"c"]
+
+[[packages]]
+name = "bash"
+
+[[packages]]
+name = "tmux"
+version = "*"
+
+[customizations]
+hostname = "host"
+
+[customizations.kernel]
+append = "nosmt=force"
+
+[[customizations.user]]
+name = "admin"
+groups = ["wheel"]
+"#,
+        }
+
+        let mut expected = v.clone();
+        expected.as_object_mut().unwrap().remove("skip");
+        assert_eq!(from_str(&s).unwrap(), expected);
+    }
+}
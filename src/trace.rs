@@ -0,0 +1,236 @@
+//! Tracing Instrumentation
+//!
+//! The parser, validator, and fetchers of this crate are instrumented with
+//! spans and events, which are reported to a process-wide subscriber. A
+//! build service installs a subscriber that forwards them to its tracing
+//! system, so traces of builds include this layer. Spans carry manifest,
+//! pipeline, and source identifiers as fields.
+//!
+//! Without a subscriber, instrumentation costs a single atomic load, and
+//! fields are not even formatted. Without the `trace` feature, it is
+//! compiled out entirely.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Fields of Spans and Events
+///
+/// The fields as pairs of names and formatted values.
+pub type Fields = Vec<(&'static str, String)>;
+
+/// Verbosity Levels
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Span Description
+///
+/// A span is a period of time of the work of a thread, like parsing a
+/// manifest. Spans nest, and the innermost span entered on a thread is its
+/// parent for new spans and events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub fields: Fields,
+}
+
+/// Event Description
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub level: Level,
+    pub span: Option<u64>,
+    pub message: &'static str,
+    pub fields: Fields,
+}
+
+/// Subscriber
+///
+/// Receivers of spans and events. Subscribers are called on the thread
+/// doing the work, and must not block it for long.
+pub trait Subscriber: Send + Sync {
+    /// Enter Span
+    fn enter(&self, span: &Span);
+
+    /// Exit Span
+    ///
+    /// Called with the span previously entered, when its work is done.
+    fn exit(&self, span: &Span);
+
+    /// Record Event
+    fn event(&self, event: &Event);
+}
+
+static SUBSCRIBER: OnceLock<Box<dyn Subscriber>> = OnceLock::new();
+static IDS: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Install Subscriber
+///
+/// Install the process-wide subscriber. It can be installed only once, so
+/// the subscriber is returned if another one is installed already.
+pub fn set_subscriber(subscriber: Box<dyn Subscriber>) -> Result<(), Box<dyn Subscriber>> {
+    SUBSCRIBER.set(subscriber)
+}
+
+/// Query Subscriber
+///
+/// Return whether a subscriber is installed.
+pub fn enabled() -> bool {
+    SUBSCRIBER.get().is_some()
+}
+
+fn current() -> Option<u64> {
+    CURRENT.with(|v| v.borrow().last().copied())
+}
+
+/// Entered Span
+///
+/// The guard of an entered span, which exits the span when dropped.
+#[must_use]
+pub struct Entered {
+    span: Option<Span>,
+}
+
+impl Span {
+    /// Enter Span
+    ///
+    /// Enter a new span on the current thread, until the returned guard is
+    /// dropped. The fields are only computed if a subscriber is installed.
+    pub fn enter(name: &'static str, fields: impl FnOnce() -> Fields) -> Entered {
+        let Some(subscriber) = SUBSCRIBER.get() else {
+            return Entered { span: None };
+        };
+
+        let span = Span {
+            id: IDS.fetch_add(1, Ordering::Relaxed),
+            parent: current(),
+            name,
+            fields: fields(),
+        };
+        subscriber.enter(&span);
+        CURRENT.with(|v| v.borrow_mut().push(span.id));
+        Entered { span: Some(span) }
+    }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if let (Some(span), Some(subscriber)) = (self.span.take(), SUBSCRIBER.get()) {
+            CURRENT.with(|v| v.borrow_mut().retain(|v| *v != span.id));
+            subscriber.exit(&span);
+        }
+    }
+}
+
+/// Record Event
+///
+/// Record an event in the current span. The fields are only computed if a
+/// subscriber is installed.
+pub fn event(level: Level, message: &'static str, fields: impl FnOnce() -> Fields) {
+    if let Some(subscriber) = SUBSCRIBER.get() {
+        subscriber.event(&Event {
+            level,
+            span: current(),
+            message,
+            fields: fields(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread::ThreadId;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(ThreadId, String)>>);
+
+    impl Recorder {
+        fn push(&self, v: String) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((std::thread::current().id(), v));
+        }
+    }
+
+    impl Subscriber for &'static Recorder {
+        fn enter(&self, span: &Span) {
+            self.push(format!("enter {} {:?}", span.name, span.fields));
+        }
+
+        fn exit(&self, span: &Span) {
+            self.push(format!("exit {}", span.name));
+        }
+
+        fn event(&self, event: &Event) {
+            self.push(format!(
+                "{:?} {} {:?}",
+                event.level, event.message, event.fields
+            ));
+        }
+    }
+
+    // Verify Instrumentation
+    //
+    // Other tests run concurrently and are reported to the subscriber as
+    // well, so only records of the current thread are compared.
+    #[test]
+    fn verify_trace() {
+        static RECORDER: OnceLock<Recorder> = OnceLock::new();
+        let recorder = RECORDER.get_or_init(Recorder::default);
+        assert!(set_subscriber(Box::new(recorder)).is_ok());
+        assert!(set_subscriber(Box::new(recorder)).is_err());
+        assert!(enabled());
+
+        let text = r#"{
+            "pipeline": {
+                "stages": [{ "name": "org.osbuild.noop" }]
+            }
+        }"#;
+        {
+            let _span = Span::enter("test", Vec::new);
+            let manifest = crate::manifest::Manifest::parse(text).unwrap();
+            let crate::manifest::Manifest::V1(v) = manifest else {
+                panic!();
+            };
+            crate::inspect::ids(&v);
+        }
+
+        let id = crate::store::ManifestStore::id(&text.parse().unwrap());
+        let stage = crate::inspect::ids(&text.parse().unwrap())[0].1;
+        let records: Vec<String> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|v| v.0 == std::thread::current().id())
+            .map(|v| v.1.clone())
+            .collect();
+        assert_eq! {
+            records[..8],
+            [
+                "enter test []".to_owned(),
+                "enter parse []".to_owned(),
+                format!("Debug parsed [(\"version\", \"1\"), (\"manifest\", \"{}\")]", id),
+                "exit parse".to_owned(),
+                "enter pipeline [(\"path\", \"/pipeline\")]".to_owned(),
+                format!("Trace stage [(\"path\", \"/pipeline/stages/0\"), (\"id\", \"{}\")]", stage),
+                "exit pipeline".to_owned(),
+                "exit test".to_owned(),
+            ],
+        }
+    }
+}
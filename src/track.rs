@@ -0,0 +1,364 @@
+//! Path Tracking
+//!
+//! Errors of the JSON parser carry the line and column they occurred at,
+//! but not the place in the document structure. This module wraps the
+//! parser to track the JSON pointer of the value being deserialized, so
+//! errors like unknown or missing fields can be reported together with the
+//! object they occurred in.
+//!
+//! Enum variants are not tracked; errors within them are reported with the
+//! path of the enum itself.
+
+use crate::error::Error;
+use crate::lint::pointer;
+use serde::de::{self, DeserializeSeed, Deserializer, IntoDeserializer, Visitor};
+use std::cell::RefCell;
+
+// Path of the value being deserialized, as linked list of segments.
+enum Chain<'a> {
+    Root,
+    Key(&'a Chain<'a>, String),
+    Index(&'a Chain<'a>, usize),
+}
+
+impl Chain<'_> {
+    fn pointer(&self) -> String {
+        match self {
+            Chain::Root => String::new(),
+            Chain::Key(parent, key) => pointer(&parent.pointer(), key),
+            Chain::Index(parent, index) => pointer(&parent.pointer(), &index.to_string()),
+        }
+    }
+}
+
+// Path of the innermost failure, recorded as errors propagate outwards.
+#[derive(Default)]
+struct Track {
+    path: RefCell<Option<String>>,
+}
+
+impl Track {
+    fn trigger<E>(&self, chain: &Chain<'_>, e: E) -> E {
+        self.path
+            .borrow_mut()
+            .get_or_insert_with(|| chain.pointer());
+        e
+    }
+}
+
+// Deserializer, visitor, or seed that tracks the path of its value.
+struct Wrap<'a, 'b, X> {
+    inner: X,
+    chain: &'a Chain<'a>,
+    track: &'b Track,
+}
+
+// Sequence that tracks the index of its elements.
+struct Seq<'a, 'b, X> {
+    inner: X,
+    chain: &'a Chain<'a>,
+    track: &'b Track,
+    index: usize,
+}
+
+// Map that tracks the key of its values.
+struct Map<'a, 'b, X> {
+    inner: X,
+    chain: &'a Chain<'a>,
+    track: &'b Track,
+    key: Option<String>,
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $type:ty),*);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $type,)*
+                visitor: V,
+            ) -> Result<V::Value, D::Error> {
+                let (chain, track) = (self.chain, self.track);
+                let visitor = Wrap { inner: visitor, chain, track };
+                self.inner
+                    .$method($($arg,)* visitor)
+                    .map_err(|e| track.trigger(chain, e))
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Wrap<'_, '_, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($($arg:ident: $type:ty),*);)*) => {
+        $(
+            fn $method<E: de::Error>(self, $($arg: $type),*) -> Result<V::Value, E> {
+                self.inner.$method($($arg),*)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrap<'_, '_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.expecting(fmt)
+    }
+
+    forward_visit! {
+        visit_bool(v: bool);
+        visit_i8(v: i8);
+        visit_i16(v: i16);
+        visit_i32(v: i32);
+        visit_i64(v: i64);
+        visit_i128(v: i128);
+        visit_u8(v: u8);
+        visit_u16(v: u16);
+        visit_u32(v: u32);
+        visit_u64(v: u64);
+        visit_u128(v: u128);
+        visit_f32(v: f32);
+        visit_f64(v: f64);
+        visit_char(v: char);
+        visit_str(v: &str);
+        visit_borrowed_str(v: &'de str);
+        visit_string(v: String);
+        visit_bytes(v: &[u8]);
+        visit_borrowed_bytes(v: &'de [u8]);
+        visit_byte_buf(v: Vec<u8>);
+        visit_none();
+        visit_unit();
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<V::Value, D::Error> {
+        self.inner.visit_some(Wrap {
+            inner: d,
+            chain: self.chain,
+            track: self.track,
+        })
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<V::Value, D::Error> {
+        self.inner.visit_newtype_struct(Wrap {
+            inner: d,
+            chain: self.chain,
+            track: self.track,
+        })
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_seq(Seq {
+            inner: seq,
+            chain: self.chain,
+            track: self.track,
+            index: 0,
+        })
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_map(Map {
+            inner: map,
+            chain: self.chain,
+            track: self.track,
+            key: None,
+        })
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_enum(data)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Wrap<'_, '_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<S::Value, D::Error> {
+        self.inner.deserialize(Wrap {
+            inner: d,
+            chain: self.chain,
+            track: self.track,
+        })
+    }
+}
+
+impl<'de, A: de::SeqAccess<'de>> de::SeqAccess<'de> for Seq<'_, '_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let chain = Chain::Index(self.chain, self.index);
+        self.index += 1;
+        let seed = Wrap {
+            inner: seed,
+            chain: &chain,
+            track: self.track,
+        };
+        self.inner
+            .next_element_seed(seed)
+            .map_err(|e| self.track.trigger(&chain, e))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, A: de::MapAccess<'de>> de::MapAccess<'de> for Map<'_, '_, A> {
+    type Error = A::Error;
+
+    // Keys of JSON objects are always strings, so they are parsed as such
+    // and only then passed to the seed, which allows remembering them.
+    fn next_key_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let (chain, track) = (self.chain, self.track);
+        let key = match self.inner.next_key::<String>() {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(track.trigger(chain, e)),
+        };
+        self.key = Some(key.clone());
+        seed.deserialize(key.into_deserializer())
+            .map(Some)
+            .map_err(|e| track.trigger(chain, e))
+    }
+
+    fn next_value_seed<S>(&mut self, seed: S) -> Result<S::Value, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let chain = Chain::Key(self.chain, self.key.take().unwrap_or_default());
+        let seed = Wrap {
+            inner: seed,
+            chain: &chain,
+            track: self.track,
+        };
+        self.inner
+            .next_value_seed(seed)
+            .map_err(|e| self.track.trigger(&chain, e))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+/// Parse JSON with Path Tracking
+///
+/// Parse JSON text like `serde_json::from_str()`, but report errors with
+/// the JSON pointer of the value they occurred in. Errors are reported as
+/// parse errors of the given manifest version.
+pub fn from_str<'de, T>(text: &'de str, version: Option<u32>) -> Result<T, Error>
+where
+    T: serde::Deserialize<'de>,
+{
+    let track = Track::default();
+    let mut de = serde_json::Deserializer::from_str(text);
+    let r = T::deserialize(Wrap {
+        inner: &mut de,
+        chain: &Chain::Root,
+        track: &track,
+    })
+    .and_then(|v| de.end().map(|_| v));
+
+    r.map_err(|e| match Error::parse(version, e) {
+        Error::Parse {
+            version,
+            line,
+            column,
+            message,
+            ..
+        } => Error::Parse {
+            version,
+            line,
+            column,
+            path: track.path.into_inner().unwrap_or_default(),
+            message,
+        },
+        v => v,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest1;
+
+    // Verify Path Tracking
+    #[test]
+    fn verify_track() {
+        let text = r#"{
+            "pipeline": {
+                "build": {
+                    "pipeline": { "asembler": {} },
+                    "runner": "org.osbuild.linux"
+                }
+            }
+        }"#;
+        let e = from_str::<Manifest1>(text, Some(1)).unwrap_err();
+        assert!(matches! {
+            &e,
+            Error::Parse { line: 4, path, .. } if path == "/pipeline/build/pipeline",
+        });
+        assert!(e.to_string().starts_with(
+            "invalid version-1 manifest: /pipeline/build/pipeline: unknown field `asembler`"
+        ));
+
+        let text = r#"{ "pipeline": { "stages": [{ "name": "a" }, { "name": 1 }] } }"#;
+        let e = from_str::<Manifest1>(text, None).unwrap_err();
+        assert!(matches! {
+            &e,
+            Error::Parse { path, .. } if path == "/pipeline/stages/1/name",
+        });
+
+        let e = from_str::<Manifest1>("{} x", None).unwrap_err();
+        assert!(matches!(&e, Error::Parse { path, .. } if path.is_empty()));
+
+        let v: serde_json::Value = from_str(r#"{ "a": [1, { "b": null }] }"#, None).unwrap();
+        assert_eq!(v, serde_json::json!({ "a": [1, { "b": null }] }));
+    }
+}
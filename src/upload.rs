@@ -0,0 +1,1235 @@
+//! Artifact Upload
+//!
+//! osbuild-composer uploads built images to the image stores of cloud
+//! providers. This module provides these upload targets as a library, so
+//! exported artifacts can be published without composer: AWS, where images
+//! are imported from S3 as AMIs, Azure, where images are created from page
+//! blobs, GCP, where images are created from storage objects, and plain S3,
+//! including S3-compatible stores.
+//!
+//! Requests are performed by a caller-provided transport, which must
+//! implement `Transport::request()`. Artifacts are uploaded in parts, so
+//! memory use is bounded by the part size regardless of the artifact size,
+//! and progress is reported after each part.
+//!
+//! Credentials are provided by the caller: AWS access keys, with which
+//! requests are signed, and OAuth bearer tokens for Azure and GCP, which
+//! the caller obtains from the respective identity service. Artifacts are
+//! not converted either, and must already be in the format the provider
+//! expects, like a fixed-size VHD for Azure or a gzipped tarball of a
+//! `disk.raw` for GCP.
+
+use crate::hash::{self, Sha256};
+use crate::http::{Response, Transport};
+use crate::manifest::Json;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Upload Errors
+#[derive(Debug)]
+pub enum Error {
+    /// The artifact at the given path cannot be read.
+    Io(PathBuf, std::io::Error),
+    /// The artifact cannot be uploaded to the target, for the given reason.
+    Artifact(String),
+    /// The transport failed.
+    Transport(String),
+    /// The remote responded to the given request with an error status and
+    /// the given message.
+    Status(String, u16, String),
+    /// The response to the given request lacks the given value.
+    Response(String, String),
+    /// The provider failed to import the image, with the given message.
+    Import(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(p, v) => write!(fmt, "{}: {}", p.display(), v),
+            Error::Artifact(v) => write!(fmt, "invalid artifact: {}", v),
+            Error::Transport(v) => write!(fmt, "transport error: {}", v),
+            Error::Status(r, s, m) => {
+                write!(fmt, "{}: remote responded with status {}: {}", r, s, m)
+            }
+            Error::Response(r, v) => write!(fmt, "{}: response lacks {}", r, v),
+            Error::Import(v) => write!(fmt, "import failed: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Upload Progress
+///
+/// The number of bytes of the artifact sent so far, out of its total size.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Progress {
+    pub sent: u64,
+    pub total: u64,
+}
+
+/// Upload Result
+///
+/// The type of the upload, named like the upload types of composer, and
+/// the description of the uploaded image, like the AMI and region for
+/// `aws`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Uploaded {
+    pub upload_type: String,
+    pub options: Json,
+}
+
+/// Upload Target
+pub trait Target {
+    /// Upload the artifact at the given path, reporting progress after each
+    /// part.
+    fn upload(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Uploaded, Error>;
+}
+
+// Artifact being uploaded, read in parts.
+struct Artifact {
+    path: PathBuf,
+    file: std::fs::File,
+    total: u64,
+    sent: u64,
+}
+
+impl Artifact {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let io = |e| Error::Io(path.to_owned(), e);
+        let file = std::fs::File::open(path).map_err(io)?;
+        let total = file.metadata().map_err(io)?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            total,
+            sent: 0,
+        })
+    }
+
+    // Read the next part of at most `size` bytes, which is only short at
+    // the end of the artifact.
+    fn next(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+        let mut acc = Vec::with_capacity(size);
+        (&mut self.file)
+            .take(size as u64)
+            .read_to_end(&mut acc)
+            .map_err(|e| Error::Io(self.path.clone(), e))?;
+        Ok(acc)
+    }
+
+    // Account for a sent part and report progress.
+    fn sent(&mut self, n: usize, progress: &mut dyn FnMut(Progress)) {
+        self.sent += n as u64;
+        progress(Progress {
+            sent: self.sent,
+            total: self.total,
+        });
+    }
+
+    fn done(&self) -> bool {
+        self.sent >= self.total
+    }
+}
+
+// Perform a request, failing on statuses other than 2xx and `accept`.
+fn send(
+    transport: &dyn Transport,
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    accept: &[u16],
+) -> Result<Response, Error> {
+    let r = transport
+        .request(method, url, headers, body)
+        .map_err(Error::Transport)?;
+    if !(200..300).contains(&r.status) && !accept.contains(&r.status) {
+        let message = String::from_utf8_lossy(&r.body).trim().to_owned();
+        return Err(Error::Status(
+            format!("{} {}", method, url),
+            r.status,
+            message,
+        ));
+    }
+    Ok(r)
+}
+
+// Return the text of the first XML element with the given name.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(&xml[start..end])
+}
+
+// Escape text for XML content.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Percent-encode a string as URI component, keeping slashes if asked to.
+fn encode(s: &str, slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if slash => "/".to_owned(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Encode parameters as query string or form body.
+fn query(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(k, v)| format!("{}={}", encode(k, false), encode(v, false)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Split a time into days since the epoch and seconds of the day, and
+// convert the days to a civil date.
+fn civil(time: SystemTime) -> (i64, u32, u32, u64, u64) {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+
+    // Howard Hinnant's civil_from_days().
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, days as u64, rest)
+}
+
+// Format a time as ISO 8601 basic timestamp, like `20150830T123600Z`.
+fn timestamp(time: SystemTime) -> String {
+    let (year, month, day, _, rest) = civil(time);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60,
+    )
+}
+
+// Format a time as HTTP date, like `Sun, 30 Aug 2015 12:36:00 GMT`.
+fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, days, rest) = civil(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60,
+    )
+}
+
+/// AWS Credentials
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Token of temporary credentials.
+    pub session_token: Option<String>,
+}
+
+// Sign a request with AWS signature version 4. The URL must be encoded
+// already. Returns the headers to send in addition to the given ones,
+// which are signed as well.
+fn sign(
+    credentials: &AwsCredentials,
+    scope: (&str, &str),
+    request: (&str, &str),
+    headers: &[(&str, &str)],
+    payload: &str,
+    time: SystemTime,
+) -> Vec<(String, String)> {
+    let ((region, service), (method, url)) = (scope, request);
+    let stamp = timestamp(time);
+    let rest = url.split_once("://").map_or(url, |v| v.1);
+    let (rest, search) = rest.split_once('?').unwrap_or((rest, ""));
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let mut params: Vec<String> = search
+        .split('&')
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.contains('=') {
+            true => v.to_owned(),
+            false => format!("{}=", v),
+        })
+        .collect();
+    params.sort();
+
+    let mut extra = vec![("x-amz-date".to_owned(), stamp.clone())];
+    if let Some(v) = &credentials.session_token {
+        extra.push(("x-amz-security-token".to_owned(), v.clone()));
+    }
+    let mut signed: Vec<(String, String)> = headers
+        .iter()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_owned()))
+        .chain([("host".to_owned(), host.to_owned())])
+        .chain(extra.iter().cloned())
+        .collect();
+    signed.sort();
+    let names = signed
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        params.join("&"),
+        signed
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v))
+            .collect::<String>(),
+        names,
+        payload,
+    );
+    let date = &stamp[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let text = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        stamp,
+        scope,
+        hash::to_hex(&Sha256::digest(canonical.as_bytes())),
+    );
+
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hash::hmac_sha256(key.as_bytes(), date.as_bytes());
+    let key = hash::hmac_sha256(&key, region.as_bytes());
+    let key = hash::hmac_sha256(&key, service.as_bytes());
+    let key = hash::hmac_sha256(&key, b"aws4_request");
+    let signature = hash::to_hex(&hash::hmac_sha256(&key, text.as_bytes()));
+
+    extra.push((
+        "authorization".to_owned(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, names, signature,
+        ),
+    ));
+    extra
+}
+
+// Perform a request signed for the given AWS service.
+fn send_aws(
+    transport: &dyn Transport,
+    credentials: &AwsCredentials,
+    scope: (&str, &str),
+    request: (&str, &str),
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, Error> {
+    let payload = hash::to_hex(&Sha256::digest(body));
+    let mut headers = headers.to_vec();
+    headers.push(("x-amz-content-sha256", &payload));
+    let extra = sign(
+        credentials,
+        scope,
+        request,
+        &headers,
+        &payload,
+        SystemTime::now(),
+    );
+    headers.extend(extra.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    send(transport, request.0, request.1, &headers, body, &[])
+}
+
+/// S3 Target
+///
+/// Uploads the artifact as object to an S3 bucket, in parts of `part_size`
+/// bytes. AWS requires parts of at least 5 MiB, except for the last one,
+/// and at most 10000 parts. S3-compatible stores can be used by setting
+/// their `endpoint`, and are addressed in path style.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct S3 {
+    /// Endpoint URL, or `None` for the AWS endpoint of the region.
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub bucket: String,
+    pub key: String,
+    pub credentials: AwsCredentials,
+    pub part_size: usize,
+}
+
+impl S3 {
+    /// Create S3 Target
+    ///
+    /// Create a target for the AWS endpoint of the region, with parts of
+    /// 64 MiB.
+    pub fn new(region: &str, bucket: &str, key: &str, credentials: AwsCredentials) -> Self {
+        Self {
+            endpoint: None,
+            region: region.to_owned(),
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            credentials,
+            part_size: 64 << 20,
+        }
+    }
+
+    /// URL of the Object
+    pub fn url(&self) -> String {
+        let endpoint = match &self.endpoint {
+            Some(v) => v.trim_end_matches('/').to_owned(),
+            None => format!("https://s3.{}.amazonaws.com", self.region),
+        };
+        format!(
+            "{}/{}/{}",
+            endpoint,
+            encode(&self.bucket, false),
+            encode(&self.key, true),
+        )
+    }
+
+    fn send(
+        &self,
+        transport: &dyn Transport,
+        method: &str,
+        url: &str,
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let scope = (self.region.as_str(), "s3");
+        send_aws(
+            transport,
+            &self.credentials,
+            scope,
+            (method, url),
+            &[],
+            body,
+        )
+    }
+
+    // Upload the parts of a multipart upload, and return their ETags.
+    fn parts(
+        &self,
+        transport: &dyn Transport,
+        artifact: &mut Artifact,
+        id: &str,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Vec<String>, Error> {
+        let mut acc = Vec::new();
+        loop {
+            let data = artifact.next(self.part_size.max(1))?;
+            let url = format!(
+                "{}?partNumber={}&uploadId={}",
+                self.url(),
+                acc.len() + 1,
+                encode(id, false),
+            );
+            let r = self.send(transport, "PUT", &url, &data)?;
+            let etag = r
+                .header("etag")
+                .ok_or_else(|| Error::Response(format!("PUT {}", url), "ETag".to_owned()))?;
+            acc.push(etag.to_owned());
+            artifact.sent(data.len(), progress);
+            if artifact.done() {
+                return Ok(acc);
+            }
+        }
+    }
+
+    fn upload_object(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<(), Error> {
+        let mut artifact = Artifact::open(path)?;
+        let url = format!("{}?uploads=", self.url());
+        let r = self.send(transport, "POST", &url, &[])?;
+        let id = element(&String::from_utf8_lossy(&r.body), "UploadId")
+            .ok_or_else(|| Error::Response(format!("POST {}", url), "UploadId".to_owned()))?
+            .to_owned();
+
+        let url = format!("{}?uploadId={}", self.url(), encode(&id, false));
+        let etags = match self.parts(transport, &mut artifact, &id, progress) {
+            Ok(v) => v,
+            Err(e) => {
+                // Abort the upload, so its parts are not retained. This is
+                // best-effort, since the original failure is reported.
+                let _ = self.send(transport, "DELETE", &url, &[]);
+                return Err(e);
+            }
+        };
+
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            body += &format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                escape(etag),
+            );
+        }
+        body += "</CompleteMultipartUpload>";
+        self.send(transport, "POST", &url, body.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Target for S3 {
+    fn upload(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Uploaded, Error> {
+        self.upload_object(transport, path, progress)?;
+        Ok(Uploaded {
+            upload_type: "aws.s3".to_owned(),
+            options: serde_json::json!({ "url": self.url() }),
+        })
+    }
+}
+
+/// AWS Target
+///
+/// Uploads the raw disk image to S3, imports it as EBS snapshot, and
+/// registers an AMI booting from it. The S3 object is deleted once the
+/// snapshot is imported. The import runs asynchronously in AWS, and is
+/// polled every `poll_interval`. The `vmimport` service role must exist in
+/// the account.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Aws {
+    pub s3: S3,
+    pub image_name: String,
+    /// Architecture of the image, like `x86_64` or `arm64`.
+    pub architecture: String,
+    /// Boot mode of the image, like `uefi`, or `None` for the default.
+    pub boot_mode: Option<String>,
+    /// Accounts the image and its snapshot are shared with.
+    pub share_with_accounts: Vec<String>,
+    pub poll_interval: Duration,
+}
+
+impl Aws {
+    /// Create AWS Target
+    ///
+    /// Create a target for an `x86_64` image, uploaded via the given S3
+    /// object, and polled every 10 seconds.
+    pub fn new(s3: S3, image_name: &str) -> Self {
+        Self {
+            s3,
+            image_name: image_name.to_owned(),
+            architecture: "x86_64".to_owned(),
+            boot_mode: None,
+            share_with_accounts: Vec::new(),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+
+    // Perform an EC2 API action, and return the response text.
+    fn action(
+        &self,
+        transport: &dyn Transport,
+        action: &str,
+        params: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        let mut all = vec![("Action", action), ("Version", "2016-11-15")];
+        all.extend_from_slice(params);
+        let body = query(&all);
+        let url = format!("https://ec2.{}.amazonaws.com/", self.s3.region);
+        let headers = [(
+            "content-type",
+            "application/x-www-form-urlencoded; charset=utf-8",
+        )];
+        let scope = (self.s3.region.as_str(), "ec2");
+        let r = send_aws(
+            transport,
+            &self.s3.credentials,
+            scope,
+            ("POST", &url),
+            &headers,
+            body.as_bytes(),
+        )?;
+        Ok(String::from_utf8_lossy(&r.body).into_owned())
+    }
+
+    // Wait for the snapshot import task to finish, and return the snapshot.
+    fn wait(&self, transport: &dyn Transport, task: &str) -> Result<String, Error> {
+        loop {
+            let r = self.action(
+                transport,
+                "DescribeImportSnapshotTasks",
+                &[("ImportTaskId.1", task)],
+            )?;
+            match element(&r, "status") {
+                Some("completed") => {
+                    return element(&r, "snapshotId").map(str::to_owned).ok_or_else(|| {
+                        Error::Response(
+                            "DescribeImportSnapshotTasks".to_owned(),
+                            "snapshotId".to_owned(),
+                        )
+                    });
+                }
+                Some("active") | None => std::thread::sleep(self.poll_interval),
+                Some(v) => {
+                    let message = element(&r, "statusMessage").unwrap_or(v);
+                    return Err(Error::Import(message.to_owned()));
+                }
+            }
+        }
+    }
+}
+
+impl Target for Aws {
+    fn upload(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Uploaded, Error> {
+        self.s3.upload_object(transport, path, progress)?;
+
+        let r = self.action(
+            transport,
+            "ImportSnapshot",
+            &[
+                ("Description", &self.image_name),
+                ("DiskContainer.Format", "RAW"),
+                ("DiskContainer.UserBucket.S3Bucket", &self.s3.bucket),
+                ("DiskContainer.UserBucket.S3Key", &self.s3.key),
+            ],
+        );
+        let snapshot = r.and_then(|r| {
+            let task = element(&r, "importTaskId").ok_or_else(|| {
+                Error::Response("ImportSnapshot".to_owned(), "importTaskId".to_owned())
+            })?;
+            self.wait(transport, task)
+        });
+        // The object is only needed for the import, so it is removed even
+        // if the import failed. This is best-effort.
+        let _ = self.s3.send(transport, "DELETE", &self.s3.url(), &[]);
+        let snapshot = snapshot?;
+
+        let mut params = vec![
+            ("Name", self.image_name.as_str()),
+            ("Architecture", self.architecture.as_str()),
+            ("VirtualizationType", "hvm"),
+            ("EnaSupport", "true"),
+            ("RootDeviceName", "/dev/xvda"),
+            ("BlockDeviceMapping.1.DeviceName", "/dev/xvda"),
+            ("BlockDeviceMapping.1.Ebs.SnapshotId", snapshot.as_str()),
+            ("BlockDeviceMapping.1.Ebs.DeleteOnTermination", "true"),
+        ];
+        if let Some(v) = &self.boot_mode {
+            params.push(("BootMode", v));
+        }
+        let r = self.action(transport, "RegisterImage", &params)?;
+        let ami = element(&r, "imageId")
+            .ok_or_else(|| Error::Response("RegisterImage".to_owned(), "imageId".to_owned()))?
+            .to_owned();
+
+        if !self.share_with_accounts.is_empty() {
+            let keys: Vec<_> = (1..=self.share_with_accounts.len())
+                .map(|i| {
+                    (
+                        format!("LaunchPermission.Add.{}.UserId", i),
+                        format!("CreateVolumePermission.Add.{}.UserId", i),
+                    )
+                })
+                .collect();
+            let mut image = vec![("ImageId", ami.as_str())];
+            let mut volume = vec![("SnapshotId", snapshot.as_str())];
+            for ((a, b), v) in keys.iter().zip(&self.share_with_accounts) {
+                image.push((a, v));
+                volume.push((b, v));
+            }
+            self.action(transport, "ModifyImageAttribute", &image)?;
+            self.action(transport, "ModifySnapshotAttribute", &volume)?;
+        }
+
+        Ok(Uploaded {
+            upload_type: "aws".to_owned(),
+            options: serde_json::json!({ "ami": ami, "region": self.s3.region }),
+        })
+    }
+}
+
+/// Azure Target
+///
+/// Uploads the fixed-size VHD as page blob to a storage account, and
+/// creates a managed image from it. Pages consisting of zeros only are
+/// skipped, since page blobs are sparse. Parts must be multiples of 512
+/// bytes and at most 4 MiB. The image is provisioned asynchronously by
+/// Azure, which is not waited for.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Azure {
+    /// Bearer token for the storage service.
+    pub storage_token: String,
+    /// Bearer token for the resource manager.
+    pub management_token: String,
+    pub storage_account: String,
+    pub container: String,
+    pub blob: String,
+    pub subscription_id: String,
+    pub resource_group: String,
+    pub location: String,
+    pub image_name: String,
+    /// Hyper-V generation of the image, `V1` or `V2`.
+    pub hyper_v_generation: String,
+    pub part_size: usize,
+}
+
+impl Azure {
+    /// URL of the Blob
+    pub fn url(&self) -> String {
+        format!(
+            "https://{}.blob.core.windows.net/{}/{}",
+            self.storage_account,
+            encode(&self.container, false),
+            encode(&self.blob, true),
+        )
+    }
+
+    fn send_blob(
+        &self,
+        transport: &dyn Transport,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let auth = format!("Bearer {}", self.storage_token);
+        let date = http_date(SystemTime::now());
+        let mut all = vec![
+            ("authorization", auth.as_str()),
+            ("x-ms-date", date.as_str()),
+            ("x-ms-version", "2020-10-02"),
+        ];
+        all.extend_from_slice(headers);
+        send(transport, "PUT", url, &all, body, &[])
+    }
+}
+
+impl Target for Azure {
+    fn upload(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Uploaded, Error> {
+        let mut artifact = Artifact::open(path)?;
+        if artifact.total % 512 != 0 {
+            return Err(Error::Artifact(
+                "size of page blobs must be a multiple of 512 bytes".to_owned(),
+            ));
+        }
+
+        let length = artifact.total.to_string();
+        self.send_blob(
+            transport,
+            &self.url(),
+            &[
+                ("x-ms-blob-type", "PageBlob"),
+                ("x-ms-blob-content-length", &length),
+            ],
+            &[],
+        )?;
+
+        let url = format!("{}?comp=page", self.url());
+        let size = self.part_size.clamp(512, 4 << 20) / 512 * 512;
+        while !artifact.done() {
+            let data = artifact.next(size)?;
+            if data.iter().any(|v| *v != 0) {
+                let range = format!(
+                    "bytes={}-{}",
+                    artifact.sent,
+                    artifact.sent + data.len() as u64 - 1,
+                );
+                self.send_blob(
+                    transport,
+                    &url,
+                    &[("x-ms-page-write", "update"), ("x-ms-range", &range)],
+                    &data,
+                )?;
+            }
+            artifact.sent(data.len(), progress);
+        }
+
+        let url = format!(
+            "https://management.azure.com/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Compute/images/{}?api-version=2022-08-01",
+            encode(&self.subscription_id, false),
+            encode(&self.resource_group, false),
+            encode(&self.image_name, false),
+        );
+        let body = serde_json::json!({
+            "location": self.location,
+            "properties": {
+                "hyperVGeneration": self.hyper_v_generation,
+                "storageProfile": {
+                    "osDisk": {
+                        "osType": "Linux",
+                        "osState": "Generalized",
+                        "blobUri": self.url(),
+                    },
+                },
+            },
+        });
+        let auth = format!("Bearer {}", self.management_token);
+        let headers = [
+            ("authorization", auth.as_str()),
+            ("content-type", "application/json"),
+        ];
+        send(
+            transport,
+            "PUT",
+            &url,
+            &headers,
+            body.to_string().as_bytes(),
+            &[],
+        )?;
+
+        Ok(Uploaded {
+            upload_type: "azure".to_owned(),
+            options: serde_json::json!({ "image_name": self.image_name }),
+        })
+    }
+}
+
+/// GCP Target
+///
+/// Uploads the gzipped tarball of the image to a storage bucket via a
+/// resumable upload, and creates a compute image from it. Parts must be
+/// multiples of 256 KiB, except for the last one. The image is created
+/// asynchronously by GCP, which is not waited for, so the storage object
+/// is kept.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Gcp {
+    /// Bearer token with access to storage and compute.
+    pub token: String,
+    pub project: String,
+    pub bucket: String,
+    pub object: String,
+    pub image_name: String,
+    pub part_size: usize,
+}
+
+impl Target for Gcp {
+    fn upload(
+        &self,
+        transport: &dyn Transport,
+        path: &Path,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Uploaded, Error> {
+        let mut artifact = Artifact::open(path)?;
+        let auth = format!("Bearer {}", self.token);
+        let total = artifact.total.to_string();
+
+        let url = format!(
+            "https://storage.googleapis.com/upload/storage/v1/b/{}/o?uploadType=resumable&name={}",
+            encode(&self.bucket, false),
+            encode(&self.object, false),
+        );
+        let headers = [
+            ("authorization", auth.as_str()),
+            ("x-upload-content-length", total.as_str()),
+        ];
+        let r = send(transport, "POST", &url, &headers, &[], &[])?;
+        let session = r
+            .header("location")
+            .ok_or_else(|| Error::Response(format!("POST {}", url), "Location".to_owned()))?
+            .to_owned();
+
+        let size = self.part_size.max(256 << 10) >> 18 << 18;
+        loop {
+            let data = artifact.next(size)?;
+            let range = match data.len() {
+                0 => format!("bytes */{}", total),
+                n => format!(
+                    "bytes {}-{}/{}",
+                    artifact.sent,
+                    artifact.sent + n as u64 - 1,
+                    total,
+                ),
+            };
+            let headers = [
+                ("authorization", auth.as_str()),
+                ("content-range", range.as_str()),
+            ];
+            send(transport, "PUT", &session, &headers, &data, &[308])?;
+            artifact.sent(data.len(), progress);
+            if artifact.done() {
+                break;
+            }
+        }
+
+        let url = format!(
+            "https://compute.googleapis.com/compute/v1/projects/{}/global/images",
+            encode(&self.project, false),
+        );
+        let body = serde_json::json!({
+            "name": self.image_name,
+            "rawDisk": {
+                "source": format!("https://storage.googleapis.com/{}/{}", self.bucket, self.object),
+            },
+        });
+        let headers = [
+            ("authorization", auth.as_str()),
+            ("content-type", "application/json"),
+        ];
+        send(
+            transport,
+            "POST",
+            &url,
+            &headers,
+            body.to_string().as_bytes(),
+            &[],
+        )?;
+
+        Ok(Uploaded {
+            upload_type: "gcp".to_owned(),
+            options: serde_json::json!({
+                "image_name": self.image_name,
+                "project_id": self.project,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Method, URL, headers, and body of a request.
+    type Request = (String, String, Vec<(String, String)>, Vec<u8>);
+
+    // Transport recording requests, answering via the given function.
+    struct Fake<F> {
+        requests: RefCell<Vec<Request>>,
+        respond: F,
+    }
+
+    impl<F: Fn(&str, &str, &[u8]) -> Response> Fake<F> {
+        fn new(respond: F) -> Self {
+            Self {
+                requests: RefCell::new(Vec::new()),
+                respond,
+            }
+        }
+
+        fn lines(&self) -> Vec<String> {
+            self.requests
+                .borrow()
+                .iter()
+                .map(|(m, u, _, b)| format!("{} {} {}", m, u, b.len()))
+                .collect()
+        }
+    }
+
+    impl<F: Fn(&str, &str, &[u8]) -> Response> Transport for Fake<F> {
+        fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, String> {
+            self.request("GET", url, headers, &[])
+        }
+
+        fn request(
+            &self,
+            method: &str,
+            url: &str,
+            headers: &[(&str, &str)],
+            body: &[u8],
+        ) -> Result<Response, String> {
+            let headers = headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            self.requests.borrow_mut().push((
+                method.to_owned(),
+                url.to_owned(),
+                headers,
+                body.to_vec(),
+            ));
+            Ok((self.respond)(method, url, body))
+        }
+    }
+
+    fn ok(body: &str, headers: &[(&str, &str)]) -> Response {
+        Response {
+            status: 200,
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn artifact(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("r-osbuild-upload-{}-{}", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        }
+    }
+
+    // Verify Signatures and Dates
+    #[test]
+    fn verify_sign() {
+        // Example of the AWS signature version 4 documentation.
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1440938160);
+        let headers = sign(
+            &credentials(),
+            ("us-east-1", "iam"),
+            (
+                "GET",
+                "https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08",
+            ),
+            &[(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )],
+            &hash::to_hex(&Sha256::digest(b"")),
+            time,
+        );
+        assert_eq! {
+            headers,
+            vec![
+                ("x-amz-date".to_owned(), "20150830T123600Z".to_owned()),
+                (
+                    "authorization".to_owned(),
+                    concat!(
+                        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, ",
+                        "SignedHeaders=content-type;host;x-amz-date, ",
+                        "Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7",
+                    )
+                    .to_owned(),
+                ),
+            ],
+        }
+        assert_eq!(http_date(time), "Sun, 30 Aug 2015 12:36:00 GMT");
+        assert_eq!(timestamp(SystemTime::UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(element("<a><b>x</b></a>", "b"), Some("x"));
+        assert_eq!(encode("a b/c", true), "a%20b/c");
+    }
+
+    // Verify S3 and AWS Targets
+    #[test]
+    fn verify_aws() {
+        let path = artifact("aws", b"0123456789");
+        let status = RefCell::new(0);
+        let fake = Fake::new(|method, url, _body: &[u8]| {
+            if url.ends_with("?uploads=") {
+                ok("<InitiateMultipartUploadResult><UploadId>u+1</UploadId></InitiateMultipartUploadResult>", &[])
+            } else if method == "PUT" {
+                ok("", &[("ETag", "\"e\"")])
+            } else if url.starts_with("https://ec2.") {
+                *status.borrow_mut() += 1;
+                let r = match *status.borrow() {
+                    1 => "<importTaskId>import-1</importTaskId>",
+                    2 => "<status>active</status>",
+                    3 => "<status>completed</status><snapshotId>snap-1</snapshotId>",
+                    4 => "<imageId>ami-1</imageId>",
+                    _ => "",
+                };
+                ok(r, &[])
+            } else {
+                ok("", &[])
+            }
+        });
+
+        let mut s3 = S3::new("eu-west-1", "bucket", "dir/image.raw", credentials());
+        s3.part_size = 4;
+        let mut aws = Aws::new(s3.clone(), "image");
+        aws.poll_interval = Duration::ZERO;
+        aws.share_with_accounts = vec!["123".to_owned()];
+
+        let mut reports = Vec::new();
+        let uploaded = aws
+            .upload(&fake, &path, &mut |v| reports.push(v.sent))
+            .unwrap();
+        assert_eq! {
+            uploaded,
+            Uploaded {
+                upload_type: "aws".to_owned(),
+                options: serde_json::json!({ "ami": "ami-1", "region": "eu-west-1" }),
+            },
+        }
+        assert_eq!(reports, [4, 8, 10]);
+
+        let url = "https://s3.eu-west-1.amazonaws.com/bucket/dir/image.raw";
+        let ec2 = "https://ec2.eu-west-1.amazonaws.com/";
+        let lines = fake.lines();
+        assert_eq!(lines[0], format!("POST {}?uploads= 0", url));
+        assert_eq!(
+            lines[1],
+            format!("PUT {}?partNumber=1&uploadId=u%2B1 4", url)
+        );
+        assert_eq!(
+            lines[3],
+            format!("PUT {}?partNumber=3&uploadId=u%2B1 2", url)
+        );
+        assert!(lines[4].starts_with(&format!("POST {}?uploadId=u%2B1 ", url)));
+        assert!(lines[5].starts_with(&format!("POST {} ", ec2)));
+        assert_eq!(lines[8], format!("DELETE {} 0", url));
+        assert_eq!(lines.len(), 12);
+
+        let requests = fake.requests.borrow();
+        let complete = String::from_utf8_lossy(&requests[4].3);
+        assert!(complete.contains("<PartNumber>3</PartNumber><ETag>&quot;e&quot;</ETag>"));
+        let register = String::from_utf8_lossy(&requests[9].3);
+        assert!(register.starts_with("Action=RegisterImage&Version=2016-11-15&Name=image"));
+        assert!(register.contains("BlockDeviceMapping.1.Ebs.SnapshotId=snap-1"));
+        let share = String::from_utf8_lossy(&requests[10].3);
+        assert!(share.ends_with("ImageId=ami-1&LaunchPermission.Add.1.UserId=123"));
+        assert!(requests[0]
+            .2
+            .iter()
+            .any(|(k, v)| k == "authorization" && v.contains("/eu-west-1/s3/aws4_request")));
+        drop(requests);
+
+        let failing = Fake::new(|method, url, _body: &[u8]| match method {
+            "PUT" => Response {
+                status: 403,
+                body: b"denied".to_vec(),
+                ..Default::default()
+            },
+            _ if url.ends_with("?uploads=") => ok("<UploadId>u</UploadId>", &[]),
+            _ => ok("", &[]),
+        });
+        assert!(matches! {
+            s3.upload(&failing, &path, &mut |_| {}),
+            Err(Error::Status(_, 403, m)) if m == "denied",
+        });
+        assert!(failing.lines()[2].starts_with("DELETE "));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Verify Azure and GCP Targets
+    #[test]
+    fn verify_azure_gcp() {
+        let mut data = vec![0u8; 1536];
+        data[1024] = 1;
+        let path = artifact("azure", &data);
+        let fake = Fake::new(|_, _, _: &[u8]| ok("", &[]));
+        let azure = Azure {
+            storage_token: "s".to_owned(),
+            management_token: "m".to_owned(),
+            storage_account: "account".to_owned(),
+            container: "images".to_owned(),
+            blob: "image.vhd".to_owned(),
+            subscription_id: "sub".to_owned(),
+            resource_group: "group".to_owned(),
+            location: "westeurope".to_owned(),
+            image_name: "image".to_owned(),
+            hyper_v_generation: "V2".to_owned(),
+            part_size: 512,
+        };
+        let uploaded = azure.upload(&fake, &path, &mut |_| {}).unwrap();
+        assert_eq!(
+            uploaded.options,
+            serde_json::json!({ "image_name": "image" })
+        );
+
+        let blob = "https://account.blob.core.windows.net/images/image.vhd";
+        let lines = fake.lines();
+        assert_eq!(
+            lines[..2],
+            [
+                format!("PUT {} 0", blob),
+                format!("PUT {}?comp=page 512", blob)
+            ]
+        );
+        assert!(lines[2].starts_with("PUT https://management.azure.com/subscriptions/sub/resourceGroups/group/providers/Microsoft.Compute/images/image?api-version=2022-08-01 "));
+        assert_eq!(lines.len(), 3);
+        let requests = fake.requests.borrow();
+        assert!(requests[1]
+            .2
+            .contains(&("x-ms-range".to_owned(), "bytes=1024-1535".to_owned())));
+        let body: Json = serde_json::from_slice(&requests[2].3).unwrap();
+        assert_eq!(
+            body["properties"]["storageProfile"]["osDisk"]["blobUri"],
+            blob
+        );
+        drop(requests);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = artifact("odd", b"x");
+        assert!(matches!(
+            azure.upload(&fake, &path, &mut |_| {}),
+            Err(Error::Artifact(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        let path = artifact("gcp", &vec![7u8; (256 << 10) + 1]);
+        let fake = Fake::new(|method, _, body: &[u8]| match method {
+            "POST" if body.is_empty() => ok("", &[("Location", "https://session")]),
+            "PUT" if body.len() > 1 => Response {
+                status: 308,
+                ..Default::default()
+            },
+            _ => ok("", &[]),
+        });
+        let gcp = Gcp {
+            token: "t".to_owned(),
+            project: "project".to_owned(),
+            bucket: "bucket".to_owned(),
+            object: "dir/image.tar.gz".to_owned(),
+            image_name: "image".to_owned(),
+            part_size: 1,
+        };
+        let uploaded = gcp.upload(&fake, &path, &mut |_| {}).unwrap();
+        assert_eq!(uploaded.upload_type, "gcp");
+
+        let lines = fake.lines();
+        assert_eq! {
+            lines[0],
+            "POST https://storage.googleapis.com/upload/storage/v1/b/bucket/o?uploadType=resumable&name=dir%2Fimage.tar.gz 0",
+        }
+        assert_eq!(lines[1], "PUT https://session 262144");
+        assert_eq!(lines[2], "PUT https://session 1");
+        assert!(lines[3].starts_with(
+            "POST https://compute.googleapis.com/compute/v1/projects/project/global/images "
+        ));
+        let requests = fake.requests.borrow();
+        assert!(requests[2].2.contains(&(
+            "content-range".to_owned(),
+            "bytes 262144-262144/262145".to_owned()
+        )));
+        drop(requests);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Identifiers and Labels
+//!
+//! This module provides validators for the identifiers and labels used
+//! throughout partitioning, file-system, and boot-loader stages, like
+//! partition-table identifiers, partition type codes, file-system UUIDs,
+//! and volume labels. Additionally, it provides deterministic generation of
+//! name-based UUIDs, so manifest generators can produce stable identifiers
+//! rather than leaving their generation to build time.
+
+use crate::hash::Sha1;
+
+/// Universally Unique Identifier
+///
+/// This represents a UUID as specified in RFC-4122. UUIDs are parsed from
+/// and formatted as their canonical hyphenated hexadecimal representation.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Uuid(pub [u8; 16]);
+
+/// DNS Namespace UUID
+///
+/// The namespace for fully-qualified domain names, as defined in RFC-4122.
+pub const NAMESPACE_DNS: Uuid = Uuid([
+    0x6b, 0xa7, 0xb8, 0x10, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8,
+]);
+
+/// URL Namespace UUID
+///
+/// The namespace for URLs, as defined in RFC-4122.
+pub const NAMESPACE_URL: Uuid = Uuid([
+    0x6b, 0xa7, 0xb8, 0x11, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8,
+]);
+
+impl Uuid {
+    /// Parse UUID
+    ///
+    /// Parse a UUID from its canonical hyphenated representation. Both
+    /// lower-case and upper-case hexadecimal digits are accepted.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.as_bytes();
+        if s.len() != 36 {
+            return None;
+        }
+
+        let mut v = [0u8; 16];
+        let mut n = 0;
+        let mut i = 0;
+        while i < 36 {
+            if i == 8 || i == 13 || i == 18 || i == 23 {
+                if s[i] != b'-' {
+                    return None;
+                }
+                i += 1;
+                continue;
+            }
+            let hi = (s[i] as char).to_digit(16)?;
+            let lo = (s[i + 1] as char).to_digit(16)?;
+            v[n] = (hi << 4 | lo) as u8;
+            n += 1;
+            i += 2;
+        }
+
+        Some(Self(v))
+    }
+
+    /// Generate Name-based UUID
+    ///
+    /// Generate a version-5 UUID from a namespace and a name, as specified
+    /// in RFC-4122. The result only depends on its inputs, so the same
+    /// namespace and name always produce the same UUID.
+    pub fn new_v5(namespace: &Uuid, name: &[u8]) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(&namespace.0);
+        hasher.update(name);
+        let digest = hasher.finalize();
+
+        let mut v = [0u8; 16];
+        v.copy_from_slice(&digest[..16]);
+        v[6] = (v[6] & 0x0f) | 0x50;
+        v[8] = (v[8] & 0x3f) | 0x80;
+        Self(v)
+    }
+
+    /// Version of the UUID
+    pub fn version(&self) -> u8 {
+        self.0[6] >> 4
+    }
+}
+
+impl std::fmt::Display for Uuid {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, v) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(fmt, "-")?;
+            }
+            write!(fmt, "{:02x}", v)?;
+        }
+        Ok(())
+    }
+}
+
+/// UUID Parsing Error
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ParseError;
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "malformed UUID")
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl std::str::FromStr for Uuid {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        Self::parse(s).ok_or(ParseError)
+    }
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|v| v.is_ascii_hexdigit())
+}
+
+/// Validate DOS Disk Identifier
+///
+/// Check whether the string is a valid identifier of an MBR partition
+/// table, which is a 32-bit hexadecimal number with `0x` prefix.
+pub fn is_dos_id(s: &str) -> bool {
+    s.strip_prefix("0x")
+        .is_some_and(|v| !v.is_empty() && v.len() <= 8 && is_hex(v, v.len()))
+}
+
+/// Validate Partition Type
+///
+/// Check whether the string is a valid partition type for the given label
+/// type. For `gpt`, partition types are type GUIDs. For `dos`, partition
+/// types are 8-bit hexadecimal type codes, with or without `0x` prefix.
+pub fn is_partition_type(label: &str, s: &str) -> bool {
+    match label {
+        "gpt" => Uuid::parse(s).is_some(),
+        "dos" => {
+            let v = s.strip_prefix("0x").unwrap_or(s);
+            !v.is_empty() && v.len() <= 2 && is_hex(v, v.len())
+        }
+        _ => false,
+    }
+}
+
+/// Validate File-System Identifier
+///
+/// Check whether the string is a valid identifier for a file-system of the
+/// given type. FAT file-systems use 32-bit volume identifiers, written as
+/// 8 hexadecimal digits with an optional hyphen in the middle. All other
+/// file-systems use UUIDs.
+pub fn is_fs_id(fs_type: &str, s: &str) -> bool {
+    match fs_type {
+        "fat" | "vfat" => {
+            is_hex(s, 8)
+                || (s.len() == 9 && &s[4..5] == "-" && is_hex(&s[..4], 4) && is_hex(&s[5..], 4))
+        }
+        _ => Uuid::parse(s).is_some(),
+    }
+}
+
+/// Validate Volume Label
+///
+/// Check whether the string is a valid volume label for a file-system of
+/// the given type, and return a description of the violated constraint if
+/// not. Lengths are checked in bytes, as the kernel and tools do.
+pub fn check_label(fs_type: &str, s: &str) -> Result<(), &'static str> {
+    let max = match fs_type {
+        "ext2" | "ext3" | "ext4" => 16,
+        "xfs" => 12,
+        "fat" | "vfat" => 11,
+        "btrfs" => 255,
+        "swap" => 16,
+        _ => 255,
+    };
+
+    if s.is_empty() {
+        Err("label must not be empty")
+    } else if s.len() > max {
+        Err("label exceeds the maximum length of the file-system")
+    } else if s.chars().any(|c| c.is_control() || c == '/') {
+        Err("label must not contain control characters or slashes")
+    } else if matches!(fs_type, "fat" | "vfat")
+        && s.chars()
+            .any(|c| !c.is_ascii() || c.is_ascii_lowercase() || "\"*+,./:;<=>?[\\]|".contains(c))
+    {
+        Err("FAT labels must only contain upper-case ASCII and no special characters")
+    } else if fs_type == "xfs" && s.contains(' ') {
+        Err("XFS labels must not contain spaces")
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify UUID Parsing and Generation
+    #[test]
+    fn verify_uuid() {
+        let v = Uuid::parse("6BA7B810-9dad-11d1-80b4-00c04fd430c8").unwrap();
+        assert_eq!(v, NAMESPACE_DNS);
+        assert_eq!(v.to_string(), "6ba7b810-9dad-11d1-80b4-00c04fd430c8");
+        assert_eq!(v.version(), 1);
+
+        assert!(Uuid::parse("6ba7b8109dad11d180b400c04fd430c8").is_none());
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b4-00c04fd430cx").is_none());
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b4+00c04fd430c8").is_none());
+        assert_eq!(
+            "6ba7b810-9dad-11d1-80b4-00c04fd430c8".parse(),
+            Ok(NAMESPACE_DNS)
+        );
+        assert_eq!("6ba7b810".parse::<Uuid>(), Err(ParseError));
+
+        // Reference values from RFC-4122 errata and Python's `uuid` module.
+        let v = Uuid::new_v5(&NAMESPACE_DNS, b"www.example.com");
+        assert_eq!(v.to_string(), "2ed6657d-e927-568b-95e1-2665a8aea6a2");
+        assert_eq!(v.version(), 5);
+        assert_eq!(v, Uuid::new_v5(&NAMESPACE_DNS, b"www.example.com"));
+    }
+
+    // Verify Validators
+    #[test]
+    fn verify_validators() {
+        assert!(is_dos_id("0x14fc63d2"));
+        assert!(!is_dos_id("14fc63d2"));
+        assert!(!is_dos_id("0x14fc63d2a"));
+
+        assert!(is_partition_type(
+            "gpt",
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        ));
+        assert!(is_partition_type("dos", "83"));
+        assert!(is_partition_type("dos", "0xef"));
+        assert!(!is_partition_type("dos", "0x183"));
+        assert!(!is_partition_type("gpt", "83"));
+
+        assert!(is_fs_id("vfat", "7B7795E7"));
+        assert!(is_fs_id("vfat", "7B77-95E7"));
+        assert!(!is_fs_id("vfat", "7B77_95E7"));
+        assert!(is_fs_id("ext4", "76a22bf4-f153-4541-b6c7-0332c0dfaeac"));
+        assert!(!is_fs_id("ext4", "7B7795E7"));
+
+        assert_eq!(check_label("ext4", "root"), Ok(()));
+        assert!(check_label("ext4", "").is_err());
+        assert!(check_label("xfs", "a-very-long-label").is_err());
+        assert!(check_label("xfs", "my root").is_err());
+        assert_eq!(check_label("vfat", "EFI-SYSTEM"), Ok(()));
+        assert!(check_label("vfat", "efi").is_err());
+    }
+}
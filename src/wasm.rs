@@ -0,0 +1,156 @@
+//! WebAssembly Interface
+//!
+//! This module exposes manifest validation, normalization, and diffing to
+//! JavaScript, for browser-based manifest tooling. Every operation takes
+//! manifests as JSON strings, in either format version, and returns a JSON
+//! string, either `{"ok": true, ...}` with the result, or
+//! `{"ok": false, "error": ...}`. The operations only use the manifest,
+//! lint, normalize, and diff modules, none of which access the filesystem
+//! or spawn processes, so they work on `wasm32-unknown-unknown`.
+//!
+//! On `wasm32` targets, the operations are exported via `wasm-bindgen`, so
+//! `wasm-bindgen` generates the JavaScript bindings. On other targets, they
+//! are plain functions. This module is only available with the `wasm`
+//! feature.
+
+use crate::diff;
+use crate::lint;
+use crate::manifest::{Json, Manifest};
+use crate::normalize;
+use crate::service;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+fn failure(e: service::Error) -> Json {
+    serde_json::json!({
+        "ok": false,
+        "error": e.message,
+        "line": e.line,
+        "column": e.column,
+    })
+}
+
+/// Validate Manifest
+///
+/// Parse the manifest and run all lints on it. On success, the result
+/// carries the list of diagnostics as `diagnostics`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn validate(input: &str) -> String {
+    let result = match service::parse(input) {
+        Err(e) => failure(e),
+        Ok(v) => serde_json::json!({
+            "ok": true,
+            "diagnostics": lint::check(&v),
+        }),
+    };
+    result.to_string()
+}
+
+/// Normalize Manifest
+///
+/// Parse the manifest and return its canonical form as `manifest`, in its
+/// own version, as used to compare manifests.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn normalize(input: &str) -> String {
+    let result = match service::parse(input) {
+        Err(e) => failure(e),
+        Ok(Manifest::V1(v)) => serde_json::json!({
+            "ok": true,
+            "manifest": normalize::normalize(&v),
+        }),
+        Ok(Manifest::V2(v)) => serde_json::json!({
+            "ok": true,
+            "manifest": normalize::normalize2(&v),
+        }),
+    };
+    result.to_string()
+}
+
+/// Diff Manifests
+///
+/// Parse both manifests and compare their normalized forms. On success,
+/// the result carries the list of changes as `changes`, each with `kind`,
+/// `path`, and the `old` and `new` values as applicable. Manifests of
+/// different versions cannot be compared.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn diff(old: &str, new: &str) -> String {
+    let changes = match (service::parse(old), service::parse(new)) {
+        (Err(e), _) | (_, Err(e)) => return failure(e).to_string(),
+        (Ok(a), Ok(b)) => diff::manifests_any(&a, &b, &[]),
+    };
+    let changes = match changes {
+        Some(v) => v,
+        None => {
+            return serde_json::json!({
+                "ok": false,
+                "error": "manifests are of different versions",
+            })
+            .to_string()
+        }
+    };
+    let changes: Vec<Json> = changes
+        .into_iter()
+        .map(|v| match v {
+            diff::Change::Added(path, new) => {
+                serde_json::json!({ "kind": "added", "path": path, "new": new })
+            }
+            diff::Change::Removed(path, old) => {
+                serde_json::json!({ "kind": "removed", "path": path, "old": old })
+            }
+            diff::Change::Changed(path, old, new) => {
+                serde_json::json!({ "kind": "changed", "path": path, "old": old, "new": new })
+            }
+        })
+        .collect();
+    serde_json::json!({ "ok": true, "changes": changes }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify WebAssembly Interface
+    #[test]
+    fn verify_wasm() {
+        let result: Json = serde_json::from_str(&validate(r#"{ "pipeline": {} }"#)).unwrap();
+        assert_eq!(result["ok"], true);
+        assert!(result["diagnostics"].is_array());
+
+        let result: Json = serde_json::from_str(&normalize("{\n\"x\": 1}")).unwrap();
+        assert_eq!(result["ok"], false);
+        assert_eq!(result["line"], 2);
+
+        // Version-2 manifests are handled natively, including devices.
+        let v2 = r#"{
+            "version": "2",
+            "pipelines": [{ "name": "image", "stages": [{
+                "type": "org.osbuild.mkfs.ext4",
+                "options": { "uuid": "x" },
+                "devices": { "device": { "type": "org.osbuild.loopback" } }
+            }] }]
+        }"#;
+        let result: Json = serde_json::from_str(&validate(v2)).unwrap();
+        assert_eq!(result["ok"], true);
+        assert_eq!(
+            result["diagnostics"][0]["path"],
+            "/pipelines/0/stages/0/options/uuid"
+        );
+        let result: Json = serde_json::from_str(&normalize(v2)).unwrap();
+        assert_eq!(result["manifest"]["version"], "2");
+
+        let result: Json = serde_json::from_str(&diff(v2, &v2.replace("\"x\"", "\"y\""))).unwrap();
+        assert_eq!(result["ok"], true);
+        assert_eq! {
+            result["changes"],
+            serde_json::json!([{
+                "kind": "changed",
+                "path": "/pipelines/0/stages/0/options/uuid",
+                "old": "x",
+                "new": "y",
+            }]),
+        }
+        let result: Json = serde_json::from_str(&diff(v2, r#"{ "pipeline": {} }"#)).unwrap();
+        assert_eq!(result["ok"], false);
+    }
+}
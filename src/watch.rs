@@ -0,0 +1,410 @@
+//! Watch Mode
+//!
+//! Editing large manifest trees goes faster if every save is validated
+//! right away. This module watches manifest files, and directories of
+//! them, and re-validates manifests when they change: they are parsed
+//! again and linted like with `lint::check()`.
+//!
+//! Files are polled, comparing modification time and size first, and
+//! content only if these differ. Linting is incremental: all checks of
+//! `lint::check()` are local to a pipeline, so diagnostics are cached per
+//! pipeline content, and only pipelines that changed since any previous
+//! run are checked again. Manifests of either version are validated in
+//! their own version.
+
+use crate::hash::Digest;
+use crate::lint::{self, Diagnostic};
+use crate::manifest::{Manifest, Manifest1, Manifest2, Pipeline1, Pipeline2};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Base of the main pipeline in diagnostics of version-1 manifests.
+const BASE: &str = "/pipeline";
+
+// Base of the only pipeline in diagnostics of single-pipeline version-2
+// manifests.
+const BASE2: &str = "/pipelines/0";
+
+/// Incremental Linter
+///
+/// Lints manifests like `lint::check()`, caching the diagnostics of every
+/// pipeline by its content. Cached diagnostics are reused by any manifest
+/// with an equal pipeline at any position of its build chain.
+#[derive(Clone, Debug, Default)]
+pub struct Linter {
+    cache: HashMap<(Digest, bool), Vec<Diagnostic>>,
+    /// Number of pipelines checked, rather than served from the cache.
+    pub checked: usize,
+    /// Number of pipelines served from the cache.
+    pub reused: usize,
+}
+
+impl Linter {
+    /// Create Linter
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check Manifest
+    ///
+    /// Return the diagnostics of the manifest, most severe first.
+    pub fn check(&mut self, manifest: &Manifest1) -> Vec<Diagnostic> {
+        let mut acc = Vec::new();
+        let mut base = BASE.to_owned();
+        for (i, pipeline) in manifest.pipeline.iter_pipelines_deep().rev().enumerate() {
+            // Check the pipeline on its own, as main pipeline of a
+            // manifest, since some checks only apply to the main pipeline.
+            let mut single = Pipeline1::default();
+            single.stages = pipeline.stages.clone();
+            single.assembler = pipeline.assembler.clone();
+            let text = serde_json::to_string(&single).expect("pipelines always serialize");
+            let key = (Digest::sha256(text.as_bytes()), i == 0);
+
+            let diagnostics = match self.cache.get(&key) {
+                Some(v) => {
+                    self.reused += 1;
+                    v
+                }
+                None => {
+                    self.checked += 1;
+                    let mut manifest = Manifest1::default();
+                    manifest.pipeline = single;
+                    let mut diagnostics = lint::check(&manifest);
+                    if i > 0 {
+                        // Build pipelines are not part of the artifact.
+                        diagnostics.retain(|v| !v.rule.starts_with("reproducibility-"));
+                    }
+                    self.cache.entry(key).or_insert(diagnostics)
+                }
+            };
+            acc.extend(diagnostics.iter().cloned().map(|mut v| {
+                v.path = format!("{}{}", base, &v.path[BASE.len()..]);
+                v
+            }));
+            base += "/build/pipeline";
+        }
+        acc.sort_by_key(|v| std::cmp::Reverse(v.severity));
+        acc
+    }
+
+    /// Check Version-2 Manifest
+    ///
+    /// Return the diagnostics of the version-2 manifest, most severe
+    /// first.
+    pub fn check2(&mut self, manifest: &Manifest2) -> Vec<Diagnostic> {
+        let builds: HashSet<&str> = manifest
+            .pipelines
+            .iter()
+            .filter_map(|v| v.build.as_deref())
+            .map(|v| v.strip_prefix("name:").unwrap_or(v))
+            .collect();
+
+        let mut acc = Vec::new();
+        for (i, pipeline) in manifest.pipelines.iter().enumerate() {
+            // Check the stages on their own, as the only pipeline of a
+            // manifest. Pipelines only used as build pipelines are not
+            // part of the artifact.
+            let tree = !builds.contains(&pipeline.name.as_str());
+            let text = serde_json::to_string(&pipeline.stages).expect("stages always serialize");
+            let key = (Digest::sha256(text.as_bytes()), tree);
+
+            let diagnostics = match self.cache.get(&key) {
+                Some(v) => {
+                    self.reused += 1;
+                    v
+                }
+                None => {
+                    self.checked += 1;
+                    let mut single = Pipeline2::default();
+                    single.stages = pipeline.stages.clone();
+                    let mut manifest = Manifest2::default();
+                    manifest.version = "2".to_owned();
+                    manifest.pipelines = vec![single];
+                    let mut diagnostics = lint::check(&manifest);
+                    if !tree {
+                        diagnostics.retain(|v| !v.rule.starts_with("reproducibility-"));
+                    }
+                    self.cache.entry(key).or_insert(diagnostics)
+                }
+            };
+            let base = format!("/pipelines/{}", i);
+            acc.extend(diagnostics.iter().cloned().map(|mut v| {
+                v.path = format!("{}{}", base, &v.path[BASE2.len()..]);
+                v
+            }));
+        }
+        acc.sort_by_key(|v| std::cmp::Reverse(v.severity));
+        acc
+    }
+}
+
+/// Validation Result
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Status {
+    /// The manifest was parsed and linted, with the given diagnostics.
+    Checked(Vec<Diagnostic>),
+    /// The manifest cannot be read or parsed.
+    Invalid(String),
+    /// The file was removed.
+    Removed,
+}
+
+/// Change Event
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub path: PathBuf,
+    pub status: Status,
+}
+
+struct File {
+    modified: Option<SystemTime>,
+    len: u64,
+    content: Digest,
+}
+
+/// Manifest Watcher
+pub struct Watcher {
+    roots: Vec<PathBuf>,
+    files: BTreeMap<PathBuf, File>,
+    linter: Linter,
+}
+
+// Check whether the file name has the extension of manifests, optionally
+// compressed.
+fn is_manifest(path: &Path) -> bool {
+    let name = path.file_name().and_then(|v| v.to_str()).unwrap_or("");
+    let name = crate::compress::Codec::from_path(path)
+        .and_then(|v| name.strip_suffix(v.extension())?.strip_suffix('.'))
+        .unwrap_or(name);
+    name.ends_with(".json")
+}
+
+// Collect the manifest files below the path, recursively.
+fn scan(path: &Path, acc: &mut Vec<PathBuf>) {
+    match std::fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    scan(&path, acc);
+                } else if is_manifest(&path) {
+                    acc.push(path);
+                }
+            }
+        }
+        Err(_) if path.is_file() => acc.push(path.to_owned()),
+        Err(_) => {}
+    }
+}
+
+fn validate(data: &[u8], linter: &mut Linter) -> Status {
+    match Manifest::parse_bytes(data) {
+        Ok(Manifest::V1(v)) => Status::Checked(linter.check(&v)),
+        Ok(Manifest::V2(v)) => Status::Checked(linter.check2(&v)),
+        Err(e) => Status::Invalid(e.to_string()),
+    }
+}
+
+impl Watcher {
+    /// Create Watcher
+    ///
+    /// Create a watcher of the given files and directories. Directories
+    /// are watched recursively for files named `*.json`, optionally with
+    /// the extension of a compression codec. Files given explicitly are
+    /// watched regardless of their names.
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            files: BTreeMap::new(),
+            linter: Linter::new(),
+        }
+    }
+
+    /// Incremental Linter
+    pub fn linter(&self) -> &Linter {
+        &self.linter
+    }
+
+    /// Poll Files
+    ///
+    /// Check all watched files for changes and return an event for every
+    /// file that was added, changed, or removed since the last poll. The
+    /// first poll reports all files.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut paths = Vec::new();
+        for root in &self.roots {
+            scan(root, &mut paths);
+        }
+        paths.sort();
+        paths.dedup();
+
+        let mut acc = Vec::new();
+        self.files.retain(|path, _| {
+            let keep = paths.binary_search(path).is_ok();
+            if !keep {
+                acc.push(Event {
+                    path: path.clone(),
+                    status: Status::Removed,
+                });
+            }
+            keep
+        });
+
+        for path in paths {
+            let metadata = std::fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|v| v.modified().ok());
+            let len = metadata.as_ref().map_or(0, |v| v.len());
+            if let Some(file) = self.files.get(&path) {
+                if file.modified == modified && file.len == len && modified.is_some() {
+                    continue;
+                }
+            }
+
+            let data = match std::fs::read(&path) {
+                Ok(v) => v,
+                Err(e) => {
+                    self.files.remove(&path);
+                    acc.push(Event {
+                        status: Status::Invalid(e.to_string()),
+                        path,
+                    });
+                    continue;
+                }
+            };
+            let content = Digest::sha256(&data);
+            let file = File {
+                modified,
+                len,
+                content,
+            };
+            match self.files.insert(path.clone(), file) {
+                Some(v) if v.content == self.files[&path].content => continue,
+                _ => {}
+            }
+            acc.push(Event {
+                status: validate(&data, &mut self.linter),
+                path,
+            });
+        }
+        acc
+    }
+
+    /// Watch Files
+    ///
+    /// Poll the files in the given interval and pass the events of every
+    /// poll with changes to the callback, until it returns `false`.
+    pub fn run(&mut self, interval: Duration, mut f: impl FnMut(&[Event]) -> bool) {
+        loop {
+            let events = self.poll();
+            if !events.is_empty() && !f(&events) {
+                return;
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(label: &str) -> String {
+        serde_json::json!({
+            "pipeline": {
+                "build": {
+                    "pipeline": {
+                        "stages": [{ "name": "org.osbuild.mkdir", "options": { "paths": [{ "path": "tmp" }] } }]
+                    },
+                    "runner": "org.osbuild.linux"
+                },
+                "stages": [{ "name": "org.osbuild.mkfs.fat", "options": { "volid": label } }]
+            }
+        })
+        .to_string()
+    }
+
+    // Verify Incremental Linting
+    #[test]
+    fn verify_linter() {
+        let mut linter = Linter::new();
+        for label in ["7B7795E7", "x", "y"] {
+            let manifest: Manifest1 = serde_json::from_str(&manifest(label)).unwrap();
+            let mut expected = lint::check(&manifest);
+            let mut actual = linter.check(&manifest);
+            expected.sort_by(|a, b| a.path.cmp(&b.path));
+            actual.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(actual, expected);
+        }
+        assert_eq!((linter.checked, linter.reused), (4, 2));
+
+        // Version-2 manifests are linted natively, including pipelines
+        // with devices, which have no version-1 equivalent.
+        let mut linter = Linter::new();
+        for uuid in ["x", "y"] {
+            let manifest: Manifest2 = serde_json::from_value(serde_json::json!({
+                "version": "2",
+                "pipelines": [
+                    { "name": "build", "stages": [{ "type": "org.osbuild.mkdir", "options": { "paths": [{ "path": "tmp" }] } }] },
+                    { "name": "image", "build": "name:build", "stages": [{
+                        "type": "org.osbuild.mkfs.ext4",
+                        "options": { "uuid": uuid },
+                        "devices": { "device": { "type": "org.osbuild.loopback" } }
+                    }] }
+                ]
+            }))
+            .unwrap();
+            let mut expected = lint::check(&manifest);
+            let mut actual = linter.check2(&manifest);
+            expected.sort_by(|a, b| a.path.cmp(&b.path));
+            actual.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(actual, expected);
+            assert_eq!(actual.len(), 2);
+        }
+        assert_eq!((linter.checked, linter.reused), (3, 1));
+    }
+
+    // Verify Watching Files
+    #[test]
+    fn verify_watch() {
+        let dir = std::env::temp_dir().join(format!("r-osbuild-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let path = dir.join("sub/a.json");
+        std::fs::write(&path, manifest("7B7795E7")).unwrap();
+        std::fs::write(dir.join("b.json"), "{").unwrap();
+        std::fs::write(dir.join("c.txt"), "{").unwrap();
+
+        let mut watcher = Watcher::new(vec![dir.clone()]);
+        let events = watcher.poll();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0].status, Status::Invalid(_)));
+        assert!(matches!(events[1].status, Status::Checked(ref v) if v.len() == 1));
+        assert!(watcher.poll().is_empty());
+
+        // Rewriting the same content is not reported.
+        std::fs::write(&path, manifest("7B7795E7")).unwrap();
+        assert!(watcher.poll().is_empty());
+
+        std::fs::write(&path, manifest("ABCD-1234")).unwrap();
+        std::fs::remove_file(dir.join("b.json")).unwrap();
+        let events = watcher.poll();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq! {
+            events,
+            vec![
+                Event { path: dir.join("b.json"), status: Status::Removed },
+                Event {
+                    path,
+                    status: Status::Checked(vec![Diagnostic::new(
+                        "path-absolute",
+                        lint::Severity::Error,
+                        "/pipeline/build/pipeline/stages/0/options/paths/0/path",
+                        "path `tmp` must be absolute",
+                    )]),
+                },
+            ],
+        }
+        assert_eq!(watcher.linter().reused, 1);
+    }
+}
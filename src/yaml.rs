@@ -0,0 +1,597 @@
+//! YAML Decoding
+//!
+//! This module implements a parser for YAML documents, mapping them to JSON
+//! values. osbuild tooling, like the manifest preprocessor, accepts
+//! manifests written in YAML, and this allows reading them without an
+//! additional dependency.
+//!
+//! The parser supports the subset of YAML used for manifests: block
+//! mappings and sequences, flow collections, plain and quoted scalars,
+//! literal and folded block scalars, and comments. Scalars are resolved
+//! following the core schema. Anchors, aliases, tags, complex keys, and
+//! multi-line plain or quoted scalars are rejected, as are streams of
+//! multiple documents.
+
+use crate::manifest::Json;
+
+type Map = serde_json::Map<String, Json>;
+
+/// YAML Error
+///
+/// This describes a syntax error in a YAML document, or a construct not
+/// supported by the parser. The line number is 1-based.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn error<T>(line: usize, message: &str) -> Result<T, Error> {
+    Err(Error {
+        line,
+        message: message.to_owned(),
+    })
+}
+
+// Remove a trailing comment, which starts with `#` at the beginning of the
+// line or after whitespace, outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), _) if escaped => escaped = false,
+            (Some('"'), '\\') => escaped = true,
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if previous.is_whitespace() || "[{,:".contains(previous) => {
+                quote = Some(c)
+            }
+            (None, '#') if previous.is_whitespace() => return &line[..i],
+            _ => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+// Resolve a plain scalar following the core schema.
+fn plain(text: &str) -> Json {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Json::Null,
+        "true" | "True" | "TRUE" => return Json::Bool(true),
+        "false" | "False" | "FALSE" => return Json::Bool(false),
+        _ => {}
+    }
+
+    let (sign, digits) = match text.strip_prefix(['-', '+']) {
+        Some(v) => (&text[..1], v),
+        None => ("", text),
+    };
+    let radix = |prefix: &str, radix: u32| {
+        let v = digits.strip_prefix(prefix)?;
+        let v = i64::from_str_radix(v, radix).ok()?;
+        Some(Json::from(if sign == "-" { -v } else { v }))
+    };
+    if let Some(v) = radix("0x", 16).or_else(|| radix("0o", 8)) {
+        return v;
+    }
+    if !digits.is_empty() && digits.bytes().all(|v| v.is_ascii_digit()) {
+        if let Ok(v) = serde_json::from_str::<Json>(text.trim_start_matches('+')) {
+            return v;
+        }
+    }
+
+    let float = digits
+        .bytes()
+        .all(|v| v.is_ascii_digit() || b".eE+-".contains(&v))
+        && digits.bytes().any(|v| v.is_ascii_digit())
+        && !digits.starts_with(['e', 'E']);
+    if float {
+        if let Some(v) = text
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+        {
+            return Json::Number(v);
+        }
+    }
+
+    Json::String(text.to_owned())
+}
+
+// Parser of flow collections and quoted scalars.
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Flow<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip();
+        match self.peek() {
+            Some(v) if v == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(format!("expected `{}`", c)),
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let quote = self.peek().unwrap_or_default();
+        self.pos += 1;
+        let mut acc = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match (quote, c) {
+                ('\'', '\'') if self.text[self.pos + i + 1..].starts_with('\'') => {
+                    chars.next();
+                    acc.push('\'');
+                }
+                ('"', '\\') => {
+                    let Some((_, c)) = chars.next() else { break };
+                    match c {
+                        'n' => acc.push('\n'),
+                        't' => acc.push('\t'),
+                        'r' => acc.push('\r'),
+                        '0' => acc.push('\0'),
+                        'x' | 'u' | 'U' => {
+                            let n = match c {
+                                'x' => 2,
+                                'u' => 4,
+                                _ => 8,
+                            };
+                            let hex: String = chars.by_ref().take(n).map(|v| v.1).collect();
+                            let v = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or("invalid escape sequence")?;
+                            acc.push(v);
+                        }
+                        c => acc.push(c),
+                    }
+                }
+                (q, c) if q == c => {
+                    self.pos += i + 1;
+                    return Ok(acc);
+                }
+                (_, c) => acc.push(c),
+            }
+        }
+        Err("unterminated quoted scalar".to_owned())
+    }
+
+    // Parse a plain scalar, which ends at flow indicators, or at a colon
+    // if it is a key.
+    fn plain(&mut self, key: bool) -> Result<Json, String> {
+        let rest = &self.text[self.pos..];
+        let end = rest
+            .find(|c| ",[]{}".contains(c) || (key && c == ':'))
+            .unwrap_or(rest.len());
+        let text = rest[..end].trim();
+        if text.starts_with(['&', '*', '!']) {
+            return Err("anchors, aliases, and tags are not supported".to_owned());
+        }
+        self.pos += end;
+        Ok(plain(text))
+    }
+
+    fn value(&mut self, key: bool) -> Result<Json, String> {
+        self.skip();
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let mut acc = Vec::new();
+                loop {
+                    self.skip();
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                        return Ok(Json::Array(acc));
+                    }
+                    acc.push(self.value(false)?);
+                    self.skip();
+                    if self.peek() != Some(']') {
+                        self.expect(',')?;
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut acc = Map::new();
+                loop {
+                    self.skip();
+                    if self.peek() == Some('}') {
+                        self.pos += 1;
+                        return Ok(Json::Object(acc));
+                    }
+                    let key = match self.value(true)? {
+                        Json::String(v) => v,
+                        v => v.to_string(),
+                    };
+                    self.expect(':')?;
+                    acc.insert(key, self.value(false)?);
+                    self.skip();
+                    if self.peek() != Some('}') {
+                        self.expect(',')?;
+                    }
+                }
+            }
+            Some('"' | '\'') => self.quoted().map(Json::String),
+            Some(_) => self.plain(key),
+            None => Err("unexpected end of flow collection".to_owned()),
+        }
+    }
+}
+
+// Parse an entire text as a single flow value or scalar.
+fn flow(text: &str) -> Result<Json, String> {
+    let mut flow = Flow { text, pos: 0 };
+    let value = match text.starts_with(['[', '{', '"', '\'']) {
+        true => flow.value(false)?,
+        false => flow.plain(false).map(|_| plain(text))?,
+    };
+    flow.skip();
+    match flow.peek() {
+        None => Ok(value),
+        Some(_) => Err("unexpected content after value".to_owned()),
+    }
+}
+
+// Check whether brackets of flow collections are balanced, ignoring
+// quoted scalars.
+fn balanced(text: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+// Split a mapping entry into its key and the rest of the line.
+fn split_key(text: &str) -> Result<Option<(String, &str)>, String> {
+    if text.starts_with(['[', '{']) || is_item(text) {
+        return Ok(None);
+    }
+    if text.starts_with("? ") {
+        return Err("complex keys are not supported".to_owned());
+    }
+
+    let (key, rest) = if text.starts_with(['"', '\'']) {
+        let mut flow = Flow { text, pos: 0 };
+        let key = flow.quoted()?;
+        (key, &text[flow.pos..])
+    } else {
+        let end = text
+            .match_indices(':')
+            .map(|v| v.0)
+            .find(|v| text[v + 1..].is_empty() || text[v + 1..].starts_with([' ', '\t']));
+        match end {
+            Some(v) => (text[..v].trim_end().to_owned(), &text[v..]),
+            None => return Ok(None),
+        }
+    };
+
+    match rest.trim_start().strip_prefix(':') {
+        Some(v) if v.is_empty() || v.starts_with([' ', '\t']) => Ok(Some((key, v.trim()))),
+        _ => Ok(None),
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<&'a str>,
+    pos: usize,
+    // Replacement of the current line, with its indentation. This is used
+    // for the content following the indicator of a sequence item.
+    overlay: Option<(usize, String)>,
+}
+
+impl Parser<'_> {
+    // Return the indentation and content of the next line with content.
+    fn peek(&mut self) -> Result<Option<(usize, String)>, Error> {
+        if let Some(v) = &self.overlay {
+            return Ok(Some(v.clone()));
+        }
+        while let Some(raw) = self.lines.get(self.pos) {
+            let text = strip_comment(raw).trim_end();
+            let content = text.trim_start_matches(' ');
+            if content.starts_with('\t') {
+                return error(self.pos + 1, "tabs are not allowed for indentation");
+            }
+            if content.is_empty() || content == "---" || content == "..." {
+                self.pos += 1;
+                continue;
+            }
+            if content.starts_with('%') {
+                return error(self.pos + 1, "directives are not supported");
+            }
+            return Ok(Some((text.len() - content.len(), content.to_owned())));
+        }
+        Ok(None)
+    }
+
+    fn advance(&mut self) {
+        self.overlay = None;
+        self.pos += 1;
+    }
+
+    fn line(&self) -> usize {
+        self.pos + 1
+    }
+
+    fn node(&mut self, indent: usize) -> Result<Json, Error> {
+        let Some((_, text)) = self.peek()? else {
+            return Ok(Json::Null);
+        };
+        let line = self.line();
+        if is_item(&text) {
+            self.sequence(indent)
+        } else if split_key(&text).or_else(|e| error(line, &e))?.is_some() {
+            self.mapping(indent)
+        } else {
+            self.advance();
+            self.scalar(text, line)
+        }
+    }
+
+    // Parse the value of a scalar or flow collection, which may continue
+    // on following lines.
+    fn scalar(&mut self, mut text: String, line: usize) -> Result<Json, Error> {
+        if text.starts_with(['&', '*', '!']) {
+            return error(line, "anchors, aliases, and tags are not supported");
+        }
+        if text.starts_with(['[', '{']) {
+            while !balanced(&text) {
+                let Some(raw) = self.lines.get(self.pos) else {
+                    return error(line, "unterminated flow collection");
+                };
+                text.push(' ');
+                text += strip_comment(raw).trim();
+                self.pos += 1;
+            }
+        }
+        flow(&text).or_else(|e| error(line, &e))
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Json, Error> {
+        let mut acc = Vec::new();
+        while let Some((i, text)) = self.peek()? {
+            if i != indent || !is_item(&text) {
+                break;
+            }
+            let rest = text[1..].trim_start();
+            if rest.is_empty() {
+                self.advance();
+                match self.peek()? {
+                    Some((i, _)) if i > indent => acc.push(self.node(i)?),
+                    _ => acc.push(Json::Null),
+                }
+            } else {
+                let indent = indent + text.len() - rest.len();
+                self.overlay = Some((indent, rest.to_owned()));
+                acc.push(self.node(indent)?);
+            }
+        }
+        Ok(Json::Array(acc))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Json, Error> {
+        let mut acc = Map::new();
+        while let Some((i, text)) = self.peek()? {
+            if i != indent {
+                break;
+            }
+            let line = self.line();
+            let Some((key, rest)) = split_key(&text).or_else(|e| error(line, &e))? else {
+                return error(line, "expected a mapping key");
+            };
+            let rest = rest.to_owned();
+            self.advance();
+
+            let value = if rest.is_empty() {
+                match self.peek()? {
+                    Some((i, _)) if i > indent => self.node(i)?,
+                    Some((i, text)) if i == indent && is_item(&text) => self.sequence(indent)?,
+                    _ => Json::Null,
+                }
+            } else if rest.starts_with(['|', '>']) {
+                self.block(indent, &rest, line)?
+            } else {
+                self.scalar(rest, line)?
+            };
+            if acc.insert(key, value).is_some() {
+                return error(line, "duplicate mapping key");
+            }
+        }
+        Ok(Json::Object(acc))
+    }
+
+    // Parse a literal or folded block scalar, given its header.
+    fn block(&mut self, indent: usize, header: &str, line: usize) -> Result<Json, Error> {
+        let folded = header.starts_with('>');
+        let chomp = header[1..].chars().find(|v| *v == '-' || *v == '+');
+        let explicit = header[1..]
+            .chars()
+            .find(char::is_ascii_digit)
+            .and_then(|v| v.to_digit(10));
+        if header[1..].chars().any(|v| !"-+123456789".contains(v)) {
+            return error(line, "invalid block scalar header");
+        }
+
+        let mut block = explicit.map(|v| indent + v as usize);
+        let mut lines: Vec<&str> = Vec::new();
+        while let Some(raw) = self.lines.get(self.pos) {
+            let content = raw.trim_start_matches(' ');
+            if content.is_empty() {
+                lines.push("");
+                self.pos += 1;
+                continue;
+            }
+            let i = raw.len() - content.len();
+            let block = *block.get_or_insert(i);
+            if i <= indent || i < block {
+                break;
+            }
+            lines.push(&raw[block..]);
+            self.pos += 1;
+        }
+
+        let trailing = lines.iter().rev().take_while(|v| v.is_empty()).count();
+        lines.truncate(lines.len() - trailing);
+        let mut acc = String::new();
+        for (i, v) in lines.iter().enumerate() {
+            if i > 0 {
+                let joined = folded && !v.is_empty() && !lines[i - 1].is_empty();
+                acc.push(if joined { ' ' } else { '\n' });
+            }
+            acc += v;
+        }
+        match chomp {
+            Some('-') => {}
+            Some(_) => acc += &"\n".repeat(trailing + 1),
+            None if !acc.is_empty() => acc.push('\n'),
+            None => {}
+        }
+        Ok(Json::String(acc))
+    }
+}
+
+/// Parse YAML
+///
+/// Parse a YAML document into a JSON value. Empty documents are `null`.
+pub fn from_str(data: &str) -> Result<Json, Error> {
+    let mut parser = Parser {
+        lines: data.lines().collect(),
+        pos: 0,
+        overlay: None,
+    };
+    let value = match parser.peek()? {
+        Some((indent, _)) => parser.node(indent)?,
+        None => Json::Null,
+    };
+    match parser.peek()? {
+        None => Ok(value),
+        Some(_) => error(parser.line(), "unexpected content, check indentation"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Verify YAML Parsing
+    #[test]
+    fn verify_parse() {
+        let yaml = r#"
+# A manifest in YAML
+version: '2'
+pipelines:
+  - name: build # the build root
+    runner: org.osbuild.fedora39
+    stages:
+    - type: org.osbuild.rpm
+      options: { gpgkeys: ["a: b", 'it''s'], exclude: [] }
+  - name: os
+    source-epoch: 1659397331
+    stages:
+      -
+        type: org.osbuild.kernel-cmdline
+        options:
+          root_fs_uuid: 6e4ff95f-f662-45ee-a82a-bdf44a2d0b75
+          kernel_opts: >-
+            ro
+            quiet
+      - type: org.osbuild.script
+        options:
+          script: |
+            #!/bin/sh
+            echo "done"
+
+sources:
+  org.osbuild.curl:
+    items:
+      "sha256:01": https://example.com/a.rpm
+empty:
+flags: [true, ~, -1.5, 0x10, 1e3, .5, "é"]
+"#;
+        assert_eq! {
+            from_str(yaml).unwrap(),
+            serde_json::json!({
+                "version": "2",
+                "pipelines": [
+                    {
+                        "name": "build",
+                        "runner": "org.osbuild.fedora39",
+                        "stages": [{
+                            "type": "org.osbuild.rpm",
+                            "options": { "gpgkeys": ["a: b", "it's"], "exclude": [] },
+                        }],
+                    },
+                    {
+                        "name": "os",
+                        "source-epoch": 1659397331,
+                        "stages": [
+                            {
+                                "type": "org.osbuild.kernel-cmdline",
+                                "options": {
+                                    "root_fs_uuid": "6e4ff95f-f662-45ee-a82a-bdf44a2d0b75",
+                                    "kernel_opts": "ro quiet",
+                                },
+                            },
+                            {
+                                "type": "org.osbuild.script",
+                                "options": { "script": "#!/bin/sh\necho \"done\"\n" },
+                            },
+                        ],
+                    },
+                ],
+                "sources": {
+                    "org.osbuild.curl": { "items": { "sha256:01": "https://example.com/a.rpm" } },
+                },
+                "empty": null,
+                "flags": [true, null, -1.5, 16, 1000.0, 0.5, "é"],
+            }),
+        }
+
+        assert_eq!(from_str("").unwrap(), Json::Null);
+        assert_eq!(
+            from_str("{\n  \"a\": [1,\n 2]\n}").unwrap(),
+            serde_json::json!({ "a": [1, 2] })
+        );
+        assert_eq!(from_str("a: 1\n  b: 2").unwrap_err().line, 2);
+        assert_eq!(from_str("a: 1\na: 2").unwrap_err().line, 2);
+        assert_eq!(from_str("a: &x 1").unwrap_err().line, 1);
+        assert_eq!(from_str("a: [1, 2").unwrap_err().line, 1);
+    }
+}
@@ -0,0 +1,151 @@
+//! Command-Line Interface Tests
+//!
+//! This runs the `r-osbuild` binary on a small manifest and checks the
+//! output and exit status of each subcommand. The binary is only built
+//! with the `cli` feature, so these tests are skipped otherwise.
+
+#![cfg(feature = "cli")]
+
+use std::process::{Command, Output};
+
+const MANIFEST: &str = r#"{
+  "pipeline": {
+    "stages": [
+      {
+        "name": "org.osbuild.locale",
+        "options": {
+          "language": "en_US"
+        }
+      }
+    ]
+  }
+}
+"#;
+
+const DEVICES: &str = r#"{
+  "version": "2",
+  "pipelines": [
+    {
+      "name": "tree",
+      "stages": [{ "type": "org.osbuild.noop" }]
+    },
+    {
+      "name": "image",
+      "stages": [
+        {
+          "type": "org.osbuild.truncate",
+          "options": { "filename": "disk.img", "size": "1G" }
+        },
+        {
+          "type": "org.osbuild.mkfs.ext4",
+          "options": { "uuid": "76a22bf4-f153-4541-b6c7-0332c0dfaeac", "label": "root" },
+          "devices": {
+            "device": { "type": "org.osbuild.loopback", "options": { "filename": "disk.img" } }
+          }
+        }
+      ]
+    }
+  ]
+}
+"#;
+
+fn scratch(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("r-osbuild-cli-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_r-osbuild"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Verify All Subcommands
+#[test]
+fn verify_cli() {
+    let dir = scratch("verify");
+    let v1 = dir.join("v1.json");
+    let v2 = dir.join("v2.json");
+    let other = dir.join("other.json");
+    std::fs::write(&v1, MANIFEST).unwrap();
+    std::fs::write(&other, MANIFEST.replace("en_US", "de_DE")).unwrap();
+    let v1 = v1.to_str().unwrap();
+    let other = other.to_str().unwrap();
+
+    let out = run(&["validate", v1]);
+    assert_eq!(out.status.code(), Some(0));
+
+    let out = run(&["format", "--check", v1]);
+    assert_eq!(out.status.code(), Some(0));
+
+    let out = run(&["inspect", "--json", v1]);
+    assert_eq!(out.status.code(), Some(0));
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(json["stats"]["stages"], 1);
+    assert!(json["ids"]["/pipeline/stages/0"].is_string());
+
+    let out = run(&["diff", v1, other]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq! {
+        String::from_utf8(out.stdout).unwrap(),
+        "~ /pipeline/stages/0/options/language: \"en_US\" -> \"de_DE\"\n",
+    }
+    let out = run(&["diff", "--ignore", "key:language", v1, other]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        run(&["diff", "--ignore", "x", v1, other]).status.code(),
+        Some(2)
+    );
+
+    // Converting to version 2 and back yields an equal manifest.
+    let out = run(&["convert", "--to", "v2", v1]);
+    assert_eq!(out.status.code(), Some(0));
+    std::fs::write(&v2, out.stdout).unwrap();
+    let v2 = v2.to_str().unwrap();
+    assert_eq!(run(&["diff", v1, v2]).status.code(), Some(2));
+    let out = run(&["convert", "--to", "v1", v2]);
+    assert_eq!(out.status.code(), Some(0));
+    std::fs::write(other, out.stdout).unwrap();
+    assert_eq!(run(&["diff", v1, other]).status.code(), Some(0));
+
+    // Version-2 manifests are used as is, even if they cannot be
+    // converted to version 1.
+    let devices = dir.join("devices.json");
+    std::fs::write(&devices, DEVICES).unwrap();
+    let devices = devices.to_str().unwrap();
+    assert_eq!(run(&["validate", devices]).status.code(), Some(0));
+    let out = run(&["inspect", "--json", devices]);
+    assert_eq!(out.status.code(), Some(0));
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(json["stats"]["stages"], 3);
+    assert!(json["ids"]["/pipelines/0/stages/0"].is_string());
+    assert_eq!(
+        run(&["convert", "--to", "v2", devices]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        run(&["convert", "--to", "v1", devices]).status.code(),
+        Some(2)
+    );
+
+    // Input errors take precedence over findings.
+    assert_eq!(
+        run(&["validate", dir.to_str().unwrap()]).status.code(),
+        Some(2)
+    );
+    assert_eq!(run(&["convert", "--to", "v3", v1]).status.code(), Some(2));
+
+    // Invalid manifests are reported with the location of the error.
+    let invalid = dir.join("invalid.json");
+    std::fs::write(&invalid, MANIFEST.replace("\"org.osbuild.locale\"", "1")).unwrap();
+    let out = run(&["format", "--check", invalid.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("/pipeline/stages/0/name"), "{}", stderr);
+    assert!(stderr.contains("line "), "{}", stderr);
+    assert_eq!(run(&["frobnicate"]).status.code(), Some(2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
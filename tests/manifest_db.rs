@@ -0,0 +1,112 @@
+//! Manifest Corpus Tests
+//!
+//! This runs the manifest parser against the osbuild manifest-db corpus,
+//! which holds thousands of manifests of real-world image builds. The
+//! corpus is not part of this repository. Point the `OSBUILD_MANIFEST_DB`
+//! environment variable at a checkout of manifest-db to enable these tests,
+//! otherwise they are skipped.
+//!
+//! Every JSON file below the corpus directory is parsed. Files of format
+//! versions this crate does not implement are reported as coverage gaps,
+//! all other files must parse. A report with per-file errors is printed to
+//! stderr, visible with `cargo test -- --nocapture`. The parser needs the
+//! `json` feature, so these tests are skipped without it.
+
+#![cfg(feature = "json")]
+
+use r_osbuild::manifest::{Json, Manifest1, Manifest2};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+enum Outcome {
+    Parsed,
+    Gap(String),
+    Failed(String),
+}
+
+fn collect(dir: &Path, acc: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, acc)?;
+        } else if path.extension().is_some_and(|v| v == "json") {
+            acc.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn check(path: &Path) -> Outcome {
+    let data = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let mut json: Json = match serde_json::from_str(&data) {
+        Ok(v) => v,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    // Corpus entries can embed the manifest alongside test metadata.
+    if let Some(v) = json.get_mut("manifest") {
+        json = v.take();
+    }
+
+    let result = match json.get("version").and_then(Json::as_str) {
+        None => serde_json::from_value::<Manifest1>(json).map(|_| ()),
+        Some("2") => serde_json::from_value::<Manifest2>(json).map(|_| ()),
+        Some(v) => return Outcome::Gap(format!("unsupported format version {}", v)),
+    };
+
+    match result {
+        Ok(()) => Outcome::Parsed,
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+// Verify the Manifest Corpus
+#[test]
+fn verify_manifest_db() {
+    let root = match std::env::var_os("OSBUILD_MANIFEST_DB") {
+        Some(v) => PathBuf::from(v),
+        None => {
+            eprintln!("OSBUILD_MANIFEST_DB not set, skipping corpus tests");
+            return;
+        }
+    };
+
+    let mut files = Vec::new();
+    collect(&root, &mut files).unwrap();
+    files.sort();
+
+    let mut n_parsed = 0;
+    let mut gaps = Vec::new();
+    let mut failures = Vec::new();
+    for path in files.iter() {
+        let name = path.strip_prefix(&root).unwrap_or(path).display();
+        match check(path) {
+            Outcome::Parsed => n_parsed += 1,
+            Outcome::Gap(v) => gaps.push(format!("{}: {}", name, v)),
+            Outcome::Failed(v) => failures.push(format!("{}: {}", name, v)),
+        }
+    }
+
+    eprintln!(
+        "manifest-db: {} files, {} parsed, {} gaps, {} failures",
+        files.len(),
+        n_parsed,
+        gaps.len(),
+        failures.len(),
+    );
+    for v in gaps.iter() {
+        eprintln!("  gap: {}", v);
+    }
+    for v in failures.iter() {
+        eprintln!("  failure: {}", v);
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} manifests failed to parse",
+        failures.len()
+    );
+}